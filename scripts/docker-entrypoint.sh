@@ -11,14 +11,9 @@ echo ""
 
 # Start mock devices in background
 echo "Starting mock devices..."
-mock_device /tmp/ttyVIOT1 iot > /app/logs/mock-iot.log 2>&1 &
-echo "  ✓ IoT Sensor (PID: $!)"
-
-mock_device /tmp/ttyVMCU1 mcu > /app/logs/mock-mcu.log 2>&1 &
-echo "  ✓ Embedded MCU (PID: $!)"
-
-mock_device /tmp/ttyVPLC1 plc > /app/logs/mock-plc.log 2>&1 &
-echo "  ✓ Industrial PLC (PID: $!)"
+mock_device --port /tmp/ttyVIOT1:iot --port /tmp/ttyVMCU1:mcu --port /tmp/ttyVPLC1:plc \
+    > /app/logs/mock-devices.log 2>&1 &
+echo "  ✓ IoT Sensor, Embedded MCU and Industrial PLC (PID: $!)"
 
 echo ""
 echo "Waiting for mock devices to initialize..."
@@ -107,16 +107,8 @@ echo "    # ... other settings"
 echo ""
 echo "========================================="
 echo ""
-echo "Now run the mock devices in separate terminals:"
-echo ""
-echo "  Terminal 1 (IoT Sensor):"
-echo "    cargo run --bin mock_device /tmp/ttyVIOT1 iot --verbose"
-echo ""
-echo "  Terminal 2 (Embedded MCU):"
-echo "    cargo run --bin mock_device /tmp/ttyVMCU1 mcu --verbose"
-echo ""
-echo "  Terminal 3 (Industrial PLC):"
-echo "    cargo run --bin mock_device /tmp/ttyVPLC1 plc --verbose"
+echo "Now run the mock devices:"
+echo "    cargo run --bin mock_device -- --port /tmp/ttyVIOT1:iot --port /tmp/ttyVMCU1:mcu --port /tmp/ttyVPLC1:plc --verbose"
 echo ""
 echo "Then start the server:"
 echo "    cargo run --release"
@@ -1,144 +1,650 @@
+// Kept under cli/ so Cargo does not build it as a binary of its own
+#[path = "cli/profiles.rs"]
+mod profiles;
+
 use anyhow::{Context, Result};
-use clap::Parser;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Local};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
 use crossterm::{
     event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
 use futures::{SinkExt, StreamExt};
-use serde_json;
+use profiles::Profile;
+use regex::Regex;
+use serde::Deserialize;
 use std::io::{self, Write};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
 use tokio::select;
-use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::{
+    client::IntoClientRequest, handshake::client::Request, http::header, Message,
+};
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+use webmux::web::close::CloseReason;
 
 #[derive(Parser, Debug)]
 #[command(name = "webmux-cli")]
 #[command(about = "WebMux CLI - Connect to serial devices through WebMux server", long_about = None)]
 struct Args {
-    /// WebMux server host
-    #[arg(short = 'H', long, default_value = "127.0.0.1")]
-    host: String,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// WebMux server port
-    #[arg(short, long, default_value = "8080")]
-    port: u16,
+    /// Server profile from ~/.config/webmux/cli.toml
+    #[arg(short = 'P', long, global = true)]
+    profile: Option<String>,
 
-    /// Serial device/connection name
+    /// WebMux server host (default 127.0.0.1)
+    #[arg(short = 'H', long, global = true)]
+    host: Option<String>,
+
+    /// WebMux server port (default 8080)
+    #[arg(short, long, global = true)]
+    port: Option<u16>,
+
+    /// Serial device/connection name; picked from the server's
+    /// connections if omitted
     #[arg(short, long)]
-    device: String,
+    device: Option<String>,
 
     /// Use TLS/WSS connection
-    #[arg(short = 's', long)]
+    #[arg(short = 's', long, global = true)]
+    tls: bool,
+
+    /// Token sent as `Authorization: Bearer <token>`
+    #[arg(long, global = true)]
+    token: Option<String>,
+
+    /// Copy stdin to the device and the device's output to stdout
+    /// byte for byte, for use in pipelines
+    #[arg(long)]
+    raw: bool,
+
+    /// Print a man page and exit
+    #[arg(long)]
+    generate_man: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Follow the output of several connections without writing to them
+    Monitor {
+        /// Connections to follow
+        #[arg(required = true)]
+        devices: Vec<String>,
+
+        /// Only print lines matching this regular expression
+        #[arg(long)]
+        grep: Option<Regex>,
+    },
+    /// Print a shell completion script
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+/// Where to connect: the command line, then the profile, then defaults.
+#[derive(Debug, Clone, PartialEq)]
+struct Server {
+    host: String,
+    port: u16,
     tls: bool,
+    token: Option<String>,
+}
+
+impl Args {
+    fn server(&self, profile: &Profile) -> Server {
+        Server {
+            host: self
+                .host
+                .clone()
+                .or_else(|| profile.host.clone())
+                .unwrap_or_else(|| "127.0.0.1".to_string()),
+            port: self.port.or(profile.port).unwrap_or(8080),
+            tls: self.tls || profile.tls.unwrap_or(false),
+            token: self.token.clone().or_else(|| profile.token.clone()),
+        }
+    }
+}
+
+impl Server {
+    fn ws_url(&self, device: &str) -> String {
+        let protocol = if self.tls { "wss" } else { "ws" };
+        format!(
+            "{}://{}:{}/api/connections/{}/ws",
+            protocol, self.host, self.port, device
+        )
+    }
+
+    fn http_url(&self, path: &str) -> String {
+        let protocol = if self.tls { "https" } else { "http" };
+        format!("{}://{}:{}{}", protocol, self.host, self.port, path)
+    }
+
+    fn authorization(&self) -> Option<String> {
+        self.token.as_ref().map(|token| format!("Bearer {}", token))
+    }
+
+    /// WebSocket handshake for `url`, carrying the token if there is one.
+    fn ws_request(&self, url: &str) -> Result<Request> {
+        let mut request = url.into_client_request()?;
+        if let Some(authorization) = self.authorization() {
+            request
+                .headers_mut()
+                .insert(header::AUTHORIZATION, authorization.parse()?);
+        }
+        Ok(request)
+    }
+}
+
+/// Pause between attempts to reach a restarting server.
+const RECONNECT_DELAY: Duration = Duration::from_secs(2);
+
+/// How a session with the server ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Ended {
+    /// The user pressed Ctrl+C.
+    Quit,
+    /// The server closed the session, with its close code if it sent one.
+    Closed(Option<u16>),
+}
+
+/// What to tell the user when the server closes the session.
+fn close_message(code: Option<u16>) -> &'static str {
+    match code.and_then(CloseReason::from_code) {
+        Some(CloseReason::ConnectionRemoved) => "Connection removed from the server",
+        Some(CloseReason::Unauthorized) => "Not authorized to open this connection",
+        Some(CloseReason::Kicked) => "Disconnected by an operator",
+        Some(CloseReason::ServerShutdown) => "Server is shutting down",
+        None => "Connection closed by server",
+    }
+}
+
+fn should_reconnect(code: Option<u16>) -> bool {
+    code.and_then(CloseReason::from_code)
+        .is_some_and(CloseReason::should_reconnect)
+}
+
+/// The text of a banner the server sends when a session attaches.
+fn banner_text(text: &str) -> Option<String> {
+    let message: serde_json::Value = serde_json::from_str(text).ok()?;
+    if message.get("type")?.as_str()? != "banner" {
+        return None;
+    }
+    Some(message.get("text")?.as_str()?.to_string())
+}
+
+/// What to tell the user when the write lock on the connection changes hands.
+fn write_lock_notice(text: &str) -> Option<String> {
+    let message: serde_json::Value = serde_json::from_str(text).ok()?;
+    if message.get("type")?.as_str()? != "write_lock" {
+        return None;
+    }
+    let holder = message.get("holder").filter(|holder| !holder.is_null());
+    Some(match holder {
+        Some(holder) => {
+            let session = holder.get("session").and_then(|s| s.as_u64()).unwrap_or(0);
+            match holder.get("client").and_then(|c| c.as_str()) {
+                Some(client) => format!("Write lock held by session {} ({})", session, client),
+                None => format!("Write lock held by session {}", session),
+            }
+        }
+        None => match message.get("reason").and_then(|r| r.as_str()) {
+            Some("idle") => "Write lock released after inactivity".to_string(),
+            Some("disconnected") => "Write lock released: the holder disconnected".to_string(),
+            _ => "Write lock released".to_string(),
+        },
+    })
+}
+
+/// A line describing what another session wrote to the connection.
+fn tx_notice(text: &str) -> Option<String> {
+    let message: serde_json::Value = serde_json::from_str(text).ok()?;
+    if message.get("type")?.as_str()? != "tx" {
+        return None;
+    }
+    let data = message.get("data")?.as_str()?;
+    let data = match message.get("format").and_then(|f| f.as_str()) {
+        Some("base64") => {
+            let bytes = general_purpose::STANDARD.decode(data).ok()?;
+            String::from_utf8_lossy(&bytes).into_owned()
+        }
+        _ => data.to_string(),
+    };
+    let session = message.get("session").and_then(|s| s.as_u64()).unwrap_or(0);
+    let who = match message.get("client").and_then(|c| c.as_str()) {
+        Some(client) => format!("session {} ({})", session, client),
+        None => format!("session {}", session),
+    };
+    Some(format!("{} wrote: {}", who, data.escape_debug()))
+}
+
+/// Waits up to `timeout` for a key, returning whether it was Ctrl+C.
+fn ctrl_c_pressed(timeout: Duration) -> Result<bool> {
+    if event::poll(timeout)? {
+        if let Event::Key(KeyEvent {
+            code: KeyCode::Char('c'),
+            modifiers: KeyModifiers::CONTROL,
+            ..
+        }) = event::read()?
+        {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+async fn run_session(
+    ws_stream: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    input_buffer: &mut String,
+) -> Result<Ended> {
+    let (mut write, mut read) = ws_stream.split();
+
+    loop {
+        select! {
+            // Handle incoming WebSocket messages
+            msg = read.next() => {
+                let Some(msg) = msg else {
+                    return Ok(Ended::Closed(None));
+                };
+                match msg? {
+                    Message::Text(text) => {
+                        if let Some(banner) = banner_text(&text) {
+                            // The terminal is in raw mode, so lines need a carriage return
+                            let lines: Vec<&str> = banner.lines().collect();
+                            print!("{}\r\n", lines.join("\r\n"));
+                            io::stdout().flush()?;
+                            continue;
+                        }
+                        if let Some(notice) = write_lock_notice(&text) {
+                            print!("\r\n[{}]\r\n", notice);
+                            io::stdout().flush()?;
+                            continue;
+                        }
+                        // Parse JSON response
+                        if let Ok(response) = serde_json::from_str::<serde_json::Value>(&text) {
+                            if response.get("type").and_then(|t| t.as_str()) == Some("error") {
+                                let message = response.get("message").and_then(|m| m.as_str()).unwrap_or_default();
+                                print!("\r\n[webmux error] {}\r\n", message);
+                                io::stdout().flush()?;
+                            } else if let Some(data) = response.get("data").and_then(|d| d.as_str()) {
+                                print!("{}", data);
+                                io::stdout().flush()?;
+                            }
+                        }
+                    }
+                    Message::Binary(data) => {
+                        // Handle binary data
+                        let text = String::from_utf8_lossy(&data);
+                        print!("{}", text);
+                        io::stdout().flush()?;
+                    }
+                    Message::Close(frame) => {
+                        return Ok(Ended::Closed(frame.map(|frame| u16::from(frame.code))));
+                    }
+                    _ => {}
+                }
+            }
+
+            // Handle keyboard input
+            _ = tokio::time::sleep(Duration::from_millis(10)) => {
+                if event::poll(Duration::from_millis(0))? {
+                    if let Event::Key(KeyEvent { code, modifiers, .. }) = event::read()? {
+                        match (code, modifiers) {
+                            // Ctrl+C to exit
+                            (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
+                                return Ok(Ended::Quit);
+                            }
+                            // Enter key - send the buffered command
+                            (KeyCode::Enter, _) => {
+                                if !input_buffer.is_empty() {
+                                    // Send the complete command with newline
+                                    write.send(Message::Text(format!("{}\r\n", input_buffer))).await?;
+                                    input_buffer.clear();
+                                } else {
+                                    // Just send newline
+                                    write.send(Message::Text("\r\n".to_string())).await?;
+                                }
+                                print!("\r\n");
+                                io::stdout().flush()?;
+                            }
+                            // Backspace - remove from buffer
+                            (KeyCode::Backspace, _) if !input_buffer.is_empty() => {
+                                input_buffer.pop();
+                                print!("\x08 \x08");
+                                io::stdout().flush()?;
+                            }
+                            // Regular character - add to buffer
+                            (KeyCode::Char(c), _) => {
+                                input_buffer.push(c);
+                                print!("{}", c);
+                                io::stdout().flush()?;
+                            }
+                            _ => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ConnectionListItem {
+    name: String,
+    /// Absent on servers from before failed connections were listed.
+    #[serde(default)]
+    state: Option<String>,
+}
+
+async fn list_connections(server: &Server) -> Result<Vec<String>> {
+    let mut request = reqwest::Client::new().get(server.http_url("/api/connections"));
+    if let Some(authorization) = server.authorization() {
+        request = request.header(header::AUTHORIZATION.as_str(), authorization);
+    }
+    let connections: Vec<ConnectionListItem> = request
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .context("Failed to list connections on WebMux server")?
+        .json()
+        .await
+        .context("Invalid connection list from WebMux server")?;
+    // Failed and disabled connections cannot be attached to
+    Ok(connections
+        .into_iter()
+        .filter(|c| matches!(c.state.as_deref(), None | Some("open")))
+        .map(|c| c.name)
+        .collect())
+}
+
+/// Parses a 1-based choice from the picker.
+fn parse_choice(input: &str, count: usize) -> Option<usize> {
+    let choice: usize = input.trim().parse().ok()?;
+    (1..=count).contains(&choice).then(|| choice - 1)
+}
+
+/// Uses the only connection, or asks which one to open if `interactive`.
+fn pick_device(mut names: Vec<String>, interactive: bool) -> Result<String> {
+    match names.len() {
+        0 => anyhow::bail!("WebMux server has no connections"),
+        1 => return Ok(names.remove(0)),
+        _ if !interactive => anyhow::bail!(
+            "WebMux server has several connections, choose one with --device: {}",
+            names.join(", ")
+        ),
+        _ => {}
+    }
+
+    println!("Available connections:");
+    for (i, name) in names.iter().enumerate() {
+        println!("  {}) {}", i + 1, name);
+    }
+    loop {
+        print!("Select a connection [1-{}]: ", names.len());
+        io::stdout().flush()?;
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input)? == 0 {
+            anyhow::bail!("No connection selected");
+        }
+        if let Some(index) = parse_choice(&input, names.len()) {
+            return Ok(names.swap_remove(index));
+        }
+    }
+}
+
+/// Longest line `monitor` buffers before printing it anyway.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Cuts one connection's stream into lines for `monitor`.
+#[derive(Default)]
+struct LineBuffer {
+    partial: Vec<u8>,
+}
+
+impl LineBuffer {
+    /// Complete lines in `data`, without their terminators.
+    fn feed(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            if byte == b'\n' || self.partial.len() >= MAX_LINE_LENGTH {
+                let line = std::mem::take(&mut self.partial);
+                let line = String::from_utf8_lossy(&line);
+                lines.push(line.trim_end_matches('\r').to_string());
+                if byte == b'\n' {
+                    continue;
+                }
+            }
+            self.partial.push(byte);
+        }
+        lines
+    }
+}
+
+/// A line as `monitor` prints it.
+fn monitor_line(device: &str, time: DateTime<Local>, line: &str) -> String {
+    format!("{} {} | {}", time.format("%H:%M:%S%.3f"), device, line)
+}
+
+/// Streams lines from one connection until the server closes it.
+async fn follow(server: Server, device: String, lines: mpsc::Sender<(String, String)>) {
+    let url = format!(
+        "{}?client=webmux-cli-monitor&share_input=true",
+        server.ws_url(&device)
+    );
+    let connected = match server.ws_request(&url) {
+        Ok(request) => connect_async(request).await.map_err(anyhow::Error::from),
+        Err(e) => Err(e),
+    };
+    let mut ws_stream = match connected {
+        Ok((ws_stream, _)) => ws_stream,
+        Err(e) => {
+            let _ = lines
+                .send((device, format!("[failed to connect: {}]", e)))
+                .await;
+            return;
+        }
+    };
+
+    let mut buffer = LineBuffer::default();
+    while let Some(msg) = ws_stream.next().await {
+        let received = match msg {
+            Ok(Message::Binary(data)) => buffer.feed(&data),
+            Ok(Message::Text(text)) => match (
+                banner_text(&text),
+                write_lock_notice(&text).or_else(|| tx_notice(&text)),
+            ) {
+                (Some(banner), _) => banner.lines().map(str::to_string).collect(),
+                (_, Some(notice)) => vec![format!("[{}]", notice)],
+                // CAN frames and error reports arrive as one JSON message each
+                _ => vec![text],
+            },
+            Ok(Message::Close(frame)) => {
+                let code = frame.map(|frame| u16::from(frame.code));
+                vec![format!("[{}]", close_message(code))]
+            }
+            Ok(_) => continue,
+            Err(e) => vec![format!("[connection lost: {}]", e)],
+        };
+        for line in received {
+            if lines.send((device.clone(), line)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Interleaves the output of `devices` until Ctrl+C or they all close.
+async fn monitor(server: &Server, devices: &[String], grep: Option<&Regex>) -> Result<()> {
+    let (lines_tx, mut lines_rx) = mpsc::channel(256);
+    for device in devices {
+        tokio::spawn(follow(server.clone(), device.clone(), lines_tx.clone()));
+    }
+    drop(lines_tx);
+
+    loop {
+        select! {
+            line = lines_rx.recv() => {
+                let Some((device, line)) = line else {
+                    return Ok(());
+                };
+                if grep.is_none_or(|grep| grep.is_match(&line)) {
+                    println!("{}", monitor_line(&device, Local::now(), &line));
+                }
+            }
+            _ = tokio::signal::ctrl_c() => return Ok(()),
+        }
+    }
+}
+
+/// `--raw`: pipes stdin and stdout through binary messages unchanged.
+///
+/// Nothing but device output is written to stdout. Once stdin ends the
+/// session is closed, and output keeps flowing until the server confirms.
+async fn passthrough(server: &Server, ws_url: &str) -> Result<()> {
+    let (ws_stream, _) = connect_async(server.ws_request(ws_url)?)
+        .await
+        .context("Failed to connect to WebMux server")?;
+    let (mut write, mut read) = ws_stream.split();
+
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = tokio::io::stdout();
+    let mut buf = vec![0u8; 4096];
+    let mut stdin_open = true;
+
+    loop {
+        select! {
+            n = stdin.read(&mut buf), if stdin_open => {
+                match n? {
+                    0 => {
+                        stdin_open = false;
+                        write.send(Message::Close(None)).await?;
+                    }
+                    n => write.send(Message::Binary(buf[..n].to_vec())).await?,
+                }
+            }
+            msg = read.next() => {
+                let msg = match msg {
+                    Some(Ok(msg)) => msg,
+                    // The server may hang up without answering our close
+                    Some(Err(_)) if !stdin_open => return Ok(()),
+                    Some(Err(e)) => return Err(e.into()),
+                    None => return Ok(()),
+                };
+                match msg {
+                    Message::Binary(data) => {
+                        stdout.write_all(&data).await?;
+                        stdout.flush().await?;
+                    }
+                    // Banners and error reports stay out of the data stream
+                    Message::Text(text) => match banner_text(&text) {
+                        Some(banner) => eprintln!("{}", banner),
+                        None => eprintln!("{}", text),
+                    },
+                    Message::Close(frame) => {
+                        let code = frame.map(|frame| u16::from(frame.code));
+                        if stdin_open {
+                            eprintln!("{}", close_message(code));
+                        }
+                        return Ok(());
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
     let args = Args::parse();
 
+    if args.generate_man {
+        clap_mangen::Man::new(Args::command()).render(&mut io::stdout())?;
+        return Ok(());
+    }
+    let profile = profiles::load()?.profile(args.profile.as_deref())?;
+    let server = args.server(&profile);
+
+    match args.command {
+        Some(Command::Monitor {
+            ref devices,
+            ref grep,
+        }) => return monitor(&server, devices, grep.as_ref()).await,
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(shell, &mut Args::command(), "webmux-cli", &mut io::stdout());
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let device = match args.device.clone().or(profile.device) {
+        Some(device) => device,
+        // In raw mode stdin carries data, so there is no one to ask
+        None => pick_device(list_connections(&server).await?, !args.raw)?,
+    };
+
     // Construct WebSocket URL
-    let protocol = if args.tls { "wss" } else { "ws" };
-    let ws_url = format!(
-        "{}://{}:{}/api/connections/{}/ws",
-        protocol, args.host, args.port, args.device
-    );
+    let ws_url = server.ws_url(&device);
+
+    if args.raw {
+        return passthrough(&server, &ws_url).await;
+    }
 
     println!("Connecting to WebMux server: {}", ws_url);
-    println!("Device: {}", args.device);
+    println!("Device: {}", device);
     println!("Press Ctrl+C to disconnect\n");
 
     // Connect to WebSocket
-    let (ws_stream, _) = connect_async(&ws_url)
+    let (ws_stream, _) = connect_async(server.ws_request(&ws_url)?)
         .await
         .context("Failed to connect to WebMux server")?;
 
     println!("Connected! Type to send data to the device.\n");
 
-    let (mut write, mut read) = ws_stream.split();
-
     // Set up terminal for raw mode
     enable_raw_mode()?;
     execute!(io::stdout(), EnterAlternateScreen)?;
 
     let result: Result<()> = async {
         let mut input_buffer = String::new();
+        let mut ws_stream = ws_stream;
 
         loop {
-            select! {
-                // Handle incoming WebSocket messages
-                Some(msg) = read.next() => {
-                    match msg? {
-                        Message::Text(text) => {
-                            // Parse JSON response
-                            if let Ok(response) = serde_json::from_str::<serde_json::Value>(&text) {
-                                if let Some(data) = response.get("data").and_then(|d| d.as_str()) {
-                                    print!("{}", data);
-                                    io::stdout().flush()?;
-                                }
-                            }
-                        }
-                        Message::Binary(data) => {
-                            // Handle binary data
-                            let text = String::from_utf8_lossy(&data);
-                            print!("{}", text);
-                            io::stdout().flush()?;
-                        }
-                        Message::Close(_) => {
-                            println!("\r\nConnection closed by server");
-                            break;
-                        }
-                        _ => {}
-                    }
+            let code = match run_session(ws_stream, &mut input_buffer).await? {
+                Ended::Quit => {
+                    println!("\r\nDisconnecting...");
+                    return Ok(());
                 }
+                Ended::Closed(code) => code,
+            };
+            print!("\r\n{}\r\n", close_message(code));
+            if !should_reconnect(code) {
+                return Ok(());
+            }
 
-                // Handle keyboard input
-                _ = tokio::time::sleep(std::time::Duration::from_millis(10)) => {
-                    if event::poll(std::time::Duration::from_millis(0))? {
-                        match event::read()? {
-                            Event::Key(KeyEvent { code, modifiers, .. }) => {
-                                match (code, modifiers) {
-                                    // Ctrl+C to exit
-                                    (KeyCode::Char('c'), KeyModifiers::CONTROL) => {
-                                        println!("\r\nDisconnecting...");
-                                        break;
-                                    }
-                                    // Enter key - send the buffered command
-                                    (KeyCode::Enter, _) => {
-                                        if !input_buffer.is_empty() {
-                                            // Send the complete command with newline
-                                            write.send(Message::Text(format!("{}\r\n", input_buffer))).await?;
-                                            input_buffer.clear();
-                                        } else {
-                                            // Just send newline
-                                            write.send(Message::Text("\r\n".to_string())).await?;
-                                        }
-                                        print!("\r\n");
-                                        io::stdout().flush()?;
-                                    }
-                                    // Backspace - remove from buffer
-                                    (KeyCode::Backspace, _) => {
-                                        if !input_buffer.is_empty() {
-                                            input_buffer.pop();
-                                            print!("\x08 \x08");
-                                            io::stdout().flush()?;
-                                        }
-                                    }
-                                    // Regular character - add to buffer
-                                    (KeyCode::Char(c), _) => {
-                                        input_buffer.push(c);
-                                        print!("{}", c);
-                                        io::stdout().flush()?;
-                                    }
-                                    _ => {}
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
+            // A server that shut down is expected back; retry until Ctrl+C
+            ws_stream = loop {
+                print!("Reconnecting in {}s...\r\n", RECONNECT_DELAY.as_secs());
+                io::stdout().flush()?;
+                if ctrl_c_pressed(RECONNECT_DELAY)? {
+                    println!("\r\nDisconnecting...");
+                    return Ok(());
                 }
-            }
+                match connect_async(server.ws_request(&ws_url)?).await {
+                    Ok((ws_stream, _)) => break ws_stream,
+                    Err(e) => print!("Reconnect failed: {}\r\n", e),
+                }
+            };
+            print!("Reconnected.\r\n");
+            io::stdout().flush()?;
         }
-        Ok(())
     }
     .await;
 
@@ -152,38 +658,39 @@ async fn main() -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use chrono::TimeZone;
 
     #[test]
     fn test_args_default_host_and_port() {
         let args = Args::try_parse_from(["webmux-cli", "--device", "test_device"]).unwrap();
-        assert_eq!(args.host, "127.0.0.1");
-        assert_eq!(args.port, 8080);
-        assert_eq!(args.device, "test_device");
-        assert_eq!(args.tls, false);
+        assert_eq!(args.server(&Profile::default()).host, "127.0.0.1");
+        assert_eq!(args.server(&Profile::default()).port, 8080);
+        assert_eq!(args.device.as_deref(), Some("test_device"));
+        assert!(!args.tls);
     }
 
     #[test]
     fn test_args_custom_host() {
         let args =
             Args::try_parse_from(["webmux-cli", "-H", "192.168.1.100", "-d", "sensor"]).unwrap();
-        assert_eq!(args.host, "192.168.1.100");
-        assert_eq!(args.port, 8080);
-        assert_eq!(args.device, "sensor");
+        assert_eq!(args.server(&Profile::default()).host, "192.168.1.100");
+        assert_eq!(args.server(&Profile::default()).port, 8080);
+        assert_eq!(args.device.as_deref(), Some("sensor"));
     }
 
     #[test]
     fn test_args_custom_port() {
         let args = Args::try_parse_from(["webmux-cli", "-p", "9000", "-d", "mcu"]).unwrap();
-        assert_eq!(args.host, "127.0.0.1");
-        assert_eq!(args.port, 9000);
-        assert_eq!(args.device, "mcu");
+        assert_eq!(args.server(&Profile::default()).host, "127.0.0.1");
+        assert_eq!(args.server(&Profile::default()).port, 9000);
+        assert_eq!(args.device.as_deref(), Some("mcu"));
     }
 
     #[test]
     fn test_args_tls_enabled() {
         let args = Args::try_parse_from(["webmux-cli", "-s", "-d", "plc"]).unwrap();
-        assert_eq!(args.tls, true);
-        assert_eq!(args.device, "plc");
+        assert!(args.tls);
+        assert_eq!(args.device.as_deref(), Some("plc"));
     }
 
     #[test]
@@ -199,16 +706,237 @@ mod tests {
             "--tls",
         ])
         .unwrap();
-        assert_eq!(args.host, "example.com");
-        assert_eq!(args.port, 443);
-        assert_eq!(args.device, "industrial_plc");
-        assert_eq!(args.tls, true);
+        assert_eq!(args.server(&Profile::default()).host, "example.com");
+        assert_eq!(args.server(&Profile::default()).port, 443);
+        assert_eq!(args.device.as_deref(), Some("industrial_plc"));
+        assert!(args.tls);
     }
 
     #[test]
-    fn test_args_missing_device() {
-        let result = Args::try_parse_from(["webmux-cli"]);
-        assert!(result.is_err());
+    fn test_profile_fills_in_unset_args() {
+        let config = profiles::parse(
+            r#"
+            # Shared bench
+            default_profile = "lab"
+
+            [profiles.lab]
+            host = "lab-mux.example.com"   # behind the proxy
+            port = 443
+            tls = true
+            token = "s3cr#t"
+            device = "router_console"
+
+            [profiles."bench 2"]
+            host = '10.0.0.2'
+            "#,
+        )
+        .unwrap();
+
+        let lab = config.profile(None).unwrap();
+        assert_eq!(lab.token.as_deref(), Some("s3cr#t"));
+        assert_eq!(lab.device.as_deref(), Some("router_console"));
+        let args = Args::try_parse_from(["webmux-cli", "-p", "8443"]).unwrap();
+        assert_eq!(
+            args.server(&lab),
+            Server {
+                host: "lab-mux.example.com".to_string(),
+                port: 8443,
+                tls: true,
+                token: Some("s3cr#t".to_string()),
+            }
+        );
+        let request = args.server(&lab).ws_request("wss://lab/ws").unwrap();
+        assert_eq!(request.headers()["authorization"], "Bearer s3cr#t");
+
+        let bench = config.profile(Some("bench 2")).unwrap();
+        assert_eq!(bench.host.as_deref(), Some("10.0.0.2"));
+        assert_eq!(args.server(&bench).port, 8443);
+        assert!(config.profile(Some("missing")).is_err());
+        assert_eq!(
+            profiles::parse("").unwrap().profile(None).unwrap(),
+            Profile::default()
+        );
+    }
+
+    #[test]
+    fn test_profiles_reject_invalid_files() {
+        assert!(profiles::parse("[profiles.lab]\nhost = lab").is_err());
+        assert!(profiles::parse("[profiles.lab]\nport = \"443\"").is_err());
+        assert!(profiles::parse("[profiles.lab]\nuser = \"me\"").is_err());
+        assert!(profiles::parse("[profiles.lab\nhost = \"lab\"").is_err());
+        assert!(profiles::parse("[profiles.lab]\nport = 1\nport = 2").is_err());
+    }
+
+    #[test]
+    fn test_reconnects_only_after_server_shutdown() {
+        assert!(should_reconnect(Some(4003)));
+        assert!(!should_reconnect(Some(4000)));
+        assert!(!should_reconnect(Some(4001)));
+        assert!(!should_reconnect(Some(4002)));
+        assert!(!should_reconnect(Some(1000)));
+        assert!(!should_reconnect(None));
+        assert_eq!(close_message(Some(4002)), "Disconnected by an operator");
+    }
+
+    #[test]
+    fn test_args_device_optional() {
+        let args = Args::try_parse_from(["webmux-cli"]).unwrap();
+        assert_eq!(args.device, None);
+    }
+
+    #[test]
+    fn test_pick_device_single_connection() {
+        let device = pick_device(vec!["only".to_string()], true).unwrap();
+        assert_eq!(device, "only");
+        assert!(pick_device(Vec::new(), true).is_err());
+        let several = vec!["a".to_string(), "b".to_string()];
+        assert!(pick_device(several, false).is_err());
+    }
+
+    #[test]
+    fn test_args_raw() {
+        let args = Args::try_parse_from(["webmux-cli", "--raw", "-d", "board"]).unwrap();
+        assert!(args.raw);
+        assert!(
+            !Args::try_parse_from(["webmux-cli", "-d", "board"])
+                .unwrap()
+                .raw
+        );
+    }
+
+    #[test]
+    fn test_monitor_args() {
+        let args = Args::try_parse_from([
+            "webmux-cli",
+            "monitor",
+            "dev1",
+            "dev2",
+            "--grep",
+            "ERROR",
+            "-H",
+            "lab",
+        ])
+        .unwrap();
+        assert_eq!(args.server(&Profile::default()).host, "lab");
+        match args.command {
+            Some(Command::Monitor { devices, grep }) => {
+                assert_eq!(devices, ["dev1", "dev2"]);
+                assert!(grep.unwrap().is_match("E: ERROR 42"));
+            }
+            other => panic!("expected monitor, got {:?}", other),
+        }
+        assert!(Args::try_parse_from(["webmux-cli", "monitor"]).is_err());
+        assert!(Args::try_parse_from(["webmux-cli", "monitor", "a", "--grep", "("]).is_err());
+    }
+
+    #[test]
+    fn test_line_buffer_splits_lines() {
+        let mut buffer = LineBuffer::default();
+        assert!(buffer.feed(b"boot").is_empty());
+        assert_eq!(buffer.feed(b"ing\r\nok\n\npart"), ["booting", "ok", ""]);
+        assert_eq!(buffer.feed(b"ial\n"), ["partial"]);
+    }
+
+    #[test]
+    fn test_monitor_line_format() {
+        let time = Local.with_ymd_and_hms(2025, 11, 30, 15, 30, 45).unwrap();
+        assert_eq!(
+            monitor_line("plc", time, "ERROR 3"),
+            "15:30:45.000 plc | ERROR 3"
+        );
+    }
+
+    #[test]
+    fn test_banner_text() {
+        assert_eq!(
+            banner_text(r#"{"type":"banner","text":"Lab PLC\nOwner: controls"}"#).as_deref(),
+            Some("Lab PLC\nOwner: controls")
+        );
+        assert_eq!(
+            banner_text(r#"{"type":"error","code":"write_failed","message":"x"}"#),
+            None
+        );
+        assert_eq!(banner_text("0a0d"), None);
+    }
+
+    #[test]
+    fn test_write_lock_notice() {
+        assert_eq!(
+            write_lock_notice(
+                r#"{"type":"write_lock","holder":{"session":3,"client":"alice","since":"2025-11-30T15:30:45Z"}}"#
+            )
+            .as_deref(),
+            Some("Write lock held by session 3 (alice)")
+        );
+        assert_eq!(
+            write_lock_notice(r#"{"type":"write_lock","holder":null,"reason":"idle"}"#).as_deref(),
+            Some("Write lock released after inactivity")
+        );
+        assert_eq!(write_lock_notice(r#"{"type":"banner","text":"x"}"#), None);
+    }
+
+    #[test]
+    fn test_tx_notice() {
+        assert_eq!(
+            tx_notice(
+                r#"{"type":"tx","session":3,"client":"alice","data":"show run\r","format":"text"}"#
+            )
+            .as_deref(),
+            Some("session 3 (alice) wrote: show run\\r")
+        );
+        assert_eq!(
+            tx_notice(r#"{"type":"tx","session":4,"data":"b2s=","format":"base64"}"#).as_deref(),
+            Some("session 4 wrote: ok")
+        );
+        assert_eq!(tx_notice(r#"{"type":"banner","text":"x"}"#), None);
+    }
+
+    #[test]
+    fn test_completions_cover_subcommands_and_flags() {
+        let args = Args::try_parse_from(["webmux-cli", "completions", "fish"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(Command::Completions { shell: Shell::Fish })
+        ));
+        assert!(Args::try_parse_from(["webmux-cli", "completions", "tcsh"]).is_err());
+
+        let generate = |shell: Shell| {
+            let mut script = Vec::new();
+            clap_complete::generate(shell, &mut Args::command(), "webmux-cli", &mut script);
+            String::from_utf8(script).unwrap()
+        };
+        let bash = generate(Shell::Bash);
+        assert!(bash.contains("webmux__cli__subcmd__monitor"));
+        assert!(bash.contains("--grep"));
+        assert!(bash.contains("--raw"));
+        assert!(generate(Shell::Zsh).starts_with("#compdef webmux-cli"));
+        assert!(generate(Shell::Fish).contains("complete -c webmux-cli"));
+    }
+
+    #[test]
+    fn test_man_page() {
+        assert!(
+            Args::try_parse_from(["webmux-cli", "--generate-man"])
+                .unwrap()
+                .generate_man
+        );
+        let mut page = Vec::new();
+        clap_mangen::Man::new(Args::command())
+            .render(&mut page)
+            .unwrap();
+        let page = String::from_utf8(page).unwrap();
+        assert!(page.contains(".TH webmux-cli 1"));
+        assert!(page.contains("WebMux server port (default 8080)"));
+        assert!(page.contains("monitor"));
+    }
+
+    #[test]
+    fn test_parse_choice() {
+        assert_eq!(parse_choice("2\n", 3), Some(1));
+        assert_eq!(parse_choice(" 1 ", 3), Some(0));
+        assert_eq!(parse_choice("0", 3), None);
+        assert_eq!(parse_choice("4", 3), None);
+        assert_eq!(parse_choice("two", 3), None);
     }
 
     #[test]
@@ -223,7 +951,10 @@ mod tests {
         let host = "127.0.0.1";
         let port = 8080;
         let device = "iot_sensor";
-        let ws_url = format!("{}://{}:{}/api/connections/{}/ws", protocol, host, port, device);
+        let ws_url = format!(
+            "{}://{}:{}/api/connections/{}/ws",
+            protocol, host, port, device
+        );
         assert_eq!(ws_url, "ws://127.0.0.1:8080/api/connections/iot_sensor/ws");
     }
 
@@ -233,7 +964,10 @@ mod tests {
         let host = "example.com";
         let port = 443;
         let device = "embedded_mcu";
-        let ws_url = format!("{}://{}:{}/api/connections/{}/ws", protocol, host, port, device);
+        let ws_url = format!(
+            "{}://{}:{}/api/connections/{}/ws",
+            protocol, host, port, device
+        );
         assert_eq!(
             ws_url,
             "wss://example.com:443/api/connections/embedded_mcu/ws"
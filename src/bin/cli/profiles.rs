@@ -0,0 +1,83 @@
+//! Named server profiles from `~/.config/webmux/cli.toml`:
+//!
+//! ```toml
+//! default_profile = "lab"
+//!
+//! [profiles.lab]
+//! host = "lab-mux.example.com"
+//! port = 443
+//! tls = true
+//! token = "s3cret"
+//! device = "router_console"
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub tls: Option<bool>,
+    /// Sent as `Authorization: Bearer <token>`, e.g. for an authenticating proxy.
+    pub token: Option<String>,
+    /// Connection opened when `--device` is not given.
+    pub device: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CliConfig {
+    /// Profile used when `--profile` is not given.
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl CliConfig {
+    /// The profile `name`, or the default profile if there is one.
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self.profiles.get(name).cloned().with_context(|| {
+                format!(
+                    "No profile named {} in {}",
+                    name,
+                    config_path().unwrap_or_default().display()
+                )
+            }),
+            None => Ok(Profile::default()),
+        }
+    }
+}
+
+/// Where the profiles live: `$XDG_CONFIG_HOME/webmux/cli.toml`, falling back
+/// to `~/.config`, or `%APPDATA%\webmux\cli.toml` on Windows.
+pub fn config_path() -> Option<PathBuf> {
+    let base = if cfg!(windows) {
+        std::env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    Some(base?.join("webmux").join("cli.toml"))
+}
+
+/// Reads the profiles; a missing file means no profiles.
+pub fn load() -> Result<CliConfig> {
+    let Some(path) = config_path() else {
+        return Ok(CliConfig::default());
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(text) => parse(&text).with_context(|| format!("Invalid {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(CliConfig::default()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    }
+}
+
+pub fn parse(text: &str) -> Result<CliConfig> {
+    Ok(toml::from_str(text)?)
+}
@@ -1,16 +1,25 @@
-use std::io::{Read, Write};
+// Kept under mock_device/ so Cargo does not build it as a binary of its own
+#[path = "mock_device/framed.rs"]
+mod framed;
+
+use clap::Parser;
 use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_serial::SerialPortBuilderExt;
 
 /// Mock Serial Device Simulator
 ///
-/// This program simulates a serial device for testing the Terminal Access Server.
-/// It responds to commands and periodically sends simulated sensor data.
+/// This program simulates serial devices for testing the Terminal Access Server.
+/// Each device responds to commands and periodically sends simulated sensor data;
+/// one process can run several devices on different ports at once.
 
 #[derive(Debug, Clone)]
 enum DeviceType {
     IoTSensor,
     EmbeddedMcu,
     IndustrialPlc,
+    /// Speaks length-prefixed packets with a CRC instead of text lines.
+    BinarySensor,
 }
 
 impl DeviceType {
@@ -19,6 +28,7 @@ impl DeviceType {
             "iot" | "sensor" => Some(DeviceType::IoTSensor),
             "mcu" | "embedded" => Some(DeviceType::EmbeddedMcu),
             "plc" | "industrial" => Some(DeviceType::IndustrialPlc),
+            "binary" | "framed" => Some(DeviceType::BinarySensor),
             _ => None,
         }
     }
@@ -28,11 +38,32 @@ impl DeviceType {
             DeviceType::IoTSensor => "IoT Sensor",
             DeviceType::EmbeddedMcu => "Embedded MCU",
             DeviceType::IndustrialPlc => "Industrial PLC",
+            DeviceType::BinarySensor => "Binary Sensor",
         }
     }
 
-    fn get_telemetry(&self, count: u32) -> String {
+    fn default_baud(&self) -> u32 {
         match self {
+            DeviceType::IoTSensor | DeviceType::BinarySensor => 115200,
+            DeviceType::EmbeddedMcu => 9600,
+            DeviceType::IndustrialPlc => 19200,
+        }
+    }
+
+    /// Data as shown in the console: hex for framed devices, text otherwise.
+    fn display(&self, data: &[u8]) -> String {
+        match self {
+            DeviceType::BinarySensor => data
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect::<Vec<_>>()
+                .join(" "),
+            _ => String::from_utf8_lossy(data).trim().to_string(),
+        }
+    }
+
+    fn get_telemetry(&self, count: u32) -> Vec<u8> {
+        let text = match self {
             DeviceType::IoTSensor => {
                 let temp = 20.0 + (count as f32 * 0.1).sin() * 5.0;
                 let humidity = 50.0 + (count as f32 * 0.05).cos() * 10.0;
@@ -53,7 +84,9 @@ impl DeviceType {
                     pressure, status, count
                 )
             }
-        }
+            DeviceType::BinarySensor => return framed::telemetry(count).encode(),
+        };
+        text.into_bytes()
     }
 
     fn process_command(&self, command: &str) -> String {
@@ -93,204 +126,424 @@ impl DeviceType {
                 "START" => "SYSTEM:STARTED\n".to_string(),
                 _ => format!("ERR:INVALID_CMD:{}\n", cmd),
             },
+            DeviceType::BinarySensor => unreachable!("framed devices answer packets, not lines"),
         }
     }
 }
 
-fn print_usage() {
-    println!("Mock Serial Device Simulator");
-    println!();
-    println!("Usage:");
-    println!("  mock_device <port> <device_type> [options]");
-    println!();
-    println!("Arguments:");
-    println!("  <port>        Serial port path (e.g., /dev/ttyUSB0, COM3)");
-    println!("  <device_type> Type of device to simulate:");
-    println!("                - iot/sensor     : IoT temperature/humidity sensor");
-    println!("                - mcu/embedded   : Arduino-like microcontroller");
-    println!("                - plc/industrial : Industrial PLC controller");
-    println!();
-    println!("Options:");
-    println!(
-        "  --baud <rate>      Baud rate (default: 115200 for iot, 9600 for mcu, 19200 for plc)"
-    );
-    println!("  --telemetry <sec>  Send telemetry every N seconds (default: 5)");
-    println!("  --echo             Echo received data back");
-    println!("  --verbose          Print debug information");
-    println!();
-    println!("Examples:");
-    println!("  # IoT sensor on /dev/ttyUSB0");
-    println!("  mock_device /dev/ttyUSB0 iot");
-    println!();
-    println!("  # Embedded MCU on COM3 with telemetry every 2 seconds");
-    println!("  mock_device COM3 mcu --telemetry 2");
-    println!();
-    println!("  # Industrial PLC with verbose output");
-    println!("  mock_device /dev/ttyS0 plc --verbose");
-    println!();
-    println!("Commands you can send to the device:");
-    println!("  STATUS, VERSION, ID, HELP (and device-specific commands)");
+/// A device to simulate, given as `<port>:<device_type>`.
+#[derive(Debug, Clone)]
+struct DeviceSpec {
+    port: String,
+    device_type: DeviceType,
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
+impl std::str::FromStr for DeviceSpec {
+    type Err = String;
 
-    if args.len() < 3 || args.contains(&"--help".to_string()) || args.contains(&"-h".to_string()) {
-        print_usage();
-        return;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // Split on the last colon so Windows paths such as `\\.\COM3` still work
+        let (port, device_type) = s
+            .rsplit_once(':')
+            .ok_or_else(|| format!("expected <port>:<device_type>, got '{}'", s))?;
+        let device_type = DeviceType::from_str(device_type).ok_or_else(|| {
+            format!(
+                "invalid device type '{}' (valid types: iot, sensor, mcu, embedded, plc, industrial, binary, framed)",
+                device_type
+            )
+        })?;
+        Ok(DeviceSpec {
+            port: port.to_string(),
+            device_type,
+        })
     }
+}
 
-    let port_name = &args[1];
-    let device_type = match DeviceType::from_str(&args[2]) {
-        Some(dt) => dt,
-        None => {
-            eprintln!("Error: Invalid device type '{}'", args[2]);
-            eprintln!("Valid types: iot, sensor, mcu, embedded, plc, industrial");
-            std::process::exit(1);
-        }
-    };
-
-    // Parse options
-    let mut baud_rate = match device_type {
-        DeviceType::IoTSensor => 115200,
-        DeviceType::EmbeddedMcu => 9600,
-        DeviceType::IndustrialPlc => 19200,
-    };
-    let mut telemetry_interval = 5;
-    let mut echo_mode = false;
-    let mut verbose = false;
-
-    let mut i = 3;
-    while i < args.len() {
-        match args[i].as_str() {
-            "--baud" => {
-                if i + 1 < args.len() {
-                    baud_rate = args[i + 1].parse().unwrap_or(baud_rate);
-                    i += 2;
-                } else {
-                    i += 1;
+#[derive(Parser, Debug)]
+#[command(name = "mock_device")]
+#[command(about = "Mock Serial Device Simulator", long_about = None)]
+#[command(after_help = "\
+Device types:
+  iot/sensor     : IoT temperature/humidity sensor
+  mcu/embedded   : Arduino-like microcontroller
+  plc/industrial : Industrial PLC controller
+  binary/framed  : Sensor speaking length-prefixed binary packets with a CRC-16
+
+Examples:
+  # IoT sensor on /dev/ttyUSB0
+  mock_device /dev/ttyUSB0 iot
+
+  # Embedded MCU on COM3 with telemetry every 2 seconds
+  mock_device COM3 mcu --telemetry 2
+
+  # Three devices in one process
+  mock_device --port /tmp/ttyVIOT1:iot --port /tmp/ttyVMCU1:mcu --port /tmp/ttyVPLC1:plc
+
+Commands you can send to the device:
+  STATUS, VERSION, ID, HELP (and device-specific commands)")]
+struct Args {
+    /// Serial port path of a single device (e.g., /dev/ttyUSB0, COM3)
+    #[arg(requires = "device_type")]
+    path: Option<String>,
+
+    /// Type of that device
+    #[arg(value_parser = parse_device_type)]
+    device_type: Option<DeviceType>,
+
+    /// Simulate a device on a port, as <port>:<device_type>; repeatable
+    #[arg(long = "port", value_name = "PORT:TYPE")]
+    ports: Vec<DeviceSpec>,
+
+    /// Baud rate (default: 115200 for iot, 9600 for mcu, 19200 for plc)
+    #[arg(long)]
+    baud: Option<u32>,
+
+    /// Send telemetry every N seconds
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    telemetry: u64,
+
+    /// Echo received data back
+    #[arg(long)]
+    echo: bool,
+
+    /// Print debug information
+    #[arg(long)]
+    verbose: bool,
+}
+
+fn parse_device_type(s: &str) -> Result<DeviceType, String> {
+    DeviceType::from_str(s).ok_or_else(|| {
+        format!(
+            "invalid device type '{}' (valid types: iot, sensor, mcu, embedded, plc, industrial, binary, framed)",
+            s
+        )
+    })
+}
+
+impl Args {
+    /// Every device to run: the positional one, then each `--port`.
+    fn devices(&self) -> Vec<DeviceSpec> {
+        let single = match (&self.path, &self.device_type) {
+            (Some(port), Some(device_type)) => Some(DeviceSpec {
+                port: port.clone(),
+                device_type: device_type.clone(),
+            }),
+            _ => None,
+        };
+        single
+            .into_iter()
+            .chain(self.ports.iter().cloned())
+            .collect()
+    }
+}
+
+/// Runs one simulated device until its port fails.
+async fn run_device(spec: DeviceSpec, args: &Args, label: Option<String>) -> Result<(), String> {
+    let device_type = spec.device_type;
+    let baud_rate = args.baud.unwrap_or_else(|| device_type.default_baud());
+    let prefix = label
+        .map(|label| format!("[{}] ", label))
+        .unwrap_or_default();
+
+    let mut port = tokio_serial::new(&spec.port, baud_rate)
+        .open_native_async()
+        .map_err(|e| format!("Error opening port {}: {}", spec.port, e))?;
+    println!(
+        "{}✓ {} listening on {} at {} baud",
+        prefix,
+        device_type.name(),
+        spec.port,
+        baud_rate
+    );
+
+    let mut buffer = [0u8; 256];
+    let mut telemetry_counter = 0u32;
+    let mut decoder =
+        matches!(device_type, DeviceType::BinarySensor).then(framed::Decoder::default);
+    let telemetry_duration = Duration::from_secs(args.telemetry);
+    let mut telemetry = tokio::time::interval_at(
+        tokio::time::Instant::now() + telemetry_duration,
+        telemetry_duration,
+    );
+
+    loop {
+        tokio::select! {
+            _ = telemetry.tick() => {
+                let data = device_type.get_telemetry(telemetry_counter);
+                if args.verbose {
+                    println!("{}📤 TELEMETRY: {}", prefix, device_type.display(&data));
                 }
-            }
-            "--telemetry" => {
-                if i + 1 < args.len() {
-                    telemetry_interval = args[i + 1].parse().unwrap_or(telemetry_interval);
-                    i += 2;
-                } else {
-                    i += 1;
+                if let Err(e) = port.write_all(&data).await {
+                    eprintln!("{}Error sending telemetry: {}", prefix, e);
                 }
+                telemetry_counter += 1;
             }
-            "--echo" => {
-                echo_mode = true;
-                i += 1;
-            }
-            "--verbose" => {
-                verbose = true;
-                i += 1;
+            result = port.read(&mut buffer) => {
+                let n = match result {
+                    Ok(0) => return Err(format!("{}Port {} closed", prefix, spec.port)),
+                    Ok(n) => n,
+                    Err(e) => return Err(format!("{}Error reading from port: {}", prefix, e)),
+                };
+                if args.verbose {
+                    println!(
+                        "{}📥 RECEIVED ({} bytes): {:?}",
+                        prefix,
+                        n,
+                        device_type.display(&buffer[..n])
+                    );
+                }
+
+                if args.echo {
+                    if let Err(e) = port.write_all(&buffer[..n]).await {
+                        eprintln!("{}Error echoing data: {}", prefix, e);
+                    }
+                }
+
+                // Process commands, or packets on framed devices
+                let exchanges: Vec<(Vec<u8>, Vec<u8>)> = match decoder {
+                    Some(ref mut decoder) => decoder
+                        .feed(&buffer[..n])
+                        .into_iter()
+                        .map(|request| {
+                            let reply = framed::respond(request.as_ref().map_err(|&c| c), telemetry_counter);
+                            let request = match request {
+                                Ok(packet) => packet.encode(),
+                                Err(command) => vec![command],
+                            };
+                            (request, reply.encode())
+                        })
+                        .collect(),
+                    None => String::from_utf8_lossy(&buffer[..n])
+                        .lines()
+                        .filter(|line| !line.trim().is_empty())
+                        .map(|line| {
+                            let response = device_type.process_command(line);
+                            (line.as_bytes().to_vec(), response.into_bytes())
+                        })
+                        .collect(),
+                };
+                for (request, response) in exchanges {
+                    if args.verbose {
+                        println!("{}📤 RESPONSE: {}", prefix, device_type.display(&response));
+                    } else {
+                        println!(
+                            "{}← {} → {}",
+                            prefix,
+                            device_type.display(&request),
+                            device_type.display(&response)
+                        );
+                    }
+
+                    if let Err(e) = port.write_all(&response).await {
+                        eprintln!("{}Error sending response: {}", prefix, e);
+                    }
+                }
             }
-            _ => i += 1,
         }
     }
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let devices = args.devices();
+    if devices.is_empty() {
+        eprintln!("Error: give a <port> <device_type> pair or at least one --port <port>:<type>");
+        eprintln!("Run with --help for usage");
+        std::process::exit(2);
+    }
 
     println!("=== Mock Serial Device Simulator ===");
-    println!("Device Type: {}", device_type.name());
-    println!("Port: {}", port_name);
-    println!("Baud Rate: {}", baud_rate);
-    println!("Telemetry Interval: {}s", telemetry_interval);
-    println!("Echo Mode: {}", echo_mode);
-    println!("Verbose: {}", verbose);
+    for device in &devices {
+        println!("Device: {} on {}", device.device_type.name(), device.port);
+    }
+    println!("Telemetry Interval: {}s", args.telemetry);
+    println!("Echo Mode: {}", args.echo);
+    println!("Verbose: {}", args.verbose);
     println!();
 
-    // Open serial port
-    let mut port = match serialport::new(port_name, baud_rate)
-        .timeout(Duration::from_millis(100))
-        .open()
-    {
-        Ok(p) => p,
-        Err(e) => {
-            eprintln!("Error opening port {}: {}", port_name, e);
-            eprintln!();
-            eprintln!("On Linux/macOS, you may need to create virtual serial ports:");
-            eprintln!("  socat -d -d pty,raw,echo=0 pty,raw,echo=0");
-            eprintln!();
-            eprintln!("On macOS, you can also use:");
-            eprintln!("  brew install socat");
-            std::process::exit(1);
-        }
-    };
+    // Label output by port once there is more than one device
+    let labelled = devices.len() > 1;
+    let args = std::sync::Arc::new(args);
+    let mut tasks = tokio::task::JoinSet::new();
+    for device in devices {
+        let args = args.clone();
+        let label = labelled.then(|| device.port.clone());
+        tasks.spawn(async move { run_device(device, &args, label).await });
+    }
 
-    println!("✓ Serial port opened successfully");
-    println!("✓ Device is ready and listening...");
-    println!();
     println!("Press Ctrl+C to stop");
     println!("----------------------------------------");
     println!();
 
-    let mut buffer = [0u8; 256];
-    let mut telemetry_counter = 0u32;
-    let mut last_telemetry = std::time::Instant::now();
-    let telemetry_duration = Duration::from_secs(telemetry_interval);
-
+    let mut failed = false;
     loop {
-        // Check if it's time to send telemetry
-        if last_telemetry.elapsed() >= telemetry_duration {
-            let data = device_type.get_telemetry(telemetry_counter);
-            if verbose {
-                print!("📤 TELEMETRY: {}", data);
-            }
-            if let Err(e) = port.write_all(data.as_bytes()) {
-                eprintln!("Error sending telemetry: {}", e);
-            }
-            telemetry_counter += 1;
-            last_telemetry = std::time::Instant::now();
+        tokio::select! {
+            result = tasks.join_next() => match result {
+                Some(Ok(Err(e))) => {
+                    eprintln!("{}", e);
+                    failed = true;
+                }
+                Some(_) => {}
+                None => break,
+            },
+            _ = tokio::signal::ctrl_c() => return,
         }
+    }
 
-        // Read incoming data
-        match port.read(&mut buffer) {
-            Ok(n) if n > 0 => {
-                let received = String::from_utf8_lossy(&buffer[..n]);
+    if failed {
+        eprintln!();
+        eprintln!("On Linux/macOS, you may need to create virtual serial ports:");
+        eprintln!("  socat -d -d pty,raw,echo=0 pty,raw,echo=0");
+        eprintln!();
+        eprintln!("On macOS, you can also use:");
+        eprintln!("  brew install socat");
+        std::process::exit(1);
+    }
+}
 
-                if verbose {
-                    println!("📥 RECEIVED ({} bytes): {:?}", n, received.trim());
-                }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-                if echo_mode {
-                    if let Err(e) = port.write_all(&buffer[..n]) {
-                        eprintln!("Error echoing data: {}", e);
-                    }
-                }
+    #[test]
+    fn test_single_device_args() {
+        let args = Args::try_parse_from(["mock_device", "/dev/ttyUSB0", "iot"]).unwrap();
+        let devices = args.devices();
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices[0].port, "/dev/ttyUSB0");
+        assert!(matches!(devices[0].device_type, DeviceType::IoTSensor));
+        assert!(Args::try_parse_from(["mock_device", "/dev/ttyUSB0"]).is_err());
+        assert!(Args::try_parse_from(["mock_device", "/dev/ttyUSB0", "modem"]).is_err());
+    }
 
-                // Process commands
-                for line in received.lines() {
-                    if !line.trim().is_empty() {
-                        let response = device_type.process_command(line);
-
-                        if verbose {
-                            print!("📤 RESPONSE: {}", response);
-                        } else {
-                            print!("← {}", line.trim());
-                            print!(" → {}", response.trim());
-                            println!();
-                        }
-
-                        if let Err(e) = port.write_all(response.as_bytes()) {
-                            eprintln!("Error sending response: {}", e);
-                        }
-                    }
-                }
-            }
-            Ok(_) => {
-                // No data, continue
-            }
-            Err(ref e) if e.kind() == std::io::ErrorKind::TimedOut => {
-                // Timeout is expected, continue
-            }
-            Err(e) => {
-                eprintln!("Error reading from port: {}", e);
-                break;
-            }
+    #[test]
+    fn test_multiple_port_args() {
+        let args = Args::try_parse_from([
+            "mock_device",
+            "--port",
+            "/dev/pts/3:iot",
+            "--port",
+            "/dev/pts/5:plc",
+            "--telemetry",
+            "2",
+        ])
+        .unwrap();
+        let devices = args.devices();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[1].port, "/dev/pts/5");
+        assert!(matches!(devices[1].device_type, DeviceType::IndustrialPlc));
+        assert_eq!(args.telemetry, 2);
+        assert!(
+            Args::try_parse_from(["mock_device", "--port", "/p:iot", "--telemetry", "0"]).is_err()
+        );
+        assert!(Args::try_parse_from(["mock_device", "--port", "/dev/pts/3"]).is_err());
+        assert!(Args::try_parse_from(["mock_device", "--port", "/dev/pts/3:fax"]).is_err());
+    }
+
+    #[test]
+    fn test_framed_packets_round_trip() {
+        assert_eq!(framed::crc16(b"123456789"), 0x29B1);
+
+        let ping = framed::Packet::new(framed::PING, vec![0xA5, 0x01]);
+        let encoded = ping.encode();
+        assert_eq!(encoded[..5], [0xA5, 0x02, 0x01, 0xA5, 0x01]);
+
+        // Noise first, then the packet split across reads
+        let mut decoder = framed::Decoder::default();
+        assert!(decoder.feed(&[0x00, 0x42]).is_empty());
+        assert!(decoder.feed(&encoded[..4]).is_empty());
+        assert_eq!(decoder.feed(&encoded[4..]), [Ok(ping.clone())]);
+
+        let mut corrupt = ping.encode();
+        corrupt[4] ^= 0xFF;
+        assert_eq!(decoder.feed(&corrupt), [Err(framed::PING)]);
+    }
+
+    #[test]
+    fn test_framed_replies() {
+        let ping = framed::Packet::new(framed::PING, vec![7]);
+        assert_eq!(
+            framed::respond(Ok(&ping), 0),
+            framed::Packet::new(0x81, vec![7])
+        );
+        let status = framed::respond(Ok(&framed::Packet::new(framed::STATUS, vec![])), 3);
+        assert_eq!(status.payload, [0x00, 0, 0, 0, 3]);
+        let unknown = framed::respond(Ok(&framed::Packet::new(0x42, vec![])), 0);
+        assert_eq!(unknown.payload, [0x42, framed::UNKNOWN_COMMAND]);
+        let bad = framed::respond(Err(framed::READ), 0);
+        assert_eq!(bad.payload, [framed::READ, framed::BAD_CRC]);
+        assert_eq!(framed::telemetry(1).payload.len(), 6);
+    }
+
+    /// Sends `command` to a simulated device and reads one reply line.
+    async fn ask(port: &mut tokio_serial::SerialStream, command: &str) -> String {
+        port.write_all(command.as_bytes()).await.unwrap();
+        let mut reply = Vec::new();
+        let mut buffer = [0u8; 64];
+        while !reply.ends_with(b"\n") {
+            let n = tokio::time::timeout(Duration::from_secs(2), port.read(&mut buffer))
+                .await
+                .unwrap()
+                .unwrap();
+            reply.extend_from_slice(&buffer[..n]);
+        }
+        String::from_utf8(reply).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_devices_answer_on_their_own_ports() {
+        use tokio_serial::SerialStream;
+
+        let (mut iot, iot_device) = SerialStream::pair().unwrap();
+        let (mut plc, plc_device) = SerialStream::pair().unwrap();
+        let name =
+            |port: &SerialStream| tokio_serial::SerialPort::name(port).expect("pty has a name");
+        let args = Args::try_parse_from([
+            "mock_device".to_string(),
+            "--port".to_string(),
+            format!("{}:iot", name(&iot_device)),
+            "--port".to_string(),
+            format!("{}:plc", name(&plc_device)),
+        ])
+        .unwrap();
+        let args = std::sync::Arc::new(args);
+        for device in args.devices() {
+            let args = args.clone();
+            tokio::spawn(async move { run_device(device, &args, None).await });
         }
 
-        // Small delay to prevent CPU spinning
-        std::thread::sleep(Duration::from_millis(10));
+        // Let both devices open their ports
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert_eq!(ask(&mut iot, "ID\n").await, "ID:IOT-SENSOR-001\n");
+        assert_eq!(ask(&mut plc, "ID\n").await, "PLC-5000-SN:98765\n");
+        drop(iot_device);
+        drop(plc_device);
+    }
+
+    #[tokio::test]
+    async fn test_binary_device_answers_packets() {
+        use tokio_serial::SerialStream;
+
+        let (mut port, device) = SerialStream::pair().unwrap();
+        let path = tokio_serial::SerialPort::name(&device).unwrap();
+        let args = std::sync::Arc::new(
+            Args::try_parse_from(["mock_device".to_string(), path, "binary".to_string()]).unwrap(),
+        );
+        let spec = args.devices().remove(0);
+        let task_args = args.clone();
+        tokio::spawn(async move { run_device(spec, &task_args, None).await });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let request = framed::Packet::new(framed::PING, b"hi".to_vec());
+        port.write_all(&request.encode()).await.unwrap();
+        let expected = framed::Packet::new(0x81, b"hi".to_vec()).encode();
+        let mut reply = vec![0u8; expected.len()];
+        tokio::time::timeout(Duration::from_secs(2), port.read_exact(&mut reply))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(reply, expected);
+        drop(device);
     }
 }
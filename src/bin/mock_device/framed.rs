@@ -0,0 +1,141 @@
+//! Framed binary protocol spoken by the `binary` device profile.
+//!
+//! Each packet is `A5 <len> <cmd> <payload: len bytes> <crc hi> <crc lo>`,
+//! where the CRC is CRC-16/CCITT-FALSE over `len`, `cmd` and the payload.
+//!
+//! | Request | Reply |
+//! |---------|-------|
+//! | `01` PING, any payload | `81` with the same payload |
+//! | `02` STATUS | `82` status byte (`00` OK) and uptime in telemetry ticks (u32) |
+//! | `03` READ | `83` temperature and humidity, hundredths (i16, u16) |
+//!
+//! Every tick the device sends `90` with the tick count (u32) and the
+//! temperature (i16). Unknown commands and packets with a bad CRC are
+//! answered with `FF <cmd> <error>`, error `01` or `02` respectively.
+//! Integers are big-endian.
+
+pub const SYNC: u8 = 0xA5;
+
+pub const PING: u8 = 0x01;
+pub const STATUS: u8 = 0x02;
+pub const READ: u8 = 0x03;
+pub const TELEMETRY: u8 = 0x90;
+pub const NAK: u8 = 0xFF;
+
+pub const UNKNOWN_COMMAND: u8 = 0x01;
+pub const BAD_CRC: u8 = 0x02;
+
+/// Sync, length, command and CRC around the payload.
+const OVERHEAD: usize = 5;
+
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0xFFFF;
+    for &byte in data {
+        crc ^= (byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+    pub command: u8,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    pub fn new(command: u8, payload: Vec<u8>) -> Self {
+        assert!(payload.len() <= u8::MAX as usize, "payload too long");
+        Self { command, payload }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut packet = vec![SYNC, self.payload.len() as u8, self.command];
+        packet.extend_from_slice(&self.payload);
+        let crc = crc16(&packet[1..]);
+        packet.extend_from_slice(&crc.to_be_bytes());
+        packet
+    }
+}
+
+/// Reassembles packets from the byte stream; bytes before a sync byte are
+/// skipped.
+#[derive(Debug, Default)]
+pub struct Decoder {
+    buffer: Vec<u8>,
+}
+
+impl Decoder {
+    /// Complete packets in `data`; a packet with a bad CRC is returned as
+    /// `Err` with its command.
+    pub fn feed(&mut self, data: &[u8]) -> Vec<Result<Packet, u8>> {
+        self.buffer.extend_from_slice(data);
+        let mut packets = Vec::new();
+        loop {
+            match self.buffer.iter().position(|&b| b == SYNC) {
+                Some(start) => {
+                    self.buffer.drain(..start);
+                }
+                None => {
+                    self.buffer.clear();
+                    break;
+                }
+            }
+            let Some(&len) = self.buffer.get(1) else {
+                break;
+            };
+            let total = len as usize + OVERHEAD;
+            if self.buffer.len() < total {
+                break;
+            }
+            let frame: Vec<u8> = self.buffer.drain(..total).collect();
+            let command = frame[2];
+            let crc = u16::from_be_bytes([frame[total - 2], frame[total - 1]]);
+            if crc16(&frame[1..total - 2]) == crc {
+                packets.push(Ok(Packet::new(command, frame[3..total - 2].to_vec())));
+            } else {
+                packets.push(Err(command));
+            }
+        }
+        packets
+    }
+}
+
+fn temperature(tick: u32) -> i16 {
+    ((20.0 + (tick as f32 * 0.1).sin() * 5.0) * 100.0) as i16
+}
+
+pub fn telemetry(tick: u32) -> Packet {
+    let mut payload = tick.to_be_bytes().to_vec();
+    payload.extend_from_slice(&temperature(tick).to_be_bytes());
+    Packet::new(TELEMETRY, payload)
+}
+
+/// The reply to a decoded packet, or to one whose CRC did not match.
+pub fn respond(request: Result<&Packet, u8>, tick: u32) -> Packet {
+    let request = match request {
+        Ok(request) => request,
+        Err(command) => return Packet::new(NAK, vec![command, BAD_CRC]),
+    };
+    match request.command {
+        PING => Packet::new(PING | 0x80, request.payload.clone()),
+        STATUS => {
+            let mut payload = vec![0x00];
+            payload.extend_from_slice(&tick.to_be_bytes());
+            Packet::new(STATUS | 0x80, payload)
+        }
+        READ => {
+            let humidity = ((50.0 + (tick as f32 * 0.05).cos() * 10.0) * 100.0) as u16;
+            let mut payload = temperature(tick).to_be_bytes().to_vec();
+            payload.extend_from_slice(&humidity.to_be_bytes());
+            Packet::new(READ | 0x80, payload)
+        }
+        command => Packet::new(NAK, vec![command, UNKNOWN_COMMAND]),
+    }
+}
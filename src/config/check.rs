@@ -0,0 +1,271 @@
+//! Deep checks behind `webmux validate`: problems that parse fine but
+//! would stop connections from opening once the server starts.
+
+use super::{Config, MirrorTarget};
+use crate::serial::discovery::{self, PortDescription};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub level: Level,
+    /// Stable identifier for scripts, e.g. `missing_device`.
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Finding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let level = match self.level {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        };
+        write!(f, "{:<8}{}", level, self.code)?;
+        if let Some(ref connection) = self.connection {
+            write!(f, " [{}]", connection)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// False if any finding is an error; warnings alone do not fail.
+    pub valid: bool,
+    pub findings: Vec<Finding>,
+}
+
+impl Report {
+    fn new(findings: Vec<Finding>) -> Self {
+        Self {
+            valid: findings.iter().all(|finding| finding.level != Level::Error),
+            findings,
+        }
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Level::Error)
+    }
+
+    pub fn warnings(&self) -> usize {
+        self.count(Level::Warning)
+    }
+
+    fn count(&self, level: Level) -> usize {
+        self.findings
+            .iter()
+            .filter(|finding| finding.level == level)
+            .count()
+    }
+}
+
+fn finding(level: Level, code: &'static str, connection: Option<&str>, message: String) -> Finding {
+    Finding {
+        level,
+        code,
+        connection: connection.map(str::to_string),
+        message,
+    }
+}
+
+/// Loads, validates and deep-checks the configuration in `paths`, later
+/// files merged over earlier ones. With `lenient`, unknown keys are
+/// warnings rather than errors.
+pub fn check_file(paths: &[String], lenient: bool) -> Report {
+    let loaded = match Config::load_layered(paths, lenient) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            return Report::new(vec![finding(
+                Level::Error,
+                "parse_error",
+                None,
+                e.to_string(),
+            )])
+        }
+    };
+
+    let mut findings: Vec<Finding> = loaded
+        .ignored_keys
+        .into_iter()
+        .map(|message| finding(Level::Warning, "unknown_key", None, message))
+        .collect();
+    if let Some(from) = loaded.migrated_from {
+        findings.push(finding(
+            Level::Warning,
+            "outdated_version",
+            None,
+            format!(
+                "Configuration version {} is older than {}; run `webmux migrate-config` to update it",
+                from,
+                super::migrate::CURRENT_VERSION
+            ),
+        ));
+    }
+    let config = loaded.config;
+    let ports: Vec<PortDescription> = match serialport::available_ports() {
+        Ok(ports) => ports.into_iter().map(Into::into).collect(),
+        Err(e) => {
+            findings.push(finding(
+                Level::Warning,
+                "port_enumeration_failed",
+                None,
+                format!("Could not list serial ports: {}", e),
+            ));
+            Vec::new()
+        }
+    };
+    findings.extend(check(&config, &ports).findings);
+    findings.extend(check_server_address(&config));
+    Report::new(findings)
+}
+
+/// Runs `Config::validate` plus the checks that need the host: device
+/// paths, ports claimed twice, permissions and log directories. `ports`
+/// are the ports found on the host, used for names such as `COM3` that
+/// are not filesystem paths.
+pub fn check(config: &Config, ports: &[PortDescription]) -> Report {
+    let mut findings = Vec::new();
+    if let Err(e) = config.validate() {
+        findings.push(finding(Level::Error, "invalid_config", None, e.to_string()));
+    }
+
+    let mut claimed: HashMap<String, &str> = HashMap::new();
+    for conn in config.serial_connections.iter().filter(|conn| conn.enabled) {
+        let name = Some(conn.name.as_str());
+
+        let path = Path::new(&conn.port);
+        if path.exists() {
+            if !can_read_write(path) {
+                findings.push(finding(
+                    Level::Error,
+                    "device_permission",
+                    name,
+                    format!(
+                        "{} is not readable and writable by this user; on Linux add it to the dialout group",
+                        conn.port
+                    ),
+                ));
+            }
+        } else if discovery::find_port(ports, &conn.port).is_none() {
+            findings.push(finding(
+                Level::Error,
+                "missing_device",
+                name,
+                format!("{} does not exist", conn.port),
+            ));
+        }
+
+        if let Some(other) = claimed.insert(device_key(&conn.port), &conn.name) {
+            findings.push(finding(
+                Level::Error,
+                "duplicate_port",
+                name,
+                format!("{} is also used by connection {}", conn.port, other),
+            ));
+        }
+
+        if conn.logging.enabled {
+            if let Some(message) = unwritable_parent(&conn.logging.path) {
+                findings.push(finding(Level::Error, "log_dir_unwritable", name, message));
+            }
+        }
+        if let Some(MirrorTarget::File { ref path }) = conn.mirror.as_ref().map(|m| &m.target) {
+            if let Some(message) = unwritable_parent(path) {
+                findings.push(finding(
+                    Level::Error,
+                    "mirror_dir_unwritable",
+                    name,
+                    message,
+                ));
+            }
+        }
+    }
+
+    Report::new(findings)
+}
+
+/// Whether the server could bind its listen address right now. Only a
+/// warning, since the address may be held by a webmux already running.
+fn check_server_address(config: &Config) -> Option<Finding> {
+    let address = format!("{}:{}", config.server.host, config.server.port);
+    std::net::TcpListener::bind(&address).err().map(|e| {
+        finding(
+            Level::Warning,
+            "server_address_unavailable",
+            None,
+            format!("Cannot listen on {}: {}", address, e),
+        )
+    })
+}
+
+/// Key under which two spellings of one device compare equal, with
+/// symlinks such as `/dev/serial/by-id/...` resolved.
+fn device_key(port: &str) -> String {
+    match std::fs::canonicalize(port) {
+        Ok(path) => path.to_string_lossy().into_owned(),
+        Err(_) => discovery::port_key(port),
+    }
+}
+
+/// Why files cannot be created next to `path`, if they cannot. Missing
+/// directories are fine as long as the nearest existing one is writable,
+/// since the server creates them.
+fn unwritable_parent(path: &Path) -> Option<String> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let existing = parent.ancestors().find(|dir| dir.exists())?;
+    if !existing.is_dir() {
+        return Some(format!("{} is not a directory", existing.display()));
+    }
+    if !can_write_dir(existing) {
+        return Some(format!(
+            "{} is not writable by this user",
+            existing.display()
+        ));
+    }
+    None
+}
+
+#[cfg(unix)]
+fn access(path: &Path, mode: libc::c_int) -> bool {
+    use std::os::unix::ffi::OsStrExt;
+    let Ok(path) = std::ffi::CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: `path` is a valid NUL-terminated string that outlives the call
+    unsafe { libc::access(path.as_ptr(), mode) == 0 }
+}
+
+/// Checked without opening the device, which would reset boards that
+/// treat DTR as a reset line.
+#[cfg(unix)]
+fn can_read_write(path: &Path) -> bool {
+    access(path, libc::R_OK | libc::W_OK)
+}
+
+#[cfg(not(unix))]
+fn can_read_write(_path: &Path) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn can_write_dir(path: &Path) -> bool {
+    access(path, libc::W_OK | libc::X_OK)
+}
+
+#[cfg(not(unix))]
+fn can_write_dir(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| !metadata.permissions().readonly())
+}
@@ -0,0 +1,118 @@
+//! Differences between the connections a server is running and those its
+//! configuration files describe, i.e. what a restart would change.
+
+use super::{Config, SerialConnectionConfig};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// How often a running server compares itself with its configuration.
+pub const DRIFT_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The configuration files a server was started from, merged in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigFiles {
+    pub paths: Vec<String>,
+    pub lenient: bool,
+}
+
+impl ConfigFiles {
+    /// The connections the files describe now.
+    pub fn connections(&self) -> anyhow::Result<Vec<SerialConnectionConfig>> {
+        Ok(Config::load_layered(&self.paths, self.lenient)?
+            .config
+            .serial_connections)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModifiedConnection {
+    pub name: String,
+    /// Top-level settings whose running value differs from the file.
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConfigDiff {
+    /// Running, but not in the configuration, such as connections opened
+    /// through the API and virtual pairs. A restart drops them.
+    pub added: Vec<String>,
+    /// Enabled in the configuration but not running, because they were
+    /// removed at runtime or failed to open. A restart opens them.
+    pub removed: Vec<String>,
+    /// Running with settings other than the configuration's.
+    pub modified: Vec<ModifiedConnection>,
+}
+
+impl ConfigDiff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// E.g. `1 added, 0 removed, 2 modified`.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} added, {} removed, {} modified",
+            self.added.len(),
+            self.removed.len(),
+            self.modified.len()
+        )
+    }
+}
+
+/// Compares `running` connections with the enabled ones in `configured`.
+/// Each list in the result is sorted by name.
+pub fn diff(
+    configured: &[SerialConnectionConfig],
+    running: &[SerialConnectionConfig],
+) -> ConfigDiff {
+    let configured: BTreeMap<&str, &SerialConnectionConfig> = configured
+        .iter()
+        .filter(|config| config.enabled)
+        .map(|config| (config.name.as_str(), config))
+        .collect();
+    let running: BTreeMap<&str, &SerialConnectionConfig> = running
+        .iter()
+        .map(|config| (config.name.as_str(), config))
+        .collect();
+
+    let mut diff = ConfigDiff::default();
+    for (name, config) in &running {
+        match configured.get(name) {
+            Some(file) => {
+                let fields = changed_fields(file, config);
+                if !fields.is_empty() {
+                    diff.modified.push(ModifiedConnection {
+                        name: name.to_string(),
+                        fields,
+                    });
+                }
+            }
+            None => diff.added.push(name.to_string()),
+        }
+    }
+    diff.removed = configured
+        .keys()
+        .filter(|name| !running.contains_key(*name))
+        .map(|name| name.to_string())
+        .collect();
+    diff
+}
+
+/// Top-level keys whose values differ between `a` and `b`, sorted.
+pub fn changed_fields<T: Serialize>(a: &T, b: &T) -> Vec<String> {
+    let (Ok(serde_json::Value::Object(a)), Ok(serde_json::Value::Object(b))) =
+        (serde_json::to_value(a), serde_json::to_value(b))
+    else {
+        return Vec::new();
+    };
+    let mut fields: Vec<String> = a
+        .keys()
+        .chain(b.keys())
+        .filter(|key| a.get(*key) != b.get(*key))
+        .cloned()
+        .collect();
+    fields.sort();
+    fields.dedup();
+    fields
+}
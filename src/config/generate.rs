@@ -0,0 +1,109 @@
+//! Starter configuration for `webmux generate-config`, with one connection
+//! per serial port found on the host.
+
+use crate::serial::PortDescription;
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Baud rate written for every port; most USB adapters and dev boards
+/// default to it, and it is the first thing users edit otherwise.
+const DEFAULT_BAUD_RATE: u32 = 115200;
+
+/// JSON strings are valid double-quoted YAML scalars.
+fn quote(value: &str) -> String {
+    serde_json::to_string(value).unwrap_or_default()
+}
+
+/// Connection name from the product name, falling back to the device file
+/// name, e.g. `FT232R USB UART` -> `ft232r_usb_uart`.
+fn connection_name(port: &PortDescription) -> String {
+    let source = port.friendly_name.clone().unwrap_or_else(|| {
+        Path::new(&port.path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+    let mut name = String::new();
+    for c in source.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c.to_ascii_lowercase());
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_end_matches('_');
+    if name.is_empty() {
+        "port".to_string()
+    } else {
+        name.to_string()
+    }
+}
+
+/// Ready-to-edit YAML for `ports`. Ports with a `/dev/serial/by-id` link
+/// are configured through it so they keep their name when re-plugged.
+pub fn generate(ports: &[PortDescription]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# Generated by `webmux generate-config`.");
+    let _ = writeln!(
+        out,
+        "# Check baud rates and names, then disable or delete unused ports."
+    );
+    let _ = writeln!(out, "version: {}", super::migrate::CURRENT_VERSION);
+    let _ = writeln!(out);
+    let _ = writeln!(out, "server:");
+    let _ = writeln!(out, "  host: \"127.0.0.1\"");
+    let _ = writeln!(out, "  port: 8080");
+    let _ = writeln!(out);
+
+    if ports.is_empty() {
+        let _ = writeln!(out, "# No serial ports were found on this host.");
+        let _ = writeln!(out, "serial_connections: []");
+        return out;
+    }
+
+    let _ = writeln!(out, "serial_connections:");
+    let mut names = HashSet::new();
+    for (index, port) in ports.iter().enumerate() {
+        if index > 0 {
+            let _ = writeln!(out);
+        }
+        let base = connection_name(port);
+        let mut name = base.clone();
+        let mut suffix = 2;
+        while !names.insert(name.clone()) {
+            name = format!("{}_{}", base, suffix);
+            suffix += 1;
+        }
+
+        let path = match port.by_id {
+            Some(ref by_id) => {
+                let _ = writeln!(out, "  # Currently {}", port.path);
+                by_id
+            }
+            None => &port.path,
+        };
+        let _ = writeln!(out, "  - name: {}", quote(&name));
+        let _ = writeln!(out, "    port: {}", quote(path));
+        let _ = writeln!(out, "    baud_rate: {}", DEFAULT_BAUD_RATE);
+        let _ = writeln!(out, "    data_bits: 8");
+        let _ = writeln!(out, "    stop_bits: 1");
+        let _ = writeln!(out, "    parity: \"none\"  # Options: none, odd, even");
+        let _ = writeln!(
+            out,
+            "    flow_control: \"none\"  # Options: none, software, hardware"
+        );
+        let _ = writeln!(out, "    enabled: true");
+        let _ = writeln!(out, "    logging:");
+        let _ = writeln!(out, "      enabled: false");
+        let _ = writeln!(
+            out,
+            "      path: {}",
+            quote(&format!("./logs/{}.log", name))
+        );
+        if let Some(ref description) = port.description {
+            let _ = writeln!(out, "    description: {}", quote(description));
+        }
+    }
+    out
+}
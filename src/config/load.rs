@@ -0,0 +1,235 @@
+//! Strict YAML loading: unknown keys are errors, and every error names
+//! its line, column and key path.
+
+use super::{migrate, profiles, Config, Loaded};
+use regex::Regex;
+use serde_path_to_error::Segment;
+use serde_yaml::Value;
+use std::sync::OnceLock;
+
+type Error = serde_path_to_error::Error<serde_yaml::Error>;
+
+fn unknown_field_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"unknown field `([^`]*)`, expected (?:one of )?(.*)").expect("valid regex")
+    })
+}
+
+/// A key the config types do not have.
+struct UnknownKey {
+    /// Path of the mapping holding the key.
+    parent: Vec<Segment>,
+    key: String,
+    /// Closest known key, for typos such as `baudrate`.
+    suggestion: Option<String>,
+}
+
+fn unknown_key(error: &Error) -> Option<UnknownKey> {
+    let message = error.inner().to_string();
+    let captures = unknown_field_pattern().captures(&message)?;
+    let key = captures[1].to_string();
+    let expected: Vec<&str> = captures[2].split('`').skip(1).step_by(2).collect();
+
+    let mut parent: Vec<Segment> = error.path().iter().cloned().collect();
+    // The path ends at the key itself except inside flattened sections
+    if matches!(parent.last(), Some(Segment::Map { key: last }) if *last == key) {
+        parent.pop();
+    }
+    Some(UnknownKey {
+        parent,
+        suggestion: suggest(&key, &expected),
+        key,
+    })
+}
+
+/// The expected key within two edits of `key`, if there is one.
+pub(super) fn suggest(key: &str, expected: &[&str]) -> Option<String> {
+    expected
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), candidate))
+        .filter(|&(distance, _)| distance <= 2)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, candidate)| candidate.to_string())
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, &cb) in b.iter().enumerate() {
+            let substitute = previous[j] + usize::from(ca != cb);
+            current.push(substitute.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+/// `serial_connections[2] (plc_01).logging`, naming the connection an
+/// index refers to when the document says.
+fn describe_path(document: Option<&Value>, segments: &[Segment]) -> String {
+    let mut out = String::new();
+    let mut node = document;
+    for segment in segments {
+        match segment {
+            Segment::Seq { index } => {
+                out.push_str(&format!("[{}]", index));
+                node = node.and_then(|node| node.get(index));
+                if let Some(Value::String(name)) = node.and_then(|node| node.get("name")) {
+                    out.push_str(&format!(" ({})", name));
+                }
+            }
+            Segment::Map { key } => {
+                if !out.is_empty() {
+                    out.push('.');
+                }
+                out.push_str(key);
+                node = node.and_then(|node| node.get(key.as_str()));
+            }
+            Segment::Enum { .. } | Segment::Unknown => {}
+        }
+    }
+    out
+}
+
+/// `line:column: path: message`, without the path and location serde_yaml
+/// folds into its own message. `located` is false for errors in a
+/// rewritten document, whose positions mean nothing to the user.
+fn describe(document: Option<&Value>, error: &Error, located: bool) -> String {
+    let mut message = error.inner().to_string();
+    if let Some(location) = error.inner().location() {
+        let suffix = format!(" at line {} column {}", location.line(), location.column());
+        if let Some(stripped) = message.strip_suffix(&suffix) {
+            message = stripped.to_string();
+        }
+    }
+    if let Some((prefix, rest)) = message.split_once(": ") {
+        if !prefix.contains(char::is_whitespace) {
+            message = rest.to_string();
+        }
+    }
+
+    let location = error.inner().location().filter(|_| located);
+    let (path, message) = match unknown_key(error) {
+        Some(unknown) => {
+            let message = match unknown.suggestion {
+                Some(suggestion) => format!(
+                    "unknown key `{}`, did you mean `{}`?",
+                    unknown.key, suggestion
+                ),
+                None => message,
+            };
+            (describe_path(document, &unknown.parent), message)
+        }
+        None => {
+            let segments: Vec<Segment> = error.path().iter().cloned().collect();
+            (describe_path(document, &segments), message)
+        }
+    };
+
+    let mut out = String::new();
+    if let Some(location) = location {
+        out.push_str(&format!("{}:{}: ", location.line(), location.column()));
+    }
+    if !path.is_empty() {
+        out.push_str(&format!("{}: ", path));
+    }
+    out.push_str(&message);
+    out
+}
+
+fn remove_key(document: &mut Value, unknown: &UnknownKey) -> bool {
+    let mut node = document;
+    for segment in &unknown.parent {
+        node = match segment {
+            Segment::Seq { index } => match node.get_mut(index) {
+                Some(node) => node,
+                None => return false,
+            },
+            Segment::Map { key } => match node.get_mut(key.as_str()) {
+                Some(node) => node,
+                None => return false,
+            },
+            Segment::Enum { .. } | Segment::Unknown => node,
+        };
+    }
+    node.as_mapping_mut()
+        .and_then(|mapping| mapping.remove(unknown.key.as_str()))
+        .is_some()
+}
+
+fn deserialize(content: &str) -> Result<Config, Error> {
+    serde_path_to_error::deserialize(serde_yaml::Deserializer::from_str(content))
+}
+
+/// Parses `content`, upgrading older layouts and expanding device
+/// profiles first. With `lenient`, unknown keys are dropped instead of
+/// failing the load. `located` is false when `content` is not what the
+/// user wrote, so its positions are left out of errors.
+pub(super) fn parse(content: &str, lenient: bool, located: bool) -> anyhow::Result<Loaded> {
+    let mut document: Option<Value> = serde_yaml::from_str(content).ok();
+    let mut migrated_from = None;
+    // Positions in a re-serialized document mean nothing to the user
+    let mut located = located;
+    let mut text = std::borrow::Cow::Borrowed(content);
+    if let Some(ref mut document) = document {
+        let migrated = migrate::migrate(document)?;
+        if migrated.from != migrate::CURRENT_VERSION {
+            migrated_from = Some(migrated.from);
+        }
+        let expanded = profiles::expand(document)?;
+        if migrated.changed || expanded {
+            text = serde_yaml::to_string(document)?.into();
+            located = false;
+        }
+    }
+    let loaded = |mut config: Config, ignored_keys| {
+        config.version = migrate::CURRENT_VERSION;
+        Ok(Loaded {
+            config,
+            ignored_keys,
+            migrated_from,
+        })
+    };
+
+    let error = match deserialize(&text) {
+        Ok(config) => return loaded(config, Vec::new()),
+        Err(error) => error,
+    };
+    let fail =
+        |error: &Error, located| anyhow::anyhow!(describe(document.as_ref(), error, located));
+    let Some(mut stripped) = document.clone().filter(|_| lenient) else {
+        return Err(fail(&error, located));
+    };
+
+    // Retries parse the document re-serialized without the unknown keys,
+    // so scalars are read exactly as in the original text
+    let mut warnings = Vec::new();
+    let mut error = error;
+    loop {
+        let Some(unknown) = unknown_key(&error) else {
+            return Err(fail(&error, located));
+        };
+        if !remove_key(&mut stripped, &unknown) {
+            return Err(fail(&error, located));
+        }
+        let path = match describe_path(document.as_ref(), &unknown.parent) {
+            path if path.is_empty() => "the top level".to_string(),
+            path => path,
+        };
+        warnings.push(match unknown.suggestion {
+            Some(ref suggestion) => format!(
+                "Ignoring unknown key `{}` in {} (did you mean `{}`?)",
+                unknown.key, path, suggestion
+            ),
+            None => format!("Ignoring unknown key `{}` in {}", unknown.key, path),
+        });
+        match deserialize(&serde_yaml::to_string(&stripped)?) {
+            Ok(config) => return loaded(config, warnings),
+            Err(next) => error = next,
+        }
+        located = false;
+    }
+}
@@ -0,0 +1,48 @@
+//! Overlaying site-specific files on a base configuration
+//! (`webmux -c base.yaml -c site.yaml`).
+//!
+//! Mappings merge key by key, so an overlay only spells out what it
+//! changes. Lists whose entries all have a `name`, such as
+//! `serial_connections`, merge entry by entry on that name, and entries
+//! the base lacks are appended. Any other value in the overlay replaces
+//! the base's.
+
+use serde_yaml::Value;
+
+fn name(entry: &Value) -> Option<&Value> {
+    entry.as_mapping()?.get("name")
+}
+
+fn is_named_list(entries: &[Value]) -> bool {
+    entries.iter().all(|entry| name(entry).is_some())
+}
+
+/// Merges `overlay` into `base`.
+pub fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Sequence(base), Value::Sequence(overlay))
+            if is_named_list(base) && is_named_list(&overlay) =>
+        {
+            for entry in overlay {
+                match base
+                    .iter_mut()
+                    .find(|existing| name(existing) == name(&entry))
+                {
+                    Some(existing) => merge(existing, entry),
+                    None => base.push(entry),
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
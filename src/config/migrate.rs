@@ -0,0 +1,122 @@
+//! Configuration layout versions and the upgrades between them.
+//!
+//! Files without a `version` key are version 0. When a release changes
+//! the layout it bumps [`CURRENT_VERSION`] and appends a step to
+//! [`MIGRATIONS`] that rewrites the previous layout, so existing files
+//! keep loading and `webmux migrate-config` can update them on disk.
+
+use anyhow::Result;
+use regex::Regex;
+use serde_yaml::Value;
+use std::path::Path;
+
+/// Layout version written by this webmux.
+pub const CURRENT_VERSION: u32 = 1;
+
+/// Upgrades a document by one version, returning whether anything other
+/// than the version number changed.
+type Migration = fn(&mut Value) -> Result<bool>;
+
+/// `MIGRATIONS[n]` upgrades version `n` to `n + 1`.
+const MIGRATIONS: [Migration; CURRENT_VERSION as usize] = [
+    // Version 1 only started recording the version
+    |_| Ok(false),
+];
+
+/// Result of bringing a document up to [`CURRENT_VERSION`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Migrated {
+    pub from: u32,
+    /// Whether the layout changed, not just the version number.
+    pub changed: bool,
+}
+
+/// The `version` a document declares, 0 if it has none.
+pub fn document_version(document: &Value) -> Result<u32> {
+    match document.get("version") {
+        None => Ok(0),
+        Some(value) => value
+            .as_u64()
+            .and_then(|version| u32::try_from(version).ok())
+            .ok_or_else(|| anyhow::anyhow!("version must be a non-negative integer")),
+    }
+}
+
+/// Upgrades `document` in place and stamps it with [`CURRENT_VERSION`].
+pub fn migrate(document: &mut Value) -> Result<Migrated> {
+    let from = document_version(document)?;
+    if from > CURRENT_VERSION {
+        anyhow::bail!(
+            "Configuration version {} is newer than this webmux supports ({}); upgrade webmux",
+            from,
+            CURRENT_VERSION
+        );
+    }
+    let mut changed = false;
+    for (version, migration) in MIGRATIONS.iter().enumerate().skip(from as usize) {
+        changed |= migration(document)
+            .map_err(|e| anyhow::anyhow!("Migrating from version {}: {}", version, e))?;
+    }
+    if let Some(mapping) = document.as_mapping_mut() {
+        mapping.insert("version".into(), CURRENT_VERSION.into());
+    }
+    Ok(Migrated { from, changed })
+}
+
+/// `content` upgraded to the current layout, or `None` if it already is.
+/// When only the version number changes it is edited in place, keeping
+/// comments and formatting; otherwise the document is re-serialized.
+pub fn migrate_text(content: &str) -> Result<Option<String>> {
+    let mut document: Value = serde_yaml::from_str(content)?;
+    let migrated = migrate(&mut document)?;
+    if migrated.from == CURRENT_VERSION {
+        return Ok(None);
+    }
+    if migrated.changed {
+        return Ok(Some(format!(
+            "# Migrated from version {} by `webmux migrate-config`.\n{}",
+            migrated.from,
+            serde_yaml::to_string(&document)?
+        )));
+    }
+
+    let line = format!("version: {}", CURRENT_VERSION);
+    let existing = Regex::new(r"(?m)^version:.*$").expect("valid regex");
+    if existing.is_match(content) {
+        return Ok(Some(existing.replace(content, line.as_str()).into_owned()));
+    }
+    // After any leading comments, which usually describe the whole file
+    let mut out = String::with_capacity(content.len() + line.len() + 1);
+    let mut inserted = false;
+    for text in content.split_inclusive('\n') {
+        let trimmed = text.trim();
+        if !inserted && !trimmed.is_empty() && !trimmed.starts_with('#') {
+            out.push_str(&line);
+            out.push('\n');
+            inserted = true;
+        }
+        out.push_str(text);
+    }
+    if !inserted {
+        out.push_str(&line);
+        out.push('\n');
+    }
+    Ok(Some(out))
+}
+
+/// Rewrites the file at `path` in the current layout, keeping the
+/// original next to it as `<path>.bak`. Returns the version it was
+/// migrated from, or `None` if it was already current.
+pub fn migrate_file(path: &str) -> Result<Option<u32>> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path, e))?;
+    let from = document_version(&serde_yaml::from_str(&content)?)?;
+    let Some(migrated) = migrate_text(&content)? else {
+        return Ok(None);
+    };
+    let mut backup = Path::new(path).as_os_str().to_owned();
+    backup.push(".bak");
+    std::fs::write(&backup, &content)?;
+    std::fs::write(path, migrated)?;
+    Ok(Some(from))
+}
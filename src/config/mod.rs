@@ -1,19 +1,500 @@
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::path::PathBuf;
 
+pub mod check;
+pub mod drift;
+pub mod generate;
+mod load;
+pub mod merge;
+pub mod migrate;
+pub mod profiles;
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Layout version; see [`migrate`]. Files without one are version 0.
+    #[serde(default)]
+    pub version: u32,
     pub server: ServerConfig,
     pub serial_connections: Vec<SerialConnectionConfig>,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct ServerConfig {
     pub host: String,
     pub port: u16,
+    /// Channels that alerts and connection events can be routed to by name.
+    #[serde(default)]
+    pub notifications: Vec<NotificationChannelConfig>,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub websocket: WebSocketConfig,
+    /// Push connection metrics out periodically, for sites where nothing
+    /// can scrape webmux.
+    #[serde(default)]
+    pub metrics_push: Option<MetricsPushConfig>,
+    /// Read-only SNMPv2c agent for connection status.
+    #[serde(default)]
+    pub snmp: Option<SnmpConfig>,
+    /// gRPC service for machine-to-machine clients.
+    #[serde(default)]
+    pub grpc: Option<GrpcConfig>,
+    /// Record client sessions, separately from the device logs.
+    #[serde(default)]
+    pub audit: Option<AuditConfig>,
+    /// Daily byte allowance for all connections together.
+    #[serde(default)]
+    pub quota: Option<QuotaConfig>,
+    /// Local socket for `webmux attach`, which reaches the consoles
+    /// without going through HTTP.
+    #[serde(default)]
+    pub attach: Option<AttachConfig>,
+    /// Second listener for the administrative endpoints, which are then
+    /// no longer served on `host`:`port`.
+    #[serde(default)]
+    pub admin: Option<AdminConfig>,
+    /// Shutting down and restarting the process through the API.
+    #[serde(default)]
+    pub remote_control: Option<RemoteControlConfig>,
+    /// Pairs of connections tapping the two lines of a link, merged into
+    /// one labelled stream.
+    #[serde(default)]
+    pub sniffers: Vec<SnifferConfig>,
+    /// Daily captures uploaded for long-term archival.
+    #[serde(default)]
+    pub capture_jobs: Vec<CaptureJobConfig>,
+    /// Reverse proxies in front of webmux, whose word on the client's
+    /// address is taken.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Accept gateways dialling in as agents and relay API requests to them.
+    #[serde(default)]
+    pub hub: Option<HubConfig>,
+    /// Dial out to a hub so this gateway is reachable through it.
+    #[serde(default)]
+    pub hub_agent: Option<HubAgentConfig>,
+    /// Ports lent to external tools.
+    #[serde(default)]
+    pub lease: LeaseConfig,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            notifications: vec![],
+            limits: LimitsConfig::default(),
+            websocket: WebSocketConfig::default(),
+            metrics_push: None,
+            snmp: None,
+            grpc: None,
+            audit: None,
+            quota: None,
+            attach: None,
+            admin: None,
+            remote_control: None,
+            sniffers: vec![],
+            capture_jobs: vec![],
+            proxy: None,
+            hub: None,
+            hub_agent: None,
+            lease: LeaseConfig::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LeaseConfig {
+    /// Interface the temporary TCP listener of a `tcp` lease binds to.
+    #[serde(default = "default_lease_bind_host")]
+    pub bind_host: String,
+}
+
+impl Default for LeaseConfig {
+    fn default() -> Self {
+        Self {
+            bind_host: default_lease_bind_host(),
+        }
+    }
+}
+
+fn default_lease_bind_host() -> String {
+    "127.0.0.1".to_string()
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebSocketConfig {
+    /// Origins browsers may open WebSocket sessions from, e.g.
+    /// `https://console.example.com`, or `same-origin` to accept pages
+    /// served by webmux itself. Empty allows any origin.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// Most WebSocket sessions open at once across all connections.
+    #[serde(default)]
+    pub max_clients: Option<usize>,
+    /// Most WebSocket sessions open at once from one client address.
+    #[serde(default)]
+    pub max_clients_per_address: Option<usize>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    /// Addresses or CIDR ranges of the proxies, e.g. `10.0.0.0/8`.
+    /// `X-Forwarded-For` is only believed from these.
+    #[serde(default)]
+    pub trusted: Vec<String>,
+    /// Every connection to the main listener starts with a PROXY protocol
+    /// (v1 or v2) header giving the client's address, as sent by HAProxy
+    /// with `send-proxy` or nginx with `proxy_protocol on`. Needs
+    /// `trusted`, as connections from other peers are refused.
+    #[serde(default)]
+    pub protocol: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnmpConfig {
+    /// UDP address to listen on, e.g. `0.0.0.0:161`.
+    pub address: String,
+    #[serde(default = "default_snmp_community")]
+    pub community: String,
+    /// OID the webmux MIB is rooted at; defaults to the Net-SNMP
+    /// experimental subtree, which sites should replace with their own
+    /// enterprise number.
+    #[serde(default = "default_snmp_base_oid")]
+    pub base_oid: String,
+}
+
+fn default_snmp_community() -> String {
+    "public".to_string()
+}
+
+fn default_snmp_base_oid() -> String {
+    "1.3.6.1.4.1.8072.9999.9999".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcConfig {
+    /// TCP address to serve cleartext HTTP/2 on, e.g. `0.0.0.0:50051`.
+    pub address: String,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    /// TCP address, e.g. `127.0.0.1:8081`.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Unix socket path, instead of `address`.
+    #[serde(default)]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SnifferConfig {
+    pub name: String,
+    /// Connection whose port listens to the link's TX line.
+    pub tx: String,
+    /// Connection whose port listens to the link's RX line.
+    pub rx: String,
+    /// Append the merged traffic here, one JSON record per line.
+    #[serde(default)]
+    pub capture_file: Option<PathBuf>,
+}
+
+impl SnifferConfig {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("Sniffer name must not be empty");
+        }
+        if self.tx == self.rx {
+            anyhow::bail!(
+                "Sniffer {}: tx and rx must be different connections",
+                self.name
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Captures `connections` for `duration_minutes` every day at `at` and
+/// uploads the bundle.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct CaptureJobConfig {
+    pub name: String,
+    pub connections: Vec<String>,
+    /// Time of day the capture starts, `HH:MM` in UTC.
+    pub at: String,
+    pub duration_minutes: u64,
+    pub upload: UploadTarget,
+    /// Notification channels told when a run fails.
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum UploadTarget {
+    /// PUT the bundle as JSON to `<url>/<job>/<start time>.json`.
+    Http {
+        url: String,
+        /// File holding the token sent as `Authorization: Bearer <token>`.
+        /// Read on every upload, so it can be rotated in place.
+        #[serde(default)]
+        token_file: Option<PathBuf>,
+    },
+    /// PUT the bundle as an object in an S3-compatible bucket, addressed
+    /// path-style as `<endpoint>/<bucket>/<prefix><job>/<start time>.json`.
+    S3(S3Config),
+}
+
+/// A bucket in an S3-compatible store such as AWS, MinIO or Ceph.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct S3Config {
+    /// Base URL of the store, e.g. `https://s3.eu-west-1.amazonaws.com`.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_s3_region")]
+    pub region: String,
+    /// Put in front of every object name.
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key_id: String,
+    /// File holding the secret access key. Read on every upload, so it can
+    /// be rotated in place.
+    pub secret_access_key_file: PathBuf,
+}
+
+fn default_s3_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// Fails unless `path` holds a non-empty bearer token for `section`.
+fn check_token_file(section: &str, path: &std::path::Path) -> anyhow::Result<()> {
+    check_secret_file(&format!("{} token", section), path)
+}
+
+/// Fails unless `path` holds a non-empty `what`, such as a key.
+fn check_secret_file(what: &str, path: &std::path::Path) -> anyhow::Result<()> {
+    let secret = std::fs::read_to_string(path)
+        .map_err(|e| anyhow::anyhow!("Cannot read {} file {}: {}", what, path.display(), e))?;
+    if secret.trim().is_empty() {
+        anyhow::bail!("{} file {} is empty", what, path.display());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HubConfig {
+    /// File holding the token agents present as `Authorization: Bearer
+    /// <token>`. Read on every connection, so it can be rotated in place.
+    pub token_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct HubAgentConfig {
+    /// The hub's agent endpoint, e.g. `wss://hub.example.com/api/hub/connect`.
+    pub url: String,
+    /// Name this gateway is listed and reached under on the hub.
+    pub name: String,
+    /// File holding the hub's token.
+    pub token_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteControlConfig {
+    /// File holding the token clients send as `Authorization: Bearer
+    /// <token>`. Read on every request, so it can be rotated in place.
+    pub token_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttachConfig {
+    /// Unix socket to listen on. Only the user running webmux may use it.
+    #[serde(default = "default_attach_socket")]
+    pub socket: PathBuf,
+}
+
+pub fn default_attach_socket() -> PathBuf {
+    PathBuf::from("/run/webmux/attach.sock")
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditConfig {
+    /// JSON-lines file the records are appended to.
+    pub path: PathBuf,
+    /// Also record the exact bytes each WebSocket session sends, for
+    /// reviewing who typed what after an incident.
+    #[serde(default)]
+    pub keystrokes: bool,
+    /// Start a new file once the active one would exceed this size.
+    #[serde(default)]
+    pub max_file_bytes: Option<u64>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MetricsPushConfig {
+    #[serde(flatten)]
+    pub target: MetricsPushTarget,
+    #[serde(default = "default_push_interval_secs")]
+    pub interval_secs: u64,
+}
+
+fn default_push_interval_secs() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum MetricsPushTarget {
+    /// POST InfluxDB line protocol to a write endpoint, e.g.
+    /// `http://influx:8086/api/v2/write?org=lab&bucket=webmux`.
+    Influxdb {
+        url: String,
+        /// Sent as `Authorization: Token <token>`.
+        #[serde(default)]
+        token: Option<String>,
+    },
+    /// Write line protocol to a Telegraf `socket_listener` (`host:port`).
+    Telegraf {
+        address: String,
+        #[serde(default)]
+        transport: TelegrafTransport,
+    },
+    /// PUT the Prometheus text format to a Pushgateway under `job`.
+    Pushgateway {
+        url: String,
+        #[serde(default = "default_push_job")]
+        job: String,
+    },
+}
+
+fn default_push_job() -> String {
+    "webmux".to_string()
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TelegrafTransport {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+/// Caps on what clients may send, so a misbehaving one cannot make the
+/// server buffer arbitrarily large payloads.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Largest decoded payload accepted by `/send`.
+    #[serde(default = "default_max_send_bytes")]
+    pub max_send_bytes: usize,
+    /// Largest body accepted by the flashing and upload endpoints.
+    #[serde(default = "default_max_upload_bytes")]
+    pub max_upload_bytes: usize,
+    /// Largest message accepted from a WebSocket client.
+    #[serde(default = "default_max_ws_message_bytes")]
+    pub max_ws_message_bytes: usize,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            max_send_bytes: default_max_send_bytes(),
+            max_upload_bytes: default_max_upload_bytes(),
+            max_ws_message_bytes: default_max_ws_message_bytes(),
+        }
+    }
+}
+
+fn default_max_send_bytes() -> usize {
+    1024 * 1024
+}
+
+fn default_max_upload_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+fn default_max_ws_message_bytes() -> usize {
+    64 * 1024
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NotificationChannelConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub kind: NotificationKind,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum NotificationKind {
+    /// Slack incoming webhook.
+    Slack { webhook_url: String },
+    /// Discord channel webhook.
+    Discord { webhook_url: String },
+    /// Generic JSON POST of the notification.
+    Webhook {
+        url: String,
+        #[serde(default)]
+        headers: HashMap<String, String>,
+        /// Shared secret for the `X-Webmux-Signature` HMAC of each body.
+        #[serde(default)]
+        secret: Option<String>,
+    },
+    /// Email through an SMTP relay.
+    Smtp(SmtpConfig),
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    #[serde(default)]
+    pub security: SmtpSecurity,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    Starttls,
+    /// Implicit TLS, usually on port 465.
+    Tls,
+    /// Plaintext, for local relays only.
+    None,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct SerialConnectionConfig {
     pub name: String,
     pub port: String,
@@ -26,12 +507,608 @@ pub struct SerialConnectionConfig {
     pub logging: LoggingConfig,
     #[serde(default)]
     pub description: String,
+    #[serde(default)]
+    pub mode: ConnectionMode,
+    #[serde(default)]
+    pub slcan: SlcanConfig,
+    #[serde(default)]
+    pub metrics: Vec<MetricConfig>,
+    /// Notification channels told when the port is lost.
+    #[serde(default)]
+    pub notify: Vec<String>,
+    /// Copy all traffic to a secondary sink, e.g. a protocol analyzer.
+    #[serde(default)]
+    pub mirror: Option<MirrorConfig>,
+    /// Pace writes to the port so slow devices are not overrun.
+    #[serde(default)]
+    pub max_tx_rate_bytes_per_sec: Option<u32>,
+    /// Most WebSocket sessions open on this connection at once.
+    #[serde(default)]
+    pub max_clients: Option<usize>,
+    /// Merge queued writes into fewer, larger writes to the port.
+    #[serde(default)]
+    pub tx_batch: TxBatchConfig,
+    /// Hide the device's echo of a WebSocket client's own writes from that
+    /// client.
+    #[serde(default)]
+    pub suppress_echo: bool,
+    /// Close the port after this many minutes without clients, reopening it
+    /// when one attaches or writes.
+    #[serde(default)]
+    pub idle_close_minutes: Option<u64>,
+    /// Connection that must be open before this one is opened.
+    #[serde(default)]
+    pub depends_on: Option<String>,
+    /// Wait this long before opening the port, after any dependency.
+    #[serde(default)]
+    pub startup_delay_ms: u64,
+    /// How to switch the attached device on and off.
+    #[serde(default)]
+    pub power: Option<PowerConfig>,
+    /// Shown to clients when they attach, e.g. what the device is, who owns
+    /// it and what not to do. Never sent to the device.
+    #[serde(default)]
+    pub banner: Option<String>,
+    /// Let only one WebSocket session write at a time.
+    #[serde(default)]
+    pub write_lock: Option<WriteLockConfig>,
+    /// Daily byte allowance for this connection.
+    #[serde(default)]
+    pub quota: Option<QuotaConfig>,
+    /// Warn when received text looks like the noise of a baud mismatch.
+    #[serde(default)]
+    pub garbage_check: Option<GarbageCheckConfig>,
+    /// Write something when nothing has been written for a while, so
+    /// switches and modems do not drop their console session.
+    #[serde(default)]
+    pub keepalive: Option<KeepaliveConfig>,
+    /// Find out the attached device's model and firmware when the
+    /// connection opens.
+    #[serde(default)]
+    pub identify: Option<IdentifyConfig>,
+    /// Exchanges the traffic must follow; violations are counted in the
+    /// stats and sent to `notify`.
+    #[serde(default)]
+    pub rules: Vec<RuleConfig>,
+    /// Values read by writing a command and matching the reply, each served
+    /// at `/api/connections/<name>/<endpoint name>`.
+    #[serde(default)]
+    pub endpoints: Vec<EndpointConfig>,
+    /// Reopen the port when it goes away, e.g. when a USB adapter is
+    /// unplugged, instead of closing the connection.
+    #[serde(default)]
+    pub reconnect: Option<ReconnectConfig>,
+}
+
+/// A line that must be answered: after one matching `when`, sent or
+/// received, a line matching `expect` has to follow within `within_ms`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RuleConfig {
+    pub name: String,
+    /// Regex matched against each line, e.g. `^REQ`.
+    pub when: String,
+    /// Regex the answering line must match, e.g. `^ACK`.
+    pub expect: String,
+    pub within_ms: u64,
+}
+
+/// A value read on request: `command` is written and the first reply line
+/// matching `pattern` answers it.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct EndpointConfig {
+    /// The last path segment it is served at, e.g. `pressure`.
+    pub name: String,
+    /// Written to the device, e.g. `"P?\r\n"`.
+    pub command: String,
+    /// Regex matched against each reply line; the first capture group holds
+    /// the value, e.g. `^P=(\d+\.\d+)`, or the whole match without one.
+    pub pattern: String,
+    /// How long to wait for a reply that matches.
+    #[serde(default = "default_endpoint_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Serve the last reading for this long before asking the device
+    /// again; every request asks without it.
+    #[serde(default)]
+    pub poll_interval_ms: Option<u64>,
+}
+
+fn default_endpoint_timeout_ms() -> u64 {
+    2000
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdentifyConfig {
+    /// Written to the device, e.g. `"*IDN?\r\n"`. Without it the
+    /// adapter's USB descriptor is read instead.
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Regex with `model` and `firmware` named groups, matched against each
+    /// line of the reply or against the USB descriptor. Required with
+    /// `command`; without either, the whole descriptor is the model.
+    #[serde(default)]
+    pub pattern: Option<String>,
+    /// How long to wait for a reply that matches.
+    #[serde(default = "default_identify_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+fn default_identify_timeout_ms() -> u64 {
+    2000
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct KeepaliveConfig {
+    /// Minutes without writes before `data` is sent, and between repeats
+    /// while the connection stays idle.
+    #[serde(default = "default_keepalive_idle_minutes")]
+    pub idle_minutes: u64,
+    #[serde(default = "default_keepalive_data")]
+    pub data: String,
+}
+
+/// Longest keepalive `idle_minutes`, a day; far larger values overflow
+/// the keepalive deadline.
+pub const MAX_KEEPALIVE_IDLE_MINUTES: u64 = 1440;
+
+fn default_keepalive_idle_minutes() -> u64 {
+    5
+}
+
+fn default_keepalive_data() -> String {
+    "\n".to_string()
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            idle_minutes: default_keepalive_idle_minutes(),
+            data: default_keepalive_data(),
+        }
+    }
+}
+
+impl KeepaliveConfig {
+    pub fn idle(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.idle_minutes * 60)
+    }
+}
+
+/// Thresholds for flagging received data as likely garbage.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GarbageCheckConfig {
+    /// Share of non-printable bytes, in percent, that marks a window of
+    /// received data as garbage.
+    #[serde(default = "default_garbage_threshold_percent")]
+    pub threshold_percent: u8,
+    /// Received bytes judged together.
+    #[serde(default = "default_garbage_window_bytes")]
+    pub window_bytes: usize,
+}
+
+fn default_garbage_threshold_percent() -> u8 {
+    30
+}
+
+fn default_garbage_window_bytes() -> usize {
+    256
+}
+
+impl Default for GarbageCheckConfig {
+    fn default() -> Self {
+        Self {
+            threshold_percent: default_garbage_threshold_percent(),
+            window_bytes: default_garbage_window_bytes(),
+        }
+    }
+}
+
+/// Bytes a connection, or the server, may move per day (UTC), for
+/// gateways on links billed by volume.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct QuotaConfig {
+    #[serde(default)]
+    pub daily_rx_bytes: Option<u64>,
+    #[serde(default)]
+    pub daily_tx_bytes: Option<u64>,
+    /// What happens to writes once either allowance is used up.
+    #[serde(default)]
+    pub action: QuotaAction,
+    /// Write rate for the rest of the day with `action: throttle`.
+    #[serde(default)]
+    pub throttle_bytes_per_sec: Option<u32>,
+    /// Channels told when an allowance is used up; on a connection, its
+    /// own `notify` channels when empty.
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum QuotaAction {
+    /// Only send a notification.
+    #[default]
+    Warn,
+    /// Slow writes down to `throttle_bytes_per_sec`.
+    Throttle,
+    /// Refuse writes until the next day.
+    Block,
+}
+
+impl QuotaConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.daily_rx_bytes.is_none() && self.daily_tx_bytes.is_none() {
+            anyhow::bail!("quota needs daily_rx_bytes or daily_tx_bytes");
+        }
+        if self.daily_rx_bytes == Some(0) || self.daily_tx_bytes == Some(0) {
+            anyhow::bail!("quota daily bytes must be greater than 0");
+        }
+        match (self.action, self.throttle_bytes_per_sec) {
+            (QuotaAction::Throttle, None | Some(0)) => {
+                anyhow::bail!("quota throttle_bytes_per_sec must be greater than 0")
+            }
+            (QuotaAction::Warn | QuotaAction::Block, Some(_)) => {
+                anyhow::bail!("quota throttle_bytes_per_sec needs action: throttle")
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReconnectConfig {
+    /// Wait before the first attempt to reopen the port; doubled after
+    /// each failed one.
+    #[serde(default = "default_reconnect_interval_ms")]
+    pub interval_ms: u64,
+    /// Longest wait between attempts.
+    #[serde(default = "default_reconnect_max_interval_ms")]
+    pub max_interval_ms: u64,
+    /// Give up and close the connection after this many failed attempts;
+    /// keeps trying without it.
+    #[serde(default)]
+    pub max_attempts: Option<u32>,
+}
+
+fn default_reconnect_interval_ms() -> u64 {
+    1000
+}
+
+fn default_reconnect_max_interval_ms() -> u64 {
+    30_000
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            interval_ms: default_reconnect_interval_ms(),
+            max_interval_ms: default_reconnect_max_interval_ms(),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriteLockConfig {
+    /// Release the lock once its holder has sent nothing for this long.
+    #[serde(default)]
+    pub idle_release_minutes: Option<u64>,
+}
+
+impl WriteLockConfig {
+    pub fn idle_release(&self) -> Option<std::time::Duration> {
+        self.idle_release_minutes
+            .map(|minutes| std::time::Duration::from_secs(minutes * 60))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionMode {
+    /// Bytes are passed through untouched.
+    #[default]
+    Raw,
+    /// The port is an SLCAN (CAN-over-serial) adapter.
+    Slcan,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SlcanConfig {
+    #[serde(default = "default_can_bitrate")]
+    pub bitrate: u32,
+}
+
+impl Default for SlcanConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: default_can_bitrate(),
+        }
+    }
+}
+
+fn default_can_bitrate() -> u32 {
+    500_000
+}
+
+/// How queued writes are merged before they reach the port. Messages
+/// already waiting are always merged up to `max_bytes`; `max_delay_ms`
+/// additionally holds a short batch back for more to arrive.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TxBatchConfig {
+    #[serde(default = "default_tx_batch_bytes")]
+    pub max_bytes: usize,
+    #[serde(default)]
+    pub max_delay_ms: u64,
+}
+
+impl Default for TxBatchConfig {
+    fn default() -> Self {
+        Self {
+            max_bytes: default_tx_batch_bytes(),
+            max_delay_ms: 0,
+        }
+    }
+}
+
+fn default_tx_batch_bytes() -> usize {
+    4096
+}
+
+/// A numeric value extracted from received lines with a regex.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricConfig {
+    pub name: String,
+    /// Regex matched against each received line; the first capture group
+    /// holds the value, e.g. `TEMP:(\d+\.\d+)`.
+    pub pattern: String,
+    /// Number of samples kept in memory.
+    #[serde(default = "default_metric_history")]
+    pub history: usize,
+    #[serde(default)]
+    pub alerts: Vec<AlertConfig>,
+}
+
+fn default_metric_history() -> usize {
+    1000
+}
+
+/// Fires when a metric stays outside its thresholds for `samples`
+/// consecutive readings, and resolves after as many readings back inside.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlertConfig {
+    pub name: String,
+    #[serde(default)]
+    pub above: Option<f64>,
+    #[serde(default)]
+    pub below: Option<f64>,
+    #[serde(default = "default_alert_samples")]
+    pub samples: usize,
+    /// URL that receives a JSON POST whenever the alert fires or resolves.
+    #[serde(default)]
+    pub webhook: Option<String>,
+    /// Shared secret for the `X-Webmux-Signature` HMAC of each webhook body.
+    #[serde(default)]
+    pub webhook_secret: Option<String>,
+    /// Notification channels told whenever the alert fires or resolves.
+    #[serde(default)]
+    pub notify: Vec<String>,
+}
+
+fn default_alert_samples() -> usize {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct MirrorConfig {
+    #[serde(flatten)]
+    pub target: MirrorTarget,
+    #[serde(default)]
+    pub direction: MirrorDirection,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum MirrorTarget {
+    /// Append raw bytes to a file.
+    File { path: PathBuf },
+    /// Stream raw bytes to a TCP endpoint (`host:port`), reconnecting as needed.
+    Tcp { address: String },
+    /// Write the bytes out through another connection's port.
+    Connection { connection: String },
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PowerConfig {
+    #[serde(flatten)]
+    pub control: PowerControl,
+    /// Command, URL or text that switches the device on.
+    pub on: String,
+    /// Command, URL or text that switches the device off.
+    pub off: String,
+    /// Pause between switching off and on again in a power cycle.
+    #[serde(default = "default_power_cycle_delay_ms")]
+    pub cycle_delay_ms: u64,
+}
+
+fn default_power_cycle_delay_ms() -> u64 {
+    5000
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum PowerControl {
+    /// `on` and `off` are shell commands.
+    Command,
+    /// `on` and `off` are URLs to POST to, e.g. on a networked PDU.
+    Http {
+        #[serde(default)]
+        headers: HashMap<String, String>,
+    },
+    /// `on` and `off` are text written through another connection, e.g.
+    /// a PDU's serial console.
+    Connection { connection: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorDirection {
+    #[default]
+    Both,
+    Rx,
+    Tx,
+}
+
+impl MirrorDirection {
+    pub fn includes_rx(self) -> bool {
+        self != MirrorDirection::Tx
+    }
+
+    pub fn includes_tx(self) -> bool {
+        self != MirrorDirection::Rx
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct LoggingConfig {
     pub enabled: bool,
     pub path: PathBuf,
+    /// Capture exact bytes instead of the text format: RX to `<stem>.bin`
+    /// and TX to `<stem>.tx.bin` next to `path`.
+    #[serde(default)]
+    pub raw: bool,
+    /// Start a new file once the active one would exceed this size; the old
+    /// one is renamed with a timestamp (`dev.20251130-153045123456.log`).
+    #[serde(default)]
+    pub max_file_bytes: Option<u64>,
+    /// Limits on the files kept for this connection.
+    #[serde(default)]
+    pub retention: Option<RetentionConfig>,
+    /// Forward every RX/TX record to a syslog collector, independently of
+    /// the file log.
+    #[serde(default)]
+    pub syslog: Option<SyslogConfig>,
+    /// Encrypt the file log, for consoles where operators type credentials.
+    #[serde(default)]
+    pub encryption: Option<LogEncryptionConfig>,
+    /// Scrub credentials from the file log, syslog and audit records.
+    #[serde(default)]
+    pub redact: Option<RedactConfig>,
+    /// Upload rotated files to an S3-compatible bucket and delete them
+    /// once stored. `{connection}` and `{date}` in the prefix are filled in.
+    #[serde(default)]
+    pub ship: Option<S3Config>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedactConfig {
+    /// Regexes for prompts the device sends, e.g. `(?i)password:`. The
+    /// next line sent after one is masked.
+    #[serde(default)]
+    pub prompts: Vec<String>,
+    /// Regexes masked wherever they appear in either direction; if one
+    /// has a capture group, only the group is masked.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogEncryptionConfig {
+    /// File holding the AES-256 key as 64 hex digits, e.g. from
+    /// `openssl rand -hex 32`.
+    pub key_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Budget for the connection's log files, active ones included.
+    #[serde(default)]
+    pub max_bytes: Option<u64>,
+    /// Rotated files older than this are deleted.
+    #[serde(default)]
+    pub max_age_days: Option<u64>,
+}
+
+impl LoggingConfig {
+    /// Size at which log files rotate. Without an explicit `max_file_bytes`,
+    /// a byte budget implies rotating at a quarter of it so old data can be
+    /// dropped.
+    pub fn rotation_size(&self) -> Option<u64> {
+        self.max_file_bytes.or_else(|| {
+            self.retention
+                .as_ref()
+                .and_then(|retention| retention.max_bytes)
+                .map(|max_bytes| (max_bytes / 4).max(1))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct SyslogConfig {
+    /// Collector address as `host:port`.
+    pub address: String,
+    #[serde(default)]
+    pub transport: SyslogTransport,
+    #[serde(default)]
+    pub facility: SyslogFacility,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogTransport {
+    #[default]
+    Udp,
+    Tcp,
+    Tls,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SyslogFacility {
+    User,
+    Daemon,
+    #[default]
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl SyslogFacility {
+    /// Facility code as defined in RFC 5424.
+    pub fn code(self) -> u8 {
+        match self {
+            SyslogFacility::User => 1,
+            SyslogFacility::Daemon => 3,
+            SyslogFacility::Local0 => 16,
+            SyslogFacility::Local1 => 17,
+            SyslogFacility::Local2 => 18,
+            SyslogFacility::Local3 => 19,
+            SyslogFacility::Local4 => 20,
+            SyslogFacility::Local5 => 21,
+            SyslogFacility::Local6 => 22,
+            SyslogFacility::Local7 => 23,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq)]
@@ -112,20 +1189,358 @@ impl From<FlowControl> for serialport::FlowControl {
     }
 }
 
+/// A configuration file as loaded, with what was done to make it fit.
+#[derive(Debug, Clone)]
+pub struct Loaded {
+    pub config: Config,
+    /// Unknown keys dropped in lenient mode.
+    pub ignored_keys: Vec<String>,
+    /// Layout version the file was upgraded from, if it was older.
+    pub migrated_from: Option<u32>,
+}
+
 impl Config {
+    /// Loads `path`, failing on keys webmux does not know.
     pub fn from_file(path: &str) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: Config = serde_yaml::from_str(&content)?;
-        Ok(config)
+        Ok(Self::load(path, false)?.config)
+    }
+
+    /// Loads `path`, upgrading older layouts. With `lenient`, unknown keys
+    /// are ignored and reported instead of failing the load. Errors read
+    /// `path:line:column: key path: message`.
+    pub fn load(path: &str, lenient: bool) -> anyhow::Result<Loaded> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path, e))?;
+        load::parse(&content, lenient, true).map_err(|e| anyhow::anyhow!("{}:{}", path, e))
+    }
+
+    /// Parses a whole configuration sent rather than read from a file,
+    /// upgrading older layouts. Errors read `line:column: key path: message`.
+    pub fn from_yaml(content: &str) -> anyhow::Result<Loaded> {
+        load::parse(content, false, true)
+    }
+
+    /// Loads `paths[0]` with each later file merged over it (see
+    /// [`merge`]). Errors name the files but not lines, which mean
+    /// nothing once the files are combined.
+    pub fn load_layered(paths: &[String], lenient: bool) -> anyhow::Result<Loaded> {
+        let [base, overlays @ ..] = paths else {
+            anyhow::bail!("No configuration file given");
+        };
+        if overlays.is_empty() {
+            return Self::load(base, lenient);
+        }
+
+        let mut merged = serde_yaml::Value::Null;
+        for path in paths {
+            let content = std::fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path, e))?;
+            let document: serde_yaml::Value =
+                serde_yaml::from_str(&content).map_err(|e| anyhow::anyhow!("{}: {}", path, e))?;
+            merge::merge(&mut merged, document);
+        }
+        let content = serde_yaml::to_string(&merged)?;
+        load::parse(&content, lenient, false)
+            .map_err(|e| anyhow::anyhow!("{}: {}", paths.join(" + "), e))
     }
 
     pub fn validate(&self) -> anyhow::Result<()> {
+        crate::notifications::Notifier::new(&self.server.notifications)?;
+        let channels: std::collections::HashSet<_> = self
+            .server
+            .notifications
+            .iter()
+            .map(|channel| channel.name.as_str())
+            .collect();
+        let check_channels = |conn: &str, names: &[String]| -> anyhow::Result<()> {
+            match names.iter().find(|name| !channels.contains(name.as_str())) {
+                Some(name) => {
+                    anyhow::bail!("Connection {}: unknown notification channel {}", conn, name)
+                }
+                None => Ok(()),
+            }
+        };
+
         // Check for duplicate connection names
         let mut names = std::collections::HashSet::new();
         for conn in &self.serial_connections {
             if !names.insert(&conn.name) {
                 anyhow::bail!("Duplicate connection name: {}", conn.name);
             }
+
+            if conn.mode == ConnectionMode::Slcan {
+                crate::slcan::init_commands(conn.slcan.bitrate)
+                    .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+            }
+
+            if conn.max_tx_rate_bytes_per_sec == Some(0) {
+                anyhow::bail!(
+                    "Connection {}: max_tx_rate_bytes_per_sec must be greater than 0",
+                    conn.name
+                );
+            }
+
+            if conn
+                .write_lock
+                .as_ref()
+                .is_some_and(|lock| lock.idle_release_minutes == Some(0))
+            {
+                anyhow::bail!(
+                    "Connection {}: write_lock idle_release_minutes must be greater than 0",
+                    conn.name
+                );
+            }
+            if let Some(ref check) = conn.garbage_check {
+                if !(1..=100).contains(&check.threshold_percent) {
+                    anyhow::bail!(
+                        "Connection {}: garbage_check threshold_percent must be between 1 and 100",
+                        conn.name
+                    );
+                }
+                if check.window_bytes == 0 {
+                    anyhow::bail!(
+                        "Connection {}: garbage_check window_bytes must be greater than 0",
+                        conn.name
+                    );
+                }
+            }
+            if let Some(ref keepalive) = conn.keepalive {
+                if keepalive.idle_minutes == 0
+                    || keepalive.idle_minutes > MAX_KEEPALIVE_IDLE_MINUTES
+                {
+                    anyhow::bail!(
+                        "Connection {}: keepalive idle_minutes must be between 1 and {}",
+                        conn.name,
+                        MAX_KEEPALIVE_IDLE_MINUTES
+                    );
+                }
+                if keepalive.data.is_empty() {
+                    anyhow::bail!("Connection {}: keepalive data must not be empty", conn.name);
+                }
+            }
+            if let Some(ref reconnect) = conn.reconnect {
+                crate::serial::reconnect::validate(reconnect)
+                    .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+            }
+            if conn.idle_close_minutes == Some(0) {
+                anyhow::bail!(
+                    "Connection {}: idle_close_minutes must be greater than 0",
+                    conn.name
+                );
+            }
+
+            if conn.tx_batch.max_bytes == 0 {
+                anyhow::bail!(
+                    "Connection {}: tx_batch.max_bytes must be greater than 0",
+                    conn.name
+                );
+            }
+            if conn.tx_batch.max_delay_ms > 1000 {
+                anyhow::bail!(
+                    "Connection {}: tx_batch.max_delay_ms must be at most 1000",
+                    conn.name
+                );
+            }
+
+            if let Some(PowerControl::Connection { ref connection }) =
+                conn.power.as_ref().map(|power| &power.control)
+            {
+                if *connection == conn.name {
+                    anyhow::bail!(
+                        "Connection {}: power is controlled through itself",
+                        conn.name
+                    );
+                }
+                if !self
+                    .serial_connections
+                    .iter()
+                    .any(|other| other.name == *connection)
+                {
+                    anyhow::bail!(
+                        "Connection {}: power connection {} does not exist",
+                        conn.name,
+                        connection
+                    );
+                }
+            }
+
+            crate::telemetry::validate(&conn.metrics)
+                .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+            crate::serial::rules::validate(&conn.rules)
+                .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+            crate::serial::endpoints::validate(&conn.endpoints)
+                .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+
+            if let Some(ref redact) = conn.logging.redact {
+                crate::logging::redact::Redactor::new(redact)
+                    .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+            }
+
+            if let Some(ref ship) = conn.logging.ship {
+                crate::logging::ship::validate(ship)
+                    .map_err(|e| anyhow::anyhow!("Connection {}: ship: {}", conn.name, e))?;
+                check_secret_file(
+                    &format!("Connection {}: ship secret_access_key", conn.name),
+                    &ship.secret_access_key_file,
+                )?;
+            }
+
+            if let Some(ref encryption) = conn.logging.encryption {
+                crate::logging::encryption::LogKey::load(&encryption.key_file)
+                    .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+            }
+
+            if let Some(ref identify) = conn.identify {
+                crate::serial::identify::compile(identify)
+                    .map_err(|e| anyhow::anyhow!("Connection {}: identify {}", conn.name, e))?;
+            }
+
+            if let Some(ref quota) = conn.quota {
+                quota
+                    .validate()
+                    .map_err(|e| anyhow::anyhow!("Connection {}: {}", conn.name, e))?;
+                check_channels(&conn.name, &quota.notify)?;
+            }
+
+            check_channels(&conn.name, &conn.notify)?;
+            for alert in conn.metrics.iter().flat_map(|metric| &metric.alerts) {
+                check_channels(&conn.name, &alert.notify)?;
+            }
+        }
+
+        self.validate_mirrors()?;
+        self.validate_dependencies()?;
+
+        let limits = &self.server.limits;
+        if limits.max_send_bytes == 0
+            || limits.max_upload_bytes == 0
+            || limits.max_ws_message_bytes == 0
+        {
+            anyhow::bail!("Server limits must be greater than 0");
+        }
+
+        if let Some(ref push) = self.server.metrics_push {
+            if push.interval_secs == 0 {
+                anyhow::bail!("metrics_push interval_secs must be greater than 0");
+            }
+            if let MetricsPushTarget::Pushgateway { ref job, .. } = push.target {
+                if job.is_empty() || job.contains('/') {
+                    anyhow::bail!("metrics_push job must be non-empty and must not contain '/'");
+                }
+            }
+        }
+
+        if let Some(ref admin) = self.server.admin {
+            if admin.address.is_some() == admin.socket.is_some() {
+                anyhow::bail!("admin needs exactly one of address and socket");
+            }
+        }
+
+        if self.server.websocket.max_clients_per_address == Some(0) {
+            anyhow::bail!("websocket max_clients_per_address must be greater than 0");
+        }
+        if let Some(ref proxy) = self.server.proxy {
+            crate::web::proxy::TrustedProxies::parse(&proxy.trusted)
+                .map_err(|e| anyhow::anyhow!("proxy: {}", e))?;
+            // Otherwise any client could name itself in a PROXY header
+            if proxy.protocol && proxy.trusted.is_empty() {
+                anyhow::bail!("proxy: protocol needs the trusted proxies listed in trusted");
+            }
+        }
+
+        let mut sniffers = std::collections::HashSet::new();
+        for sniffer in &self.server.sniffers {
+            if !sniffers.insert(&sniffer.name) {
+                anyhow::bail!("Duplicate sniffer name: {}", sniffer.name);
+            }
+            sniffer.validate()?;
+            for tap in [&sniffer.tx, &sniffer.rx] {
+                if !self.serial_connections.iter().any(|conn| &conn.name == tap) {
+                    anyhow::bail!(
+                        "Sniffer {}: connection {} does not exist",
+                        sniffer.name,
+                        tap
+                    );
+                }
+            }
+        }
+
+        let mut jobs = std::collections::HashSet::new();
+        for job in &self.server.capture_jobs {
+            if !jobs.insert(&job.name) {
+                anyhow::bail!("Duplicate capture job name: {}", job.name);
+            }
+            crate::serial::capture_jobs::validate(job)
+                .map_err(|e| anyhow::anyhow!("Capture job {}: {}", job.name, e))?;
+            match job.upload {
+                UploadTarget::Http {
+                    token_file: Some(ref path),
+                    ..
+                } => check_token_file(&format!("Capture job {}: upload", job.name), path)?,
+                UploadTarget::Http { .. } => {}
+                UploadTarget::S3(ref s3) => check_secret_file(
+                    &format!("Capture job {}: upload secret_access_key", job.name),
+                    &s3.secret_access_key_file,
+                )?,
+            }
+            if let Some(name) = job.connections.iter().find(|name| {
+                !self
+                    .serial_connections
+                    .iter()
+                    .any(|conn| &conn.name == *name)
+            }) {
+                anyhow::bail!(
+                    "Capture job {}: connection {} does not exist",
+                    job.name,
+                    name
+                );
+            }
+            if let Some(name) = job
+                .notify
+                .iter()
+                .find(|name| !channels.contains(name.as_str()))
+            {
+                anyhow::bail!(
+                    "Capture job {}: unknown notification channel {}",
+                    job.name,
+                    name
+                );
+            }
+        }
+
+        if let Some(ref control) = self.server.remote_control {
+            check_token_file("remote_control", &control.token_file)?;
+        }
+
+        if let Some(ref hub) = self.server.hub {
+            check_token_file("hub", &hub.token_file)?;
+        }
+        if let Some(ref agent) = self.server.hub_agent {
+            crate::web::hub::validate_agent(agent)
+                .map_err(|e| anyhow::anyhow!("hub_agent: {}", e))?;
+            check_token_file("hub_agent", &agent.token_file)?;
+        }
+
+        if let Some(ref audit) = self.server.audit {
+            if audit.max_file_bytes == Some(0) {
+                anyhow::bail!("audit max_file_bytes must be greater than 0");
+            }
+        }
+
+        if let Some(ref quota) = self.server.quota {
+            quota.validate()?;
+            if let Some(name) = quota
+                .notify
+                .iter()
+                .find(|name| !channels.contains(name.as_str()))
+            {
+                anyhow::bail!("quota: unknown notification channel {}", name);
+            }
+        }
+
+        if let Some(ref snmp) = self.server.snmp {
+            crate::snmp::ber::parse_oid(&snmp.base_oid)
+                .map_err(|e| anyhow::anyhow!("snmp base_oid: {}", e))?;
         }
 
         // Validate port numbers
@@ -135,6 +1550,73 @@ impl Config {
 
         Ok(())
     }
+
+    /// Connection mirrors must name another configured connection and must
+    /// not loop back, which would echo traffic forever.
+    fn validate_mirrors(&self) -> anyhow::Result<()> {
+        let targets: std::collections::HashMap<&str, Option<&str>> = self
+            .serial_connections
+            .iter()
+            .map(|conn| {
+                let target = match conn.mirror.as_ref().map(|mirror| &mirror.target) {
+                    Some(MirrorTarget::Connection { connection }) => Some(connection.as_str()),
+                    _ => None,
+                };
+                (conn.name.as_str(), target)
+            })
+            .collect();
+
+        for conn in &self.serial_connections {
+            let mut current = conn.name.as_str();
+            let mut hops = 0;
+            while let Some(Some(next)) = targets.get(current) {
+                if !targets.contains_key(next) {
+                    anyhow::bail!(
+                        "Connection {}: mirror target {} does not exist",
+                        current,
+                        next
+                    );
+                }
+                if *next == conn.name || hops > targets.len() {
+                    anyhow::bail!("Connection {}: mirror loops back to itself", conn.name);
+                }
+                current = next;
+                hops += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_dependencies(&self) -> anyhow::Result<()> {
+        let dependencies: std::collections::HashMap<&str, Option<&str>> = self
+            .serial_connections
+            .iter()
+            .map(|conn| (conn.name.as_str(), conn.depends_on.as_deref()))
+            .collect();
+
+        for conn in &self.serial_connections {
+            // The chain of dependencies from this connection
+            let mut chain = vec![conn.name.as_str()];
+            while let Some(Some(next)) = chain.last().and_then(|current| dependencies.get(current))
+            {
+                if !dependencies.contains_key(next) {
+                    anyhow::bail!(
+                        "Connection {}: depends on {}, which does not exist",
+                        chain.last().expect("never empty"),
+                        next
+                    );
+                }
+                // Named from where the cycle starts, which may be further on
+                if let Some(start) = chain.iter().position(|name| name == next) {
+                    let mut cycle = chain.split_off(start);
+                    cycle.push(next);
+                    anyhow::bail!("dependency cycle {}", cycle.join(" → "));
+                }
+                chain.push(next);
+            }
+        }
+        Ok(())
+    }
 }
 
 #[cfg(test)]
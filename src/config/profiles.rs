@@ -0,0 +1,180 @@
+//! Named presets for well-known device classes.
+//!
+//! A connection with `profile: cisco-console` gets the preset's baud rate,
+//! framing and flow control for any of those keys it leaves out; keys it
+//! sets itself win.
+
+use super::{DataBits, FlowControl, Parity, StopBits};
+use serde_yaml::Value;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub stop_bits: StopBits,
+    pub parity: Parity,
+    pub flow_control: FlowControl,
+}
+
+const fn preset(
+    name: &'static str,
+    description: &'static str,
+    baud_rate: u32,
+    parity: Parity,
+    stop_bits: StopBits,
+) -> Profile {
+    Profile {
+        name,
+        description,
+        baud_rate,
+        data_bits: DataBits::Eight,
+        stop_bits,
+        parity,
+        flow_control: FlowControl::None,
+    }
+}
+
+pub const PROFILES: &[Profile] = &[
+    preset(
+        "cisco-console",
+        "Cisco IOS console port",
+        9600,
+        Parity::None,
+        StopBits::One,
+    ),
+    preset(
+        "juniper-console",
+        "Juniper Junos console port",
+        9600,
+        Parity::None,
+        StopBits::One,
+    ),
+    preset(
+        "arduino",
+        "Arduino sketch using Serial.begin(9600)",
+        9600,
+        Parity::None,
+        StopBits::One,
+    ),
+    preset(
+        "esp32",
+        "ESP32/ESP8266 boot and IDF console",
+        115200,
+        Parity::None,
+        StopBits::One,
+    ),
+    preset(
+        "raspberry-pi",
+        "Raspberry Pi UART console",
+        115200,
+        Parity::None,
+        StopBits::One,
+    ),
+    preset(
+        "gps-nmea",
+        "NMEA 0183 GPS receiver",
+        4800,
+        Parity::None,
+        StopBits::One,
+    ),
+    preset(
+        "modbus-9600-8E1",
+        "Modbus RTU, 9600 baud, even parity",
+        9600,
+        Parity::Even,
+        StopBits::One,
+    ),
+    preset(
+        "modbus-19200-8E1",
+        "Modbus RTU, 19200 baud, even parity",
+        19200,
+        Parity::Even,
+        StopBits::One,
+    ),
+    preset(
+        "modbus-9600-8N2",
+        "Modbus RTU, 9600 baud, no parity",
+        9600,
+        Parity::None,
+        StopBits::Two,
+    ),
+];
+
+pub fn find(name: &str) -> Option<&'static Profile> {
+    PROFILES.iter().find(|profile| profile.name == name)
+}
+
+impl Profile {
+    /// Preset keys in config form, e.g. `("data_bits", "8")`.
+    fn settings(&self) -> [(&'static str, Value); 5] {
+        fn value(setting: impl serde::Serialize) -> Value {
+            serde_yaml::to_value(setting).expect("config enums serialize")
+        }
+        [
+            ("baud_rate", self.baud_rate.into()),
+            ("data_bits", value(self.data_bits)),
+            ("stop_bits", value(self.stop_bits)),
+            ("parity", value(self.parity)),
+            ("flow_control", value(self.flow_control)),
+        ]
+    }
+
+    /// Fills the keys `connection` leaves out and drops its `profile` key.
+    pub fn apply(&self, connection: &mut serde_yaml::Mapping) {
+        connection.remove("profile");
+        for (key, value) in self.settings() {
+            if !connection.contains_key(key) {
+                connection.insert(key.into(), value);
+            }
+        }
+    }
+}
+
+/// Expands the `profile` of every connection in `document`, returning
+/// whether any were found.
+pub fn expand(document: &mut Value) -> anyhow::Result<bool> {
+    let Some(connections) = document
+        .get_mut("serial_connections")
+        .and_then(Value::as_sequence_mut)
+    else {
+        return Ok(false);
+    };
+    let mut expanded = false;
+    for (index, connection) in connections.iter_mut().enumerate() {
+        let Some(connection) = connection.as_mapping_mut() else {
+            continue;
+        };
+        let Some(name) = connection.get("profile") else {
+            continue;
+        };
+        let label = match connection.get("name") {
+            Some(Value::String(conn)) => format!("serial_connections[{}] ({})", index, conn),
+            _ => format!("serial_connections[{}]", index),
+        };
+        let Some(name) = name.as_str() else {
+            anyhow::bail!("{}.profile: expected a profile name", label);
+        };
+        let Some(profile) = find(name) else {
+            let names: Vec<&str> = PROFILES.iter().map(|profile| profile.name).collect();
+            if let Some(suggestion) = super::load::suggest(name, &names) {
+                anyhow::bail!(
+                    "{}.profile: unknown profile `{}`, did you mean `{}`?",
+                    label,
+                    name,
+                    suggestion
+                );
+            }
+            anyhow::bail!(
+                "{}.profile: unknown profile `{}`; known profiles are {}",
+                label,
+                name,
+                names.join(", ")
+            );
+        };
+        profile.apply(connection);
+        expanded = true;
+    }
+    Ok(expanded)
+}
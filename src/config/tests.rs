@@ -1,4 +1,5 @@
 use super::*;
+use crate::serial::PortDescription;
 use std::io::Write;
 use tempfile::NamedTempFile;
 
@@ -39,7 +40,7 @@ serial_connections:
     assert_eq!(config.serial_connections[0].stop_bits, StopBits::One);
     assert_eq!(config.serial_connections[0].parity, Parity::None);
     assert_eq!(config.serial_connections[0].flow_control, FlowControl::None);
-    assert_eq!(config.serial_connections[0].enabled, true);
+    assert!(config.serial_connections[0].enabled);
 }
 
 #[test]
@@ -278,5 +279,1386 @@ serial_connections:
     let config = Config::from_file(file.path().to_str().unwrap()).unwrap();
 
     assert_eq!(config.serial_connections.len(), 1);
-    assert_eq!(config.serial_connections[0].enabled, false);
+    assert!(!config.serial_connections[0].enabled);
+}
+
+#[test]
+fn test_config_slcan_mode() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "can_bus"
+    port: "/dev/ttyACM0"
+    baud_rate: 115200
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    mode: "slcan"
+    slcan:
+      bitrate: 250000
+    logging:
+      enabled: false
+      path: "./logs/can.log"
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.serial_connections[0].mode, ConnectionMode::Slcan);
+    assert_eq!(config.serial_connections[0].slcan.bitrate, 250000);
+    config.validate().unwrap();
+
+    let mut bad = config.clone();
+    bad.serial_connections[0].slcan.bitrate = 42;
+    assert!(bad.validate().is_err());
+}
+
+#[test]
+fn test_config_mode_defaults_to_raw() {
+    let yaml = r#"
+name: "plain"
+port: "/dev/ttyUSB0"
+baud_rate: 9600
+data_bits: 8
+stop_bits: 1
+parity: "none"
+flow_control: "none"
+enabled: true
+logging:
+  enabled: false
+  path: "./logs/plain.log"
+"#;
+
+    let conn: SerialConnectionConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(conn.mode, ConnectionMode::Raw);
+    assert_eq!(conn.slcan.bitrate, 500_000);
+}
+
+#[test]
+fn test_config_metrics() {
+    let yaml = r#"
+name: "sensor"
+port: "/dev/ttyUSB0"
+baud_rate: 9600
+data_bits: 8
+stop_bits: 1
+parity: "none"
+flow_control: "none"
+enabled: true
+logging:
+  enabled: false
+  path: "./logs/sensor.log"
+metrics:
+  - name: "temperature"
+    pattern: 'TEMP:(\d+\.\d+)'
+    alerts:
+      - name: "overheat"
+        above: 80.0
+        samples: 3
+        webhook: "http://localhost:9000/hook"
+"#;
+
+    let conn: SerialConnectionConfig = serde_yaml::from_str(yaml).unwrap();
+    let metric = &conn.metrics[0];
+    assert_eq!(metric.name, "temperature");
+    assert_eq!(metric.pattern, r"TEMP:(\d+\.\d+)");
+    assert_eq!(metric.history, 1000);
+    assert_eq!(metric.alerts[0].above, Some(80.0));
+    assert_eq!(metric.alerts[0].below, None);
+    assert_eq!(metric.alerts[0].samples, 3);
+
+    let mut config = Config {
+        version: migrate::CURRENT_VERSION,
+        server: ServerConfig {
+            host: "127.0.0.1".to_string(),
+            port: 8080,
+            ..Default::default()
+        },
+        serial_connections: vec![conn],
+    };
+    config.validate().unwrap();
+
+    config.serial_connections[0].metrics[0].pattern = "TEMP".to_string();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_admin_listener_needs_one_address() {
+    let mut config = Config::from_yaml(
+        r#"
+server:
+  host: "0.0.0.0"
+  port: 8080
+  admin:
+    address: "127.0.0.1:8081"
+serial_connections: []
+"#,
+    )
+    .unwrap()
+    .config;
+    config.validate().unwrap();
+
+    let admin = config.server.admin.as_mut().unwrap();
+    admin.socket = Some(PathBuf::from("/run/webmux/admin.sock"));
+    assert!(config.validate().is_err());
+    let admin = config.server.admin.as_mut().unwrap();
+    admin.address = None;
+    admin.socket = None;
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_rules_validated() {
+    let yaml = r#"
+name: "plc"
+port: "/dev/ttyUSB0"
+baud_rate: 9600
+data_bits: 8
+stop_bits: 1
+parity: "none"
+flow_control: "none"
+enabled: true
+logging:
+  enabled: false
+  path: "./logs/plc.log"
+rules:
+  - name: "req-ack"
+    when: "^REQ"
+    expect: "^ACK"
+    within_ms: 500
+"#;
+
+    let conn: SerialConnectionConfig = serde_yaml::from_str(yaml).unwrap();
+    let mut config = Config {
+        version: migrate::CURRENT_VERSION,
+        server: ServerConfig::default(),
+        serial_connections: vec![conn],
+    };
+    config.validate().unwrap();
+
+    let rule = &mut config.serial_connections[0].rules[0];
+    rule.expect = "(ACK".to_string();
+    assert!(config.validate().is_err());
+    let rule = &mut config.serial_connections[0].rules[0];
+    rule.expect = "^ACK".to_string();
+    rule.within_ms = 0;
+    assert!(config.validate().is_err());
+    let rule = &mut config.serial_connections[0].rules[0];
+    rule.within_ms = 500;
+    let duplicate = rule.clone();
+    config.serial_connections[0].rules.push(duplicate);
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_endpoints_validated() {
+    let yaml = r#"
+name: "plc"
+port: "/dev/ttyUSB0"
+baud_rate: 9600
+data_bits: 8
+stop_bits: 1
+parity: "none"
+flow_control: "none"
+enabled: true
+logging:
+  enabled: false
+  path: "./logs/plc.log"
+endpoints:
+  - name: "pressure"
+    command: "P?\r\n"
+    pattern: "^P=(\\d+\\.\\d+)"
+"#;
+
+    let conn: SerialConnectionConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(conn.endpoints[0].command, "P?\r\n");
+    assert_eq!(conn.endpoints[0].timeout_ms, 2000);
+    let mut config = Config {
+        version: migrate::CURRENT_VERSION,
+        server: ServerConfig::default(),
+        serial_connections: vec![conn],
+    };
+    config.validate().unwrap();
+
+    let endpoint = &mut config.serial_connections[0].endpoints[0];
+    endpoint.pattern = "(P=".to_string();
+    assert!(config.validate().is_err());
+    let endpoint = &mut config.serial_connections[0].endpoints[0];
+    endpoint.pattern = "^P=(.*)".to_string();
+    endpoint.name = "stats".to_string();
+    assert!(config.validate().is_err());
+    let endpoint = &mut config.serial_connections[0].endpoints[0];
+    endpoint.name = "a/b".to_string();
+    assert!(config.validate().is_err());
+    let endpoint = &mut config.serial_connections[0].endpoints[0];
+    endpoint.name = "pressure".to_string();
+    endpoint.timeout_ms = 0;
+    assert!(config.validate().is_err());
+    let endpoint = &mut config.serial_connections[0].endpoints[0];
+    endpoint.timeout_ms = 2000;
+    endpoint.poll_interval_ms = Some(0);
+    assert!(config.validate().is_err());
+    let endpoint = &mut config.serial_connections[0].endpoints[0];
+    endpoint.poll_interval_ms = Some(5000);
+    config.validate().unwrap();
+    let endpoint = &mut config.serial_connections[0].endpoints[0];
+    let duplicate = endpoint.clone();
+    config.serial_connections[0].endpoints.push(duplicate);
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_reconnect_validated() {
+    let yaml = r#"
+name: "usb"
+port: "/dev/ttyUSB0"
+baud_rate: 115200
+data_bits: 8
+stop_bits: 1
+parity: "none"
+flow_control: "none"
+enabled: true
+logging:
+  enabled: false
+  path: "./logs/usb.log"
+reconnect:
+  max_attempts: 10
+"#;
+
+    let conn: SerialConnectionConfig = serde_yaml::from_str(yaml).unwrap();
+    let reconnect = conn.reconnect.as_ref().unwrap();
+    assert_eq!(reconnect.interval_ms, 1000);
+    assert_eq!(reconnect.max_interval_ms, 30_000);
+    let mut config = Config {
+        version: migrate::CURRENT_VERSION,
+        server: ServerConfig::default(),
+        serial_connections: vec![conn],
+    };
+    config.validate().unwrap();
+
+    let reconnect = config.serial_connections[0].reconnect.as_mut().unwrap();
+    reconnect.max_attempts = Some(0);
+    assert!(config.validate().is_err());
+    let reconnect = config.serial_connections[0].reconnect.as_mut().unwrap();
+    reconnect.max_attempts = None;
+    reconnect.interval_ms = 0;
+    assert!(config.validate().is_err());
+    let reconnect = config.serial_connections[0].reconnect.as_mut().unwrap();
+    reconnect.interval_ms = 60_000;
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_log_shipping_validated() {
+    let yaml = r#"
+name: "plc"
+port: "/dev/ttyUSB0"
+baud_rate: 9600
+data_bits: 8
+stop_bits: 1
+parity: "none"
+flow_control: "none"
+enabled: true
+logging:
+  enabled: true
+  path: "./logs/plc.log"
+  max_file_bytes: 1048576
+  ship:
+    endpoint: "https://minio.example.com"
+    bucket: "serial-logs"
+    prefix: "site-a/{connection}/{date}/"
+    access_key_id: "AKID"
+    secret_access_key_file: "{secret}"
+"#;
+    let dir = tempfile::tempdir().unwrap();
+    let secret_file = dir.path().join("secret");
+    std::fs::write(&secret_file, "secret\n").unwrap();
+    let yaml = yaml.replace("{secret}", &secret_file.display().to_string());
+
+    let conn: SerialConnectionConfig = serde_yaml::from_str(&yaml).unwrap();
+    let mut config = Config {
+        version: migrate::CURRENT_VERSION,
+        server: ServerConfig::default(),
+        serial_connections: vec![conn],
+    };
+    config.validate().unwrap();
+    let ship = config.serial_connections[0].logging.ship.as_mut().unwrap();
+    assert_eq!(ship.region, "us-east-1");
+
+    ship.prefix = "{host}/".to_string();
+    assert!(config.validate().is_err());
+    let ship = config.serial_connections[0].logging.ship.as_mut().unwrap();
+    ship.prefix = "{date".to_string();
+    assert!(config.validate().is_err());
+    let ship = config.serial_connections[0].logging.ship.as_mut().unwrap();
+    ship.prefix = String::new();
+    ship.bucket = "a/b".to_string();
+    assert!(config.validate().is_err());
+    let ship = config.serial_connections[0].logging.ship.as_mut().unwrap();
+    ship.bucket = "serial-logs".to_string();
+    config.validate().unwrap();
+    std::fs::write(&secret_file, "\n").unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_proxy_validated() {
+    let mut config = Config::from_yaml(
+        r#"
+server:
+  host: "0.0.0.0"
+  port: 8080
+  websocket:
+    max_clients_per_address: 4
+  proxy:
+    trusted: ["10.0.0.5", "10.1.0.0/16", "fd00::/8"]
+    protocol: true
+serial_connections: []
+"#,
+    )
+    .unwrap()
+    .config;
+    config.validate().unwrap();
+    assert!(config.server.proxy.as_ref().unwrap().protocol);
+
+    config.server.proxy.as_mut().unwrap().trusted = vec!["10.1.0.0/40".to_string()];
+    assert!(config.validate().is_err());
+    config.server.proxy.as_mut().unwrap().trusted = vec!["haproxy.lan".to_string()];
+    assert!(config.validate().is_err());
+    // Any client could name itself in a PROXY header
+    config.server.proxy.as_mut().unwrap().trusted = vec![];
+    assert!(config.validate().is_err());
+    config.server.proxy.as_mut().unwrap().protocol = false;
+    config.validate().unwrap();
+    config.server.proxy = None;
+    config.server.websocket.max_clients_per_address = Some(0);
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_hub_validated() {
+    let dir = tempfile::tempdir().unwrap();
+    let token_file = dir.path().join("token");
+    std::fs::write(&token_file, "s3cret\n").unwrap();
+    let mut config = Config::from_yaml(&format!(
+        r#"
+server:
+  host: "0.0.0.0"
+  port: 8080
+  hub:
+    token_file: "{token}"
+  hub_agent:
+    url: "wss://hub.example.com/api/hub/connect"
+    name: "lab-3"
+    token_file: "{token}"
+serial_connections: []
+"#,
+        token = token_file.display()
+    ))
+    .unwrap()
+    .config;
+    config.validate().unwrap();
+
+    let agent = config.server.hub_agent.clone().unwrap();
+    config.server.hub_agent.as_mut().unwrap().url = "https://hub.example.com".to_string();
+    assert!(config.validate().is_err());
+    config.server.hub_agent = Some(agent.clone());
+    config.server.hub_agent.as_mut().unwrap().name = "lab/3".to_string();
+    assert!(config.validate().is_err());
+    config.server.hub_agent = Some(agent);
+    std::fs::write(&token_file, "\n").unwrap();
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_capture_jobs_validated() {
+    let dir = tempfile::tempdir().unwrap();
+    let secret_file = dir.path().join("secret");
+    std::fs::write(&secret_file, "secret\n").unwrap();
+    let mut config = Config::from_yaml(&format!(
+        r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+  capture_jobs:
+    - name: "nightly"
+      connections: ["plc"]
+      at: "02:00"
+      duration_minutes: 10
+      upload:
+        type: "s3"
+        endpoint: "https://s3.example.com"
+        bucket: "serial-archive"
+        access_key_id: "AKID"
+        secret_access_key_file: "{secret}"
+serial_connections:
+  - name: "plc"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/plc.log"
+"#,
+        secret = secret_file.display()
+    ))
+    .unwrap()
+    .config;
+    config.validate().unwrap();
+    let UploadTarget::S3(ref s3) = config.server.capture_jobs[0].upload else {
+        panic!("expected an S3 target");
+    };
+    assert_eq!(s3.region, "us-east-1");
+
+    config.server.capture_jobs[0].at = "2am".to_string();
+    assert!(config.validate().is_err());
+    config.server.capture_jobs[0].at = "02:00".to_string();
+    config.server.capture_jobs[0].duration_minutes = 61;
+    assert!(config.validate().is_err());
+    config.server.capture_jobs[0].duration_minutes = 10;
+    config.server.capture_jobs[0].connections = vec!["missing".to_string()];
+    assert!(config.validate().is_err());
+    config.server.capture_jobs[0].connections = vec!["plc".to_string()];
+    config.server.capture_jobs[0].upload = UploadTarget::Http {
+        url: "not a url".to_string(),
+        token_file: None,
+    };
+    assert!(config.validate().is_err());
+    config.server.capture_jobs[0].upload = UploadTarget::Http {
+        url: "https://archive.example.com".to_string(),
+        token_file: Some(dir.path().join("missing")),
+    };
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_sniffers_need_two_configured_connections() {
+    let mut config = Config::from_yaml(
+        r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+  sniffers:
+    - name: "link"
+      tx: "tap_tx"
+      rx: "tap_rx"
+serial_connections:
+  - name: "tap_tx"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/tap.log"
+  - name: "tap_rx"
+    port: "/dev/ttyUSB1"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/tap.log"
+"#,
+    )
+    .unwrap()
+    .config;
+    config.validate().unwrap();
+
+    config.server.sniffers[0].rx = "tap_tx".to_string();
+    assert!(config.validate().is_err());
+    config.server.sniffers[0].rx = "missing".to_string();
+    assert!(config.validate().is_err());
+
+    config.server.sniffers[0].rx = "tap_rx".to_string();
+    let duplicate = config.server.sniffers[0].clone();
+    config.server.sniffers.push(duplicate);
+    assert!(config.validate().is_err());
+}
+
+#[test]
+fn test_config_notifications() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+  notifications:
+    - name: "ops"
+      type: "slack"
+      webhook_url: "https://hooks.slack.com/services/T000/B000/XXXX"
+    - name: "mail"
+      type: "smtp"
+      host: "smtp.example.com"
+      username: "webmux"
+      password: "secret"
+      from: "webmux@example.com"
+      to: ["oncall@example.com"]
+
+serial_connections:
+  - name: "plc"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    notify: ["ops"]
+    logging:
+      enabled: false
+      path: "./logs/plc.log"
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    let channels = &config.server.notifications;
+    assert_eq!(channels.len(), 2);
+    assert!(matches!(
+        &channels[0].kind,
+        NotificationKind::Slack { webhook_url } if webhook_url.contains("hooks.slack.com")
+    ));
+    match &channels[1].kind {
+        NotificationKind::Smtp(smtp) => {
+            assert_eq!(smtp.port, 587);
+            assert_eq!(smtp.security, SmtpSecurity::Starttls);
+            assert_eq!(smtp.to, vec!["oncall@example.com"]);
+        }
+        other => panic!("unexpected channel {:?}", other),
+    }
+    config.validate().unwrap();
+
+    let mut bad = config.clone();
+    bad.serial_connections[0].notify = vec!["pager".to_string()];
+    assert!(bad.validate().is_err());
+}
+
+#[test]
+fn test_config_logging_syslog() {
+    let yaml = r#"
+enabled: false
+path: "./logs/plc.log"
+syslog:
+  address: "logs.example.com:6514"
+  transport: "tls"
+"#;
+
+    let logging: LoggingConfig = serde_yaml::from_str(yaml).unwrap();
+    let syslog = logging.syslog.unwrap();
+    assert_eq!(syslog.address, "logs.example.com:6514");
+    assert_eq!(syslog.transport, SyslogTransport::Tls);
+    assert_eq!(syslog.facility, SyslogFacility::Local0);
+    assert_eq!(syslog.facility.code(), 16);
+
+    let plain: LoggingConfig =
+        serde_yaml::from_str("enabled: true\npath: \"./logs/a.log\"\n").unwrap();
+    assert!(plain.syslog.is_none());
+}
+
+#[test]
+fn test_config_mirror() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "bus"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    mirror:
+      type: "connection"
+      connection: "analyzer"
+      direction: "rx"
+    logging:
+      enabled: false
+      path: "./logs/bus.log"
+  - name: "analyzer"
+    port: "/dev/ttyUSB1"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/analyzer.log"
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    let mirror = config.serial_connections[0].mirror.as_ref().unwrap();
+    assert_eq!(
+        mirror.target,
+        MirrorTarget::Connection {
+            connection: "analyzer".to_string()
+        }
+    );
+    assert_eq!(mirror.direction, MirrorDirection::Rx);
+    assert!(config.serial_connections[1].mirror.is_none());
+    config.validate().unwrap();
+
+    // Mirroring back to the source would echo forever
+    let mut looped = config.clone();
+    looped.serial_connections[1].mirror = Some(MirrorConfig {
+        target: MirrorTarget::Connection {
+            connection: "bus".to_string(),
+        },
+        direction: MirrorDirection::Both,
+    });
+    assert!(looped.validate().is_err());
+
+    let mut missing = config.clone();
+    missing.serial_connections[0].mirror = Some(MirrorConfig {
+        target: MirrorTarget::Connection {
+            connection: "nowhere".to_string(),
+        },
+        direction: MirrorDirection::Both,
+    });
+    assert!(missing.validate().is_err());
+}
+
+#[test]
+fn test_config_dependencies() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "power"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/power.log"
+  - name: "console"
+    port: "/dev/ttyUSB1"
+    baud_rate: 115200
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    depends_on: "power"
+    startup_delay_ms: 2000
+    logging:
+      enabled: false
+      path: "./logs/console.log"
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.serial_connections[0].depends_on, None);
+    assert_eq!(config.serial_connections[0].startup_delay_ms, 0);
+    assert_eq!(
+        config.serial_connections[1].depends_on.as_deref(),
+        Some("power")
+    );
+    assert_eq!(config.serial_connections[1].startup_delay_ms, 2000);
+    config.validate().unwrap();
+
+    let mut cycle = config.clone();
+    cycle.serial_connections[0].depends_on = Some("console".to_string());
+    assert!(cycle.validate().is_err());
+
+    // Only the members of a cycle are named, not what leads into it
+    let mut tail = config.clone();
+    let mut extra = tail.serial_connections[1].clone();
+    extra.name = "hub".to_string();
+    extra.depends_on = Some("console".to_string());
+    tail.serial_connections.push(extra);
+    tail.serial_connections[0].depends_on = Some("hub".to_string());
+    tail.serial_connections[1].depends_on = Some("hub".to_string());
+    let err = tail.validate().unwrap_err().to_string();
+    assert!(
+        err.contains("dependency cycle hub → console → hub"),
+        "{}",
+        err
+    );
+    assert!(!err.contains("power"), "{}", err);
+
+    let mut missing = config.clone();
+    missing.serial_connections[1].depends_on = Some("nowhere".to_string());
+    assert!(missing.validate().is_err());
+}
+
+#[test]
+fn test_config_power() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "pdu"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    power:
+      type: "http"
+      on: "http://pdu.local/outlets/1/on"
+      off: "http://pdu.local/outlets/1/off"
+      headers:
+        Authorization: "Bearer secret"
+    logging:
+      enabled: false
+      path: "./logs/pdu.log"
+  - name: "router"
+    port: "/dev/ttyUSB1"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    power:
+      type: "connection"
+      connection: "pdu"
+      on: "outlet 3 on\r"
+      off: "outlet 3 off\r"
+      cycle_delay_ms: 2000
+    logging:
+      enabled: false
+      path: "./logs/router.log"
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    let power = config.serial_connections[0].power.as_ref().unwrap();
+    assert!(matches!(
+        power.control,
+        PowerControl::Http { ref headers } if headers.len() == 1
+    ));
+    assert_eq!(power.cycle_delay_ms, 5000);
+    let power = config.serial_connections[1].power.as_ref().unwrap();
+    assert_eq!(
+        power.control,
+        PowerControl::Connection {
+            connection: "pdu".to_string()
+        }
+    );
+    assert_eq!(power.off, "outlet 3 off\r");
+    config.validate().unwrap();
+
+    let mut missing = config.clone();
+    missing.serial_connections[1]
+        .power
+        .as_mut()
+        .unwrap()
+        .control = PowerControl::Connection {
+        connection: "nowhere".to_string(),
+    };
+    assert!(missing.validate().is_err());
+
+    // Commands need no settings beyond on and off
+    let command: PowerConfig =
+        serde_yaml::from_str("type: command\non: relay 1 on\noff: relay 1 off\n").unwrap();
+    assert_eq!(command.control, PowerControl::Command);
+}
+
+#[test]
+fn test_config_quota() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+  quota:
+    daily_rx_bytes: 50000000
+    daily_tx_bytes: 5000000
+
+serial_connections:
+  - name: "modem"
+    port: "/dev/ttyUSB0"
+    baud_rate: 115200
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    quota:
+      daily_tx_bytes: 1000000
+      action: throttle
+      throttle_bytes_per_sec: 100
+    logging:
+      enabled: false
+      path: "./logs/modem.log"
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    let server = config.server.quota.as_ref().unwrap();
+    assert_eq!(server.action, QuotaAction::Warn);
+    assert_eq!(server.daily_rx_bytes, Some(50_000_000));
+    let quota = config.serial_connections[0].quota.as_ref().unwrap();
+    assert_eq!(quota.action, QuotaAction::Throttle);
+    assert_eq!(quota.throttle_bytes_per_sec, Some(100));
+    config.validate().unwrap();
+
+    let invalid = [
+        "action: block",
+        "daily_tx_bytes: 0",
+        "daily_tx_bytes: 10\naction: throttle",
+        "daily_tx_bytes: 10\nthrottle_bytes_per_sec: 100",
+        "daily_tx_bytes: 10\nnotify: [nowhere]",
+    ];
+    for quota in invalid {
+        let mut config = config.clone();
+        config.serial_connections[0].quota = Some(serde_yaml::from_str(quota).unwrap());
+        assert!(config.validate().is_err(), "{}", quota);
+    }
+}
+
+#[test]
+fn test_config_limits_default() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+  limits:
+    max_send_bytes: 4096
+
+serial_connections: []
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(config.server.limits.max_send_bytes, 4096);
+    assert_eq!(config.server.limits.max_upload_bytes, 16 * 1024 * 1024);
+    assert_eq!(config.server.limits.max_ws_message_bytes, 64 * 1024);
+    config.validate().unwrap();
+
+    let mut bad = config.clone();
+    bad.server.limits.max_ws_message_bytes = 0;
+    assert!(bad.validate().is_err());
+}
+
+#[test]
+fn test_config_metrics_push() {
+    let yaml = r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+  metrics_push:
+    type: "telegraf"
+    address: "telegraf.lab:8094"
+    transport: "udp"
+
+serial_connections: []
+"#;
+
+    let config: Config = serde_yaml::from_str(yaml).unwrap();
+    let push = config.server.metrics_push.as_ref().unwrap();
+    assert_eq!(push.interval_secs, 10);
+    assert_eq!(
+        push.target,
+        MetricsPushTarget::Telegraf {
+            address: "telegraf.lab:8094".to_string(),
+            transport: TelegrafTransport::Udp,
+        }
+    );
+    config.validate().unwrap();
+
+    let yaml = r#"
+type: "pushgateway"
+url: "http://pushgateway.lab:9091"
+interval_secs: 30
+"#;
+    let mut bad = config.clone();
+    let mut push: MetricsPushConfig = serde_yaml::from_str(yaml).unwrap();
+    assert_eq!(
+        push.target,
+        MetricsPushTarget::Pushgateway {
+            url: "http://pushgateway.lab:9091".to_string(),
+            job: "webmux".to_string(),
+        }
+    );
+    push.target = MetricsPushTarget::Pushgateway {
+        url: "http://pushgateway.lab:9091".to_string(),
+        job: "a/b".to_string(),
+    };
+    bad.server.metrics_push = Some(push);
+    assert!(bad.validate().is_err());
+}
+
+fn check_connection(name: &str, port: &str, log_path: &str) -> String {
+    format!(
+        r#"
+  - name: "{}"
+    port: "{}"
+    baud_rate: 115200
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: true
+      path: "{}"
+"#,
+        name, port, log_path
+    )
+}
+
+#[test]
+fn test_check_finds_host_problems() {
+    let dir = tempfile::tempdir().unwrap();
+    let device = dir.path().join("ttyFAKE0");
+    std::fs::write(&device, b"").unwrap();
+    let blocker = dir.path().join("not-a-dir");
+    std::fs::write(&blocker, b"").unwrap();
+    let logs = dir.path().join("logs/nested");
+
+    let mut yaml = "server:\n  host: \"127.0.0.1\"\n  port: 8080\nserial_connections:".to_string();
+    yaml += &check_connection(
+        "good",
+        device.to_str().unwrap(),
+        logs.join("good.log").to_str().unwrap(),
+    );
+    // Same device spelled differently
+    yaml += &check_connection(
+        "twin",
+        dir.path().join(".").join("ttyFAKE0").to_str().unwrap(),
+        logs.join("twin.log").to_str().unwrap(),
+    );
+    yaml += &check_connection(
+        "gone",
+        "/dev/webmux-does-not-exist",
+        blocker.join("gone.log").to_str().unwrap(),
+    );
+    let config: Config = serde_yaml::from_str(&yaml).unwrap();
+
+    let report = check::check(&config, &[]);
+    let codes: Vec<(&str, Option<&str>)> = report
+        .findings
+        .iter()
+        .map(|finding| (finding.code, finding.connection.as_deref()))
+        .collect();
+    assert!(!report.valid);
+    assert!(codes.contains(&("missing_device", Some("gone"))));
+    assert!(codes.contains(&("log_dir_unwritable", Some("gone"))));
+    assert!(codes.contains(&("duplicate_port", Some("twin"))));
+    assert!(!codes.iter().any(|(_, name)| *name == Some("good")));
+}
+
+fn detected(path: &str, friendly_name: Option<&str>, by_id: Option<&str>) -> PortDescription {
+    PortDescription {
+        path: path.to_string(),
+        kind: crate::serial::discovery::PortKind::Usb,
+        friendly_name: friendly_name.map(str::to_string),
+        description: friendly_name.map(|name| format!("FTDI {} (0403:6001)", name)),
+        manufacturer: None,
+        serial_number: None,
+        vid: None,
+        pid: None,
+        by_id: by_id.map(str::to_string),
+        usb_path: None,
+    }
+}
+
+#[test]
+fn test_generate_config_round_trips() {
+    let by_id = "/dev/serial/by-id/usb-FTDI_FT232R_USB_UART_A1B2C3-if00-port0";
+    let yaml = generate::generate(&[
+        detected("/dev/ttyUSB0", Some("FT232R USB UART"), Some(by_id)),
+        detected("/dev/ttyUSB1", Some("FT232R USB UART"), None),
+        detected("/dev/ttyS0", None, None),
+    ]);
+    assert!(yaml.contains("# Currently /dev/ttyUSB0"));
+
+    let config: Config = serde_yaml::from_str(&yaml).unwrap();
+    config.validate().unwrap();
+    let ports: Vec<(&str, &str)> = config
+        .serial_connections
+        .iter()
+        .map(|conn| (conn.name.as_str(), conn.port.as_str()))
+        .collect();
+    assert_eq!(
+        ports,
+        vec![
+            ("ft232r_usb_uart", by_id),
+            ("ft232r_usb_uart_2", "/dev/ttyUSB1"),
+            ("ttys0", "/dev/ttyS0"),
+        ]
+    );
+    assert_eq!(config.serial_connections[0].baud_rate, 115200);
+    assert_eq!(
+        config.serial_connections[0].description,
+        "FTDI FT232R USB UART (0403:6001)"
+    );
+
+    let empty: Config = serde_yaml::from_str(&generate::generate(&[])).unwrap();
+    assert!(empty.serial_connections.is_empty());
+}
+
+#[test]
+fn test_unknown_keys_are_rejected_with_location() {
+    let yaml = r#"server:
+  host: "127.0.0.1"
+  port: 8080
+serial_connections:
+  - name: "plc_01"
+    port: "/dev/ttyUSB0"
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    baudrate: 9600
+    baud_rate: 9600
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/plc.log"
+    mirror:
+      type: file
+      path: "/tmp/plc.raw"
+      pth: "/tmp/typo.raw"
+"#;
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(yaml.as_bytes()).unwrap();
+    file.flush().unwrap();
+    let path = file.path().to_str().unwrap();
+
+    let error = Config::from_file(path).unwrap_err().to_string();
+    assert_eq!(
+        error,
+        format!(
+            "{}:11:5: serial_connections[0] (plc_01): unknown key `baudrate`, did you mean `baud_rate`?",
+            path
+        )
+    );
+
+    let loaded = Config::load(path, true).unwrap();
+    assert_eq!(loaded.config.serial_connections[0].baud_rate, 9600);
+    assert_eq!(
+        loaded.ignored_keys,
+        vec![
+            "Ignoring unknown key `baudrate` in serial_connections[0] (plc_01) (did you mean `baud_rate`?)",
+            "Ignoring unknown key `pth` in serial_connections[0] (plc_01).mirror (did you mean `path`?)",
+        ]
+    );
+
+    // Other errors are located the same way
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(b"server:\n  host: \"h\"\n  port: eighty\nserial_connections: []\n")
+        .unwrap();
+    file.flush().unwrap();
+    let path = file.path().to_str().unwrap();
+    let error = Config::load(path, true).unwrap_err().to_string();
+    assert_eq!(
+        error,
+        format!(
+            "{}:3:9: server.port: invalid type: string \"eighty\", expected u16",
+            path
+        )
+    );
+}
+
+#[test]
+fn test_migrate_config_stamps_version() {
+    let yaml =
+        "# Lab bench\n\nserver:\n  host: \"127.0.0.1\"\n  port: 8080\nserial_connections: []\n";
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(yaml.as_bytes()).unwrap();
+    file.flush().unwrap();
+    let path = file.path().to_str().unwrap();
+
+    // Unversioned files still load, and say they are outdated
+    let loaded = Config::load(path, false).unwrap();
+    assert_eq!(loaded.migrated_from, Some(0));
+    assert_eq!(loaded.config.version, migrate::CURRENT_VERSION);
+
+    assert_eq!(migrate::migrate_file(path).unwrap(), Some(0));
+    let migrated = std::fs::read_to_string(path).unwrap();
+    assert_eq!(
+        migrated,
+        format!(
+            "# Lab bench\n\nversion: {}\nserver:\n  host: \"127.0.0.1\"\n  port: 8080\nserial_connections: []\n",
+            migrate::CURRENT_VERSION
+        )
+    );
+    assert_eq!(
+        std::fs::read_to_string(format!("{}.bak", path)).unwrap(),
+        yaml
+    );
+    assert_eq!(Config::load(path, false).unwrap().migrated_from, None);
+    assert_eq!(migrate::migrate_file(path).unwrap(), None);
+    std::fs::remove_file(format!("{}.bak", path)).unwrap();
+
+    let future = format!("version: {}\n{}", migrate::CURRENT_VERSION + 1, yaml);
+    let error = migrate::migrate_text(&future).unwrap_err().to_string();
+    assert!(
+        error.contains("newer than this webmux supports"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_profiles_fill_unset_serial_settings() {
+    let yaml = r#"server:
+  host: "127.0.0.1"
+  port: 8080
+serial_connections:
+  - name: "switch"
+    port: "/dev/ttyUSB0"
+    profile: cisco-console
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/x.log"
+  - name: "meter"
+    port: "/dev/ttyUSB1"
+    profile: modbus-19200-8E1
+    baud_rate: 38400
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/x.log"
+"#;
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(yaml.as_bytes()).unwrap();
+    file.flush().unwrap();
+    let config = Config::from_file(file.path().to_str().unwrap()).unwrap();
+
+    let switch = &config.serial_connections[0];
+    assert_eq!(switch.baud_rate, 9600);
+    assert_eq!(switch.data_bits, DataBits::Eight);
+    assert_eq!(switch.parity, Parity::None);
+    assert_eq!(switch.flow_control, FlowControl::None);
+
+    // Settings given alongside the profile win
+    let meter = &config.serial_connections[1];
+    assert_eq!(meter.baud_rate, 38400);
+    assert_eq!(meter.parity, Parity::Even);
+    assert_eq!(meter.stop_bits, StopBits::One);
+
+    let typo = yaml.replace("cisco-console", "cisco-consol");
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(typo.as_bytes()).unwrap();
+    file.flush().unwrap();
+    let error = Config::from_file(file.path().to_str().unwrap())
+        .unwrap_err()
+        .to_string();
+    assert!(
+        error.ends_with(
+            "serial_connections[0] (switch).profile: unknown profile `cisco-consol`, did you mean `cisco-console`?"
+        ),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_layered_config_merges_connections_by_name() {
+    let base = r#"
+version: 1
+server:
+  host: "0.0.0.0"
+  port: 8080
+serial_connections:
+  - name: "router"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    description: "Core router"
+    logging:
+      enabled: true
+      path: "./logs/router.log"
+"#;
+    let site = r#"
+server:
+  port: 9000
+serial_connections:
+  - name: "router"
+    port: "/dev/ttyS3"
+    logging:
+      path: "/var/log/webmux/router.log"
+  - name: "switch"
+    port: "/dev/ttyS4"
+    baud_rate: 115200
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/switch.log"
+"#;
+    let write = |content: &str| {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file.flush().unwrap();
+        file
+    };
+    let (base_file, site_file) = (write(base), write(site));
+    let paths = vec![
+        base_file.path().to_str().unwrap().to_string(),
+        site_file.path().to_str().unwrap().to_string(),
+    ];
+
+    let config = Config::load_layered(&paths, false).unwrap().config;
+    assert_eq!(config.server.host, "0.0.0.0");
+    assert_eq!(config.server.port, 9000);
+    assert_eq!(config.serial_connections.len(), 2);
+    let router = &config.serial_connections[0];
+    assert_eq!(router.port, "/dev/ttyS3");
+    assert_eq!(router.baud_rate, 9600);
+    assert_eq!(router.description, "Core router");
+    assert!(router.logging.enabled);
+    assert_eq!(
+        router.logging.path,
+        PathBuf::from("/var/log/webmux/router.log")
+    );
+    assert_eq!(config.serial_connections[1].name, "switch");
+
+    // Errors name both files, without positions in the merged text
+    let typo = write("server:\n  prot: 9000\n");
+    let paths = vec![paths[0].clone(), typo.path().to_str().unwrap().to_string()];
+    let error = Config::load_layered(&paths, false).unwrap_err().to_string();
+    assert!(error.contains(" + "), "{}", error);
+    assert!(error.contains("server: unknown key `prot`"), "{}", error);
+}
+
+#[test]
+fn test_config_garbage_check() {
+    let yaml = |check: &str| {
+        format!(
+            r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "console"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    garbage_check: {}
+    logging:
+      enabled: false
+      path: "./logs/console.log"
+"#,
+            check
+        )
+    };
+
+    let config: Config = serde_yaml::from_str(&yaml("{}")).unwrap();
+    let check = config.serial_connections[0].garbage_check.as_ref().unwrap();
+    assert_eq!(check.threshold_percent, 30);
+    assert_eq!(check.window_bytes, 256);
+    config.validate().unwrap();
+
+    for invalid in [
+        "{threshold_percent: 0}",
+        "{threshold_percent: 101}",
+        "{window_bytes: 0}",
+    ] {
+        let config: Config = serde_yaml::from_str(&yaml(invalid)).unwrap();
+        assert!(config.validate().is_err(), "{} should be rejected", invalid);
+    }
+}
+
+#[test]
+fn test_config_keepalive() {
+    let yaml = |keepalive: &str| {
+        format!(
+            r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "switch"
+    port: "/dev/ttyUSB0"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    keepalive: {}
+    logging:
+      enabled: false
+      path: "./logs/switch.log"
+"#,
+            keepalive
+        )
+    };
+
+    let config: Config = serde_yaml::from_str(&yaml("{}")).unwrap();
+    let keepalive = config.serial_connections[0].keepalive.as_ref().unwrap();
+    assert_eq!(keepalive.idle_minutes, 5);
+    assert_eq!(keepalive.data, "\n");
+    config.validate().unwrap();
+
+    let config: Config = serde_yaml::from_str(&yaml(r#"{idle_minutes: 9, data: "\r\n"}"#)).unwrap();
+    let keepalive = config.serial_connections[0].keepalive.as_ref().unwrap();
+    assert_eq!(keepalive.idle(), std::time::Duration::from_secs(540));
+    assert_eq!(keepalive.data, "\r\n");
+
+    for invalid in ["{idle_minutes: 0}", "{idle_minutes: 1441}", r#"{data: ""}"#] {
+        let config: Config = serde_yaml::from_str(&yaml(invalid)).unwrap();
+        assert!(config.validate().is_err(), "{} should be rejected", invalid);
+    }
+}
+
+#[test]
+fn test_config_drift_diff() {
+    use crate::config::drift::{diff, ModifiedConnection};
+    use crate::serial::virtual_pair::endpoint_config;
+
+    let plc = endpoint_config("plc", "p");
+    let modem = endpoint_config("modem", "p");
+    let mut spare = endpoint_config("spare", "p");
+    spare.enabled = false;
+    let configured = vec![plc.clone(), modem, spare];
+
+    assert!(diff(&configured, &[plc.clone(), endpoint_config("modem", "p")]).is_empty());
+
+    let mut running_plc = plc.clone();
+    running_plc.baud_rate = 9600;
+    running_plc.banner = Some("maintenance".to_string());
+    let result = diff(&configured, &[running_plc, endpoint_config("bench", "p")]);
+    assert_eq!(result.added, vec!["bench"]);
+    // Disabled connections are not expected to run
+    assert_eq!(result.removed, vec!["modem"]);
+    assert_eq!(
+        result.modified,
+        vec![ModifiedConnection {
+            name: "plc".to_string(),
+            fields: vec!["banner".to_string(), "baud_rate".to_string()],
+        }]
+    );
+    assert_eq!(result.summary(), "1 added, 1 removed, 1 modified");
 }
@@ -0,0 +1,265 @@
+//! Espressif ROM bootloader protocol (the subset esptool uses to write flash).
+
+use super::{report, FlashPort, FlashProgress};
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+
+const SLIP_END: u8 = 0xC0;
+const SLIP_ESC: u8 = 0xDB;
+const SLIP_ESC_END: u8 = 0xDC;
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+const CMD_FLASH_BEGIN: u8 = 0x02;
+const CMD_FLASH_DATA: u8 = 0x03;
+const CMD_FLASH_END: u8 = 0x04;
+const CMD_SYNC: u8 = 0x08;
+
+/// Block size accepted by the ROM loader's FLASH_DATA command.
+pub const FLASH_BLOCK_SIZE: usize = 0x400;
+const FLASH_SECTOR_SIZE: u32 = 0x1000;
+const CHECKSUM_SEED: u8 = 0xEF;
+
+const SYNC_ATTEMPTS: usize = 10;
+const SYNC_TIMEOUT: Duration = Duration::from_millis(200);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(3);
+const ERASE_TIMEOUT_PER_MB: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EspChip {
+    #[default]
+    Esp32,
+    Esp8266,
+}
+
+#[derive(Debug, Clone)]
+pub struct EspFlashOptions {
+    pub chip: EspChip,
+    pub offset: u32,
+    /// Reboot into the new firmware once writing completes.
+    pub reboot: bool,
+}
+
+/// Wraps `data` in a SLIP frame.
+pub fn slip_encode(data: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(data.len() + 2);
+    frame.push(SLIP_END);
+    for &byte in data {
+        match byte {
+            SLIP_END => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_END]),
+            SLIP_ESC => frame.extend_from_slice(&[SLIP_ESC, SLIP_ESC_ESC]),
+            b => frame.push(b),
+        }
+    }
+    frame.push(SLIP_END);
+    frame
+}
+
+/// Reads the next non-empty SLIP frame, discarding any bytes outside frames
+/// (such as the ROM's boot banner).
+pub(crate) async fn read_slip_frame<P: FlashPort>(port: &mut P) -> Result<Vec<u8>> {
+    // Skip until a frame starts
+    while port.read_u8().await? != SLIP_END {}
+
+    let mut frame = Vec::new();
+    loop {
+        match port.read_u8().await? {
+            SLIP_END if frame.is_empty() => continue,
+            SLIP_END => return Ok(frame),
+            SLIP_ESC => match port.read_u8().await? {
+                SLIP_ESC_END => frame.push(SLIP_END),
+                SLIP_ESC_ESC => frame.push(SLIP_ESC),
+                b => anyhow::bail!("Invalid SLIP escape 0x{:02X}", b),
+            },
+            b => frame.push(b),
+        }
+    }
+}
+
+/// XOR checksum over a FLASH_DATA payload, as expected by the ROM loader.
+pub fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(CHECKSUM_SEED, |acc, b| acc ^ b) as u32
+}
+
+/// Builds a request packet: direction, command, length, checksum, payload.
+pub fn command_packet(command: u8, data: &[u8], checksum: u32) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(data.len() + 8);
+    packet.push(0x00);
+    packet.push(command);
+    packet.extend_from_slice(&(data.len() as u16).to_le_bytes());
+    packet.extend_from_slice(&checksum.to_le_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// Number of bytes to request in FLASH_BEGIN.
+///
+/// The ESP8266 ROM erases more than it is asked to, so esptool compensates
+/// by requesting a smaller region; the ESP32 ROM erases exactly `size`.
+pub fn erase_size(chip: EspChip, offset: u32, size: u32) -> u32 {
+    match chip {
+        EspChip::Esp32 => size,
+        EspChip::Esp8266 => {
+            let sectors_per_block = 16;
+            let num_sectors = size.div_ceil(FLASH_SECTOR_SIZE);
+            let start_sector = offset / FLASH_SECTOR_SIZE;
+            let head_sectors =
+                (sectors_per_block - (start_sector % sectors_per_block)).min(num_sectors);
+
+            if num_sectors < 2 * head_sectors {
+                num_sectors.div_ceil(2) * FLASH_SECTOR_SIZE
+            } else {
+                (num_sectors - head_sectors) * FLASH_SECTOR_SIZE
+            }
+        }
+    }
+}
+
+async fn command<P: FlashPort>(
+    port: &mut P,
+    command: u8,
+    data: &[u8],
+    checksum: u32,
+    wait: Duration,
+) -> Result<()> {
+    port.write_all(&slip_encode(&command_packet(command, data, checksum)))
+        .await?;
+    port.flush().await?;
+
+    timeout(wait, async {
+        loop {
+            let response = read_slip_frame(port).await?;
+            if response.len() < 8 || response[0] != 0x01 || response[1] != command {
+                continue;
+            }
+
+            // Status bytes follow the 8-byte header
+            let status = &response[8..];
+            if status.len() >= 2 && status[0] != 0 {
+                anyhow::bail!(
+                    "Command 0x{:02X} failed with error 0x{:02X}",
+                    command,
+                    status[1]
+                );
+            }
+            return Ok(());
+        }
+    })
+    .await
+    .map_err(|_| {
+        anyhow::anyhow!(
+            "Timed out waiting for response to command 0x{:02X}",
+            command
+        )
+    })?
+}
+
+/// Pulses EN/IO0 through RTS/DTR the way esptool's classic reset does.
+async fn enter_bootloader<P: FlashPort>(port: &mut P) -> Result<()> {
+    port.set_dtr(false)?;
+    port.set_rts(true)?;
+    sleep(Duration::from_millis(100)).await;
+    port.set_dtr(true)?;
+    port.set_rts(false)?;
+    sleep(Duration::from_millis(50)).await;
+    port.set_dtr(false)?;
+    Ok(())
+}
+
+async fn hard_reset<P: FlashPort>(port: &mut P) -> Result<()> {
+    port.set_rts(true)?;
+    sleep(Duration::from_millis(100)).await;
+    port.set_rts(false)?;
+    Ok(())
+}
+
+async fn sync<P: FlashPort>(port: &mut P) -> Result<()> {
+    let mut payload = vec![0x07, 0x07, 0x12, 0x20];
+    payload.extend_from_slice(&[0x55; 32]);
+
+    for _ in 0..SYNC_ATTEMPTS {
+        if command(port, CMD_SYNC, &payload, 0, SYNC_TIMEOUT)
+            .await
+            .is_ok()
+        {
+            // The ROM answers each SYNC several times; drain the extras
+            let _ = timeout(Duration::from_millis(100), async {
+                while read_slip_frame(port).await.is_ok() {}
+            })
+            .await;
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!("Failed to sync with the ESP bootloader")
+}
+
+/// Resets the target into its ROM loader and writes `image` at `options.offset`.
+pub async fn flash<P: FlashPort>(
+    port: &mut P,
+    image: &[u8],
+    options: &EspFlashOptions,
+    progress: &mpsc::Sender<FlashProgress>,
+) -> Result<()> {
+    if image.is_empty() {
+        anyhow::bail!("Firmware image is empty");
+    }
+
+    report(progress, FlashProgress::Resetting).await;
+    enter_bootloader(port).await?;
+
+    report(progress, FlashProgress::Syncing).await;
+    sync(port).await?;
+
+    let total = image.len() as u32;
+    let blocks = image.len().div_ceil(FLASH_BLOCK_SIZE) as u32;
+    let erase = erase_size(options.chip, options.offset, total);
+
+    report(progress, FlashProgress::Erasing { bytes: erase }).await;
+    let mut begin = Vec::with_capacity(16);
+    for value in [erase, blocks, FLASH_BLOCK_SIZE as u32, options.offset] {
+        begin.extend_from_slice(&value.to_le_bytes());
+    }
+    let erase_timeout = ERASE_TIMEOUT_PER_MB
+        .mul_f64(erase as f64 / (1024.0 * 1024.0))
+        .max(COMMAND_TIMEOUT);
+    command(port, CMD_FLASH_BEGIN, &begin, 0, erase_timeout).await?;
+
+    let mut written = 0u32;
+    for (seq, chunk) in image.chunks(FLASH_BLOCK_SIZE).enumerate() {
+        let mut block = chunk.to_vec();
+        block.resize(FLASH_BLOCK_SIZE, 0xFF);
+
+        let mut data = Vec::with_capacity(16 + FLASH_BLOCK_SIZE);
+        for value in [block.len() as u32, seq as u32, 0, 0] {
+            data.extend_from_slice(&value.to_le_bytes());
+        }
+        data.extend_from_slice(&block);
+
+        command(
+            port,
+            CMD_FLASH_DATA,
+            &data,
+            checksum(&block),
+            COMMAND_TIMEOUT,
+        )
+        .await?;
+
+        written += chunk.len() as u32;
+        report(progress, FlashProgress::Writing { written, total }).await;
+    }
+
+    // FLASH_END's flag is "stay in the loader", so invert `reboot`
+    let stay = u32::from(!options.reboot);
+    command(port, CMD_FLASH_END, &stay.to_le_bytes(), 0, COMMAND_TIMEOUT).await?;
+
+    if options.reboot {
+        hard_reset(port).await?;
+    }
+
+    Ok(())
+}
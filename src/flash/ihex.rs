@@ -0,0 +1,99 @@
+//! Intel HEX decoding, as produced by avr-gcc / the Arduino toolchain.
+
+use anyhow::Result;
+
+/// Largest image accepted, the flash of an ATmega2560. Bounds the buffer
+/// allocated for the span between the lowest and highest record.
+pub const MAX_IMAGE_BYTES: u32 = 256 * 1024;
+
+/// A contiguous memory image decoded from Intel HEX records.
+///
+/// Gaps between records are filled with `0xFF` (erased flash).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HexImage {
+    pub base_address: u32,
+    pub data: Vec<u8>,
+}
+
+pub fn parse(text: &str) -> Result<HexImage> {
+    let mut segments: Vec<(u32, Vec<u8>)> = Vec::new();
+    let mut upper = 0u32;
+    let mut saw_eof = false;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_no = index + 1;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        if saw_eof {
+            anyhow::bail!("Line {}: data after end-of-file record", line_no);
+        }
+
+        let hex = line
+            .strip_prefix(':')
+            .ok_or_else(|| anyhow::anyhow!("Line {}: missing ':' start code", line_no))?;
+        let bytes = hex::decode(hex).map_err(|e| anyhow::anyhow!("Line {}: {}", line_no, e))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            anyhow::bail!("Line {}: record length mismatch", line_no);
+        }
+        if bytes.iter().fold(0u8, |acc, b| acc.wrapping_add(*b)) != 0 {
+            anyhow::bail!("Line {}: checksum mismatch", line_no);
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let payload = &bytes[4..bytes.len() - 1];
+
+        match bytes[3] {
+            0x00 => {
+                let start = upper + address;
+                start
+                    .checked_add(payload.len() as u32)
+                    .ok_or_else(|| anyhow::anyhow!("Line {}: data past 4 GiB", line_no))?;
+                segments.push((start, payload.to_vec()))
+            }
+            0x01 => saw_eof = true,
+            0x02 if payload.len() == 2 => {
+                upper = (u16::from_be_bytes([payload[0], payload[1]]) as u32) << 4
+            }
+            0x04 if payload.len() == 2 => {
+                upper = (u16::from_be_bytes([payload[0], payload[1]]) as u32) << 16
+            }
+            // Start addresses are irrelevant to a bootloader upload
+            0x03 | 0x05 => {}
+            other => anyhow::bail!("Line {}: unsupported record type {:02X}", line_no, other),
+        }
+    }
+
+    if !saw_eof {
+        anyhow::bail!("Missing end-of-file record");
+    }
+
+    let base_address = segments
+        .iter()
+        .map(|(address, _)| *address)
+        .min()
+        .ok_or_else(|| anyhow::anyhow!("HEX file contains no data"))?;
+    let end = segments
+        .iter()
+        .map(|(address, data)| *address + data.len() as u32)
+        .max()
+        .unwrap_or(base_address);
+
+    // Checked per record above, so the end addresses cannot overflow
+    if end - base_address > MAX_IMAGE_BYTES {
+        anyhow::bail!(
+            "HEX records span {} bytes, more than the {} byte limit",
+            end - base_address,
+            MAX_IMAGE_BYTES
+        );
+    }
+
+    let mut data = vec![0xFF; (end - base_address) as usize];
+    for (address, bytes) in segments {
+        let start = (address - base_address) as usize;
+        data[start..start + bytes.len()].copy_from_slice(&bytes);
+    }
+
+    Ok(HexImage { base_address, data })
+}
@@ -0,0 +1,61 @@
+use anyhow::Result;
+use serde::Serialize;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::mpsc;
+
+pub mod esp;
+pub mod ihex;
+pub mod stk500;
+
+/// A port a bootloader can be driven over: a byte stream plus the modem
+/// control lines used to reset the target into its bootloader.
+pub trait FlashPort: AsyncRead + AsyncWrite + Unpin + Send {
+    fn set_dtr(&mut self, level: bool) -> Result<()>;
+    fn set_rts(&mut self, level: bool) -> Result<()>;
+}
+
+impl FlashPort for tokio_serial::SerialStream {
+    fn set_dtr(&mut self, level: bool) -> Result<()> {
+        serialport::SerialPort::write_data_terminal_ready(self, level)?;
+        Ok(())
+    }
+
+    fn set_rts(&mut self, level: bool) -> Result<()> {
+        serialport::SerialPort::write_request_to_send(self, level)?;
+        Ok(())
+    }
+}
+
+/// Progress reported while flashing, streamed to API clients as NDJSON.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "stage", rename_all = "snake_case")]
+pub enum FlashProgress {
+    Resetting,
+    Syncing,
+    Erasing { bytes: u32 },
+    Writing { written: u32, total: u32 },
+    Verifying { verified: u32, total: u32 },
+    Done,
+    Error { message: String },
+}
+
+/// Sends a progress event, ignoring clients that have stopped listening.
+pub(crate) async fn report(progress: &mpsc::Sender<FlashProgress>, event: FlashProgress) {
+    let _ = progress.send(event).await;
+}
+
+/// Parses a flash offset given either in decimal or as `0x`-prefixed hex.
+pub fn parse_offset(value: &str) -> Result<u32> {
+    let value = value.trim();
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|e| anyhow::anyhow!("Invalid offset {:?}: {}", value, e))
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,422 @@
+//! STK500 bootloader protocols used by Arduino boards: v1 (Optiboot on the
+//! Uno/Nano) and v2 (the Mega 2560 bootloader), as spoken by avrdude.
+
+use super::{report, FlashPort, FlashProgress};
+use anyhow::Result;
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, timeout};
+
+const SYNC_ATTEMPTS: usize = 10;
+const SYNC_TIMEOUT: Duration = Duration::from_millis(200);
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(2);
+
+// STK500v1 constants
+const STK_OK: u8 = 0x10;
+const STK_INSYNC: u8 = 0x14;
+const CRC_EOP: u8 = 0x20;
+const STK_GET_SYNC: u8 = 0x30;
+const STK_ENTER_PROGMODE: u8 = 0x50;
+const STK_LEAVE_PROGMODE: u8 = 0x51;
+const STK_LOAD_ADDRESS: u8 = 0x55;
+const STK_PROG_PAGE: u8 = 0x64;
+const STK_READ_PAGE: u8 = 0x74;
+const MEMTYPE_FLASH: u8 = b'F';
+
+// STK500v2 constants
+const MESSAGE_START: u8 = 0x1B;
+const TOKEN: u8 = 0x0E;
+const STATUS_CMD_OK: u8 = 0x00;
+const CMD_SIGN_ON: u8 = 0x01;
+const CMD_LOAD_ADDRESS: u8 = 0x06;
+const CMD_ENTER_PROGMODE_ISP: u8 = 0x10;
+const CMD_LEAVE_PROGMODE_ISP: u8 = 0x11;
+const CMD_PROGRAM_FLASH_ISP: u8 = 0x13;
+const CMD_READ_FLASH_ISP: u8 = 0x14;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stk500Protocol {
+    #[default]
+    Stk500v1,
+    Stk500v2,
+}
+
+impl Stk500Protocol {
+    /// Flash page size of the boards that usually speak this protocol.
+    pub fn default_page_size(self) -> usize {
+        match self {
+            Stk500Protocol::Stk500v1 => 128,
+            Stk500Protocol::Stk500v2 => 256,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Stk500Options {
+    pub protocol: Stk500Protocol,
+    pub page_size: usize,
+    /// Read every page back and compare after writing.
+    pub verify: bool,
+}
+
+/// Pulses DTR/RTS to trigger the Arduino auto-reset circuit, like avrdude's
+/// `arduino` programmer does.
+async fn reset<P: FlashPort>(port: &mut P) -> Result<()> {
+    port.set_dtr(false)?;
+    port.set_rts(false)?;
+    sleep(Duration::from_millis(250)).await;
+    port.set_dtr(true)?;
+    port.set_rts(true)?;
+    sleep(Duration::from_millis(50)).await;
+    Ok(())
+}
+
+/// Discards anything the sketch or bootloader printed before we got in sync.
+async fn drain<P: FlashPort>(port: &mut P) {
+    let mut buffer = [0u8; 64];
+    while let Ok(Ok(n)) = timeout(Duration::from_millis(50), port.read(&mut buffer)).await {
+        if n == 0 {
+            break;
+        }
+    }
+}
+
+async fn read_exact_within<P: FlashPort>(
+    port: &mut P,
+    len: usize,
+    wait: Duration,
+) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; len];
+    timeout(wait, port.read_exact(&mut buffer))
+        .await
+        .map_err(|_| anyhow::anyhow!("Timed out waiting for bootloader response"))??;
+    Ok(buffer)
+}
+
+/// Sends a v1 command and returns the payload between INSYNC and OK.
+async fn v1_command<P: FlashPort>(
+    port: &mut P,
+    command: &[u8],
+    response_len: usize,
+    wait: Duration,
+) -> Result<Vec<u8>> {
+    port.write_all(command).await?;
+    port.flush().await?;
+
+    let response = read_exact_within(port, response_len + 2, wait).await?;
+    if response[0] != STK_INSYNC || response[response_len + 1] != STK_OK {
+        anyhow::bail!(
+            "Bootloader out of sync after command 0x{:02X}: {:02X?}",
+            command[0],
+            response
+        );
+    }
+    Ok(response[1..=response_len].to_vec())
+}
+
+async fn v1_load_address<P: FlashPort>(port: &mut P, address: u32) -> Result<()> {
+    let word = address / 2;
+    if word > 0xFFFF {
+        anyhow::bail!(
+            "Address 0x{:X} is beyond STK500v1's 16-bit word range",
+            address
+        );
+    }
+    v1_command(
+        port,
+        &[STK_LOAD_ADDRESS, word as u8, (word >> 8) as u8, CRC_EOP],
+        0,
+        COMMAND_TIMEOUT,
+    )
+    .await?;
+    Ok(())
+}
+
+async fn flash_v1<P: FlashPort>(
+    port: &mut P,
+    base_address: u32,
+    image: &[u8],
+    options: &Stk500Options,
+    progress: &mpsc::Sender<FlashProgress>,
+) -> Result<()> {
+    report(progress, FlashProgress::Syncing).await;
+    drain(port).await;
+    let mut synced = false;
+    for _ in 0..SYNC_ATTEMPTS {
+        if v1_command(port, &[STK_GET_SYNC, CRC_EOP], 0, SYNC_TIMEOUT)
+            .await
+            .is_ok()
+        {
+            synced = true;
+            break;
+        }
+        drain(port).await;
+    }
+    if !synced {
+        anyhow::bail!("Failed to sync with the STK500v1 bootloader");
+    }
+
+    v1_command(port, &[STK_ENTER_PROGMODE, CRC_EOP], 0, COMMAND_TIMEOUT).await?;
+
+    let total = image.len() as u32;
+    let mut written = 0u32;
+    for (index, page) in image.chunks(options.page_size).enumerate() {
+        v1_load_address(port, base_address + (index * options.page_size) as u32).await?;
+
+        let mut command = vec![
+            STK_PROG_PAGE,
+            (page.len() >> 8) as u8,
+            page.len() as u8,
+            MEMTYPE_FLASH,
+        ];
+        command.extend_from_slice(page);
+        command.push(CRC_EOP);
+        v1_command(port, &command, 0, COMMAND_TIMEOUT).await?;
+
+        written += page.len() as u32;
+        report(progress, FlashProgress::Writing { written, total }).await;
+    }
+
+    if options.verify {
+        let mut verified = 0u32;
+        for (index, page) in image.chunks(options.page_size).enumerate() {
+            let address = base_address + (index * options.page_size) as u32;
+            v1_load_address(port, address).await?;
+
+            let command = [
+                STK_READ_PAGE,
+                (page.len() >> 8) as u8,
+                page.len() as u8,
+                MEMTYPE_FLASH,
+                CRC_EOP,
+            ];
+            let read_back = v1_command(port, &command, page.len(), COMMAND_TIMEOUT).await?;
+            if read_back != page {
+                anyhow::bail!("Verification failed in page at 0x{:X}", address);
+            }
+
+            verified += page.len() as u32;
+            report(progress, FlashProgress::Verifying { verified, total }).await;
+        }
+    }
+
+    v1_command(port, &[STK_LEAVE_PROGMODE, CRC_EOP], 0, COMMAND_TIMEOUT).await?;
+    Ok(())
+}
+
+/// Frames an STK500v2 message body with sequence number and checksum.
+pub fn v2_frame(sequence: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(body.len() + 6);
+    frame.push(MESSAGE_START);
+    frame.push(sequence);
+    frame.extend_from_slice(&(body.len() as u16).to_be_bytes());
+    frame.push(TOKEN);
+    frame.extend_from_slice(body);
+    frame.push(frame.iter().fold(0, |acc, b| acc ^ b));
+    frame
+}
+
+/// Reads one STK500v2 message and returns its body.
+pub(crate) async fn v2_read_message<P: FlashPort>(port: &mut P) -> Result<(u8, Vec<u8>)> {
+    while port.read_u8().await? != MESSAGE_START {}
+
+    let mut header = [0u8; 4];
+    port.read_exact(&mut header).await?;
+    if header[3] != TOKEN {
+        anyhow::bail!("Invalid STK500v2 token 0x{:02X}", header[3]);
+    }
+
+    let len = u16::from_be_bytes([header[1], header[2]]) as usize;
+    let mut body = vec![0u8; len + 1];
+    port.read_exact(&mut body).await?;
+    let checksum = body.pop().unwrap_or_default();
+
+    let expected = header
+        .iter()
+        .chain(body.iter())
+        .fold(MESSAGE_START, |acc, b| acc ^ b);
+    if checksum != expected {
+        anyhow::bail!("STK500v2 checksum mismatch");
+    }
+
+    Ok((header[0], body))
+}
+
+struct V2Session {
+    sequence: u8,
+}
+
+impl V2Session {
+    async fn command<P: FlashPort>(
+        &mut self,
+        port: &mut P,
+        body: &[u8],
+        wait: Duration,
+    ) -> Result<Vec<u8>> {
+        let sequence = self.sequence;
+        self.sequence = self.sequence.wrapping_add(1);
+
+        port.write_all(&v2_frame(sequence, body)).await?;
+        port.flush().await?;
+
+        let (answer_sequence, response) = timeout(wait, v2_read_message(port))
+            .await
+            .map_err(|_| anyhow::anyhow!("Timed out waiting for bootloader response"))??;
+
+        if answer_sequence != sequence || response.first() != Some(&body[0]) {
+            anyhow::bail!("Unexpected STK500v2 answer to command 0x{:02X}", body[0]);
+        }
+        if response.get(1) != Some(&STATUS_CMD_OK) {
+            anyhow::bail!(
+                "STK500v2 command 0x{:02X} failed with status {:02X?}",
+                body[0],
+                response.get(1)
+            );
+        }
+        Ok(response)
+    }
+
+    async fn load_address<P: FlashPort>(&mut self, port: &mut P, address: u32) -> Result<()> {
+        let mut word = address / 2;
+        if word > 0xFFFF {
+            // Tell the bootloader to use the extended address byte
+            word |= 0x8000_0000;
+        }
+        let mut body = vec![CMD_LOAD_ADDRESS];
+        body.extend_from_slice(&word.to_be_bytes());
+        self.command(port, &body, COMMAND_TIMEOUT).await?;
+        Ok(())
+    }
+}
+
+async fn flash_v2<P: FlashPort>(
+    port: &mut P,
+    base_address: u32,
+    image: &[u8],
+    options: &Stk500Options,
+    progress: &mpsc::Sender<FlashProgress>,
+) -> Result<()> {
+    let mut session = V2Session { sequence: 0 };
+
+    report(progress, FlashProgress::Syncing).await;
+    drain(port).await;
+    let mut synced = false;
+    for _ in 0..SYNC_ATTEMPTS {
+        if session
+            .command(port, &[CMD_SIGN_ON], SYNC_TIMEOUT)
+            .await
+            .is_ok()
+        {
+            synced = true;
+            break;
+        }
+        drain(port).await;
+    }
+    if !synced {
+        anyhow::bail!("Failed to sign on to the STK500v2 bootloader");
+    }
+
+    // timeout, stabDelay, cmdexeDelay, synchLoops, byteDelay, pollValue,
+    // pollIndex, then the four "Programming Enable" instruction bytes
+    session
+        .command(
+            port,
+            &[
+                CMD_ENTER_PROGMODE_ISP,
+                200,
+                100,
+                25,
+                32,
+                0,
+                0x53,
+                3,
+                0xAC,
+                0x53,
+                0x00,
+                0x00,
+            ],
+            COMMAND_TIMEOUT,
+        )
+        .await?;
+
+    let total = image.len() as u32;
+    let mut written = 0u32;
+    for (index, page) in image.chunks(options.page_size).enumerate() {
+        session
+            .load_address(port, base_address + (index * options.page_size) as u32)
+            .await?;
+
+        // Page mode write with the standard "load/write program memory" instructions
+        let mut body = vec![
+            CMD_PROGRAM_FLASH_ISP,
+            (page.len() >> 8) as u8,
+            page.len() as u8,
+            0xC1,
+            10,
+            0x40,
+            0x4C,
+            0x20,
+            0x00,
+            0x00,
+        ];
+        body.extend_from_slice(page);
+        session.command(port, &body, COMMAND_TIMEOUT).await?;
+
+        written += page.len() as u32;
+        report(progress, FlashProgress::Writing { written, total }).await;
+    }
+
+    if options.verify {
+        let mut verified = 0u32;
+        for (index, page) in image.chunks(options.page_size).enumerate() {
+            let address = base_address + (index * options.page_size) as u32;
+            session.load_address(port, address).await?;
+
+            let body = [
+                CMD_READ_FLASH_ISP,
+                (page.len() >> 8) as u8,
+                page.len() as u8,
+                0x20,
+            ];
+            let response = session.command(port, &body, COMMAND_TIMEOUT).await?;
+            if response.get(2..2 + page.len()) != Some(page) {
+                anyhow::bail!("Verification failed in page at 0x{:X}", address);
+            }
+
+            verified += page.len() as u32;
+            report(progress, FlashProgress::Verifying { verified, total }).await;
+        }
+    }
+
+    session
+        .command(port, &[CMD_LEAVE_PROGMODE_ISP, 1, 1], COMMAND_TIMEOUT)
+        .await?;
+    Ok(())
+}
+
+/// Resets the board into its bootloader and uploads `image` starting at `base_address`.
+pub async fn flash<P: FlashPort>(
+    port: &mut P,
+    base_address: u32,
+    image: &[u8],
+    options: &Stk500Options,
+    progress: &mpsc::Sender<FlashProgress>,
+) -> Result<()> {
+    if image.is_empty() {
+        anyhow::bail!("Firmware image is empty");
+    }
+    if options.page_size == 0 || options.page_size > 1024 {
+        anyhow::bail!("Invalid page size {}", options.page_size);
+    }
+
+    report(progress, FlashProgress::Resetting).await;
+    reset(port).await?;
+
+    match options.protocol {
+        Stk500Protocol::Stk500v1 => flash_v1(port, base_address, image, options, progress).await,
+        Stk500Protocol::Stk500v2 => flash_v2(port, base_address, image, options, progress).await,
+    }
+}
@@ -0,0 +1,295 @@
+use super::esp::*;
+use super::*;
+use tokio::io::{AsyncWriteExt, DuplexStream};
+
+impl FlashPort for DuplexStream {
+    fn set_dtr(&mut self, _level: bool) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_rts(&mut self, _level: bool) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_parse_offset() {
+    assert_eq!(parse_offset("0x10000").unwrap(), 0x10000);
+    assert_eq!(parse_offset("0X1000").unwrap(), 0x1000);
+    assert_eq!(parse_offset("4096").unwrap(), 4096);
+    assert!(parse_offset("0xZZ").is_err());
+    assert!(parse_offset("-1").is_err());
+}
+
+#[test]
+fn test_slip_encode_escapes() {
+    assert_eq!(
+        slip_encode(&[0x01, 0xC0, 0xDB, 0x02]),
+        vec![0xC0, 0x01, 0xDB, 0xDC, 0xDB, 0xDD, 0x02, 0xC0]
+    );
+}
+
+#[test]
+fn test_checksum() {
+    assert_eq!(checksum(&[]), 0xEF);
+    assert_eq!(checksum(&[0xEF]), 0x00);
+    assert_eq!(checksum(&[0x01, 0x02]), 0xEF ^ 0x03);
+}
+
+#[test]
+fn test_command_packet_layout() {
+    let packet = command_packet(0x03, &[0xAA, 0xBB], 0x12);
+    assert_eq!(
+        packet,
+        vec![0x00, 0x03, 0x02, 0x00, 0x12, 0, 0, 0, 0xAA, 0xBB]
+    );
+}
+
+#[test]
+fn test_erase_size() {
+    assert_eq!(erase_size(EspChip::Esp32, 0x1000, 12345), 12345);
+    // Small image at a block boundary: half the sectors, rounded up
+    assert_eq!(erase_size(EspChip::Esp8266, 0, 3 * 4096), 2 * 4096);
+    // Large image: everything past the head sectors
+    assert_eq!(erase_size(EspChip::Esp8266, 0, 64 * 4096), 48 * 4096);
+}
+
+#[test]
+fn test_progress_serialization() {
+    let json = serde_json::to_value(FlashProgress::Writing {
+        written: 1024,
+        total: 2048,
+    })
+    .unwrap();
+    assert_eq!(json["stage"], "writing");
+    assert_eq!(json["written"], 1024);
+}
+
+/// Minimal stand-in for the ROM loader: acknowledges every command and
+/// records which ones it saw.
+async fn fake_rom(mut port: DuplexStream) -> Vec<u8> {
+    let mut commands = Vec::new();
+    while let Ok(frame) = read_slip_frame(&mut port).await {
+        let command = frame[1];
+        commands.push(command);
+        let response = [0x01, command, 0x02, 0x00, 0, 0, 0, 0, 0x00, 0x00];
+        if port.write_all(&slip_encode(&response)).await.is_err() {
+            break;
+        }
+        if command == 0x04 {
+            break;
+        }
+    }
+    commands
+}
+
+#[tokio::test]
+async fn test_flash_session_against_fake_rom() {
+    let (mut host, device) = tokio::io::duplex(64 * 1024);
+    let rom = tokio::spawn(fake_rom(device));
+
+    let (progress_tx, mut progress_rx) = mpsc::channel(64);
+    let image = vec![0x5A; FLASH_BLOCK_SIZE + 10];
+    let options = EspFlashOptions {
+        chip: EspChip::Esp32,
+        offset: 0x10000,
+        reboot: true,
+    };
+
+    flash(&mut host, &image, &options, &progress_tx)
+        .await
+        .unwrap();
+    drop(progress_tx);
+
+    let commands = rom.await.unwrap();
+    assert_eq!(commands.first(), Some(&0x08));
+    assert_eq!(
+        commands.iter().filter(|&&c| c == 0x03).count(),
+        2,
+        "two FLASH_DATA blocks expected"
+    );
+    assert_eq!(commands.last(), Some(&0x04));
+
+    let mut events = Vec::new();
+    while let Some(event) = progress_rx.recv().await {
+        events.push(event);
+    }
+    assert_eq!(events[0], FlashProgress::Resetting);
+    assert_eq!(
+        events.last(),
+        Some(&FlashProgress::Writing {
+            written: image.len() as u32,
+            total: image.len() as u32
+        })
+    );
+}
+
+#[tokio::test]
+async fn test_flash_rejects_empty_image() {
+    let (mut host, _device) = tokio::io::duplex(64);
+    let (progress_tx, _progress_rx) = mpsc::channel(8);
+    let options = EspFlashOptions {
+        chip: EspChip::Esp32,
+        offset: 0,
+        reboot: false,
+    };
+    assert!(flash(&mut host, &[], &options, &progress_tx).await.is_err());
+}
+
+#[test]
+fn test_ihex_parse() {
+    let text = ":0400000001020304F2\n:02000800AABB91\n:00000001FF\n";
+    let image = ihex::parse(text).unwrap();
+    assert_eq!(image.base_address, 0);
+    assert_eq!(
+        image.data,
+        vec![0x01, 0x02, 0x03, 0x04, 0xFF, 0xFF, 0xFF, 0xFF, 0xAA, 0xBB]
+    );
+}
+
+#[test]
+fn test_ihex_extended_linear_address() {
+    let text = ":020000040001F9\n:0100100042AD\n:00000001FF\n";
+    let image = ihex::parse(text).unwrap();
+    assert_eq!(image.base_address, 0x10010);
+    assert_eq!(image.data, vec![0x42]);
+}
+
+#[test]
+fn test_ihex_rejects_bad_input() {
+    assert!(ihex::parse(":0400000001020304F3\n:00000001FF\n").is_err());
+    assert!(ihex::parse("0400000001020304F2\n:00000001FF\n").is_err());
+    assert!(ihex::parse(":0400000001020304F2\n").is_err());
+    assert!(ihex::parse(":00000001FF\n").is_err());
+    // A record running past 0xFFFFFFFF
+    assert!(ihex::parse(":02000004FFFFFC\n:02FFFF0042427C\n:00000001FF\n").is_err());
+    // Two tiny records gigabytes apart
+    assert!(ihex::parse(":0100000042BD\n:020000044000BA\n:0100000042BD\n:00000001FF\n").is_err());
+}
+
+#[test]
+fn test_stk500v2_frame() {
+    let frame = stk500::v2_frame(1, &[0x01]);
+    assert_eq!(frame[..6], [0x1B, 0x01, 0x00, 0x01, 0x0E, 0x01]);
+    assert_eq!(frame[6], 0x1B ^ 0x01 ^ 0x01 ^ 0x0E ^ 0x01);
+}
+
+/// Optiboot stand-in that keeps written pages in memory so verification can read them back.
+async fn fake_optiboot(mut port: DuplexStream) -> Vec<u8> {
+    use tokio::io::AsyncReadExt;
+
+    let mut flash = vec![0xFFu8; 1024];
+    let mut address = 0usize;
+    while let Ok(command) = port.read_u8().await {
+        let mut reply = vec![0x14];
+        match command {
+            0x30 | 0x50 => {
+                port.read_u8().await.unwrap();
+            }
+            0x51 => {
+                port.read_u8().await.unwrap();
+                port.write_all(&[0x14, 0x10]).await.unwrap();
+                break;
+            }
+            0x55 => {
+                let mut args = [0u8; 3];
+                port.read_exact(&mut args).await.unwrap();
+                address = u16::from_le_bytes([args[0], args[1]]) as usize * 2;
+            }
+            0x64 => {
+                let mut header = [0u8; 3];
+                port.read_exact(&mut header).await.unwrap();
+                let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+                let mut data = vec![0u8; len + 1];
+                port.read_exact(&mut data).await.unwrap();
+                flash[address..address + len].copy_from_slice(&data[..len]);
+            }
+            0x74 => {
+                let mut header = [0u8; 4];
+                port.read_exact(&mut header).await.unwrap();
+                let len = u16::from_be_bytes([header[0], header[1]]) as usize;
+                reply.extend_from_slice(&flash[address..address + len]);
+            }
+            _ => panic!("unexpected command 0x{:02X}", command),
+        }
+        reply.push(0x10);
+        port.write_all(&reply).await.unwrap();
+    }
+    flash
+}
+
+#[tokio::test]
+async fn test_stk500v1_upload_and_verify() {
+    let (mut host, device) = tokio::io::duplex(64 * 1024);
+    let bootloader = tokio::spawn(fake_optiboot(device));
+
+    let (progress_tx, mut progress_rx) = mpsc::channel(64);
+    let image: Vec<u8> = (0..200u32).map(|b| b as u8).collect();
+    let options = stk500::Stk500Options {
+        protocol: stk500::Stk500Protocol::Stk500v1,
+        page_size: 128,
+        verify: true,
+    };
+
+    stk500::flash(&mut host, 0, &image, &options, &progress_tx)
+        .await
+        .unwrap();
+    drop(progress_tx);
+
+    let flash_contents = bootloader.await.unwrap();
+    assert_eq!(&flash_contents[..200], &image[..]);
+
+    let mut events = Vec::new();
+    while let Some(event) = progress_rx.recv().await {
+        events.push(event);
+    }
+    assert!(events.contains(&FlashProgress::Writing {
+        written: 200,
+        total: 200
+    }));
+    assert_eq!(
+        events.last(),
+        Some(&FlashProgress::Verifying {
+            verified: 200,
+            total: 200
+        })
+    );
+}
+
+/// Mega 2560 bootloader stand-in acknowledging every STK500v2 command.
+async fn fake_stk500v2(mut port: DuplexStream) -> Vec<u8> {
+    let mut commands = Vec::new();
+    while let Ok((sequence, body)) = stk500::v2_read_message(&mut port).await {
+        commands.push(body[0]);
+        let reply = stk500::v2_frame(sequence, &[body[0], 0x00]);
+        port.write_all(&reply).await.unwrap();
+        if body[0] == 0x11 {
+            break;
+        }
+    }
+    commands
+}
+
+#[tokio::test]
+async fn test_stk500v2_upload() {
+    let (mut host, device) = tokio::io::duplex(64 * 1024);
+    let bootloader = tokio::spawn(fake_stk500v2(device));
+
+    let (progress_tx, _progress_rx) = mpsc::channel(64);
+    let options = stk500::Stk500Options {
+        protocol: stk500::Stk500Protocol::Stk500v2,
+        page_size: 256,
+        verify: false,
+    };
+
+    stk500::flash(&mut host, 0, &[0xAB; 300], &options, &progress_tx)
+        .await
+        .unwrap();
+
+    let commands = bootloader.await.unwrap();
+    assert_eq!(
+        commands,
+        vec![0x01, 0x10, 0x06, 0x13, 0x06, 0x13, 0x11],
+        "sign on, enter progmode, two pages, leave progmode"
+    );
+}
@@ -0,0 +1,229 @@
+//! gRPC service `webmux.v1.Webmux` (see `proto/webmux.proto`) sharing the
+//! serial manager with the HTTP API, for machine-to-machine clients that
+//! want protobuf contracts. Served over cleartext HTTP/2 on its own
+//! address.
+//!
+//! `StreamData` is flow controlled in both directions: a client message is
+//! only read once the previous one has been written to the port, and
+//! received data waits for the client to read it. Chunks a slow reader
+//! misses are counted in the `dropped` field of the next response.
+
+pub mod proto;
+
+use crate::config::GrpcConfig;
+use crate::serial::SerialManager;
+use crate::telemetry::push;
+use anyhow::Result;
+use proto::webmux_server::{Webmux, WebmuxServer};
+use std::net::SocketAddr;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{error, info, warn};
+
+/// Largest message accepted in either direction, the usual gRPC default.
+pub const MAX_MESSAGE_BYTES: usize = 4 * 1024 * 1024;
+
+/// Response messages buffered per stream before the serial side waits
+/// for the client.
+const STREAM_BUFFER: usize = 16;
+
+type ResponseSender = mpsc::Sender<Result<proto::StreamDataResponse, Status>>;
+
+/// The `Webmux` service over a serial manager.
+#[derive(Clone)]
+pub struct Service {
+    manager: SerialManager,
+}
+
+impl Service {
+    pub fn new(manager: SerialManager) -> Self {
+        Self { manager }
+    }
+
+    async fn require_connection(&self, name: &str) -> Result<(), Status> {
+        if name.is_empty() {
+            return Err(Status::invalid_argument("connection is required"));
+        }
+        if self.manager.get_connection(name).await.is_none() {
+            return Err(Status::not_found(format!("Connection not found: {}", name)));
+        }
+        Ok(())
+    }
+}
+
+#[tonic::async_trait]
+impl Webmux for Service {
+    async fn list_connections(
+        &self,
+        _request: Request<proto::ListConnectionsRequest>,
+    ) -> Result<Response<proto::ListConnectionsResponse>, Status> {
+        let connections = push::snapshot(&self.manager)
+            .await
+            .into_iter()
+            .map(|snapshot| proto::Connection {
+                name: snapshot.stats.name,
+                port: snapshot.stats.port,
+                connected: snapshot.stats.is_connected,
+                bytes_received: snapshot.stats.bytes_received,
+                bytes_sent: snapshot.stats.bytes_sent,
+            })
+            .collect();
+        Ok(Response::new(proto::ListConnectionsResponse {
+            connections,
+        }))
+    }
+
+    async fn send_data(
+        &self,
+        request: Request<proto::SendDataRequest>,
+    ) -> Result<Response<proto::SendDataResponse>, Status> {
+        let request = request.into_inner();
+        self.require_connection(&request.connection).await?;
+        self.manager
+            .send_data(&request.connection, &request.data)
+            .await
+            .map_err(|e| Status::unavailable(e.to_string()))?;
+        Ok(Response::new(proto::SendDataResponse {
+            bytes_sent: request.data.len() as u64,
+        }))
+    }
+
+    type StreamDataStream = ReceiverStream<Result<proto::StreamDataResponse, Status>>;
+
+    async fn stream_data(
+        &self,
+        request: Request<Streaming<proto::StreamDataRequest>>,
+    ) -> Result<Response<Self::StreamDataStream>, Status> {
+        let mut messages = request.into_inner();
+        // The first message names the connection for the whole call
+        let first = messages
+            .message()
+            .await?
+            .ok_or_else(|| Status::invalid_argument("Missing request message"))?;
+        let name = first.connection.clone();
+        self.require_connection(&name).await?;
+        let serial_rx = self
+            .manager
+            .subscribe(&name)
+            .await
+            .map_err(|e| Status::not_found(e.to_string()))?;
+        info!("gRPC stream opened for {}", name);
+
+        let (status_tx, status_rx) = mpsc::channel::<Status>(1);
+        let writer = tokio::spawn(write_requests(
+            self.manager.clone(),
+            name.clone(),
+            first,
+            messages,
+            status_tx,
+        ));
+        let (responses, rx) = mpsc::channel(STREAM_BUFFER);
+        tokio::spawn(async move {
+            let status = forward_data(serial_rx, status_rx, &responses).await;
+            writer.abort();
+            if let Some(status) = status {
+                let _ = responses.send(Err(status)).await;
+            }
+            info!("gRPC stream closed for {}", name);
+        });
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+/// Writes each client message to the port before reading the next. Ends
+/// the call with a status on the first failure.
+async fn write_requests(
+    manager: SerialManager,
+    name: String,
+    first: proto::StreamDataRequest,
+    mut messages: Streaming<proto::StreamDataRequest>,
+    status_tx: mpsc::Sender<Status>,
+) {
+    let mut request = first;
+    let status = loop {
+        if !request.connection.is_empty() && request.connection != name {
+            break Status::invalid_argument(format!("Stream is bound to connection {}", name));
+        }
+        if !request.data.is_empty() {
+            if let Err(e) = manager.send_data(&name, &request.data).await {
+                error!("Failed to send gRPC data to {}: {}", name, e);
+                break Status::unavailable(format!(
+                    "{} bytes were not sent: {}",
+                    request.data.len(),
+                    e
+                ));
+            }
+        }
+        request = match messages.message().await {
+            Ok(Some(request)) => request,
+            // The client is done sending but may keep reading
+            Ok(None) => return,
+            Err(status) => break status,
+        };
+    };
+    let _ = status_tx.send(status).await;
+}
+
+/// Streams received data to the client until the call ends. Returns the
+/// status to finish with, or `None` if the client went away.
+async fn forward_data(
+    mut serial_rx: broadcast::Receiver<Vec<u8>>,
+    mut status_rx: mpsc::Receiver<Status>,
+    responses: &ResponseSender,
+) -> Option<Status> {
+    let mut dropped = 0u64;
+    loop {
+        let data = tokio::select! {
+            _ = responses.closed() => return None,
+            Some(status) = status_rx.recv() => return Some(status),
+            result = serial_rx.recv() => match result {
+                Ok(data) => data,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("gRPC subscriber lagged, skipped {} chunks", skipped);
+                    dropped += skipped;
+                    continue;
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    return Some(Status::unavailable("Connection closed"));
+                }
+            },
+        };
+        let message = proto::StreamDataResponse { data, dropped };
+        dropped = 0;
+        // Waits while the client is not reading
+        if responses.send(Ok(message)).await.is_err() {
+            return None;
+        }
+    }
+}
+
+/// The service with the message size limits applied.
+pub fn server(manager: SerialManager) -> WebmuxServer<Service> {
+    WebmuxServer::new(Service::new(manager))
+        .max_decoding_message_size(MAX_MESSAGE_BYTES)
+        .max_encoding_message_size(MAX_MESSAGE_BYTES)
+}
+
+/// Binds the service's address and serves it in the background,
+/// returning the bound address.
+pub async fn spawn(manager: &SerialManager, config: &GrpcConfig) -> Result<SocketAddr> {
+    let listener = tokio::net::TcpListener::bind(&config.address).await?;
+    let address = listener.local_addr()?;
+    info!("gRPC service listening on {}", address);
+
+    let service = server(manager.clone());
+    tokio::spawn(async move {
+        if let Err(e) = tonic::transport::Server::builder()
+            .add_service(service)
+            .serve_with_incoming(TcpListenerStream::new(listener))
+            .await
+        {
+            error!("gRPC service stopped: {}", e);
+        }
+    });
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests;
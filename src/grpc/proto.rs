@@ -0,0 +1,3 @@
+//! Messages and service stubs generated from `proto/webmux.proto`.
+
+tonic::include_proto!("webmux.v1");
@@ -0,0 +1,137 @@
+use super::proto::webmux_client::WebmuxClient;
+use super::proto::*;
+use super::*;
+use prost::Message;
+use std::time::Duration;
+use tonic::transport::Channel;
+use tonic::Code;
+
+#[test]
+fn test_generated_messages_use_protobuf_wire_format() {
+    let request = SendDataRequest {
+        connection: "a".to_string(),
+        data: vec![1, 2],
+    };
+    assert_eq!(request.encode_to_vec(), vec![0x0a, 1, b'a', 0x12, 2, 1, 2]);
+    assert_eq!(
+        SendDataResponse { bytes_sent: 300 }.encode_to_vec(),
+        vec![0x08, 0xac, 0x02]
+    );
+}
+
+async fn connect(addr: SocketAddr) -> WebmuxClient<Channel> {
+    WebmuxClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_grpc_service_over_http2() {
+    let manager = SerialManager::new();
+    manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let addr = spawn(
+        &manager,
+        &GrpcConfig {
+            address: "127.0.0.1:0".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+    let mut client = connect(addr).await;
+
+    let names: Vec<String> = client
+        .list_connections(ListConnectionsRequest {})
+        .await
+        .unwrap()
+        .into_inner()
+        .connections
+        .into_iter()
+        .map(|connection| connection.name)
+        .collect();
+    assert_eq!(names, vec!["nm-a", "nm-b"]);
+
+    let missing = SendDataRequest {
+        connection: "nope".to_string(),
+        data: b"x".to_vec(),
+    };
+    assert_eq!(
+        client.send_data(missing).await.unwrap_err().code(),
+        Code::NotFound
+    );
+
+    // Open a stream on one end of the pair and write to the other
+    let (requests, rx) = mpsc::channel(4);
+    requests
+        .send(StreamDataRequest {
+            connection: "nm-b".to_string(),
+            data: vec![],
+        })
+        .await
+        .unwrap();
+    let mut responses = client
+        .clone()
+        .stream_data(ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    let mut a_rx = manager.subscribe("nm-a").await.unwrap();
+
+    let sent = client
+        .send_data(SendDataRequest {
+            connection: "nm-a".to_string(),
+            data: b"ping".to_vec(),
+        })
+        .await
+        .unwrap();
+    assert_eq!(sent.into_inner().bytes_sent, 4);
+
+    let received = tokio::time::timeout(Duration::from_secs(5), responses.message())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(received.data, b"ping");
+    assert_eq!(received.dropped, 0);
+
+    // Messages on the stream are written to its connection
+    requests
+        .send(StreamDataRequest {
+            connection: String::new(),
+            data: b"pong".to_vec(),
+        })
+        .await
+        .unwrap();
+    let echoed = tokio::time::timeout(Duration::from_secs(5), a_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(echoed, b"pong");
+
+    // Switching connections mid-stream ends the call
+    requests
+        .send(StreamDataRequest {
+            connection: "nm-a".to_string(),
+            data: b"x".to_vec(),
+        })
+        .await
+        .unwrap();
+    let status = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            match responses.message().await {
+                Ok(Some(_)) => continue,
+                Ok(None) => panic!("stream ended without a status"),
+                Err(status) => break status,
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(status.code(), Code::InvalidArgument);
+}
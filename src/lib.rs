@@ -1,6 +1,16 @@
 // Library exports for testing
 
 pub mod config;
+pub mod flash;
+pub mod grpc;
 pub mod logging;
+pub mod notifications;
+pub mod s3;
 pub mod serial;
+pub mod slcan;
+pub mod snmp;
+pub mod telemetry;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod trace;
 pub mod web;
@@ -0,0 +1,129 @@
+//! Audit trail of client sessions, kept apart from the device logs: who
+//! attached to which connection, when they left, and with `keystrokes`
+//! enabled the exact bytes each session sent.
+//!
+//! Each record is one JSON line:
+//!
+//! ```text
+//! {"timestamp":"2025-11-30T15:30:45.123Z","event":"input","session":7,"connection":"core-sw","client":"alice","address":"203.0.113.9","trace_id":"4bf92f3577b34da6a3ce929d0e0e4736","span_id":"00f067aa0ba902b7","hex":"72656c6f61640d","text":"reload\r"}
+//! ```
+
+use super::{append, open_log_file, LogWriter};
+use crate::config::AuditConfig;
+use crate::trace::TraceContext;
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditEvent {
+    /// A session attached to the connection.
+    Opened,
+    /// Bytes a session sent to the device.
+    Input,
+    /// An operator disconnected the session.
+    Kicked,
+    /// The session ended.
+    Closed,
+}
+
+/// The session a record is about.
+#[derive(Debug, Clone, Copy)]
+pub struct AuditSession<'a> {
+    pub id: u64,
+    pub connection: &'a str,
+    pub client: Option<&'a str>,
+    /// Where the client connected from, behind any trusted proxies.
+    pub address: Option<IpAddr>,
+    /// The session's span in its connection's trace.
+    pub trace: Option<&'a TraceContext>,
+}
+
+#[derive(Debug, Serialize)]
+struct AuditRecord<'a> {
+    timestamp: String,
+    event: AuditEvent,
+    session: u64,
+    connection: &'a str,
+    client: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    address: Option<IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trace_id: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    span_id: Option<&'a str>,
+    /// Exact bytes sent, for `input` records.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hex: Option<String>,
+    /// The same bytes as text, with invalid UTF-8 replaced.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+}
+
+/// One audit record as a line of JSON.
+pub fn format_record(
+    timestamp: DateTime<Utc>,
+    event: AuditEvent,
+    session: AuditSession,
+    data: Option<&[u8]>,
+) -> String {
+    let record = AuditRecord {
+        timestamp: timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        event,
+        session: session.id,
+        connection: session.connection,
+        client: session.client,
+        address: session.address,
+        trace_id: session.trace.map(|trace| trace.trace_id.as_str()),
+        span_id: session.trace.map(|trace| trace.span_id.as_str()),
+        hex: data.map(hex::encode),
+        text: data.map(|data| String::from_utf8_lossy(data).into_owned()),
+    };
+    let mut line = serde_json::to_string(&record).unwrap_or_default();
+    line.push('\n');
+    line
+}
+
+/// Where audit records go; the default records nothing.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    file: Option<Arc<Mutex<LogWriter>>>,
+    keystrokes: bool,
+}
+
+impl AuditLog {
+    pub async fn open(config: &AuditConfig) -> Result<Self> {
+        Ok(Self {
+            file: Some(open_log_file(&config.path, config.max_file_bytes, None).await?),
+            keystrokes: config.keystrokes,
+        })
+    }
+
+    /// Records a session event other than input.
+    pub async fn record(&self, event: AuditEvent, session: AuditSession<'_>) {
+        self.write(event, session, None).await;
+    }
+
+    /// Records what a session sent, if keystrokes are audited.
+    pub async fn input(&self, session: AuditSession<'_>, data: &[u8]) {
+        if self.keystrokes {
+            self.write(AuditEvent::Input, session, Some(data)).await;
+        }
+    }
+
+    async fn write(&self, event: AuditEvent, session: AuditSession<'_>, data: Option<&[u8]>) {
+        let Some(ref file) = self.file else {
+            return;
+        };
+        let line = format_record(Utc::now(), event, session, data);
+        // A failing audit log must not take consoles down with it
+        if let Err(e) = append(file, line.as_bytes()).await {
+            warn!("Failed to write audit record: {}", e);
+        }
+    }
+}
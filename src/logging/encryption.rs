@@ -0,0 +1,125 @@
+//! AES-256-GCM encryption of log files at rest, for consoles where
+//! operators type credentials.
+//!
+//! An encrypted file starts with [`MAGIC`], followed by one sealed record
+//! per append: the ciphertext length as a big-endian `u32`, a random
+//! 12-byte nonce, and the ciphertext with its tag. Appends never touch
+//! earlier records, so a crash loses at most the record being written;
+//! what is left of that record is cut off before appending again.
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use anyhow::Result;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+/// First bytes of every encrypted log file.
+pub const MAGIC: &[u8; 8] = b"WMXENC1\n";
+
+const NONCE_BYTES: usize = 12;
+
+/// Key for sealing log records, read from a file of 64 hex digits.
+#[derive(Clone)]
+pub struct LogKey {
+    cipher: Aes256Gcm,
+}
+
+impl std::fmt::Debug for LogKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("LogKey(..)")
+    }
+}
+
+impl LogKey {
+    pub fn from_hex(hex_key: &str) -> Result<Self> {
+        let bytes = hex::decode(hex_key.trim())
+            .map_err(|_| anyhow::anyhow!("key must be 64 hex digits"))?;
+        let cipher = Aes256Gcm::new_from_slice(&bytes)
+            .map_err(|_| anyhow::anyhow!("key must be 64 hex digits"))?;
+        Ok(Self { cipher })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read key file {}: {}", path.display(), e))?;
+        Self::from_hex(&content).map_err(|e| anyhow::anyhow!("Key file {}: {}", path.display(), e))
+    }
+
+    /// `plaintext` as one record, ready to append after [`MAGIC`].
+    pub fn seal(&self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .cipher
+            .encrypt(&nonce, plaintext)
+            .expect("AES-GCM encryption of an in-memory buffer");
+        let mut record = Vec::with_capacity(4 + NONCE_BYTES + ciphertext.len());
+        record.extend_from_slice(&(ciphertext.len() as u32).to_be_bytes());
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(&ciphertext);
+        record
+    }
+
+    /// The plaintext of a whole encrypted file. A record cut short at the
+    /// end, as after a crash mid-write, is ignored, and so is everything
+    /// from a record that does not decrypt on, unless that is the first.
+    pub fn open(&self, file: &[u8]) -> Result<Vec<u8>> {
+        let mut rest = file
+            .strip_prefix(MAGIC.as_slice())
+            .ok_or_else(|| anyhow::anyhow!("not an encrypted webmux log"))?;
+        let mut plaintext = Vec::new();
+        while rest.len() >= 4 + NONCE_BYTES {
+            let length = u32::from_be_bytes(rest[..4].try_into()?) as usize;
+            let Some(record) = rest.get(4..4 + NONCE_BYTES + length) else {
+                break;
+            };
+            let (nonce, ciphertext) = record.split_at(NONCE_BYTES);
+            let Ok(chunk) = self.cipher.decrypt(Nonce::from_slice(nonce), ciphertext) else {
+                if rest.len() + MAGIC.len() == file.len() {
+                    anyhow::bail!("wrong key, or the file was modified");
+                }
+                break;
+            };
+            plaintext.extend_from_slice(&chunk);
+            rest = &rest[4 + NONCE_BYTES + length..];
+        }
+        Ok(plaintext)
+    }
+}
+
+/// Whether `file` starts like an encrypted log.
+pub fn is_encrypted(file: &[u8]) -> bool {
+    file.starts_with(MAGIC)
+}
+
+/// Whether the file at `path` is an encrypted log, reading only its start.
+pub async fn starts_encrypted(path: &Path) -> Result<bool> {
+    let mut start = Vec::with_capacity(MAGIC.len());
+    tokio::fs::File::open(path)
+        .await?
+        .take(MAGIC.len() as u64)
+        .read_to_end(&mut start)
+        .await?;
+    Ok(is_encrypted(&start))
+}
+
+/// Length of the whole records at the start of the encrypted log at
+/// `path`, reading only their headers. Anything after them is a record cut
+/// short by a crash.
+pub async fn whole_records_len(path: &Path) -> Result<u64> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let size = file.metadata().await?.len();
+    let header = (4 + NONCE_BYTES) as u64;
+    let mut end = MAGIC.len() as u64;
+    let mut length = [0u8; 4];
+    while end + header <= size {
+        file.seek(SeekFrom::Start(end)).await?;
+        file.read_exact(&mut length).await?;
+        let next = end + header + u64::from(u32::from_be_bytes(length));
+        if next > size {
+            break;
+        }
+        end = next;
+    }
+    Ok(end.min(size))
+}
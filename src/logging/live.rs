@@ -0,0 +1,133 @@
+//! The server's own tracing events, broadcast as they happen so operators
+//! can watch reconnect attempts and write errors without shell access.
+
+use chrono::{DateTime, Utc};
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::OnceLock;
+use tokio::sync::broadcast;
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Subscriber};
+use tracing_subscriber::layer::Context;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+/// Events a slow watcher may fall behind by before it misses some.
+const CHANNEL_EVENTS: usize = 1024;
+
+static EVENTS: OnceLock<broadcast::Sender<LogEvent>> = OnceLock::new();
+
+fn serialize_level<S: Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&level.as_str().to_ascii_lowercase())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEvent {
+    pub timestamp: DateTime<Utc>,
+    #[serde(serialize_with = "serialize_level")]
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+    /// The event's other fields and those of the spans it happened in,
+    /// such as a session's `trace_id`, formatted with `Debug`.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+/// Layer that broadcasts events to [`subscribe`]rs. Only events that
+/// reach it are seen, so give it its own filter.
+pub struct LiveLogLayer {
+    events: broadcast::Sender<LogEvent>,
+}
+
+/// The layer to add to the tracing subscriber; `None` if one was made
+/// already, as there is only one process-wide stream.
+pub fn layer() -> Option<LiveLogLayer> {
+    let events = broadcast::channel(CHANNEL_EVENTS).0;
+    EVENTS.set(events.clone()).ok()?;
+    Some(LiveLogLayer { events })
+}
+
+/// Events from now on; `None` if [`layer`] was never installed.
+pub fn subscribe() -> Option<broadcast::Receiver<LogEvent>> {
+    EVENTS.get().map(broadcast::Sender::subscribe)
+}
+
+#[derive(Default)]
+struct Fields {
+    message: String,
+    others: BTreeMap<String, String>,
+}
+
+impl Visit for Fields {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            self.others
+                .insert(field.name().to_string(), value.to_string());
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            let _ = write!(self.message, "{:?}", value);
+        } else {
+            self.others
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+}
+
+/// A span's fields so far, kept with the span for the events inside it.
+struct SpanFields(BTreeMap<String, String>);
+
+impl<S> Layer<S> for LiveLogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        attrs.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            span.extensions_mut().insert(SpanFields(fields.others));
+        }
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let mut fields = Fields::default();
+        values.record(&mut fields);
+        if let Some(span) = ctx.span(id) {
+            if let Some(SpanFields(known)) = span.extensions_mut().get_mut::<SpanFields>() {
+                known.extend(fields.others);
+            }
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        // Nobody is watching most of the time; skip the formatting then
+        if self.events.receiver_count() == 0 {
+            return;
+        }
+        let mut fields = Fields::default();
+        // Outermost span first, so inner spans and the event win
+        if let Some(scope) = ctx.event_scope(event) {
+            for span in scope.from_root() {
+                if let Some(SpanFields(known)) = span.extensions().get::<SpanFields>() {
+                    fields.others.extend(known.clone());
+                }
+            }
+        }
+        event.record(&mut fields);
+        let metadata = event.metadata();
+        let _ = self.events.send(LogEvent {
+            timestamp: Utc::now(),
+            level: *metadata.level(),
+            target: metadata.target().to_string(),
+            message: fields.message,
+            fields: fields.others,
+        });
+    }
+}
@@ -1,33 +1,153 @@
+use crate::config::LoggingConfig;
 use anyhow::Result;
 use chrono::Local;
-use std::path::Path;
+use encryption::LogKey;
+use redact::Redactor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use tokio::fs::{File, OpenOptions};
 use tokio::io::AsyncWriteExt;
 use tokio::sync::Mutex;
+use tracing::warn;
+
+pub mod audit;
+pub mod encryption;
+pub mod live;
+pub mod redact;
+pub mod retention;
+pub mod search;
+pub mod ship;
+pub mod syslog;
+pub use audit::AuditLog;
+pub use syslog::SyslogForwarder;
+
+/// An append-only log file that rotates once it reaches `max_size`, and
+/// seals each append with `key` if it has one.
+struct LogWriter {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    key: Option<LogKey>,
+}
+
+impl LogWriter {
+    async fn append(&mut self, bytes: &[u8]) -> Result<()> {
+        let sealed;
+        let bytes = match self.key {
+            Some(ref key) => {
+                sealed = key.seal(bytes);
+                &sealed
+            }
+            None => bytes,
+        };
+        if self
+            .max_size
+            .is_some_and(|max| self.size > 0 && self.size + bytes.len() as u64 > max)
+        {
+            self.rotate().await?;
+        }
+
+        if self.size == 0 && self.key.is_some() {
+            self.file.write_all(encryption::MAGIC).await?;
+            self.size += encryption::MAGIC.len() as u64;
+        }
+        self.file.write_all(bytes).await?;
+        self.file.flush().await?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    async fn rotate(&mut self) -> Result<()> {
+        let now = chrono::Utc::now();
+        let mut attempt = 0;
+        let mut target = retention::rotated_path(&self.path, now, attempt);
+        while tokio::fs::try_exists(&target).await? {
+            attempt += 1;
+            target = retention::rotated_path(&self.path, now, attempt);
+        }
+
+        tokio::fs::rename(&self.path, &target).await?;
+        self.file = open_append(&self.path).await?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+enum LogFile {
+    /// Timestamped HEX/ASCII lines.
+    Text(Arc<Mutex<LogWriter>>),
+    /// Exact bytes, one file per direction.
+    Raw {
+        rx: Arc<Mutex<LogWriter>>,
+        tx: Arc<Mutex<LogWriter>>,
+    },
+}
 
 #[derive(Clone)]
 pub struct SerialLogger {
-    file: std::sync::Arc<Mutex<File>>,
+    file: Option<LogFile>,
+    syslog: Option<SyslogForwarder>,
+    /// Shared by RX and TX, which prompt tracking needs to see in order.
+    redactor: Option<Arc<std::sync::Mutex<Redactor>>>,
     connection_name: String,
 }
 
 impl SerialLogger {
     pub async fn new(path: &Path, connection_name: &str) -> Result<Self> {
-        // Create parent directories if they don't exist
-        if let Some(parent) = path.parent() {
-            tokio::fs::create_dir_all(parent).await?;
+        Ok(Self {
+            file: Some(LogFile::Text(open_log_file(path, None, None).await?)),
+            syslog: None,
+            redactor: None,
+            connection_name: connection_name.to_string(),
+        })
+    }
+
+    /// Builds the logger described by a connection's `logging` section, or
+    /// `None` if neither the file log nor syslog forwarding is enabled.
+    pub async fn from_config(
+        config: &LoggingConfig,
+        connection_name: &str,
+    ) -> Result<Option<Self>> {
+        let max_size = config.rotation_size();
+        let key = match config.encryption {
+            Some(ref encryption) if config.enabled => Some(LogKey::load(&encryption.key_file)?),
+            _ => None,
+        };
+        let file = match (config.enabled, config.raw) {
+            (false, _) => None,
+            (true, false) => Some(LogFile::Text(
+                open_log_file(&config.path, max_size, key).await?,
+            )),
+            (true, true) => {
+                let (rx, tx) = raw_capture_paths(&config.path);
+                Some(LogFile::Raw {
+                    rx: open_log_file(&rx, max_size, key.clone()).await?,
+                    tx: open_log_file(&tx, max_size, key).await?,
+                })
+            }
+        };
+        let syslog = config
+            .syslog
+            .as_ref()
+            .map(|syslog| SyslogForwarder::new(syslog, connection_name));
+
+        if file.is_none() && syslog.is_none() {
+            return Ok(None);
         }
 
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)
-            .await?;
+        let redactor = match config.redact {
+            Some(ref redact) => Some(Arc::new(std::sync::Mutex::new(Redactor::new(redact)?))),
+            None => None,
+        };
 
-        Ok(Self {
-            file: std::sync::Arc::new(Mutex::new(file)),
+        Ok(Some(Self {
+            file,
+            syslog,
+            redactor,
             connection_name: connection_name.to_string(),
-        })
+        }))
     }
 
     pub async fn log_received(&self, data: &[u8]) -> Result<()> {
@@ -39,38 +159,132 @@ impl SerialLogger {
     }
 
     async fn log_data(&self, direction: &str, data: &[u8]) -> Result<()> {
-        let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
-        let hex_data = data
-            .iter()
-            .map(|b| format!("{:02x}", b))
-            .collect::<Vec<_>>()
-            .join(" ");
-
-        let ascii_data: String = data
-            .iter()
-            .map(|&b| {
-                if b.is_ascii_graphic() || b == b' ' {
-                    b as char
+        let scrubbed;
+        let data = match self.redactor {
+            Some(ref redactor) => {
+                let mut redactor = redactor.lock().unwrap();
+                scrubbed = if direction == "RX" {
+                    redactor.received(data)
                 } else {
-                    '.'
+                    redactor.sent(data)
+                };
+                // The rest of a secret already logged as REDACTED
+                if scrubbed.is_empty() {
+                    return Ok(());
                 }
-            })
-            .collect();
-
-        let log_line = format!(
-            "[{}] {} | {} | {} bytes | HEX: {} | ASCII: {}\n",
-            timestamp,
-            self.connection_name,
-            direction,
-            data.len(),
-            hex_data,
-            ascii_data
-        );
-
-        let mut file = self.file.lock().await;
-        file.write_all(log_line.as_bytes()).await?;
-        file.flush().await?;
+                &scrubbed
+            }
+            None => data,
+        };
+        let record = format_record(data);
+
+        if let Some(ref syslog) = self.syslog {
+            syslog.forward(direction, &record);
+        }
+
+        match self.file {
+            Some(LogFile::Text(ref file)) => {
+                let timestamp = Local::now().format("%Y-%m-%d %H:%M:%S%.3f");
+                let log_line = format!(
+                    "[{}] {} | {} | {}\n",
+                    timestamp, self.connection_name, direction, record
+                );
+                append(file, log_line.as_bytes()).await?;
+            }
+            Some(LogFile::Raw { ref rx, ref tx }) => {
+                append(if direction == "RX" { rx } else { tx }, data).await?;
+            }
+            None => {}
+        }
 
         Ok(())
     }
 }
+
+/// Files used by `raw: true` capture: `logs/dev.log` becomes
+/// `logs/dev.bin` (RX) and `logs/dev.tx.bin` (TX).
+pub fn raw_capture_paths(path: &Path) -> (PathBuf, PathBuf) {
+    (path.with_extension("bin"), path.with_extension("tx.bin"))
+}
+
+async fn append(file: &Mutex<LogWriter>, bytes: &[u8]) -> Result<()> {
+    file.lock().await.append(bytes).await
+}
+
+async fn open_append(path: &Path) -> Result<File> {
+    Ok(OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?)
+}
+
+async fn open_log_file(
+    path: &Path,
+    max_size: Option<u64>,
+    key: Option<LogKey>,
+) -> Result<Arc<Mutex<LogWriter>>> {
+    // Create parent directories if they don't exist
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let file = open_append(path).await?;
+    let size = file.metadata().await?.len();
+    let mut writer = LogWriter {
+        path: path.to_path_buf(),
+        file,
+        size,
+        max_size,
+        key,
+    };
+
+    // Encryption was switched on or off since the file was started, so
+    // keep it whole under a rotated name rather than mixing the two
+    if size > 0 && encryption::starts_encrypted(path).await? != writer.key.is_some() {
+        writer.rotate().await?;
+    } else if size > 0 && writer.key.is_some() {
+        // A record cut short by a crash would hide every one appended after it
+        let end = encryption::whole_records_len(path).await?;
+        if end < size {
+            warn!(
+                "Dropping {} bytes of a record cut short at the end of {}",
+                size - end,
+                path.display()
+            );
+            writer.file.set_len(end).await?;
+            writer.size = end;
+        }
+    }
+    Ok(Arc::new(Mutex::new(writer)))
+}
+
+/// Describes a chunk of data as `N bytes | HEX: .. | ASCII: ..`.
+pub fn format_record(data: &[u8]) -> String {
+    let hex_data = data
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    let ascii_data: String = data
+        .iter()
+        .map(|&b| {
+            if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+
+    format!(
+        "{} bytes | HEX: {} | ASCII: {}",
+        data.len(),
+        hex_data,
+        ascii_data
+    )
+}
+
+#[cfg(test)]
+mod tests;
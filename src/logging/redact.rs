@@ -0,0 +1,124 @@
+//! Scrubbing of credentials from what is written to disk (`logging.redact`).
+//!
+//! Log files, syslog and audit records get the scrubbed bytes; the live
+//! stream to clients and the port itself are never touched. Two kinds of
+//! secret are recognised: the line sent after a received prompt such as
+//! `Password:`, and anything matching a configured pattern.
+
+use crate::config::RedactConfig;
+use anyhow::Result;
+use regex::bytes::Regex;
+
+/// What a secret is replaced with.
+pub const REDACTED: &[u8] = b"[REDACTED]";
+
+/// Longest received line kept for matching prompts.
+const MAX_LINE: usize = 256;
+
+/// Scrubber for one stream of traffic. Prompts are tracked across chunks,
+/// so received and sent data must be fed in the order they happened.
+#[derive(Debug)]
+pub struct Redactor {
+    prompts: Vec<Regex>,
+    patterns: Vec<Regex>,
+    /// The received line so far, for spotting a prompt split over chunks.
+    line: Vec<u8>,
+    /// A prompt was received and the reply has not been finished yet.
+    armed: bool,
+    /// `REDACTED` has been written for the reply being masked.
+    masked: bool,
+}
+
+impl Redactor {
+    pub fn new(config: &RedactConfig) -> Result<Self> {
+        let prompts = config
+            .prompts
+            .iter()
+            .map(|prompt| {
+                Regex::new(&format!(r"(?:{})\s*$", prompt))
+                    .map_err(|e| anyhow::anyhow!("redact prompt {:?}: {}", prompt, e))
+            })
+            .collect::<Result<_>>()?;
+        let patterns = config
+            .patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern)
+                    .map_err(|e| anyhow::anyhow!("redact pattern {:?}: {}", pattern, e))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            prompts,
+            patterns,
+            line: Vec::new(),
+            armed: false,
+            masked: false,
+        })
+    }
+
+    /// Scrubs data received from the device, noting any prompt it ends on.
+    pub fn received(&mut self, data: &[u8]) -> Vec<u8> {
+        for &b in data {
+            if b == b'\r' || b == b'\n' {
+                self.line.clear();
+            } else {
+                self.line.push(b);
+            }
+        }
+        let excess = self.line.len().saturating_sub(MAX_LINE);
+        self.line.drain(..excess);
+        if self
+            .prompts
+            .iter()
+            .any(|prompt| prompt.is_match(&self.line))
+        {
+            self.line.clear();
+            self.armed = true;
+            self.masked = false;
+        }
+        self.scrub(data)
+    }
+
+    /// Scrubs data sent to the device. After a prompt, everything up to
+    /// the next line ending becomes a single `REDACTED`, however many
+    /// writes it took to type.
+    pub fn sent(&mut self, data: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(data.len());
+        for &b in data {
+            if !self.armed {
+                out.push(b);
+            } else if b == b'\r' || b == b'\n' {
+                self.armed = false;
+                out.push(b);
+            } else if !self.masked {
+                self.masked = true;
+                out.extend_from_slice(REDACTED);
+            }
+        }
+        self.scrub(&out)
+    }
+
+    /// Replaces pattern matches, or only their first group if they have
+    /// one. Matches are found within a chunk, not across chunks.
+    fn scrub(&self, data: &[u8]) -> Vec<u8> {
+        let mut data = data.to_vec();
+        for pattern in &self.patterns {
+            if !pattern.is_match(&data) {
+                continue;
+            }
+            let mut out = Vec::with_capacity(data.len());
+            let mut last = 0;
+            for captures in pattern.captures_iter(&data) {
+                let Some(secret) = captures.get(1).or_else(|| captures.get(0)) else {
+                    continue;
+                };
+                out.extend_from_slice(&data[last..secret.start()]);
+                out.extend_from_slice(REDACTED);
+                last = secret.end();
+            }
+            out.extend_from_slice(&data[last..]);
+            data = out;
+        }
+        data
+    }
+}
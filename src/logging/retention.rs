@@ -0,0 +1,187 @@
+//! Rotated log files and the retention policy applied to them.
+
+use crate::config::LoggingConfig;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::info;
+
+/// How often the background sweeper applies retention policies.
+pub const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Name a rotated copy of `active` gets: the timestamp goes between the
+/// stem and the extension, with `-N` appended to resolve collisions.
+pub fn rotated_path(active: &Path, now: DateTime<Utc>, attempt: u32) -> PathBuf {
+    let stem = active
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut name = format!("{}.{}", stem, now.format("%Y%m%d-%H%M%S%6f"));
+    if attempt > 0 {
+        name.push_str(&format!("-{}", attempt));
+    }
+    if let Some(ext) = active.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    active.with_file_name(name)
+}
+
+/// Whether `name` is a rotated copy of `active` (see [`rotated_path`]).
+pub fn is_rotated(active: &Path, name: &str) -> bool {
+    let Some(stem) = active.file_stem().map(|stem| stem.to_string_lossy()) else {
+        return false;
+    };
+    let Some(rest) = name
+        .strip_prefix(stem.as_ref())
+        .and_then(|rest| rest.strip_prefix('.'))
+    else {
+        return false;
+    };
+    let middle = match active.extension() {
+        Some(ext) => match rest
+            .strip_suffix(ext.to_string_lossy().as_ref())
+            .and_then(|rest| rest.strip_suffix('.'))
+        {
+            Some(middle) => middle,
+            None => return false,
+        },
+        None => rest,
+    };
+
+    middle.len() > 9
+        && middle[..8].bytes().all(|b| b.is_ascii_digit())
+        && middle.as_bytes()[8] == b'-'
+        && middle[9..].bytes().all(|b| b.is_ascii_digit() || b == b'-')
+}
+
+/// Files a connection writes to directly.
+pub fn active_paths(config: &LoggingConfig) -> Vec<PathBuf> {
+    if config.raw {
+        let (rx, tx) = super::raw_capture_paths(&config.path);
+        vec![rx, tx]
+    } else {
+        vec![config.path.clone()]
+    }
+}
+
+struct LogFileEntry {
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+/// Active and rotated files belonging to a connection, rotated ones oldest first.
+async fn scan(config: &LoggingConfig) -> Result<(Vec<LogFileEntry>, Vec<LogFileEntry>)> {
+    let mut active = Vec::new();
+    let mut rotated = Vec::new();
+
+    for path in active_paths(config) {
+        if let Ok(metadata) = tokio::fs::metadata(&path).await {
+            active.push(LogFileEntry {
+                path: path.clone(),
+                size: metadata.len(),
+                modified: metadata.modified()?,
+            });
+        }
+
+        let dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        };
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            if !is_rotated(&path, &entry.file_name().to_string_lossy()) {
+                continue;
+            }
+            let metadata = entry.metadata().await?;
+            if metadata.is_file() {
+                rotated.push(LogFileEntry {
+                    path: entry.path(),
+                    size: metadata.len(),
+                    modified: metadata.modified()?,
+                });
+            }
+        }
+    }
+
+    rotated.sort_by_key(|entry| entry.modified);
+    Ok((active, rotated))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct LogUsage {
+    pub bytes: u64,
+    pub files: usize,
+}
+
+/// Disk space taken by a connection's log files, rotated ones included.
+pub async fn usage(config: &LoggingConfig) -> Result<LogUsage> {
+    let (active, rotated) = scan(config).await?;
+    Ok(active
+        .iter()
+        .chain(&rotated)
+        .fold(LogUsage::default(), |usage, entry| LogUsage {
+            bytes: usage.bytes + entry.size,
+            files: usage.files + 1,
+        }))
+}
+
+/// A connection's log files in the order they were written: rotated ones
+/// oldest first, then the active ones.
+pub async fn files(config: &LoggingConfig) -> Result<Vec<PathBuf>> {
+    let (active, rotated) = scan(config).await?;
+    Ok(rotated
+        .into_iter()
+        .chain(active)
+        .map(|entry| entry.path)
+        .collect())
+}
+
+/// Rotated files, oldest first, with when each was last written to.
+pub async fn rotated_files(config: &LoggingConfig) -> Result<Vec<(PathBuf, SystemTime)>> {
+    let (_, rotated) = scan(config).await?;
+    Ok(rotated
+        .into_iter()
+        .map(|entry| (entry.path, entry.modified))
+        .collect())
+}
+
+/// Deletes rotated files that are too old or exceed the byte budget, oldest
+/// first. Active files are never touched. Returns the number of files removed.
+pub async fn sweep(config: &LoggingConfig, now: SystemTime) -> Result<usize> {
+    let Some(ref retention) = config.retention else {
+        return Ok(0);
+    };
+    let (active, rotated) = scan(config).await?;
+
+    let max_age = retention
+        .max_age_days
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    let mut total: u64 = active.iter().chain(&rotated).map(|entry| entry.size).sum();
+    let mut removed = 0;
+
+    for entry in rotated {
+        let expired = max_age.is_some_and(|max_age| {
+            now.duration_since(entry.modified)
+                .is_ok_and(|age| age > max_age)
+        });
+        let over_budget = retention.max_bytes.is_some_and(|max| total > max);
+        if !expired && !over_budget {
+            continue;
+        }
+
+        tokio::fs::remove_file(&entry.path).await?;
+        info!("Removed old log file {}", entry.path.display());
+        total -= entry.size;
+        removed += 1;
+    }
+
+    Ok(removed)
+}
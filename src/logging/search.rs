@@ -0,0 +1,177 @@
+//! Regex search over the text logs of several connections.
+
+use super::encryption::{self, LogKey};
+use super::retention;
+use crate::config::LoggingConfig;
+use crate::serial::Direction;
+use anyhow::Result;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use regex::Regex;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::OnceLock;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
+
+/// Most records a single page can hold.
+pub const MAX_LIMIT: usize = 1000;
+
+/// Furthest into the matches a page can end, `offset + limit`. Each
+/// connection's matches up to there are held in memory to order them.
+pub const MAX_DEPTH: usize = 10_000;
+
+/// `[2024-01-15 10:30:00.123] plc | RX | 5 bytes | HEX: 48 65 | ASCII: He`
+fn line_pattern() -> &'static Regex {
+    static PATTERN: OnceLock<Regex> = OnceLock::new();
+    PATTERN.get_or_init(|| {
+        Regex::new(r"^\[([^\]]+)\] .* \| (RX|TX) \| \d+ bytes \| HEX: ([0-9a-f ]*) \| ASCII: ")
+            .expect("valid regex")
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    /// Matched against each logged chunk, decoded as UTF-8.
+    pub pattern: Regex,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Matches to skip, from a previous page's `next_offset`.
+    pub offset: usize,
+    pub limit: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchRecord {
+    pub timestamp: DateTime<Utc>,
+    pub connection: String,
+    pub direction: Direction,
+    /// The bytes as UTF-8, with invalid sequences replaced.
+    pub text: String,
+    pub hex: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchResults {
+    pub records: Vec<SearchRecord>,
+    /// Offset of the next page, if there are more matches.
+    pub next_offset: Option<usize>,
+}
+
+fn parse_line(line: &str) -> Option<(DateTime<Utc>, Direction, Vec<u8>)> {
+    let captures = line_pattern().captures(line)?;
+    // Text logs are written in local time
+    let local = NaiveDateTime::parse_from_str(&captures[1], "%Y-%m-%d %H:%M:%S%.3f").ok()?;
+    let timestamp = Local
+        .from_local_datetime(&local)
+        .earliest()?
+        .with_timezone(&Utc);
+    let direction = match &captures[2] {
+        "RX" => Direction::Rx,
+        _ => Direction::Tx,
+    };
+    let data = hex::decode(captures[3].replace(' ', "")).ok()?;
+    Some((timestamp, direction, data))
+}
+
+/// A log file's lines, decrypted first if it is encrypted. Plain files are
+/// streamed; encrypted ones are read whole.
+async fn open_log(
+    path: &Path,
+    key: Option<&LogKey>,
+) -> Result<Box<dyn AsyncBufRead + Unpin + Send>> {
+    if !encryption::starts_encrypted(path).await? {
+        return Ok(Box::new(BufReader::new(tokio::fs::File::open(path).await?)));
+    }
+    let Some(key) = key else {
+        anyhow::bail!("{} is encrypted and no key is configured", path.display());
+    };
+    let plaintext = key
+        .open(&tokio::fs::read(path).await?)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    Ok(Box::new(std::io::Cursor::new(plaintext)))
+}
+
+/// The first `wanted` matches in one connection's logs, oldest first.
+async fn search_connection(
+    name: &str,
+    logging: &LoggingConfig,
+    query: &SearchQuery,
+    wanted: usize,
+) -> Result<Vec<SearchRecord>> {
+    let key = match logging.encryption {
+        Some(ref encryption) => Some(LogKey::load(&encryption.key_file)?),
+        None => None,
+    };
+    let mut found = Vec::new();
+    for path in retention::files(logging).await? {
+        let reader = match open_log(&path, key.as_ref()).await {
+            Ok(reader) => reader,
+            // Rotated away or swept since the directory was listed
+            Err(e)
+                if e.downcast_ref::<std::io::Error>()
+                    .is_some_and(|e| e.kind() == std::io::ErrorKind::NotFound) =>
+            {
+                continue
+            }
+            Err(e) => return Err(e),
+        };
+        let mut lines = reader.lines();
+        while let Some(line) = lines.next_line().await? {
+            let Some((timestamp, direction, data)) = parse_line(&line) else {
+                continue;
+            };
+            if query.from.is_some_and(|from| timestamp < from) {
+                continue;
+            }
+            if query.to.is_some_and(|to| timestamp > to) {
+                continue;
+            }
+            let text = String::from_utf8_lossy(&data);
+            if !query.pattern.is_match(&text) {
+                continue;
+            }
+            found.push(SearchRecord {
+                timestamp,
+                connection: name.to_string(),
+                direction,
+                text: text.into_owned(),
+                hex: hex::encode(&data),
+            });
+            if found.len() == wanted {
+                return Ok(found);
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Searches the text logs of `connections`, returning one page of matches
+/// in time order across all of them.
+pub async fn search(
+    connections: &[(String, LoggingConfig)],
+    query: &SearchQuery,
+) -> Result<SearchResults> {
+    // Each log is in time order, so only the first `wanted` matches of each
+    // can land on this page or show that another one follows
+    let end = query.offset.saturating_add(query.limit);
+    let wanted = end.saturating_add(1);
+    let mut records = Vec::new();
+    for (name, logging) in connections {
+        records.extend(search_connection(name, logging, query, wanted).await?);
+    }
+    records.sort_by(|a, b| {
+        a.timestamp
+            .cmp(&b.timestamp)
+            .then_with(|| a.connection.cmp(&b.connection))
+    });
+
+    let more = records.len() > end;
+    let records: Vec<SearchRecord> = records
+        .into_iter()
+        .skip(query.offset)
+        .take(query.limit)
+        .collect();
+    Ok(SearchResults {
+        records,
+        next_offset: more.then_some(end),
+    })
+}
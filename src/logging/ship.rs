@@ -0,0 +1,103 @@
+//! Log shipping (`logging.ship`): rotated files are uploaded to an
+//! S3-compatible bucket and deleted once stored, for gateways whose disks
+//! are too small to keep much history.
+
+use super::retention;
+use crate::config::{LoggingConfig, S3Config};
+use crate::s3;
+use anyhow::Result;
+use chrono::{DateTime, NaiveDate, Utc};
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Uploads tried per file and sweep; a file still not stored is tried
+/// again on the next sweep.
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+/// Wait before the second attempt, doubled before each one after.
+const FIRST_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// Checks the bucket and that the prefix only uses known placeholders.
+pub fn validate(ship: &S3Config) -> Result<()> {
+    s3::validate(ship)?;
+    let mut rest = ship.prefix.as_str();
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            anyhow::bail!("unclosed placeholder in prefix {}", ship.prefix);
+        };
+        let placeholder = &rest[start..start + end + 1];
+        if placeholder != "{connection}" && placeholder != "{date}" {
+            anyhow::bail!(
+                "unknown placeholder {} in prefix; use {{connection}} or {{date}}",
+                placeholder
+            );
+        }
+        rest = &rest[start + end + 1..];
+    }
+    Ok(())
+}
+
+/// Where a rotated file goes within the bucket: the prefix with its
+/// placeholders filled in, then the file's own name.
+pub fn object_name(template: &str, connection: &str, date: NaiveDate, file: &Path) -> String {
+    let prefix = template
+        .replace("{connection}", connection)
+        .replace("{date}", &date.format("%Y-%m-%d").to_string());
+    let name = file
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    format!("{}{}", prefix, name)
+}
+
+async fn upload_with_retries(
+    client: &reqwest::Client,
+    ship: &S3Config,
+    name: &str,
+    path: &Path,
+) -> Result<String> {
+    // The prefix is a template here, already filled into `name`
+    let bucket = S3Config {
+        prefix: String::new(),
+        ..ship.clone()
+    };
+    let mut delay = FIRST_RETRY_DELAY;
+    let mut attempt = 1;
+    loop {
+        match s3::put_file(client, &bucket, name, path, "application/octet-stream").await {
+            Ok(location) => return Ok(location),
+            Err(e) if attempt < UPLOAD_ATTEMPTS => {
+                warn!("Upload attempt {} of {} failed: {}", attempt, name, e);
+                attempt += 1;
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Uploads a connection's rotated files oldest first, deleting each once
+/// stored, and stops at the first that cannot be. Returns the number shipped.
+pub async fn ship(
+    client: &reqwest::Client,
+    connection: &str,
+    config: &LoggingConfig,
+) -> Result<usize> {
+    let Some(ref ship) = config.ship else {
+        return Ok(0);
+    };
+    let mut shipped = 0;
+    for (path, modified) in retention::rotated_files(config).await? {
+        let date = DateTime::<Utc>::from(modified).date_naive();
+        let name = object_name(&ship.prefix, connection, date, &path);
+        let location = upload_with_retries(client, ship, &name, &path)
+            .await
+            .map_err(|e| anyhow::anyhow!("Cannot ship {}: {}", path.display(), e))?;
+        tokio::fs::remove_file(&path).await?;
+        info!("Shipped log file {} to {}", path.display(), location);
+        shipped += 1;
+    }
+    Ok(shipped)
+}
@@ -0,0 +1,179 @@
+//! RFC 5424 forwarding of serial log records.
+
+use crate::config::{SyslogConfig, SyslogTransport};
+use anyhow::Result;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::time::Duration;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Records waiting to be sent; more are dropped rather than stalling the port.
+const QUEUE_DEPTH: usize = 1024;
+/// Severity "informational".
+const SEVERITY: u8 = 6;
+const MAX_APP_NAME: usize = 48;
+/// Pause before reconnecting after a failure; records are dropped meanwhile.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Formats one RFC 5424 message.
+///
+/// The connection name becomes the APP-NAME (spaces and other characters
+/// syslog disallows are replaced with `_`) and the direction the MSGID.
+pub fn format_message(
+    facility: u8,
+    timestamp: DateTime<Utc>,
+    hostname: &str,
+    app_name: &str,
+    direction: &str,
+    message: &str,
+) -> String {
+    let app_name: String = app_name
+        .chars()
+        .map(|c| if c.is_ascii_graphic() { c } else { '_' })
+        .take(MAX_APP_NAME)
+        .collect();
+
+    format!(
+        "<{}>1 {} {} {} {} {} - {}",
+        facility * 8 + SEVERITY,
+        timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+        if hostname.is_empty() { "-" } else { hostname },
+        if app_name.is_empty() { "-" } else { &app_name },
+        std::process::id(),
+        direction,
+        message
+    )
+}
+
+/// Sends log records to a syslog collector from a background task.
+#[derive(Clone)]
+pub struct SyslogForwarder {
+    tx: mpsc::Sender<String>,
+    facility: u8,
+    hostname: String,
+    app_name: String,
+}
+
+impl SyslogForwarder {
+    pub fn new(config: &SyslogConfig, app_name: &str) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_DEPTH);
+        tokio::spawn(run(config.clone(), rx));
+
+        Self {
+            tx,
+            facility: config.facility.code(),
+            hostname: hostname::get()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            app_name: app_name.to_string(),
+        }
+    }
+
+    pub fn forward(&self, direction: &str, message: &str) {
+        let message = format_message(
+            self.facility,
+            Utc::now(),
+            &self.hostname,
+            &self.app_name,
+            direction,
+            message,
+        );
+        if self.tx.try_send(message).is_err() {
+            debug!(
+                "Syslog queue for {} is full, dropping record",
+                self.app_name
+            );
+        }
+    }
+}
+
+enum Sink {
+    Udp(UdpSocket),
+    Stream(Box<dyn AsyncWrite + Unpin + Send>),
+}
+
+async fn connect(config: &SyslogConfig) -> Result<Sink> {
+    match config.transport {
+        SyslogTransport::Udp => {
+            let target = tokio::net::lookup_host(&config.address)
+                .await?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Cannot resolve {}", config.address))?;
+            let bind = if target.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(bind).await?;
+            socket.connect(target).await?;
+            Ok(Sink::Udp(socket))
+        }
+        SyslogTransport::Tcp => Ok(Sink::Stream(Box::new(
+            TcpStream::connect(&config.address).await?,
+        ))),
+        SyslogTransport::Tls => {
+            let stream = TcpStream::connect(&config.address).await?;
+            let domain = config
+                .address
+                .rsplit_once(':')
+                .map_or(config.address.as_str(), |(host, _)| host)
+                .trim_matches(['[', ']']);
+            let connector = tokio_native_tls::TlsConnector::from(
+                tokio_native_tls::native_tls::TlsConnector::new()?,
+            );
+            Ok(Sink::Stream(Box::new(
+                connector.connect(domain, stream).await?,
+            )))
+        }
+    }
+}
+
+async fn send(sink: &mut Sink, message: &str) -> Result<()> {
+    match sink {
+        Sink::Udp(socket) => {
+            socket.send(message.as_bytes()).await?;
+        }
+        // Octet-counted framing (RFC 6587)
+        Sink::Stream(stream) => {
+            stream
+                .write_all(format!("{} {}", message.len(), message).as_bytes())
+                .await?;
+            stream.flush().await?;
+        }
+    }
+    Ok(())
+}
+
+/// Delivers queued records, reconnecting after failures. Records that
+/// cannot be delivered are dropped.
+async fn run(config: SyslogConfig, mut rx: mpsc::Receiver<String>) {
+    let mut sink: Option<Sink> = None;
+    let mut retry_at = Instant::now();
+
+    while let Some(message) = rx.recv().await {
+        if sink.is_none() {
+            if Instant::now() < retry_at {
+                continue;
+            }
+            match connect(&config).await {
+                Ok(connected) => sink = Some(connected),
+                Err(e) => {
+                    warn!("Failed to connect to syslog at {}: {}", config.address, e);
+                    retry_at = Instant::now() + RETRY_DELAY;
+                    continue;
+                }
+            }
+        }
+
+        if let Some(ref mut connected) = sink {
+            if let Err(e) = send(connected, &message).await {
+                warn!("Failed to send to syslog at {}: {}", config.address, e);
+                sink = None;
+                retry_at = Instant::now() + RETRY_DELAY;
+            }
+        }
+    }
+}
@@ -0,0 +1,715 @@
+use super::*;
+use crate::config::{RetentionConfig, SyslogConfig, SyslogFacility, SyslogTransport};
+use chrono::{TimeZone, Utc};
+use tokio::io::AsyncReadExt;
+
+#[test]
+fn test_format_record() {
+    assert_eq!(
+        format_record(b"OK\r\n"),
+        "4 bytes | HEX: 4f 4b 0d 0a | ASCII: OK.."
+    );
+}
+
+#[test]
+fn test_format_syslog_message() {
+    let timestamp = Utc.with_ymd_and_hms(2025, 11, 30, 15, 30, 45).unwrap();
+    let message = syslog::format_message(
+        SyslogFacility::Local0.code(),
+        timestamp,
+        "gateway",
+        "plc line 1",
+        "RX",
+        "2 bytes | HEX: 4f 4b | ASCII: OK",
+    );
+
+    assert_eq!(
+        message,
+        format!(
+            "<134>1 2025-11-30T15:30:45.000000Z gateway plc_line_1 {} RX - 2 bytes | HEX: 4f 4b | ASCII: OK",
+            std::process::id()
+        )
+    );
+}
+
+#[tokio::test]
+async fn test_file_log_lines() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("nested/device.log");
+    let logger = SerialLogger::new(&path, "device").await.unwrap();
+
+    logger.log_received(b"hi").await.unwrap();
+    logger.log_sent(b"\x01").await.unwrap();
+
+    let contents = tokio::fs::read_to_string(&path).await.unwrap();
+    let lines: Vec<&str> = contents.lines().collect();
+    assert!(lines[0].ends_with("] device | RX | 2 bytes | HEX: 68 69 | ASCII: hi"));
+    assert!(lines[1].ends_with("] device | TX | 1 bytes | HEX: 01 | ASCII: ."));
+}
+
+#[tokio::test]
+async fn test_from_config_disabled() {
+    let config = LoggingConfig {
+        enabled: false,
+        path: "unused.log".into(),
+        raw: false,
+        max_file_bytes: None,
+        retention: None,
+        syslog: None,
+        encryption: None,
+        redact: None,
+        ship: None,
+    };
+    assert!(SerialLogger::from_config(&config, "device")
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_syslog_udp_forwarding() {
+    let collector = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let config = LoggingConfig {
+        enabled: false,
+        path: "unused.log".into(),
+        raw: false,
+        max_file_bytes: None,
+        retention: None,
+        syslog: Some(SyslogConfig {
+            address: collector.local_addr().unwrap().to_string(),
+            transport: SyslogTransport::Udp,
+            facility: SyslogFacility::User,
+        }),
+        encryption: None,
+        redact: None,
+        ship: None,
+    };
+
+    let logger = SerialLogger::from_config(&config, "sensor")
+        .await
+        .unwrap()
+        .unwrap();
+    logger.log_received(b"T=21").await.unwrap();
+
+    let mut buf = [0u8; 1024];
+    let n = collector.recv(&mut buf).await.unwrap();
+    let message = std::str::from_utf8(&buf[..n]).unwrap();
+    assert!(message.starts_with("<14>1 "));
+    assert!(message.contains(" sensor "));
+    assert!(message.ends_with(" RX - 4 bytes | HEX: 54 3d 32 31 | ASCII: T=21"));
+}
+
+#[tokio::test]
+async fn test_syslog_tcp_octet_counting() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = SyslogConfig {
+        address: listener.local_addr().unwrap().to_string(),
+        transport: SyslogTransport::Tcp,
+        facility: SyslogFacility::Local0,
+    };
+
+    let forwarder = SyslogForwarder::new(&config, "plc");
+    forwarder.forward("TX", "1 bytes | HEX: 0a | ASCII: .");
+
+    let (mut socket, _) = listener.accept().await.unwrap();
+    let mut received = Vec::new();
+    while !received.ends_with(b"ASCII: .") {
+        let mut buf = [0u8; 256];
+        let n = socket.read(&mut buf).await.unwrap();
+        assert!(n > 0);
+        received.extend_from_slice(&buf[..n]);
+    }
+
+    let text = String::from_utf8(received).unwrap();
+    let (length, message) = text.split_once(' ').unwrap();
+    assert_eq!(length.parse::<usize>().unwrap(), message.len());
+    assert!(message.starts_with("<134>1 "));
+}
+
+#[test]
+fn test_raw_capture_paths() {
+    let (rx, tx) = raw_capture_paths(Path::new("logs/plc.log"));
+    assert_eq!(rx, Path::new("logs/plc.bin"));
+    assert_eq!(tx, Path::new("logs/plc.tx.bin"));
+}
+
+#[tokio::test]
+async fn test_raw_capture_keeps_exact_bytes() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = LoggingConfig {
+        enabled: true,
+        path: dir.path().join("plc.log"),
+        raw: true,
+        max_file_bytes: None,
+        retention: None,
+        syslog: None,
+        encryption: None,
+        redact: None,
+        ship: None,
+    };
+
+    let logger = SerialLogger::from_config(&config, "plc")
+        .await
+        .unwrap()
+        .unwrap();
+    logger.log_received(&[0x00, 0xFF, b'\n']).await.unwrap();
+    logger.log_sent(&[0x10, 0x02]).await.unwrap();
+    logger.log_received(&[0x7E]).await.unwrap();
+
+    let (rx, tx) = raw_capture_paths(&config.path);
+    assert_eq!(
+        tokio::fs::read(rx).await.unwrap(),
+        vec![0x00, 0xFF, b'\n', 0x7E]
+    );
+    assert_eq!(tokio::fs::read(tx).await.unwrap(), vec![0x10, 0x02]);
+    assert!(!config.path.exists());
+}
+
+const TEST_KEY: &str = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+#[test]
+fn test_seal_and_open() {
+    let key = encryption::LogKey::from_hex(TEST_KEY).unwrap();
+    let mut file = encryption::MAGIC.to_vec();
+    file.extend(key.seal(b"login: admin\r\n"));
+    file.extend(key.seal(b"password: hunter2\r\n"));
+    assert_eq!(
+        key.open(&file).unwrap(),
+        b"login: admin\r\npassword: hunter2\r\n"
+    );
+
+    // A record cut short by a crash is dropped, the rest still readable
+    file.extend(&key.seal(b"lost")[..20]);
+    assert_eq!(
+        key.open(&file).unwrap(),
+        b"login: admin\r\npassword: hunter2\r\n"
+    );
+
+    // Records appended after it are out of step, so reading stops there
+    file.extend(key.seal(b"later"));
+    assert_eq!(
+        key.open(&file).unwrap(),
+        b"login: admin\r\npassword: hunter2\r\n"
+    );
+
+    let other = encryption::LogKey::from_hex(&"ff".repeat(32)).unwrap();
+    assert!(other.open(&file).is_err());
+    assert!(key.open(b"plain text\n").is_err());
+    assert!(encryption::LogKey::from_hex("abcd").is_err());
+}
+
+#[tokio::test]
+async fn test_encrypted_log_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let key_file = dir.path().join("log.key");
+    std::fs::write(&key_file, format!("{}\n", TEST_KEY)).unwrap();
+    let mut config = retained_config(dir.path(), None);
+    std::fs::write(&config.path, b"[earlier plain line]\n").unwrap();
+    config.encryption = Some(crate::config::LogEncryptionConfig {
+        key_file: key_file.clone(),
+    });
+
+    let logger = SerialLogger::from_config(&config, "plc")
+        .await
+        .unwrap()
+        .unwrap();
+    logger.log_sent(b"password: hunter2\r\n").await.unwrap();
+
+    // The plain file started before encryption was enabled is rotated away
+    assert_eq!(retention::files(&config).await.unwrap().len(), 2);
+    let file = tokio::fs::read(&config.path).await.unwrap();
+    assert!(encryption::is_encrypted(&file));
+    assert!(!String::from_utf8_lossy(&file).contains("hunter2"));
+    let plaintext = encryption::LogKey::load(&key_file)
+        .unwrap()
+        .open(&file)
+        .unwrap();
+    assert!(String::from_utf8(plaintext).unwrap().contains("hunter2"));
+
+    // A record cut short by a crash is cut off when the file is reopened
+    let key = encryption::LogKey::load(&key_file).unwrap();
+    let mut torn = file.clone();
+    torn.extend(&key.seal(b"lost")[..20]);
+    tokio::fs::write(&config.path, &torn).await.unwrap();
+    let logger = SerialLogger::from_config(&config, "plc")
+        .await
+        .unwrap()
+        .unwrap();
+    logger.log_sent(b"enable\r\n").await.unwrap();
+    let file = tokio::fs::read(&config.path).await.unwrap();
+    let plaintext = String::from_utf8(key.open(&file).unwrap()).unwrap();
+    assert!(plaintext.contains("hunter2"));
+    assert!(plaintext.contains("65 6e 61 62 6c 65"));
+
+    // Search reads encrypted and plain files alike
+    let connections = vec![("plc".to_string(), config)];
+    let query = search::SearchQuery {
+        pattern: regex::Regex::new("hunter2").unwrap(),
+        from: None,
+        to: None,
+        offset: 0,
+        limit: 10,
+    };
+    let page = search::search(&connections, &query).await.unwrap();
+    assert_eq!(page.records.len(), 1);
+    assert_eq!(page.records[0].direction, crate::serial::Direction::Tx);
+}
+
+#[test]
+fn test_redactor_masks_reply_to_prompt() {
+    let mut redactor = redact::Redactor::new(&crate::config::RedactConfig {
+        prompts: vec!["(?i)password:".to_string()],
+        patterns: vec![],
+    })
+    .unwrap();
+
+    assert_eq!(redactor.sent(b"admin\r"), b"admin\r");
+    // The prompt arrives split over two chunks
+    assert_eq!(redactor.received(b"\r\nPass"), b"\r\nPass");
+    assert_eq!(redactor.received(b"word: "), b"word: ");
+    // Typed a key at a time, the secret is masked once
+    assert_eq!(redactor.sent(b"h"), b"[REDACTED]");
+    assert_eq!(redactor.sent(b"unter"), b"");
+    assert_eq!(redactor.sent(b"2\rshow run\r"), b"\rshow run\r");
+    assert_eq!(redactor.sent(b"exit\r"), b"exit\r");
+
+    assert_eq!(redactor.received(b"Password:\r\n"), b"Password:\r\n");
+    assert_eq!(redactor.sent(b"exit\r"), b"exit\r");
+}
+
+#[test]
+fn test_redactor_patterns() {
+    let mut redactor = redact::Redactor::new(&crate::config::RedactConfig {
+        prompts: vec![],
+        patterns: vec![
+            r"community (\S+)".to_string(),
+            r"\b\d{4}-\d{4}-\d{4}-\d{4}\b".to_string(),
+        ],
+    })
+    .unwrap();
+
+    assert_eq!(
+        redactor.received(b"snmp-server community s3cret RO\r\n"),
+        b"snmp-server community [REDACTED] RO\r\n"
+    );
+    assert_eq!(
+        redactor.sent(b"card 1234-5678-9012-3456 and 1111-2222-3333-4444\r"),
+        b"card [REDACTED] and [REDACTED]\r"
+    );
+    assert_eq!(redactor.sent(b"show version\r"), b"show version\r");
+
+    assert!(redact::Redactor::new(&crate::config::RedactConfig {
+        prompts: vec!["(".to_string()],
+        patterns: vec![],
+    })
+    .is_err());
+}
+
+#[tokio::test]
+async fn test_redacted_log_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = retained_config(dir.path(), None);
+    config.redact = Some(crate::config::RedactConfig {
+        prompts: vec!["Password:".to_string()],
+        patterns: vec![],
+    });
+
+    let logger = SerialLogger::from_config(&config, "sw")
+        .await
+        .unwrap()
+        .unwrap();
+    logger.log_received(b"Password: ").await.unwrap();
+    for key in [b"h", b"u", b"n"] {
+        logger.log_sent(key).await.unwrap();
+    }
+    logger.log_sent(b"\r").await.unwrap();
+    logger.log_received(b"sw#").await.unwrap();
+
+    let content = tokio::fs::read_to_string(&config.path).await.unwrap();
+    let lines: Vec<&str> = content.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert!(lines[1]
+        .ends_with("| TX | 10 bytes | HEX: 5b 52 45 44 41 43 54 45 44 5d | ASCII: [REDACTED]"));
+    assert!(lines[2].contains("| TX | 1 bytes |"));
+}
+
+fn retained_config(dir: &Path, retention: Option<RetentionConfig>) -> LoggingConfig {
+    LoggingConfig {
+        enabled: true,
+        path: dir.join("plc.log"),
+        raw: false,
+        max_file_bytes: None,
+        retention,
+        syslog: None,
+        encryption: None,
+        redact: None,
+        ship: None,
+    }
+}
+
+#[test]
+fn test_rotated_names() {
+    let now = Utc.with_ymd_and_hms(2025, 11, 30, 15, 30, 45).unwrap();
+    let active = Path::new("logs/plc.log");
+
+    let rotated = retention::rotated_path(active, now, 0);
+    assert_eq!(rotated, Path::new("logs/plc.20251130-153045000000.log"));
+    assert_eq!(
+        retention::rotated_path(active, now, 2),
+        Path::new("logs/plc.20251130-153045000000-2.log")
+    );
+
+    assert!(retention::is_rotated(
+        active,
+        "plc.20251130-153045000000.log"
+    ));
+    assert!(retention::is_rotated(
+        active,
+        "plc.20251130-153045000000-2.log"
+    ));
+    assert!(!retention::is_rotated(active, "plc.log"));
+    assert!(!retention::is_rotated(
+        active,
+        "plc2.20251130-153045000000.log"
+    ));
+    assert!(!retention::is_rotated(
+        active,
+        "plc.20251130-153045000000.bin"
+    ));
+
+    // Raw RX and TX captures do not claim each other's rotations
+    let rx = Path::new("logs/plc.bin");
+    assert!(!retention::is_rotated(
+        rx,
+        "plc.tx.20251130-153045000000.bin"
+    ));
+    assert!(retention::is_rotated(
+        Path::new("logs/plc.tx.bin"),
+        "plc.tx.20251130-153045000000.bin"
+    ));
+}
+
+#[test]
+fn test_rotation_size_defaults_from_budget() {
+    let mut config = retained_config(Path::new("."), None);
+    assert_eq!(config.rotation_size(), None);
+
+    config.retention = Some(RetentionConfig {
+        max_bytes: Some(1000),
+        max_age_days: None,
+    });
+    assert_eq!(config.rotation_size(), Some(250));
+
+    config.max_file_bytes = Some(64);
+    assert_eq!(config.rotation_size(), Some(64));
+}
+
+#[tokio::test]
+async fn test_rotation_and_budget_sweep() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = retained_config(
+        dir.path(),
+        Some(RetentionConfig {
+            max_bytes: Some(300),
+            max_age_days: None,
+        }),
+    );
+    config.raw = true;
+    config.max_file_bytes = Some(100);
+
+    let logger = SerialLogger::from_config(&config, "plc")
+        .await
+        .unwrap()
+        .unwrap();
+    for _ in 0..10 {
+        logger.log_received(&[0xAA; 60]).await.unwrap();
+    }
+
+    // Every file but the active one holds a single 60-byte chunk
+    let before = retention::usage(&config).await.unwrap();
+    assert_eq!(before.bytes, 600);
+    assert_eq!(before.files, 11);
+
+    let removed = retention::sweep(&config, std::time::SystemTime::now())
+        .await
+        .unwrap();
+    assert_eq!(removed, 5);
+
+    let after = retention::usage(&config).await.unwrap();
+    assert_eq!(after.bytes, 300);
+    let (rx, tx) = raw_capture_paths(&config.path);
+    assert!(rx.exists());
+    assert!(tx.exists());
+}
+
+#[tokio::test]
+async fn test_age_sweep_keeps_active_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = retained_config(
+        dir.path(),
+        Some(RetentionConfig {
+            max_bytes: None,
+            max_age_days: Some(7),
+        }),
+    );
+
+    let old = retention::rotated_path(&config.path, Utc::now(), 0);
+    let recent = retention::rotated_path(&config.path, Utc::now(), 1);
+    for path in [&config.path, &old, &recent] {
+        std::fs::write(path, b"line\n").unwrap();
+    }
+    let ten_days_ago =
+        std::time::SystemTime::now() - std::time::Duration::from_secs(10 * 24 * 60 * 60);
+    for path in [&config.path, &old] {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(ten_days_ago)
+            .unwrap();
+    }
+
+    let removed = retention::sweep(&config, std::time::SystemTime::now())
+        .await
+        .unwrap();
+    assert_eq!(removed, 1);
+    assert!(!old.exists());
+    assert!(recent.exists());
+    assert!(config.path.exists());
+}
+
+#[test]
+fn test_ship_object_names() {
+    let date = chrono::NaiveDate::from_ymd_opt(2025, 11, 30).unwrap();
+    let file = Path::new("logs/plc.20251130-153045000000.log");
+    assert_eq!(
+        ship::object_name("site-a/{connection}/{date}/", "plc", date, file),
+        "site-a/plc/2025-11-30/plc.20251130-153045000000.log"
+    );
+    assert_eq!(
+        ship::object_name("", "plc", date, file),
+        "plc.20251130-153045000000.log"
+    );
+}
+
+#[tokio::test]
+async fn test_ship_uploads_and_removes_rotated_files() {
+    use axum::http::{HeaderMap, StatusCode, Uri};
+    use axum::{body::Bytes, extract::State, routing::put, Router};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    type Uploads = (
+        Arc<AtomicBool>,
+        tokio::sync::mpsc::UnboundedSender<(Uri, HeaderMap, Bytes)>,
+    );
+    let failing = Arc::new(AtomicBool::new(false));
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let app = Router::new()
+        .route(
+            "/*path",
+            put(
+                |State((failing, tx)): State<Uploads>,
+                 uri: Uri,
+                 headers: HeaderMap,
+                 body: Bytes| async move {
+                    if failing.load(Ordering::Relaxed) {
+                        return StatusCode::SERVICE_UNAVAILABLE;
+                    }
+                    tx.send((uri, headers, body)).unwrap();
+                    StatusCode::OK
+                },
+            ),
+        )
+        .with_state((failing.clone(), tx));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+
+    let dir = tempfile::tempdir().unwrap();
+    let secret_file = dir.path().join("secret");
+    std::fs::write(&secret_file, "secret\n").unwrap();
+    let mut config = retained_config(dir.path(), None);
+    config.ship = Some(crate::config::S3Config {
+        endpoint: format!("http://{}", addr),
+        bucket: "serial-logs".to_string(),
+        region: "us-east-1".to_string(),
+        prefix: "{connection}/".to_string(),
+        access_key_id: "AKID".to_string(),
+        secret_access_key_file: secret_file,
+    });
+    let rotated = retention::rotated_path(&config.path, Utc::now(), 0);
+    std::fs::write(&config.path, b"active\n").unwrap();
+    std::fs::write(&rotated, b"rotated\n").unwrap();
+
+    // Files that cannot be stored are kept for the next sweep
+    failing.store(true, Ordering::Relaxed);
+    assert!(ship::ship(&client, "plc", &config).await.is_err());
+    assert!(rotated.exists());
+
+    failing.store(false, Ordering::Relaxed);
+    assert_eq!(ship::ship(&client, "plc", &config).await.unwrap(), 1);
+    let (uri, headers, body) = rx.recv().await.unwrap();
+    assert_eq!(
+        uri.path(),
+        format!(
+            "/serial-logs/plc/{}",
+            rotated.file_name().unwrap().to_string_lossy()
+        )
+    );
+    assert_eq!(&body[..], b"rotated\n");
+    // Streamed from the file with its length, not chunked
+    assert_eq!(headers["content-length"], "8");
+    assert_eq!(
+        headers["x-amz-content-sha256"],
+        "dcc0366721e7f48bbab60fec60e1504898812604ceadd3d21852d64b8e3b6320"
+    );
+    assert!(!rotated.exists());
+    assert!(config.path.exists());
+}
+
+#[tokio::test]
+async fn test_search_pages_across_connections() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut plc = retained_config(dir.path(), None);
+    plc.max_file_bytes = Some(200);
+    let mut hmi = retained_config(dir.path(), None);
+    hmi.path = dir.path().join("hmi.log");
+
+    let plc_logger = SerialLogger::from_config(&plc, "plc")
+        .await
+        .unwrap()
+        .unwrap();
+    let hmi_logger = SerialLogger::from_config(&hmi, "hmi")
+        .await
+        .unwrap()
+        .unwrap();
+    for i in 0..4 {
+        plc_logger
+            .log_received(format!("ERR {}\r\n", i).as_bytes())
+            .await
+            .unwrap();
+        plc_logger.log_received(b"OK\r\n").await.unwrap();
+        hmi_logger.log_sent(b"poll ERR?").await.unwrap();
+        // Keep timestamps, which have millisecond resolution, distinct
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+    }
+    assert!(retention::usage(&plc).await.unwrap().files > 1);
+
+    let connections = vec![("plc".to_string(), plc), ("hmi".to_string(), hmi)];
+    let mut query = search::SearchQuery {
+        pattern: regex::Regex::new(r"ERR \d").unwrap(),
+        from: None,
+        to: None,
+        offset: 0,
+        limit: 3,
+    };
+    let page = search::search(&connections, &query).await.unwrap();
+    let texts: Vec<&str> = page.records.iter().map(|r| r.text.as_str()).collect();
+    assert_eq!(texts, vec!["ERR 0\r\n", "ERR 1\r\n", "ERR 2\r\n"]);
+    assert_eq!(page.records[0].connection, "plc");
+    assert_eq!(page.records[0].direction, crate::serial::Direction::Rx);
+    assert_eq!(page.records[0].hex, "45525220300d0a");
+    assert_eq!(page.next_offset, Some(3));
+
+    query.offset = 3;
+    let page = search::search(&connections, &query).await.unwrap();
+    assert_eq!(page.records.len(), 1);
+    assert_eq!(page.next_offset, None);
+
+    // Matches from both connections come back in time order
+    query.pattern = regex::Regex::new("ERR").unwrap();
+    query.offset = 0;
+    query.limit = search::MAX_LIMIT;
+    let page = search::search(&connections, &query).await.unwrap();
+    assert_eq!(page.records.len(), 8);
+    assert!(page
+        .records
+        .windows(2)
+        .all(|pair| pair[0].timestamp <= pair[1].timestamp));
+    assert!(page.records.iter().any(|r| r.connection == "hmi"));
+
+    query.from = Some(Utc::now());
+    let page = search::search(&connections, &query).await.unwrap();
+    assert!(page.records.is_empty());
+}
+
+#[test]
+fn test_audit_record_format() {
+    use audit::{AuditEvent, AuditSession};
+
+    let timestamp = Utc.with_ymd_and_hms(2025, 11, 30, 15, 30, 45).unwrap();
+    let trace = crate::trace::TraceContext {
+        trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+        span_id: "00f067aa0ba902b7".to_string(),
+    };
+    let session = AuditSession {
+        id: 7,
+        connection: "core-sw",
+        client: Some("alice"),
+        address: Some("203.0.113.9".parse().unwrap()),
+        trace: Some(&trace),
+    };
+    assert_eq!(
+        audit::format_record(timestamp, AuditEvent::Input, session, Some(b"reload\r")),
+        "{\"timestamp\":\"2025-11-30T15:30:45.000Z\",\"event\":\"input\",\"session\":7,\"connection\":\"core-sw\",\"client\":\"alice\",\"address\":\"203.0.113.9\",\"trace_id\":\"4bf92f3577b34da6a3ce929d0e0e4736\",\"span_id\":\"00f067aa0ba902b7\",\"hex\":\"72656c6f61640d\",\"text\":\"reload\\r\"}\n"
+    );
+    assert_eq!(
+        audit::format_record(
+            timestamp,
+            AuditEvent::Opened,
+            AuditSession {
+                client: None,
+                address: None,
+                trace: None,
+                ..session
+            },
+            None
+        ),
+        "{\"timestamp\":\"2025-11-30T15:30:45.000Z\",\"event\":\"opened\",\"session\":7,\"connection\":\"core-sw\",\"client\":null}\n"
+    );
+}
+
+#[tokio::test]
+async fn test_audit_log_keystrokes_are_opt_in() {
+    use crate::config::AuditConfig;
+    use audit::{AuditEvent, AuditSession};
+
+    let dir = tempfile::tempdir().unwrap();
+    let session = AuditSession {
+        id: 1,
+        connection: "plc",
+        client: None,
+        address: None,
+        trace: None,
+    };
+    for keystrokes in [false, true] {
+        let path = dir.path().join(format!("audit-{}.jsonl", keystrokes));
+        let audit = AuditLog::open(&AuditConfig {
+            path: path.clone(),
+            keystrokes,
+            max_file_bytes: None,
+        })
+        .await
+        .unwrap();
+        audit.record(AuditEvent::Opened, session).await;
+        audit.input(session, b"\x03").await;
+        audit.record(AuditEvent::Closed, session).await;
+
+        let text = tokio::fs::read_to_string(&path).await.unwrap();
+        let events: Vec<String> = text
+            .lines()
+            .map(|line| {
+                let record: serde_json::Value = serde_json::from_str(line).unwrap();
+                record["event"].as_str().unwrap().to_string()
+            })
+            .collect();
+        if keystrokes {
+            assert_eq!(events, ["opened", "input", "closed"]);
+            assert!(text.contains("\"hex\":\"03\""));
+        } else {
+            assert_eq!(events, ["opened", "closed"]);
+        }
+    }
+}
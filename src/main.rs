@@ -1,34 +1,205 @@
 use anyhow::Result;
-use tracing::{error, info};
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-use webmux::config::Config;
-use webmux::serial::SerialManager;
-use webmux::web;
+use clap::{Parser, Subcommand, ValueEnum};
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
+use webmux::config::{check, drift::ConfigFiles, generate, migrate, Config};
+use webmux::logging::{encryption::LogKey, live, AuditLog};
+use webmux::notifications::Notifier;
+use webmux::serial::{discovery, SerialManager};
+use webmux::web::lifecycle::{self, Lifecycle, StopKind};
+use webmux::web::{self, close::CloseReason, sessions::SessionRegistry};
+
+/// How long WebSocket sessions get to close cleanly on shutdown.
+const SESSION_CLOSE_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Parser, Debug)]
+#[command(name = "webmux")]
+#[command(about = "Web-based serial port multiplexer", long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Args {
+    /// Configuration file to serve
+    config: Option<String>,
+
+    /// Configuration file to serve; repeat to merge site-specific files
+    /// over a base, e.g. `-c base.yaml -c site.yaml`
+    #[arg(short = 'c', long = "config", value_name = "FILE")]
+    layers: Vec<String>,
+
+    /// Ignore unknown configuration keys instead of refusing to start
+    #[arg(long)]
+    lenient: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Check a configuration against this host without starting the server
+    Validate {
+        /// Repeat to check files merged as the server would merge them
+        #[arg(short, long, default_value = "config.yaml")]
+        config: Vec<String>,
+
+        #[arg(long, value_enum, default_value_t = Format::Text)]
+        format: Format,
+
+        /// Report unknown keys as warnings instead of errors
+        #[arg(long)]
+        lenient: bool,
+    },
+    /// Print a starter configuration for the serial ports on this host
+    GenerateConfig,
+    /// Rewrite a configuration in the current layout, keeping a .bak copy
+    MigrateConfig {
+        #[arg(short, long, default_value = "config.yaml")]
+        config: String,
+    },
+    /// Print the plaintext of a log file written with encryption enabled
+    DecryptLog {
+        /// File holding the key, as set in `logging.encryption.key_file`
+        #[arg(short, long)]
+        key_file: PathBuf,
+
+        file: PathBuf,
+
+        /// Write here instead of to stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Open a console on a running connection through the server's local
+    /// socket (`server.attach`), bypassing HTTP. Ctrl+] detaches.
+    Attach {
+        /// Port path of the connection, e.g. /dev/ttyUSB0, or its name
+        port: String,
+
+        #[arg(long, default_value_os_t = webmux::config::default_attach_socket())]
+        socket: PathBuf,
+
+        /// Label for the session in listings and the audit log; defaults
+        /// to `local:` and the user name
+        #[arg(long)]
+        client: Option<String>,
+    },
+    /// OpenAPI description of the HTTP and WebSocket API
+    Openapi {
+        #[command(subcommand)]
+        command: OpenapiCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum OpenapiCommand {
+    /// Print the document as JSON, e.g. for generating client SDKs
+    Dump,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum Format {
+    Text,
+    Json,
+}
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    // Initialize tracing
+    let args = Args::parse();
+    match args.command {
+        Some(Command::Validate {
+            config,
+            format,
+            lenient,
+        }) => return validate(&config, format, lenient),
+        Some(Command::GenerateConfig) => {
+            let ports = discovery::list_ports().await?;
+            print!("{}", generate::generate(&ports));
+            return Ok(());
+        }
+        Some(Command::MigrateConfig { config }) => {
+            match migrate::migrate_file(&config)? {
+                Some(from) => println!(
+                    "{}: migrated from version {} to {} (original saved as {}.bak)",
+                    config,
+                    from,
+                    migrate::CURRENT_VERSION,
+                    config
+                ),
+                None => println!(
+                    "{}: already at version {}",
+                    config,
+                    migrate::CURRENT_VERSION
+                ),
+            }
+            return Ok(());
+        }
+        Some(Command::DecryptLog {
+            key_file,
+            file,
+            output,
+        }) => {
+            let key = LogKey::load(&key_file)?;
+            let plaintext = key
+                .open(&std::fs::read(&file)?)
+                .map_err(|e| anyhow::anyhow!("{}: {}", file.display(), e))?;
+            match output {
+                Some(output) => std::fs::write(output, plaintext)?,
+                None => std::io::stdout().write_all(&plaintext)?,
+            }
+            return Ok(());
+        }
+        Some(Command::Attach {
+            port,
+            socket,
+            client,
+        }) => return attach(port, &socket, client).await,
+        Some(Command::Openapi {
+            command: OpenapiCommand::Dump,
+        }) => {
+            println!("{}", serde_json::to_string_pretty(&web::openapi::spec())?);
+            return Ok(());
+        }
+        None => {}
+    }
+
+    // Initialize tracing; the live stream filters on its own, per request
     tracing_subscriber::registry()
         .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "terminal_access_server=info,tower_http=debug".into()),
+            tracing_subscriber::fmt::layer().with_filter(
+                tracing_subscriber::EnvFilter::try_from_default_env()
+                    .unwrap_or_else(|_| "terminal_access_server=info,tower_http=debug".into()),
+            ),
         )
-        .with(tracing_subscriber::fmt::layer())
+        .with(live::layer().with_filter(LevelFilter::DEBUG))
         .init();
 
     info!("Starting Terminal Access Server");
 
     // Load configuration
-    let config_path = std::env::args()
-        .nth(1)
-        .unwrap_or_else(|| "config.yaml".to_string());
+    let mut config_paths: Vec<String> = args.config.into_iter().chain(args.layers).collect();
+    if config_paths.is_empty() {
+        config_paths.push("config.yaml".to_string());
+    }
 
-    info!("Loading configuration from: {}", config_path);
+    info!("Loading configuration from: {}", config_paths.join(", "));
 
-    let config = Config::from_file(&config_path).map_err(|e| {
+    let loaded = Config::load_layered(&config_paths, args.lenient).map_err(|e| {
         error!("Failed to load configuration: {}", e);
         e
     })?;
+    for warning in loaded.ignored_keys {
+        warn!("{}", warning);
+    }
+    if let Some(from) = loaded.migrated_from {
+        warn!(
+            "{} uses configuration version {}; run `webmux migrate-config -c {}` to update it",
+            config_paths[0], from, config_paths[0]
+        );
+    }
+    let config = loaded.config;
 
     config.validate().map_err(|e| {
         error!("Configuration validation failed: {}", e);
@@ -41,21 +212,82 @@ async fn main() -> Result<()> {
     );
 
     // Create serial manager
-    let serial_manager = SerialManager::new();
-
-    // Initialize serial connections
-    for conn_config in config.serial_connections {
-        match serial_manager.add_connection(conn_config.clone()).await {
-            Ok(_) => info!("Successfully initialized connection: {}", conn_config.name),
-            Err(e) => error!(
-                "Failed to initialize connection {}: {}",
-                conn_config.name, e
-            ),
-        }
+    let notifier = Notifier::new(&config.server.notifications)?;
+    let mut serial_manager =
+        SerialManager::with_notifier(notifier).with_config_files(ConfigFiles {
+            paths: config_paths.clone(),
+            lenient: args.lenient,
+        });
+    if let Some(ref quota) = config.server.quota {
+        serial_manager = serial_manager.with_quota(quota.clone());
+    }
+    serial_manager.spawn_log_sweeper();
+    serial_manager.spawn_drift_check();
+    if let Some(ref push) = config.server.metrics_push {
+        webmux::telemetry::push::spawn(&serial_manager, push)?;
+    }
+    if let Some(ref snmp) = config.server.snmp {
+        webmux::snmp::spawn(&serial_manager, snmp).await?;
+    }
+    if let Some(ref grpc) = config.server.grpc {
+        webmux::grpc::spawn(&serial_manager, grpc).await?;
     }
 
+    // Initialize serial connections in the background, so the API can
+    // report on those still waiting for a dependency or a startup delay
+    let starting = serial_manager.clone();
+    let connections = config.serial_connections;
+    let sniffers = config.server.sniffers.clone();
+    let startup = tokio::spawn(async move {
+        for (name, result) in starting.start_connections(connections).await {
+            match result {
+                Ok(_) => info!("Successfully initialized connection: {}", name),
+                Err(e) => error!("Failed to initialize connection {}: {}", name, e),
+            }
+        }
+        for sniffer in &sniffers {
+            if let Err(e) = starting.add_sniffer(sniffer).await {
+                error!("Failed to start sniffer {}: {}", sniffer.name, e);
+            }
+        }
+    });
+    webmux::serial::capture_jobs::spawn(&serial_manager, &config.server.capture_jobs)?;
+
     // Create web server
-    let app = web::create_router(serial_manager.clone());
+    let audit = match config.server.audit {
+        Some(ref audit) => AuditLog::open(audit).await?,
+        None => AuditLog::default(),
+    };
+    let sessions = SessionRegistry::with_audit(audit);
+    if let Some(ref attach) = config.server.attach {
+        #[cfg(unix)]
+        web::attach::spawn(&serial_manager, &sessions, attach).await?;
+        #[cfg(not(unix))]
+        warn!(
+            "server.attach ({}) needs Unix sockets and is ignored",
+            attach.socket.display()
+        );
+    }
+    let lifecycle = Lifecycle::default();
+    let app = web::create_router_with_lifecycle(
+        serial_manager.clone(),
+        &config.server,
+        sessions.clone(),
+        lifecycle.clone(),
+    );
+    if let Some(ref admin) = config.server.admin {
+        let admin_app = web::create_admin_router(
+            serial_manager.clone(),
+            &config.server,
+            sessions.clone(),
+            lifecycle.clone(),
+        );
+        web::admin::spawn(admin_app, admin).await?;
+    }
+    if let Some(ref agent) = config.server.hub_agent {
+        info!("Dialling hub {} as agent {}", agent.url, agent.name);
+        web::hub::spawn_agent(app.clone(), serial_manager.clone(), agent.clone());
+    }
 
     let bind_addr = format!("{}:{}", config.server.host, config.server.port);
     info!("Starting web server on {}", bind_addr);
@@ -65,6 +297,7 @@ async fn main() -> Result<()> {
     info!("Server is ready and listening on {}", bind_addr);
     info!("API endpoints:");
     info!("  GET  /health");
+    info!("  GET  /api/stats");
     info!("  GET  /api/connections");
     info!("  GET  /api/connections/:name");
     info!("  POST /api/connections/:name/send");
@@ -72,14 +305,141 @@ async fn main() -> Result<()> {
     info!("  WS   /api/connections/:name/ws");
 
     // Run server with graceful shutdown
-    axum::serve(listener, app)
-        .with_graceful_shutdown(shutdown_signal())
-        .await?;
+    let stop = lifecycle.clone();
+    let shutdown = async move {
+        tokio::select! {
+            _ = shutdown_signal() => {}
+            kind = stop.stopped() => info!("Received {:?} request", kind),
+        }
+        // Tell WebSocket clients to reconnect later, before ports close under them
+        sessions
+            .close_all(CloseReason::ServerShutdown, SESSION_CLOSE_GRACE)
+            .await;
+    };
+    match config.server.proxy {
+        Some(ref proxy) if proxy.protocol => {
+            info!("Expecting a PROXY protocol header on every connection");
+            web::proxy::serve_proxy_protocol(listener, app, proxy, shutdown).await?;
+        }
+        _ => {
+            axum::serve(
+                listener,
+                app.into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown)
+            .await?;
+        }
+    }
 
     info!("Shutting down serial connections...");
+    // Connections still waiting to open would otherwise open after the rest closed
+    startup.abort();
+    let _ = startup.await;
     serial_manager.shutdown().await;
 
     info!("Server shutdown complete");
+    if lifecycle.requested() == Some(StopKind::Restart) {
+        info!("Restarting");
+        lifecycle::restart()?;
+    }
+    Ok(())
+}
+
+/// Relays the terminal to a connection through the attach socket until
+/// Ctrl+] or the server ends the session.
+#[cfg(unix)]
+async fn attach(port: String, socket: &std::path::Path, client: Option<String>) -> Result<()> {
+    use crossterm::terminal::{disable_raw_mode, enable_raw_mode};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use web::attach::AttachRequest;
+
+    /// Ctrl+], as in telnet.
+    const DETACH: u8 = 0x1d;
+
+    let client = client.or_else(|| {
+        std::env::var("USER")
+            .ok()
+            .map(|user| format!("local:{}", user))
+    });
+    let (stream, connection) =
+        web::attach::connect(socket, &AttachRequest { port, client }).await?;
+    println!("Attached to {}. Press Ctrl+] to detach.", connection);
+
+    // Blocking stdin reads on a thread of their own, which is simply
+    // abandoned when the session ends
+    let (input_tx, mut input_rx) = tokio::sync::mpsc::channel::<Vec<u8>>(16);
+    std::thread::spawn(move || {
+        use std::io::Read;
+        let mut stdin = std::io::stdin();
+        let mut buf = [0u8; 1024];
+        while let Ok(n) = stdin.read(&mut buf) {
+            if n == 0 || input_tx.blocking_send(buf[..n].to_vec()).is_err() {
+                break;
+            }
+        }
+    });
+
+    let (mut read, mut write) = stream.into_split();
+    enable_raw_mode()?;
+    let result: Result<()> = async {
+        let mut stdout = tokio::io::stdout();
+        let mut buf = vec![0u8; 4096];
+        loop {
+            tokio::select! {
+                n = read.read(&mut buf) => {
+                    let n = n?;
+                    if n == 0 {
+                        return Ok(());
+                    }
+                    stdout.write_all(&buf[..n]).await?;
+                    stdout.flush().await?;
+                }
+                input = input_rx.recv() => {
+                    let Some(input) = input else {
+                        return Ok(());
+                    };
+                    match input.iter().position(|&b| b == DETACH) {
+                        Some(end) => {
+                            write.write_all(&input[..end]).await?;
+                            return Ok(());
+                        }
+                        None => write.write_all(&input).await?,
+                    }
+                }
+            }
+        }
+    }
+    .await;
+    disable_raw_mode()?;
+    println!("\r\nDetached from {}.", connection);
+    result
+}
+
+#[cfg(not(unix))]
+async fn attach(_port: String, _socket: &std::path::Path, _client: Option<String>) -> Result<()> {
+    anyhow::bail!("webmux attach needs Unix sockets")
+}
+
+/// Prints the deep-check report and exits non-zero if it found errors.
+fn validate(config_paths: &[String], format: Format, lenient: bool) -> Result<()> {
+    let report = check::check_file(config_paths, lenient);
+    match format {
+        Format::Json => println!("{}", serde_json::to_string_pretty(&report)?),
+        Format::Text => {
+            for finding in &report.findings {
+                println!("{}", finding);
+            }
+            println!(
+                "{}: {} error(s), {} warning(s)",
+                config_paths.join(" + "),
+                report.errors(),
+                report.warnings()
+            );
+        }
+    }
+    if !report.valid {
+        std::process::exit(1);
+    }
     Ok(())
 }
 
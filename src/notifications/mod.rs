@@ -0,0 +1,538 @@
+//! Delivery of incidents (alerts, lost ports) to Slack, Discord, generic
+//! webhooks and email.
+//!
+//! Background deliveries are retried with exponential backoff, and the
+//! most recent ones are kept for the delivery-status endpoint.
+
+use crate::config::{NotificationChannelConfig, NotificationKind, SmtpConfig, SmtpSecurity};
+use crate::trace::{TraceContext, TRACEPARENT_HEADER};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::warn;
+
+pub mod silence;
+
+/// Attempts made at each background delivery.
+pub const MAX_ATTEMPTS: u32 = 5;
+
+/// Wait before the first retry; each later retry waits twice as long.
+const FIRST_RETRY: Duration = Duration::from_secs(2);
+
+/// Deliveries kept for the delivery-status endpoint.
+pub const MAX_DELIVERIES: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub severity: Severity,
+    pub title: String,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+    pub timestamp: DateTime<Utc>,
+    /// The connection or session the incident happened in, for finding
+    /// its log lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+}
+
+impl Notification {
+    pub fn new(severity: Severity, title: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            title: title.into(),
+            message: message.into(),
+            connection: None,
+            timestamp: Utc::now(),
+            trace: None,
+        }
+    }
+
+    pub fn for_connection(mut self, name: &str) -> Self {
+        self.connection = Some(name.to_string());
+        self
+    }
+
+    pub fn with_trace(mut self, trace: &TraceContext) -> Self {
+        self.trace = Some(trace.clone());
+        self
+    }
+}
+
+/// Body of a Slack incoming-webhook message.
+pub fn slack_payload(notification: &Notification) -> Value {
+    json!({ "text": format!("*{}*\n{}", notification.title, notification.message) })
+}
+
+/// Body of a Discord webhook message.
+pub fn discord_payload(notification: &Notification) -> Value {
+    json!({ "content": format!("**{}**\n{}", notification.title, notification.message) })
+}
+
+enum Channel {
+    Slack {
+        url: String,
+    },
+    Discord {
+        url: String,
+    },
+    Webhook {
+        url: String,
+        headers: HashMap<String, String>,
+        secret: Option<String>,
+    },
+    Smtp {
+        transport: AsyncSmtpTransport<Tokio1Executor>,
+        from: Mailbox,
+        to: Vec<Mailbox>,
+    },
+}
+
+fn smtp_channel(config: &SmtpConfig) -> Result<Channel> {
+    let builder = match config.security {
+        SmtpSecurity::Starttls => {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&config.host)?
+        }
+        SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(&config.host)?,
+        SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&config.host),
+    };
+    let builder = match (&config.username, &config.password) {
+        (Some(username), Some(password)) => {
+            builder.credentials(Credentials::new(username.clone(), password.clone()))
+        }
+        (None, None) => builder,
+        _ => anyhow::bail!("SMTP username and password must be given together"),
+    };
+
+    if config.to.is_empty() {
+        anyhow::bail!("SMTP channel needs at least one recipient");
+    }
+    let to = config
+        .to
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|e| anyhow::anyhow!("Invalid recipient {:?}: {}", address, e))
+        })
+        .collect::<Result<_>>()?;
+    let from = config
+        .from
+        .parse()
+        .map_err(|e| anyhow::anyhow!("Invalid sender {:?}: {}", config.from, e))?;
+
+    Ok(Channel::Smtp {
+        transport: builder.port(config.port).build(),
+        from,
+        to,
+    })
+}
+
+/// Builds the email sent for `notification`.
+pub fn email_message(
+    from: &Mailbox,
+    to: &[Mailbox],
+    notification: &Notification,
+) -> Result<Message> {
+    let mut builder = Message::builder()
+        .from(from.clone())
+        .subject(format!("[webmux] {}", notification.title));
+    for recipient in to {
+        builder = builder.to(recipient.clone());
+    }
+    Ok(builder.body(format!(
+        "{}\n\nSeverity: {:?}\nTime: {}\n",
+        notification.message,
+        notification.severity,
+        notification.timestamp.to_rfc3339()
+    ))?)
+}
+
+/// One POST of a JSON `body` to a webhook, with its signature, delivery id
+/// and trace headers.
+async fn post_webhook(
+    client: &reqwest::Client,
+    url: &str,
+    headers: &HashMap<String, String>,
+    secret: Option<&str>,
+    body: Vec<u8>,
+    delivery: Option<u64>,
+    trace: Option<&TraceContext>,
+) -> Result<()> {
+    let mut request = client
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    if let Some(secret) = secret {
+        request = request.header("X-Webmux-Signature", signature(secret, &body));
+    }
+    if let Some(delivery) = delivery {
+        request = request.header("X-Webmux-Delivery", delivery.to_string());
+    }
+    if let Some(trace) = trace {
+        request = request.header(TRACEPARENT_HEADER, trace.traceparent());
+    }
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    request.body(body).send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// `sha256=<hex>`, the `X-Webmux-Signature` of a webhook body.
+pub fn signature(secret: &str, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+    mac.update(body);
+    format!("sha256={}", hex::encode(mac.finalize().into_bytes()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeliveryState {
+    /// Not delivered yet; more attempts will be made.
+    Pending,
+    Delivered,
+    /// Given up on after the last attempt or a permanent error.
+    Failed,
+}
+
+/// One notification's delivery to one channel.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub channel: String,
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+    pub state: DeliveryState,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Client errors other than timeouts and rate limiting will not go away
+/// on their own.
+fn is_permanent(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<reqwest::Error>()
+        .and_then(reqwest::Error::status)
+        .is_some_and(|status| {
+            status.is_client_error()
+                && status != reqwest::StatusCode::REQUEST_TIMEOUT
+                && status != reqwest::StatusCode::TOO_MANY_REQUESTS
+        })
+}
+
+struct Inner {
+    channels: HashMap<String, Channel>,
+    client: reqwest::Client,
+    deliveries: Mutex<VecDeque<Delivery>>,
+    next_delivery: AtomicU64,
+    silences: silence::Silences,
+}
+
+impl Inner {
+    fn new(channels: HashMap<String, Channel>) -> Self {
+        Self {
+            channels,
+            client: reqwest::Client::new(),
+            deliveries: Mutex::new(VecDeque::new()),
+            next_delivery: AtomicU64::new(1),
+            silences: silence::Silences::default(),
+        }
+    }
+}
+
+/// Routes notifications to the channels configured in `server.notifications`.
+#[derive(Clone)]
+pub struct Notifier {
+    inner: Arc<Inner>,
+    first_retry: Duration,
+}
+
+impl Default for Notifier {
+    fn default() -> Self {
+        Self {
+            inner: Arc::new(Inner::new(HashMap::new())),
+            first_retry: FIRST_RETRY,
+        }
+    }
+}
+
+impl Notifier {
+    pub fn new(configs: &[NotificationChannelConfig]) -> Result<Self> {
+        let mut channels = HashMap::new();
+        for config in configs {
+            let channel = match &config.kind {
+                NotificationKind::Slack { webhook_url } => Channel::Slack {
+                    url: webhook_url.clone(),
+                },
+                NotificationKind::Discord { webhook_url } => Channel::Discord {
+                    url: webhook_url.clone(),
+                },
+                NotificationKind::Webhook {
+                    url,
+                    headers,
+                    secret,
+                } => Channel::Webhook {
+                    url: url.clone(),
+                    headers: headers.clone(),
+                    secret: secret.clone(),
+                },
+                NotificationKind::Smtp(smtp) => smtp_channel(smtp)
+                    .map_err(|e| anyhow::anyhow!("Notification channel {}: {}", config.name, e))?,
+            };
+            if channels.insert(config.name.clone(), channel).is_some() {
+                anyhow::bail!("Duplicate notification channel: {}", config.name);
+            }
+        }
+
+        Ok(Self {
+            inner: Arc::new(Inner::new(channels)),
+            first_retry: FIRST_RETRY,
+        })
+    }
+
+    /// Waits `delay` before the first retry instead of two seconds.
+    pub fn with_first_retry(mut self, delay: Duration) -> Self {
+        self.first_retry = delay;
+        self
+    }
+
+    /// Shared HTTP client, for callers posting their own payloads.
+    pub fn client(&self) -> &reqwest::Client {
+        &self.inner.client
+    }
+
+    /// Alerts muted through the API.
+    pub fn silences(&self) -> &silence::Silences {
+        &self.inner.silences
+    }
+
+    /// Delivers `notification` to one channel, once.
+    pub async fn send(&self, channel: &str, notification: &Notification) -> Result<()> {
+        self.attempt(channel, notification, None).await
+    }
+
+    /// One attempt at a delivery; `delivery` is sent to webhooks so
+    /// receivers can spot retries of a delivery they already have.
+    async fn attempt(
+        &self,
+        channel: &str,
+        notification: &Notification,
+        delivery: Option<u64>,
+    ) -> Result<()> {
+        let channel = self
+            .inner
+            .channels
+            .get(channel)
+            .ok_or_else(|| anyhow::anyhow!("Unknown notification channel: {}", channel))?;
+        let client = &self.inner.client;
+
+        match channel {
+            Channel::Slack { url } => {
+                client
+                    .post(url)
+                    .json(&slack_payload(notification))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Channel::Discord { url } => {
+                client
+                    .post(url)
+                    .json(&discord_payload(notification))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            Channel::Webhook {
+                url,
+                headers,
+                secret,
+            } => {
+                let body = serde_json::to_vec(notification)?;
+                post_webhook(
+                    client,
+                    url,
+                    headers,
+                    secret.as_deref(),
+                    body,
+                    delivery,
+                    notification.trace.as_ref(),
+                )
+                .await?;
+            }
+            Channel::Smtp {
+                transport,
+                from,
+                to,
+            } => {
+                transport
+                    .send(email_message(from, to, notification)?)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Delivers `notification` to each named channel in the background,
+    /// retrying failures and logging the ones given up on.
+    pub fn notify(&self, channels: &[String], notification: Notification) {
+        for channel in channels {
+            let id = self.record(channel, &notification);
+            let notifier = self.clone();
+            let channel = channel.clone();
+            let notification = notification.clone();
+            tokio::spawn(async move {
+                // Configuration does not change, so an unknown channel
+                // is not worth retrying
+                let known = notifier.inner.channels.contains_key(&channel);
+                notifier
+                    .deliver(id, &channel, known, || {
+                        notifier.attempt(&channel, &notification, Some(id))
+                    })
+                    .await
+            });
+        }
+    }
+
+    /// POSTs `payload` as JSON to `url` in the background, signed with
+    /// `secret` and retried like a webhook channel's deliveries. The
+    /// delivery is listed under the URL and described by `notification`.
+    pub fn notify_url(
+        &self,
+        url: &str,
+        secret: Option<String>,
+        payload: &impl Serialize,
+        notification: &Notification,
+    ) {
+        // Signed bytes must be exactly the bytes sent
+        let body = match serde_json::to_vec(payload) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Could not encode webhook payload for {}: {}", url, e);
+                return;
+            }
+        };
+        let id = self.record(url, notification);
+        let notifier = self.clone();
+        let url = url.to_string();
+        let trace = notification.trace.clone();
+        let headers = HashMap::new();
+        tokio::spawn(async move {
+            notifier
+                .deliver(id, &url, true, || {
+                    post_webhook(
+                        notifier.client(),
+                        &url,
+                        &headers,
+                        secret.as_deref(),
+                        body.clone(),
+                        Some(id),
+                        trace.as_ref(),
+                    )
+                })
+                .await
+        });
+    }
+
+    /// Makes up to [`MAX_ATTEMPTS`] attempts at delivery `id`, only the
+    /// first unless `retry`, keeping its record up to date.
+    async fn deliver<F, Fut>(&self, id: u64, channel: &str, retry: bool, send: F)
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let mut delay = self.first_retry;
+        for attempt in 1..=MAX_ATTEMPTS {
+            let error = match send().await {
+                Ok(()) => {
+                    self.update(id, attempt, DeliveryState::Delivered, None);
+                    return;
+                }
+                Err(e) => e,
+            };
+            let last = attempt == MAX_ATTEMPTS || is_permanent(&error) || !retry;
+            let state = if last {
+                DeliveryState::Failed
+            } else {
+                DeliveryState::Pending
+            };
+            self.update(id, attempt, state, Some(error.to_string()));
+            if last {
+                warn!(
+                    "Notification to {} failed after {} attempt(s): {}",
+                    channel, attempt, error
+                );
+                return;
+            }
+            tokio::time::sleep(delay).await;
+            delay *= 2;
+        }
+    }
+
+    /// Adds a pending delivery, forgetting the oldest past [`MAX_DELIVERIES`].
+    fn record(&self, channel: &str, notification: &Notification) -> u64 {
+        let id = self.inner.next_delivery.fetch_add(1, Ordering::Relaxed);
+        let now = Utc::now();
+        let mut deliveries = self.inner.deliveries.lock().unwrap();
+        if deliveries.len() == MAX_DELIVERIES {
+            deliveries.pop_front();
+        }
+        deliveries.push_back(Delivery {
+            id,
+            channel: channel.to_string(),
+            title: notification.title.clone(),
+            connection: notification.connection.clone(),
+            state: DeliveryState::Pending,
+            attempts: 0,
+            last_error: None,
+            created_at: now,
+            updated_at: now,
+        });
+        id
+    }
+
+    fn update(&self, id: u64, attempts: u32, state: DeliveryState, error: Option<String>) {
+        let mut deliveries = self.inner.deliveries.lock().unwrap();
+        if let Some(delivery) = deliveries.iter_mut().find(|delivery| delivery.id == id) {
+            delivery.attempts = attempts;
+            delivery.state = state;
+            if error.is_some() {
+                delivery.last_error = error;
+            }
+            delivery.updated_at = Utc::now();
+        }
+    }
+
+    /// Recent background deliveries, newest first.
+    pub fn deliveries(&self) -> Vec<Delivery> {
+        let deliveries = self.inner.deliveries.lock().unwrap();
+        deliveries.iter().rev().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests;
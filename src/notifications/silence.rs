@@ -0,0 +1,115 @@
+//! Temporary muting of noisy alerts through the API, so on-call engineers
+//! need not edit the configuration to quiet a known problem.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Longest a silence may last.
+pub const MAX_SILENCE: Duration = Duration::from_secs(7 * 24 * 3600);
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SilenceRequest {
+    /// Only alerts of this connection; all connections if unset.
+    #[serde(default)]
+    pub connection: Option<String>,
+    /// Regex searched for in alert names.
+    pub pattern: String,
+    /// Seconds until the silence ends.
+    pub duration: u64,
+    #[serde(default)]
+    pub comment: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Silence {
+    pub id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+    pub pattern: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct Inner {
+    silences: Vec<(Silence, Regex)>,
+    next_id: u64,
+}
+
+/// Active silences, dropped once they expire.
+#[derive(Clone, Default)]
+pub struct Silences {
+    inner: Arc<Mutex<Inner>>,
+}
+
+impl Silences {
+    pub fn add(&self, request: SilenceRequest, now: DateTime<Utc>) -> Result<Silence> {
+        let duration = Duration::from_secs(request.duration);
+        if duration.is_zero() || duration > MAX_SILENCE {
+            anyhow::bail!(
+                "duration must be between 1 and {} seconds",
+                MAX_SILENCE.as_secs()
+            );
+        }
+        let regex =
+            Regex::new(&request.pattern).map_err(|e| anyhow::anyhow!("invalid pattern: {}", e))?;
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_id += 1;
+        let silence = Silence {
+            id: inner.next_id,
+            connection: request.connection,
+            pattern: request.pattern,
+            comment: request.comment,
+            created_at: now,
+            expires_at: now + chrono::Duration::from_std(duration)?,
+        };
+        inner.silences.push((silence.clone(), regex));
+        Ok(silence)
+    }
+
+    /// Silences still in effect at `now`, oldest first.
+    pub fn list(&self, now: DateTime<Utc>) -> Vec<Silence> {
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .silences
+            .retain(|(silence, _)| silence.expires_at > now);
+        inner
+            .silences
+            .iter()
+            .map(|(silence, _)| silence.clone())
+            .collect()
+    }
+
+    /// Ends a silence early; false if there is none with `id`.
+    pub fn remove(&self, id: u64) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let before = inner.silences.len();
+        inner.silences.retain(|(silence, _)| silence.id != id);
+        inner.silences.len() != before
+    }
+
+    /// The silence muting `alert` on `connection` at `now`, if any.
+    pub fn matching(&self, connection: &str, alert: &str, now: DateTime<Utc>) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .silences
+            .iter()
+            .find(|(silence, regex)| {
+                silence.expires_at > now
+                    && silence
+                        .connection
+                        .as_deref()
+                        .is_none_or(|name| name == connection)
+                    && regex.is_match(alert)
+            })
+            .map(|(silence, _)| silence.id)
+    }
+}
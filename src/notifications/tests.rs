@@ -0,0 +1,308 @@
+use super::*;
+use axum::{
+    body::Bytes,
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    routing::post,
+    Router,
+};
+use tokio::sync::mpsc;
+
+fn channel(name: &str, kind: NotificationKind) -> NotificationChannelConfig {
+    NotificationChannelConfig {
+        name: name.to_string(),
+        kind,
+    }
+}
+
+fn smtp(from: &str, to: &[&str]) -> NotificationKind {
+    NotificationKind::Smtp(SmtpConfig {
+        host: "smtp.example.com".to_string(),
+        port: 587,
+        username: None,
+        password: None,
+        security: SmtpSecurity::Starttls,
+        from: from.to_string(),
+        to: to.iter().map(|s| s.to_string()).collect(),
+    })
+}
+
+#[test]
+fn test_chat_payloads() {
+    let notification = Notification::new(Severity::Critical, "Port lost", "EOF");
+    assert_eq!(slack_payload(&notification)["text"], "*Port lost*\nEOF");
+    assert_eq!(
+        discord_payload(&notification)["content"],
+        "**Port lost**\nEOF"
+    );
+}
+
+#[test]
+fn test_email_message() {
+    let from: Mailbox = "webmux <webmux@example.com>".parse().unwrap();
+    let to: Vec<Mailbox> = vec!["ops@example.com".parse().unwrap()];
+    let notification =
+        Notification::new(Severity::Warning, "Alert", "temp = 90").for_connection("sensor");
+
+    let message = String::from_utf8(
+        email_message(&from, &to, &notification)
+            .unwrap()
+            .formatted(),
+    )
+    .unwrap();
+    assert!(message.contains("Subject: [webmux] Alert"));
+    assert!(message.contains("To: ops@example.com"));
+    assert!(message.contains("temp = 90"));
+}
+
+#[test]
+fn test_notifier_rejects_bad_channels() {
+    let slack = NotificationKind::Slack {
+        webhook_url: "https://hooks.slack.com/services/x".to_string(),
+    };
+    assert!(
+        Notifier::new(&[channel("ops", slack.clone()), channel("ops", slack.clone())]).is_err()
+    );
+
+    assert!(Notifier::new(&[channel(
+        "mail",
+        smtp("webmux@example.com", &["ops@example.com"])
+    )])
+    .is_ok());
+    assert!(Notifier::new(&[channel(
+        "mail",
+        smtp("not an address", &["ops@example.com"])
+    )])
+    .is_err());
+    assert!(Notifier::new(&[channel("mail", smtp("webmux@example.com", &[]))]).is_err());
+}
+
+#[tokio::test]
+async fn test_unknown_channel() {
+    let notifier = Notifier::default();
+    let notification = Notification::new(Severity::Info, "x", "y");
+    assert!(notifier.send("missing", &notification).await.is_err());
+}
+
+#[tokio::test]
+async fn test_webhook_delivery() {
+    let (tx, mut rx) = mpsc::channel(1);
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(
+                |State(tx): State<mpsc::Sender<(HeaderMap, Bytes)>>,
+                 headers: HeaderMap,
+                 body: Bytes| async move {
+                    tx.send((headers, body)).await.unwrap();
+                },
+            ),
+        )
+        .with_state(tx);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let notifier = Notifier::new(&[channel(
+        "hook",
+        NotificationKind::Webhook {
+            url: format!("http://{}/hook", addr),
+            headers: HashMap::from([("X-Token".to_string(), "secret".to_string())]),
+            secret: Some("s3cret".to_string()),
+        },
+    )])
+    .unwrap();
+
+    let trace = crate::trace::TraceContext::root();
+    let notification = Notification::new(Severity::Critical, "Connection plc lost", "EOF")
+        .for_connection("plc")
+        .with_trace(&trace);
+    notifier.send("hook", &notification).await.unwrap();
+
+    let (headers, raw) = rx.recv().await.unwrap();
+    assert_eq!(headers["x-token"], "secret");
+    // Receivers verify the signature over the raw body
+    assert_eq!(headers["x-webmux-signature"], signature("s3cret", &raw));
+    assert!(headers.get("x-webmux-delivery").is_none());
+    let body: Value = serde_json::from_slice(&raw).unwrap();
+    assert_eq!(body["severity"], "critical");
+    assert_eq!(body["title"], "Connection plc lost");
+    assert_eq!(body["connection"], "plc");
+    // Correlates with the connection's log lines and tracing backends
+    assert_eq!(body["trace"]["trace_id"], trace.trace_id);
+    assert_eq!(body["trace"]["span_id"], trace.span_id);
+    assert_eq!(
+        headers["traceparent"],
+        format!("00-{}-{}-01", trace.trace_id, trace.span_id)
+    );
+}
+
+#[tokio::test]
+async fn test_background_delivery_retries_and_records_status() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Fails twice, then accepts; /gone always rejects
+    let calls = Arc::new(AtomicUsize::new(0));
+    let app = Router::new()
+        .route(
+            "/flaky",
+            post(|State(calls): State<Arc<AtomicUsize>>| async move {
+                if calls.fetch_add(1, Ordering::SeqCst) < 2 {
+                    StatusCode::SERVICE_UNAVAILABLE
+                } else {
+                    StatusCode::OK
+                }
+            }),
+        )
+        .route("/gone", post(|| async { StatusCode::GONE }))
+        .with_state(calls.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let webhook = |path: &str| NotificationKind::Webhook {
+        url: format!("http://{}{}", addr, path),
+        headers: HashMap::new(),
+        secret: None,
+    };
+    let notifier = Notifier::new(&[
+        channel("flaky", webhook("/flaky")),
+        channel("gone", webhook("/gone")),
+    ])
+    .unwrap()
+    .with_first_retry(Duration::from_millis(10));
+
+    let notification = Notification::new(Severity::Warning, "Alert", "temp = 90");
+    notifier.notify(&["flaky".to_string(), "gone".to_string()], notification);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while notifier
+        .deliveries()
+        .iter()
+        .any(|delivery| delivery.state == DeliveryState::Pending)
+    {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let deliveries = notifier.deliveries();
+    assert_eq!(deliveries.len(), 2);
+    let gone = &deliveries[0];
+    assert_eq!(gone.channel, "gone");
+    assert_eq!(gone.state, DeliveryState::Failed);
+    // A client error is not retried
+    assert_eq!(gone.attempts, 1);
+    assert!(gone.last_error.as_ref().unwrap().contains("410"));
+    let flaky = &deliveries[1];
+    assert_eq!(flaky.state, DeliveryState::Delivered);
+    assert_eq!(flaky.attempts, 3);
+    assert_eq!(calls.load(Ordering::SeqCst), 3);
+}
+
+#[test]
+fn test_silences_match_and_expire() {
+    use super::silence::{SilenceRequest, Silences};
+
+    let silences = Silences::default();
+    let now = Utc::now();
+    let request = |connection: Option<&str>, pattern: &str, duration: u64| SilenceRequest {
+        connection: connection.map(str::to_string),
+        pattern: pattern.to_string(),
+        duration,
+        comment: None,
+    };
+    assert!(silences.add(request(None, "(", 60), now).is_err());
+    assert!(silences.add(request(None, "temp", 0), now).is_err());
+    assert!(silences
+        .add(request(None, "temp", 8 * 24 * 3600), now)
+        .is_err());
+
+    let plc = silences
+        .add(request(Some("plc"), "^temp_", 60), now)
+        .unwrap();
+    let everywhere = silences.add(request(None, "fan", 3600), now).unwrap();
+    assert_eq!(silences.matching("plc", "temp_high", now), Some(plc.id));
+    assert_eq!(silences.matching("ups", "temp_high", now), None);
+    assert_eq!(
+        silences.matching("ups", "fan_stall", now),
+        Some(everywhere.id)
+    );
+
+    let later = now + chrono::Duration::seconds(61);
+    assert_eq!(silences.matching("plc", "temp_high", later), None);
+    assert_eq!(silences.list(later), vec![everywhere.clone()]);
+    assert!(silences.remove(everywhere.id));
+    assert!(!silences.remove(everywhere.id));
+    assert!(silences.list(now).is_empty());
+}
+
+type Received = mpsc::Sender<(HeaderMap, Bytes)>;
+
+#[tokio::test]
+async fn test_url_delivery_is_signed_retried_and_recorded() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // Fails once, then hands the request over
+    let calls = Arc::new(AtomicUsize::new(0));
+    let (tx, mut rx) = mpsc::channel(1);
+    let app = Router::new()
+        .route(
+            "/alert",
+            post(
+                |State((calls, tx)): State<(Arc<AtomicUsize>, Received)>,
+                 headers: HeaderMap,
+                 body: Bytes| async move {
+                    if calls.fetch_add(1, Ordering::SeqCst) == 0 {
+                        return StatusCode::SERVICE_UNAVAILABLE;
+                    }
+                    tx.send((headers, body)).await.unwrap();
+                    StatusCode::OK
+                },
+            ),
+        )
+        .with_state((calls, tx));
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+    let notifier = Notifier::default().with_first_retry(Duration::from_millis(10));
+    let url = format!("http://{}/alert", addr);
+    let notification = Notification::new(Severity::Warning, "Alert overheat firing", "t = 82");
+    notifier.notify_url(
+        &url,
+        Some("s3cret".to_string()),
+        &json!({ "alert": "overheat", "state": "firing" }),
+        &notification,
+    );
+
+    let (headers, raw) = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    // The caller's payload, not the notification, is what gets signed
+    let body: Value = serde_json::from_slice(&raw).unwrap();
+    assert_eq!(body["alert"], "overheat");
+    assert_eq!(headers["x-webmux-signature"], signature("s3cret", &raw));
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let delivery = loop {
+        let delivery = notifier.deliveries().remove(0);
+        if delivery.state != DeliveryState::Pending {
+            break delivery;
+        }
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(delivery.channel, url);
+    assert_eq!(delivery.state, DeliveryState::Delivered);
+    assert_eq!(delivery.attempts, 2);
+    assert_eq!(headers["x-webmux-delivery"], delivery.id.to_string());
+}
+
+#[test]
+fn test_signature_is_hmac_sha256() {
+    // RFC 4231 test case 2
+    assert_eq!(
+        signature("Jefe", b"what do ya want for nothing?"),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
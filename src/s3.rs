@@ -0,0 +1,208 @@
+//! Storing objects in S3-compatible buckets such as AWS, MinIO or Ceph,
+//! for capture jobs and log shipping. Buckets are addressed path-style,
+//! `<endpoint>/<bucket>/<prefix><name>`, and each PUT is signed with
+//! Signature Version 4.
+
+use crate::config::S3Config;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// Bytes read from a file at a time, when hashing and when uploading it.
+const FILE_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Checks that a bucket can be addressed path-style.
+pub fn validate(s3: &S3Config) -> Result<()> {
+    let url = reqwest::Url::parse(&s3.endpoint)
+        .map_err(|e| anyhow::anyhow!("invalid endpoint {}: {}", s3.endpoint, e))?;
+    if url.host_str().is_none() || url.path() != "/" {
+        anyhow::bail!(
+            "endpoint {} must be a bare URL such as https://s3.example.com",
+            s3.endpoint
+        );
+    }
+    if s3.bucket.is_empty() || s3.bucket.contains('/') {
+        anyhow::bail!("bucket must be non-empty and must not contain '/'");
+    }
+    Ok(())
+}
+
+/// Percent-encodes all but the characters RFC 3986 leaves unreserved,
+/// and `/`, as S3 expects in object paths.
+pub fn uri_encode(path: &str) -> String {
+    let mut encoded = String::with_capacity(path.len());
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// The secret in the file at `path`, read afresh so it can be rotated.
+pub async fn read_secret(path: &Path) -> Result<String> {
+    let secret = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|e| anyhow::anyhow!("Cannot read {}: {}", path.display(), e))?;
+    Ok(secret.trim().to_string())
+}
+
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any size");
+    mac.update(message);
+    mac.finalize().into_bytes().into()
+}
+
+/// The AWS Signature Version 4 key for one day, region and service.
+pub fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> [u8; 32] {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+/// Headers signing a PUT to `path` on `host`, of a body whose SHA-256 is
+/// `payload_hash` in hex, with Signature Version 4: `x-amz-date`,
+/// `x-amz-content-sha256` and `Authorization`.
+pub fn sign_put(
+    host: &str,
+    path: &str,
+    payload_hash: &str,
+    region: &str,
+    access_key_id: &str,
+    secret_access_key: &str,
+    now: DateTime<Utc>,
+) -> [(&'static str, String); 3] {
+    let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = now.format("%Y%m%d").to_string();
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!(
+        "PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        path, host, payload_hash, amz_date, signed_headers, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex::encode(Sha256::digest(canonical_request.as_bytes()))
+    );
+    let key = signing_key(secret_access_key, &date, region, "s3");
+    let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+    [
+        ("x-amz-date", amz_date),
+        ("x-amz-content-sha256", payload_hash.to_string()),
+        (
+            "Authorization",
+            format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                access_key_id, scope, signed_headers, signature
+            ),
+        ),
+    ]
+}
+
+/// Stores `body` as `<prefix><name>` in the bucket, returning its URL.
+pub async fn put(
+    client: &reqwest::Client,
+    s3: &S3Config,
+    name: &str,
+    body: Vec<u8>,
+    content_type: &str,
+) -> Result<String> {
+    let payload_hash = hex::encode(Sha256::digest(&body));
+    let length = body.len() as u64;
+    put_signed(
+        client,
+        s3,
+        name,
+        &payload_hash,
+        length,
+        body.into(),
+        content_type,
+    )
+    .await
+}
+
+/// Stores the file at `path` as `<prefix><name>` in the bucket, returning
+/// its URL. The file is read twice, to hash and then to send it, and never
+/// held in memory whole.
+pub async fn put_file(
+    client: &reqwest::Client,
+    s3: &S3Config,
+    name: &str,
+    path: &Path,
+    content_type: &str,
+) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; FILE_CHUNK_BYTES];
+    let mut length = 0u64;
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        length += n as u64;
+    }
+    let payload_hash = hex::encode(hasher.finalize());
+
+    // Sends exactly the bytes hashed, should the file have grown since
+    let file = tokio::fs::File::open(path).await?.take(length);
+    let chunks = futures::stream::try_unfold(file, |mut file| async move {
+        let mut chunk = vec![0u8; FILE_CHUNK_BYTES];
+        let n = file.read(&mut chunk).await?;
+        chunk.truncate(n);
+        Ok::<_, std::io::Error>((n > 0).then_some((chunk, file)))
+    });
+    let body = reqwest::Body::wrap_stream(chunks);
+    put_signed(client, s3, name, &payload_hash, length, body, content_type).await
+}
+
+async fn put_signed(
+    client: &reqwest::Client,
+    s3: &S3Config,
+    name: &str,
+    payload_hash: &str,
+    length: u64,
+    body: reqwest::Body,
+    content_type: &str,
+) -> Result<String> {
+    let endpoint = reqwest::Url::parse(&s3.endpoint)?;
+    let host = match (endpoint.host_str(), endpoint.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => anyhow::bail!("endpoint {} has no host", s3.endpoint),
+    };
+    let secret_access_key = read_secret(&s3.secret_access_key_file).await?;
+    let path = uri_encode(&format!("/{}/{}{}", s3.bucket, s3.prefix, name));
+    let location = format!("{}{}", s3.endpoint.trim_end_matches('/'), path);
+    let mut request = client.put(&location);
+    for (header, value) in sign_put(
+        &host,
+        &path,
+        payload_hash,
+        &s3.region,
+        &s3.access_key_id,
+        &secret_access_key,
+        Utc::now(),
+    ) {
+        request = request.header(header, value);
+    }
+    request
+        .header("Content-Type", content_type)
+        // S3 does not take chunked uploads
+        .header("Content-Length", length)
+        .body(body)
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(location)
+}
@@ -0,0 +1,126 @@
+//! Notes pinned to a point in a connection's received data, such as
+//! "started firmware update here", for whoever reviews a long debug
+//! session later.
+
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Annotations kept per connection; the oldest are dropped first.
+pub const MAX_ANNOTATIONS: usize = 1000;
+
+/// Longest annotation text, in characters.
+pub const MAX_TEXT_CHARS: usize = 1024;
+
+/// Longest author, in characters.
+pub const MAX_AUTHOR_CHARS: usize = 128;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationSeverity {
+    #[default]
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnnotationRequest {
+    pub text: String,
+    #[serde(default)]
+    pub severity: AnnotationSeverity,
+    #[serde(default)]
+    pub author: Option<String>,
+    /// Received chunk the note follows; it must still be kept.
+    #[serde(default)]
+    pub seq: Option<u64>,
+    /// When the noted event happened, instead of `seq`.
+    #[serde(default)]
+    pub timestamp: Option<DateTime<Utc>>,
+}
+
+impl AnnotationRequest {
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<()> {
+        let text = self.text.trim();
+        if text.is_empty() || text.chars().count() > MAX_TEXT_CHARS {
+            anyhow::bail!("text must be 1 to {} characters", MAX_TEXT_CHARS);
+        }
+        if let Some(ref author) = self.author {
+            if author.trim().is_empty() || author.chars().count() > MAX_AUTHOR_CHARS {
+                anyhow::bail!("author must be 1 to {} characters", MAX_AUTHOR_CHARS);
+            }
+        }
+        if self.seq.is_some() && self.timestamp.is_some() {
+            anyhow::bail!("Give at most one of seq and timestamp");
+        }
+        if self.seq == Some(0) {
+            anyhow::bail!("seq starts at 1");
+        }
+        if self.timestamp.is_some_and(|timestamp| timestamp > now) {
+            anyhow::bail!("timestamp is in the future");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Annotation {
+    pub id: u64,
+    /// The last chunk received at or before the noted point, 0 if none.
+    pub seq: u64,
+    pub timestamp: DateTime<Utc>,
+    pub severity: AnnotationSeverity,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub author: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// The last [`MAX_ANNOTATIONS`] annotations of one connection.
+#[derive(Debug, Default)]
+pub struct Annotations {
+    next_id: u64,
+    list: VecDeque<Annotation>,
+}
+
+impl Annotations {
+    /// Keeps `request`, already validated, pinned at `seq` and `timestamp`.
+    pub fn add(
+        &mut self,
+        request: AnnotationRequest,
+        seq: u64,
+        timestamp: DateTime<Utc>,
+        now: DateTime<Utc>,
+    ) -> Annotation {
+        self.next_id += 1;
+        let annotation = Annotation {
+            id: self.next_id,
+            seq,
+            timestamp,
+            severity: request.severity,
+            text: request.text.trim().to_string(),
+            author: request.author.map(|author| author.trim().to_string()),
+            created_at: now,
+        };
+        if self.list.len() == MAX_ANNOTATIONS {
+            self.list.pop_front();
+        }
+        self.list.push_back(annotation.clone());
+        annotation
+    }
+
+    /// Annotations with `seq` in `first..=last`, in the order of the data
+    /// they follow.
+    pub fn between(&self, first: u64, last: u64) -> Vec<Annotation> {
+        let mut found: Vec<Annotation> = self
+            .list
+            .iter()
+            .filter(|annotation| (first..=last).contains(&annotation.seq))
+            .cloned()
+            .collect();
+        found.sort_by_key(|annotation| (annotation.seq, annotation.id));
+        found
+    }
+}
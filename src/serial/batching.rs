@@ -0,0 +1,187 @@
+//! Merging queued writes for `tx_batch`.
+
+use super::SerialData;
+use crate::config::TxBatchConfig;
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TryRecvError};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// Told whether a queued message made it to the port, with the error if not.
+pub type WriteAck = oneshot::Sender<Result<(), String>>;
+
+/// A message waiting in a write queue.
+#[derive(Debug)]
+pub struct Outgoing {
+    pub data: SerialData,
+    /// Completed once the whole message has been written to the port.
+    pub ack: Option<WriteAck>,
+}
+
+impl Outgoing {
+    /// A message whose sender is told when it has been written.
+    pub fn acked(data: SerialData) -> (Self, oneshot::Receiver<Result<(), String>>) {
+        let (ack, written) = oneshot::channel();
+        let message = Self {
+            data,
+            ack: Some(ack),
+        };
+        (message, written)
+    }
+}
+
+impl From<SerialData> for Outgoing {
+    fn from(data: SerialData) -> Self {
+        Self { data, ack: None }
+    }
+}
+
+/// Queued messages merged into one write.
+#[derive(Debug, Default)]
+pub struct Batch {
+    pub data: SerialData,
+    /// Acknowledgements, each with the offset its message ends at.
+    acks: Vec<(usize, WriteAck)>,
+}
+
+impl Batch {
+    fn push(&mut self, message: Outgoing) {
+        self.data.extend_from_slice(&message.data);
+        if let Some(ack) = message.ack {
+            self.acks.push((self.data.len(), ack));
+        }
+    }
+
+    /// Acknowledges the messages that lie within the first `written` bytes.
+    pub fn written(&mut self, written: usize) {
+        let done = self
+            .acks
+            .iter()
+            .take_while(|(end, _)| *end <= written)
+            .count();
+        for (_, ack) in self.acks.drain(..done) {
+            let _ = ack.send(Ok(()));
+        }
+    }
+
+    /// Tells the messages not written yet that they never will be.
+    pub fn failed(self, error: &str) {
+        for (_, ack) in self.acks {
+            let _ = ack.send(Err(error.to_string()));
+        }
+    }
+}
+
+impl From<SerialData> for Batch {
+    fn from(data: SerialData) -> Self {
+        Self {
+            data,
+            acks: Vec::new(),
+        }
+    }
+}
+
+/// The receiving end of a connection's write queue.
+///
+/// Never waits itself: a batch still open for more writes is kept here
+/// until its [`deadline`](Self::deadline), which the I/O task races with
+/// its other events.
+pub struct TxQueue {
+    rx: mpsc::Receiver<Outgoing>,
+    /// A message that did not fit in the previous batch.
+    held: Option<Outgoing>,
+    /// A batch waiting up to `max_delay_ms` for more messages.
+    filling: Option<(Batch, Instant)>,
+    config: TxBatchConfig,
+}
+
+impl TxQueue {
+    pub fn new(rx: mpsc::Receiver<Outgoing>, config: TxBatchConfig) -> Self {
+        Self {
+            rx,
+            held: None,
+            filling: None,
+            config,
+        }
+    }
+
+    /// The next queued message. Cancel-safe, so it can race other events.
+    pub async fn recv(&mut self) -> Option<Outgoing> {
+        match self.held.take() {
+            Some(held) => Some(held),
+            None => self.rx.recv().await,
+        }
+    }
+
+    /// The next queued message, if one is waiting.
+    pub fn try_recv(&mut self) -> Option<Outgoing> {
+        self.held.take().or_else(|| self.rx.try_recv().ok())
+    }
+
+    /// Whether no message is waiting, received or not.
+    pub fn is_empty(&self) -> bool {
+        self.held.is_none() && self.rx.is_empty()
+    }
+
+    /// Puts back a message taken with [`TxQueue::recv`], to be received
+    /// again first. Only for an empty queue.
+    pub fn unrecv(&mut self, message: Outgoing) {
+        debug_assert!(self.held.is_none());
+        self.held = Some(message);
+    }
+
+    /// When the batch being filled must go out, if there is one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.filling.as_ref().map(|(_, deadline)| *deadline)
+    }
+
+    /// Adds a received message to the batch being filled, starting one if
+    /// needed, along with any others already waiting. Returns the batch
+    /// once it is ready: it would exceed `max_bytes` (the message that
+    /// does not fit is kept for the next one), or there is no
+    /// `max_delay_ms` to wait for more.
+    pub fn push(&mut self, message: Outgoing) -> Option<Batch> {
+        let (mut batch, deadline) = match self.filling.take() {
+            Some(filling) => filling,
+            None => {
+                let mut batch = Batch::default();
+                batch.push(message);
+                let deadline = Instant::now() + Duration::from_millis(self.config.max_delay_ms);
+                return self.gather(batch, deadline);
+            }
+        };
+        if batch.data.len() + message.data.len() > self.config.max_bytes {
+            self.held = Some(message);
+            return Some(batch);
+        }
+        batch.push(message);
+        self.gather(batch, deadline)
+    }
+
+    /// The batch being filled, once its deadline has passed.
+    pub fn flush(&mut self) -> Option<Batch> {
+        self.filling.take().map(|(batch, _)| batch)
+    }
+
+    fn gather(&mut self, mut batch: Batch, deadline: Instant) -> Option<Batch> {
+        while batch.data.len() < self.config.max_bytes {
+            let next = match self.rx.try_recv() {
+                Ok(next) => next,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => break,
+            };
+            if batch.data.len() + next.data.len() > self.config.max_bytes {
+                self.held = Some(next);
+                return Some(batch);
+            }
+            batch.push(next);
+        }
+        if batch.data.len() >= self.config.max_bytes
+            || self.config.max_delay_ms == 0
+            || deadline <= Instant::now()
+        {
+            return Some(batch);
+        }
+        self.filling = Some((batch, deadline));
+        None
+    }
+}
@@ -0,0 +1,164 @@
+//! Opening many similar connections at once from a template, e.g. one for
+//! every port of a 32-port adapter.
+
+use super::discovery::PortDescription;
+use super::ephemeral::ConnectionRequest;
+use crate::config::{DataBits, FlowControl, Parity, StopBits};
+use anyhow::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Most connections one bulk request may open.
+pub const MAX_BULK_CONNECTIONS: usize = 256;
+
+/// A [`ConnectionRequest`] without the port. `{index}` (counting from 1)
+/// and `{port}` (the port's file name) in `name` and `description` are
+/// replaced for each port.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionTemplate {
+    pub name: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub baud_rate: Option<u32>,
+    #[serde(default)]
+    pub data_bits: Option<DataBits>,
+    #[serde(default)]
+    pub stop_bits: Option<StopBits>,
+    #[serde(default)]
+    pub parity: Option<Parity>,
+    #[serde(default)]
+    pub flow_control: Option<FlowControl>,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub banner: Option<String>,
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+impl ConnectionTemplate {
+    fn request(&self, index: usize, port: &str) -> ConnectionRequest {
+        let file_name = port.rsplit(['/', '\\']).next().unwrap_or(port);
+        let fill = |text: &str| {
+            text.replace("{index}", &index.to_string())
+                .replace("{port}", file_name)
+        };
+        ConnectionRequest {
+            name: fill(&self.name),
+            port: port.to_string(),
+            profile: self.profile.clone(),
+            baud_rate: self.baud_rate,
+            data_bits: self.data_bits,
+            stop_bits: self.stop_bits,
+            parity: self.parity,
+            flow_control: self.flow_control,
+            description: fill(&self.description),
+            banner: self.banner.clone(),
+            expires_in: self.expires_in,
+        }
+    }
+}
+
+/// A template and the ports to open it on, listed or as a glob.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BulkConnectionRequest {
+    pub template: ConnectionTemplate,
+    #[serde(default)]
+    pub ports: Vec<String>,
+    /// Matched against the paths and `/dev/serial/by-id` links of the
+    /// ports present, e.g. `/dev/ttyUSB*`.
+    #[serde(default)]
+    pub glob: Option<String>,
+}
+
+impl BulkConnectionRequest {
+    /// One request per port, in the order given or, for a glob, in port
+    /// number order. `present` is only used for a glob.
+    pub fn expand(&self, present: &[PortDescription]) -> Result<Vec<ConnectionRequest>> {
+        let ports = match (&self.glob, self.ports.is_empty()) {
+            (Some(_), false) => anyhow::bail!("Give either ports or glob, not both"),
+            (None, true) => anyhow::bail!("Give ports or a glob"),
+            (Some(glob), true) => {
+                let ports = matching_ports(glob, present)?;
+                if ports.is_empty() {
+                    anyhow::bail!("No ports match {}", glob);
+                }
+                ports
+            }
+            (None, false) => self.ports.clone(),
+        };
+        if ports.len() > MAX_BULK_CONNECTIONS {
+            anyhow::bail!(
+                "{} ports given, at most {} can be opened at once",
+                ports.len(),
+                MAX_BULK_CONNECTIONS
+            );
+        }
+        if ports.len() > 1
+            && !self.template.name.contains("{index}")
+            && !self.template.name.contains("{port}")
+        {
+            anyhow::bail!("The name must contain {{index}} or {{port}} to tell the ports apart");
+        }
+
+        let requests: Vec<ConnectionRequest> = ports
+            .iter()
+            .enumerate()
+            .map(|(i, port)| self.template.request(i + 1, port))
+            .collect();
+        let mut names = HashSet::new();
+        for request in &requests {
+            if request.name.is_empty() {
+                anyhow::bail!("Connection name must not be empty");
+            }
+            if !names.insert(&request.name) {
+                anyhow::bail!("The template names two connections {}", request.name);
+            }
+        }
+        Ok(requests)
+    }
+}
+
+/// The ports in `present` whose path or by-id link matches `glob`, as
+/// whichever of the two matched. `*` and `?` do not match `/`.
+pub fn matching_ports(glob: &str, present: &[PortDescription]) -> Result<Vec<String>> {
+    let mut pattern = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    let regex = Regex::new(&pattern)?;
+
+    let mut ports: Vec<String> = present
+        .iter()
+        .filter_map(|port| {
+            if regex.is_match(&port.path) {
+                Some(port.path.clone())
+            } else {
+                port.by_id.clone().filter(|link| regex.is_match(link))
+            }
+        })
+        .collect();
+    // ttyUSB2 before ttyUSB10
+    ports.sort_by_key(|path| {
+        let digits = path.len() - path.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+        let (stem, number) = path.split_at(path.len() - digits);
+        (stem.to_string(), number.parse::<u64>().ok())
+    });
+    Ok(ports)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkFailure {
+    pub name: String,
+    pub port: String,
+    pub error: String,
+}
@@ -0,0 +1,298 @@
+//! Capture sessions: several connections recorded into one bundle on a
+//! shared monotonic clock, so exchanges between devices can be interleaved.
+
+use super::annotations::Annotation;
+use super::clock::ClockInfo;
+use super::{Direction, SerialConnection, Traffic};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tracing::{info, warn};
+
+/// Longest a capture may run before it stops on its own.
+pub const MAX_CAPTURE_DURATION: Duration = Duration::from_secs(60 * 60);
+
+/// Largest payload a single capture may hold in memory.
+pub const MAX_CAPTURE_BYTES: usize = 64 * 1024 * 1024;
+
+/// Captures kept (running or finished) before new ones are refused.
+pub const MAX_CAPTURES: usize = 16;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CaptureRequest {
+    pub connections: Vec<String>,
+    #[serde(default = "default_duration_secs")]
+    pub duration_secs: u64,
+    /// Stop once this many payload bytes have been recorded.
+    #[serde(default = "default_max_bytes")]
+    pub max_bytes: usize,
+}
+
+fn default_duration_secs() -> u64 {
+    300
+}
+
+fn default_max_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+impl CaptureRequest {
+    pub fn validate(&self) -> Result<()> {
+        if self.connections.is_empty() {
+            anyhow::bail!("connections must name at least one connection");
+        }
+        let mut seen = HashSet::new();
+        if let Some(name) = self.connections.iter().find(|name| !seen.insert(*name)) {
+            anyhow::bail!("Connection {} is listed more than once", name);
+        }
+        if self.duration_secs == 0 || self.duration_secs > MAX_CAPTURE_DURATION.as_secs() {
+            anyhow::bail!(
+                "duration_secs must be between 1 and {}",
+                MAX_CAPTURE_DURATION.as_secs()
+            );
+        }
+        if self.max_bytes == 0 || self.max_bytes > MAX_CAPTURE_BYTES {
+            anyhow::bail!("max_bytes must be between 1 and {}", MAX_CAPTURE_BYTES);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CaptureState {
+    Running,
+    /// Ended by request.
+    Stopped,
+    /// Ran for its full `duration_secs`.
+    Completed,
+    /// Hit `max_bytes`.
+    Full,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureInfo {
+    pub id: u64,
+    pub connections: Vec<String>,
+    pub state: CaptureState,
+    pub started_at: DateTime<Utc>,
+    pub stopped_at: Option<DateTime<Utc>>,
+    pub records: usize,
+    pub bytes: usize,
+    /// Chunks missed because the capture fell behind a connection.
+    pub dropped_chunks: u64,
+    pub clock: ClockInfo,
+}
+
+pub(super) fn serialize_hex<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(data))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureRecord {
+    /// Microseconds since the capture started, on the shared clock.
+    pub offset_us: u64,
+    pub connection: String,
+    pub direction: Direction,
+    #[serde(serialize_with = "serialize_hex")]
+    pub data: Vec<u8>,
+}
+
+/// An annotation made on a connection while it was being captured.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureAnnotation {
+    /// Microseconds from the capture's start to the annotation's
+    /// timestamp, by the wall clock.
+    pub offset_us: u64,
+    pub connection: String,
+    #[serde(flatten)]
+    pub annotation: Annotation,
+}
+
+/// A capture with its records in time order.
+#[derive(Debug, Clone, Serialize)]
+pub struct CaptureBundle {
+    #[serde(flatten)]
+    pub info: CaptureInfo,
+    pub records: Vec<CaptureRecord>,
+    pub annotations: Vec<CaptureAnnotation>,
+}
+
+struct Recording {
+    info: CaptureInfo,
+    records: Vec<CaptureRecord>,
+    annotations: Vec<CaptureAnnotation>,
+}
+
+pub(crate) struct Capture {
+    recording: Arc<Mutex<Recording>>,
+    stop_tx: Option<oneshot::Sender<()>>,
+}
+
+impl Capture {
+    /// Taps every connection and records until stopped, `duration_secs`
+    /// pass or `max_bytes` is reached.
+    pub(crate) fn start(
+        id: u64,
+        request: &CaptureRequest,
+        connections: &[SerialConnection],
+    ) -> Self {
+        // Tap before taking the start time so nothing after it is missed
+        let taps: Vec<(String, broadcast::Receiver<Traffic>)> = connections
+            .iter()
+            .map(|connection| (connection.config().name.clone(), connection.tap()))
+            .collect();
+        let start = Instant::now();
+        let started_at = Utc::now();
+
+        let recording = Arc::new(Mutex::new(Recording {
+            info: CaptureInfo {
+                id,
+                connections: request.connections.clone(),
+                state: CaptureState::Running,
+                started_at,
+                stopped_at: None,
+                records: 0,
+                bytes: 0,
+                dropped_chunks: 0,
+                clock: ClockInfo::new(start, started_at),
+            },
+            records: Vec::new(),
+            annotations: Vec::new(),
+        }));
+
+        let (events_tx, events_rx) = mpsc::channel(1024);
+        for (name, tap) in taps {
+            tokio::spawn(forward(name, tap, events_tx.clone(), recording.clone()));
+        }
+
+        let (stop_tx, stop_rx) = oneshot::channel();
+        tokio::spawn(record(
+            recording.clone(),
+            events_rx,
+            stop_rx,
+            start,
+            Duration::from_secs(request.duration_secs),
+            request.max_bytes,
+        ));
+
+        Self {
+            recording,
+            stop_tx: Some(stop_tx),
+        }
+    }
+
+    pub(crate) fn info(&self) -> CaptureInfo {
+        self.recording.lock().unwrap().info.clone()
+    }
+
+    pub(crate) fn bundle(&self) -> CaptureBundle {
+        let recording = self.recording.lock().unwrap();
+        let mut records = recording.records.clone();
+        // Connections are forwarded independently, so merge by timestamp
+        records.sort_by_key(|record| record.offset_us);
+        let mut annotations = recording.annotations.clone();
+        annotations.sort_by_key(|annotation| annotation.offset_us);
+        CaptureBundle {
+            info: recording.info.clone(),
+            records,
+            annotations,
+        }
+    }
+
+    /// Keeps an annotation on `connection` if this capture is recording
+    /// it and the annotation falls after the capture started.
+    pub(crate) fn annotate(&self, connection: &str, annotation: &Annotation) {
+        let mut recording = self.recording.lock().unwrap();
+        let info = &recording.info;
+        if info.state != CaptureState::Running
+            || !info.connections.iter().any(|name| name == connection)
+        {
+            return;
+        }
+        let Some(offset_us) = (annotation.timestamp - info.started_at)
+            .num_microseconds()
+            .and_then(|offset| u64::try_from(offset).ok())
+        else {
+            return;
+        };
+        recording.annotations.push(CaptureAnnotation {
+            offset_us,
+            connection: connection.to_string(),
+            annotation: annotation.clone(),
+        });
+    }
+
+    pub(crate) fn stop(&mut self) {
+        if let Some(stop_tx) = self.stop_tx.take() {
+            let _ = stop_tx.send(());
+        }
+    }
+}
+
+/// Moves one connection's traffic into the capture until either side goes away.
+async fn forward(
+    name: String,
+    mut tap: broadcast::Receiver<Traffic>,
+    events_tx: mpsc::Sender<(String, Traffic)>,
+    recording: Arc<Mutex<Recording>>,
+) {
+    loop {
+        match tap.recv().await {
+            Ok(traffic) => {
+                if events_tx.send((name.clone(), traffic)).await.is_err() {
+                    break;
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                warn!("Capture fell behind {}, missed {} chunks", name, missed);
+                recording.lock().unwrap().info.dropped_chunks += missed;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        }
+    }
+}
+
+async fn record(
+    recording: Arc<Mutex<Recording>>,
+    mut events_rx: mpsc::Receiver<(String, Traffic)>,
+    mut stop_rx: oneshot::Receiver<()>,
+    start: Instant,
+    duration: Duration,
+    max_bytes: usize,
+) {
+    let deadline = tokio::time::Instant::from_std(start + duration);
+
+    let state = loop {
+        tokio::select! {
+            Some((connection, traffic)) = events_rx.recv() => {
+                let mut recording = recording.lock().unwrap();
+                if recording.info.bytes + traffic.data.len() > max_bytes {
+                    break CaptureState::Full;
+                }
+                recording.info.records += 1;
+                recording.info.bytes += traffic.data.len();
+                recording.records.push(CaptureRecord {
+                    offset_us: traffic.at.saturating_duration_since(start).as_micros() as u64,
+                    connection,
+                    direction: traffic.direction,
+                    data: traffic.data,
+                });
+            }
+            _ = &mut stop_rx => break CaptureState::Stopped,
+            _ = tokio::time::sleep_until(deadline) => break CaptureState::Completed,
+        }
+    };
+
+    let mut recording = recording.lock().unwrap();
+    recording.info.state = state;
+    recording.info.stopped_at = Some(Utc::now());
+    info!(
+        "Capture {} finished ({:?}) with {} records",
+        recording.info.id, state, recording.info.records
+    );
+}
@@ -0,0 +1,181 @@
+//! Capture jobs (`server.capture_jobs`): a capture of some connections
+//! taken at the same time every day and uploaded to an HTTP endpoint or
+//! an S3-compatible bucket, for long-term archival of device behavior.
+
+use super::capture::{CaptureRequest, CaptureState, MAX_CAPTURE_BYTES};
+use super::SerialManager;
+use crate::config::{CaptureJobConfig, UploadTarget};
+use crate::notifications::{Notification, Severity};
+use crate::s3;
+use anyhow::Result;
+use chrono::{DateTime, Duration as ChronoDuration, NaiveTime, Utc};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Longest a job may capture for.
+pub const MAX_DURATION_MINUTES: u64 = 60;
+
+/// Uploads tried per run before it is given up.
+const UPLOAD_ATTEMPTS: u32 = 3;
+
+const UPLOAD_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Checks a job's definition; its connections are checked by the caller.
+pub fn validate(job: &CaptureJobConfig) -> Result<()> {
+    if job.name.is_empty() || job.name.contains('/') {
+        anyhow::bail!("name must be non-empty and must not contain '/'");
+    }
+    parse_at(&job.at)?;
+    if job.duration_minutes == 0 || job.duration_minutes > MAX_DURATION_MINUTES {
+        anyhow::bail!(
+            "duration_minutes must be between 1 and {}",
+            MAX_DURATION_MINUTES
+        );
+    }
+    request(job).validate()?;
+    match job.upload {
+        UploadTarget::Http { ref url, .. } => {
+            reqwest::Url::parse(url).map_err(|e| anyhow::anyhow!("invalid url {}: {}", url, e))?;
+        }
+        UploadTarget::S3(ref bucket) => s3::validate(bucket)?,
+    }
+    Ok(())
+}
+
+fn parse_at(at: &str) -> Result<NaiveTime> {
+    NaiveTime::parse_from_str(at, "%H:%M")
+        .map_err(|_| anyhow::anyhow!("at must be a time of day as HH:MM, not {}", at))
+}
+
+fn request(job: &CaptureJobConfig) -> CaptureRequest {
+    CaptureRequest {
+        connections: job.connections.clone(),
+        duration_secs: job.duration_minutes * 60,
+        max_bytes: MAX_CAPTURE_BYTES,
+    }
+}
+
+/// The first time after `now` that the clock reads `at`.
+pub fn next_run(at: NaiveTime, now: DateTime<Utc>) -> DateTime<Utc> {
+    let today = now.date_naive().and_time(at).and_utc();
+    if today > now {
+        today
+    } else {
+        today + ChronoDuration::days(1)
+    }
+}
+
+/// Where a run's bundle goes, relative to the target: `<job>/<start>.json`.
+pub fn object_name(job: &str, started_at: DateTime<Utc>) -> String {
+    format!("{}/{}.json", job, started_at.format("%Y-%m-%dT%H-%M-%SZ"))
+}
+
+/// Uploads `body` as `name` to `target`, returning where it went.
+pub async fn upload(
+    client: &reqwest::Client,
+    target: &UploadTarget,
+    name: &str,
+    body: Vec<u8>,
+) -> Result<String> {
+    match target {
+        UploadTarget::Http { url, token_file } => {
+            let location = format!("{}/{}", url.trim_end_matches('/'), s3::uri_encode(name));
+            let mut request = client.put(&location);
+            if let Some(token_file) = token_file {
+                request = request.bearer_auth(s3::read_secret(token_file).await?);
+            }
+            request
+                .header("Content-Type", "application/json")
+                .body(body)
+                .send()
+                .await?
+                .error_for_status()?;
+            Ok(location)
+        }
+        UploadTarget::S3(bucket) => s3::put(client, bucket, name, body, "application/json").await,
+    }
+}
+
+/// Takes one capture for `job` and uploads it, returning where it went.
+async fn run(
+    manager: &SerialManager,
+    client: &reqwest::Client,
+    job: &CaptureJobConfig,
+) -> Result<String> {
+    let capture = manager.start_capture(&request(job)).await?;
+    tokio::time::sleep(Duration::from_secs(job.duration_minutes * 60)).await;
+    // The capture stops itself once its time is up
+    while manager
+        .list_captures()
+        .await
+        .iter()
+        .any(|info| info.id == capture.id && info.state == CaptureState::Running)
+    {
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+    let bundle = manager
+        .get_capture(capture.id)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Capture {} was deleted while running", capture.id));
+    // Uploaded or not, it is not kept in memory
+    let _ = manager.delete_capture(capture.id).await;
+    let body = serde_json::to_vec(&bundle?)?;
+
+    let name = object_name(&job.name, capture.started_at);
+    let mut attempt = 1;
+    loop {
+        match upload(client, &job.upload, &name, body.clone()).await {
+            Ok(location) => return Ok(location),
+            Err(e) if attempt < UPLOAD_ATTEMPTS => {
+                warn!(
+                    "Capture job {}: upload attempt {} failed: {}",
+                    job.name, attempt, e
+                );
+                attempt += 1;
+                tokio::time::sleep(UPLOAD_RETRY_DELAY).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs each job every day at its `at`. Failed runs are logged and sent
+/// to the job's `notify` channels; the next one is tried the day after.
+pub fn spawn(manager: &SerialManager, jobs: &[CaptureJobConfig]) -> Result<()> {
+    if jobs.is_empty() {
+        return Ok(());
+    }
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(300))
+        .build()?;
+    for job in jobs {
+        let at = parse_at(&job.at)?;
+        let job = job.clone();
+        let manager = manager.clone();
+        let client = client.clone();
+        info!("Capture job {} runs daily at {} UTC", job.name, job.at);
+        tokio::spawn(async move {
+            loop {
+                let now = Utc::now();
+                let wait = (next_run(at, now) - now).to_std().unwrap_or_default();
+                tokio::time::sleep(wait).await;
+
+                match run(&manager, &client, &job).await {
+                    Ok(location) => info!("Capture job {} uploaded {}", job.name, location),
+                    Err(e) => {
+                        warn!("Capture job {} failed: {}", job.name, e);
+                        manager.notifier().notify(
+                            &job.notify,
+                            Notification::new(
+                                Severity::Warning,
+                                format!("Capture job {} failed", job.name),
+                                e.to_string(),
+                            ),
+                        );
+                    }
+                }
+            }
+        });
+    }
+    Ok(())
+}
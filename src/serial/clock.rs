@@ -0,0 +1,72 @@
+//! Which clocks a capture's timestamps come from, so captures taken on
+//! different hosts, or at different times on one, can be lined up.
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::OnceLock;
+use std::time::Instant;
+
+/// Reference point for monotonic readings, fixed the first time one is taken.
+static MONOTONIC_EPOCH: OnceLock<Instant> = OnceLock::new();
+
+/// Microseconds from the server's monotonic reference point to `at`.
+pub fn monotonic_us(at: Instant) -> u64 {
+    let epoch = *MONOTONIC_EPOCH.get_or_init(|| at);
+    at.saturating_duration_since(epoch).as_micros() as u64
+}
+
+/// The clocks behind a capture's timestamps.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ClockInfo {
+    /// Host the capture was taken on.
+    pub host: String,
+    /// Wall-clock time at offset 0, to the microsecond.
+    pub start_wall: DateTime<Utc>,
+    /// Monotonic clock at offset 0, in microseconds from a reference
+    /// point fixed while the server runs. Record offsets are monotonic
+    /// too, so they are unaffected by steps of the wall clock.
+    pub start_monotonic_us: u64,
+    /// Whether the kernel reports the wall clock as synchronized, e.g. by
+    /// NTP; `None` where this cannot be told.
+    pub ntp_synchronized: Option<bool>,
+    /// The kernel's bound on the wall clock's error, in microseconds.
+    pub max_error_us: Option<u64>,
+}
+
+impl ClockInfo {
+    /// Describes a capture whose offsets count from `start`, which was
+    /// taken at wall-clock time `wall`.
+    pub fn new(start: Instant, wall: DateTime<Utc>) -> Self {
+        let (ntp_synchronized, max_error_us) = match sync_status() {
+            Some((synchronized, max_error)) => (Some(synchronized), Some(max_error)),
+            None => (None, None),
+        };
+        Self {
+            host: hostname::get()
+                .map(|host| host.to_string_lossy().into_owned())
+                .unwrap_or_default(),
+            start_wall: wall,
+            start_monotonic_us: monotonic_us(start),
+            ntp_synchronized,
+            max_error_us,
+        }
+    }
+}
+
+/// Whether the system clock is synchronized, and its maximum error.
+#[cfg(target_os = "linux")]
+fn sync_status() -> Option<(bool, u64)> {
+    // SAFETY: an all-zero timex with no mode bits set is a valid read-only query
+    let mut timex: libc::timex = unsafe { std::mem::zeroed() };
+    // SAFETY: `timex` is a valid, writable timex for the duration of the call
+    let state = unsafe { libc::adjtimex(&mut timex) };
+    if state < 0 {
+        return None;
+    }
+    Some((state != libc::TIME_ERROR, timex.maxerror.max(0) as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn sync_status() -> Option<(bool, u64)> {
+    None
+}
@@ -1,22 +1,177 @@
-use crate::config::SerialConnectionConfig;
+use crate::config::{ConnectionMode, SerialConnectionConfig};
 use crate::logging::SerialLogger;
+use crate::notifications::{Notification, Notifier, Severity};
+use crate::telemetry::histogram::{Histogram, LATENCY_BOUNDS};
+use crate::telemetry::{MetricSummary, Sample, Telemetry};
+use crate::trace::TraceContext;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::pin::Pin;
 use std::sync::Arc;
-use std::time::Instant;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::sync::{broadcast, mpsc, RwLock};
-use tokio_serial::SerialPortBuilderExt;
-use tracing::{error, info, warn};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, DuplexStream, ReadBuf};
+use tokio::sync::{broadcast, mpsc, oneshot, watch, RwLock};
+use tokio_serial::{SerialPortBuilderExt, SerialStream};
+use tracing::{error, field, info, info_span, warn, Instrument};
 
-use super::{ConnectionStats, SerialData};
+use super::annotations::{Annotation, AnnotationRequest};
+use super::batching::{Batch, Outgoing, TxQueue};
+use super::endpoints::ReadingCache;
+#[cfg(feature = "chaos")]
+use super::faults::Faults;
+use super::garbage::GarbageDetector;
+use super::history::{TrafficHistory, TrafficRecord};
+use super::identify::DeviceIdentity;
+use super::line_errors::{self, LineErrorMonitor, LineErrors};
+use super::pacing::TokenBucket;
+use super::quota::{QuotaUsage, Quotas};
+use super::reconnect::{Backoff, ReconnectStats};
+use super::replay::{FramePage, Replay, ReplayBuffer};
+use super::rules::{RuleChecker, RuleStats, RuleViolation};
+use super::throughput::{Throughput, ThroughputTracker};
+use super::traffic_profile::{TrafficProfile, TrafficProfiler};
+use super::{ConnectionHistograms, ConnectionStats, KeepaliveStats, Mirror, PortLease, SerialData};
 
 #[derive(Clone)]
 pub struct SerialConnection {
     config: SerialConnectionConfig,
-    tx: mpsc::Sender<SerialData>,
+    tx: mpsc::Sender<Outgoing>,
+    bulk_tx: mpsc::Sender<Outgoing>,
     rx: broadcast::Sender<SerialData>,
+    /// Numbers what goes out on `rx`; held while broadcasting so
+    /// subscribers know where their stream starts.
+    replay: Arc<std::sync::Mutex<ReplayBuffer>>,
+    traffic: broadcast::Sender<Traffic>,
     stats: Arc<RwLock<Stats>>,
     shutdown_tx: Arc<RwLock<Option<mpsc::Sender<()>>>>,
+    lease_tx: mpsc::Sender<oneshot::Sender<PortLease>>,
+    /// Asks the I/O task to reopen a port closed while idle.
+    wake_tx: mpsc::Sender<()>,
+    telemetry: Option<Arc<RwLock<Telemetry>>>,
+    quotas: Quotas,
+    /// Held while queueing a write, and by a running sequence for its
+    /// whole length.
+    transaction: Arc<tokio::sync::Mutex<()>>,
+    /// What the device reported itself as, once `identify` has succeeded.
+    identity: Arc<std::sync::Mutex<Option<DeviceIdentity>>>,
+    /// Last readings of endpoints with a `poll_interval_ms`.
+    readings: Arc<ReadingCache>,
+    /// Why the port was lost, once the I/O task has given up on it.
+    lost: watch::Receiver<Option<String>>,
+    is_virtual: bool,
+    #[cfg(feature = "chaos")]
+    faults: Faults,
+    /// Started when the connection was opened; sessions are spans in it.
+    trace: TraceContext,
+}
+
+/// Sole use of a connection's write queues; other writers wait until it
+/// is dropped.
+pub struct Transaction<'a> {
+    connection: &'a SerialConnection,
+    _turn: tokio::sync::MutexGuard<'a, ()>,
+}
+
+impl Transaction<'_> {
+    /// Writes `data` and waits until it has gone out.
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        let (message, written) = Outgoing::acked(data.to_vec());
+        self.connection
+            .enqueue(message, Priority::Interactive)
+            .await?;
+        self.connection.wait_written(written).await
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Rx,
+    Tx,
+}
+
+/// Which write queue a message joins. Interactive messages, such as an
+/// operator's keystrokes, are written between the chunks of a bulk
+/// transfer instead of waiting for it to finish.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    #[default]
+    Interactive,
+    Bulk,
+}
+
+/// Largest piece of a bulk message written before queued interactive
+/// messages get a turn.
+pub const BULK_CHUNK_BYTES: usize = 256;
+
+/// How often a connection with `idle_close_minutes` checks for clients.
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the driver's framing, parity and overrun counters are read.
+const LINE_ERROR_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest [`SerialConnection::stop`] waits for the port to be closed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A chunk of traffic in either direction, stamped by the I/O task on the
+/// monotonic clock so chunks from different connections can be ordered.
+#[derive(Debug, Clone)]
+pub struct Traffic {
+    pub direction: Direction,
+    pub data: SerialData,
+    pub at: Instant,
+    /// Sequence number of a received chunk, as subscriptions number it.
+    pub seq: Option<u64>,
+}
+
+/// The byte stream a connection's I/O task drives.
+enum Port {
+    Serial(SerialStream),
+    /// One end of an in-memory null-modem pair.
+    Virtual(DuplexStream),
+}
+
+impl AsyncRead for Port {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_read(cx, buf),
+            Port::Virtual(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Port {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_write(cx, buf),
+            Port::Virtual(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_flush(cx),
+            Port::Virtual(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            Port::Serial(port) => Pin::new(port).poll_shutdown(cx),
+            Port::Virtual(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -24,148 +179,1058 @@ struct Stats {
     bytes_received: u64,
     bytes_sent: u64,
     is_connected: bool,
+    /// False while the port is closed for being idle.
+    port_open: bool,
     start_time: Instant,
+    throughput: ThroughputTracker,
+    history: TrafficHistory,
+    profile: TrafficProfiler,
+    /// `None` until the driver's error counters have been read.
+    line_errors: Option<LineErrors>,
+    keepalive: Option<KeepaliveStats>,
+    rules: Vec<RuleStats>,
+    reconnect: Option<ReconnectStats>,
+    /// API request latency by route, since the connection was opened.
+    request_seconds: BTreeMap<&'static str, Histogram>,
 }
 
-impl SerialConnection {
-    pub async fn new(config: SerialConnectionConfig) -> Result<Self> {
-        let (tx, mut write_rx) = mpsc::channel::<SerialData>(100);
-        let (read_tx, _) = broadcast::channel::<SerialData>(1000);
-        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+/// Logs lines that went unanswered and tells the connection's channels,
+/// unless a silence mutes the rule.
+fn report_violations(
+    notifier: &Notifier,
+    config: &SerialConnectionConfig,
+    trace: &TraceContext,
+    violations: Vec<RuleViolation>,
+) {
+    for violation in violations {
+        let message = violation.describe();
+        warn!(
+            "{}: protocol rule {} violated: {}",
+            config.name, violation.rule, message
+        );
+        let now = chrono::Utc::now();
+        if let Some(id) = notifier
+            .silences()
+            .matching(&config.name, &violation.rule, now)
+        {
+            info!("Rule {} is muted by silence {}", violation.rule, id);
+            continue;
+        }
+        notifier.notify(
+            &config.notify,
+            Notification::new(
+                Severity::Warning,
+                format!(
+                    "Protocol rule {} violated on {}",
+                    violation.rule, config.name
+                ),
+                message,
+            )
+            .for_connection(&config.name)
+            .with_trace(trace),
+        );
+    }
+}
 
-        let stats = Arc::new(RwLock::new(Stats {
-            bytes_received: 0,
-            bytes_sent: 0,
-            is_connected: true,
-            start_time: Instant::now(),
-        }));
+/// Opens the OS serial port described by `config`.
+pub(crate) fn open_port(config: &SerialConnectionConfig) -> Result<SerialStream> {
+    let path = if cfg!(windows) {
+        super::discovery::device_path(&config.port)
+    } else {
+        config.port.clone()
+    };
+    let port = tokio_serial::new(path, config.baud_rate)
+        .data_bits(config.data_bits.into())
+        .stop_bits(config.stop_bits.into())
+        .parity(config.parity.into())
+        .flow_control(config.flow_control.into())
+        .open_native_async()?;
+    Ok(port)
+}
 
-        let logger = if config.logging.enabled {
-            Some(SerialLogger::new(&config.logging.path, &config.name).await?)
-        } else {
-            None
-        };
+/// How waiting for a lost port to come back ended.
+enum Reopened {
+    Port(SerialStream),
+    /// No more attempts are made; why the connection is lost.
+    GaveUp(String),
+    Shutdown,
+}
 
+/// Reopens a lost port with the waits the connection's `reconnect` sets
+/// out, failing the writes queued in the meantime. Without `reconnect` the
+/// connection gives up straight away.
+#[allow(clippy::too_many_arguments)]
+async fn reopen_lost_port(
+    config: &SerialConnectionConfig,
+    reason: String,
+    stats: &RwLock<Stats>,
+    write_rx: &mut TxQueue,
+    bulk_rx: &mut TxQueue,
+    lease_rx: &mut mpsc::Receiver<oneshot::Sender<PortLease>>,
+    shutdown_rx: &mut mpsc::Receiver<()>,
+    notifier: &Notifier,
+    trace: &TraceContext,
+) -> Reopened {
+    let Some(ref reconnect) = config.reconnect else {
+        return Reopened::GaveUp(reason);
+    };
+    {
+        let mut stats = stats.write().await;
+        stats.is_connected = false;
+        stats.port_open = false;
+        if let Some(ref mut reconnecting) = stats.reconnect {
+            reconnecting.reconnecting = true;
+            reconnecting.attempts = 0;
+            reconnecting.last_error = Some(reason.clone());
+            reconnecting.lost_at = Some(chrono::Utc::now());
+        }
+    }
+    notifier.notify(
+        &config.notify,
+        Notification::new(
+            Severity::Warning,
+            format!("Connection {} lost, reconnecting", config.name),
+            reason.clone(),
+        )
+        .for_connection(&config.name)
+        .with_trace(trace),
+    );
+
+    let refused = format!("Serial port {} is disconnected", config.port);
+    // Writers are refused before queueing while reconnecting; these got in
+    // just before the port was lost
+    let refuse = |message: Outgoing| match message.ack {
+        Some(ack) => {
+            let _ = ack.send(Err(refused.clone()));
+        }
+        None => warn!("Dropped a write to {}: {}", config.name, refused),
+    };
+    let mut backoff = Backoff::new(reconnect);
+    let mut last_error = reason.clone();
+    while let Some(delay) = backoff.next_delay() {
+        let attempt_at = tokio::time::Instant::now() + delay;
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(attempt_at) => break,
+                Some(message) = write_rx.recv() => refuse(message),
+                Some(message) = bulk_rx.recv() => refuse(message),
+                // Dropping the reply fails the request
+                Some(_) = lease_rx.recv() => {}
+                _ = shutdown_rx.recv() => return Reopened::Shutdown,
+            }
+        }
+
+        match open_port(config) {
+            Ok(serial) => {
+                let attempts = backoff.attempts();
+                {
+                    let mut stats = stats.write().await;
+                    stats.is_connected = true;
+                    stats.port_open = true;
+                    if let Some(ref mut reconnecting) = stats.reconnect {
+                        reconnecting.reconnecting = false;
+                        reconnecting.reconnects += 1;
+                        reconnecting.reconnected_at = Some(chrono::Utc::now());
+                    }
+                }
+                info!(
+                    "Reopened lost port {} for {} after {} attempts",
+                    config.port, config.name, attempts
+                );
+                notifier.notify(
+                    &config.notify,
+                    Notification::new(
+                        Severity::Info,
+                        format!("Connection {} reconnected", config.name),
+                        format!(
+                            "Serial port {} was reopened after {} attempts",
+                            config.port, attempts
+                        ),
+                    )
+                    .for_connection(&config.name)
+                    .with_trace(trace),
+                );
+                return Reopened::Port(serial);
+            }
+            Err(e) => {
+                warn!(
+                    "Attempt {} to reopen serial port {} failed: {}",
+                    backoff.attempts(),
+                    config.port,
+                    e
+                );
+                last_error = e.to_string();
+                if let Some(ref mut reconnecting) = stats.write().await.reconnect {
+                    reconnecting.attempts = backoff.attempts();
+                    reconnecting.last_error = Some(last_error.clone());
+                }
+            }
+        }
+    }
+
+    if let Some(ref mut reconnecting) = stats.write().await.reconnect {
+        reconnecting.reconnecting = false;
+    }
+    Reopened::GaveUp(format!(
+        "{}; gave up after {} attempts to reopen it: {}",
+        reason,
+        backoff.attempts(),
+        last_error
+    ))
+}
+
+/// Sets an SLCAN adapter up again after its port was reopened, as it may
+/// have been reset in the meantime.
+async fn reinit_slcan(port: &mut Port, config: &SerialConnectionConfig) {
+    if config.mode != ConnectionMode::Slcan {
+        return;
+    }
+    for command in crate::slcan::init_commands(config.slcan.bitrate).unwrap_or_default() {
+        if let Err(e) = port.write_all(command.as_bytes()).await {
+            warn!(
+                "Failed to reinitialise SLCAN adapter on {}: {}",
+                config.port, e
+            );
+            break;
+        }
+    }
+}
+
+impl SerialConnection {
+    pub async fn new(
+        config: SerialConnectionConfig,
+        notifier: Notifier,
+        mirror: Option<Mirror>,
+        quotas: Quotas,
+    ) -> Result<Self> {
         // Open the serial port
-        let port = tokio_serial::new(&config.port, config.baud_rate)
-            .data_bits(config.data_bits.into())
-            .stop_bits(config.stop_bits.into())
-            .parity(config.parity.into())
-            .flow_control(config.flow_control.into())
-            .open_native_async()?;
+        let port = open_port(&config)?;
 
         info!(
             "Opened serial port {} for connection {}",
             config.port, config.name
         );
 
-        let (mut read_half, mut write_half) = tokio::io::split(port);
+        Self::start(config, Port::Serial(port), notifier, mirror, quotas).await
+    }
+
+    /// Creates a connection over one end of an in-memory stream instead of
+    /// a serial port.
+    pub async fn new_virtual(
+        config: SerialConnectionConfig,
+        stream: DuplexStream,
+        notifier: Notifier,
+    ) -> Result<Self> {
+        info!("Created virtual port for connection {}", config.name);
+        Self::start(
+            config,
+            Port::Virtual(stream),
+            notifier,
+            None,
+            Quotas::default(),
+        )
+        .await
+    }
+
+    async fn start(
+        config: SerialConnectionConfig,
+        port: Port,
+        notifier: Notifier,
+        mirror: Option<Mirror>,
+        quotas: Quotas,
+    ) -> Result<Self> {
+        let is_virtual = matches!(port, Port::Virtual(_));
+        let (tx, write_rx) = mpsc::channel::<Outgoing>(100);
+        let mut write_rx = TxQueue::new(write_rx, config.tx_batch.clone());
+        let (bulk_tx, bulk_rx) = mpsc::channel::<Outgoing>(100);
+        let mut bulk_rx = TxQueue::new(bulk_rx, config.tx_batch.clone());
+        let (read_tx, _) = broadcast::channel::<SerialData>(1000);
+        let (traffic_tx, _) = broadcast::channel::<Traffic>(1000);
+        let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
+        let (lease_tx, mut lease_rx) = mpsc::channel::<oneshot::Sender<PortLease>>(1);
+        let (wake_tx, mut wake_rx) = mpsc::channel::<()>(1);
+
+        let logger = SerialLogger::from_config(&config.logging, &config.name).await?;
+
+        let telemetry = if config.metrics.is_empty() {
+            None
+        } else {
+            Some(Arc::new(RwLock::new(Telemetry::new(
+                &config.name,
+                &config.metrics,
+            )?)))
+        };
+
+        let trace = TraceContext::root();
+        let span = info_span!(
+            "connection",
+            connection = %config.name,
+            trace_id = %trace.trace_id,
+            bytes_rx = field::Empty,
+            bytes_tx = field::Empty,
+        );
+
+        // Subscribe before the I/O task starts so no lines are missed
+        if let Some(ref telemetry) = telemetry {
+            tokio::spawn(
+                crate::telemetry::run(
+                    telemetry.clone(),
+                    read_tx.subscribe(),
+                    notifier.clone(),
+                    trace.clone(),
+                )
+                .instrument(span.clone()),
+            );
+        }
+
+        // Terminator that marks a frame for throughput accounting
+        let frame_end = match config.mode {
+            ConnectionMode::Raw => b'\n',
+            ConnectionMode::Slcan => b'\r',
+        };
+        let count_frames = move |data: &[u8]| data.iter().filter(|&&b| b == frame_end).count();
+
+        let stats = Arc::new(RwLock::new(Stats {
+            bytes_received: 0,
+            bytes_sent: 0,
+            is_connected: true,
+            port_open: true,
+            start_time: Instant::now(),
+            throughput: ThroughputTracker::default(),
+            history: TrafficHistory::new(frame_end),
+            profile: TrafficProfiler::new(frame_end),
+            line_errors: None,
+            keepalive: config.keepalive.as_ref().map(|_| KeepaliveStats::default()),
+            rules: vec![],
+            reconnect: config
+                .reconnect
+                .as_ref()
+                .filter(|_| !is_virtual)
+                .map(|_| ReconnectStats::default()),
+            request_seconds: BTreeMap::new(),
+        }));
 
-        // Clone necessary data for the tasks
+        // Clone necessary data for the I/O task
         let read_tx_clone = read_tx.clone();
+        let replay = Arc::new(std::sync::Mutex::new(ReplayBuffer::default()));
+        let replay_clone = replay.clone();
+        let traffic_tx_clone = traffic_tx.clone();
         let stats_clone = stats.clone();
         let config_clone = config.clone();
-        let logger_clone = logger.clone();
+        let quotas_clone = quotas.clone();
+        #[cfg(feature = "chaos")]
+        let faults = Faults::default();
+        #[cfg(feature = "chaos")]
+        let faults_clone = faults.clone();
+        let trace_clone = trace.clone();
+        let transaction: Arc<tokio::sync::Mutex<()>> = Arc::default();
+        let transaction_clone = transaction.clone();
+        let (lost_tx, lost_rx) = watch::channel(None);
 
-        // Spawn read task
+        // Spawn the I/O task, which owns the port for the lifetime of the connection
         tokio::spawn(async move {
+            let mut port = port;
             let mut buffer = vec![0u8; 1024];
+            // Set when the port goes away rather than being shut down
+            let mut lost: Option<String> = None;
+            let mut pacer = config_clone
+                .max_tx_rate_bytes_per_sec
+                .map(|rate| TokenBucket::new(rate, Instant::now()));
+            // Replaces the pacer while a used-up quota slows writes down
+            let mut throttle: Option<(u32, TokenBucket)> = None;
+            // Messages being written, one per lane, and how much of each
+            // has gone out already
+            let mut pending: Option<(Batch, usize)> = None;
+            let mut bulk: Option<(Batch, usize)> = None;
+            // Virtual ports have nothing to reopen, so they are never closed
+            let idle_after = config_clone
+                .idle_close_minutes
+                .filter(|_| !is_virtual)
+                .map(|minutes| Duration::from_secs(minutes * 60));
+            let mut idle_check = tokio::time::interval(IDLE_CHECK_INTERVAL);
+            let mut last_active = tokio::time::Instant::now();
+            // Stops once a read shows the driver keeps no error counters
+            let mut watch_line_errors = !is_virtual;
+            let mut line_check = tokio::time::interval(LINE_ERROR_CHECK_INTERVAL);
+            let mut line_errors = LineErrorMonitor::default();
+            // Virtual ports have no baud rate to get wrong
+            let mut garbage = config_clone
+                .garbage_check
+                .as_ref()
+                .filter(|_| !is_virtual)
+                .map(GarbageDetector::new);
+            let has_clients =
+                || read_tx_clone.receiver_count() + traffic_tx_clone.receiver_count() > 0;
+            let keepalive_idle = config_clone
+                .keepalive
+                .as_ref()
+                .map(|keepalive| keepalive.idle());
+            let mut last_write = tokio::time::Instant::now();
+            let mut rules = RuleChecker::new(&config_clone.rules);
 
             loop {
+                let throttle_rate = quotas_clone.throttle_rate().filter(|rate| {
+                    config_clone
+                        .max_tx_rate_bytes_per_sec
+                        .is_none_or(|max| rate < &max)
+                });
+                if throttle_rate != throttle.as_ref().map(|(rate, _)| *rate) {
+                    throttle =
+                        throttle_rate.map(|rate| (rate, TokenBucket::new(rate, Instant::now())));
+                }
+
+                let active_pacer = throttle
+                    .as_ref()
+                    .map(|(_, bucket)| bucket)
+                    .or(pacer.as_ref());
+                let next_write = match (pending.as_ref().or(bulk.as_ref()), active_pacer) {
+                    (Some((batch, offset)), Some(bucket)) => {
+                        bucket.ready_at((batch.data.len() - offset).min(BULK_CHUNK_BYTES))
+                    }
+                    _ => Instant::now(),
+                };
+                let keepalive_at = last_write + keepalive_idle.unwrap_or_default();
+                let rule_deadline = rules.as_ref().and_then(RuleChecker::next_deadline);
+                let write_deadline = write_rx.deadline();
+                let bulk_deadline = bulk_rx.deadline();
+
                 tokio::select! {
-                    result = read_half.read(&mut buffer) => {
+                    result = {
+                        #[cfg(feature = "chaos")]
+                        let read = faults_clone.read(port.read(&mut buffer));
+                        #[cfg(not(feature = "chaos"))]
+                        let read = port.read(&mut buffer);
+                        read
+                    } => {
                         match result {
                             Ok(0) => {
                                 warn!("Serial port {} closed", config_clone.port);
-                                break;
+                                lost = Some(format!("Serial port {} closed", config_clone.port));
                             }
+                            #[cfg(feature = "chaos")]
+                            Ok(_) if faults_clone.drop_chunk() => {}
                             Ok(n) => {
                                 let data = buffer[..n].to_vec();
+                                let at = Instant::now();
 
                                 // Update stats
                                 {
                                     let mut stats = stats_clone.write().await;
                                     stats.bytes_received += n as u64;
+                                    let second = stats.start_time.elapsed().as_secs();
+                                    stats.throughput.record_rx(second, n, count_frames(&data));
+                                    stats.history.record(Direction::Rx, &data, chrono::Utc::now());
+                                    stats.profile.record(Direction::Rx, &data, at);
+                                    if let Some(ref mut rules) = rules {
+                                        let violations = rules.record(Direction::Rx, &data, at, chrono::Utc::now());
+                                        stats.rules = rules.stats();
+                                        report_violations(&notifier, &config_clone, &trace_clone, violations);
+                                    }
+                                }
+                                quotas_clone.record(Direction::Rx, n);
+
+                                if let Some(percent) = garbage.as_mut().and_then(|detector| detector.record(&data)) {
+                                    let message = format!(
+                                        "{}% of recently received bytes are not printable text, \
+                                         which usually means {} baud does not match the device; \
+                                         autobaud the port or try other common rates such as 9600 or 115200",
+                                        percent, config_clone.baud_rate
+                                    );
+                                    warn!("{}: {}", config_clone.name, message);
+                                    notifier.notify(
+                                        &config_clone.notify,
+                                        Notification::new(
+                                            Severity::Warning,
+                                            format!("Connection {} is receiving garbage", config_clone.name),
+                                            message,
+                                        )
+                                        .for_connection(&config_clone.name)
+                                        .with_trace(&trace_clone),
+                                    );
                                 }
 
                                 // Log if enabled
-                                if let Some(ref logger) = logger_clone {
+                                if let Some(ref logger) = logger {
                                     if let Err(e) = logger.log_received(&data).await {
                                         error!("Failed to log data: {}", e);
                                     }
                                 }
 
-                                // Broadcast to subscribers
+                                if let Some(ref mirror) = mirror {
+                                    mirror.received(&data);
+                                }
+
+                                // Broadcast to subscribers. Tapped after
+                                // buffering, so a tap taken before a
+                                // subscription misses nothing its replay does.
+                                let mut replay = replay_clone.lock().unwrap();
+                                let seq = replay.push(data.clone(), chrono::Utc::now());
+                                // Nobody may be tapping, which is fine
+                                let _ = traffic_tx_clone.send(Traffic {
+                                    direction: Direction::Rx,
+                                    data: data.clone(),
+                                    at,
+                                    seq: Some(seq),
+                                });
                                 if let Err(e) = read_tx_clone.send(data) {
                                     error!("Failed to broadcast data: {}", e);
                                 }
                             }
                             Err(e) => {
                                 error!("Error reading from serial port {}: {}", config_clone.port, e);
+                                lost = Some(format!("Error reading from serial port {}: {}", config_clone.port, e));
+                            }
+                        }
+
+                        let Some(reason) = lost.take() else {
+                            continue;
+                        };
+                        // Virtual ports have nothing to reopen
+                        let Port::Serial(serial) = port else {
+                            lost = Some(reason);
+                            break;
+                        };
+                        // Closed first, as the port is opened for exclusive use
+                        drop(serial);
+                        for (batch, _) in [pending.take(), bulk.take()].into_iter().flatten() {
+                            batch.failed(&reason);
+                        }
+                        for batch in [write_rx.flush(), bulk_rx.flush()].into_iter().flatten() {
+                            batch.failed(&reason);
+                        }
+                        match reopen_lost_port(
+                            &config_clone,
+                            reason,
+                            &stats_clone,
+                            &mut write_rx,
+                            &mut bulk_rx,
+                            &mut lease_rx,
+                            &mut shutdown_rx,
+                            &notifier,
+                            &trace_clone,
+                        )
+                        .await
+                        {
+                            Reopened::Port(serial) => port = Port::Serial(serial),
+                            Reopened::GaveUp(reason) => {
+                                lost = Some(reason);
+                                break;
+                            }
+                            Reopened::Shutdown => {
+                                info!("Shutting down I/O task for {} while reconnecting", config_clone.name);
                                 break;
                             }
                         }
+                        line_errors.reopened();
+                        reinit_slcan(&mut port, &config_clone).await;
+                        last_active = tokio::time::Instant::now();
+                        last_write = last_active;
                     }
-                    _ = shutdown_rx.recv() => {
-                        info!("Shutting down read task for {}", config_clone.name);
-                        break;
+                    Some(data) = write_rx.recv(), if pending.is_none() => {
+                        pending = write_rx.push(data).map(|batch| (batch, 0));
                     }
-                }
-            }
+                    Some(data) = bulk_rx.recv(), if bulk.is_none() => {
+                        bulk = bulk_rx.push(data).map(|batch| (batch, 0));
+                    }
+                    // Batches waiting for more writes go out at their deadline
+                    _ = tokio::time::sleep_until(write_deadline.unwrap_or_else(tokio::time::Instant::now)), if pending.is_none() && write_deadline.is_some() => {
+                        pending = write_rx.flush().map(|batch| (batch, 0));
+                    }
+                    _ = tokio::time::sleep_until(bulk_deadline.unwrap_or_else(tokio::time::Instant::now)), if bulk.is_none() && bulk_deadline.is_some() => {
+                        bulk = bulk_rx.flush().map(|batch| (batch, 0));
+                    }
+                    _ = tokio::time::sleep_until(next_write.into()), if pending.is_some() || bulk.is_some() => {
+                        // Interactive messages queued during a bulk transfer
+                        // go out before its next chunk
+                        if pending.is_none() {
+                            if let Some(data) = write_rx.try_recv() {
+                                pending = write_rx.push(data).map(|batch| (batch, 0));
+                            }
+                        }
+                        let (slot, limit) = if pending.is_some() {
+                            (&mut pending, usize::MAX)
+                        } else {
+                            (&mut bulk, BULK_CHUNK_BYTES)
+                        };
+                        let (mut message, offset) = slot.take().expect("checked by the branch guard");
+                        let want = (message.data.len() - offset).min(limit);
+                        let active_pacer = match throttle {
+                            Some((_, ref mut bucket)) => Some(bucket),
+                            None => pacer.as_mut(),
+                        };
+                        let granted = match active_pacer {
+                            Some(bucket) => bucket.take(want, Instant::now()),
+                            None => want,
+                        };
+                        if granted == 0 && want > 0 {
+                            *slot = Some((message, offset));
+                            continue;
+                        }
+                        let written = offset + granted;
+                        let data = message.data[offset..written].to_vec();
+                        // Nothing to write for an empty message, which marks
+                        // a point in the queue
+                        if data.is_empty() {
+                            message.written(written);
+                            continue;
+                        }
 
-            let mut stats = stats_clone.write().await;
-            stats.is_connected = false;
-        });
+                        #[cfg(feature = "chaos")]
+                        if let Some(delay) = faults_clone.write_delay() {
+                            tokio::time::sleep(delay).await;
+                        }
+                        let at = Instant::now();
+                        match port.write_all(&data).await {
+                            Ok(_) => {
+                                let mut stats = stats_clone.write().await;
+                                stats.bytes_sent += data.len() as u64;
+                                let second = stats.start_time.elapsed().as_secs();
+                                stats.throughput.record_tx(second, data.len(), count_frames(&data));
+                                stats.history.record(Direction::Tx, &data, chrono::Utc::now());
+                                stats.profile.record(Direction::Tx, &data, at);
+                                if let Some(ref mut rules) = rules {
+                                    let violations = rules.record(Direction::Tx, &data, at, chrono::Utc::now());
+                                    stats.rules = rules.stats();
+                                    report_violations(&notifier, &config_clone, &trace_clone, violations);
+                                }
+                                quotas_clone.record(Direction::Tx, data.len());
+                                last_write = tokio::time::Instant::now();
 
-        // Clone necessary data for write task
-        let stats_clone = stats.clone();
-        let config_clone = config.clone();
+                                if let Some(ref logger) = logger {
+                                    if let Err(e) = logger.log_sent(&data).await {
+                                        error!("Failed to log sent data: {}", e);
+                                    }
+                                }
 
-        // Spawn write task
-        tokio::spawn(async move {
-            while let Some(data) = write_rx.recv().await {
-                match write_half.write_all(&data).await {
-                    Ok(_) => {
-                        let mut stats = stats_clone.write().await;
-                        stats.bytes_sent += data.len() as u64;
+                                if let Some(ref mirror) = mirror {
+                                    mirror.sent(&data);
+                                }
+
+                                let _ = traffic_tx_clone.send(Traffic {
+                                    direction: Direction::Tx,
+                                    data,
+                                    at,
+                                    seq: None,
+                                });
+
+                                message.written(written);
+                                if written < message.data.len() {
+                                    *slot = Some((message, written));
+                                }
+                            }
+                            Err(e) => {
+                                error!("Error writing to serial port {}: {}", config_clone.port, e);
+                                // Drop the rest of the message
+                                message.failed(&e.to_string());
+                            }
+                        }
+                    }
+                    _ = tokio::time::sleep_until(keepalive_at), if keepalive_idle.is_some() && pending.is_none() && bulk.is_none() && write_deadline.is_none() && bulk_deadline.is_none() => {
+                        // Counts from now in case the write is held up
+                        last_write = tokio::time::Instant::now();
+                        // Not sent between the steps of a sequence, which
+                        // keeps the port busy enough anyway
+                        if transaction_clone.try_lock().is_err() {
+                            continue;
+                        }
+                        let keepalive = config_clone.keepalive.as_ref().expect("checked by the branch guard");
+                        pending = Some((Batch::from(keepalive.data.as_bytes().to_vec()), 0));
+                        if let Some(ref mut stats) = stats_clone.write().await.keepalive {
+                            stats.sent += 1;
+                            stats.last_sent = Some(chrono::Utc::now());
+                        }
+                    }
+                    _ = tokio::time::sleep_until(rule_deadline.unwrap_or_else(Instant::now).into()), if rule_deadline.is_some() => {
+                        let rules = rules.as_mut().expect("checked by the branch guard");
+                        let violations = rules.expire(Instant::now(), chrono::Utc::now());
+                        stats_clone.write().await.rules = rules.stats();
+                        report_violations(&notifier, &config_clone, &trace_clone, violations);
+                    }
+                    Some(reply) = lease_rx.recv() => {
+                        // Virtual ports cannot be lent out; dropping the reply
+                        // fails the request
+                        let serial = match port {
+                            Port::Serial(serial) => serial,
+                            other => {
+                                port = other;
+                                continue;
+                            }
+                        };
+
+                        let (return_tx, return_rx) = oneshot::channel();
+                        // If the requester has gone away the lease is dropped here,
+                        // which hands the port straight back.
+                        let _ = reply.send(PortLease::new(serial, return_tx));
+                        info!("Port {} leased for exclusive access", config_clone.port);
+
+                        tokio::select! {
+                            returned = return_rx => match returned {
+                                Ok(returned) => {
+                                    port = Port::Serial(returned);
+                                    info!("Port {} returned from exclusive access", config_clone.port);
+                                }
+                                Err(_) => {
+                                    error!("Port {} was lost during exclusive access", config_clone.port);
+                                    let reason = format!("Port {} was lost during exclusive access", config_clone.port);
+                                    for (batch, _) in [pending.take(), bulk.take()].into_iter().flatten() {
+                                        batch.failed(&reason);
+                                    }
+                                    for batch in [write_rx.flush(), bulk_rx.flush()].into_iter().flatten() {
+                                        batch.failed(&reason);
+                                    }
+                                    match reopen_lost_port(
+                                        &config_clone,
+                                        reason,
+                                        &stats_clone,
+                                        &mut write_rx,
+                                        &mut bulk_rx,
+                                        &mut lease_rx,
+                                        &mut shutdown_rx,
+                                        &notifier,
+                                        &trace_clone,
+                                    )
+                                    .await
+                                    {
+                                        Reopened::Port(serial) => {
+                                            port = Port::Serial(serial);
+                                            line_errors.reopened();
+                                            reinit_slcan(&mut port, &config_clone).await;
+                                        }
+                                        Reopened::GaveUp(reason) => {
+                                            lost = Some(reason);
+                                            break;
+                                        }
+                                        Reopened::Shutdown => {
+                                            info!("Shutting down I/O task for {} while reconnecting", config_clone.name);
+                                            break;
+                                        }
+                                    }
+                                }
+                            },
+                            _ = shutdown_rx.recv() => {
+                                info!("Shutting down I/O task for {} while port is leased", config_clone.name);
+                                break;
+                            }
+                        }
+                    }
+                    _ = line_check.tick(), if watch_line_errors => {
+                        let Port::Serial(ref serial) = port else {
+                            continue;
+                        };
+                        let Some(reading) = line_errors::read(serial) else {
+                            watch_line_errors = false;
+                            continue;
+                        };
+                        let report = line_errors.update(reading, Instant::now());
+                        stats_clone.write().await.line_errors = Some(line_errors.total());
+                        if let Some(errors) = report {
+                            let message = format!(
+                                "{} on {}; check the baud rate, parity and wiring",
+                                errors.describe(),
+                                config_clone.port
+                            );
+                            warn!("{}: {}", config_clone.name, message);
+                            notifier.notify(
+                                &config_clone.notify,
+                                Notification::new(
+                                    Severity::Warning,
+                                    format!("Line errors on connection {}", config_clone.name),
+                                    message,
+                                )
+                                .for_connection(&config_clone.name)
+                                .with_trace(&trace_clone),
+                            );
+                        }
+                    }
+                    _ = idle_check.tick(), if idle_after.is_some() => {
+                        let now = tokio::time::Instant::now();
+                        if has_clients() || pending.is_some() || bulk.is_some() || write_deadline.is_some() || bulk_deadline.is_some() {
+                            last_active = now;
+                            continue;
+                        }
+                        if now - last_active < idle_after.expect("checked by the branch guard") {
+                            continue;
+                        }
+                        // Wake-ups sent before this point are stale; checking
+                        // for clients and writes again catches any since
+                        while wake_rx.try_recv().is_ok() {}
+                        if has_clients() || !write_rx.is_empty() || !bulk_rx.is_empty() {
+                            last_active = now;
+                            continue;
+                        }
+                        let serial = match port {
+                            Port::Serial(serial) => serial,
+                            other => {
+                                port = other;
+                                continue;
+                            }
+                        };
 
-                        if let Some(ref logger) = logger {
-                            if let Err(e) = logger.log_sent(&data).await {
-                                error!("Failed to log sent data: {}", e);
+                        drop(serial);
+                        stats_clone.write().await.port_open = false;
+                        info!("Closed idle port {} for {}", config_clone.port, config_clone.name);
+
+                        // Writers wake the task before queueing, so a write
+                        // whose wake-up was drained above reopens it too
+                        tokio::select! {
+                            _ = wake_rx.recv() => {}
+                            Some(message) = write_rx.recv() => write_rx.unrecv(message),
+                            Some(message) = bulk_rx.recv() => bulk_rx.unrecv(message),
+                            _ = shutdown_rx.recv() => {
+                                info!("Shutting down I/O task for {} while port is closed", config_clone.name);
+                                break;
                             }
                         }
+                        let reopened = match open_port(&config_clone) {
+                            Ok(serial) => Reopened::Port(serial),
+                            Err(e) => {
+                                error!("Failed to reopen serial port {}: {}", config_clone.port, e);
+                                let reason = format!("Failed to reopen serial port {}: {}", config_clone.port, e);
+                                reopen_lost_port(
+                                    &config_clone,
+                                    reason,
+                                    &stats_clone,
+                                    &mut write_rx,
+                                    &mut bulk_rx,
+                                    &mut lease_rx,
+                                    &mut shutdown_rx,
+                                    &notifier,
+                                    &trace_clone,
+                                )
+                                .await
+                            }
+                        };
+                        match reopened {
+                            Reopened::Port(serial) => {
+                                port = Port::Serial(serial);
+                                line_errors.reopened();
+                            }
+                            Reopened::GaveUp(reason) => {
+                                lost = Some(reason);
+                                break;
+                            }
+                            Reopened::Shutdown => {
+                                info!("Shutting down I/O task for {} while reconnecting", config_clone.name);
+                                break;
+                            }
+                        }
+                        info!("Reopened port {} for {}", config_clone.port, config_clone.name);
+                        // The adapter may have been reset while closed
+                        reinit_slcan(&mut port, &config_clone).await;
+                        stats_clone.write().await.port_open = true;
+                        last_active = tokio::time::Instant::now();
+                        last_write = last_active;
                     }
-                    Err(e) => {
-                        error!("Error writing to serial port {}: {}", config_clone.port, e);
+                    _ = shutdown_rx.recv() => {
+                        info!("Shutting down I/O task for {}", config_clone.name);
+                        break;
                     }
                 }
             }
-        });
+
+            let mut stats = stats_clone.write().await;
+            stats.is_connected = false;
+            let span = tracing::Span::current();
+            span.record("bytes_rx", stats.bytes_received);
+            span.record("bytes_tx", stats.bytes_sent);
+            info!("Closed connection {}", config_clone.name);
+
+            if let Some(reason) = lost {
+                notifier.notify(
+                    &config_clone.notify,
+                    Notification::new(
+                        Severity::Critical,
+                        format!("Connection {} lost", config_clone.name),
+                        reason.clone(),
+                    )
+                    .for_connection(&config_clone.name)
+                    .with_trace(&trace_clone),
+                );
+                let _ = lost_tx.send(Some(reason));
+            }
+        }.instrument(span));
+
+        if config.mode == ConnectionMode::Slcan {
+            for command in crate::slcan::init_commands(config.slcan.bitrate)? {
+                tx.send(command.into_bytes().into()).await?;
+            }
+            info!(
+                "Opened SLCAN channel at {} bit/s for connection {}",
+                config.slcan.bitrate, config.name
+            );
+        }
 
         Ok(Self {
             config,
             tx,
+            bulk_tx,
             rx: read_tx,
+            replay,
+            traffic: traffic_tx,
             stats,
             shutdown_tx: Arc::new(RwLock::new(Some(shutdown_tx))),
+            lease_tx,
+            wake_tx,
+            telemetry,
+            quotas,
+            transaction,
+            identity: Arc::default(),
+            readings: Arc::default(),
+            lost: lost_rx,
+            is_virtual,
+            #[cfg(feature = "chaos")]
+            faults,
+            trace,
         })
     }
 
     pub async fn send(&self, data: &[u8]) -> Result<()> {
-        self.tx
-            .send(data.to_vec())
+        self.send_with_priority(data, Priority::Interactive).await
+    }
+
+    pub async fn send_with_priority(&self, data: &[u8], priority: Priority) -> Result<()> {
+        self.queue(data.to_vec().into(), priority).await
+    }
+
+    /// Queues `data` like [`send_with_priority`](Self::send_with_priority),
+    /// then waits until the I/O task has written all of it to the port.
+    pub async fn send_written(&self, data: &[u8], priority: Priority) -> Result<()> {
+        let (message, written) = Outgoing::acked(data.to_vec());
+        self.queue(message, priority).await?;
+        self.wait_written(written).await
+    }
+
+    async fn wait_written(&self, written: oneshot::Receiver<Result<(), String>>) -> Result<()> {
+        match written.await {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(e)) => anyhow::bail!("Failed to write data: {}", e),
+            Err(_) => anyhow::bail!(
+                "Connection {} closed before the data was written",
+                self.config.name
+            ),
+        }
+    }
+
+    async fn queue(&self, message: Outgoing, priority: Priority) -> Result<()> {
+        // Waits while a sequence is running
+        let _turn = self.transaction.lock().await;
+        self.enqueue(message, priority).await
+    }
+
+    async fn enqueue(&self, message: Outgoing, priority: Priority) -> Result<()> {
+        let reconnecting = self
+            .stats
+            .read()
+            .await
+            .reconnect
+            .as_ref()
+            .is_some_and(|reconnect| reconnect.reconnecting);
+        if reconnecting {
+            anyhow::bail!(
+                "Serial port {} is disconnected, reconnecting",
+                self.config.port
+            );
+        }
+        self.quotas.check_write()?;
+        self.wake();
+        let queue = match priority {
+            Priority::Interactive => &self.tx,
+            Priority::Bulk => &self.bulk_tx,
+        };
+        queue
+            .send(message)
             .await
             .map_err(|e| anyhow::anyhow!("Failed to send data: {}", e))
     }
 
+    /// Waits for writes queued so far to go out, then keeps other writers
+    /// waiting until the returned transaction is dropped.
+    pub async fn transaction(&self) -> Result<Transaction<'_>> {
+        let turn = self.transaction.lock().await;
+        // Interactive writes are already ahead of the transaction's; a
+        // bulk transfer must finish before its first step
+        let (barrier, written) = Outgoing::acked(Vec::new());
+        self.enqueue(barrier, Priority::Bulk).await?;
+        self.wait_written(written).await?;
+        Ok(Transaction {
+            connection: self,
+            _turn: turn,
+        })
+    }
+
+    /// Takes the port away from the I/O task until the returned lease is dropped.
+    ///
+    /// Reads and writes through the connection are paused in the meantime;
+    /// queued writes are delivered once the port is returned.
+    pub async fn acquire_exclusive(&self) -> Result<PortLease> {
+        if self.is_virtual {
+            anyhow::bail!(
+                "Connection {} is virtual and has no port to lend",
+                self.config.name
+            );
+        }
+        self.wake();
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.lease_tx
+            .send(reply_tx)
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection {} is not running", self.config.name))?;
+        reply_rx
+            .await
+            .map_err(|_| anyhow::anyhow!("Connection {} is not running", self.config.name))
+    }
+
     pub fn subscribe(&self) -> broadcast::Receiver<SerialData> {
-        self.rx.subscribe()
+        let rx = self.rx.subscribe();
+        self.wake();
+        rx
+    }
+
+    /// Subscribes along with the buffered chunks after sequence number
+    /// `since`; chunks received live continue from `Replay::next_seq`.
+    pub fn subscribe_from(&self, since: Option<u64>) -> (broadcast::Receiver<SerialData>, Replay) {
+        let replay = self.replay.lock().unwrap();
+        let rx = self.rx.subscribe();
+        let missed = replay.since(since);
+        drop(replay);
+        self.wake();
+        (rx, missed)
+    }
+
+    /// Receives both directions of traffic with monotonic timestamps.
+    pub fn tap(&self) -> broadcast::Receiver<Traffic> {
+        let rx = self.traffic.subscribe();
+        self.wake();
+        rx
+    }
+
+    /// Reopens the port if it was closed for being idle.
+    fn wake(&self) {
+        // A full channel already has a wake-up waiting
+        let _ = self.wake_tx.try_send(());
+    }
+
+    pub fn is_virtual(&self) -> bool {
+        self.is_virtual
     }
 
     pub fn config(&self) -> &SerialConnectionConfig {
         &self.config
     }
 
+    /// The trace this connection's logs, notifications and sessions share.
+    pub fn trace(&self) -> &TraceContext {
+        &self.trace
+    }
+
+    pub fn identity(&self) -> Option<DeviceIdentity> {
+        self.identity.lock().unwrap().clone()
+    }
+
+    pub(super) fn set_identity(&self, identity: DeviceIdentity) {
+        *self.identity.lock().unwrap() = Some(identity);
+    }
+
     pub async fn get_stats(&self) -> ConnectionStats {
         let stats = self.stats.read().await;
         ConnectionStats {
@@ -174,14 +1239,136 @@ impl SerialConnection {
             bytes_received: stats.bytes_received,
             bytes_sent: stats.bytes_sent,
             is_connected: stats.is_connected,
+            port_open: stats.port_open,
             uptime_seconds: stats.start_time.elapsed().as_secs(),
+            line_errors: stats.line_errors,
+            keepalive: stats.keepalive.clone(),
+            rules: stats.rules.clone(),
+            reconnect: stats.reconnect.clone(),
+        }
+    }
+
+    /// Records how long an API request on this connection took.
+    pub async fn observe_request(&self, route: &'static str, elapsed: Duration) {
+        self.stats
+            .write()
+            .await
+            .request_seconds
+            .entry(route)
+            .or_insert_with(|| Histogram::new(LATENCY_BOUNDS))
+            .observe(elapsed.as_secs_f64());
+    }
+
+    pub async fn histograms(&self) -> ConnectionHistograms {
+        let stats = self.stats.read().await;
+        ConnectionHistograms {
+            request_seconds: stats
+                .request_seconds
+                .iter()
+                .map(|(route, histogram)| (*route, histogram.clone()))
+                .collect(),
+            frame_bytes: [Direction::Rx, Direction::Tx]
+                .into_iter()
+                .map(|direction| (direction, stats.profile.frame_sizes(direction)))
+                .collect(),
         }
     }
 
+    /// Average traffic rates over the last `window` seconds.
+    pub async fn throughput(&self, window: u64) -> Throughput {
+        let stats = self.stats.read().await;
+        stats
+            .throughput
+            .rates(stats.start_time.elapsed().as_secs(), window)
+    }
+
+    /// The most recent `lines` frames of traffic, oldest first.
+    pub async fn tail(&self, lines: usize, direction: Option<Direction>) -> Vec<TrafficRecord> {
+        self.stats.read().await.history.tail(lines, direction)
+    }
+
+    /// Frame size and spacing distributions over the last `window` seconds.
+    pub async fn traffic_profile(&self, window: u64) -> TrafficProfile {
+        self.stats
+            .read()
+            .await
+            .profile
+            .profile(window, Instant::now())
+    }
+
+    pub async fn recent_bytes(&self, bytes: usize, direction: Direction) -> Vec<u8> {
+        self.stats
+            .read()
+            .await
+            .history
+            .recent_bytes(bytes, direction)
+    }
+
+    /// Today's usage of the connection's own quota, if it has one.
+    pub fn quota_usage(&self) -> Option<QuotaUsage> {
+        let day = chrono::Utc::now().date_naive();
+        self.quotas.own().map(|quota| quota.usage(day))
+    }
+
+    /// Buffered received chunks after sequence number `since`.
+    pub fn frames(&self, since: u64, limit: usize) -> FramePage {
+        self.replay.lock().unwrap().page(since, limit)
+    }
+
+    pub fn read_frames(&self, cursor: &str, limit: usize) -> FramePage {
+        self.replay.lock().unwrap().read(cursor, limit)
+    }
+
+    /// Pins a validated note to the received data; `None` if
+    /// `request.seq` is not a chunk still kept.
+    pub fn annotate(&self, request: AnnotationRequest) -> Option<Annotation> {
+        self.replay
+            .lock()
+            .unwrap()
+            .annotate(request, chrono::Utc::now())
+    }
+
+    pub fn annotations(&self, since: u64) -> Vec<Annotation> {
+        self.replay.lock().unwrap().annotations(since)
+    }
+
+    /// Faults injected into the connection's I/O.
+    #[cfg(feature = "chaos")]
+    pub fn faults(&self) -> &Faults {
+        &self.faults
+    }
+
+    pub(crate) fn readings(&self) -> &ReadingCache {
+        &self.readings
+    }
+
+    /// Set to why the port was lost once the I/O task gives up on it; the
+    /// sender is dropped when the task ends either way.
+    pub(crate) fn lost(&self) -> watch::Receiver<Option<String>> {
+        self.lost.clone()
+    }
+
+    /// Current state of the connection's extracted metrics.
+    pub async fn metric_summaries(&self) -> Vec<MetricSummary> {
+        match self.telemetry {
+            Some(ref telemetry) => telemetry.read().await.summaries(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Recorded samples of one metric, or `None` if it is not defined.
+    pub async fn metric_samples(&self, metric: &str, limit: Option<usize>) -> Option<Vec<Sample>> {
+        self.telemetry.as_ref()?.read().await.samples(metric, limit)
+    }
+
+    /// Ends the I/O task and waits for it to close the port, so the port
+    /// can be opened again straight away.
     pub async fn stop(&mut self) {
         let mut shutdown = self.shutdown_tx.write().await;
         if let Some(tx) = shutdown.take() {
             let _ = tx.send(()).await;
+            // The task drops its receiver, after the port, when it ends
+            let _ = tokio::time::timeout(STOP_TIMEOUT, tx.closed()).await;
         }
     }
 }
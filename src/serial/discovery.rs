@@ -0,0 +1,206 @@
+//! Enumeration of the serial ports present on the host, and the path
+//! quirks needed to open and recognise them on Windows.
+
+use anyhow::Result;
+use serde::Serialize;
+use serialport::{SerialPortInfo, SerialPortType};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortKind {
+    Usb,
+    Pci,
+    Bluetooth,
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortDescription {
+    pub path: String,
+    pub kind: PortKind,
+    /// Name the OS shows for the device, e.g. `USB-SERIAL CH340 (COM5)` in
+    /// the Windows Device Manager.
+    pub friendly_name: Option<String>,
+    /// Manufacturer, product and USB IDs in one line.
+    pub description: Option<String>,
+    pub manufacturer: Option<String>,
+    pub serial_number: Option<String>,
+    /// USB vendor and product IDs as four hex digits.
+    pub vid: Option<String>,
+    pub pid: Option<String>,
+    /// Stable `/dev/serial/by-id/...` link to the device on Linux, which
+    /// survives re-plugging unlike `/dev/ttyUSB0`.
+    pub by_id: Option<String>,
+    /// Bus and hub ports the USB adapter is plugged into, e.g. `1-2.3` for
+    /// port 3 of the hub on port 2 of bus 1 (Linux only).
+    pub usb_path: Option<String>,
+}
+
+/// One-line summary of a USB adapter, e.g. `FTDI FT232R USB UART (0403:6001)`.
+pub fn usb_description(
+    manufacturer: Option<&str>,
+    product: Option<&str>,
+    vid: u16,
+    pid: u16,
+) -> String {
+    let mut parts: Vec<&str> = Vec::new();
+    if let Some(manufacturer) = manufacturer.filter(|m| !m.is_empty()) {
+        parts.push(manufacturer);
+    }
+    // Windows product strings often repeat the manufacturer
+    if let Some(product) = product.filter(|p| !p.is_empty() && !parts.contains(p)) {
+        parts.push(product);
+    }
+    let ids = format!("({:04x}:{:04x})", vid, pid);
+    if parts.is_empty() {
+        ids
+    } else {
+        format!("{} {}", parts.join(" "), ids)
+    }
+}
+
+impl From<SerialPortInfo> for PortDescription {
+    fn from(info: SerialPortInfo) -> Self {
+        let mut description = PortDescription {
+            path: info.port_name,
+            kind: PortKind::Unknown,
+            friendly_name: None,
+            description: None,
+            manufacturer: None,
+            serial_number: None,
+            vid: None,
+            pid: None,
+            by_id: None,
+            usb_path: None,
+        };
+        match info.port_type {
+            SerialPortType::UsbPort(usb) => {
+                description.kind = PortKind::Usb;
+                description.description = Some(usb_description(
+                    usb.manufacturer.as_deref(),
+                    usb.product.as_deref(),
+                    usb.vid,
+                    usb.pid,
+                ));
+                // On Windows the product string is the SetupAPI friendly name
+                description.friendly_name = usb.product;
+                description.manufacturer = usb.manufacturer;
+                description.serial_number = usb.serial_number;
+                description.vid = Some(format!("{:04x}", usb.vid));
+                description.pid = Some(format!("{:04x}", usb.pid));
+            }
+            SerialPortType::PciPort => description.kind = PortKind::Pci,
+            SerialPortType::BluetoothPort => description.kind = PortKind::Bluetooth,
+            SerialPortType::Unknown => {}
+        }
+        description
+    }
+}
+
+/// Ports currently present on the host.
+pub async fn list_ports() -> Result<Vec<PortDescription>> {
+    // Enumeration can block for a while on Windows
+    let mut ports = tokio::task::spawn_blocking(|| -> Result<Vec<PortDescription>> {
+        let mut ports: Vec<PortDescription> = serialport::available_ports()?
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        for port in &mut ports {
+            port.by_id = by_id_path(&port.path);
+            if port.kind == PortKind::Usb {
+                port.usb_path = sysfs_usb_path(&port.path);
+            }
+        }
+        Ok(ports)
+    })
+    .await??;
+    ports.sort_by_key(|port| port_key(&port.path));
+    Ok(ports)
+}
+
+const BY_ID_DIR: &str = "/dev/serial/by-id";
+
+/// The `/dev/serial/by-id` link that resolves to `port`, if udev made one.
+pub fn by_id_path(port: &str) -> Option<String> {
+    let target = std::fs::canonicalize(port).ok()?;
+    std::fs::read_dir(BY_ID_DIR)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .find(|link| std::fs::canonicalize(link).ok().as_ref() == Some(&target))
+        .map(|link| link.to_string_lossy().into_owned())
+}
+
+/// The USB path of the adapter behind a tty, from where its device sits
+/// under `/sys/devices`.
+fn sysfs_usb_path(port: &str) -> Option<String> {
+    let name = port.rsplit('/').next()?;
+    let device = std::fs::canonicalize(format!("/sys/class/tty/{}/device", name)).ok()?;
+    usb_path_of(&device.to_string_lossy())
+}
+
+/// The last USB device in a sysfs path, e.g. `1-2.3` in
+/// `/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2.3/1-2.3:1.0/ttyUSB0`.
+/// Interfaces (`1-2.3:1.0`) and root hubs (`usb1`) are skipped.
+pub fn usb_path_of(sysfs: &str) -> Option<String> {
+    sysfs
+        .rsplit('/')
+        .find(|segment| {
+            segment.split_once('-').is_some_and(|(bus, ports)| {
+                !bus.is_empty()
+                    && bus.bytes().all(|b| b.is_ascii_digit())
+                    && !ports.is_empty()
+                    && ports
+                        .split('.')
+                        .all(|port| !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()))
+            })
+        })
+        .map(str::to_string)
+}
+
+/// `COMn` without any prefix or trailing colon, if `port` names a COM port.
+fn com_number(port: &str) -> Option<u32> {
+    let name = port.trim();
+    let name = name.strip_prefix(r"\\.\").unwrap_or(name);
+    let name = name.strip_suffix(':').unwrap_or(name);
+    let digits = name
+        .get(..3)?
+        .eq_ignore_ascii_case("COM")
+        .then(|| &name[3..])?;
+    if digits.is_empty() || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// Path to hand to the OS for `port`. Windows only opens `COM10` and above
+/// through the `\\.\COM10` device namespace, which works for every COM
+/// port, so all of them are rewritten; anything else is left alone.
+pub fn device_path(port: &str) -> String {
+    match com_number(port) {
+        Some(number) => format!(r"\\.\COM{}", number),
+        None => port.to_string(),
+    }
+}
+
+/// Key under which two spellings of the same port compare equal, e.g.
+/// `com10`, `COM10:` and `\\.\COM10`.
+pub fn port_key(port: &str) -> String {
+    match com_number(port) {
+        Some(number) => format!("COM{}", number),
+        None => port.to_string(),
+    }
+}
+
+/// The discovered port a configured path refers to. Symlinks such as
+/// `/dev/serial/by-id/...` are resolved before comparing.
+pub fn find_port<'a>(ports: &'a [PortDescription], port: &str) -> Option<&'a PortDescription> {
+    let key = port_key(port);
+    let resolved = std::fs::canonicalize(port).ok();
+    ports.iter().find(|candidate| {
+        port_key(&candidate.path) == key
+            || resolved.as_ref().is_some_and(|resolved| {
+                std::fs::canonicalize(&candidate.path).ok().as_ref() == Some(resolved)
+            })
+    })
+}
@@ -0,0 +1,278 @@
+//! Derived endpoints (`endpoints`): a command written to the device and a
+//! value pulled out of its reply, served as
+//! `GET /api/connections/:name/:endpoint`, so a trivial device "driver"
+//! can be declared in the config instead of written.
+
+use super::SerialConnection;
+use crate::config::EndpointConfig;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::{timeout_at, Instant};
+
+/// Longest an endpoint may wait for its reply.
+pub const MAX_TIMEOUT_MS: u64 = 60_000;
+
+/// Longest a reading may be served again before the device is asked anew.
+pub const MAX_POLL_INTERVAL_MS: u64 = 3_600_000;
+
+/// Longest unterminated reply line kept while waiting for a match.
+const MAX_LINE_BYTES: usize = 4096;
+
+/// Path segments the built-in connection routes use, which an endpoint
+/// would never be reached under.
+const RESERVED: &[&str] = &[
+    "annotations",
+    "can",
+    "disable",
+    "enable",
+    "faults",
+    "flash",
+    "frames",
+    "hexdump",
+    "latency-test",
+    "lease",
+    "metrics",
+    "poll",
+    "power",
+    "read",
+    "retry",
+    "send",
+    "sequence",
+    "stats",
+    "tail",
+    "throughput",
+    "traffic-profile",
+    "upload",
+    "write-lock",
+    "ws",
+];
+
+/// Checks a connection's endpoint definitions.
+pub fn validate(endpoints: &[EndpointConfig]) -> Result<()> {
+    let mut names = HashSet::new();
+    for endpoint in endpoints {
+        let valid_name = !endpoint.name.is_empty()
+            && endpoint
+                .name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !valid_name {
+            anyhow::bail!(
+                "Endpoint name {:?} must be letters, digits, '-' and '_'",
+                endpoint.name
+            );
+        }
+        if RESERVED.contains(&endpoint.name.as_str()) {
+            anyhow::bail!(
+                "Endpoint name {} is used by a built-in route",
+                endpoint.name
+            );
+        }
+        if !names.insert(&endpoint.name) {
+            anyhow::bail!("Duplicate endpoint name: {}", endpoint.name);
+        }
+        if endpoint.command.is_empty() {
+            anyhow::bail!("Endpoint {}: command must not be empty", endpoint.name);
+        }
+        if endpoint.timeout_ms == 0 || endpoint.timeout_ms > MAX_TIMEOUT_MS {
+            anyhow::bail!(
+                "Endpoint {}: timeout_ms must be between 1 and {}",
+                endpoint.name,
+                MAX_TIMEOUT_MS
+            );
+        }
+        if let Some(interval) = endpoint.poll_interval_ms {
+            if interval == 0 || interval > MAX_POLL_INTERVAL_MS {
+                anyhow::bail!(
+                    "Endpoint {}: poll_interval_ms must be between 1 and {}",
+                    endpoint.name,
+                    MAX_POLL_INTERVAL_MS
+                );
+            }
+        }
+        compile(endpoint)?;
+    }
+    Ok(())
+}
+
+fn compile(endpoint: &EndpointConfig) -> Result<Regex> {
+    Regex::new(&endpoint.pattern)
+        .map_err(|e| anyhow::anyhow!("Endpoint {}: invalid pattern: {}", endpoint.name, e))
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct EndpointReading {
+    pub connection: String,
+    pub endpoint: String,
+    /// The pattern's first capture group, or its whole match without one.
+    pub value: String,
+    /// `value` as a number, when it is one.
+    pub number: Option<f64>,
+    /// The reply line the pattern matched.
+    pub line: String,
+    pub read_at: DateTime<Utc>,
+    /// True when served from an earlier query rather than read just now.
+    pub cached: bool,
+}
+
+/// No reply line matched the endpoint's pattern before its timeout.
+#[derive(Debug)]
+pub struct NoReply {
+    pub endpoint: String,
+    pub timeout_ms: u64,
+}
+
+impl std::fmt::Display for NoReply {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "No reply to endpoint {} matched its pattern within {} ms",
+            self.endpoint, self.timeout_ms
+        )
+    }
+}
+
+impl std::error::Error for NoReply {}
+
+/// The reading in `line`, if `regex` matches it.
+pub fn extract(regex: &Regex, line: &str) -> Option<(String, Option<f64>)> {
+    let captures = regex.captures(line)?;
+    let value = captures
+        .get(1)
+        .or_else(|| captures.get(0))?
+        .as_str()
+        .trim()
+        .to_string();
+    let number = value.parse().ok();
+    Some((value, number))
+}
+
+/// Writes the endpoint's command and reads the value from the first reply
+/// line that matches.
+pub async fn query(
+    connection: &SerialConnection,
+    endpoint: &EndpointConfig,
+) -> Result<EndpointReading> {
+    let regex = compile(endpoint)?;
+    let deadline = Instant::now() + Duration::from_millis(endpoint.timeout_ms);
+    let no_reply = || NoReply {
+        endpoint: endpoint.name.clone(),
+        timeout_ms: endpoint.timeout_ms,
+    };
+    // Held until the reply is in, so no other writes get a response mixed
+    // into it. Queued bulk writes go out first, within the same timeout.
+    let (_transaction, mut rx) = timeout_at(deadline, async {
+        let transaction = connection.transaction().await?;
+        let rx = connection.subscribe();
+        transaction.write(endpoint.command.as_bytes()).await?;
+        anyhow::Ok((transaction, rx))
+    })
+    .await
+    .map_err(|_| no_reply())??;
+
+    let (line, (value, number)) = wait_for_match(&mut rx, &regex, deadline)
+        .await?
+        .ok_or_else(no_reply)?;
+    Ok(EndpointReading {
+        connection: connection.config().name.clone(),
+        endpoint: endpoint.name.clone(),
+        value,
+        number,
+        line,
+        read_at: Utc::now(),
+        cached: false,
+    })
+}
+
+#[derive(Debug)]
+struct CachedReading {
+    reading: EndpointReading,
+    taken: Instant,
+}
+
+/// The last reading of each endpoint, by name.
+#[derive(Debug, Default)]
+pub struct ReadingCache {
+    readings: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<CachedReading>>>>>,
+}
+
+impl ReadingCache {
+    fn slot(&self, endpoint: &str) -> Arc<tokio::sync::Mutex<Option<CachedReading>>> {
+        self.readings
+            .lock()
+            .unwrap()
+            .entry(endpoint.to_string())
+            .or_default()
+            .clone()
+    }
+}
+
+/// Reads an endpoint, serving its last reading again while that is younger
+/// than `poll_interval_ms`. Requests that arrive while the device is being
+/// asked wait for that answer instead of writing the command again.
+pub async fn read(
+    connection: &SerialConnection,
+    endpoint: &EndpointConfig,
+) -> Result<EndpointReading> {
+    let Some(interval) = endpoint.poll_interval_ms else {
+        return query(connection, endpoint).await;
+    };
+    let slot = connection.readings().slot(&endpoint.name);
+    let mut cached = slot.lock().await;
+    let fresh = cached
+        .as_ref()
+        .filter(|cached| cached.taken.elapsed() < Duration::from_millis(interval));
+    if let Some(fresh) = fresh {
+        return Ok(EndpointReading {
+            cached: true,
+            ..fresh.reading.clone()
+        });
+    }
+
+    let reading = query(connection, endpoint).await?;
+    *cached = Some(CachedReading {
+        reading: reading.clone(),
+        taken: Instant::now(),
+    });
+    Ok(reading)
+}
+
+/// Matches `regex` against each received line until one matches, or
+/// returns `None` once `deadline` passes.
+async fn wait_for_match(
+    rx: &mut broadcast::Receiver<Vec<u8>>,
+    regex: &Regex,
+    deadline: Instant,
+) -> Result<Option<(String, (String, Option<f64>))>> {
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        match timeout_at(deadline, rx.recv()).await {
+            Ok(Ok(data)) => {
+                for &byte in &data {
+                    if byte != b'\n' && byte != b'\r' {
+                        if line.len() < MAX_LINE_BYTES {
+                            line.push(byte);
+                        }
+                        continue;
+                    }
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    line.clear();
+                    if let Some(reading) = extract(regex, &text) {
+                        return Ok(Some((text, reading)));
+                    }
+                }
+            }
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) => {
+                anyhow::bail!("connection closed before the device replied")
+            }
+            Err(_) => return Ok(None),
+        }
+    }
+}
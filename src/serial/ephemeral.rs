@@ -0,0 +1,145 @@
+//! Connections and virtual pairs added at runtime with an `expires_in`,
+//! closed and removed once it runs out so temporary attachments do not
+//! pile up on shared servers.
+
+use crate::config::profiles;
+use crate::config::{
+    ConnectionMode, DataBits, FlowControl, LoggingConfig, Parity, SerialConnectionConfig,
+    SlcanConfig, StopBits, TxBatchConfig,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Longest time a runtime connection or virtual pair may be kept.
+pub const MAX_EXPIRES_IN: Duration = Duration::from_secs(24 * 3600);
+
+/// A connection opened through the API rather than the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ConnectionRequest {
+    pub name: String,
+    pub port: String,
+    /// Device preset filling in the serial settings left out.
+    #[serde(default)]
+    pub profile: Option<String>,
+    #[serde(default)]
+    pub baud_rate: Option<u32>,
+    #[serde(default)]
+    pub data_bits: Option<DataBits>,
+    #[serde(default)]
+    pub stop_bits: Option<StopBits>,
+    #[serde(default)]
+    pub parity: Option<Parity>,
+    #[serde(default)]
+    pub flow_control: Option<FlowControl>,
+    #[serde(default)]
+    pub description: String,
+    /// Shown to clients when they attach.
+    #[serde(default)]
+    pub banner: Option<String>,
+    /// Seconds until the connection is closed and removed.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+impl ConnectionRequest {
+    /// Config for the requested port, without logging or any of the
+    /// optional features. Settings left out come from the `profile`, or
+    /// are 115200 8N1 without one.
+    pub fn config(&self) -> Result<SerialConnectionConfig> {
+        let profile = match &self.profile {
+            Some(name) => Some(
+                profiles::find(name).ok_or_else(|| anyhow::anyhow!("Unknown profile {}", name))?,
+            ),
+            None => None,
+        };
+        Ok(SerialConnectionConfig {
+            name: self.name.clone(),
+            port: self.port.clone(),
+            baud_rate: self
+                .baud_rate
+                .or(profile.map(|p| p.baud_rate))
+                .unwrap_or(115200),
+            data_bits: self
+                .data_bits
+                .or(profile.map(|p| p.data_bits))
+                .unwrap_or(DataBits::Eight),
+            stop_bits: self
+                .stop_bits
+                .or(profile.map(|p| p.stop_bits))
+                .unwrap_or(StopBits::One),
+            parity: self
+                .parity
+                .or(profile.map(|p| p.parity))
+                .unwrap_or(Parity::None),
+            flow_control: self
+                .flow_control
+                .or(profile.map(|p| p.flow_control))
+                .unwrap_or(FlowControl::None),
+            enabled: true,
+            logging: LoggingConfig {
+                enabled: false,
+                path: format!("{}.log", self.name).into(),
+                raw: false,
+                max_file_bytes: None,
+                retention: None,
+                syslog: None,
+                encryption: None,
+                redact: None,
+                ship: None,
+            },
+            description: self.description.clone(),
+            mode: ConnectionMode::Raw,
+            slcan: SlcanConfig::default(),
+            metrics: vec![],
+            notify: vec![],
+            mirror: None,
+            max_tx_rate_bytes_per_sec: None,
+            max_clients: None,
+            tx_batch: TxBatchConfig::default(),
+            suppress_echo: false,
+            idle_close_minutes: None,
+            depends_on: None,
+            startup_delay_ms: 0,
+            power: None,
+            banner: self.banner.clone(),
+            write_lock: None,
+            quota: None,
+            garbage_check: None,
+            keepalive: None,
+            identify: None,
+            rules: vec![],
+            endpoints: vec![],
+            reconnect: None,
+        })
+    }
+}
+
+/// Checks an `expires_in` in seconds.
+pub fn expires_in(seconds: u64) -> Result<Duration> {
+    let duration = Duration::from_secs(seconds);
+    if duration.is_zero() || duration > MAX_EXPIRES_IN {
+        anyhow::bail!(
+            "expires_in must be between 1 and {} seconds",
+            MAX_EXPIRES_IN.as_secs()
+        );
+    }
+    Ok(duration)
+}
+
+/// What an expiry removes.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub(crate) enum Expiring {
+    Connection(String),
+    VirtualPair(String),
+}
+
+pub(crate) struct Expiry {
+    /// Distinguishes successive expiries of the same name.
+    pub id: u64,
+    pub expires_at: DateTime<Utc>,
+    pub task: JoinHandle<()>,
+}
@@ -0,0 +1,129 @@
+//! Faults injected into a connection's I/O, so the resilience of clients
+//! and of the handling of lost ports can be tested in staging. Only built
+//! with the `chaos` feature; other builds run the I/O task without the
+//! hooks.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Longest a write may be held up.
+pub const MAX_WRITE_DELAY_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultSettings {
+    /// Held before each write goes out, stalling the connection's I/O
+    /// as a wedged device would.
+    #[serde(default)]
+    pub write_delay_ms: u64,
+    /// Drop one in every this many received chunks; 0 drops none.
+    #[serde(default)]
+    pub drop_every: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FaultStatus {
+    #[serde(flatten)]
+    pub settings: FaultSettings,
+    /// A read error has been asked for and not happened yet.
+    pub read_error_pending: bool,
+    pub dropped_chunks: u64,
+    pub read_errors: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    settings: Mutex<FaultSettings>,
+    /// Chunks received since `drop_every` was set.
+    received: AtomicU64,
+    dropped: AtomicU64,
+    read_errors: AtomicU64,
+    read_error_pending: AtomicBool,
+    read_error: Notify,
+}
+
+/// The faults in effect on one connection.
+#[derive(Clone, Default)]
+pub struct Faults {
+    inner: Arc<Inner>,
+}
+
+impl Faults {
+    /// Replaces the write delay and drop rate.
+    pub fn set(&self, settings: FaultSettings) -> Result<()> {
+        if settings.write_delay_ms > MAX_WRITE_DELAY_MS {
+            anyhow::bail!("write_delay_ms must be at most {}", MAX_WRITE_DELAY_MS);
+        }
+        *self.inner.settings.lock().unwrap() = settings;
+        self.inner.received.store(0, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Removes every fault, including a read error not yet injected.
+    pub fn clear(&self) {
+        *self.inner.settings.lock().unwrap() = FaultSettings::default();
+        self.inner
+            .read_error_pending
+            .store(false, Ordering::Relaxed);
+    }
+
+    /// Makes the next read fail, or the one in progress, as if the
+    /// device had gone away. The connection is then lost.
+    pub fn fail_next_read(&self) {
+        self.inner.read_error_pending.store(true, Ordering::Relaxed);
+        self.inner.read_error.notify_one();
+    }
+
+    pub fn status(&self) -> FaultStatus {
+        FaultStatus {
+            settings: *self.inner.settings.lock().unwrap(),
+            read_error_pending: self.inner.read_error_pending.load(Ordering::Relaxed),
+            dropped_chunks: self.inner.dropped.load(Ordering::Relaxed),
+            read_errors: self.inner.read_errors.load(Ordering::Relaxed),
+        }
+    }
+
+    /// `read`, unless a read error is asked for first.
+    pub(crate) async fn read<F>(&self, read: F) -> std::io::Result<usize>
+    where
+        F: Future<Output = std::io::Result<usize>>,
+    {
+        tokio::pin!(read);
+        loop {
+            tokio::select! {
+                result = &mut read => return result,
+                _ = self.inner.read_error.notified() => {
+                    // Cleared since it was asked for
+                    if self.inner.read_error_pending.swap(false, Ordering::Relaxed) {
+                        self.inner.read_errors.fetch_add(1, Ordering::Relaxed);
+                        return Err(std::io::Error::other("injected read error"));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Whether to drop a chunk just received.
+    pub(crate) fn drop_chunk(&self) -> bool {
+        let drop_every = self.inner.settings.lock().unwrap().drop_every;
+        if drop_every == 0 {
+            return false;
+        }
+        let received = self.inner.received.fetch_add(1, Ordering::Relaxed) + 1;
+        let drop = received.is_multiple_of(u64::from(drop_every));
+        if drop {
+            self.inner.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+        drop
+    }
+
+    pub(crate) fn write_delay(&self) -> Option<Duration> {
+        let delay = self.inner.settings.lock().unwrap().write_delay_ms;
+        (delay > 0).then(|| Duration::from_millis(delay))
+    }
+}
@@ -0,0 +1,62 @@
+//! Spots received data that looks like the noise of a baud rate mismatch
+//! (`garbage_check`): text consoles send almost only printable ASCII, so a
+//! high share of other bytes usually means the port runs at the wrong speed.
+
+use crate::config::GarbageCheckConfig;
+
+/// Printable ASCII and the control characters terminals commonly send:
+/// bell, backspace, tab, line feed, carriage return and escape.
+fn printable(byte: u8) -> bool {
+    matches!(
+        byte,
+        0x20..=0x7e | 0x07 | 0x08 | b'\t' | b'\n' | b'\r' | 0x1b
+    )
+}
+
+#[derive(Debug)]
+pub struct GarbageDetector {
+    threshold_percent: u8,
+    window_bytes: usize,
+    seen: usize,
+    unprintable: usize,
+    /// Set once a window has been flagged, until a clean one follows.
+    flagged: bool,
+}
+
+impl GarbageDetector {
+    pub fn new(config: &GarbageCheckConfig) -> Self {
+        Self {
+            threshold_percent: config.threshold_percent,
+            window_bytes: config.window_bytes,
+            seen: 0,
+            unprintable: 0,
+            flagged: false,
+        }
+    }
+
+    /// Counts received bytes, returning the share of non-printable ones, in
+    /// percent, when a window reaches the threshold. Later windows are only
+    /// reported again after a clean one.
+    pub fn record(&mut self, data: &[u8]) -> Option<u8> {
+        let mut report = None;
+        for &byte in data {
+            self.seen += 1;
+            if !printable(byte) {
+                self.unprintable += 1;
+            }
+            if self.seen < self.window_bytes {
+                continue;
+            }
+            let percent = (self.unprintable * 100 / self.seen) as u8;
+            self.seen = 0;
+            self.unprintable = 0;
+            if percent < self.threshold_percent {
+                self.flagged = false;
+            } else if !self.flagged {
+                self.flagged = true;
+                report = Some(percent);
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,63 @@
+//! Connection health for `GET /health` with `Accept: application/json`.
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HealthState {
+    Connected,
+    /// Up, but not fully usable: leased out, or with an alert firing.
+    Degraded,
+    /// The port was lost.
+    Disconnected,
+}
+
+/// A connection that is not [`HealthState::Connected`], and why.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ConnectionHealth {
+    pub name: String,
+    pub state: HealthState,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OverallHealth {
+    Ok,
+    Degraded,
+    /// Every connection is disconnected.
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HealthReport {
+    pub status: OverallHealth,
+    pub connected: usize,
+    pub degraded: usize,
+    pub disconnected: usize,
+    /// Degraded and disconnected connections, by name.
+    pub problems: Vec<ConnectionHealth>,
+}
+
+impl HealthReport {
+    pub fn new(connected: usize, mut problems: Vec<ConnectionHealth>) -> Self {
+        problems.sort_by(|a, b| a.name.cmp(&b.name));
+        let count = |state| problems.iter().filter(|p| p.state == state).count();
+        let degraded = count(HealthState::Degraded);
+        let disconnected = count(HealthState::Disconnected);
+        let status = if connected + degraded == 0 && disconnected > 0 {
+            OverallHealth::Down
+        } else if problems.is_empty() {
+            OverallHealth::Ok
+        } else {
+            OverallHealth::Degraded
+        };
+        Self {
+            status,
+            connected,
+            degraded,
+            disconnected,
+            problems,
+        }
+    }
+}
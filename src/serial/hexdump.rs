@@ -0,0 +1,38 @@
+//! Canonical hex+ASCII dumps, as printed by `hexdump -C`.
+
+use std::fmt::Write;
+
+/// Formats `data` as lines of 16 bytes: the offset, the bytes in hex in
+/// two groups of 8, and the printable ones between bars, ending with a
+/// line holding the length. Repeated lines are printed in full rather
+/// than squeezed into `*`.
+pub fn canonical(data: &[u8]) -> String {
+    let mut dump = String::new();
+    for (line, chunk) in data.chunks(16).enumerate() {
+        let _ = write!(dump, "{:08x} ", line * 16);
+        for i in 0..16 {
+            if i % 8 == 0 {
+                dump.push(' ');
+            }
+            match chunk.get(i) {
+                Some(byte) => {
+                    let _ = write!(dump, "{:02x} ", byte);
+                }
+                None => dump.push_str("   "),
+            }
+        }
+        dump.push_str(" |");
+        dump.extend(chunk.iter().map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        }));
+        dump.push_str("|\n");
+    }
+    if !data.is_empty() {
+        let _ = writeln!(dump, "{:08x}", data.len());
+    }
+    dump
+}
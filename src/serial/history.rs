@@ -0,0 +1,128 @@
+//! Recent traffic kept in memory, one record per frame.
+
+use super::Direction;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Frames kept per connection, and the most a tail can return.
+pub const MAX_FRAMES: usize = 1000;
+
+/// Most bytes a hexdump can return.
+pub const MAX_HEXDUMP_BYTES: usize = 65536;
+
+/// Longest frame kept whole; longer runs without a terminator are cut.
+pub(super) const MAX_FRAME_BYTES: usize = 4096;
+
+#[derive(Debug, Clone)]
+struct Frame {
+    /// When the first byte of the frame was seen.
+    at: DateTime<Utc>,
+    direction: Direction,
+    data: Vec<u8>,
+}
+
+/// A frame as returned by the tail endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrafficRecord {
+    pub timestamp: DateTime<Utc>,
+    pub direction: Direction,
+    /// The bytes as UTF-8, with invalid sequences replaced.
+    pub text: String,
+    pub hex: String,
+    /// Set on the frame still being received, which has no terminator yet.
+    pub partial: bool,
+}
+
+impl TrafficRecord {
+    fn new(frame: &Frame, partial: bool) -> Self {
+        Self {
+            timestamp: frame.at,
+            direction: frame.direction,
+            text: String::from_utf8_lossy(&frame.data).into_owned(),
+            hex: hex::encode(&frame.data),
+            partial,
+        }
+    }
+}
+
+/// The last [`MAX_FRAMES`] frames in each direction combined, split on the
+/// same terminator as the throughput counters.
+#[derive(Debug)]
+pub struct TrafficHistory {
+    frame_end: u8,
+    frames: VecDeque<Frame>,
+    /// Unterminated frames, received and sent.
+    pending: [Option<Frame>; 2],
+}
+
+impl TrafficHistory {
+    pub fn new(frame_end: u8) -> Self {
+        Self {
+            frame_end,
+            frames: VecDeque::new(),
+            pending: [None, None],
+        }
+    }
+
+    pub fn record(&mut self, direction: Direction, data: &[u8], at: DateTime<Utc>) {
+        let slot = direction as usize;
+        for chunk in data.split_inclusive(|&b| b == self.frame_end) {
+            let frame = self.pending[slot].get_or_insert_with(|| Frame {
+                at,
+                direction,
+                data: Vec::new(),
+            });
+            frame.data.extend_from_slice(chunk);
+            if chunk.ends_with(&[self.frame_end]) || frame.data.len() >= MAX_FRAME_BYTES {
+                let frame = self.pending[slot].take().expect("inserted above");
+                if self.frames.len() == MAX_FRAMES {
+                    self.frames.pop_front();
+                }
+                self.frames.push_back(frame);
+            }
+        }
+    }
+
+    /// The last `lines` frames, oldest first, optionally in one direction
+    /// only. Frames still being received come last.
+    pub fn tail(&self, lines: usize, direction: Option<Direction>) -> Vec<TrafficRecord> {
+        let wanted = |frame: &&Frame| direction.is_none_or(|d| frame.direction == d);
+        let complete = self
+            .frames
+            .iter()
+            .filter(wanted)
+            .map(|frame| TrafficRecord::new(frame, false));
+        let partial = self
+            .pending
+            .iter()
+            .flatten()
+            .filter(wanted)
+            .map(|frame| TrafficRecord::new(frame, true));
+        let records: Vec<TrafficRecord> = complete.chain(partial).collect();
+        let skip = records.len().saturating_sub(lines);
+        records.into_iter().skip(skip).collect()
+    }
+
+    /// The last `bytes` bytes kept in `direction`, including those of the
+    /// frame still being received.
+    pub fn recent_bytes(&self, bytes: usize, direction: Direction) -> Vec<u8> {
+        let frames = self
+            .frames
+            .iter()
+            .chain(self.pending[direction as usize].iter())
+            .filter(|frame| frame.direction == direction);
+        let mut recent = Vec::new();
+        let mut total = 0;
+        for frame in frames.rev() {
+            if total >= bytes {
+                break;
+            }
+            total += frame.data.len();
+            recent.push(frame.data.as_slice());
+        }
+        let mut data: Vec<u8> = recent.into_iter().rev().flatten().copied().collect();
+        data.drain(..data.len().saturating_sub(bytes));
+        data
+    }
+}
@@ -0,0 +1,151 @@
+//! Finding out what is attached to a port when its connection opens
+//! (`identify`), so listings can show e.g. `PLC-5000 3.2.1` next to it.
+
+use super::{discovery, SerialConnection};
+use crate::config::IdentifyConfig;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::{timeout_at, Instant};
+
+pub const MAX_IDENTIFY_TIMEOUT_MS: u64 = 60_000;
+
+/// Longest unterminated reply line kept while waiting for a match.
+const MAX_LINE_BYTES: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum IdentitySource {
+    /// The device's reply to the configured command.
+    Probe,
+    /// The adapter's USB descriptor.
+    Usb,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceIdentity {
+    pub model: Option<String>,
+    pub firmware: Option<String>,
+    pub source: IdentitySource,
+    pub identified_at: DateTime<Utc>,
+}
+
+/// Checks `config` and compiles its pattern, if it has one.
+pub fn compile(config: &IdentifyConfig) -> Result<Option<Regex>> {
+    if config.timeout_ms == 0 || config.timeout_ms > MAX_IDENTIFY_TIMEOUT_MS {
+        anyhow::bail!(
+            "timeout_ms must be between 1 and {}",
+            MAX_IDENTIFY_TIMEOUT_MS
+        );
+    }
+    if config.command.as_deref() == Some("") {
+        anyhow::bail!("command must not be empty");
+    }
+    let Some(ref pattern) = config.pattern else {
+        if config.command.is_some() {
+            anyhow::bail!("command needs a pattern");
+        }
+        return Ok(None);
+    };
+    let regex = Regex::new(pattern).map_err(|e| anyhow::anyhow!("invalid pattern: {}", e))?;
+    if !regex
+        .capture_names()
+        .flatten()
+        .any(|name| name == "model" || name == "firmware")
+    {
+        anyhow::bail!("pattern must contain a `model` or `firmware` group");
+    }
+    Ok(Some(regex))
+}
+
+/// The identity in `text`, if `regex` matches it.
+pub fn extract(regex: &Regex, text: &str, source: IdentitySource) -> Option<DeviceIdentity> {
+    let captures = regex.captures(text)?;
+    let group = |name: &str| {
+        captures
+            .name(name)
+            .map(|group| group.as_str().trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+    Some(DeviceIdentity {
+        model: group("model"),
+        firmware: group("firmware"),
+        source,
+        identified_at: Utc::now(),
+    })
+}
+
+/// Asks the device on `connection` what it is, as `config` says.
+pub async fn probe(
+    connection: &SerialConnection,
+    config: &IdentifyConfig,
+) -> Result<DeviceIdentity> {
+    let regex = compile(config)?;
+    let timeout = Duration::from_millis(config.timeout_ms);
+    match (config.command.as_deref(), regex) {
+        (Some(command), Some(regex)) => {
+            // Held until the reply is in, so no other writes get a response
+            // mixed into it
+            let transaction = connection.transaction().await?;
+            let mut rx = connection.subscribe();
+            transaction.write(command.as_bytes()).await?;
+            wait_for_match(&mut rx, &regex, Instant::now() + timeout).await
+        }
+        (_, regex) => {
+            let ports = discovery::list_ports().await?;
+            let description = discovery::find_port(&ports, &connection.config().port)
+                .and_then(|port| port.description.clone())
+                .ok_or_else(|| anyhow::anyhow!("no USB descriptor for this port"))?;
+            match regex {
+                Some(regex) => {
+                    extract(&regex, &description, IdentitySource::Usb).ok_or_else(|| {
+                        anyhow::anyhow!("USB descriptor {} does not match the pattern", description)
+                    })
+                }
+                None => Ok(DeviceIdentity {
+                    model: Some(description),
+                    firmware: None,
+                    source: IdentitySource::Usb,
+                    identified_at: Utc::now(),
+                }),
+            }
+        }
+    }
+}
+
+/// Matches `regex` against each received line until one matches or
+/// `deadline` passes.
+async fn wait_for_match(
+    rx: &mut broadcast::Receiver<Vec<u8>>,
+    regex: &Regex,
+    deadline: Instant,
+) -> Result<DeviceIdentity> {
+    let mut line: Vec<u8> = Vec::new();
+    loop {
+        match timeout_at(deadline, rx.recv()).await {
+            Ok(Ok(data)) => {
+                for &byte in &data {
+                    if byte != b'\n' && byte != b'\r' {
+                        if line.len() < MAX_LINE_BYTES {
+                            line.push(byte);
+                        }
+                        continue;
+                    }
+                    let text = String::from_utf8_lossy(&line).into_owned();
+                    line.clear();
+                    if let Some(identity) = extract(regex, &text, IdentitySource::Probe) {
+                        return Ok(identity);
+                    }
+                }
+            }
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) => {
+                anyhow::bail!("connection closed before the device replied")
+            }
+            Err(_) => anyhow::bail!("no reply matched the pattern in time"),
+        }
+    }
+}
@@ -0,0 +1,159 @@
+//! Round-trip measurements through a device that echoes what it receives
+//! (a TX-RX jumper, loopback plug or echo firmware).
+
+use super::SerialConnection;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::time::{timeout_at, Instant};
+
+pub const MAX_PROBES: u32 = 1000;
+pub const MAX_PROBE_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LatencyTestOptions {
+    #[serde(default = "default_count")]
+    pub count: u32,
+    /// How long to wait for each probe to come back.
+    #[serde(default = "default_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Pause between probes.
+    #[serde(default)]
+    pub interval_ms: u64,
+}
+
+impl Default for LatencyTestOptions {
+    fn default() -> Self {
+        Self {
+            count: default_count(),
+            timeout_ms: default_timeout_ms(),
+            interval_ms: 0,
+        }
+    }
+}
+
+fn default_count() -> u32 {
+    10
+}
+
+fn default_timeout_ms() -> u64 {
+    1000
+}
+
+impl LatencyTestOptions {
+    pub fn validate(&self) -> Result<()> {
+        if self.count == 0 || self.count > MAX_PROBES {
+            anyhow::bail!("count must be between 1 and {}", MAX_PROBES);
+        }
+        if self.timeout_ms == 0 || self.timeout_ms > MAX_PROBE_TIMEOUT_MS {
+            anyhow::bail!("timeout_ms must be between 1 and {}", MAX_PROBE_TIMEOUT_MS);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LatencyReport {
+    pub sent: u32,
+    pub received: u32,
+    pub lost: u32,
+    pub min_ms: Option<f64>,
+    pub avg_ms: Option<f64>,
+    pub p50_ms: Option<f64>,
+    pub p95_ms: Option<f64>,
+    pub p99_ms: Option<f64>,
+    pub max_ms: Option<f64>,
+    /// Round-trip time of each probe in order, `null` for lost ones.
+    pub samples_ms: Vec<Option<f64>>,
+}
+
+/// Nearest-rank percentile of an ascending slice.
+pub(super) fn percentile(sorted: &[f64], pct: f64) -> Option<f64> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+impl LatencyReport {
+    pub fn from_samples(samples_ms: Vec<Option<f64>>) -> Self {
+        let mut sorted: Vec<f64> = samples_ms.iter().flatten().copied().collect();
+        sorted.sort_by(f64::total_cmp);
+
+        let sent = samples_ms.len() as u32;
+        let received = sorted.len() as u32;
+        Self {
+            sent,
+            received,
+            lost: sent - received,
+            min_ms: sorted.first().copied(),
+            avg_ms: (!sorted.is_empty()).then(|| sorted.iter().sum::<f64>() / sorted.len() as f64),
+            p50_ms: percentile(&sorted, 50.0),
+            p95_ms: percentile(&sorted, 95.0),
+            p99_ms: percentile(&sorted, 99.0),
+            max_ms: sorted.last().copied(),
+            samples_ms,
+        }
+    }
+}
+
+/// Waits until `probe` shows up in the received stream.
+pub(super) async fn wait_for_echo(
+    rx: &mut broadcast::Receiver<Vec<u8>>,
+    probe: &[u8],
+    deadline: Instant,
+) -> bool {
+    let mut seen: Vec<u8> = Vec::new();
+    loop {
+        match timeout_at(deadline, rx.recv()).await {
+            Ok(Ok(data)) => {
+                seen.extend_from_slice(&data);
+                if seen.windows(probe.len()).any(|window| window == probe) {
+                    return true;
+                }
+                // Only a partial probe can still complete
+                let keep = seen.len().min(probe.len() - 1);
+                seen.drain(..seen.len() - keep);
+            }
+            Ok(Err(broadcast::error::RecvError::Lagged(_))) => continue,
+            Ok(Err(broadcast::error::RecvError::Closed)) | Err(_) => return false,
+        }
+    }
+}
+
+/// Sends `options.count` uniquely tagged probes one at a time and times how
+/// long each takes to come back.
+pub async fn run(
+    connection: &SerialConnection,
+    options: &LatencyTestOptions,
+) -> Result<LatencyReport> {
+    options.validate()?;
+
+    let test_id = chrono::Utc::now().timestamp_micros();
+    let mut rx = connection.subscribe();
+    let mut samples = Vec::with_capacity(options.count as usize);
+
+    for seq in 0..options.count {
+        if seq > 0 && options.interval_ms > 0 {
+            tokio::time::sleep(Duration::from_millis(options.interval_ms)).await;
+        }
+
+        // Drop anything that arrived since the previous probe
+        rx = rx.resubscribe();
+
+        let probe = format!("webmux-probe-{:x}-{}\n", test_id, seq).into_bytes();
+        let started = Instant::now();
+        connection.send(&probe).await?;
+
+        let deadline = started + Duration::from_millis(options.timeout_ms);
+        samples.push(
+            wait_for_echo(&mut rx, &probe, deadline)
+                .await
+                .then(|| started.elapsed().as_secs_f64() * 1000.0),
+        );
+    }
+
+    Ok(LatencyReport::from_samples(samples))
+}
@@ -0,0 +1,164 @@
+use crate::config::SerialConnectionConfig;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_serial::SerialStream;
+use tracing::{error, info, warn};
+
+use super::connection::open_port;
+
+/// Exclusive access to a connection's port.
+///
+/// Handed out by [`SerialConnection::acquire_exclusive`](super::SerialConnection::acquire_exclusive);
+/// the port goes back to the connection's I/O task when the lease is dropped.
+pub struct PortLease {
+    port: Option<SerialStream>,
+    return_tx: Option<oneshot::Sender<SerialStream>>,
+}
+
+impl PortLease {
+    pub(crate) fn new(port: SerialStream, return_tx: oneshot::Sender<SerialStream>) -> Self {
+        Self {
+            port: Some(port),
+            return_tx: Some(return_tx),
+        }
+    }
+
+    /// Closes the OS handle so other programs can open the device.
+    pub fn close(&mut self) {
+        self.port = None;
+    }
+
+    /// Reopens a port previously released with [`close`](Self::close).
+    pub fn reopen(&mut self, config: &SerialConnectionConfig) -> Result<()> {
+        if self.port.is_none() {
+            self.port = Some(open_port(config)?);
+        }
+        Ok(())
+    }
+}
+
+impl Deref for PortLease {
+    type Target = SerialStream;
+
+    fn deref(&self) -> &Self::Target {
+        self.port.as_ref().expect("port is open while leased")
+    }
+}
+
+impl DerefMut for PortLease {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.port.as_mut().expect("port is open while leased")
+    }
+}
+
+impl Drop for PortLease {
+    fn drop(&mut self) {
+        // A port that was closed and never reopened is reported as lost by
+        // the I/O task when the return channel is dropped.
+        if let (Some(port), Some(return_tx)) = (self.port.take(), self.return_tx.take()) {
+            let _ = return_tx.send(port);
+        }
+    }
+}
+
+/// Longest time an external tool may borrow a port.
+pub const MAX_LEASE_DURATION: Duration = Duration::from_secs(3600);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LeaseMode {
+    /// Close the OS handle so the tool can open the device path itself.
+    #[default]
+    Release,
+    /// Keep the port open and proxy it over a temporary raw TCP socket.
+    Tcp,
+}
+
+/// A timed lease held by an external tool.
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaseInfo {
+    pub connection: String,
+    pub mode: LeaseMode,
+    pub expires_at: DateTime<Utc>,
+    /// Device path to open, for `release` leases.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device: Option<String>,
+    /// Address to connect to, for `tcp` leases.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_address: Option<String>,
+}
+
+pub(crate) struct ActiveLease {
+    /// Distinguishes successive leases on the same connection.
+    pub id: u64,
+    pub info: LeaseInfo,
+    pub cancel_tx: oneshot::Sender<()>,
+}
+
+/// Runs a timed lease until it expires or is cancelled, then hands the port back.
+pub(crate) async fn run_lease(
+    mut lease: PortLease,
+    config: SerialConnectionConfig,
+    listener: Option<TcpListener>,
+    duration: Duration,
+    mut cancel_rx: oneshot::Receiver<()>,
+) {
+    let expiry = tokio::time::sleep(duration);
+    tokio::pin!(expiry);
+
+    match listener {
+        None => {
+            tokio::select! {
+                _ = &mut expiry => info!("Lease on {} expired", config.name),
+                _ = &mut cancel_rx => info!("Lease on {} released", config.name),
+            }
+
+            if let Err(e) = lease.reopen(&config) {
+                error!("Failed to reopen {} after lease: {}", config.port, e);
+            }
+        }
+        Some(listener) => loop {
+            tokio::select! {
+                _ = &mut expiry => {
+                    info!("Lease on {} expired", config.name);
+                    break;
+                }
+                _ = &mut cancel_rx => {
+                    info!("Lease on {} released", config.name);
+                    break;
+                }
+                accepted = listener.accept() => {
+                    let (mut socket, peer) = match accepted {
+                        Ok(accepted) => accepted,
+                        Err(e) => {
+                            warn!("Lease listener for {} failed: {}", config.name, e);
+                            continue;
+                        }
+                    };
+                    info!("Lease client {} attached to {}", peer, config.name);
+
+                    // One client at a time; the lease may still be cut short mid-session
+                    let done = tokio::select! {
+                        result = tokio::io::copy_bidirectional(&mut socket, &mut *lease) => {
+                            if let Err(e) = result {
+                                warn!("Lease proxy for {} ended: {}", config.name, e);
+                            }
+                            false
+                        }
+                        _ = &mut expiry => true,
+                        _ = &mut cancel_rx => true,
+                    };
+                    info!("Lease client {} detached from {}", peer, config.name);
+                    if done {
+                        break;
+                    }
+                }
+            }
+        },
+    }
+}
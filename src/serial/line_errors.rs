@@ -0,0 +1,133 @@
+//! Framing, parity and overrun errors counted by the serial driver, read
+//! where the OS exposes them, so problems on the wire (a wrong baud rate,
+//! noise, a bad ground) can be told apart from garbage the device sent.
+
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tokio_serial::SerialStream;
+
+/// Shortest time between two notifications about a connection's errors.
+pub const LINE_ERROR_REPORT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct LineErrors {
+    pub framing: u64,
+    pub parity: u64,
+    /// Bytes lost because the UART's or the driver's buffer was full.
+    pub overrun: u64,
+}
+
+impl LineErrors {
+    /// Errors counted since the `earlier` reading of the same counters.
+    pub fn since(&self, earlier: &LineErrors) -> LineErrors {
+        LineErrors {
+            framing: self.framing.saturating_sub(earlier.framing),
+            parity: self.parity.saturating_sub(earlier.parity),
+            overrun: self.overrun.saturating_sub(earlier.overrun),
+        }
+    }
+
+    pub fn add(&mut self, other: &LineErrors) {
+        self.framing += other.framing;
+        self.parity += other.parity;
+        self.overrun += other.overrun;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.framing + self.parity + self.overrun
+    }
+
+    /// `3 framing, 0 parity and 1 overrun errors`
+    pub fn describe(&self) -> String {
+        format!(
+            "{} framing, {} parity and {} overrun errors",
+            self.framing, self.parity, self.overrun
+        )
+    }
+}
+
+/// The driver's counters for `port`, which start at zero when it is
+/// opened, or `None` if the driver keeps none (as for pseudo-terminals).
+#[cfg(target_os = "linux")]
+pub fn read(port: &SerialStream) -> Option<LineErrors> {
+    use std::os::unix::io::AsRawFd;
+
+    /// `struct serial_icounter_struct` from `<linux/serial.h>`.
+    #[repr(C)]
+    #[derive(Default)]
+    struct SerialIcounter {
+        cts: libc::c_int,
+        dsr: libc::c_int,
+        rng: libc::c_int,
+        dcd: libc::c_int,
+        rx: libc::c_int,
+        tx: libc::c_int,
+        frame: libc::c_int,
+        overrun: libc::c_int,
+        parity: libc::c_int,
+        brk: libc::c_int,
+        buf_overrun: libc::c_int,
+        reserved: [libc::c_int; 9],
+    }
+
+    let mut counts = SerialIcounter::default();
+    // SAFETY: TIOCGICOUNT writes one serial_icounter_struct, which `counts`
+    // matches in layout and outlives the call
+    if unsafe { libc::ioctl(port.as_raw_fd(), libc::TIOCGICOUNT, &mut counts) } < 0 {
+        return None;
+    }
+    let count = |value: libc::c_int| value.max(0) as u64;
+    Some(LineErrors {
+        framing: count(counts.frame),
+        parity: count(counts.parity),
+        overrun: count(counts.overrun) + count(counts.buf_overrun),
+    })
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn read(_port: &SerialStream) -> Option<LineErrors> {
+    None
+}
+
+/// Adds up readings of a port's counters across reopens, and decides when
+/// new errors are worth telling the connection's channels about.
+#[derive(Debug, Default)]
+pub struct LineErrorMonitor {
+    /// The driver's counters at the last reading.
+    last: LineErrors,
+    total: LineErrors,
+    /// Errors since the last report.
+    unreported: LineErrors,
+    last_report: Option<Instant>,
+}
+
+impl LineErrorMonitor {
+    /// Errors counted since the connection opened.
+    pub fn total(&self) -> LineErrors {
+        self.total
+    }
+
+    /// The port was opened again, restarting the driver's counters.
+    pub fn reopened(&mut self) {
+        self.last = LineErrors::default();
+    }
+
+    /// Takes a reading of the driver's counters, returning the errors to
+    /// report if there are any and the last report was at least
+    /// `LINE_ERROR_REPORT_INTERVAL` ago.
+    pub fn update(&mut self, reading: LineErrors, now: Instant) -> Option<LineErrors> {
+        let new = reading.since(&self.last);
+        self.last = reading;
+        self.total.add(&new);
+        self.unreported.add(&new);
+        if self.unreported.total() == 0
+            || self
+                .last_report
+                .is_some_and(|at| now.duration_since(at) < LINE_ERROR_REPORT_INTERVAL)
+        {
+            return None;
+        }
+        self.last_report = Some(now);
+        Some(std::mem::take(&mut self.unreported))
+    }
+}
@@ -0,0 +1,164 @@
+//! Copies a connection's traffic to a secondary sink (`mirror:`), so a
+//! protocol analyzer can follow along without a WebSocket subscription.
+
+use super::{SerialConnection, SerialData};
+use crate::config::{MirrorConfig, MirrorDirection, MirrorTarget};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Weak;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, RwLock};
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+/// Chunks buffered before new traffic is dropped.
+const QUEUE_SIZE: usize = 1024;
+
+/// Pause before reopening a sink after a failure.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+pub(crate) type Peers = Weak<RwLock<HashMap<String, SerialConnection>>>;
+
+/// Handle the I/O task pushes traffic into; delivery happens on a
+/// background task so a slow sink never stalls the port.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    tx: mpsc::Sender<SerialData>,
+    direction: MirrorDirection,
+    connection: String,
+}
+
+impl Mirror {
+    /// Starts delivering to `config.target`. Connection targets are looked
+    /// up in `peers` on every write, so they may come and go.
+    pub(crate) fn spawn(connection: &str, config: &MirrorConfig, peers: Peers) -> Self {
+        let (tx, rx) = mpsc::channel(QUEUE_SIZE);
+        tokio::spawn(run(config.target.clone(), rx, peers));
+        Self {
+            tx,
+            direction: config.direction,
+            connection: connection.to_string(),
+        }
+    }
+
+    pub fn received(&self, data: &[u8]) {
+        if self.direction.includes_rx() {
+            self.push(data);
+        }
+    }
+
+    pub fn sent(&self, data: &[u8]) {
+        if self.direction.includes_tx() {
+            self.push(data);
+        }
+    }
+
+    fn push(&self, data: &[u8]) {
+        if self.tx.try_send(data.to_vec()).is_err() {
+            debug!(
+                "Mirror queue for {} is full, dropping {} bytes",
+                self.connection,
+                data.len()
+            );
+        }
+    }
+}
+
+enum Sink {
+    File(tokio::fs::File),
+    Tcp(TcpStream),
+}
+
+async fn open(target: &MirrorTarget) -> Result<Sink> {
+    match target {
+        MirrorTarget::File { path } => {
+            if let Some(parent) = path.parent() {
+                tokio::fs::create_dir_all(parent).await?;
+            }
+            let file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            Ok(Sink::File(file))
+        }
+        MirrorTarget::Tcp { address } => {
+            let stream = TcpStream::connect(address).await?;
+            stream.set_nodelay(true)?;
+            Ok(Sink::Tcp(stream))
+        }
+        MirrorTarget::Connection { connection } => {
+            anyhow::bail!("Mirror to connection {} has no sink to open", connection)
+        }
+    }
+}
+
+async fn write(sink: &mut Sink, data: &[u8]) -> Result<()> {
+    match sink {
+        Sink::File(file) => {
+            file.write_all(data).await?;
+            file.flush().await?;
+        }
+        Sink::Tcp(stream) => stream.write_all(data).await?,
+    }
+    Ok(())
+}
+
+fn describe(target: &MirrorTarget) -> String {
+    match target {
+        MirrorTarget::File { path } => path.display().to_string(),
+        MirrorTarget::Tcp { address } => address.clone(),
+        MirrorTarget::Connection { connection } => connection.clone(),
+    }
+}
+
+/// Delivers queued traffic, reopening the sink after failures. Data that
+/// cannot be delivered is dropped.
+async fn run(target: MirrorTarget, mut rx: mpsc::Receiver<SerialData>, peers: Peers) {
+    if let MirrorTarget::Connection { ref connection } = target {
+        while let Some(data) = rx.recv().await {
+            let Some(peers) = peers.upgrade() else {
+                break;
+            };
+            let peer = peers.read().await.get(connection).cloned();
+            match peer {
+                Some(peer) => {
+                    if let Err(e) = peer.send(&data).await {
+                        debug!("Failed to mirror to {}: {}", connection, e);
+                    }
+                }
+                None => debug!("Mirror target {} is not running", connection),
+            }
+        }
+        return;
+    }
+
+    let mut sink: Option<Sink> = None;
+    let mut retry_at = Instant::now();
+
+    while let Some(data) = rx.recv().await {
+        if sink.is_none() {
+            if Instant::now() < retry_at {
+                continue;
+            }
+            match open(&target).await {
+                Ok(opened) => sink = Some(opened),
+                Err(e) => {
+                    warn!("Failed to open mirror {}: {}", describe(&target), e);
+                    retry_at = Instant::now() + RETRY_DELAY;
+                    continue;
+                }
+            }
+        }
+
+        if let Some(ref mut opened) = sink {
+            if let Err(e) = write(opened, &data).await {
+                warn!("Failed to write to mirror {}: {}", describe(&target), e);
+                sink = None;
+                retry_at = Instant::now() + RETRY_DELAY;
+            }
+        }
+    }
+}
@@ -1,53 +1,571 @@
-use crate::config::SerialConnectionConfig;
+use crate::config::drift::{self, ConfigDiff, ConfigFiles};
+use crate::config::{QuotaConfig, SerialConnectionConfig, SnifferConfig};
+use crate::logging::retention::{self, LogUsage};
+use crate::logging::ship;
+use crate::notifications::Notifier;
+use crate::telemetry::histogram::Histogram;
+use crate::telemetry::{MetricSummary, Sample};
 use anyhow::Result;
+use capture::Capture;
+use chrono::{DateTime, Utc};
+use ephemeral::{Expiring, Expiry};
+use lease::ActiveLease;
+use quota::{Quota, Quotas};
+use sniffer::Sniffer;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use tokio::sync::{broadcast, RwLock};
-use tracing::info;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, watch, RwLock};
+use tracing::{info, warn};
 
+pub mod annotations;
+pub mod batching;
+pub mod bulk;
+pub mod capture;
+pub mod capture_jobs;
+pub mod clock;
 pub mod connection;
-pub use connection::SerialConnection;
+pub mod discovery;
+pub mod endpoints;
+pub mod ephemeral;
+#[cfg(feature = "chaos")]
+pub mod faults;
+pub mod garbage;
+pub mod health;
+pub mod hexdump;
+pub mod history;
+pub mod identify;
+pub mod latency;
+pub mod lease;
+pub mod line_errors;
+pub mod mirror;
+pub mod pacing;
+pub mod port_groups;
+pub mod power;
+pub mod quota;
+pub mod reconnect;
+pub mod replay;
+pub mod rules;
+pub mod sequence;
+pub mod snapshot;
+pub mod sniffer;
+pub mod startup;
+pub mod throughput;
+pub mod traffic_profile;
+pub mod virtual_pair;
+pub use annotations::{Annotation, AnnotationRequest};
+pub use capture::{CaptureBundle, CaptureInfo, CaptureRequest};
+pub use connection::{Direction, Priority, SerialConnection, Traffic};
+pub use discovery::PortDescription;
+pub use endpoints::EndpointReading;
+pub use ephemeral::ConnectionRequest;
+pub use health::{ConnectionHealth, HealthReport, HealthState, OverallHealth};
+pub use history::TrafficRecord;
+pub use identify::DeviceIdentity;
+pub use latency::{LatencyReport, LatencyTestOptions};
+pub use lease::{LeaseInfo, LeaseMode, PortLease};
+pub use mirror::Mirror;
+pub use port_groups::{PortGroup, RestartReport};
+pub use power::PowerAction;
+pub use quota::QuotaUsage;
+pub use reconnect::ReconnectStats;
+pub use replay::{FramePage, FrameRecord, Replay, ReplayGap};
+pub use sequence::{SequenceReport, SequenceStep};
+pub use snapshot::{ImportFailure, ImportReport};
+pub use sniffer::{SnifferInfo, SnifferRecord};
+use startup::{startup_delay, AbortOnDrop};
+pub use startup::{FailedConnection, StartupConnection, StartupLog, StartupReport, StartupState};
+pub use throughput::Throughput;
+pub use traffic_profile::TrafficProfile;
+pub use virtual_pair::{VirtualPairInfo, VirtualPairRequest};
 
 pub type SerialData = Vec<u8>;
 
+static NEXT_LEASE_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_CAPTURE_ID: AtomicU64 = AtomicU64::new(1);
+static NEXT_EXPIRY_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Returned by [`SerialManager::add_connection`] when a connection is
+/// already open under the name.
+#[derive(Debug)]
+pub struct ConnectionExists(pub String);
+
+impl std::fmt::Display for ConnectionExists {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Connection {} already exists", self.0)
+    }
+}
+
+impl std::error::Error for ConnectionExists {}
+
 #[derive(Clone)]
 pub struct SerialManager {
     connections: Arc<RwLock<HashMap<String, SerialConnection>>>,
+    leases: Arc<RwLock<HashMap<String, ActiveLease>>>,
+    virtual_pairs: Arc<RwLock<HashMap<String, VirtualPairInfo>>>,
+    captures: Arc<RwLock<HashMap<u64, Capture>>>,
+    sniffers: Arc<RwLock<HashMap<String, Sniffer>>>,
+    expiries: Arc<RwLock<HashMap<Expiring, Expiry>>>,
+    notifier: Notifier,
+    /// Shared by all connections.
+    quota: Option<Arc<Quota>>,
+    /// Where the configured connections came from, for drift checks.
+    config_files: Option<Arc<ConfigFiles>>,
+    /// How opening the configured connections went.
+    startup: StartupLog,
+    /// Configured connections that could not be opened, by name.
+    failed: Arc<RwLock<HashMap<String, FailedConnection>>>,
+    /// Connections configured with `enabled: false` or disabled through the
+    /// API, by name, kept so they can be enabled again.
+    disabled: Arc<RwLock<HashMap<String, SerialConnectionConfig>>>,
+}
+
+impl Default for SerialManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl SerialManager {
     pub fn new() -> Self {
+        Self::with_notifier(Notifier::default())
+    }
+
+    /// Creates a manager whose connections report incidents through `notifier`.
+    pub fn with_notifier(notifier: Notifier) -> Self {
         Self {
             connections: Arc::new(RwLock::new(HashMap::new())),
+            leases: Arc::new(RwLock::new(HashMap::new())),
+            virtual_pairs: Arc::new(RwLock::new(HashMap::new())),
+            captures: Arc::new(RwLock::new(HashMap::new())),
+            sniffers: Arc::new(RwLock::new(HashMap::new())),
+            expiries: Arc::new(RwLock::new(HashMap::new())),
+            notifier,
+            quota: None,
+            config_files: None,
+            startup: StartupLog::default(),
+            failed: Arc::new(RwLock::new(HashMap::new())),
+            disabled: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// Counts the traffic of connections added from now on against a
+    /// server-wide quota.
+    pub fn with_quota(mut self, config: QuotaConfig) -> Self {
+        self.quota = Some(Arc::new(Quota::new(None, config)));
+        self
+    }
+
+    /// Compares running connections with `files` on request and from
+    /// [`Self::spawn_drift_check`].
+    pub fn with_config_files(mut self, files: ConfigFiles) -> Self {
+        self.config_files = Some(Arc::new(files));
+        self
+    }
+
+    pub fn config_files(&self) -> Option<&ConfigFiles> {
+        self.config_files.as_deref()
+    }
+
+    /// What restarting from the configuration files would change.
+    pub async fn config_diff(&self) -> Result<ConfigDiff> {
+        let files = self
+            .config_files
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Not started from a configuration file"))?;
+        let configured = files.connections()?;
+        let running: Vec<SerialConnectionConfig> = self
+            .connections
+            .read()
+            .await
+            .values()
+            .map(|connection| connection.config().clone())
+            .collect();
+        Ok(drift::diff(&configured, &running))
+    }
+
+    /// Periodically compares running connections with the configuration
+    /// files, logging whenever the difference changes.
+    pub fn spawn_drift_check(&self) {
+        if self.config_files.is_none() {
+            return;
         }
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(drift::DRIFT_CHECK_INTERVAL);
+            let mut last: Option<std::result::Result<ConfigDiff, String>> = None;
+            loop {
+                interval.tick().await;
+                let current = manager.config_diff().await.map_err(|e| e.to_string());
+                if last.as_ref() == Some(&current) {
+                    continue;
+                }
+                match current {
+                    Ok(ref diff) if diff.is_empty() => {
+                        if last.is_some() {
+                            info!("Running connections match the configuration again");
+                        }
+                    }
+                    Ok(ref diff) => warn!(
+                        "Running connections differ from the configuration ({}); see GET /api/config/diff",
+                        diff.summary()
+                    ),
+                    Err(ref e) => warn!("Cannot compare with the configuration: {}", e),
+                }
+                last = Some(current);
+            }
+        });
+    }
+
+    /// Where connections and their metrics report incidents.
+    pub fn notifier(&self) -> &Notifier {
+        &self.notifier
     }
 
+    /// Opens `config` unless a connection is already open under its name,
+    /// which fails with [`ConnectionExists`].
     pub async fn add_connection(&self, config: SerialConnectionConfig) -> Result<()> {
         if !config.enabled {
             info!("Connection {} is disabled, skipping", config.name);
+            self.disabled
+                .write()
+                .await
+                .insert(config.name.clone(), config);
             return Ok(());
         }
 
+        if self.get_connection(&config.name).await.is_some() {
+            return Err(ConnectionExists(config.name).into());
+        }
         info!(
             "Adding serial connection: {} at {}",
             config.name, config.port
         );
 
-        let connection = SerialConnection::new(config.clone()).await?;
+        let mirror = config
+            .mirror
+            .as_ref()
+            .map(|mirror| Mirror::spawn(&config.name, mirror, Arc::downgrade(&self.connections)));
+        let quotas = Quotas::new(
+            config
+                .quota
+                .clone()
+                .map(|quota| Arc::new(Quota::new(Some(&config.name), quota))),
+            self.quota.clone(),
+            self.notifier.clone(),
+            config.notify.clone(),
+        );
+        let connection =
+            SerialConnection::new(config.clone(), self.notifier.clone(), mirror, quotas).await?;
+        if let Some(identify) = config.identify.clone() {
+            let connection = connection.clone();
+            tokio::spawn(async move {
+                match identify::probe(&connection, &identify).await {
+                    Ok(identity) => {
+                        info!(
+                            "Identified the device on {}: {} {}",
+                            connection.config().name,
+                            identity.model.as_deref().unwrap_or("unknown model"),
+                            identity.firmware.as_deref().unwrap_or_default()
+                        );
+                        connection.set_identity(identity);
+                    }
+                    Err(e) => warn!(
+                        "Could not identify the device on {}: {}",
+                        connection.config().name,
+                        e
+                    ),
+                }
+            });
+        }
+
+        // Virtual ports cannot be opened again
+        if !connection.is_virtual() {
+            let manager = self.clone();
+            let name = config.name.clone();
+            let mut lost = connection.lost();
+            tokio::spawn(async move {
+                // Fails once the connection has been shut down instead
+                let Ok(reason) = lost
+                    .wait_for(Option::is_some)
+                    .await
+                    .map(|reason| reason.clone())
+                else {
+                    return;
+                };
+                manager
+                    .connection_lost(&name, &lost, reason.unwrap_or_default())
+                    .await;
+            });
+        }
 
+        // Checked again under the lock, as another request may have opened
+        // one under the same name while this port was being opened
         let mut connections = self.connections.write().await;
+        if connections.contains_key(&config.name) {
+            drop(connections);
+            let mut connection = connection;
+            connection.stop().await;
+            return Err(ConnectionExists(config.name).into());
+        }
         connections.insert(config.name.clone(), connection);
+        drop(connections);
+        self.failed.write().await.remove(&config.name);
+        self.disabled.write().await.remove(&config.name);
 
         Ok(())
     }
 
-    pub async fn remove_connection(&self, name: &str) -> Result<()> {
-        let mut connections = self.connections.write().await;
+    /// Moves a connection whose port was lost, and not reopened, to the
+    /// failed connections, from where it can be retried or enabled again.
+    async fn connection_lost(
+        &self,
+        name: &str,
+        lost: &watch::Receiver<Option<String>>,
+        reason: String,
+    ) {
+        let config = {
+            let mut connections = self.connections.write().await;
+            // Not one opened under the same name since
+            let current = connections
+                .get(name)
+                .filter(|current| current.lost().same_channel(lost));
+            let Some(current) = current else {
+                return;
+            };
+            let config = current.config().clone();
+            connections.remove(name);
+            config
+        };
+        warn!("Connection {} moved to failed: {}", config.name, reason);
+        self.opened(&config, &Err(anyhow::anyhow!(reason))).await;
+    }
 
-        if let Some(mut connection) = connections.remove(name) {
+    /// Opens `configs` concurrently, each once the connection it depends on
+    /// is open and its startup delay has passed. Results are in the order
+    /// given; a connection whose dependency did not open, or is neither in
+    /// `configs` nor open already, is not opened. Enabled connections that
+    /// fail are kept in [`Self::failed_connections`] until retried. Those
+    /// not opened yet are given up on if this is dropped.
+    pub async fn start_connections(
+        &self,
+        configs: Vec<SerialConnectionConfig>,
+    ) -> Vec<(String, Result<()>)> {
+        for config in &configs {
+            let waiting_for = match config.depends_on {
+                Some(ref dependency) => format!("dependency {}", dependency),
+                None if config.startup_delay_ms > 0 => startup_delay(config),
+                None => "port".to_string(),
+            };
+            self.startup
+                .pending(&config.name, &config.port, waiting_for);
+        }
+        let (senders, started): (Vec<_>, HashMap<_, _>) = configs
+            .iter()
+            .map(|config| {
+                let (tx, rx) = watch::channel(None::<bool>);
+                (tx, (config.name.clone(), rx))
+            })
+            .unzip();
+
+        let tasks: Vec<_> = configs
+            .into_iter()
+            .zip(senders)
+            .map(|(config, opened_tx)| {
+                let manager = self.clone();
+                let dependency = config
+                    .depends_on
+                    .clone()
+                    .map(|name| (started.get(&name).cloned(), name));
+                let name = config.name.clone();
+                let task = tokio::spawn(async move {
+                    if let Some((opened, dependency)) = dependency {
+                        let opened = match opened {
+                            Some(mut rx) => {
+                                matches!(
+                                    rx.wait_for(Option::is_some).await.as_deref(),
+                                    Ok(Some(true))
+                                )
+                            }
+                            // Outside this batch, so it has to be open already
+                            None => manager.get_connection(&dependency).await.is_some(),
+                        };
+                        if !opened {
+                            opened_tx.send_replace(Some(false));
+                            let result =
+                                Err(anyhow::anyhow!("dependency {} was not opened", dependency));
+                            manager.opened(&config, &result).await;
+                            return result;
+                        }
+                    }
+                    if config.startup_delay_ms > 0 {
+                        manager
+                            .startup
+                            .pending(&config.name, &config.port, startup_delay(&config));
+                        tokio::time::sleep(Duration::from_millis(config.startup_delay_ms)).await;
+                    }
+                    manager.startup.pending(&config.name, &config.port, "port");
+                    let result = manager.add_connection(config.clone()).await;
+                    manager.opened(&config, &result).await;
+                    opened_tx.send_replace(Some(config.enabled && result.is_ok()));
+                    result
+                });
+                (name, task)
+            })
+            .collect();
+        // Dropping this future, as on shutdown, stops those still waiting
+        let _abort = AbortOnDrop(tasks.iter().map(|(_, task)| task.abort_handle()).collect());
+
+        let mut results = Vec::new();
+        for (name, task) in tasks {
+            let result = task.await.unwrap_or_else(|e| Err(e.into()));
+            results.push((name, result));
+        }
+        results
+    }
+
+    /// Records how opening a configured connection went.
+    async fn opened(&self, config: &SerialConnectionConfig, result: &Result<()>) {
+        self.startup
+            .finished(&config.name, &config.port, config.enabled, result);
+        if let Err(e) = result {
+            if config.enabled {
+                self.failed.write().await.insert(
+                    config.name.clone(),
+                    FailedConnection::new(config.clone(), e),
+                );
+            }
+        }
+    }
+
+    /// Which of the connections started by [`Self::start_connections`]
+    /// opened, failed or are still pending.
+    pub fn startup_report(&self) -> StartupReport {
+        self.startup.report()
+    }
+
+    /// Configured connections that could not be opened, by name.
+    pub async fn failed_connections(&self) -> Vec<FailedConnection> {
+        let mut failed: Vec<_> = self.failed.read().await.values().cloned().collect();
+        failed.sort_by(|a, b| a.name.cmp(&b.name));
+        failed
+    }
+
+    pub async fn get_failed_connection(&self, name: &str) -> Option<FailedConnection> {
+        self.failed.read().await.get(name).cloned()
+    }
+
+    /// Tries again to open a connection that failed, with the settings it
+    /// was configured with. It stays failed, with the new error, if this
+    /// does not work either.
+    pub async fn retry_connection(&self, name: &str) -> Result<()> {
+        let config = self
+            .get_failed_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No failed connection named {}", name))?
+            .config;
+        let result = match config.depends_on {
+            Some(ref dependency) if self.get_connection(dependency).await.is_none() => {
+                Err(anyhow::anyhow!("dependency {} is not open", dependency))
+            }
+            _ => self.add_connection(config.clone()).await,
+        };
+        self.opened(&config, &result).await;
+        if result.is_ok() {
+            info!("Opened {} on retry", name);
+        }
+        result
+    }
+
+    /// Connections that are disabled, by name.
+    pub async fn disabled_connections(&self) -> Vec<SerialConnectionConfig> {
+        let mut disabled: Vec<_> = self.disabled.read().await.values().cloned().collect();
+        disabled.sort_by(|a, b| a.name.cmp(&b.name));
+        disabled
+    }
+
+    pub async fn get_disabled_connection(&self, name: &str) -> Option<SerialConnectionConfig> {
+        self.disabled.read().await.get(name).cloned()
+    }
+
+    /// Closes a connection but keeps its settings, so it can be enabled
+    /// again. A failed connection is kept the same way.
+    pub async fn disable_connection(&self, name: &str) -> Result<()> {
+        if self.disabled.read().await.contains_key(name) {
+            return Ok(());
+        }
+        let removed = self.connections.write().await.remove(name);
+        let config = match removed {
+            Some(mut connection) => {
+                self.cancel_expiry(&Expiring::Connection(name.to_string()))
+                    .await;
+                connection.stop().await;
+                connection.config().clone()
+            }
+            None => match self.failed.write().await.remove(name) {
+                Some(failed) => failed.config,
+                None => anyhow::bail!("Connection not found: {}", name),
+            },
+        };
+        info!("Disabled serial connection: {}", name);
+        self.disabled.write().await.insert(
+            name.to_string(),
+            SerialConnectionConfig {
+                enabled: false,
+                ..config
+            },
+        );
+        Ok(())
+    }
+
+    /// Opens a disabled connection with the settings it was kept with. If
+    /// that fails it is kept as a failed connection, to be retried; enabling
+    /// a failed connection retries it.
+    pub async fn enable_connection(&self, name: &str) -> Result<()> {
+        let disabled = self.disabled.write().await.remove(name);
+        let Some(config) = disabled else {
+            if self.get_failed_connection(name).await.is_some() {
+                return self.retry_connection(name).await;
+            }
+            if self.get_connection(name).await.is_some() {
+                return Ok(());
+            }
+            anyhow::bail!("Connection not found: {}", name);
+        };
+        let config = SerialConnectionConfig {
+            enabled: true,
+            ..config
+        };
+        let result = match config.depends_on {
+            Some(ref dependency) if self.get_connection(dependency).await.is_none() => {
+                Err(anyhow::anyhow!("dependency {} is not open", dependency))
+            }
+            _ => self.add_connection(config.clone()).await,
+        };
+        self.opened(&config, &result).await;
+        if result.is_ok() {
+            info!("Enabled serial connection: {}", name);
+        }
+        result
+    }
+
+    pub async fn remove_connection(&self, name: &str) -> Result<()> {
+        // Removed from the map first, so readers of other connections are
+        // not held up while this one stops
+        let removed = self.connections.write().await.remove(name);
+        if let Some(mut connection) = removed {
+            self.cancel_expiry(&Expiring::Connection(name.to_string()))
+                .await;
             connection.stop().await;
             info!("Removed serial connection: {}", name);
             Ok(())
+        } else if self.failed.write().await.remove(name).is_some() {
+            info!("Removed failed connection: {}", name);
+            Ok(())
+        } else if self.disabled.write().await.remove(name).is_some() {
+            info!("Removed disabled connection: {}", name);
+            Ok(())
         } else {
             anyhow::bail!("Connection not found: {}", name)
         }
@@ -58,26 +576,73 @@ impl SerialManager {
         connections.get(name).cloned()
     }
 
+    /// Records how long an API request on `name` took, under `route`.
+    pub async fn observe_request(&self, name: &str, route: &'static str, elapsed: Duration) {
+        if let Some(connection) = self.get_connection(name).await {
+            connection.observe_request(route, elapsed).await;
+        }
+    }
+
     pub async fn list_connections(&self) -> Vec<String> {
         let connections = self.connections.read().await;
         connections.keys().cloned().collect()
     }
 
     pub async fn send_data(&self, name: &str, data: &[u8]) -> Result<()> {
+        self.send_data_with_priority(name, data, Priority::Interactive)
+            .await
+    }
+
+    pub async fn send_data_with_priority(
+        &self,
+        name: &str,
+        data: &[u8],
+        priority: Priority,
+    ) -> Result<()> {
+        // Not holding the connections lock while waiting for a turn to write
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+        connection.send_with_priority(data, priority).await
+    }
+
+    /// Sends data and waits until it has been written to the port.
+    pub async fn send_data_written(
+        &self,
+        name: &str,
+        data: &[u8],
+        priority: Priority,
+    ) -> Result<()> {
+        // Not holding the connections lock while the write is pending
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+        connection.send_written(data, priority).await
+    }
+
+    pub async fn subscribe(&self, name: &str) -> Result<broadcast::Receiver<SerialData>> {
         let connections = self.connections.read().await;
 
         if let Some(connection) = connections.get(name) {
-            connection.send(data).await
+            Ok(connection.subscribe())
         } else {
             anyhow::bail!("Connection not found: {}", name)
         }
     }
 
-    pub async fn subscribe(&self, name: &str) -> Result<broadcast::Receiver<SerialData>> {
+    /// Subscribes to a connection, replaying what was received after
+    /// sequence number `since` if it is still buffered.
+    pub async fn subscribe_from(
+        &self,
+        name: &str,
+        since: Option<u64>,
+    ) -> Result<(broadcast::Receiver<SerialData>, Replay)> {
         let connections = self.connections.read().await;
 
         if let Some(connection) = connections.get(name) {
-            Ok(connection.subscribe())
+            Ok(connection.subscribe_from(since))
         } else {
             anyhow::bail!("Connection not found: {}", name)
         }
@@ -93,7 +658,791 @@ impl SerialManager {
         }
     }
 
+    pub async fn get_throughput(&self, name: &str, window: u64) -> Result<Throughput> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.throughput(window).await)
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    pub async fn traffic_profile(&self, name: &str, window: u64) -> Result<TrafficProfile> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.traffic_profile(window).await)
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    /// Today's usage of the server's quota and each connection's, the
+    /// server's first.
+    pub async fn quotas(&self) -> Vec<QuotaUsage> {
+        let day = Utc::now().date_naive();
+        let connections = self.connections.read().await;
+        let mut usage: Vec<QuotaUsage> = connections
+            .values()
+            .filter_map(SerialConnection::quota_usage)
+            .collect();
+        usage.sort_by(|a, b| a.connection.cmp(&b.connection));
+        if let Some(ref quota) = self.quota {
+            usage.insert(0, quota.usage(day));
+        }
+        usage
+    }
+
+    /// A page of the connection's numbered received chunks.
+    pub async fn frames(&self, name: &str, since: u64, limit: usize) -> Result<FramePage> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.frames(since, limit))
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    /// The chunks received since `cursor` last read, advancing it.
+    pub async fn read_frames(&self, name: &str, cursor: &str, limit: usize) -> Result<FramePage> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.read_frames(cursor, limit))
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    /// Pins a note to the connection's received data, and to the running
+    /// captures that include it. `None` if `request.seq` is not a chunk
+    /// still kept.
+    pub async fn annotate(
+        &self,
+        name: &str,
+        request: AnnotationRequest,
+    ) -> Result<Option<Annotation>> {
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+        let Some(annotation) = connection.annotate(request) else {
+            return Ok(None);
+        };
+        for capture in self.captures.read().await.values() {
+            capture.annotate(name, &annotation);
+        }
+        Ok(Some(annotation))
+    }
+
+    /// The connection's annotations following chunks after `since`.
+    pub async fn annotations(&self, name: &str, since: u64) -> Result<Vec<Annotation>> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.annotations(since))
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    /// Like [`frames`](Self::frames), but if nothing after `since` has
+    /// been received yet, waits up to `wait` for the next chunk first.
+    pub async fn poll_frames(
+        &self,
+        name: &str,
+        since: u64,
+        limit: usize,
+        wait: Duration,
+    ) -> Result<FramePage> {
+        let (mut rx, replay) = self.subscribe_from(name, Some(since)).await?;
+        if replay.chunks.is_empty() && replay.gap.is_none() {
+            // A closed channel means the connection went away, which the
+            // lookup below reports
+            let _ = tokio::time::timeout(wait, rx.recv()).await;
+        }
+        self.frames(name, since, limit).await
+    }
+
+    /// Recent framed traffic from the connection's in-memory history.
+    pub async fn tail(
+        &self,
+        name: &str,
+        lines: usize,
+        direction: Option<Direction>,
+    ) -> Result<Vec<TrafficRecord>> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.tail(lines, direction).await)
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    /// The last `bytes` bytes of the connection's in-memory history in one
+    /// direction.
+    pub async fn recent_bytes(
+        &self,
+        name: &str,
+        bytes: usize,
+        direction: Direction,
+    ) -> Result<Vec<u8>> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.recent_bytes(bytes, direction).await)
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    /// Times probes echoed back by a loopback-capable device.
+    pub async fn latency_test(
+        &self,
+        name: &str,
+        options: &LatencyTestOptions,
+    ) -> Result<LatencyReport> {
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+        latency::run(&connection, options).await
+    }
+
+    /// Runs steps that no other client's writes may come between.
+    pub async fn run_sequence(&self, name: &str, steps: &[SequenceStep]) -> Result<SequenceReport> {
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+        sequence::run(&connection, steps).await
+    }
+
+    /// Switches the device behind the connection on, off, or off and on.
+    pub async fn power(&self, name: &str, action: PowerAction) -> Result<()> {
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+        let config = connection
+            .config()
+            .power
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("Connection {} has no power control", name))?;
+        power::run(self, name, config, action).await
+    }
+
+    pub async fn get_metrics(&self, name: &str) -> Result<Vec<MetricSummary>> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.metric_summaries().await)
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    pub async fn get_metric_samples(
+        &self,
+        name: &str,
+        metric: &str,
+        limit: Option<usize>,
+    ) -> Result<Option<Vec<Sample>>> {
+        let connections = self.connections.read().await;
+
+        if let Some(connection) = connections.get(name) {
+            Ok(connection.metric_samples(metric, limit).await)
+        } else {
+            anyhow::bail!("Connection not found: {}", name)
+        }
+    }
+
+    /// Reads a configured endpoint's value from the device, or from its last
+    /// reading if it has a `poll_interval_ms`, or `None` if the connection
+    /// has no endpoint of that name.
+    pub async fn query_endpoint(
+        &self,
+        name: &str,
+        endpoint: &str,
+    ) -> Result<Option<EndpointReading>> {
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+        let Some(config) = connection
+            .config()
+            .endpoints
+            .iter()
+            .find(|config| config.name == endpoint)
+            .cloned()
+        else {
+            return Ok(None);
+        };
+        endpoints::read(&connection, &config).await.map(Some)
+    }
+
+    /// Counts connected, degraded and disconnected connections.
+    pub async fn health(&self) -> HealthReport {
+        let connections: Vec<SerialConnection> =
+            self.connections.read().await.values().cloned().collect();
+        let leased: Vec<String> = self.leases.read().await.keys().cloned().collect();
+
+        let mut connected = 0;
+        let mut problems = Vec::new();
+        for connection in connections {
+            let name = connection.config().name.clone();
+            let problem = |state, reason: String| ConnectionHealth {
+                name: name.clone(),
+                state,
+                reason,
+            };
+            let stats = connection.get_stats().await;
+            if !stats.is_connected {
+                let reason = match stats.reconnect {
+                    Some(reconnect) if reconnect.reconnecting => format!(
+                        "Port was lost, reconnecting ({} failed attempts)",
+                        reconnect.attempts
+                    ),
+                    _ => "Port was lost".to_string(),
+                };
+                problems.push(problem(HealthState::Disconnected, reason));
+                continue;
+            }
+            if leased.contains(&name) {
+                problems.push(problem(
+                    HealthState::Degraded,
+                    "Leased to an external tool".to_string(),
+                ));
+                continue;
+            }
+            let firing: Vec<String> = connection
+                .metric_summaries()
+                .await
+                .into_iter()
+                .flat_map(|metric| metric.alerts)
+                .filter(|alert| alert.firing)
+                .map(|alert| alert.name)
+                .collect();
+            if firing.is_empty() {
+                connected += 1;
+            } else {
+                problems.push(problem(
+                    HealthState::Degraded,
+                    format!("Alerts firing: {}", firing.join(", ")),
+                ));
+            }
+        }
+        // Including those whose port was lost and not reopened
+        for failed in self.failed_connections().await {
+            problems.push(ConnectionHealth {
+                name: failed.name,
+                state: HealthState::Disconnected,
+                reason: failed.error,
+            });
+        }
+        HealthReport::new(connected, problems)
+    }
+
+    /// Totals across all connections, including disk used by their logs.
+    pub async fn get_server_stats(&self) -> ServerStats {
+        let connections: Vec<SerialConnection> =
+            self.connections.read().await.values().cloned().collect();
+
+        let mut stats = ServerStats {
+            connections: connections.len(),
+            bytes_received: 0,
+            bytes_sent: 0,
+            log_disk_usage: LogDiskUsage::default(),
+        };
+
+        for connection in connections {
+            let connection_stats = connection.get_stats().await;
+            stats.bytes_received += connection_stats.bytes_received;
+            stats.bytes_sent += connection_stats.bytes_sent;
+
+            let logging = &connection.config().logging;
+            if !logging.enabled {
+                continue;
+            }
+            match retention::usage(logging).await {
+                Ok(usage) => {
+                    stats.log_disk_usage.total_bytes += usage.bytes;
+                    stats.log_disk_usage.connections.push(ConnectionLogUsage {
+                        name: connection_stats.name,
+                        usage,
+                    });
+                }
+                Err(e) => warn!(
+                    "Failed to measure logs for {}: {}",
+                    connection_stats.name, e
+                ),
+            }
+        }
+
+        stats
+            .log_disk_usage
+            .connections
+            .sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    /// Periodically ships each connection's rotated logs and applies its
+    /// retention policy until the manager is dropped. Each connection is
+    /// swept in a task of its own, so a slow upload only holds up its own
+    /// connection; one still running when the next sweep is due is left
+    /// to finish.
+    pub fn spawn_log_sweeper(&self) {
+        let connections = Arc::downgrade(&self.connections);
+        let client = reqwest::Client::builder()
+            .timeout(std::time::Duration::from_secs(300))
+            .build()
+            .expect("default TLS backend is available");
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(retention::SWEEP_INTERVAL);
+            let mut sweeps: HashMap<String, tokio::task::JoinHandle<()>> = HashMap::new();
+            loop {
+                interval.tick().await;
+                let Some(connections) = connections.upgrade() else {
+                    break;
+                };
+                let configs: Vec<SerialConnectionConfig> = connections
+                    .read()
+                    .await
+                    .values()
+                    .map(|connection| connection.config().clone())
+                    .collect();
+                drop(connections);
+
+                sweeps.retain(|_, sweep| !sweep.is_finished());
+                for config in configs {
+                    if !config.logging.enabled || sweeps.contains_key(&config.name) {
+                        continue;
+                    }
+                    let client = client.clone();
+                    let name = config.name.clone();
+                    let sweep = tokio::spawn(async move {
+                        // Shipped first, so retention only removes what could not be
+                        if let Err(e) = ship::ship(&client, &config.name, &config.logging).await {
+                            warn!("Log shipping for {} failed: {}", config.name, e);
+                        }
+                        if let Err(e) =
+                            retention::sweep(&config.logging, std::time::SystemTime::now()).await
+                        {
+                            warn!("Log retention for {} failed: {}", config.name, e);
+                        }
+                    });
+                    sweeps.insert(name, sweep);
+                }
+            }
+        });
+    }
+
+    /// Creates two connections wired to each other: whatever is sent to one
+    /// is received by the other.
+    pub async fn add_virtual_pair(&self, request: &VirtualPairRequest) -> Result<VirtualPairInfo> {
+        let (a, b) = request.endpoints();
+        if request.name.is_empty() || a.is_empty() || b.is_empty() {
+            anyhow::bail!("Virtual pair and connection names must not be empty");
+        }
+        if a == b {
+            anyhow::bail!("Both ends of a virtual pair need distinct names");
+        }
+
+        let mut pairs = self.virtual_pairs.write().await;
+        let mut connections = self.connections.write().await;
+        if pairs.contains_key(&request.name) {
+            anyhow::bail!("Virtual pair {} already exists", request.name);
+        }
+        for name in [&a, &b] {
+            if connections.contains_key(name) {
+                anyhow::bail!("Connection {} already exists", name);
+            }
+        }
+
+        let (stream_a, stream_b) = tokio::io::duplex(virtual_pair::PAIR_BUFFER_BYTES);
+        let connection_a = SerialConnection::new_virtual(
+            virtual_pair::endpoint_config(&a, &request.name),
+            stream_a,
+            self.notifier.clone(),
+        )
+        .await?;
+        let connection_b = SerialConnection::new_virtual(
+            virtual_pair::endpoint_config(&b, &request.name),
+            stream_b,
+            self.notifier.clone(),
+        )
+        .await?;
+        connections.insert(a.clone(), connection_a);
+        connections.insert(b.clone(), connection_b);
+
+        let info = VirtualPairInfo {
+            name: request.name.clone(),
+            a,
+            b,
+            expires_at: None,
+        };
+        pairs.insert(request.name.clone(), info.clone());
+        info!(
+            "Created virtual pair {} ({} <-> {})",
+            info.name, info.a, info.b
+        );
+
+        Ok(info)
+    }
+
+    pub async fn get_virtual_pair(&self, name: &str) -> Option<VirtualPairInfo> {
+        let mut info = self.virtual_pairs.read().await.get(name).cloned()?;
+        info.expires_at = self.expiry(&Expiring::VirtualPair(info.name.clone())).await;
+        Some(info)
+    }
+
+    pub async fn list_virtual_pairs(&self) -> Vec<VirtualPairInfo> {
+        let mut list: Vec<VirtualPairInfo> =
+            self.virtual_pairs.read().await.values().cloned().collect();
+        list.sort_by(|x, y| x.name.cmp(&y.name));
+        for info in &mut list {
+            info.expires_at = self.expiry(&Expiring::VirtualPair(info.name.clone())).await;
+        }
+        list
+    }
+
+    /// Stops and removes both connections of a virtual pair.
+    pub async fn remove_virtual_pair(&self, name: &str) -> Result<()> {
+        let info = self
+            .virtual_pairs
+            .write()
+            .await
+            .remove(name)
+            .ok_or_else(|| anyhow::anyhow!("Virtual pair not found: {}", name))?;
+        self.cancel_expiry(&Expiring::VirtualPair(name.to_string()))
+            .await;
+
+        for endpoint in [&info.a, &info.b] {
+            // Either end may already have been removed on its own
+            let _ = self.remove_connection(endpoint).await;
+        }
+        info!("Removed virtual pair {}", name);
+        Ok(())
+    }
+
+    /// Starts recording several connections into one time-ordered bundle.
+    pub async fn start_capture(&self, request: &CaptureRequest) -> Result<CaptureInfo> {
+        request.validate()?;
+
+        let mut connections = Vec::with_capacity(request.connections.len());
+        for name in &request.connections {
+            connections.push(
+                self.get_connection(name)
+                    .await
+                    .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?,
+            );
+        }
+
+        let mut captures = self.captures.write().await;
+        if captures.len() >= capture::MAX_CAPTURES {
+            anyhow::bail!(
+                "Too many captures ({}); delete finished ones first",
+                capture::MAX_CAPTURES
+            );
+        }
+
+        let id = NEXT_CAPTURE_ID.fetch_add(1, Ordering::Relaxed);
+        let capture = Capture::start(id, request, &connections);
+        let info = capture.info();
+        captures.insert(id, capture);
+        info!(
+            "Started capture {} of {}",
+            id,
+            request.connections.join(", ")
+        );
+
+        Ok(info)
+    }
+
+    pub async fn list_captures(&self) -> Vec<CaptureInfo> {
+        let captures = self.captures.read().await;
+        let mut list: Vec<CaptureInfo> = captures.values().map(Capture::info).collect();
+        list.sort_by_key(|info| info.id);
+        list
+    }
+
+    pub async fn get_capture(&self, id: u64) -> Option<CaptureBundle> {
+        let captures = self.captures.read().await;
+        captures.get(&id).map(Capture::bundle)
+    }
+
+    /// Stops a running capture; its records stay available until deleted.
+    pub async fn stop_capture(&self, id: u64) -> Result<()> {
+        let mut captures = self.captures.write().await;
+        match captures.get_mut(&id) {
+            Some(capture) => {
+                capture.stop();
+                Ok(())
+            }
+            None => anyhow::bail!("Capture not found: {}", id),
+        }
+    }
+
+    pub async fn delete_capture(&self, id: u64) -> Result<()> {
+        match self.captures.write().await.remove(&id) {
+            Some(mut capture) => {
+                capture.stop();
+                Ok(())
+            }
+            None => anyhow::bail!("Capture not found: {}", id),
+        }
+    }
+
+    /// Merges what the ports of `config.tx` and `config.rx` receive into
+    /// one stream labelled by direction.
+    pub async fn add_sniffer(&self, config: &SnifferConfig) -> Result<SnifferInfo> {
+        config.validate()?;
+        let tx = self
+            .get_connection(&config.tx)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", config.tx))?;
+        let rx = self
+            .get_connection(&config.rx)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", config.rx))?;
+
+        let mut sniffers = self.sniffers.write().await;
+        if sniffers.contains_key(&config.name) {
+            anyhow::bail!("Sniffer already exists: {}", config.name);
+        }
+        let sniffer = Sniffer::start(config, &tx, &rx).await?;
+        let info = sniffer.info();
+        sniffers.insert(config.name.clone(), sniffer);
+        Ok(info)
+    }
+
+    pub async fn list_sniffers(&self) -> Vec<SnifferInfo> {
+        let sniffers = self.sniffers.read().await;
+        let mut list: Vec<SnifferInfo> = sniffers.values().map(Sniffer::info).collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// The merged stream from now on; it closes when the sniffer is removed.
+    pub async fn subscribe_sniffer(
+        &self,
+        name: &str,
+    ) -> Option<broadcast::Receiver<SnifferRecord>> {
+        let sniffers = self.sniffers.read().await;
+        sniffers.get(name).map(Sniffer::subscribe)
+    }
+
+    /// Stops a sniffer; its connections keep running.
+    pub async fn remove_sniffer(&self, name: &str) -> Result<()> {
+        match self.sniffers.write().await.remove(name) {
+            Some(_) => {
+                info!("Removed sniffer {}", name);
+                Ok(())
+            }
+            None => anyhow::bail!("Sniffer not found: {}", name),
+        }
+    }
+
+    /// Lends a connection's port to an external tool for up to `duration`.
+    ///
+    /// In `release` mode the OS handle is closed so the tool can open the
+    /// device itself; in `tcp` mode the port is proxied on a temporary
+    /// listener bound to `bind_host`. Either way the connection resumes
+    /// automatically when the lease expires or is ended.
+    pub async fn start_lease(
+        &self,
+        name: &str,
+        mode: LeaseMode,
+        duration: Duration,
+        bind_host: &str,
+    ) -> Result<LeaseInfo> {
+        if duration.is_zero() || duration > lease::MAX_LEASE_DURATION {
+            anyhow::bail!(
+                "Lease duration must be between 1 and {} seconds",
+                lease::MAX_LEASE_DURATION.as_secs()
+            );
+        }
+
+        let connection = self
+            .get_connection(name)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+
+        if self.leases.read().await.contains_key(name) {
+            anyhow::bail!("Connection {} is already leased", name);
+        }
+
+        // Not under the lock: the port may be out on a flash for minutes
+        let mut port = connection.acquire_exclusive().await?;
+        let config = connection.config().clone();
+        let expires_at = chrono::Utc::now() + chrono::Duration::from_std(duration)?;
+
+        let (listener, info) = match mode {
+            LeaseMode::Release => {
+                port.close();
+                let info = LeaseInfo {
+                    connection: name.to_string(),
+                    mode,
+                    expires_at,
+                    device: Some(config.port.clone()),
+                    tcp_address: None,
+                };
+                (None, info)
+            }
+            LeaseMode::Tcp => {
+                let listener = tokio::net::TcpListener::bind((bind_host, 0)).await?;
+                let info = LeaseInfo {
+                    connection: name.to_string(),
+                    mode,
+                    expires_at,
+                    device: None,
+                    tcp_address: Some(listener.local_addr()?.to_string()),
+                };
+                (Some(listener), info)
+            }
+        };
+
+        // Checked again, as another lease may have started meanwhile; the
+        // port goes back to the connection when dropped
+        let mut leases = self.leases.write().await;
+        if leases.contains_key(name) {
+            anyhow::bail!("Connection {} is already leased", name);
+        }
+
+        info!("Leasing {} ({:?}) for {}s", name, mode, duration.as_secs());
+
+        let id = NEXT_LEASE_ID.fetch_add(1, Ordering::Relaxed);
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+        leases.insert(
+            name.to_string(),
+            ActiveLease {
+                id,
+                info: info.clone(),
+                cancel_tx,
+            },
+        );
+
+        let leases = self.leases.clone();
+        let name = name.to_string();
+        tokio::spawn(async move {
+            lease::run_lease(port, config, listener, duration, cancel_rx).await;
+
+            let mut leases = leases.write().await;
+            if leases.get(&name).is_some_and(|active| active.id == id) {
+                leases.remove(&name);
+            }
+        });
+
+        Ok(info)
+    }
+
+    /// Ends a lease early, handing the port back to the connection.
+    pub async fn end_lease(&self, name: &str) -> Result<()> {
+        match self.leases.write().await.remove(name) {
+            Some(active) => {
+                let _ = active.cancel_tx.send(());
+                Ok(())
+            }
+            None => anyhow::bail!("Connection {} is not leased", name),
+        }
+    }
+
+    pub async fn get_lease(&self, name: &str) -> Option<LeaseInfo> {
+        let leases = self.leases.read().await;
+        leases.get(name).map(|active| active.info.clone())
+    }
+
+    /// Closes and removes connection `name` once `after` has passed,
+    /// replacing any earlier expiry.
+    pub async fn expire_connection(&self, name: &str, after: Duration) -> Result<DateTime<Utc>> {
+        if self.get_connection(name).await.is_none() {
+            anyhow::bail!("Connection not found: {}", name);
+        }
+        self.expire(Expiring::Connection(name.to_string()), after)
+            .await
+    }
+
+    /// Removes virtual pair `name` and both its connections once `after`
+    /// has passed, replacing any earlier expiry.
+    pub async fn expire_virtual_pair(&self, name: &str, after: Duration) -> Result<DateTime<Utc>> {
+        if self.virtual_pairs.read().await.get(name).is_none() {
+            anyhow::bail!("Virtual pair not found: {}", name);
+        }
+        self.expire(Expiring::VirtualPair(name.to_string()), after)
+            .await
+    }
+
+    /// When connection `name` is due to be removed, if it expires.
+    pub async fn connection_expiry(&self, name: &str) -> Option<DateTime<Utc>> {
+        self.expiry(&Expiring::Connection(name.to_string())).await
+    }
+
+    async fn expiry(&self, target: &Expiring) -> Option<DateTime<Utc>> {
+        let expiries = self.expiries.read().await;
+        expiries.get(target).map(|expiry| expiry.expires_at)
+    }
+
+    async fn expire(&self, target: Expiring, after: Duration) -> Result<DateTime<Utc>> {
+        let expires_at = chrono::Utc::now() + chrono::Duration::from_std(after)?;
+        let id = NEXT_EXPIRY_ID.fetch_add(1, Ordering::Relaxed);
+        // Held until the entry is in, so even a very short expiry finds it
+        let mut expiries = self.expiries.write().await;
+
+        let manager = self.clone();
+        let expired = target.clone();
+        let task = tokio::spawn(async move {
+            tokio::time::sleep(after).await;
+            {
+                let mut expiries = manager.expiries.write().await;
+                if expiries.get(&expired).is_none_or(|expiry| expiry.id != id) {
+                    return;
+                }
+                expiries.remove(&expired);
+            }
+            let result = match &expired {
+                Expiring::Connection(name) => {
+                    info!("Connection {} expired", name);
+                    manager.remove_connection(name).await
+                }
+                Expiring::VirtualPair(name) => {
+                    info!("Virtual pair {} expired", name);
+                    manager.remove_virtual_pair(name).await
+                }
+            };
+            if let Err(e) = result {
+                warn!("Failed to remove expired {:?}: {}", expired, e);
+            }
+        });
+
+        let previous = expiries.insert(
+            target,
+            Expiry {
+                id,
+                expires_at,
+                task,
+            },
+        );
+        if let Some(previous) = previous {
+            previous.task.abort();
+        }
+        Ok(expires_at)
+    }
+
+    async fn cancel_expiry(&self, target: &Expiring) {
+        if let Some(expiry) = self.expiries.write().await.remove(target) {
+            expiry.task.abort();
+        }
+    }
+
     pub async fn shutdown(&self) {
+        for (_, expiry) in self.expiries.write().await.drain() {
+            expiry.task.abort();
+        }
+        self.sniffers.write().await.clear();
+        self.virtual_pairs.write().await.clear();
         let mut connections = self.connections.write().await;
 
         for (name, mut connection) in connections.drain() {
@@ -103,6 +1452,15 @@ impl SerialManager {
     }
 }
 
+/// Distributions since the connection was opened, for scraping.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConnectionHistograms {
+    /// API request latency in seconds, by route, e.g. `send`.
+    pub request_seconds: Vec<(&'static str, Histogram)>,
+    /// Frame sizes in bytes, split on the throughput terminator.
+    pub frame_bytes: Vec<(Direction, Histogram)>,
+}
+
 #[derive(Debug, Clone, serde::Serialize)]
 pub struct ConnectionStats {
     pub name: String,
@@ -110,5 +1468,47 @@ pub struct ConnectionStats {
     pub bytes_received: u64,
     pub bytes_sent: u64,
     pub is_connected: bool,
+    /// False while the port is closed for being idle.
+    pub port_open: bool,
     pub uptime_seconds: u64,
+    /// Framing, parity and overrun errors the driver counted, or `None`
+    /// where it does not count them.
+    pub line_errors: Option<line_errors::LineErrors>,
+    /// `None` unless the connection has a `keepalive`.
+    pub keepalive: Option<KeepaliveStats>,
+    /// One entry per configured protocol rule.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<rules::RuleStats>,
+    /// `None` unless the connection has `reconnect`.
+    pub reconnect: Option<ReconnectStats>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, serde::Serialize)]
+pub struct KeepaliveStats {
+    pub sent: u64,
+    pub last_sent: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ServerStats {
+    pub connections: usize,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub log_disk_usage: LogDiskUsage,
 }
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct LogDiskUsage {
+    pub total_bytes: u64,
+    pub connections: Vec<ConnectionLogUsage>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ConnectionLogUsage {
+    pub name: String,
+    #[serde(flatten)]
+    pub usage: LogUsage,
+}
+
+#[cfg(test)]
+mod tests;
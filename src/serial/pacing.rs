@@ -0,0 +1,52 @@
+//! Token-bucket pacing for `max_tx_rate_bytes_per_sec`.
+
+use std::time::{Duration, Instant};
+
+/// Hands out write budget at a fixed byte rate.
+///
+/// The bucket holds 10 ms worth of bytes (at least one), so writes reach
+/// the device in small, evenly spaced chunks rather than bursts that could
+/// overflow its receive buffer.
+#[derive(Debug)]
+pub struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    last: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(bytes_per_sec: u32, now: Instant) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        let capacity = (rate / 100.0).floor().max(1.0);
+        Self {
+            rate,
+            capacity,
+            tokens: capacity,
+            last: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.last = now;
+    }
+
+    /// Takes up to `want` bytes of budget and returns how many were granted.
+    pub fn take(&mut self, want: usize, now: Instant) -> usize {
+        self.refill(now);
+        let granted = (self.tokens.floor() as usize).min(want);
+        self.tokens -= granted as f64;
+        granted
+    }
+
+    /// When the next write of up to `want` bytes gets a full grant.
+    pub fn ready_at(&self, want: usize) -> Instant {
+        let needed = (want as f64).min(self.capacity);
+        if self.tokens >= needed {
+            return self.last;
+        }
+        self.last + Duration::from_secs_f64((needed - self.tokens) / self.rate)
+    }
+}
@@ -0,0 +1,133 @@
+//! Ports that belong to the same multi-port USB adapter, found by the USB
+//! device or hub they share, so a box of eight ports can be listed and
+//! restarted as one.
+
+use super::discovery::{self, PortDescription};
+use super::SerialManager;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PortGroup {
+    /// `usb-` and the shared USB path, e.g. `usb-1-2`. It stays the same
+    /// as long as the adapter stays plugged into the same socket.
+    pub id: String,
+    pub usb_path: String,
+    /// The adapter's description, if all its ports report the same one.
+    pub description: Option<String>,
+    pub ports: Vec<String>,
+    /// Connections open on the group's ports.
+    pub connections: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RestartFailure {
+    pub name: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RestartReport {
+    pub restarted: Vec<String>,
+    pub failed: Vec<RestartFailure>,
+}
+
+/// Groups the USB ports in `present` by adapter, leaving out ports that
+/// are alone on theirs. Ports sharing a USB device are the interfaces of
+/// one multi-port chip; otherwise ports whose devices share a hub other
+/// than the root hub are chips behind the adapter's own hub.
+pub fn group(present: &[PortDescription]) -> Vec<PortGroup> {
+    let mut per_device: BTreeMap<&str, usize> = BTreeMap::new();
+    for path in present.iter().filter_map(|port| port.usb_path.as_deref()) {
+        *per_device.entry(path).or_default() += 1;
+    }
+
+    let mut groups: BTreeMap<String, Vec<&PortDescription>> = BTreeMap::new();
+    for port in present {
+        let Some(ref path) = port.usb_path else {
+            continue;
+        };
+        let key = if per_device[path.as_str()] > 1 {
+            path.clone()
+        } else {
+            // A device on the root hub, e.g. `1-2`, has no hub to share
+            match path.rsplit_once('.') {
+                Some((hub, _)) => hub.to_string(),
+                None => continue,
+            }
+        };
+        groups.entry(key).or_default().push(port);
+    }
+
+    groups
+        .into_iter()
+        .filter(|(_, ports)| ports.len() > 1)
+        .map(|(usb_path, ports)| {
+            let description = ports[0].description.clone().filter(|first| {
+                ports
+                    .iter()
+                    .all(|port| port.description.as_ref() == Some(first))
+            });
+            PortGroup {
+                id: format!("usb-{}", usb_path),
+                usb_path,
+                description,
+                ports: ports.iter().map(|port| port.path.clone()).collect(),
+                connections: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+impl SerialManager {
+    /// The multi-port adapters present, with the connections open on each.
+    pub async fn port_groups(&self) -> Result<Vec<PortGroup>> {
+        let present = discovery::list_ports().await?;
+        let mut groups = group(&present);
+        for (name, connection) in self.connections.read().await.iter() {
+            let Some(port) = discovery::find_port(&present, &connection.config().port) else {
+                continue;
+            };
+            if let Some(group) = groups
+                .iter_mut()
+                .find(|group| group.ports.contains(&port.path))
+            {
+                group.connections.push(name.clone());
+            }
+        }
+        for group in &mut groups {
+            group.connections.sort();
+        }
+        Ok(groups)
+    }
+
+    /// Closes all of `names`, then opens them again with the settings they
+    /// ran with, honouring dependencies and startup delays as at startup.
+    pub async fn restart_connections(&self, names: &[String]) -> RestartReport {
+        let mut report = RestartReport::default();
+        let mut configs = Vec::new();
+        for name in names {
+            let Some(connection) = self.get_connection(name).await else {
+                continue;
+            };
+            match self.remove_connection(name).await {
+                Ok(()) => configs.push(connection.config().clone()),
+                Err(e) => report.failed.push(RestartFailure {
+                    name: name.clone(),
+                    error: e.to_string(),
+                }),
+            }
+        }
+        for (name, result) in self.start_connections(configs).await {
+            match result {
+                Ok(()) => report.restarted.push(name),
+                Err(e) => report.failed.push(RestartFailure {
+                    name,
+                    error: e.to_string(),
+                }),
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,86 @@
+//! Switching the device behind a connection on and off (`power:`).
+
+use super::SerialManager;
+use crate::config::{PowerConfig, PowerControl};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use tracing::info;
+
+/// Longest a power command or request may take.
+const POWER_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PowerAction {
+    On,
+    Off,
+    /// Off, then on again after `cycle_delay_ms`.
+    Cycle,
+}
+
+/// Runs one shell command, failing if it exits unsuccessfully.
+async fn run_command(name: &str, command: &str) -> Result<()> {
+    let mut process = if cfg!(windows) {
+        let mut process = tokio::process::Command::new("cmd");
+        process.arg("/C");
+        process
+    } else {
+        let mut process = tokio::process::Command::new("sh");
+        process.arg("-c");
+        process
+    };
+    process
+        .arg(command)
+        .env("WEBMUX_CONNECTION", name)
+        .kill_on_drop(true);
+
+    let output = tokio::time::timeout(POWER_TIMEOUT, process.output())
+        .await
+        .map_err(|_| anyhow::anyhow!("Power command timed out: {}", command))??;
+    if !output.status.success() {
+        anyhow::bail!(
+            "Power command failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+async fn switch(manager: &SerialManager, name: &str, config: &PowerConfig, on: bool) -> Result<()> {
+    let step = if on { &config.on } else { &config.off };
+    info!("Switching {} {}", name, if on { "on" } else { "off" });
+    match config.control {
+        PowerControl::Command => run_command(name, step).await,
+        PowerControl::Http { ref headers } => {
+            let mut request = manager.notifier.client().post(step).timeout(POWER_TIMEOUT);
+            for (header, value) in headers {
+                request = request.header(header, value);
+            }
+            request.send().await?.error_for_status()?;
+            Ok(())
+        }
+        PowerControl::Connection { ref connection } => {
+            manager.send_data(connection, step.as_bytes()).await
+        }
+    }
+}
+
+/// Switches the device behind connection `name` as `config` describes.
+pub async fn run(
+    manager: &SerialManager,
+    name: &str,
+    config: &PowerConfig,
+    action: PowerAction,
+) -> Result<()> {
+    match action {
+        PowerAction::On => switch(manager, name, config, true).await,
+        PowerAction::Off => switch(manager, name, config, false).await,
+        PowerAction::Cycle => {
+            switch(manager, name, config, false).await?;
+            tokio::time::sleep(Duration::from_millis(config.cycle_delay_ms)).await;
+            switch(manager, name, config, true).await
+        }
+    }
+}
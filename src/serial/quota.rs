@@ -0,0 +1,225 @@
+//! Daily byte quotas (`quota`) for single connections and for the server
+//! as a whole, so gateways on metered links cannot run through their
+//! allowance unnoticed. Usage starts again at midnight UTC.
+
+use super::Direction;
+use crate::config::{QuotaAction, QuotaConfig};
+use crate::notifications::{Notification, Notifier, Severity};
+use anyhow::Result;
+use chrono::{NaiveDate, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+/// A quota's usage on one day.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct QuotaUsage {
+    /// The connection the quota belongs to, or `None` for the server's.
+    pub connection: Option<String>,
+    pub day: NaiveDate,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub daily_rx_bytes: Option<u64>,
+    pub daily_tx_bytes: Option<u64>,
+    pub action: QuotaAction,
+    /// Set once either allowance is used up.
+    pub exceeded: bool,
+}
+
+#[derive(Debug)]
+struct Usage {
+    day: NaiveDate,
+    bytes: [u64; 2],
+    /// Directions whose allowance has been used up.
+    exceeded: [bool; 2],
+}
+
+#[derive(Debug)]
+pub struct Quota {
+    connection: Option<String>,
+    config: QuotaConfig,
+    usage: Mutex<Usage>,
+}
+
+impl Quota {
+    pub fn new(connection: Option<&str>, config: QuotaConfig) -> Self {
+        Self {
+            connection: connection.map(str::to_string),
+            config,
+            usage: Mutex::new(Usage {
+                day: Utc::now().date_naive(),
+                bytes: [0; 2],
+                exceeded: [false; 2],
+            }),
+        }
+    }
+
+    fn limit(&self, direction: Direction) -> Option<u64> {
+        match direction {
+            Direction::Rx => self.config.daily_rx_bytes,
+            Direction::Tx => self.config.daily_tx_bytes,
+        }
+    }
+
+    /// Usage on `day`, starting over if it is a new one.
+    fn usage_on(&self, day: NaiveDate) -> std::sync::MutexGuard<'_, Usage> {
+        let mut usage = self.usage.lock().unwrap();
+        if usage.day != day {
+            *usage = Usage {
+                day,
+                bytes: [0; 2],
+                exceeded: [false; 2],
+            };
+        }
+        usage
+    }
+
+    /// Counts `bytes` moved on `day`, returning the total if they used up
+    /// the allowance for `direction`.
+    pub fn record(&self, direction: Direction, bytes: u64, day: NaiveDate) -> Option<u64> {
+        let slot = direction as usize;
+        let mut usage = self.usage_on(day);
+        usage.bytes[slot] += bytes;
+        let limit = self.limit(direction)?;
+        if usage.exceeded[slot] || usage.bytes[slot] < limit {
+            return None;
+        }
+        usage.exceeded[slot] = true;
+        Some(usage.bytes[slot])
+    }
+
+    /// Whether an allowance has been used up on `day`.
+    pub fn exceeded(&self, day: NaiveDate) -> bool {
+        self.usage_on(day).exceeded.contains(&true)
+    }
+
+    pub fn usage(&self, day: NaiveDate) -> QuotaUsage {
+        let usage = self.usage_on(day);
+        QuotaUsage {
+            connection: self.connection.clone(),
+            day,
+            rx_bytes: usage.bytes[Direction::Rx as usize],
+            tx_bytes: usage.bytes[Direction::Tx as usize],
+            daily_rx_bytes: self.config.daily_rx_bytes,
+            daily_tx_bytes: self.config.daily_tx_bytes,
+            action: self.config.action,
+            exceeded: usage.exceeded.contains(&true),
+        }
+    }
+
+    fn used_up(&self, direction: Direction, total: u64) -> Notification {
+        let (moved, limit) = match direction {
+            Direction::Rx => ("Received", self.config.daily_rx_bytes),
+            Direction::Tx => ("Sent", self.config.daily_tx_bytes),
+        };
+        let consequence = match self.config.action {
+            QuotaAction::Warn => String::new(),
+            QuotaAction::Throttle => format!(
+                "; writes are limited to {} bytes/s until midnight UTC",
+                self.config.throttle_bytes_per_sec.unwrap_or_default()
+            ),
+            QuotaAction::Block => "; writes are refused until midnight UTC".to_string(),
+        };
+        let message = format!(
+            "{} {} of {} bytes today{}",
+            moved,
+            total,
+            limit.unwrap_or_default(),
+            consequence
+        );
+        let direction = match direction {
+            Direction::Rx => "RX",
+            Direction::Tx => "TX",
+        };
+        match self.connection {
+            Some(ref name) => Notification::new(
+                Severity::Warning,
+                format!("Connection {} used its daily {} quota", name, direction),
+                message,
+            )
+            .for_connection(name),
+            None => Notification::new(
+                Severity::Warning,
+                format!("Connections used the server's daily {} quota", direction),
+                message,
+            ),
+        }
+    }
+}
+
+/// The quotas a connection's traffic counts against: its own and the
+/// server's.
+#[derive(Clone, Default)]
+pub struct Quotas {
+    own: Option<Arc<Quota>>,
+    server: Option<Arc<Quota>>,
+    notifier: Notifier,
+    /// The connection's `notify` channels, for quotas without their own.
+    channels: Vec<String>,
+}
+
+impl Quotas {
+    pub fn new(
+        own: Option<Arc<Quota>>,
+        server: Option<Arc<Quota>>,
+        notifier: Notifier,
+        channels: Vec<String>,
+    ) -> Self {
+        Self {
+            own,
+            server,
+            notifier,
+            channels,
+        }
+    }
+
+    fn all(&self) -> impl Iterator<Item = &Arc<Quota>> {
+        self.own.iter().chain(self.server.iter())
+    }
+
+    pub fn own(&self) -> Option<&Arc<Quota>> {
+        self.own.as_ref()
+    }
+
+    /// Counts traffic, telling the quota's channels when it runs out.
+    pub fn record(&self, direction: Direction, bytes: usize) {
+        let day = Utc::now().date_naive();
+        for quota in self.all() {
+            if let Some(total) = quota.record(direction, bytes as u64, day) {
+                let notification = quota.used_up(direction, total);
+                warn!("{}: {}", notification.title, notification.message);
+                let channels = if quota.config.notify.is_empty() {
+                    &self.channels
+                } else {
+                    &quota.config.notify
+                };
+                self.notifier.notify(channels, notification);
+            }
+        }
+    }
+
+    /// Refuses writes once a blocking quota is used up.
+    pub fn check_write(&self) -> Result<()> {
+        let day = Utc::now().date_naive();
+        for quota in self.all() {
+            if quota.config.action == QuotaAction::Block && quota.exceeded(day) {
+                match quota.connection {
+                    Some(ref name) => {
+                        anyhow::bail!("Connection {} has used its daily quota", name)
+                    }
+                    None => anyhow::bail!("The server's daily quota is used up"),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The rate writes are slowed to by used-up throttling quotas.
+    pub fn throttle_rate(&self) -> Option<u32> {
+        let day = Utc::now().date_naive();
+        self.all()
+            .filter(|quota| quota.config.action == QuotaAction::Throttle && quota.exceeded(day))
+            .filter_map(|quota| quota.config.throttle_bytes_per_sec)
+            .min()
+    }
+}
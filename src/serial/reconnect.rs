@@ -0,0 +1,83 @@
+//! Reopening a port that went away (`reconnect`), such as a USB adapter
+//! that was unplugged, waiting longer after each failed attempt.
+
+use crate::config::ReconnectConfig;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+
+/// Longest wait between two attempts.
+pub const MAX_INTERVAL_MS: u64 = 3_600_000;
+
+/// Checks a connection's `reconnect` settings.
+pub fn validate(config: &ReconnectConfig) -> Result<()> {
+    if config.interval_ms == 0 || config.interval_ms > MAX_INTERVAL_MS {
+        anyhow::bail!(
+            "reconnect interval_ms must be between 1 and {}",
+            MAX_INTERVAL_MS
+        );
+    }
+    if config.max_interval_ms < config.interval_ms || config.max_interval_ms > MAX_INTERVAL_MS {
+        anyhow::bail!(
+            "reconnect max_interval_ms must be between interval_ms and {}",
+            MAX_INTERVAL_MS
+        );
+    }
+    if config.max_attempts == Some(0) {
+        anyhow::bail!("reconnect max_attempts must be greater than 0");
+    }
+    Ok(())
+}
+
+/// The waits before each attempt: `interval_ms`, doubling up to
+/// `max_interval_ms`, until `max_attempts` have been made.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    next: Duration,
+    max: Duration,
+    attempts: u32,
+    max_attempts: Option<u32>,
+}
+
+impl Backoff {
+    pub fn new(config: &ReconnectConfig) -> Self {
+        Self {
+            next: Duration::from_millis(config.interval_ms),
+            max: Duration::from_millis(config.max_interval_ms),
+            attempts: 0,
+            max_attempts: config.max_attempts,
+        }
+    }
+
+    /// How long to wait before the next attempt, or `None` once all of
+    /// them have been made.
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| self.attempts >= max) {
+            return None;
+        }
+        self.attempts += 1;
+        let delay = self.next;
+        self.next = (self.next * 2).min(self.max);
+        Some(delay)
+    }
+
+    /// Attempts made so far.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ReconnectStats {
+    /// True while the port is gone and being reopened.
+    pub reconnecting: bool,
+    /// Failed attempts to reopen the port since it was last lost.
+    pub attempts: u32,
+    /// Times the port was reopened after being lost.
+    pub reconnects: u64,
+    /// Why the port was lost, or why the last attempt failed.
+    pub last_error: Option<String>,
+    pub lost_at: Option<DateTime<Utc>>,
+    pub reconnected_at: Option<DateTime<Utc>>,
+}
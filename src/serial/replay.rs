@@ -0,0 +1,229 @@
+//! Numbered copies of the chunks most recently broadcast to subscribers, so
+//! a client that reconnects after a brief outage can pick up where it left
+//! off instead of losing what arrived in between.
+
+use super::annotations::{Annotation, AnnotationRequest, Annotations};
+use super::SerialData;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+
+/// Chunks kept per connection, the same as a subscriber may lag behind.
+pub const REPLAY_CHUNKS: usize = 1000;
+
+/// Most named read cursors a connection keeps; a new one beyond that
+/// replaces the one read least recently.
+pub const MAX_CURSORS: usize = 64;
+
+/// Longest a long-poll for new chunks may wait, in seconds.
+pub const MAX_POLL_SECS: u64 = 120;
+
+/// Why a replay does not start right after the requested chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum ReplayGap {
+    /// Chunks `first` to `last` were broadcast but are no longer kept.
+    Expired { first: u64, last: u64 },
+    /// The sequence number was never given out, for example because it
+    /// is from before the connection was reopened.
+    Unknown,
+}
+
+/// What a subscriber missed, and where its live chunks start.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Replay {
+    /// Sequence number of the first chunk the new subscription receives.
+    pub next_seq: u64,
+    /// Buffered chunks after the requested one, oldest first.
+    pub chunks: Vec<(u64, SerialData)>,
+    pub gap: Option<ReplayGap>,
+}
+
+/// A buffered chunk as returned by the frames endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FrameRecord {
+    pub seq: u64,
+    /// When the chunk was received.
+    pub timestamp: DateTime<Utc>,
+    /// The bytes as UTF-8, with invalid sequences replaced.
+    pub text: String,
+    pub hex: String,
+}
+
+/// One page of buffered chunks, oldest first.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FramePage {
+    pub frames: Vec<FrameRecord>,
+    /// Sequence number of the newest chunk so far, 0 before the first.
+    pub latest_seq: u64,
+    /// Set when chunks after the requested one can no longer be returned.
+    pub gap: Option<ReplayGap>,
+    /// Annotations following the chunks on this page.
+    pub annotations: Vec<Annotation>,
+}
+
+#[derive(Debug)]
+struct Chunk {
+    seq: u64,
+    at: DateTime<Utc>,
+    data: SerialData,
+}
+
+/// The last [`REPLAY_CHUNKS`] received chunks, numbered from 1 in the
+/// order they were broadcast.
+#[derive(Debug)]
+pub struct ReplayBuffer {
+    next_seq: u64,
+    chunks: VecDeque<Chunk>,
+    /// Last chunk returned to each named reader, and the read that
+    /// returned it.
+    cursors: HashMap<String, (u64, u64)>,
+    /// Cursor reads so far, to tell which cursor was read least recently.
+    reads: u64,
+    annotations: Annotations,
+}
+
+impl Default for ReplayBuffer {
+    fn default() -> Self {
+        Self {
+            next_seq: 1,
+            chunks: VecDeque::new(),
+            cursors: HashMap::new(),
+            reads: 0,
+            annotations: Annotations::default(),
+        }
+    }
+}
+
+impl ReplayBuffer {
+    /// Keeps a chunk about to be broadcast, returning its sequence number.
+    pub fn push(&mut self, data: SerialData, at: DateTime<Utc>) -> u64 {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        if self.chunks.len() == REPLAY_CHUNKS {
+            self.chunks.pop_front();
+        }
+        self.chunks.push_back(Chunk { seq, at, data });
+        seq
+    }
+
+    fn gap(&self, since: u64) -> Option<ReplayGap> {
+        if since >= self.next_seq {
+            return Some(ReplayGap::Unknown);
+        }
+        let oldest = self.chunks.front().map_or(self.next_seq, |chunk| chunk.seq);
+        (since + 1 < oldest).then(|| ReplayGap::Expired {
+            first: since + 1,
+            last: oldest - 1,
+        })
+    }
+
+    fn after(&self, since: u64) -> impl Iterator<Item = &Chunk> {
+        self.chunks.iter().filter(move |chunk| chunk.seq > since)
+    }
+
+    /// The chunks after `since`, or none without it. `Some(0)` asks for
+    /// everything still kept.
+    pub fn since(&self, since: Option<u64>) -> Replay {
+        let mut replay = Replay {
+            next_seq: self.next_seq,
+            ..Replay::default()
+        };
+        let Some(since) = since else {
+            return replay;
+        };
+        replay.gap = self.gap(since);
+        replay.chunks = self
+            .after(since)
+            .map(|chunk| (chunk.seq, chunk.data.clone()))
+            .collect();
+        replay
+    }
+
+    /// Up to `limit` chunks after `since`, for paging through the buffer
+    /// by passing the last `seq` returned.
+    pub fn page(&self, since: u64, limit: usize) -> FramePage {
+        let frames: Vec<FrameRecord> = self
+            .after(since)
+            .take(limit)
+            .map(|chunk| FrameRecord {
+                seq: chunk.seq,
+                timestamp: chunk.at,
+                text: String::from_utf8_lossy(&chunk.data).into_owned(),
+                hex: hex::encode(&chunk.data),
+            })
+            .collect();
+        let annotations = match frames.last() {
+            Some(last) => self.annotations.between(since + 1, last.seq),
+            None => Vec::new(),
+        };
+        FramePage {
+            frames,
+            latest_seq: self.next_seq - 1,
+            gap: self.gap(since),
+            annotations,
+        }
+    }
+
+    /// Keeps a validated note, following chunk `request.seq` if given, the
+    /// last chunk received by `request.timestamp` if that is given, or
+    /// else the newest chunk. `None` if `request.seq` is not kept.
+    pub fn annotate(
+        &mut self,
+        request: AnnotationRequest,
+        now: DateTime<Utc>,
+    ) -> Option<Annotation> {
+        let (seq, timestamp) = match (request.seq, request.timestamp) {
+            (Some(seq), _) => {
+                let chunk = self.chunks.iter().find(|chunk| chunk.seq == seq)?;
+                (seq, chunk.at)
+            }
+            (None, Some(timestamp)) => {
+                // Before everything kept, the chunk just older is the best guess
+                let oldest = self.chunks.front().map_or(self.next_seq, |chunk| chunk.seq);
+                let seq = self
+                    .chunks
+                    .iter()
+                    .rev()
+                    .find(|chunk| chunk.at <= timestamp)
+                    .map_or(oldest - 1, |chunk| chunk.seq);
+                (seq, timestamp)
+            }
+            (None, None) => (self.next_seq - 1, now),
+        };
+        Some(self.annotations.add(request, seq, timestamp, now))
+    }
+
+    /// Annotations following chunks after `since`, `0` for all.
+    pub fn annotations(&self, since: u64) -> Vec<Annotation> {
+        let first = if since == 0 { 0 } else { since + 1 };
+        self.annotations.between(first, u64::MAX)
+    }
+
+    /// Up to `limit` chunks after the ones `cursor` was last given, moving
+    /// it past them. A new cursor starts at the oldest chunk still kept;
+    /// with [`MAX_CURSORS`] already, the one read least recently is dropped.
+    pub fn read(&mut self, cursor: &str, limit: usize) -> FramePage {
+        let since = match self.cursors.get(cursor) {
+            Some(&(since, _)) => since,
+            None => {
+                if self.cursors.len() >= MAX_CURSORS {
+                    let idle = self
+                        .cursors
+                        .iter()
+                        .min_by_key(|(_, &(_, read))| read)
+                        .map(|(name, _)| name.clone());
+                    if let Some(idle) = idle {
+                        self.cursors.remove(&idle);
+                    }
+                }
+                0
+            }
+        };
+        let page = self.page(since, limit);
+        let next = page.frames.last().map_or(since, |frame| frame.seq);
+        self.reads += 1;
+        self.cursors.insert(cursor.to_string(), (next, self.reads));
+        page
+    }
+}
@@ -0,0 +1,209 @@
+//! Protocol rules (`rules`): exchanges a connection's traffic must follow,
+//! such as every `REQ` line being answered by an `ACK` within 500 ms, so
+//! webmux can act as a lightweight protocol monitor.
+
+use super::Direction;
+use crate::config::RuleConfig;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+/// Longest a rule may wait for an answer.
+pub const MAX_WITHIN_MS: u64 = 3_600_000;
+
+/// Lines a rule waits on at once; the oldest are forgotten beyond this.
+const MAX_PENDING: usize = 1000;
+
+/// Longest partial line buffered while waiting for a newline.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Checks a connection's rule definitions.
+pub fn validate(rules: &[RuleConfig]) -> Result<()> {
+    let mut names = HashSet::new();
+    for rule in rules {
+        if !names.insert(&rule.name) {
+            anyhow::bail!("Duplicate rule name: {}", rule.name);
+        }
+        compile(rule)?;
+        if rule.within_ms == 0 || rule.within_ms > MAX_WITHIN_MS {
+            anyhow::bail!(
+                "Rule {}: within_ms must be between 1 and {}",
+                rule.name,
+                MAX_WITHIN_MS
+            );
+        }
+    }
+    Ok(())
+}
+
+fn compile(rule: &RuleConfig) -> Result<(Regex, Regex)> {
+    let when = Regex::new(&rule.when)
+        .map_err(|e| anyhow::anyhow!("Rule {}: invalid when: {}", rule.name, e))?;
+    let expect = Regex::new(&rule.expect)
+        .map_err(|e| anyhow::anyhow!("Rule {}: invalid expect: {}", rule.name, e))?;
+    Ok((when, expect))
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RuleStats {
+    pub name: String,
+    /// Lines answered in time.
+    pub answered: u64,
+    pub violations: u64,
+    pub last_violation: Option<DateTime<Utc>>,
+}
+
+/// A line that went unanswered.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleViolation {
+    pub rule: String,
+    /// The line that matched `when`.
+    pub line: String,
+    pub direction: Direction,
+    pub within_ms: u64,
+}
+
+impl RuleViolation {
+    pub fn describe(&self) -> String {
+        let sent = match self.direction {
+            Direction::Rx => "received",
+            Direction::Tx => "sent",
+        };
+        format!(
+            "{} line {:?} was not answered within {} ms",
+            sent, self.line, self.within_ms
+        )
+    }
+}
+
+struct Rule {
+    config: RuleConfig,
+    when: Regex,
+    expect: Regex,
+    /// Lines matching `when` still waiting for an answer, oldest first.
+    pending: VecDeque<(Instant, Direction, String)>,
+    stats: RuleStats,
+}
+
+/// Follows one connection's traffic in both directions, line by line.
+pub struct RuleChecker {
+    rules: Vec<Rule>,
+    /// Unterminated lines, received and sent.
+    partial: [Vec<u8>; 2],
+}
+
+impl RuleChecker {
+    /// `None` without rules. The rules must have been validated.
+    pub fn new(configs: &[RuleConfig]) -> Option<Self> {
+        if configs.is_empty() {
+            return None;
+        }
+        let rules = configs
+            .iter()
+            .map(|config| {
+                let (when, expect) = compile(config).expect("rules are validated with the config");
+                Rule {
+                    config: config.clone(),
+                    when,
+                    expect,
+                    pending: VecDeque::new(),
+                    stats: RuleStats {
+                        name: config.name.clone(),
+                        ..Default::default()
+                    },
+                }
+            })
+            .collect();
+        Some(Self {
+            rules,
+            partial: [Vec::new(), Vec::new()],
+        })
+    }
+
+    /// Matches the complete lines in `data` against every rule, returning
+    /// the lines that went unanswered by `at`.
+    pub fn record(
+        &mut self,
+        direction: Direction,
+        data: &[u8],
+        at: Instant,
+        now: DateTime<Utc>,
+    ) -> Vec<RuleViolation> {
+        // A late answer must not settle a line that already timed out
+        let mut violations = self.expire(at, now);
+        let partial = &mut self.partial[direction as usize];
+        let mut lines = Vec::new();
+        for &byte in data {
+            if byte == b'\n' {
+                let line = String::from_utf8_lossy(partial);
+                lines.push(line.trim_end_matches('\r').to_string());
+                partial.clear();
+            } else if partial.len() < MAX_LINE_LENGTH {
+                partial.push(byte);
+            }
+        }
+
+        for line in lines {
+            for rule in &mut self.rules {
+                if rule.expect.is_match(&line) && rule.pending.pop_front().is_some() {
+                    rule.stats.answered += 1;
+                }
+                if rule.when.is_match(&line) {
+                    if rule.pending.len() == MAX_PENDING {
+                        rule.pending.pop_front();
+                    }
+                    rule.pending.push_back((at, direction, line.clone()));
+                }
+            }
+        }
+        violations.extend(self.expire(at, now));
+        violations
+    }
+
+    /// The lines whose time to be answered has run out by `at`.
+    pub fn expire(&mut self, at: Instant, now: DateTime<Utc>) -> Vec<RuleViolation> {
+        let mut violations = Vec::new();
+        for rule in &mut self.rules {
+            let within = Duration::from_millis(rule.config.within_ms);
+            while rule
+                .pending
+                .front()
+                .is_some_and(|(since, ..)| at.saturating_duration_since(*since) > within)
+            {
+                let (_, direction, line) = rule.pending.pop_front().expect("checked above");
+                rule.stats.violations += 1;
+                rule.stats.last_violation = Some(now);
+                violations.push(RuleViolation {
+                    rule: rule.config.name.clone(),
+                    line,
+                    direction,
+                    within_ms: rule.config.within_ms,
+                });
+            }
+        }
+        violations
+    }
+
+    /// When the oldest line waiting for an answer runs out of time.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.rules
+            .iter()
+            .filter_map(|rule| {
+                let (since, ..) = rule.pending.front()?;
+                // Just past the limit, as answers at exactly `within_ms` count
+                Some(
+                    *since
+                        + Duration::from_millis(rule.config.within_ms)
+                        + Duration::from_millis(1),
+                )
+            })
+            .min()
+    }
+
+    pub fn stats(&self) -> Vec<RuleStats> {
+        self.rules.iter().map(|rule| rule.stats.clone()).collect()
+    }
+}
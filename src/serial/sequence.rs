@@ -0,0 +1,109 @@
+//! Multi-step exchanges, such as a protocol handshake, run as one
+//! transaction: no other client's writes go out between the steps.
+
+use super::latency::wait_for_echo;
+use super::SerialConnection;
+use anyhow::Result;
+use serde::Serialize;
+use std::time::Duration;
+use tokio::time::Instant;
+
+pub const MAX_STEPS: usize = 100;
+/// Longest pause after a step, and longest wait for its reply.
+pub const MAX_STEP_WAIT_MS: u64 = 60_000;
+/// Longest a whole sequence may hold the transaction, counting every
+/// reply wait and pause at its limit.
+pub const MAX_SEQUENCE_MS: u64 = 300_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceStep {
+    pub data: Vec<u8>,
+    /// Received bytes to wait for once the data is written, ending the
+    /// sequence if they do not arrive within `timeout`.
+    pub expect: Option<Vec<u8>>,
+    pub timeout: Duration,
+    /// Pause before the next step.
+    pub delay: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StepReport {
+    pub bytes_written: usize,
+    /// Whether the expected reply arrived; `None` without `expect`.
+    pub matched: Option<bool>,
+    /// From the start of the step until its data was written and its
+    /// reply received.
+    pub elapsed_ms: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SequenceReport {
+    /// False if an expected reply did not arrive, which ends the sequence.
+    pub completed: bool,
+    /// One report per step run, in order.
+    pub steps: Vec<StepReport>,
+}
+
+/// How long `steps` can take at most: each reply wait at its timeout, and
+/// each pause before a next step.
+pub fn longest_wait(steps: &[SequenceStep]) -> Duration {
+    let waits = steps
+        .iter()
+        .filter(|step| step.expect.is_some())
+        .map(|step| step.timeout);
+    let delays = steps
+        .iter()
+        .take(steps.len().saturating_sub(1))
+        .map(|step| step.delay);
+    waits.chain(delays).sum()
+}
+
+/// Runs `steps` in order while holding the connection's write transaction.
+/// Fails without writing if they could take longer than
+/// [`MAX_SEQUENCE_MS`].
+pub async fn run(connection: &SerialConnection, steps: &[SequenceStep]) -> Result<SequenceReport> {
+    let longest = longest_wait(steps);
+    if longest > Duration::from_millis(MAX_SEQUENCE_MS) {
+        anyhow::bail!(
+            "Steps could wait {} ms in total, more than {}",
+            longest.as_millis(),
+            MAX_SEQUENCE_MS
+        );
+    }
+    let transaction = connection.transaction().await?;
+    let mut reports = Vec::with_capacity(steps.len());
+
+    for (i, step) in steps.iter().enumerate() {
+        if i > 0 && !steps[i - 1].delay.is_zero() {
+            tokio::time::sleep(steps[i - 1].delay).await;
+        }
+
+        // Subscribed before writing so a quick reply is not missed
+        let mut rx = connection.subscribe();
+        let started = Instant::now();
+        transaction.write(&step.data).await?;
+        let matched = match step.expect {
+            Some(ref expect) => {
+                let deadline = Instant::now() + step.timeout;
+                Some(wait_for_echo(&mut rx, expect, deadline).await)
+            }
+            None => None,
+        };
+        reports.push(StepReport {
+            bytes_written: step.data.len(),
+            matched,
+            elapsed_ms: started.elapsed().as_secs_f64() * 1000.0,
+        });
+        if matched == Some(false) {
+            return Ok(SequenceReport {
+                completed: false,
+                steps: reports,
+            });
+        }
+    }
+
+    Ok(SequenceReport {
+        completed: true,
+        steps: reports,
+    })
+}
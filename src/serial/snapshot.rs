@@ -0,0 +1,160 @@
+//! Exporting the running connections as configuration, and bringing them
+//! in line with an imported configuration, for backing up and cloning a
+//! server's setup.
+
+use super::SerialManager;
+use crate::config::drift::{self, ModifiedConnection};
+use crate::config::SerialConnectionConfig;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ImportFailure {
+    pub name: String,
+    pub error: String,
+}
+
+/// What an import changed, or would change on a dry run.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    /// Connections in the import that were not running.
+    pub open: Vec<String>,
+    /// Running connections the import leaves out.
+    pub close: Vec<String>,
+    /// Running connections whose settings the import changes, closed and
+    /// opened again with the new ones.
+    pub reopen: Vec<ModifiedConnection>,
+    /// Server settings that differ from the running ones; they only take
+    /// effect once the imported file is used for a restart.
+    pub restart_required: Vec<String>,
+    /// Connections that could not be opened or closed.
+    pub failed: Vec<ImportFailure>,
+}
+
+impl SerialManager {
+    /// Names of the connections that are ends of virtual pairs.
+    async fn virtual_endpoints(&self) -> Vec<String> {
+        self.virtual_pairs
+            .read()
+            .await
+            .values()
+            .flat_map(|pair| [pair.a.clone(), pair.b.clone()])
+            .collect()
+    }
+
+    /// Configs of the running connections other than virtual pairs.
+    async fn running_configs(&self) -> Vec<SerialConnectionConfig> {
+        let endpoints = self.virtual_endpoints().await;
+        let connections = self.connections.read().await;
+        connections
+            .values()
+            .filter(|connection| !endpoints.contains(&connection.config().name))
+            .map(|connection| connection.config().clone())
+            .collect()
+    }
+
+    /// The connections as they run now, as configuration: those from the
+    /// configuration files in file order, with the disabled ones, then
+    /// those opened or disabled at runtime. Virtual pairs are left out.
+    pub async fn export_connections(&self) -> Result<Vec<SerialConnectionConfig>> {
+        let mut running: HashMap<String, SerialConnectionConfig> = self
+            .running_configs()
+            .await
+            .into_iter()
+            .map(|config| (config.name.clone(), config))
+            .collect();
+        let configured = match self.config_files() {
+            Some(files) => files.connections()?,
+            None => Vec::new(),
+        };
+
+        // Disabled through the API, wherever they came from
+        let mut disabled: HashMap<String, SerialConnectionConfig> = self
+            .disabled_connections()
+            .await
+            .into_iter()
+            .map(|config| (config.name.clone(), config))
+            .collect();
+
+        let mut exported = Vec::new();
+        for config in configured {
+            if let Some(running) = running.remove(&config.name) {
+                exported.push(running);
+            } else if let Some(disabled) = disabled.remove(&config.name) {
+                exported.push(disabled);
+            } else if !config.enabled {
+                exported.push(config);
+            }
+        }
+        let mut added: Vec<SerialConnectionConfig> = running
+            .into_values()
+            .chain(disabled.into_values())
+            .collect();
+        added.sort_by(|a, b| a.name.cmp(&b.name));
+        exported.extend(added);
+        Ok(exported)
+    }
+
+    /// Opens, closes and reopens connections so that the running ones
+    /// match the enabled ones in `configs`, honouring dependencies and
+    /// startup delays as at startup.
+    /// Virtual pairs are left alone. With `dry_run`, only reports what
+    /// would be done.
+    pub async fn import_connections(
+        &self,
+        configs: Vec<SerialConnectionConfig>,
+        dry_run: bool,
+    ) -> ImportReport {
+        let diff = drift::diff(&configs, &self.running_configs().await);
+        let mut report = ImportReport {
+            dry_run,
+            open: diff.removed,
+            close: diff.added,
+            reopen: diff.modified,
+            ..Default::default()
+        };
+        if dry_run {
+            return report;
+        }
+
+        let mut failed = Vec::new();
+        let reopened = report.reopen.iter().map(|modified| &modified.name);
+        for name in report.close.iter().chain(reopened.clone()) {
+            if let Err(e) = self.remove_connection(name).await {
+                failed.push(ImportFailure {
+                    name: name.clone(),
+                    error: e.to_string(),
+                });
+            }
+        }
+
+        let opening: Vec<&String> = report.open.iter().chain(reopened).collect();
+        let endpoints = self.virtual_endpoints().await;
+        let mut to_open = Vec::new();
+        for config in configs
+            .into_iter()
+            .filter(|config| opening.contains(&&config.name))
+        {
+            if endpoints.contains(&config.name) {
+                failed.push(ImportFailure {
+                    error: format!("Connection {} is the end of a virtual pair", config.name),
+                    name: config.name,
+                });
+            } else {
+                to_open.push(config);
+            }
+        }
+        for (name, result) in self.start_connections(to_open).await {
+            if let Err(e) = result {
+                failed.push(ImportFailure {
+                    name,
+                    error: e.to_string(),
+                });
+            }
+        }
+        report.failed = failed;
+        report
+    }
+}
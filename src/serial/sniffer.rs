@@ -0,0 +1,176 @@
+//! Sniffers: two connections whose ports listen to the TX and RX lines of
+//! a link under test, merged into one stream labelled by direction, as a
+//! poor man's protocol analyzer.
+
+use super::capture::serialize_hex;
+use super::{Direction, SerialConnection, Traffic};
+use crate::config::SnifferConfig;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tracing::{info, warn};
+
+/// Records a slow watcher may fall behind by before it misses some.
+const CHANNEL_RECORDS: usize = 1000;
+
+/// One chunk seen on the link.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnifferRecord {
+    pub timestamp: DateTime<Utc>,
+    /// Microseconds since the sniffer started, on the monotonic clock.
+    pub offset_us: u64,
+    /// `tx` for the line the `tx` connection listens to.
+    pub direction: Direction,
+    #[serde(serialize_with = "serialize_hex")]
+    pub data: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SnifferInfo {
+    pub name: String,
+    pub tx: String,
+    pub rx: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capture_file: Option<PathBuf>,
+    pub started_at: DateTime<Utc>,
+    /// False once either connection has gone away.
+    pub running: bool,
+    pub bytes_tx: u64,
+    pub bytes_rx: u64,
+    /// Chunks missed because the sniffer fell behind a connection.
+    pub dropped_chunks: u64,
+}
+
+pub(crate) struct Sniffer {
+    info: Arc<Mutex<SnifferInfo>>,
+    records: broadcast::Sender<SnifferRecord>,
+    task: JoinHandle<()>,
+}
+
+impl Sniffer {
+    /// Taps both connections and merges what their ports receive.
+    pub(crate) async fn start(
+        config: &SnifferConfig,
+        tx: &SerialConnection,
+        rx: &SerialConnection,
+    ) -> Result<Self> {
+        let file = match config.capture_file {
+            Some(ref path) => Some(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await
+                    .map_err(|e| anyhow::anyhow!("Cannot open {}: {}", path.display(), e))?,
+            ),
+            None => None,
+        };
+        let taps = [tx.tap(), rx.tap()];
+        let start = Instant::now();
+        let info = Arc::new(Mutex::new(SnifferInfo {
+            name: config.name.clone(),
+            tx: config.tx.clone(),
+            rx: config.rx.clone(),
+            capture_file: config.capture_file.clone(),
+            started_at: Utc::now(),
+            running: true,
+            bytes_tx: 0,
+            bytes_rx: 0,
+            dropped_chunks: 0,
+        }));
+        let records = broadcast::channel(CHANNEL_RECORDS).0;
+        let task = tokio::spawn(merge(taps, start, info.clone(), records.clone(), file));
+        info!(
+            "Started sniffer {} on {} (tx) and {} (rx)",
+            config.name, config.tx, config.rx
+        );
+        Ok(Self {
+            info,
+            records,
+            task,
+        })
+    }
+
+    pub(crate) fn info(&self) -> SnifferInfo {
+        self.info.lock().unwrap().clone()
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<SnifferRecord> {
+        self.records.subscribe()
+    }
+}
+
+impl Drop for Sniffer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Labels what each tap's port receives with its line and passes it on
+/// in the order it arrives, until either connection goes away.
+async fn merge(
+    [mut tx_tap, mut rx_tap]: [broadcast::Receiver<Traffic>; 2],
+    start: Instant,
+    info: Arc<Mutex<SnifferInfo>>,
+    records: broadcast::Sender<SnifferRecord>,
+    mut file: Option<tokio::fs::File>,
+) {
+    let started_at = info.lock().unwrap().started_at;
+    loop {
+        let (direction, result) = tokio::select! {
+            result = tx_tap.recv() => (Direction::Tx, result),
+            result = rx_tap.recv() => (Direction::Rx, result),
+        };
+        let traffic = match result {
+            // Anything written to a listening port is not on the link
+            Ok(traffic) if traffic.direction == Direction::Tx => continue,
+            Ok(traffic) => traffic,
+            Err(broadcast::error::RecvError::Lagged(missed)) => {
+                info.lock().unwrap().dropped_chunks += missed;
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        let offset = traffic.at.saturating_duration_since(start);
+        let record = SnifferRecord {
+            timestamp: started_at + offset,
+            offset_us: offset.as_micros() as u64,
+            direction,
+            data: traffic.data,
+        };
+        {
+            let mut info = info.lock().unwrap();
+            match direction {
+                Direction::Tx => info.bytes_tx += record.data.len() as u64,
+                Direction::Rx => info.bytes_rx += record.data.len() as u64,
+            }
+        }
+        if let Some(ref mut writer) = file {
+            let mut line = serde_json::to_vec(&record).expect("records serialize");
+            line.push(b'\n');
+            // Flushed per record, so the file is complete up to what was sent
+            let written = match writer.write_all(&line).await {
+                Ok(()) => writer.flush().await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = written {
+                let name = info.lock().unwrap().name.clone();
+                warn!("Sniffer {} stopped writing its capture file: {}", name, e);
+                file = None;
+            }
+        }
+        // Nobody may be watching, which is fine
+        let _ = records.send(record);
+    }
+
+    let mut info = info.lock().unwrap();
+    info.running = false;
+    info!("Sniffer {} stopped: a connection went away", info.name);
+}
@@ -0,0 +1,155 @@
+//! What became of each configured connection when it was opened.
+
+use crate::config::SerialConnectionConfig;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StartupState {
+    /// Waiting for a dependency, its startup delay or the port to open.
+    Pending,
+    Opened,
+    Failed,
+    /// Configured with `enabled: false`, so never opened.
+    Disabled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StartupConnection {
+    pub name: String,
+    pub port: String,
+    pub state: StartupState,
+    /// Why a failed connection did not open, as the open reported it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// What a pending connection is waiting for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub waiting_for: Option<String>,
+    /// When the connection entered its state.
+    pub since: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StartupReport {
+    pub opened: usize,
+    pub failed: usize,
+    pub pending: usize,
+    pub disabled: usize,
+    /// In the order they were configured.
+    pub connections: Vec<StartupConnection>,
+}
+
+/// A configured connection that could not be opened, kept so it can be
+/// seen and retried from the API.
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedConnection {
+    pub name: String,
+    pub port: String,
+    pub error: String,
+    pub failed_at: DateTime<Utc>,
+    #[serde(skip)]
+    pub config: SerialConnectionConfig,
+}
+
+impl FailedConnection {
+    pub(crate) fn new(config: SerialConnectionConfig, error: &anyhow::Error) -> Self {
+        Self {
+            name: config.name.clone(),
+            port: config.port.clone(),
+            error: format!("{:#}", error),
+            failed_at: Utc::now(),
+            config,
+        }
+    }
+}
+
+/// Shared record of connection starts, updated as each one progresses.
+#[derive(Debug, Clone, Default)]
+pub struct StartupLog {
+    connections: Arc<Mutex<Vec<StartupConnection>>>,
+}
+
+impl StartupLog {
+    pub(crate) fn pending(&self, name: &str, port: &str, waiting_for: impl Into<String>) {
+        self.set(
+            name,
+            port,
+            StartupState::Pending,
+            None,
+            Some(waiting_for.into()),
+        );
+    }
+
+    pub(crate) fn finished(
+        &self,
+        name: &str,
+        port: &str,
+        enabled: bool,
+        result: &anyhow::Result<()>,
+    ) {
+        match result {
+            Ok(()) if enabled => self.set(name, port, StartupState::Opened, None, None),
+            Ok(()) => self.set(name, port, StartupState::Disabled, None, None),
+            Err(e) => self.set(
+                name,
+                port,
+                StartupState::Failed,
+                Some(format!("{:#}", e)),
+                None,
+            ),
+        }
+    }
+
+    fn set(
+        &self,
+        name: &str,
+        port: &str,
+        state: StartupState,
+        error: Option<String>,
+        waiting_for: Option<String>,
+    ) {
+        let entry = StartupConnection {
+            name: name.to_string(),
+            port: port.to_string(),
+            state,
+            error,
+            waiting_for,
+            since: Utc::now(),
+        };
+        let mut connections = self.connections.lock().unwrap();
+        match connections.iter_mut().find(|c| c.name == name) {
+            Some(existing) => *existing = entry,
+            None => connections.push(entry),
+        }
+    }
+
+    pub fn report(&self) -> StartupReport {
+        let connections = self.connections.lock().unwrap().clone();
+        let count = |state| connections.iter().filter(|c| c.state == state).count();
+        StartupReport {
+            opened: count(StartupState::Opened),
+            failed: count(StartupState::Failed),
+            pending: count(StartupState::Pending),
+            disabled: count(StartupState::Disabled),
+            connections,
+        }
+    }
+}
+
+pub(crate) fn startup_delay(config: &SerialConnectionConfig) -> String {
+    format!("startup delay of {} ms", config.startup_delay_ms)
+}
+
+/// Aborts tasks when dropped, so ones a caller stopped waiting for do not
+/// carry on.
+pub(super) struct AbortOnDrop(pub(super) Vec<tokio::task::AbortHandle>);
+
+impl Drop for AbortOnDrop {
+    fn drop(&mut self) {
+        for task in &self.0 {
+            task.abort();
+        }
+    }
+}
@@ -0,0 +1,2282 @@
+use super::batching::TxQueue;
+use super::history::TrafficHistory;
+use super::pacing::TokenBucket;
+use super::replay::{ReplayBuffer, REPLAY_CHUNKS};
+use super::throughput::ThroughputTracker;
+use super::*;
+use crate::config::{
+    ConnectionMode, DataBits, FlowControl, LoggingConfig, MirrorConfig, MirrorDirection,
+    MirrorTarget, Parity, SlcanConfig, StopBits, TxBatchConfig,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+/// Config for a connection on `port`, with everything optional switched off.
+pub(crate) fn test_config(name: &str, port: &str) -> SerialConnectionConfig {
+    SerialConnectionConfig {
+        name: name.to_string(),
+        port: port.to_string(),
+        baud_rate: 115200,
+        data_bits: DataBits::Eight,
+        stop_bits: StopBits::One,
+        parity: Parity::None,
+        flow_control: FlowControl::None,
+        enabled: true,
+        logging: LoggingConfig {
+            enabled: false,
+            path: "unused.log".into(),
+            raw: false,
+            max_file_bytes: None,
+            retention: None,
+            syslog: None,
+            encryption: None,
+            redact: None,
+            ship: None,
+        },
+        description: String::new(),
+        mode: ConnectionMode::Raw,
+        slcan: SlcanConfig::default(),
+        metrics: vec![],
+        notify: vec![],
+        mirror: None,
+        max_tx_rate_bytes_per_sec: None,
+        max_clients: None,
+        tx_batch: TxBatchConfig::default(),
+        suppress_echo: false,
+        idle_close_minutes: None,
+        depends_on: None,
+        startup_delay_ms: 0,
+        power: None,
+        banner: None,
+        write_lock: None,
+        quota: None,
+        garbage_check: None,
+        keepalive: None,
+        identify: None,
+        rules: vec![],
+        endpoints: vec![],
+        reconnect: None,
+    }
+}
+
+/// A pseudo-terminal: the device path a connection can open, and the
+/// controlling end that plays the device.
+pub(crate) fn pty_pair() -> (String, tokio_serial::SerialStream) {
+    let (device, slave) = tokio_serial::SerialStream::pair().unwrap();
+    let path = serialport::SerialPort::name(&slave).unwrap();
+    // Keep a slave handle open so the pty is not hung up before it is reopened
+    std::mem::forget(slave);
+    (path, device)
+}
+
+#[test]
+fn test_throughput_rates() {
+    let mut tracker = ThroughputTracker::default();
+    tracker.record_rx(0, 100, 1);
+    tracker.record_rx(1, 300, 3);
+    tracker.record_tx(1, 10, 1);
+    tracker.record_rx(2, 1000, 10);
+
+    // Second 2 is still in progress and is left out
+    let rates = tracker.rates(2, 2);
+    assert_eq!(rates.window_seconds, 2);
+    assert_eq!(rates.rx_bytes_per_sec, 200.0);
+    assert_eq!(rates.rx_frames_per_sec, 2.0);
+    assert_eq!(rates.tx_bytes_per_sec, 5.0);
+    assert_eq!(rates.tx_frames_per_sec, 0.5);
+
+    // Old buckets age out of the window
+    assert_eq!(tracker.rates(100, 10).rx_bytes_per_sec, 0.0);
+    assert_eq!(tracker.rates(100, 0).window_seconds, 1);
+}
+
+#[test]
+fn test_traffic_profile_distributions() {
+    use super::traffic_profile::TrafficProfiler;
+    use std::time::Instant;
+
+    let start = Instant::now();
+    let ms = |ms: u64| start + Duration::from_millis(ms);
+    let mut profiler = TrafficProfiler::new(b'\n');
+    profiler.record(Direction::Rx, b"OK\n", ms(0));
+    // A frame split across chunks starts when its first chunk arrives
+    profiler.record(Direction::Rx, b"TEMP:", ms(30));
+    profiler.record(Direction::Rx, b"23.5\nA\nB", ms(40));
+    profiler.record(Direction::Tx, b"AT\n", ms(50));
+
+    let profile = profiler.profile(60, ms(100));
+    assert_eq!(profile.window_seconds, 60);
+    let rx = profile.rx;
+    assert_eq!(rx.frame_bytes.count, 3);
+    assert_eq!(rx.frame_bytes.min, Some(2.0));
+    assert_eq!(rx.frame_bytes.max, Some(10.0));
+    let counts: Vec<u64> = rx.frame_bytes.buckets.iter().map(|b| b.count).collect();
+    assert_eq!(counts[..6], [0, 1, 1, 0, 1, 0]);
+    assert_eq!(rx.frame_bytes.buckets.last().unwrap().le, None);
+    // 30 ms before TEMP, nothing before A which arrived with it
+    assert_eq!(rx.gap_ms.count, 2);
+    assert_eq!(rx.gap_ms.min, Some(0.0));
+    assert_eq!(rx.gap_ms.max, Some(30.0));
+    assert_eq!(profile.tx.frame_bytes.count, 1);
+    assert_eq!(profile.tx.gap_ms.count, 0);
+
+    // Frames age out of the window
+    let later = start + Duration::from_secs(120);
+    assert_eq!(profiler.profile(60, later).rx.frame_bytes.count, 0);
+}
+
+#[test]
+fn test_traffic_history_frames_and_filters() {
+    let mut history = TrafficHistory::new(b'\n');
+    let at = chrono::Utc::now();
+    history.record(Direction::Rx, b"temp=2", at);
+    history.record(Direction::Tx, b"read\n", at);
+    history.record(Direction::Rx, b"1\nhum=40\nba", at);
+
+    let texts = |records: Vec<TrafficRecord>| -> Vec<(Direction, String, bool)> {
+        records
+            .into_iter()
+            .map(|record| (record.direction, record.text, record.partial))
+            .collect()
+    };
+    assert_eq!(
+        texts(history.tail(10, None)),
+        vec![
+            (Direction::Tx, "read\n".to_string(), false),
+            (Direction::Rx, "temp=21\n".to_string(), false),
+            (Direction::Rx, "hum=40\n".to_string(), false),
+            (Direction::Rx, "ba".to_string(), true),
+        ]
+    );
+    assert_eq!(
+        texts(history.tail(1, Some(Direction::Tx))),
+        vec![(Direction::Tx, "read\n".to_string(), false)]
+    );
+    assert_eq!(history.tail(1, None)[0].hex, "6261");
+
+    // Only the newest frames are kept
+    for _ in 0..history::MAX_FRAMES {
+        history.record(Direction::Tx, b"x\n", at);
+    }
+    assert!(history
+        .tail(history::MAX_FRAMES, Some(Direction::Rx))
+        .iter()
+        .all(|record| record.partial));
+}
+
+#[test]
+fn test_replay_buffer_resumes_after_sequence() {
+    let mut buffer = ReplayBuffer::default();
+    assert_eq!(buffer.since(None).next_seq, 1);
+    assert_eq!(buffer.since(Some(0)).chunks, vec![]);
+    assert_eq!(buffer.since(Some(1)).gap, Some(ReplayGap::Unknown));
+
+    for i in 0..REPLAY_CHUNKS + 5 {
+        buffer.push(i.to_string().into_bytes(), chrono::Utc::now());
+    }
+    let replay = buffer.since(Some(REPLAY_CHUNKS as u64 + 3));
+    assert_eq!(replay.next_seq, REPLAY_CHUNKS as u64 + 6);
+    assert_eq!(replay.gap, None);
+    assert_eq!(
+        replay.chunks,
+        vec![
+            (REPLAY_CHUNKS as u64 + 4, b"1003".to_vec()),
+            (REPLAY_CHUNKS as u64 + 5, b"1004".to_vec()),
+        ]
+    );
+
+    // The oldest chunks have made way for newer ones
+    let replay = buffer.since(Some(2));
+    assert_eq!(replay.gap, Some(ReplayGap::Expired { first: 3, last: 5 }));
+    assert_eq!(replay.chunks.len(), REPLAY_CHUNKS);
+    assert_eq!(replay.chunks[0].0, 6);
+
+    let page = buffer.page(REPLAY_CHUNKS as u64, 2);
+    assert_eq!(page.latest_seq, REPLAY_CHUNKS as u64 + 5);
+    assert_eq!(page.gap, None);
+    let seqs: Vec<u64> = page.frames.iter().map(|frame| frame.seq).collect();
+    assert_eq!(seqs, [REPLAY_CHUNKS as u64 + 1, REPLAY_CHUNKS as u64 + 2]);
+    assert_eq!(page.frames[0].text, "1000");
+}
+
+#[test]
+fn test_replay_buffer_read_cursors() {
+    use super::replay::MAX_CURSORS;
+
+    let mut buffer = ReplayBuffer::default();
+    for line in ["one", "two", "three"] {
+        buffer.push(line.as_bytes().to_vec(), chrono::Utc::now());
+    }
+    let texts = |page: super::FramePage| -> Vec<String> {
+        page.frames.into_iter().map(|frame| frame.text).collect()
+    };
+    assert_eq!(texts(buffer.read("a", 2)), ["one", "two"]);
+    assert_eq!(texts(buffer.read("a", 2)), ["three"]);
+    assert!(buffer.read("a", 2).frames.is_empty());
+    // Each cursor keeps its own place
+    assert_eq!(buffer.read("b", 10).frames.len(), 3);
+
+    buffer.push(b"four".to_vec(), chrono::Utc::now());
+    assert_eq!(texts(buffer.read("a", 10)), ["four"]);
+
+    for i in 2..MAX_CURSORS {
+        buffer.read(&i.to_string(), 1);
+    }
+    // One more replaces the cursor read least recently, "b"
+    assert_eq!(buffer.read("one too many", 1).frames.len(), 1);
+    assert!(buffer.read("a", 1).frames.is_empty());
+    assert_eq!(buffer.read("b", 10).frames.len(), 4);
+}
+
+#[test]
+fn test_replay_buffer_annotations() {
+    use super::annotations::AnnotationRequest;
+    use chrono::{Duration, TimeZone, Utc};
+
+    let start = Utc.with_ymd_and_hms(2024, 1, 15, 10, 0, 0).unwrap();
+    let mut buffer = ReplayBuffer::default();
+    for (i, line) in ["one", "two", "three"].iter().enumerate() {
+        buffer.push(
+            line.as_bytes().to_vec(),
+            start + Duration::seconds(i as i64),
+        );
+    }
+    let request = |seq, timestamp| AnnotationRequest {
+        text: "note".to_string(),
+        severity: Default::default(),
+        author: None,
+        seq,
+        timestamp,
+    };
+    let now = start + Duration::seconds(10);
+
+    // By sequence number, taking the chunk's time
+    let annotation = buffer.annotate(request(Some(2), None), now).unwrap();
+    assert_eq!(
+        (annotation.seq, annotation.timestamp),
+        (2, start + Duration::seconds(1))
+    );
+    // By time, following the last chunk received by then
+    let at = start + Duration::milliseconds(500);
+    let annotation = buffer.annotate(request(None, Some(at)), now).unwrap();
+    assert_eq!((annotation.seq, annotation.timestamp), (1, at));
+    // Neither: the newest chunk, now
+    let annotation = buffer.annotate(request(None, None), now).unwrap();
+    assert_eq!((annotation.seq, annotation.timestamp), (3, now));
+    // Chunks never received cannot be annotated
+    assert!(buffer.annotate(request(Some(4), None), now).is_none());
+
+    let seqs = |annotations: Vec<super::Annotation>| -> Vec<u64> {
+        annotations
+            .into_iter()
+            .map(|annotation| annotation.seq)
+            .collect()
+    };
+    assert_eq!(seqs(buffer.annotations(0)), [1, 2, 3]);
+    assert_eq!(seqs(buffer.annotations(2)), [3]);
+    // Pages carry the annotations of their own chunks only
+    assert_eq!(seqs(buffer.page(0, 2).annotations), [1, 2]);
+    assert_eq!(seqs(buffer.page(2, 10).annotations), [3]);
+    assert!(buffer.page(3, 10).annotations.is_empty());
+}
+
+#[tokio::test]
+async fn test_line_error_monitor_totals_and_reports() {
+    use super::line_errors::{LineErrorMonitor, LineErrors, LINE_ERROR_REPORT_INTERVAL};
+    use std::time::Instant;
+
+    let errors = |framing, parity, overrun| LineErrors {
+        framing,
+        parity,
+        overrun,
+    };
+    let start = Instant::now();
+    let mut monitor = LineErrorMonitor::default();
+    assert_eq!(monitor.update(errors(0, 0, 0), start), None);
+    assert_eq!(
+        monitor.update(errors(2, 0, 1), start),
+        Some(errors(2, 0, 1))
+    );
+
+    // Further errors wait for the next report
+    let soon = start + Duration::from_secs(5);
+    assert_eq!(monitor.update(errors(3, 1, 1), soon), None);
+    let later = start + LINE_ERROR_REPORT_INTERVAL;
+    assert_eq!(
+        monitor.update(errors(3, 1, 1), later),
+        Some(errors(1, 1, 0))
+    );
+
+    // Reopening the port restarts the driver's counters
+    monitor.reopened();
+    assert_eq!(monitor.update(errors(1, 0, 0), later), None);
+    assert_eq!(monitor.total(), errors(4, 1, 1));
+    assert_eq!(
+        monitor.total().describe(),
+        "4 framing, 1 parity and 1 overrun errors"
+    );
+
+    // Pseudo-terminals keep no counters
+    let (_path, pty) = pty_pair();
+    assert_eq!(super::line_errors::read(&pty), None);
+}
+
+#[test]
+fn test_garbage_detector_flags_noisy_windows() {
+    use super::garbage::GarbageDetector;
+
+    let mut detector = GarbageDetector::new(&crate::config::GarbageCheckConfig {
+        threshold_percent: 30,
+        window_bytes: 10,
+    });
+    assert_eq!(detector.record(b"login:\r\n\x1b["), None);
+    // 4 of 10 bytes are not printable, split across chunks
+    assert_eq!(detector.record(b"ab\xfe\x00"), None);
+    assert_eq!(detector.record(b"\x80cd\xffef"), Some(40));
+    // Still noisy: reported once only
+    assert_eq!(detector.record(&[0xf0; 10]), None);
+    assert_eq!(detector.record(b"Router>   "), None);
+    assert_eq!(detector.record(&[0xf0; 10]), Some(100));
+}
+
+#[test]
+fn test_rule_checker_matches_answers() {
+    use super::rules::RuleChecker;
+    use crate::config::RuleConfig;
+
+    let mut checker = RuleChecker::new(&[RuleConfig {
+        name: "req-ack".to_string(),
+        when: "^REQ".to_string(),
+        expect: "^ACK".to_string(),
+        within_ms: 500,
+    }])
+    .unwrap();
+    let start = std::time::Instant::now();
+    let now = chrono::Utc::now();
+    let ms = |ms| start + Duration::from_millis(ms);
+
+    // Answered in time, with the request split across writes
+    assert!(checker.record(Direction::Tx, b"RE", ms(0), now).is_empty());
+    assert!(checker
+        .record(Direction::Tx, b"Q 1\r\n", ms(0), now)
+        .is_empty());
+    assert_eq!(checker.next_deadline(), Some(ms(501)));
+    assert!(checker
+        .record(Direction::Rx, b"ACK 1\r\n", ms(500), now)
+        .is_empty());
+    assert_eq!(checker.next_deadline(), None);
+
+    // A late answer does not settle the request
+    checker.record(Direction::Tx, b"REQ 2\n", ms(1000), now);
+    let violations = checker.record(Direction::Rx, b"ACK 2\n", ms(1600), now);
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].line, "REQ 2");
+    assert_eq!(violations[0].direction, Direction::Tx);
+
+    checker.record(Direction::Tx, b"REQ 3\n", ms(2000), now);
+    assert!(checker.expire(ms(2400), now).is_empty());
+    assert_eq!(checker.expire(ms(2600), now).len(), 1);
+
+    let stats = &checker.stats()[0];
+    assert_eq!((stats.answered, stats.violations), (1, 2));
+    assert_eq!(stats.last_violation, Some(now));
+}
+
+#[test]
+fn test_latency_report_statistics() {
+    let mut samples: Vec<Option<f64>> = (1..=100).map(|ms| Some(ms as f64)).collect();
+    samples[10] = None;
+    let report = LatencyReport::from_samples(samples);
+
+    assert_eq!(report.sent, 100);
+    assert_eq!(report.received, 99);
+    assert_eq!(report.lost, 1);
+    assert_eq!(report.min_ms, Some(1.0));
+    assert_eq!(report.max_ms, Some(100.0));
+    assert_eq!(report.p50_ms, Some(51.0));
+    assert_eq!(report.p99_ms, Some(100.0));
+    assert_eq!(report.samples_ms[10], None);
+
+    let empty = LatencyReport::from_samples(vec![None, None]);
+    assert_eq!(empty.lost, 2);
+    assert_eq!(empty.avg_ms, None);
+}
+
+#[test]
+fn test_latency_options_validation() {
+    assert!(LatencyTestOptions::default().validate().is_ok());
+    let too_many = LatencyTestOptions {
+        count: latency::MAX_PROBES + 1,
+        ..Default::default()
+    };
+    assert!(too_many.validate().is_err());
+    let no_wait = LatencyTestOptions {
+        timeout_ms: 0,
+        ..Default::default()
+    };
+    assert!(no_wait.validate().is_err());
+}
+
+#[tokio::test]
+async fn test_latency_and_throughput_over_loopback() {
+    let (path, mut far_end) = pty_pair();
+    let manager = SerialManager::new();
+    manager
+        .add_connection(test_config("loop", &path))
+        .await
+        .unwrap();
+
+    // Echo everything back, like a TX-RX jumper
+    tokio::spawn(async move {
+        let mut buf = [0u8; 256];
+        while let Ok(n) = far_end.read(&mut buf).await {
+            if n == 0 || far_end.write_all(&buf[..n]).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let options = LatencyTestOptions {
+        count: 5,
+        timeout_ms: 2000,
+        interval_ms: 0,
+    };
+    let report = manager.latency_test("loop", &options).await.unwrap();
+    assert_eq!(report.sent, 5);
+    assert_eq!(report.received, 5);
+    assert!(report.min_ms.unwrap() <= report.max_ms.unwrap());
+
+    let stats = manager.get_stats("loop").await.unwrap();
+    assert!(stats.bytes_sent > 0);
+    assert_eq!(stats.bytes_received, stats.bytes_sent);
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_virtual_pair_is_cross_connected() {
+    let manager = SerialManager::new();
+    let info = manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: Some("device".to_string()),
+        })
+        .await
+        .unwrap();
+    assert_eq!(info.a, "nm-a");
+    assert_eq!(info.b, "device");
+
+    let mut device_rx = manager.subscribe("device").await.unwrap();
+    let mut host_rx = manager.subscribe("nm-a").await.unwrap();
+
+    manager.send_data("nm-a", b"ping\n").await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(2), device_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, b"ping\n");
+
+    manager.send_data("device", b"pong\n").await.unwrap();
+    let received = tokio::time::timeout(Duration::from_secs(2), host_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, b"pong\n");
+
+    // Neither end has a port to lend out
+    let connection = manager.get_connection("device").await.unwrap();
+    assert!(connection.is_virtual());
+    assert!(connection.acquire_exclusive().await.is_err());
+
+    manager.remove_virtual_pair("nm").await.unwrap();
+    assert!(manager.list_connections().await.is_empty());
+    assert!(manager.list_virtual_pairs().await.is_empty());
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn test_injected_faults() {
+    use super::faults::FaultSettings;
+
+    let manager = SerialManager::new();
+    manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let faults = manager
+        .get_connection("nm-a")
+        .await
+        .unwrap()
+        .faults()
+        .clone();
+    faults
+        .set(FaultSettings {
+            write_delay_ms: 0,
+            drop_every: 2,
+        })
+        .unwrap();
+    assert!(faults
+        .set(FaultSettings {
+            write_delay_ms: 600_000,
+            drop_every: 0,
+        })
+        .is_err());
+    let mut rx = manager.subscribe("nm-a").await.unwrap();
+
+    // Every second chunk is dropped; wait for each so none are merged
+    for (i, line) in ["one\n", "two\n", "three\n"].iter().enumerate() {
+        manager.send_data("nm-b", line.as_bytes()).await.unwrap();
+        if i == 1 {
+            tokio::time::timeout(Duration::from_secs(2), async {
+                while faults.status().dropped_chunks == 0 {
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+            })
+            .await
+            .unwrap();
+        } else {
+            let received = tokio::time::timeout(Duration::from_secs(2), rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(received, line.as_bytes());
+        }
+    }
+
+    // A read error loses the connection even while nothing arrives
+    faults.fail_next_read();
+    tokio::time::timeout(Duration::from_secs(2), async {
+        let connection = manager.get_connection("nm-a").await.unwrap();
+        while connection.get_stats().await.is_connected {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let status = faults.status();
+    assert_eq!((status.read_errors, status.read_error_pending), (1, false));
+}
+
+#[tokio::test]
+async fn test_expiring_connections_are_removed() {
+    let manager = SerialManager::new();
+    let (path, _device) = pty_pair();
+    manager
+        .add_connection(test_config("scratch", &path))
+        .await
+        .unwrap();
+    manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+
+    let expires_at = manager
+        .expire_connection("scratch", Duration::from_millis(100))
+        .await
+        .unwrap();
+    assert_eq!(manager.connection_expiry("scratch").await, Some(expires_at));
+    manager
+        .expire_virtual_pair("nm", Duration::from_millis(100))
+        .await
+        .unwrap();
+    assert!(manager.list_virtual_pairs().await[0].expires_at.is_some());
+    assert!(manager
+        .expire_connection("missing", Duration::from_secs(1))
+        .await
+        .is_err());
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+    while !manager.list_connections().await.is_empty() {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert!(manager.list_virtual_pairs().await.is_empty());
+    assert_eq!(manager.connection_expiry("scratch").await, None);
+}
+
+#[tokio::test]
+async fn test_removing_a_connection_cancels_its_expiry() {
+    let manager = SerialManager::new();
+    let (path, _device) = pty_pair();
+    manager
+        .add_connection(test_config("scratch", &path))
+        .await
+        .unwrap();
+    manager
+        .expire_connection("scratch", Duration::from_millis(100))
+        .await
+        .unwrap();
+    manager.remove_connection("scratch").await.unwrap();
+
+    // A new connection under the same name is not removed by the old expiry
+    let (path, _device) = pty_pair();
+    manager
+        .add_connection(test_config("scratch", &path))
+        .await
+        .unwrap();
+    assert_eq!(manager.connection_expiry("scratch").await, None);
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    assert_eq!(manager.list_connections().await, vec!["scratch"]);
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_adding_a_taken_name_keeps_the_open_connection() {
+    let manager = SerialManager::new();
+    let (first, _first_device) = pty_pair();
+    let (second, _second_device) = pty_pair();
+    let (a, b) = tokio::join!(
+        manager.add_connection(test_config("race", &first)),
+        manager.add_connection(test_config("race", &second)),
+    );
+    let (opened, refused) = match (a, b) {
+        (Ok(()), Err(e)) => (&first, e),
+        (Err(e), Ok(())) => (&second, e),
+        results => panic!("expected one of the two to open: {:?}", results),
+    };
+    assert!(refused.downcast_ref::<ConnectionExists>().is_some());
+    let connection = manager.get_connection("race").await.unwrap();
+    assert_eq!(&connection.config().port, opened);
+    assert_eq!(manager.list_connections().await, vec!["race"]);
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_interactive_writes_overtake_bulk_transfers() {
+    let manager = SerialManager::new();
+    manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let mut device_rx = manager.subscribe("nm-b").await.unwrap();
+
+    let upload = vec![b'.'; 16 * connection::BULK_CHUNK_BYTES];
+    manager
+        .send_data_with_priority("nm-a", &upload, Priority::Bulk)
+        .await
+        .unwrap();
+    manager.send_data("nm-a", b"k").await.unwrap();
+
+    let mut received = Vec::new();
+    while received.len() < upload.len() + 1 {
+        let chunk = tokio::time::timeout(Duration::from_secs(2), device_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.extend_from_slice(&chunk);
+    }
+    // The keystroke waits for at most one chunk of the upload
+    let position = received.iter().position(|&b| b == b'k').unwrap();
+    assert!(position <= connection::BULK_CHUNK_BYTES, "{}", position);
+    assert_eq!(
+        received.iter().filter(|&&b| b == b'.').count(),
+        upload.len()
+    );
+}
+
+#[tokio::test]
+async fn test_mirror_copies_both_directions_to_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let mirror_path = dir.path().join("mirror.bin");
+    let (path, mut far_end) = pty_pair();
+
+    let mut config = test_config("tapped", &path);
+    config.mirror = Some(MirrorConfig {
+        target: MirrorTarget::File {
+            path: mirror_path.clone(),
+        },
+        direction: MirrorDirection::Both,
+    });
+    let manager = SerialManager::new();
+    manager.add_connection(config).await.unwrap();
+
+    let mut rx = manager.subscribe("tapped").await.unwrap();
+    far_end.write_all(b"reading\n").await.unwrap();
+    tokio::time::timeout(Duration::from_secs(2), rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    manager.send_data("tapped", b"cmd\n").await.unwrap();
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+    loop {
+        let mirrored = tokio::fs::read(&mirror_path).await.unwrap_or_default();
+        if mirrored == b"reading\ncmd\n" {
+            break;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "mirror has {:?}",
+            String::from_utf8_lossy(&mirrored)
+        );
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_sniffer_merges_both_lines() {
+    let manager = SerialManager::new();
+    // Each tap receives one line of the link under test
+    for (name, tap) in [("line-tx", "tap-tx"), ("line-rx", "tap-rx")] {
+        manager
+            .add_virtual_pair(&VirtualPairRequest {
+                name: name.to_string(),
+                a: None,
+                b: Some(tap.to_string()),
+            })
+            .await
+            .unwrap();
+    }
+    let path = std::env::temp_dir().join(format!("webmux-sniffer-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let config = SnifferConfig {
+        name: "link".to_string(),
+        tx: "tap-tx".to_string(),
+        rx: "tap-rx".to_string(),
+        capture_file: Some(path.clone()),
+    };
+    manager.add_sniffer(&config).await.unwrap();
+    assert!(manager.add_sniffer(&config).await.is_err());
+    let mut records = manager.subscribe_sniffer("link").await.unwrap();
+
+    manager.send_data("line-tx-a", b"REQ").await.unwrap();
+    let record = tokio::time::timeout(Duration::from_secs(2), records.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.direction, Direction::Tx);
+    assert_eq!(record.data, b"REQ");
+
+    manager.send_data("line-rx-a", b"ACK").await.unwrap();
+    let record = tokio::time::timeout(Duration::from_secs(2), records.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(record.direction, Direction::Rx);
+    assert_eq!(record.data, b"ACK");
+
+    // Sent to a tap, so never on the link
+    manager.send_data("tap-rx", b"noise").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(records.try_recv().is_err());
+
+    let info = &manager.list_sniffers().await[0];
+    assert!(info.running);
+    assert_eq!((info.bytes_tx, info.bytes_rx), (3, 3));
+    let lines: Vec<serde_json::Value> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0]["direction"], "tx");
+    assert_eq!(lines[0]["data"], "524551");
+
+    manager.remove_sniffer("link").await.unwrap();
+    assert!(manager.list_sniffers().await.is_empty());
+    assert!(manager.get_connection("tap-tx").await.is_some());
+    let _ = std::fs::remove_file(&path);
+}
+
+#[test]
+fn test_capture_job_schedule_and_signing() {
+    use super::capture_jobs::{next_run, object_name};
+    use crate::s3::signing_key;
+    use chrono::{NaiveTime, TimeZone, Utc};
+
+    let at = NaiveTime::from_hms_opt(2, 0, 0).unwrap();
+    let before = Utc.with_ymd_and_hms(2025, 11, 30, 1, 59, 0).unwrap();
+    let after = Utc.with_ymd_and_hms(2025, 11, 30, 2, 0, 0).unwrap();
+    assert_eq!(
+        next_run(at, before),
+        Utc.with_ymd_and_hms(2025, 11, 30, 2, 0, 0).unwrap()
+    );
+    assert_eq!(
+        next_run(at, after),
+        Utc.with_ymd_and_hms(2025, 12, 1, 2, 0, 0).unwrap()
+    );
+    assert_eq!(
+        object_name("nightly", after),
+        "nightly/2025-11-30T02-00-00Z.json"
+    );
+
+    // From the AWS Signature Version 4 documentation
+    assert_eq!(
+        hex::encode(signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam"
+        )),
+        "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+    );
+}
+
+#[tokio::test]
+async fn test_capture_job_uploads() {
+    use super::capture_jobs::upload;
+    use crate::config::{S3Config, UploadTarget};
+    use axum::{body::Bytes, extract::State, http::HeaderMap, http::Uri, routing::put, Router};
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel::<(Uri, HeaderMap, Bytes)>(4);
+    let app = Router::new()
+        .route(
+            "/*path",
+            put(
+                |State(tx): State<tokio::sync::mpsc::Sender<(Uri, HeaderMap, Bytes)>>,
+                 uri: Uri,
+                 headers: HeaderMap,
+                 body: Bytes| async move {
+                    tx.send((uri, headers, body)).await.unwrap();
+                },
+            ),
+        )
+        .with_state(tx);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+    let client = reqwest::Client::new();
+    let dir = tempfile::tempdir().unwrap();
+    let token_file = dir.path().join("token");
+    std::fs::write(&token_file, "t0ken\n").unwrap();
+    let secret_file = dir.path().join("secret");
+    std::fs::write(&secret_file, "secret\n").unwrap();
+
+    let target = UploadTarget::Http {
+        url: format!("http://{}/archive/", addr),
+        token_file: Some(token_file),
+    };
+    let location = upload(&client, &target, "nightly/x.json", b"{}".to_vec())
+        .await
+        .unwrap();
+    assert_eq!(location, format!("http://{}/archive/nightly/x.json", addr));
+    let (uri, headers, body) = rx.recv().await.unwrap();
+    assert_eq!(uri.path(), "/archive/nightly/x.json");
+    assert_eq!(headers["authorization"], "Bearer t0ken");
+    assert_eq!(&body[..], b"{}");
+
+    let target = UploadTarget::S3(S3Config {
+        endpoint: format!("http://{}", addr),
+        bucket: "serial".to_string(),
+        region: "eu-west-1".to_string(),
+        prefix: "site a/".to_string(),
+        access_key_id: "AKID".to_string(),
+        secret_access_key_file: secret_file,
+    });
+    upload(&client, &target, "nightly/x.json", b"{}".to_vec())
+        .await
+        .unwrap();
+    let (uri, headers, _) = rx.recv().await.unwrap();
+    assert_eq!(uri.path(), "/serial/site%20a/nightly/x.json");
+    let authorization = headers["authorization"].to_str().unwrap();
+    assert!(authorization.starts_with("AWS4-HMAC-SHA256 Credential=AKID/"));
+    assert!(authorization.contains("/eu-west-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature="));
+    assert_eq!(
+        headers["x-amz-content-sha256"],
+        "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+    );
+}
+
+#[tokio::test]
+async fn test_capture_interleaves_connections() {
+    let manager = SerialManager::new();
+    manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "bus".to_string(),
+            a: Some("controller".to_string()),
+            b: Some("peripheral".to_string()),
+        })
+        .await
+        .unwrap();
+
+    let info = manager
+        .start_capture(&CaptureRequest {
+            connections: vec!["controller".to_string(), "peripheral".to_string()],
+            duration_secs: 60,
+            max_bytes: 1024,
+        })
+        .await
+        .unwrap();
+
+    let mut peripheral_rx = manager.subscribe("peripheral").await.unwrap();
+    manager.send_data("controller", b"REQ\n").await.unwrap();
+    tokio::time::timeout(Duration::from_secs(2), peripheral_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    let mut controller_rx = manager.subscribe("controller").await.unwrap();
+    manager.send_data("peripheral", b"ACK\n").await.unwrap();
+    tokio::time::timeout(Duration::from_secs(2), controller_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+
+    manager.stop_capture(info.id).await.unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+    let bundle = loop {
+        let bundle = manager.get_capture(info.id).await.unwrap();
+        if bundle.info.stopped_at.is_some() {
+            break bundle;
+        }
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+
+    let records: Vec<(&str, Direction, &[u8])> = bundle
+        .records
+        .iter()
+        .map(|record| {
+            (
+                record.connection.as_str(),
+                record.direction,
+                record.data.as_slice(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        records,
+        vec![
+            ("controller", Direction::Tx, &b"REQ\n"[..]),
+            ("peripheral", Direction::Rx, &b"REQ\n"[..]),
+            ("peripheral", Direction::Tx, &b"ACK\n"[..]),
+            ("controller", Direction::Rx, &b"ACK\n"[..]),
+        ]
+    );
+    assert!(bundle
+        .records
+        .windows(2)
+        .all(|pair| pair[0].offset_us <= pair[1].offset_us));
+    assert_eq!(bundle.info.bytes, 16);
+
+    // Enough about the clocks to line this capture up with others
+    let clock = &bundle.info.clock;
+    assert_eq!(clock.start_wall, bundle.info.started_at);
+    assert!(!clock.host.is_empty());
+    assert_eq!(
+        clock.ntp_synchronized.is_some(),
+        clock.max_error_us.is_some()
+    );
+    #[cfg(target_os = "linux")]
+    assert!(clock.ntp_synchronized.is_some());
+
+    manager.delete_capture(info.id).await.unwrap();
+    assert!(manager.list_captures().await.is_empty());
+}
+
+#[test]
+fn test_windows_com_paths() {
+    assert_eq!(discovery::device_path("COM3"), r"\\.\COM3");
+    assert_eq!(discovery::device_path("com10"), r"\\.\COM10");
+    assert_eq!(discovery::device_path("COM12:"), r"\\.\COM12");
+    assert_eq!(discovery::device_path(r"\\.\COM10"), r"\\.\COM10");
+    assert_eq!(discovery::device_path("/dev/ttyUSB0"), "/dev/ttyUSB0");
+    assert_eq!(discovery::device_path("COMX"), "COMX");
+
+    assert_eq!(discovery::port_key(r"\\.\com10"), "COM10");
+    assert_eq!(discovery::port_key("COM10:"), "COM10");
+}
+
+#[test]
+fn test_port_descriptions() {
+    assert_eq!(
+        discovery::usb_description(Some("FTDI"), Some("FT232R USB UART"), 0x0403, 0x6001),
+        "FTDI FT232R USB UART (0403:6001)"
+    );
+    assert_eq!(
+        discovery::usb_description(Some("wch.cn"), Some("wch.cn"), 0x1a86, 0x7523),
+        "wch.cn (1a86:7523)"
+    );
+    assert_eq!(
+        discovery::usb_description(None, None, 0x2341, 0x0043),
+        "(2341:0043)"
+    );
+
+    let ports: Vec<PortDescription> = vec![serialport::SerialPortInfo {
+        port_name: "COM10".to_string(),
+        port_type: serialport::SerialPortType::PciPort,
+    }
+    .into()];
+    assert_eq!(ports[0].kind, discovery::PortKind::Pci);
+    assert_eq!(
+        discovery::find_port(&ports, r"\\.\com10").map(|port| port.path.as_str()),
+        Some("COM10")
+    );
+    assert!(discovery::find_port(&ports, "COM1").is_none());
+}
+
+#[test]
+fn test_token_bucket_paces_writes() {
+    let start = std::time::Instant::now();
+    let mut bucket = TokenBucket::new(1000, start);
+
+    // 10 ms worth of budget is available straight away
+    assert_eq!(bucket.take(100, start), 10);
+    assert_eq!(bucket.take(100, start), 0);
+    assert_eq!(bucket.ready_at(100), start + Duration::from_millis(10));
+
+    // Budget refills at the configured rate but never beyond the burst size
+    assert_eq!(bucket.take(100, start + Duration::from_millis(5)), 5);
+    assert_eq!(bucket.take(100, start + Duration::from_secs(10)), 10);
+
+    // Small messages do not wait for a full burst
+    let mut bucket = TokenBucket::new(1000, start);
+    assert_eq!(bucket.take(9, start), 9);
+    assert_eq!(bucket.ready_at(1), start);
+    assert_eq!(bucket.ready_at(3), start + Duration::from_millis(2));
+}
+
+#[tokio::test]
+async fn test_tx_queue_merges_waiting_writes() {
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    let mut queue = TxQueue::new(
+        rx,
+        TxBatchConfig {
+            max_bytes: 8,
+            max_delay_ms: 0,
+        },
+    );
+    for message in [&b"ab"[..], b"cd", b"efg", b"hi"] {
+        tx.send(message.to_vec().into()).await.unwrap();
+    }
+
+    // Batches stop short of max_bytes; the message that did not fit is next
+    let first = queue.recv().await.unwrap();
+    assert_eq!(queue.push(first).unwrap().data, b"abcdefg");
+    let second = queue.recv().await.unwrap();
+    assert_eq!(queue.push(second).unwrap().data, b"hi");
+
+    // Without a delay nothing is waited for
+    tokio::time::timeout(std::time::Duration::from_millis(100), async {
+        tx.send(b"x".to_vec().into()).await.unwrap();
+        let next = queue.recv().await.unwrap();
+        assert_eq!(queue.push(next).unwrap().data, b"x");
+    })
+    .await
+    .unwrap();
+
+    // With one, writes arriving shortly after join the batch
+    let (tx, rx) = tokio::sync::mpsc::channel(16);
+    let mut queue = TxQueue::new(
+        rx,
+        TxBatchConfig {
+            max_bytes: 64,
+            max_delay_ms: 200,
+        },
+    );
+    tx.send(b"a".to_vec().into()).await.unwrap();
+    tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        tx.send(b"b".to_vec().into()).await.unwrap();
+    });
+    let first = queue.recv().await.unwrap();
+    // The batch is held open rather than waited for, until its deadline
+    assert!(queue.push(first).is_none());
+    let deadline = queue.deadline().unwrap();
+    let second = queue.recv().await.unwrap();
+    assert!(queue.push(second).is_none());
+    tokio::time::sleep_until(deadline).await;
+    assert_eq!(queue.flush().unwrap().data, b"ab");
+    assert!(queue.deadline().is_none());
+}
+
+#[tokio::test]
+async fn test_tx_rate_limit_over_pty() {
+    let (path, mut far_end) = pty_pair();
+    let mut config = test_config("slow", &path);
+    config.max_tx_rate_bytes_per_sec = Some(1000);
+    let manager = SerialManager::new();
+    manager.add_connection(config).await.unwrap();
+
+    let payload: Vec<u8> = (0..300u32).map(|i| b'a' + (i % 26) as u8).collect();
+    let started = std::time::Instant::now();
+    manager.send_data("slow", &payload).await.unwrap();
+
+    let mut received = Vec::new();
+    let mut buf = [0u8; 256];
+    while received.len() < payload.len() {
+        let n = tokio::time::timeout(Duration::from_secs(2), far_end.read(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        received.extend_from_slice(&buf[..n]);
+    }
+
+    assert_eq!(received, payload);
+    // 300 bytes at 1000 B/s, less the initial 10-byte burst
+    assert!(started.elapsed() >= Duration::from_millis(250));
+    assert_eq!(manager.get_stats("slow").await.unwrap().bytes_sent, 300);
+}
+
+#[tokio::test]
+async fn test_send_written_waits_for_the_port() {
+    let (path, mut far_end) = pty_pair();
+    let mut config = test_config("acked", &path);
+    config.max_tx_rate_bytes_per_sec = Some(1000);
+    let manager = SerialManager::new();
+    manager.add_connection(config).await.unwrap();
+
+    let reader = tokio::spawn(async move {
+        let mut received = 0;
+        let mut buf = [0u8; 256];
+        while received < 300 {
+            received += far_end.read(&mut buf).await.unwrap();
+        }
+    });
+    let started = std::time::Instant::now();
+    manager
+        .send_data_written("acked", &[b'x'; 300], Priority::Bulk)
+        .await
+        .unwrap();
+    // Paced writes have all gone out by the time it returns
+    assert!(started.elapsed() >= Duration::from_millis(250));
+    assert_eq!(manager.get_stats("acked").await.unwrap().bytes_sent, 300);
+    reader.await.unwrap();
+
+    // Messages in a batch are acknowledged as the bytes they end at go out
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    let mut queue = TxQueue::new(rx, TxBatchConfig::default());
+    let (first_ack, mut first) = tokio::sync::oneshot::channel();
+    let (second_ack, mut second) = tokio::sync::oneshot::channel();
+    for (data, ack) in [(b"abc", first_ack), (b"def", second_ack)] {
+        let message = super::batching::Outgoing {
+            data: data.to_vec(),
+            ack: Some(ack),
+        };
+        tx.send(message).await.unwrap();
+    }
+    let head = queue.recv().await.unwrap();
+    let mut batch = queue.push(head).unwrap();
+    batch.written(4);
+    assert_eq!(first.try_recv().unwrap(), Ok(()));
+    assert!(second.try_recv().is_err());
+    batch.failed("port gone");
+    assert_eq!(second.try_recv().unwrap(), Err("port gone".to_string()));
+}
+
+#[tokio::test]
+async fn test_tx_queue_takes_back_a_received_message() {
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    let mut queue = TxQueue::new(rx, TxBatchConfig::default());
+    assert!(queue.is_empty());
+    for data in [b"abc", b"def"] {
+        let message = super::batching::Outgoing {
+            data: data.to_vec(),
+            ack: None,
+        };
+        tx.send(message).await.unwrap();
+    }
+    assert!(!queue.is_empty());
+
+    // As the idle port does when a write arrives while it is closed
+    let message = queue.recv().await.unwrap();
+    queue.recv().await.unwrap();
+    assert!(queue.is_empty());
+    queue.unrecv(message);
+    assert!(!queue.is_empty());
+    assert_eq!(queue.recv().await.unwrap().data, b"abc");
+}
+
+#[test]
+fn test_quota_counts_per_day() {
+    use crate::config::QuotaConfig;
+    use quota::Quota;
+
+    let quota = Quota::new(
+        Some("modem"),
+        QuotaConfig {
+            daily_tx_bytes: Some(10),
+            ..QuotaConfig::default()
+        },
+    );
+    let day = chrono::NaiveDate::from_ymd_opt(2025, 11, 30).unwrap();
+    assert_eq!(quota.record(Direction::Tx, 6, day), None);
+    // Reported once, when the allowance runs out
+    assert_eq!(quota.record(Direction::Tx, 6, day), Some(12));
+    assert_eq!(quota.record(Direction::Tx, 1, day), None);
+    assert_eq!(quota.record(Direction::Rx, 1000, day), None);
+    assert!(quota.exceeded(day));
+
+    let usage = quota.usage(day);
+    assert_eq!((usage.rx_bytes, usage.tx_bytes), (1000, 13));
+    assert_eq!(usage.connection.as_deref(), Some("modem"));
+
+    let next = day.succ_opt().unwrap();
+    assert!(!quota.exceeded(next));
+    assert_eq!(quota.usage(next).tx_bytes, 0);
+}
+
+#[tokio::test]
+async fn test_quota_blocks_writes_once_used_up() {
+    use crate::config::{QuotaAction, QuotaConfig};
+
+    let (path, mut far_end) = pty_pair();
+    let mut config = test_config("modem", &path);
+    config.quota = Some(QuotaConfig {
+        daily_tx_bytes: Some(4),
+        action: QuotaAction::Block,
+        ..QuotaConfig::default()
+    });
+    let manager = SerialManager::new().with_quota(QuotaConfig {
+        daily_rx_bytes: Some(1_000_000),
+        ..QuotaConfig::default()
+    });
+    manager.add_connection(config).await.unwrap();
+
+    manager.send_data("modem", b"ATZ\r").await.unwrap();
+    let mut buf = [0u8; 4];
+    tokio::time::timeout(Duration::from_secs(2), far_end.read_exact(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+    while !manager.quotas().await[1].exceeded {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    let err = manager.send_data("modem", b"ATH\r").await.unwrap_err();
+    assert!(err.to_string().contains("daily quota"));
+    let quotas = manager.quotas().await;
+    assert_eq!(quotas.len(), 2);
+    assert_eq!(quotas[0].connection, None);
+    assert_eq!(quotas[0].tx_bytes, 4);
+    assert_eq!(quotas[1].connection.as_deref(), Some("modem"));
+    manager.shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_idle_port_closes_and_reopens_on_demand() {
+    let (path, mut far_end) = pty_pair();
+    let mut config = test_config("idle", &path);
+    config.idle_close_minutes = Some(1);
+    let manager = SerialManager::new();
+    manager.add_connection(config).await.unwrap();
+    let port_open = || async { manager.get_stats("idle").await.unwrap().port_open };
+
+    // Closed after a minute with nobody attached
+    tokio::time::sleep(Duration::from_secs(61)).await;
+    assert!(!port_open().await);
+
+    // Subscribing reopens it, and it stays open while the subscriber lives
+    let mut rx = manager.subscribe("idle").await.unwrap();
+    while !port_open().await {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    far_end.write_all(b"hello").await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), b"hello");
+    tokio::time::sleep(Duration::from_secs(120)).await;
+    assert!(port_open().await);
+
+    // Writes reopen it too
+    drop(rx);
+    tokio::time::sleep(Duration::from_secs(61)).await;
+    assert!(!port_open().await);
+    manager.send_data("idle", b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    far_end.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    manager.shutdown().await;
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_dropping_start_connections_stops_pending_opens() {
+    let manager = SerialManager::new();
+    let (path, _device) = pty_pair();
+    let mut config = test_config("late", &path);
+    config.startup_delay_ms = 60_000;
+    let starting = manager.start_connections(vec![config]);
+    assert!(tokio::time::timeout(Duration::from_secs(1), starting)
+        .await
+        .is_err());
+    tokio::time::sleep(Duration::from_secs(120)).await;
+    assert!(manager.list_connections().await.is_empty());
+}
+
+#[tokio::test]
+async fn test_start_connections_waits_for_dependencies() {
+    let (path, _far_end) = pty_pair();
+    let mut power = test_config("power", &path);
+    power.startup_delay_ms = 50;
+    let mut console = test_config("console", "/dev/webmux-missing");
+    console.depends_on = Some("power".to_string());
+    let mut attached = test_config("attached", "/dev/webmux-missing");
+    attached.depends_on = Some("console".to_string());
+
+    let manager = SerialManager::new();
+    let results = manager
+        .start_connections(vec![attached, console, power])
+        .await;
+    let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["attached", "console", "power"]);
+    assert!(results[2].1.is_ok());
+    // The console port does not exist, so nothing depending on it opens
+    assert!(results[1].1.is_err());
+    let error = results[0].1.as_ref().unwrap_err().to_string();
+    assert!(error.contains("console"), "{}", error);
+    assert_eq!(manager.list_connections().await, vec!["power".to_string()]);
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_startup_report_tracks_each_connection() {
+    let (path, _far_end) = pty_pair();
+    let mut power = test_config("power", &path);
+    power.startup_delay_ms = 300;
+    let mut console = test_config("console", "/dev/webmux-missing");
+    console.depends_on = Some("power".to_string());
+    let mut spare = test_config("spare", "/dev/webmux-missing");
+    spare.enabled = false;
+
+    let manager = SerialManager::new();
+    let starting = manager.clone();
+    let task = tokio::spawn(async move {
+        starting
+            .start_connections(vec![power, console, spare])
+            .await
+    });
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(2);
+    let report = loop {
+        let report = manager.startup_report();
+        let delayed = report
+            .connections
+            .first()
+            .and_then(|c| c.waiting_for.as_deref())
+            == Some("startup delay of 300 ms");
+        if delayed {
+            break report;
+        }
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    };
+    assert_eq!(
+        report.connections[1].waiting_for.as_deref(),
+        Some("dependency power")
+    );
+
+    task.await.unwrap();
+    let report = manager.startup_report();
+    assert_eq!(
+        (
+            report.opened,
+            report.failed,
+            report.pending,
+            report.disabled
+        ),
+        (1, 1, 0, 1)
+    );
+    let states: Vec<_> = report.connections.iter().map(|c| c.state).collect();
+    assert_eq!(
+        states,
+        vec![
+            StartupState::Opened,
+            StartupState::Failed,
+            StartupState::Disabled
+        ]
+    );
+    // The error is the one opening the missing port returned
+    let error = report.connections[1].error.as_deref().unwrap();
+    assert!(error.contains("No such file"), "{}", error);
+    assert!(report.connections[0].error.is_none());
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_failed_connection_is_kept_for_retry() {
+    let (path, _far_end) = pty_pair();
+    let mut console = test_config("console", &path);
+    console.depends_on = Some("nm-a".to_string());
+
+    let manager = SerialManager::new();
+    let results = manager.start_connections(vec![console]).await;
+    assert!(results[0].1.is_err());
+    let failed = manager.failed_connections().await;
+    assert_eq!(failed.len(), 1);
+    assert_eq!(failed[0].name, "console");
+    assert_eq!(failed[0].error, "dependency nm-a was not opened");
+
+    let error = manager.retry_connection("console").await.unwrap_err();
+    assert_eq!(error.to_string(), "dependency nm-a is not open");
+    let failed = manager.get_failed_connection("console").await.unwrap();
+    assert_eq!(failed.error, "dependency nm-a is not open");
+
+    manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    manager.retry_connection("console").await.unwrap();
+    assert!(manager.get_connection("console").await.is_some());
+    assert!(manager.failed_connections().await.is_empty());
+    assert_eq!(
+        manager.startup_report().connections[0].state,
+        StartupState::Opened
+    );
+    assert!(manager.retry_connection("console").await.is_err());
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_connections_disabled_and_enabled_at_runtime() {
+    let (path, _far_end) = pty_pair();
+    let (spare_path, _spare_end) = pty_pair();
+    let mut spare = test_config("spare", &spare_path);
+    spare.enabled = false;
+
+    let manager = SerialManager::new();
+    manager
+        .add_connection(test_config("console", &path))
+        .await
+        .unwrap();
+    manager.add_connection(spare).await.unwrap();
+    // Configured as disabled, so kept to be enabled later
+    assert!(manager.get_connection("spare").await.is_none());
+    assert_eq!(manager.disabled_connections().await[0].name, "spare");
+
+    manager.disable_connection("console").await.unwrap();
+    assert!(manager.get_connection("console").await.is_none());
+    let disabled = manager.get_disabled_connection("console").await.unwrap();
+    assert!(!disabled.enabled);
+    assert_eq!(disabled.port, path);
+    // Disabling twice is harmless
+    manager.disable_connection("console").await.unwrap();
+
+    manager.enable_connection("console").await.unwrap();
+    manager.enable_connection("spare").await.unwrap();
+    assert!(
+        manager
+            .get_connection("console")
+            .await
+            .unwrap()
+            .config()
+            .enabled
+    );
+    assert!(manager.get_connection("spare").await.is_some());
+    assert!(manager.disabled_connections().await.is_empty());
+    manager.enable_connection("console").await.unwrap();
+
+    assert!(manager.disable_connection("missing").await.is_err());
+    assert!(manager.enable_connection("missing").await.is_err());
+
+    // A disabled connection can still be removed for good
+    manager.disable_connection("spare").await.unwrap();
+    manager.remove_connection("spare").await.unwrap();
+    assert!(manager.get_disabled_connection("spare").await.is_none());
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_power_cycle_through_connection_and_command() {
+    let manager = SerialManager::new();
+    manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "pdu".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let mut pdu_rx = manager.subscribe("pdu-b").await.unwrap();
+
+    let config = crate::config::PowerConfig {
+        control: crate::config::PowerControl::Connection {
+            connection: "pdu-a".to_string(),
+        },
+        on: "outlet 3 on\r".to_string(),
+        off: "outlet 3 off\r".to_string(),
+        cycle_delay_ms: 10,
+    };
+    power::run(&manager, "router", &config, PowerAction::Cycle)
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    while received.len() < b"outlet 3 off\routlet 3 on\r".len() {
+        let data = tokio::time::timeout(Duration::from_secs(2), pdu_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.extend(data);
+    }
+    assert_eq!(received, b"outlet 3 off\routlet 3 on\r");
+
+    #[cfg(unix)]
+    {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state");
+        let config = crate::config::PowerConfig {
+            control: crate::config::PowerControl::Command,
+            on: format!("echo \"$WEBMUX_CONNECTION on\" > {}", state.display()),
+            off: "exit 3".to_string(),
+            cycle_delay_ms: 0,
+        };
+        power::run(&manager, "router", &config, PowerAction::On)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_to_string(&state).unwrap(), "router on\n");
+        assert!(power::run(&manager, "router", &config, PowerAction::Off)
+            .await
+            .is_err());
+    }
+
+    // Virtual connections have no power block
+    assert!(manager.power("pdu-a", PowerAction::On).await.is_err());
+}
+
+#[test]
+fn test_health_report_overall_status() {
+    let problem = |name: &str, state| ConnectionHealth {
+        name: name.to_string(),
+        state,
+        reason: String::new(),
+    };
+    assert_eq!(HealthReport::new(0, vec![]).status, OverallHealth::Ok);
+    assert_eq!(HealthReport::new(3, vec![]).status, OverallHealth::Ok);
+
+    let report = HealthReport::new(
+        1,
+        vec![
+            problem("plc", HealthState::Disconnected),
+            problem("gps", HealthState::Degraded),
+        ],
+    );
+    assert_eq!(report.status, OverallHealth::Degraded);
+    assert_eq!((report.degraded, report.disconnected), (1, 1));
+    assert_eq!(report.problems[0].name, "gps");
+
+    // A degraded connection is still up
+    let report = HealthReport::new(0, vec![problem("gps", HealthState::Degraded)]);
+    assert_eq!(report.status, OverallHealth::Degraded);
+    let report = HealthReport::new(0, vec![problem("plc", HealthState::Disconnected)]);
+    assert_eq!(report.status, OverallHealth::Down);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_keepalive_sent_when_idle() {
+    let (stream, mut device) = tokio::io::duplex(1024);
+    let mut config = virtual_pair::endpoint_config("switch", "ka");
+    config.keepalive = Some(crate::config::KeepaliveConfig {
+        idle_minutes: 5,
+        data: "\r".to_string(),
+    });
+    let connection = SerialConnection::new_virtual(config, stream, Notifier::default())
+        .await
+        .unwrap();
+    let started = tokio::time::Instant::now();
+    let mut buf = [0u8; 16];
+
+    let n = device.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"\r");
+    assert!(started.elapsed() >= Duration::from_secs(300));
+    let keepalive = connection.get_stats().await.keepalive.unwrap();
+    assert_eq!(keepalive.sent, 1);
+    assert!(keepalive.last_sent.is_some());
+
+    // Writes push the next keepalive back
+    tokio::time::sleep(Duration::from_secs(240)).await;
+    connection.send(b"show clock\n").await.unwrap();
+    let n = device.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"show clock\n");
+    let written = tokio::time::Instant::now();
+    let n = device.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"\r");
+    assert!(written.elapsed() >= Duration::from_secs(300));
+    assert_eq!(connection.get_stats().await.keepalive.unwrap().sent, 2);
+
+    // None go out while a sequence holds the transaction
+    let transaction = connection.transaction().await.unwrap();
+    let read = tokio::time::timeout(Duration::from_secs(400), device.read(&mut buf)).await;
+    assert!(read.is_err());
+    drop(transaction);
+    let n = device.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"\r");
+    assert_eq!(connection.get_stats().await.keepalive.unwrap().sent, 3);
+}
+
+#[tokio::test]
+async fn test_rule_violations_counted_in_stats() {
+    use crate::config::RuleConfig;
+
+    let (stream, mut device) = tokio::io::duplex(1024);
+    let mut config = virtual_pair::endpoint_config("plc", "rules");
+    config.rules = vec![RuleConfig {
+        name: "req-ack".to_string(),
+        when: "^REQ".to_string(),
+        expect: "^ACK".to_string(),
+        within_ms: 50,
+    }];
+    let connection = SerialConnection::new_virtual(config, stream, Notifier::default())
+        .await
+        .unwrap();
+    let mut buf = [0u8; 4];
+
+    connection.send(b"REQ\n").await.unwrap();
+    device.read_exact(&mut buf).await.unwrap();
+    device.write_all(b"ACK\n").await.unwrap();
+    // Unanswered, so it times out without further traffic
+    connection.send(b"REQ\n").await.unwrap();
+    device.read_exact(&mut buf).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    let rules = connection.get_stats().await.rules;
+    assert_eq!(rules.len(), 1);
+    assert_eq!(rules[0].answered, 1);
+    assert_eq!(rules[0].violations, 1);
+}
+
+#[tokio::test]
+async fn test_identify_probe() {
+    use super::identify::{self, IdentitySource};
+    use crate::config::IdentifyConfig;
+
+    let (stream, mut device) = tokio::io::duplex(1024);
+    let connection = SerialConnection::new_virtual(
+        virtual_pair::endpoint_config("plc", "id"),
+        stream,
+        Notifier::default(),
+    )
+    .await
+    .unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 16];
+        let n = device.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"*IDN?\r\n");
+        // The matching line arrives in pieces, after one that does not match
+        device
+            .write_all(b"ready\r\nACME PLC-5000 v3.")
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        device.write_all(b"2.1\r\n").await.unwrap();
+        device
+    });
+
+    let config = IdentifyConfig {
+        command: Some("*IDN?\r\n".to_string()),
+        pattern: Some(r"ACME (?P<model>\S+) v(?P<firmware>\S+)".to_string()),
+        timeout_ms: 2000,
+    };
+    let identity = identify::probe(&connection, &config).await.unwrap();
+    assert_eq!(identity.model.as_deref(), Some("PLC-5000"));
+    assert_eq!(identity.firmware.as_deref(), Some("3.2.1"));
+    assert_eq!(identity.source, IdentitySource::Probe);
+
+    // Nothing answers a second time
+    let config = IdentifyConfig {
+        timeout_ms: 50,
+        ..config
+    };
+    assert!(identify::probe(&connection, &config).await.is_err());
+}
+
+#[test]
+fn test_identify_config_checks() {
+    use super::identify::compile;
+    use crate::config::IdentifyConfig;
+
+    let config = |command: Option<&str>, pattern: Option<&str>| IdentifyConfig {
+        command: command.map(str::to_string),
+        pattern: pattern.map(str::to_string),
+        timeout_ms: 2000,
+    };
+    assert!(compile(&config(None, None)).unwrap().is_none());
+    assert!(compile(&config(None, Some("(?P<model>PLC-\\d+)")))
+        .unwrap()
+        .is_some());
+    assert!(compile(&config(Some("ver\r"), Some("v(?P<firmware>.+)"))).is_ok());
+
+    assert!(compile(&config(Some("ver\r"), None)).is_err());
+    assert!(compile(&config(Some(""), Some("(?P<model>.+)"))).is_err());
+    assert!(compile(&config(Some("ver\r"), Some("v(.+)"))).is_err());
+    assert!(compile(&config(Some("ver\r"), Some("(?P<model>"))).is_err());
+    let slow = IdentifyConfig {
+        timeout_ms: 0,
+        ..config(None, None)
+    };
+    assert!(compile(&slow).is_err());
+}
+
+#[tokio::test]
+async fn test_endpoint_query() {
+    use super::endpoints::{self, NoReply};
+    use crate::config::EndpointConfig;
+
+    let (stream, mut device) = tokio::io::duplex(1024);
+    let connection = SerialConnection::new_virtual(
+        virtual_pair::endpoint_config("plc", "ep"),
+        stream,
+        Notifier::default(),
+    )
+    .await
+    .unwrap();
+    let responder = tokio::spawn(async move {
+        let mut buf = [0u8; 16];
+        let n = device.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"P?\r\n");
+        device.write_all(b"P?\r\nP=12.5 bar\r\n").await.unwrap();
+        device
+    });
+
+    let config = EndpointConfig {
+        name: "pressure".to_string(),
+        command: "P?\r\n".to_string(),
+        pattern: r"^P=(\S+)".to_string(),
+        timeout_ms: 2000,
+        poll_interval_ms: None,
+    };
+    let reading = endpoints::query(&connection, &config).await.unwrap();
+    assert_eq!(reading.connection, "plc");
+    assert_eq!(reading.endpoint, "pressure");
+    assert_eq!(reading.value, "12.5");
+    assert_eq!(reading.number, Some(12.5));
+    assert_eq!(reading.line, "P=12.5 bar");
+    assert!(!reading.cached);
+    // Kept open, but silent from here on
+    let _device = responder.await.unwrap();
+
+    // Nothing answers a second time
+    let config = EndpointConfig {
+        timeout_ms: 50,
+        ..config
+    };
+    let err = endpoints::query(&connection, &config).await.unwrap_err();
+    assert!(err.downcast_ref::<NoReply>().is_some());
+
+    // Nor while waiting for the port, held by another transaction
+    let held = connection.transaction().await.unwrap();
+    let started = tokio::time::Instant::now();
+    let err = endpoints::query(&connection, &config).await.unwrap_err();
+    assert!(err.downcast_ref::<NoReply>().is_some());
+    assert!(started.elapsed() < Duration::from_secs(1));
+    drop(held);
+}
+
+#[tokio::test]
+async fn test_endpoint_readings_cached() {
+    use super::endpoints::{self, NoReply};
+    use crate::config::EndpointConfig;
+
+    let (stream, mut device) = tokio::io::duplex(1024);
+    let connection = SerialConnection::new_virtual(
+        virtual_pair::endpoint_config("plc", "cache"),
+        stream,
+        Notifier::default(),
+    )
+    .await
+    .unwrap();
+    let responder = tokio::spawn(async move {
+        let mut buf = [0u8; 16];
+        let n = device.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"T?\r\n");
+        device.write_all(b"T=21\r\n").await.unwrap();
+        device
+    });
+
+    let config = EndpointConfig {
+        name: "temperature".to_string(),
+        command: "T?\r\n".to_string(),
+        pattern: r"^T=(\d+)".to_string(),
+        timeout_ms: 500,
+        poll_interval_ms: Some(60_000),
+    };
+    // The second request waits for the first one's answer
+    let (first, second) = tokio::join!(
+        endpoints::read(&connection, &config),
+        endpoints::read(&connection, &config)
+    );
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert!(!first.cached);
+    assert!(second.cached);
+    assert_eq!(second.value, "21");
+    assert_eq!(second.read_at, first.read_at);
+    let mut device = responder.await.unwrap();
+
+    // Served without asking the device
+    assert!(endpoints::read(&connection, &config).await.unwrap().cached);
+    let mut buf = [0u8; 16];
+    let asked = tokio::time::timeout(Duration::from_millis(50), device.read(&mut buf)).await;
+    assert!(asked.is_err());
+
+    // Once the reading is older than the interval the device is asked again
+    let config = EndpointConfig {
+        timeout_ms: 50,
+        poll_interval_ms: Some(1),
+        ..config
+    };
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    let err = endpoints::read(&connection, &config).await.unwrap_err();
+    assert!(err.downcast_ref::<NoReply>().is_some());
+    let n = device.read(&mut buf).await.unwrap();
+    assert_eq!(&buf[..n], b"T?\r\n");
+}
+
+#[test]
+fn test_endpoint_extract() {
+    use super::endpoints::extract;
+
+    let regex = regex::Regex::new(r"TEMP:(-?\d+)C").unwrap();
+    assert_eq!(
+        extract(&regex, "TEMP:-4C"),
+        Some(("-4".to_string(), Some(-4.0)))
+    );
+    assert_eq!(extract(&regex, "HUMIDITY:40%"), None);
+    // Without a group the whole match is the value
+    let regex = regex::Regex::new(r"(?i)ok|fault").unwrap();
+    assert_eq!(
+        extract(&regex, "status FAULT"),
+        Some(("FAULT".to_string(), None))
+    );
+}
+
+#[tokio::test]
+async fn test_import_connections_applies_the_difference() {
+    let (old_path, _old_end) = pty_pair();
+    let (console_path, _console_end) = pty_pair();
+    let (gps_path, _gps_end) = pty_pair();
+    let manager = SerialManager::new();
+    manager
+        .add_connection(test_config("old", &old_path))
+        .await
+        .unwrap();
+    manager
+        .add_connection(test_config("console", &console_path))
+        .await
+        .unwrap();
+    manager
+        .add_virtual_pair(&VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+
+    let mut console = test_config("console", &console_path);
+    console.baud_rate = 9600;
+    let mut gps = test_config("gps", &gps_path);
+    gps.depends_on = Some("console".to_string());
+    let missing = test_config("missing", "/dev/webmux-missing");
+    // Opened after the connection it depends on, though listed first
+    let configs = vec![gps, console, missing];
+
+    let plan = manager.import_connections(configs.clone(), true).await;
+    assert!(plan.dry_run);
+    assert_eq!(plan.open, vec!["gps", "missing"]);
+    assert_eq!(plan.close, vec!["old"]);
+    assert_eq!(plan.reopen.len(), 1);
+    assert_eq!(plan.reopen[0].fields, vec!["baud_rate"]);
+    assert!(plan.failed.is_empty());
+    assert_eq!(manager.list_connections().await.len(), 4);
+
+    let report = manager.import_connections(configs, false).await;
+    assert_eq!(report.failed.len(), 1, "{:?}", report.failed);
+    assert_eq!(report.failed[0].name, "missing");
+    // Kept to be retried, as at startup
+    assert!(manager.get_failed_connection("missing").await.is_some());
+    let mut names = manager.list_connections().await;
+    names.sort();
+    // Virtual pairs are not part of the import
+    assert_eq!(names, vec!["console", "gps", "nm-a", "nm-b"]);
+    let console = manager.get_connection("console").await.unwrap();
+    assert_eq!(console.config().baud_rate, 9600);
+
+    let exported = manager.export_connections().await.unwrap();
+    let names: Vec<&str> = exported.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, vec!["console", "gps"]);
+
+    manager.shutdown().await;
+}
+
+#[test]
+fn test_bulk_request_expands_template() {
+    use super::bulk::{matching_ports, BulkConnectionRequest};
+    use super::discovery::{PortDescription, PortKind};
+
+    let port = |path: &str, by_id: Option<&str>| PortDescription {
+        path: path.to_string(),
+        kind: PortKind::Usb,
+        friendly_name: None,
+        description: None,
+        manufacturer: None,
+        serial_number: None,
+        vid: None,
+        pid: None,
+        by_id: by_id.map(str::to_string),
+        usb_path: None,
+    };
+    let present = vec![
+        port("/dev/ttyS0", None),
+        port("/dev/ttyUSB10", None),
+        port("/dev/ttyUSB2", None),
+        port("/dev/ttyACM0", Some("/dev/serial/by-id/usb-Quad_A1-if00")),
+    ];
+    assert_eq!(
+        matching_ports("/dev/ttyUSB*", &present).unwrap(),
+        vec!["/dev/ttyUSB2", "/dev/ttyUSB10"]
+    );
+    assert_eq!(
+        matching_ports("/dev/serial/by-id/usb-Quad_*", &present).unwrap(),
+        vec!["/dev/serial/by-id/usb-Quad_A1-if00"]
+    );
+    assert!(matching_ports("/dev/*", &present).unwrap().len() == 4);
+    assert!(matching_ports("/dev/tty?", &present).unwrap().is_empty());
+
+    let request: BulkConnectionRequest = serde_json::from_value(serde_json::json!({
+        "template": { "name": "rack3-{index}", "description": "Rack 3 {port}", "baud_rate": 9600 },
+        "glob": "/dev/ttyUSB*"
+    }))
+    .unwrap();
+    let requests = request.expand(&present).unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].name, "rack3-1");
+    assert_eq!(requests[0].port, "/dev/ttyUSB2");
+    assert_eq!(requests[1].description, "Rack 3 ttyUSB10");
+    assert_eq!(requests[1].baud_rate, Some(9600));
+
+    let request: BulkConnectionRequest = serde_json::from_value(serde_json::json!({
+        "template": { "name": "rack3" },
+        "ports": ["/dev/ttyUSB0", "/dev/ttyUSB1"]
+    }))
+    .unwrap();
+    assert!(request.expand(&present).is_err());
+    let request: BulkConnectionRequest = serde_json::from_value(serde_json::json!({
+        "template": { "name": "{index}" },
+        "glob": "/dev/ttyXR*"
+    }))
+    .unwrap();
+    let error = request.expand(&present).unwrap_err().to_string();
+    assert!(error.contains("No ports match"), "{}", error);
+}
+
+#[test]
+fn test_connection_request_profile() {
+    use crate::config::{Parity, StopBits};
+    use crate::serial::ephemeral::ConnectionRequest;
+
+    let request: ConnectionRequest = serde_json::from_value(serde_json::json!({
+        "name": "meter", "port": "/dev/ttyUSB1", "profile": "modbus-19200-8E1", "baud_rate": 38400
+    }))
+    .unwrap();
+    let config = request.config().unwrap();
+    assert_eq!(config.baud_rate, 38400);
+    assert_eq!(config.parity, Parity::Even);
+    assert_eq!(config.stop_bits, StopBits::One);
+
+    let request: ConnectionRequest = serde_json::from_value(serde_json::json!({
+        "name": "meter", "port": "/dev/ttyUSB1"
+    }))
+    .unwrap();
+    assert_eq!(request.config().unwrap().baud_rate, 115200);
+
+    let request: ConnectionRequest = serde_json::from_value(serde_json::json!({
+        "name": "meter", "port": "/dev/ttyUSB1", "profile": "nope"
+    }))
+    .unwrap();
+    assert!(request.config().is_err());
+}
+
+#[test]
+fn test_port_groups_follow_usb_topology() {
+    use super::discovery::{usb_path_of, PortDescription, PortKind};
+    use super::port_groups::group;
+
+    assert_eq!(
+        usb_path_of("/sys/devices/pci0000:00/0000:00:14.0/usb1/1-2/1-2.3/1-2.3:1.0/ttyUSB0"),
+        Some("1-2.3".to_string())
+    );
+    assert_eq!(
+        usb_path_of("/sys/devices/pci0000:00/0000:00:14.0/usb3/3-1/3-1:1.2"),
+        Some("3-1".to_string())
+    );
+    assert_eq!(
+        usb_path_of("/sys/devices/platform/serial8250/tty/ttyS0"),
+        None
+    );
+
+    let port = |path: &str, usb_path: Option<&str>, description: &str| PortDescription {
+        path: path.to_string(),
+        kind: PortKind::Usb,
+        friendly_name: None,
+        description: Some(description.to_string()),
+        manufacturer: None,
+        serial_number: None,
+        vid: None,
+        pid: None,
+        by_id: None,
+        usb_path: usb_path.map(str::to_string),
+    };
+    let present = vec![
+        // A quad chip on a root port: one device, four interfaces
+        port("/dev/ttyUSB0", Some("1-1"), "FTDI Quad (0403:6011)"),
+        port("/dev/ttyUSB1", Some("1-1"), "FTDI Quad (0403:6011)"),
+        // Two chips behind a box's hub
+        port("/dev/ttyUSB2", Some("1-2.1"), "FTDI FT232R (0403:6001)"),
+        port("/dev/ttyUSB3", Some("1-2.2"), "Prolific PL2303 (067b:2303)"),
+        // Alone on a hub port, and straight on the host
+        port("/dev/ttyUSB4", Some("2-4.1"), "CH340 (1a86:7523)"),
+        port("/dev/ttyUSB5", Some("1-3"), "CH340 (1a86:7523)"),
+        port("/dev/ttyS0", None, "16550A"),
+    ];
+    let groups = group(&present);
+    assert_eq!(groups.len(), 2);
+    assert_eq!(groups[0].id, "usb-1-1");
+    assert_eq!(groups[0].ports, vec!["/dev/ttyUSB0", "/dev/ttyUSB1"]);
+    assert_eq!(
+        groups[0].description.as_deref(),
+        Some("FTDI Quad (0403:6011)")
+    );
+    assert_eq!(groups[1].usb_path, "1-2");
+    assert_eq!(groups[1].ports, vec!["/dev/ttyUSB2", "/dev/ttyUSB3"]);
+    assert_eq!(groups[1].description, None);
+}
+
+#[tokio::test]
+async fn test_restart_connections_reopens_them() {
+    let (power_path, _power_end) = pty_pair();
+    let (console_path, _console_end) = pty_pair();
+    let manager = SerialManager::new();
+    manager
+        .add_connection(test_config("power", &power_path))
+        .await
+        .unwrap();
+    let mut console = test_config("console", &console_path);
+    console.depends_on = Some("power".to_string());
+    manager.add_connection(console).await.unwrap();
+
+    // The dependency is outside the restart but already open
+    let report = manager
+        .restart_connections(&["console".to_string(), "gone".to_string()])
+        .await;
+    assert_eq!(report.restarted, vec!["console"]);
+    assert!(report.failed.is_empty(), "{:?}", report.failed);
+    let console = manager.get_connection("console").await.unwrap();
+    assert_eq!(console.config().depends_on.as_deref(), Some("power"));
+
+    manager.shutdown().await;
+}
+
+#[test]
+fn test_reconnect_backoff() {
+    let config = crate::config::ReconnectConfig {
+        interval_ms: 100,
+        max_interval_ms: 350,
+        max_attempts: Some(4),
+    };
+    let mut backoff = reconnect::Backoff::new(&config);
+    let delays: Vec<u64> = std::iter::from_fn(|| backoff.next_delay())
+        .map(|delay| delay.as_millis() as u64)
+        .collect();
+    assert_eq!(delays, vec![100, 200, 350, 350]);
+    assert_eq!(backoff.attempts(), 4);
+
+    let config = crate::config::ReconnectConfig {
+        max_attempts: None,
+        ..config
+    };
+    let mut backoff = reconnect::Backoff::new(&config);
+    assert!((0..100).all(|_| backoff.next_delay().is_some()));
+}
+
+#[tokio::test]
+async fn test_lost_port_reconnects() {
+    // The port is opened through a link, so it can be pointed at a new
+    // device while the first one is gone
+    let dir = tempfile::tempdir().unwrap();
+    let link = dir.path().join("ttyUSB0");
+    let (first, device) = pty_pair();
+    std::os::unix::fs::symlink(&first, &link).unwrap();
+    let mut config = test_config("usb", link.to_str().unwrap());
+    config.reconnect = Some(crate::config::ReconnectConfig {
+        interval_ms: 20,
+        max_interval_ms: 100,
+        max_attempts: None,
+    });
+    let manager = SerialManager::new();
+    manager.add_connection(config).await.unwrap();
+    let mut rx = manager.subscribe("usb").await.unwrap();
+
+    std::fs::remove_file(&link).unwrap();
+    drop(device);
+    let stats = || async { manager.get_stats("usb").await.unwrap() };
+    let reconnect = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let reconnect = stats().await.reconnect.unwrap();
+            if reconnect.reconnecting && reconnect.attempts > 0 {
+                return reconnect;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(reconnect.lost_at.is_some());
+    assert!(!stats().await.is_connected);
+    let refused = manager
+        .send_data_written("usb", b"ping", Priority::Interactive)
+        .await;
+    assert!(refused.is_err());
+    // Writes that are not waited on are refused too, not dropped
+    let refused = manager.send_data("usb", b"ping").await.unwrap_err();
+    assert!(refused.to_string().contains("reconnecting"), "{}", refused);
+
+    let (second, mut device) = pty_pair();
+    std::os::unix::fs::symlink(&second, &link).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !stats().await.is_connected {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    let reconnect = stats().await.reconnect.unwrap();
+    assert!(!reconnect.reconnecting);
+    assert_eq!(reconnect.reconnects, 1);
+
+    // The subscriber from before the loss is still attached
+    device.write_all(b"back").await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), b"back");
+    manager.send_data("usb", b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    device.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_lost_port_moved_to_failed() {
+    let dir = tempfile::tempdir().unwrap();
+    let link = dir.path().join("ttyUSB0");
+    let (first, device) = pty_pair();
+    std::os::unix::fs::symlink(&first, &link).unwrap();
+    let mut config = test_config("usb", link.to_str().unwrap());
+    config.reconnect = Some(crate::config::ReconnectConfig {
+        interval_ms: 20,
+        max_interval_ms: 20,
+        max_attempts: Some(2),
+    });
+    let manager = SerialManager::new();
+    manager.add_connection(config).await.unwrap();
+
+    std::fs::remove_file(&link).unwrap();
+    drop(device);
+    let failed = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            if let Some(failed) = manager.get_failed_connection("usb").await {
+                return failed;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(
+        failed.error.contains("gave up after 2 attempts"),
+        "{}",
+        failed.error
+    );
+    assert!(manager.list_connections().await.is_empty());
+
+    // Retried like a connection that failed to open
+    let (second, mut device) = pty_pair();
+    std::os::unix::fs::symlink(&second, &link).unwrap();
+    manager.retry_connection("usb").await.unwrap();
+    assert!(manager.get_failed_connection("usb").await.is_none());
+    manager.send_data("usb", b"ping").await.unwrap();
+    let mut buf = [0u8; 4];
+    device.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"ping");
+
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_lease_waiting_for_the_port_does_not_block_lookups() {
+    let (path, _far_end) = pty_pair();
+    let manager = SerialManager::new();
+    manager
+        .add_connection(test_config("flashing", &path))
+        .await
+        .unwrap();
+    // Out on a flash, which takes the port without a lease
+    let connection = manager.get_connection("flashing").await.unwrap();
+    let flash = connection.acquire_exclusive().await.unwrap();
+
+    let starting = tokio::spawn({
+        let manager = manager.clone();
+        async move {
+            manager
+                .start_lease(
+                    "flashing",
+                    lease::LeaseMode::Release,
+                    Duration::from_secs(60),
+                    "127.0.0.1",
+                )
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let lease = tokio::time::timeout(Duration::from_secs(1), manager.get_lease("flashing"))
+        .await
+        .unwrap();
+    assert!(lease.is_none());
+
+    drop(flash);
+    starting.await.unwrap().unwrap();
+    assert!(manager.get_lease("flashing").await.is_some());
+    manager.end_lease("flashing").await.unwrap();
+    manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_port_lost_during_lease_reconnects() {
+    let (path, mut device) = pty_pair();
+    let mut config = test_config("leased", &path);
+    config.reconnect = Some(crate::config::ReconnectConfig {
+        interval_ms: 20,
+        max_interval_ms: 100,
+        max_attempts: None,
+    });
+    let manager = SerialManager::new();
+    manager.add_connection(config).await.unwrap();
+    let mut rx = manager.subscribe("leased").await.unwrap();
+
+    // Closed by the borrower and never handed back
+    let connection = manager.get_connection("leased").await.unwrap();
+    let mut lease = connection.acquire_exclusive().await.unwrap();
+    lease.close();
+    drop(lease);
+
+    let stats = || async { manager.get_stats("leased").await.unwrap() };
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while stats().await.reconnect.unwrap().reconnects == 0 {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await
+    .unwrap();
+    assert!(stats().await.is_connected);
+    device.write_all(b"back").await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), b"back");
+
+    manager.shutdown().await;
+}
@@ -0,0 +1,96 @@
+//! Rolling per-second traffic counters.
+
+use serde::Serialize;
+use std::collections::VecDeque;
+
+/// Seconds of history kept, and the longest window that can be queried.
+pub const MAX_WINDOW_SECS: u64 = 60;
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Bucket {
+    second: u64,
+    rx_bytes: u64,
+    tx_bytes: u64,
+    rx_frames: u64,
+    tx_frames: u64,
+}
+
+/// Counts traffic in one-second buckets.
+///
+/// A "frame" is a line-terminated message: `\n` on raw connections and `\r`
+/// on SLCAN connections.
+#[derive(Debug, Default)]
+pub struct ThroughputTracker {
+    buckets: VecDeque<Bucket>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Throughput {
+    pub window_seconds: u64,
+    pub rx_bytes_per_sec: f64,
+    pub tx_bytes_per_sec: f64,
+    pub rx_frames_per_sec: f64,
+    pub tx_frames_per_sec: f64,
+}
+
+impl ThroughputTracker {
+    fn bucket(&mut self, second: u64) -> &mut Bucket {
+        if self
+            .buckets
+            .back()
+            .is_none_or(|bucket| bucket.second != second)
+        {
+            self.buckets.push_back(Bucket {
+                second,
+                ..Bucket::default()
+            });
+        }
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.second + MAX_WINDOW_SECS < second)
+        {
+            self.buckets.pop_front();
+        }
+        self.buckets.back_mut().expect("bucket was just pushed")
+    }
+
+    pub fn record_rx(&mut self, second: u64, bytes: usize, frames: usize) {
+        let bucket = self.bucket(second);
+        bucket.rx_bytes += bytes as u64;
+        bucket.rx_frames += frames as u64;
+    }
+
+    pub fn record_tx(&mut self, second: u64, bytes: usize, frames: usize) {
+        let bucket = self.bucket(second);
+        bucket.tx_bytes += bytes as u64;
+        bucket.tx_frames += frames as u64;
+    }
+
+    /// Average rates over the `window` complete seconds before `now`.
+    pub fn rates(&self, now: u64, window: u64) -> Throughput {
+        let window = window.clamp(1, MAX_WINDOW_SECS);
+        let start = now.saturating_sub(window);
+
+        let total = self
+            .buckets
+            .iter()
+            .filter(|bucket| bucket.second >= start && bucket.second < now)
+            .fold(Bucket::default(), |acc, bucket| Bucket {
+                second: 0,
+                rx_bytes: acc.rx_bytes + bucket.rx_bytes,
+                tx_bytes: acc.tx_bytes + bucket.tx_bytes,
+                rx_frames: acc.rx_frames + bucket.rx_frames,
+                tx_frames: acc.tx_frames + bucket.tx_frames,
+            });
+
+        let secs = window as f64;
+        Throughput {
+            window_seconds: window,
+            rx_bytes_per_sec: total.rx_bytes as f64 / secs,
+            tx_bytes_per_sec: total.tx_bytes as f64 / secs,
+            rx_frames_per_sec: total.rx_frames as f64 / secs,
+            tx_frames_per_sec: total.tx_frames as f64 / secs,
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! Distributions of frame sizes and of the gaps between frames over a
+//! sliding window, for tuning framing timeouts and coalescing windows.
+
+use super::history::MAX_FRAME_BYTES;
+use super::latency::percentile;
+use super::Direction;
+use crate::telemetry::histogram::Histogram;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Seconds of frames kept, and the longest window that can be queried.
+pub const MAX_PROFILE_WINDOW_SECS: u64 = 600;
+
+/// Frames kept per direction, however short the window they arrive in.
+const MAX_SAMPLES: usize = 10_000;
+
+/// Upper bounds of the frame size buckets, in bytes.
+const SIZE_BOUNDS: &[f64] = &[
+    1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0, 512.0, 1024.0, 2048.0, 4096.0,
+];
+
+/// Upper bounds of the gap buckets, in milliseconds.
+const GAP_BOUNDS_MS: &[f64] = &[
+    1.0, 2.0, 5.0, 10.0, 20.0, 50.0, 100.0, 200.0, 500.0, 1000.0, 2000.0, 5000.0, 10000.0,
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct HistogramBucket {
+    /// Inclusive upper bound, `None` for the bucket above the last bound.
+    pub le: Option<f64>,
+    pub count: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Distribution {
+    pub count: u64,
+    pub min: Option<f64>,
+    pub p50: Option<f64>,
+    pub p95: Option<f64>,
+    pub p99: Option<f64>,
+    pub max: Option<f64>,
+    pub buckets: Vec<HistogramBucket>,
+}
+
+impl Distribution {
+    fn new(mut values: Vec<f64>, bounds: &[f64]) -> Self {
+        values.sort_by(f64::total_cmp);
+        let mut buckets: Vec<HistogramBucket> = bounds
+            .iter()
+            .map(|&le| HistogramBucket {
+                le: Some(le),
+                count: 0,
+            })
+            .chain(std::iter::once(HistogramBucket { le: None, count: 0 }))
+            .collect();
+        for value in &values {
+            let slot = bounds
+                .iter()
+                .position(|&le| *value <= le)
+                .unwrap_or(bounds.len());
+            buckets[slot].count += 1;
+        }
+        Self {
+            count: values.len() as u64,
+            min: values.first().copied(),
+            p50: percentile(&values, 50.0),
+            p95: percentile(&values, 95.0),
+            p99: percentile(&values, 99.0),
+            max: values.last().copied(),
+            buckets,
+        }
+    }
+}
+
+/// Frames in one direction over the window.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DirectionProfile {
+    pub frame_bytes: Distribution,
+    /// From the chunk that ended one frame to the chunk that started the
+    /// next; frames that share a chunk are 0 ms apart.
+    pub gap_ms: Distribution,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TrafficProfile {
+    pub window_seconds: u64,
+    pub rx: DirectionProfile,
+    pub tx: DirectionProfile,
+}
+
+#[derive(Debug)]
+struct Sample {
+    /// When the frame's last chunk arrived.
+    at: Instant,
+    bytes: usize,
+    /// Gap since the previous frame, unless this is the first.
+    gap: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Lane {
+    /// The frame being received: when it started and its length so far.
+    current: Option<(Instant, usize)>,
+    last_end: Option<Instant>,
+    samples: VecDeque<Sample>,
+    /// Every frame since the connection was opened, for scraping.
+    totals: Histogram,
+}
+
+impl Default for Lane {
+    fn default() -> Self {
+        Self {
+            current: None,
+            last_end: None,
+            samples: VecDeque::new(),
+            totals: Histogram::new(SIZE_BOUNDS),
+        }
+    }
+}
+
+impl Lane {
+    fn finish(&mut self, at: Instant) {
+        let Some((start, bytes)) = self.current.take() else {
+            return;
+        };
+        self.totals.observe(bytes as f64);
+        let gap = self
+            .last_end
+            .map(|end| start.saturating_duration_since(end));
+        self.last_end = Some(at);
+        if self.samples.len() == MAX_SAMPLES {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample { at, bytes, gap });
+    }
+
+    fn profile(&self, since: Option<Instant>) -> DirectionProfile {
+        let recent: Vec<&Sample> = self
+            .samples
+            .iter()
+            .filter(|sample| since.is_none_or(|since| sample.at >= since))
+            .collect();
+        DirectionProfile {
+            frame_bytes: Distribution::new(
+                recent.iter().map(|sample| sample.bytes as f64).collect(),
+                SIZE_BOUNDS,
+            ),
+            gap_ms: Distribution::new(
+                recent
+                    .iter()
+                    .filter_map(|sample| sample.gap)
+                    .map(|gap| gap.as_secs_f64() * 1000.0)
+                    .collect(),
+                GAP_BOUNDS_MS,
+            ),
+        }
+    }
+}
+
+/// Splits traffic into frames on the same terminator as the throughput
+/// counters and remembers their sizes and spacing.
+#[derive(Debug)]
+pub struct TrafficProfiler {
+    frame_end: u8,
+    lanes: [Lane; 2],
+}
+
+impl TrafficProfiler {
+    pub fn new(frame_end: u8) -> Self {
+        Self {
+            frame_end,
+            lanes: Default::default(),
+        }
+    }
+
+    pub fn record(&mut self, direction: Direction, data: &[u8], at: Instant) {
+        let lane = &mut self.lanes[direction as usize];
+        for chunk in data.split_inclusive(|&b| b == self.frame_end) {
+            let (_, bytes) = lane.current.get_or_insert((at, 0));
+            *bytes += chunk.len();
+            if chunk.ends_with(&[self.frame_end]) || *bytes >= MAX_FRAME_BYTES {
+                lane.finish(at);
+            }
+        }
+        let horizon = Duration::from_secs(MAX_PROFILE_WINDOW_SECS);
+        while lane
+            .samples
+            .front()
+            .is_some_and(|sample| at.saturating_duration_since(sample.at) > horizon)
+        {
+            lane.samples.pop_front();
+        }
+    }
+
+    /// Sizes of every frame completed in `direction` since the connection
+    /// was opened.
+    pub fn frame_sizes(&self, direction: Direction) -> Histogram {
+        self.lanes[direction as usize].totals.clone()
+    }
+
+    /// Frames completed in the last `window` seconds before `now`.
+    pub fn profile(&self, window: u64, now: Instant) -> TrafficProfile {
+        let window = window.clamp(1, MAX_PROFILE_WINDOW_SECS);
+        // Everything so far if the clock started less than a window ago
+        let since = now.checked_sub(Duration::from_secs(window));
+        TrafficProfile {
+            window_seconds: window,
+            rx: self.lanes[Direction::Rx as usize].profile(since),
+            tx: self.lanes[Direction::Tx as usize].profile(since),
+        }
+    }
+}
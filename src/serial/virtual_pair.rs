@@ -0,0 +1,92 @@
+//! In-memory null-modem pairs: two connections whose TX and RX are
+//! cross-wired, for exercising serial clients without hardware.
+
+use crate::config::{
+    ConnectionMode, DataBits, FlowControl, LoggingConfig, Parity, SerialConnectionConfig,
+    SlcanConfig, StopBits, TxBatchConfig,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Bytes buffered in each direction before writers wait for the reader.
+pub const PAIR_BUFFER_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VirtualPairRequest {
+    pub name: String,
+    /// Name of the first connection; defaults to `<name>-a`.
+    #[serde(default)]
+    pub a: Option<String>,
+    /// Name of the second connection; defaults to `<name>-b`.
+    #[serde(default)]
+    pub b: Option<String>,
+}
+
+impl VirtualPairRequest {
+    /// The names of the two connections making up the pair.
+    pub fn endpoints(&self) -> (String, String) {
+        (
+            self.a.clone().unwrap_or_else(|| format!("{}-a", self.name)),
+            self.b.clone().unwrap_or_else(|| format!("{}-b", self.name)),
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct VirtualPairInfo {
+    pub name: String,
+    pub a: String,
+    pub b: String,
+    /// When the pair is due to be removed, if it was created with `expires_in`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// Config for one end of pair `pair`. Line settings are nominal since no
+/// UART is involved.
+pub fn endpoint_config(name: &str, pair: &str) -> SerialConnectionConfig {
+    SerialConnectionConfig {
+        name: name.to_string(),
+        port: format!("virtual:{}", pair),
+        baud_rate: 115200,
+        data_bits: DataBits::Eight,
+        stop_bits: StopBits::One,
+        parity: Parity::None,
+        flow_control: FlowControl::None,
+        enabled: true,
+        logging: LoggingConfig {
+            enabled: false,
+            path: format!("{}.log", name).into(),
+            raw: false,
+            max_file_bytes: None,
+            retention: None,
+            syslog: None,
+            encryption: None,
+            redact: None,
+            ship: None,
+        },
+        description: format!("Virtual null-modem pair {}", pair),
+        mode: ConnectionMode::Raw,
+        slcan: SlcanConfig::default(),
+        metrics: vec![],
+        notify: vec![],
+        mirror: None,
+        max_tx_rate_bytes_per_sec: None,
+        max_clients: None,
+        tx_batch: TxBatchConfig::default(),
+        suppress_echo: false,
+        idle_close_minutes: None,
+        depends_on: None,
+        startup_delay_ms: 0,
+        power: None,
+        banner: None,
+        write_lock: None,
+        quota: None,
+        garbage_check: None,
+        keepalive: None,
+        identify: None,
+        rules: vec![],
+        endpoints: vec![],
+        reconnect: None,
+    }
+}
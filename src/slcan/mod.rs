@@ -0,0 +1,223 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+/// Maximum 11-bit standard CAN identifier.
+pub const MAX_STANDARD_ID: u32 = 0x7FF;
+/// Maximum 29-bit extended CAN identifier.
+pub const MAX_EXTENDED_ID: u32 = 0x1FFF_FFFF;
+/// Classic CAN frames carry at most 8 data bytes.
+pub const MAX_DLC: u8 = 8;
+
+/// A classic CAN frame as carried over the SLCAN (Lawicel) ASCII protocol.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CanFrame {
+    pub id: u32,
+    #[serde(default)]
+    pub extended: bool,
+    #[serde(default)]
+    pub rtr: bool,
+    pub dlc: u8,
+    #[serde(with = "hex_bytes", default)]
+    pub data: Vec<u8>,
+    /// Adapter timestamp in milliseconds (0-59999), if timestamps are enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timestamp: Option<u16>,
+}
+
+impl CanFrame {
+    /// Builds a frame, checking the identifier range and DLC/data consistency.
+    pub fn new(id: u32, extended: bool, rtr: bool, dlc: Option<u8>, data: Vec<u8>) -> Result<Self> {
+        let max_id = if extended {
+            MAX_EXTENDED_ID
+        } else {
+            MAX_STANDARD_ID
+        };
+        if id > max_id {
+            anyhow::bail!(
+                "CAN id 0x{:X} out of range for {} frame (max 0x{:X})",
+                id,
+                if extended { "extended" } else { "standard" },
+                max_id
+            );
+        }
+
+        if data.len() > MAX_DLC as usize {
+            anyhow::bail!("CAN data too long: {} bytes (max {})", data.len(), MAX_DLC);
+        }
+
+        let dlc = if rtr {
+            if !data.is_empty() {
+                anyhow::bail!("Remote frames cannot carry data");
+            }
+            dlc.unwrap_or(0)
+        } else {
+            match dlc {
+                Some(dlc) if dlc as usize != data.len() => {
+                    anyhow::bail!("DLC {} does not match data length {}", dlc, data.len())
+                }
+                _ => data.len() as u8,
+            }
+        };
+
+        if dlc > MAX_DLC {
+            anyhow::bail!("DLC {} out of range (max {})", dlc, MAX_DLC);
+        }
+
+        Ok(Self {
+            id,
+            extended,
+            rtr,
+            dlc,
+            data,
+            timestamp: None,
+        })
+    }
+
+    /// Encodes the frame as an SLCAN transmit command, including the trailing `\r`.
+    pub fn encode(&self) -> String {
+        let command = match (self.extended, self.rtr) {
+            (false, false) => 't',
+            (true, false) => 'T',
+            (false, true) => 'r',
+            (true, true) => 'R',
+        };
+        let id = if self.extended {
+            format!("{:08X}", self.id)
+        } else {
+            format!("{:03X}", self.id)
+        };
+        let data = if self.rtr {
+            String::new()
+        } else {
+            hex::encode_upper(&self.data)
+        };
+        format!("{}{}{}{}\r", command, id, self.dlc, data)
+    }
+
+    /// Parses a single SLCAN frame line (without the trailing `\r`).
+    pub fn parse(line: &str) -> Result<Self> {
+        if !line.is_ascii() {
+            anyhow::bail!("Not an SLCAN frame: {:?}", line);
+        }
+
+        let mut chars = line.chars();
+        let (extended, rtr) = match chars.next() {
+            Some('t') => (false, false),
+            Some('T') => (true, false),
+            Some('r') => (false, true),
+            Some('R') => (true, true),
+            _ => anyhow::bail!("Not an SLCAN frame: {:?}", line),
+        };
+
+        let id_len = if extended { 8 } else { 3 };
+        let rest = &line[1..];
+        if rest.len() < id_len + 1 {
+            anyhow::bail!("Truncated SLCAN frame: {:?}", line);
+        }
+
+        let id = u32::from_str_radix(&rest[..id_len], 16)
+            .map_err(|e| anyhow::anyhow!("Invalid CAN id in {:?}: {}", line, e))?;
+        let dlc = rest[id_len..id_len + 1]
+            .parse::<u8>()
+            .map_err(|e| anyhow::anyhow!("Invalid DLC in {:?}: {}", line, e))?;
+        if dlc > MAX_DLC {
+            anyhow::bail!("DLC {} out of range in {:?}", dlc, line);
+        }
+
+        let payload = &rest[id_len + 1..];
+        let data_len = if rtr { 0 } else { dlc as usize * 2 };
+        if payload.len() < data_len {
+            anyhow::bail!("Truncated SLCAN data in {:?}", line);
+        }
+
+        let data = hex::decode(&payload[..data_len])
+            .map_err(|e| anyhow::anyhow!("Invalid CAN data in {:?}: {}", line, e))?;
+
+        let timestamp = match &payload[data_len..] {
+            "" => None,
+            ts if ts.len() == 4 => Some(
+                u16::from_str_radix(ts, 16)
+                    .map_err(|e| anyhow::anyhow!("Invalid timestamp in {:?}: {}", line, e))?,
+            ),
+            extra => anyhow::bail!("Unexpected trailing data {:?} in {:?}", extra, line),
+        };
+
+        let mut frame = Self::new(id, extended, rtr, Some(dlc), data)?;
+        frame.timestamp = timestamp;
+        Ok(frame)
+    }
+}
+
+/// Incrementally splits an SLCAN byte stream into frames.
+///
+/// Command acknowledgements (`\r`, `z`, `Z`) and error bells (`\x07`) are
+/// silently discarded.
+#[derive(Debug, Default)]
+pub struct SlcanDecoder {
+    buffer: String,
+}
+
+impl SlcanDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn feed(&mut self, data: &[u8]) -> Vec<CanFrame> {
+        let mut frames = Vec::new();
+
+        for &byte in data {
+            match byte {
+                b'\r' | b'\n' | 0x07 => {
+                    if !self.buffer.is_empty() {
+                        if let Ok(frame) = CanFrame::parse(&self.buffer) {
+                            frames.push(frame);
+                        }
+                        self.buffer.clear();
+                    }
+                }
+                b if b.is_ascii_graphic() => self.buffer.push(b as char),
+                _ => self.buffer.clear(),
+            }
+        }
+
+        frames
+    }
+}
+
+/// Returns the commands that close, configure and reopen the channel at `bitrate`.
+pub fn init_commands(bitrate: u32) -> Result<Vec<String>> {
+    let code = match bitrate {
+        10_000 => 0,
+        20_000 => 1,
+        50_000 => 2,
+        100_000 => 3,
+        125_000 => 4,
+        250_000 => 5,
+        500_000 => 6,
+        800_000 => 7,
+        1_000_000 => 8,
+        _ => anyhow::bail!("Unsupported SLCAN bitrate: {}", bitrate),
+    };
+
+    Ok(vec![
+        "C\r".to_string(),
+        format!("S{}\r", code),
+        "O\r".to_string(),
+    ])
+}
+
+mod hex_bytes {
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(data: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode_upper(data))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        hex::decode(s.replace(' ', "")).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,99 @@
+use super::*;
+
+#[test]
+fn test_parse_standard_frame() {
+    let frame = CanFrame::parse("t1232AABB").unwrap();
+    assert_eq!(frame.id, 0x123);
+    assert!(!frame.extended);
+    assert!(!frame.rtr);
+    assert_eq!(frame.dlc, 2);
+    assert_eq!(frame.data, vec![0xAA, 0xBB]);
+    assert_eq!(frame.timestamp, None);
+}
+
+#[test]
+fn test_parse_extended_frame_with_timestamp() {
+    let frame = CanFrame::parse("T1ABCDEF0301020304D2").unwrap();
+    assert_eq!(frame.id, 0x1ABCDEF0);
+    assert!(frame.extended);
+    assert_eq!(frame.dlc, 3);
+    assert_eq!(frame.data, vec![0x01, 0x02, 0x03]);
+    assert_eq!(frame.timestamp, Some(0x04D2));
+}
+
+#[test]
+fn test_parse_remote_frame() {
+    let frame = CanFrame::parse("r7FF4").unwrap();
+    assert_eq!(frame.id, 0x7FF);
+    assert!(frame.rtr);
+    assert_eq!(frame.dlc, 4);
+    assert!(frame.data.is_empty());
+}
+
+#[test]
+fn test_parse_rejects_garbage() {
+    assert!(CanFrame::parse("").is_err());
+    assert!(CanFrame::parse("z").is_err());
+    assert!(CanFrame::parse("t12").is_err());
+    assert!(CanFrame::parse("t1239").is_err());
+    assert!(CanFrame::parse("t1232AA").is_err());
+    assert!(CanFrame::parse("t1231AAXYZ").is_err());
+}
+
+#[test]
+fn test_encode_round_trip() {
+    let frame = CanFrame::new(0x18FF50E5, true, false, None, vec![0xDE, 0xAD]).unwrap();
+    assert_eq!(frame.encode(), "T18FF50E52DEAD\r");
+    let parsed = CanFrame::parse(frame.encode().trim_end()).unwrap();
+    assert_eq!(parsed, frame);
+}
+
+#[test]
+fn test_new_validates_id_range() {
+    assert!(CanFrame::new(0x800, false, false, None, vec![]).is_err());
+    assert!(CanFrame::new(0x800, true, false, None, vec![]).is_ok());
+    assert!(CanFrame::new(0x2000_0000, true, false, None, vec![]).is_err());
+}
+
+#[test]
+fn test_new_validates_dlc_and_data() {
+    assert!(CanFrame::new(0x1, false, false, None, vec![0; 9]).is_err());
+    assert!(CanFrame::new(0x1, false, false, Some(3), vec![0; 2]).is_err());
+    assert!(CanFrame::new(0x1, false, true, Some(2), vec![0; 2]).is_err());
+    assert!(CanFrame::new(0x1, false, true, Some(9), vec![]).is_err());
+    assert_eq!(
+        CanFrame::new(0x1, false, true, Some(8), vec![])
+            .unwrap()
+            .dlc,
+        8
+    );
+}
+
+#[test]
+fn test_decoder_splits_stream() {
+    let mut decoder = SlcanDecoder::new();
+    assert!(decoder.feed(b"\r\rt1001").is_empty());
+    let frames = decoder.feed(b"11\rz\r\x07T000000010\r");
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0].id, 0x100);
+    assert_eq!(frames[0].data, vec![0x11]);
+    assert_eq!(frames[1].id, 0x1);
+    assert!(frames[1].extended);
+}
+
+#[test]
+fn test_init_commands() {
+    assert_eq!(
+        init_commands(500_000).unwrap(),
+        vec!["C\r".to_string(), "S6\r".to_string(), "O\r".to_string()]
+    );
+    assert!(init_commands(123_456).is_err());
+}
+
+#[test]
+fn test_frame_json_uses_hex_data() {
+    let frame = CanFrame::new(0x123, false, false, None, vec![0x01, 0xFF]).unwrap();
+    let json = serde_json::to_value(&frame).unwrap();
+    assert_eq!(json["data"], "01FF");
+    assert_eq!(json["id"], 0x123);
+}
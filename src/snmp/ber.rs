@@ -0,0 +1,310 @@
+//! The subset of BER (X.690) needed to read and write SNMPv2c messages.
+
+use anyhow::Result;
+
+pub type Oid = Vec<u32>;
+
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_ID: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const COUNTER32: u8 = 0x41;
+const GAUGE32: u8 = 0x42;
+const TIME_TICKS: u8 = 0x43;
+const COUNTER64: u8 = 0x46;
+const NO_SUCH_OBJECT: u8 = 0x80;
+const NO_SUCH_INSTANCE: u8 = 0x81;
+const END_OF_MIB_VIEW: u8 = 0x82;
+
+pub const GET_REQUEST: u8 = 0xA0;
+pub const GET_NEXT_REQUEST: u8 = 0xA1;
+pub const RESPONSE: u8 = 0xA2;
+pub const SET_REQUEST: u8 = 0xA3;
+pub const GET_BULK_REQUEST: u8 = 0xA5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Integer(i64),
+    OctetString(Vec<u8>),
+    Null,
+    ObjectId(Oid),
+    Counter32(u32),
+    Gauge32(u32),
+    TimeTicks(u32),
+    Counter64(u64),
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pdu {
+    pub kind: u8,
+    pub request_id: i64,
+    /// `non-repeaters` in a GetBulkRequest.
+    pub error_status: i64,
+    /// `max-repetitions` in a GetBulkRequest.
+    pub error_index: i64,
+    pub varbinds: Vec<(Oid, Value)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub version: i64,
+    pub community: Vec<u8>,
+    pub pdu: Pdu,
+}
+
+/// Dotted OID such as `1.3.6.1.4.1.8072`.
+pub fn parse_oid(text: &str) -> Result<Oid> {
+    let oid = text
+        .trim_start_matches('.')
+        .split('.')
+        .map(|arc| {
+            arc.parse::<u32>()
+                .map_err(|_| anyhow::anyhow!("Invalid OID {}", text))
+        })
+        .collect::<Result<Oid>>()?;
+    if oid.len() < 2 || oid[0] > 2 || (oid[0] < 2 && oid[1] >= 40) {
+        anyhow::bail!("Invalid OID {}", text);
+    }
+    Ok(oid)
+}
+
+fn push_tlv(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+}
+
+fn encode_signed(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    // Drop leading bytes that only repeat the sign bit
+    while start < 7
+        && ((bytes[start] == 0x00 && bytes[start + 1] & 0x80 == 0)
+            || (bytes[start] == 0xff && bytes[start + 1] & 0x80 != 0))
+    {
+        start += 1;
+    }
+    bytes[start..].to_vec()
+}
+
+fn encode_unsigned(value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+    let mut content = Vec::with_capacity(9);
+    if bytes[skip] & 0x80 != 0 {
+        content.push(0);
+    }
+    content.extend_from_slice(&bytes[skip..]);
+    content
+}
+
+fn encode_oid(oid: &[u32]) -> Vec<u8> {
+    let mut content = Vec::new();
+    let first = oid.first().copied().unwrap_or(0) * 40 + oid.get(1).copied().unwrap_or(0);
+    for arc in std::iter::once(first).chain(oid.iter().skip(2).copied()) {
+        let mut groups = vec![(arc & 0x7f) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            groups.push((rest & 0x7f) as u8 | 0x80);
+            rest >>= 7;
+        }
+        content.extend(groups.iter().rev());
+    }
+    content
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &Value) {
+    match value {
+        Value::Integer(v) => push_tlv(out, INTEGER, &encode_signed(*v)),
+        Value::OctetString(v) => push_tlv(out, OCTET_STRING, v),
+        Value::Null => push_tlv(out, NULL, &[]),
+        Value::ObjectId(v) => push_tlv(out, OBJECT_ID, &encode_oid(v)),
+        Value::Counter32(v) => push_tlv(out, COUNTER32, &encode_unsigned(*v as u64)),
+        Value::Gauge32(v) => push_tlv(out, GAUGE32, &encode_unsigned(*v as u64)),
+        Value::TimeTicks(v) => push_tlv(out, TIME_TICKS, &encode_unsigned(*v as u64)),
+        Value::Counter64(v) => push_tlv(out, COUNTER64, &encode_unsigned(*v)),
+        Value::NoSuchObject => push_tlv(out, NO_SUCH_OBJECT, &[]),
+        Value::NoSuchInstance => push_tlv(out, NO_SUCH_INSTANCE, &[]),
+        Value::EndOfMibView => push_tlv(out, END_OF_MIB_VIEW, &[]),
+    }
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut varbinds = Vec::new();
+        for (oid, value) in &self.pdu.varbinds {
+            let mut varbind = Vec::new();
+            push_tlv(&mut varbind, OBJECT_ID, &encode_oid(oid));
+            encode_value(&mut varbind, value);
+            push_tlv(&mut varbinds, SEQUENCE, &varbind);
+        }
+
+        let mut pdu = Vec::new();
+        push_tlv(&mut pdu, INTEGER, &encode_signed(self.pdu.request_id));
+        push_tlv(&mut pdu, INTEGER, &encode_signed(self.pdu.error_status));
+        push_tlv(&mut pdu, INTEGER, &encode_signed(self.pdu.error_index));
+        push_tlv(&mut pdu, SEQUENCE, &varbinds);
+
+        let mut message = Vec::new();
+        push_tlv(&mut message, INTEGER, &encode_signed(self.version));
+        push_tlv(&mut message, OCTET_STRING, &self.community);
+        push_tlv(&mut message, self.pdu.kind, &pdu);
+
+        let mut out = Vec::new();
+        push_tlv(&mut out, SEQUENCE, &message);
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut outer = Reader(data);
+        let mut message = Reader(outer.expect(SEQUENCE)?);
+        let version = decode_signed(message.expect(INTEGER)?)?;
+        let community = message.expect(OCTET_STRING)?.to_vec();
+
+        let (kind, content) = message.tlv()?;
+        let mut pdu = Reader(content);
+        let request_id = decode_signed(pdu.expect(INTEGER)?)?;
+        let error_status = decode_signed(pdu.expect(INTEGER)?)?;
+        let error_index = decode_signed(pdu.expect(INTEGER)?)?;
+
+        let mut list = Reader(pdu.expect(SEQUENCE)?);
+        let mut varbinds = Vec::new();
+        while !list.0.is_empty() {
+            let mut varbind = Reader(list.expect(SEQUENCE)?);
+            let oid = decode_oid(varbind.expect(OBJECT_ID)?)?;
+            let (tag, content) = varbind.tlv()?;
+            varbinds.push((oid, decode_value(tag, content)?));
+        }
+
+        Ok(Self {
+            version,
+            community,
+            pdu: Pdu {
+                kind,
+                request_id,
+                error_status,
+                error_index,
+                varbinds,
+            },
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn tlv(&mut self) -> Result<(u8, &'a [u8])> {
+        let data = self.0;
+        if data.len() < 2 {
+            anyhow::bail!("Truncated BER element");
+        }
+        let tag = data[0];
+        let (len, header) = match data[1] {
+            len if len < 0x80 => (len as usize, 2),
+            0x81..=0x84 => {
+                let count = (data[1] & 0x7f) as usize;
+                let bytes = data
+                    .get(2..2 + count)
+                    .ok_or_else(|| anyhow::anyhow!("Truncated BER length"))?;
+                let len = bytes.iter().fold(0usize, |len, &b| (len << 8) | b as usize);
+                (len, 2 + count)
+            }
+            _ => anyhow::bail!("Unsupported BER length encoding"),
+        };
+        let content = data
+            .get(header..header + len)
+            .ok_or_else(|| anyhow::anyhow!("Truncated BER element"))?;
+        self.0 = &data[header + len..];
+        Ok((tag, content))
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<&'a [u8]> {
+        let (found, content) = self.tlv()?;
+        if found != tag {
+            anyhow::bail!("Expected BER tag {:#04x}, found {:#04x}", tag, found);
+        }
+        Ok(content)
+    }
+}
+
+fn decode_signed(content: &[u8]) -> Result<i64> {
+    if content.is_empty() || content.len() > 8 {
+        anyhow::bail!("Invalid INTEGER length {}", content.len());
+    }
+    let initial = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    Ok(content
+        .iter()
+        .fold(initial, |value: i64, &b| (value << 8) | b as i64))
+}
+
+fn decode_unsigned(content: &[u8]) -> Result<u64> {
+    let content = match content {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        _ => content,
+    };
+    if content.is_empty() || content.len() > 8 {
+        anyhow::bail!("Invalid unsigned length {}", content.len());
+    }
+    Ok(content
+        .iter()
+        .fold(0u64, |value, &b| (value << 8) | b as u64))
+}
+
+fn decode_oid(content: &[u8]) -> Result<Oid> {
+    let mut arcs = Vec::new();
+    let mut arc: u32 = 0;
+    for (i, &b) in content.iter().enumerate() {
+        if arc > u32::MAX >> 7 {
+            anyhow::bail!("OID arc out of range");
+        }
+        arc = (arc << 7) | (b & 0x7f) as u32;
+        if b & 0x80 == 0 {
+            arcs.push(arc);
+            arc = 0;
+        } else if i == content.len() - 1 {
+            anyhow::bail!("Truncated OID");
+        }
+    }
+    let Some(&first) = arcs.first() else {
+        anyhow::bail!("Empty OID");
+    };
+    let (a, b) = match first {
+        0..=39 => (0, first),
+        40..=79 => (1, first - 40),
+        _ => (2, first - 80),
+    };
+    let mut oid = vec![a, b];
+    oid.extend_from_slice(&arcs[1..]);
+    Ok(oid)
+}
+
+fn decode_value(tag: u8, content: &[u8]) -> Result<Value> {
+    let narrow =
+        |value: u64| u32::try_from(value).map_err(|_| anyhow::anyhow!("32-bit value out of range"));
+    Ok(match tag {
+        INTEGER => Value::Integer(decode_signed(content)?),
+        OCTET_STRING => Value::OctetString(content.to_vec()),
+        NULL => Value::Null,
+        OBJECT_ID => Value::ObjectId(decode_oid(content)?),
+        COUNTER32 => Value::Counter32(narrow(decode_unsigned(content)?)?),
+        GAUGE32 => Value::Gauge32(narrow(decode_unsigned(content)?)?),
+        TIME_TICKS => Value::TimeTicks(narrow(decode_unsigned(content)?)?),
+        COUNTER64 => Value::Counter64(decode_unsigned(content)?),
+        NO_SUCH_OBJECT => Value::NoSuchObject,
+        NO_SUCH_INSTANCE => Value::NoSuchInstance,
+        END_OF_MIB_VIEW => Value::EndOfMibView,
+        _ => anyhow::bail!("Unsupported value type {:#04x}", tag),
+    })
+}
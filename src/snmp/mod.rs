@@ -0,0 +1,198 @@
+//! Read-only SNMPv2c agent exposing connection state and byte counters
+//! under a private MIB, for monitoring systems that only speak SNMP.
+//!
+//! Objects, relative to the configured base OID:
+//!
+//! - `.1.0` connection count (Gauge32)
+//! - `.2.1.<column>.<row>` connection table, one row per connection in
+//!   name order: `1` index, `2` name, `3` port, `4` status (`1` up,
+//!   `2` down), `5` bytes received and `6` bytes sent (Counter64), and
+//!   `7` uptime (TimeTicks)
+
+pub mod ber;
+
+use crate::config::SnmpConfig;
+use crate::serial::SerialManager;
+use crate::telemetry::push::{self, Snapshot};
+use anyhow::Result;
+use ber::{Message, Oid, Pdu, Value};
+use std::net::SocketAddr;
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+
+/// `version` field value for SNMPv2c.
+pub const VERSION_2C: i64 = 1;
+
+/// Most varbinds returned for one GetBulkRequest, keeping replies well
+/// inside a UDP datagram.
+const MAX_BULK_VARBINDS: usize = 128;
+
+/// `notWritable` error status.
+const NOT_WRITABLE: i64 = 17;
+
+const STATUS_UP: i64 = 1;
+const STATUS_DOWN: i64 = 2;
+
+/// Every object the agent serves, in OID order.
+pub fn build_view(base: &[u32], snapshots: &[Snapshot]) -> Vec<(Oid, Value)> {
+    let oid = |suffix: &[u32]| -> Oid { base.iter().chain(suffix).copied().collect() };
+
+    let mut view = vec![(oid(&[1, 0]), Value::Gauge32(snapshots.len() as u32))];
+    let columns: [fn(u32, &Snapshot) -> Value; 7] = [
+        |row, _| Value::Integer(row as i64),
+        |_, snapshot| Value::OctetString(snapshot.stats.name.clone().into_bytes()),
+        |_, snapshot| Value::OctetString(snapshot.stats.port.clone().into_bytes()),
+        |_, snapshot| {
+            Value::Integer(if snapshot.stats.is_connected {
+                STATUS_UP
+            } else {
+                STATUS_DOWN
+            })
+        },
+        |_, snapshot| Value::Counter64(snapshot.stats.bytes_received),
+        |_, snapshot| Value::Counter64(snapshot.stats.bytes_sent),
+        |_, snapshot| {
+            let ticks = snapshot.stats.uptime_seconds.saturating_mul(100);
+            Value::TimeTicks(ticks.min(u32::MAX as u64) as u32)
+        },
+    ];
+    // Column-major, as GetNext walks a table
+    for (column, value) in (1..).zip(columns) {
+        for (row, snapshot) in (1..).zip(snapshots) {
+            view.push((oid(&[2, 1, column, row]), value(row, snapshot)));
+        }
+    }
+    view
+}
+
+fn get(view: &[(Oid, Value)], oid: &Oid) -> Value {
+    match view.binary_search_by(|(candidate, _)| candidate.cmp(oid)) {
+        Ok(index) => view[index].1.clone(),
+        Err(_) => Value::NoSuchObject,
+    }
+}
+
+fn get_next(view: &[(Oid, Value)], oid: &Oid) -> (Oid, Value) {
+    let index = view.partition_point(|(candidate, _)| candidate <= oid);
+    match view.get(index) {
+        Some(entry) => entry.clone(),
+        None => (oid.clone(), Value::EndOfMibView),
+    }
+}
+
+fn get_bulk(view: &[(Oid, Value)], pdu: &Pdu) -> Vec<(Oid, Value)> {
+    let non_repeaters = pdu.error_status.clamp(0, pdu.varbinds.len() as i64) as usize;
+    let max_repetitions = pdu.error_index.clamp(0, MAX_BULK_VARBINDS as i64) as usize;
+    let (single, repeated) = pdu.varbinds.split_at(non_repeaters);
+
+    let mut varbinds: Vec<(Oid, Value)> =
+        single.iter().map(|(oid, _)| get_next(view, oid)).collect();
+    let mut cursors: Vec<Oid> = repeated.iter().map(|(oid, _)| oid.clone()).collect();
+    for _ in 0..max_repetitions {
+        if varbinds.len() + cursors.len() > MAX_BULK_VARBINDS {
+            break;
+        }
+        let mut exhausted = true;
+        for cursor in &mut cursors {
+            let (oid, value) = get_next(view, cursor);
+            exhausted &= value == Value::EndOfMibView;
+            cursor.clone_from(&oid);
+            varbinds.push((oid, value));
+        }
+        if exhausted {
+            break;
+        }
+    }
+    varbinds
+}
+
+/// Answers one request datagram. Requests for another SNMP version or
+/// with the wrong community are ignored, as the protocol requires.
+pub fn respond(request: &[u8], community: &str, view: &[(Oid, Value)]) -> Result<Option<Vec<u8>>> {
+    let message = Message::decode(request)?;
+    if message.version != VERSION_2C || message.community != community.as_bytes() {
+        return Ok(None);
+    }
+
+    let request = message.pdu;
+    let mut response = Pdu {
+        kind: ber::RESPONSE,
+        request_id: request.request_id,
+        error_status: 0,
+        error_index: 0,
+        varbinds: Vec::new(),
+    };
+    match request.kind {
+        ber::GET_REQUEST => {
+            response.varbinds = request
+                .varbinds
+                .iter()
+                .map(|(oid, _)| (oid.clone(), get(view, oid)))
+                .collect();
+        }
+        ber::GET_NEXT_REQUEST => {
+            response.varbinds = request
+                .varbinds
+                .iter()
+                .map(|(oid, _)| get_next(view, oid))
+                .collect();
+        }
+        ber::GET_BULK_REQUEST => response.varbinds = get_bulk(view, &request),
+        ber::SET_REQUEST => {
+            response.error_status = NOT_WRITABLE;
+            response.error_index = 1;
+            response.varbinds = request.varbinds;
+        }
+        kind => anyhow::bail!("Unsupported PDU type {:#04x}", kind),
+    }
+
+    Ok(Some(
+        Message {
+            version: message.version,
+            community: message.community,
+            pdu: response,
+        }
+        .encode(),
+    ))
+}
+
+/// Binds the agent's socket and serves requests in the background,
+/// returning the bound address.
+pub async fn spawn(manager: &SerialManager, config: &SnmpConfig) -> Result<SocketAddr> {
+    let base = ber::parse_oid(&config.base_oid)?;
+    let socket = UdpSocket::bind(&config.address).await?;
+    let address = socket.local_addr()?;
+    info!("SNMP agent listening on {}", address);
+
+    let manager = manager.clone();
+    let community = config.community.clone();
+    tokio::spawn(async move {
+        let mut buf = vec![0u8; 65535];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    warn!("SNMP receive failed: {}", e);
+                    continue;
+                }
+            };
+            let view = build_view(&base, &push::snapshot(&manager).await);
+            match respond(&buf[..len], &community, &view) {
+                Ok(Some(reply)) => {
+                    if let Err(e) = socket.send_to(&reply, peer).await {
+                        warn!("Failed to send SNMP response to {}: {}", peer, e);
+                    }
+                }
+                Ok(None) => debug!(
+                    "Ignoring SNMP request from {} with wrong version or community",
+                    peer
+                ),
+                Err(e) => debug!("Malformed SNMP request from {}: {}", peer, e),
+            }
+        }
+    });
+    Ok(address)
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,238 @@
+use super::ber::*;
+use super::*;
+use crate::serial::ConnectionStats;
+
+const BASE: [u32; 9] = [1, 3, 6, 1, 4, 1, 8072, 9999, 9999];
+
+fn oid(suffix: &[u32]) -> Oid {
+    BASE.iter().chain(suffix).copied().collect()
+}
+
+fn snapshot(name: &str, connected: bool, bytes_received: u64) -> Snapshot {
+    Snapshot {
+        stats: ConnectionStats {
+            name: name.to_string(),
+            port: format!("/dev/{}", name),
+            bytes_received,
+            bytes_sent: 7,
+            is_connected: connected,
+            port_open: true,
+            uptime_seconds: 90,
+            line_errors: None,
+            keepalive: None,
+            rules: vec![],
+            reconnect: None,
+        },
+        metrics: vec![],
+        histograms: Default::default(),
+    }
+}
+
+fn request(kind: u8, community: &str, oids: &[Oid], status: i64, index: i64) -> Vec<u8> {
+    Message {
+        version: VERSION_2C,
+        community: community.as_bytes().to_vec(),
+        pdu: Pdu {
+            kind,
+            request_id: 42,
+            error_status: status,
+            error_index: index,
+            varbinds: oids.iter().map(|oid| (oid.clone(), Value::Null)).collect(),
+        },
+    }
+    .encode()
+}
+
+fn view() -> Vec<(Oid, Value)> {
+    build_view(
+        &BASE,
+        &[snapshot("gps", true, 1 << 40), snapshot("plc", false, 0)],
+    )
+}
+
+fn reply(data: &[u8]) -> Pdu {
+    let reply = respond(data, "public", &view()).unwrap().unwrap();
+    Message::decode(&reply).unwrap().pdu
+}
+
+#[test]
+fn test_ber_round_trip() {
+    let message = Message {
+        version: VERSION_2C,
+        community: b"public".to_vec(),
+        pdu: Pdu {
+            kind: RESPONSE,
+            request_id: -129,
+            error_status: 0,
+            error_index: 0,
+            varbinds: vec![
+                (vec![1, 3, 6, 1, 2, 1, 1, 3, 0], Value::TimeTicks(u32::MAX)),
+                (oid(&[300_000]), Value::Counter64(u64::MAX)),
+                (vec![2, 999], Value::OctetString(vec![b'x'; 300])),
+                (vec![1, 3], Value::Integer(i64::MIN)),
+                (vec![1, 3], Value::EndOfMibView),
+            ],
+        },
+    };
+    assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+}
+
+#[test]
+fn test_ber_known_encoding() {
+    // snmpget -v2c -c public host sysUpTime.0, as sent by Net-SNMP
+    let packet = [
+        0x30, 0x29, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c', 0xa0, 0x1c,
+        0x02, 0x04, 0x12, 0x34, 0x56, 0x78, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00, 0x30, 0x0e, 0x30,
+        0x0c, 0x06, 0x08, 0x2b, 0x06, 0x01, 0x02, 0x01, 0x01, 0x03, 0x00, 0x05, 0x00,
+    ];
+    let message = Message::decode(&packet).unwrap();
+    assert_eq!(message.pdu.kind, GET_REQUEST);
+    assert_eq!(message.pdu.request_id, 0x12345678);
+    assert_eq!(message.pdu.varbinds[0].0, vec![1, 3, 6, 1, 2, 1, 1, 3, 0]);
+    assert_eq!(message.encode(), packet);
+}
+
+#[test]
+fn test_ber_rejects_truncated() {
+    let packet = request(GET_REQUEST, "public", &[oid(&[1, 0])], 0, 0);
+    assert!(Message::decode(&packet[..packet.len() - 1]).is_err());
+    assert!(Message::decode(&[]).is_err());
+}
+
+#[test]
+fn test_parse_oid() {
+    assert_eq!(
+        parse_oid(".1.3.6.1.4.1.8072").unwrap(),
+        vec![1, 3, 6, 1, 4, 1, 8072]
+    );
+    assert!(parse_oid("1").is_err());
+    assert!(parse_oid("3.1").is_err());
+    assert!(parse_oid("1.3.x").is_err());
+}
+
+#[test]
+fn test_get_returns_connection_status() {
+    let pdu = reply(&request(
+        GET_REQUEST,
+        "public",
+        &[
+            oid(&[1, 0]),
+            oid(&[2, 1, 4, 2]),
+            oid(&[2, 1, 5, 1]),
+            oid(&[3]),
+        ],
+        0,
+        0,
+    ));
+    assert_eq!(pdu.kind, RESPONSE);
+    assert_eq!(pdu.request_id, 42);
+    let values: Vec<Value> = pdu.varbinds.into_iter().map(|(_, value)| value).collect();
+    assert_eq!(
+        values,
+        vec![
+            Value::Gauge32(2),
+            Value::Integer(STATUS_DOWN),
+            Value::Counter64(1 << 40),
+            Value::NoSuchObject,
+        ]
+    );
+}
+
+#[test]
+fn test_get_next_walks_table() {
+    let mut cursor = BASE.to_vec();
+    let mut walked = Vec::new();
+    loop {
+        let pdu = reply(&request(GET_NEXT_REQUEST, "public", &[cursor], 0, 0));
+        let (next, value) = pdu.varbinds[0].clone();
+        if value == Value::EndOfMibView {
+            break;
+        }
+        walked.push((next.clone(), value));
+        cursor = next;
+    }
+    assert_eq!(walked.len(), 1 + 7 * 2);
+    assert_eq!(walked[1].0, oid(&[2, 1, 1, 1]));
+    assert_eq!(
+        walked[3],
+        (oid(&[2, 1, 2, 1]), Value::OctetString(b"gps".to_vec()))
+    );
+    assert_eq!(walked[14], (oid(&[2, 1, 7, 2]), Value::TimeTicks(9000)));
+}
+
+#[test]
+fn test_get_bulk() {
+    let pdu = reply(&request(
+        GET_BULK_REQUEST,
+        "public",
+        &[BASE.to_vec(), oid(&[2, 1, 2])],
+        1,
+        3,
+    ));
+    let oids: Vec<Oid> = pdu.varbinds.iter().map(|(oid, _)| oid.clone()).collect();
+    assert_eq!(
+        oids,
+        vec![
+            oid(&[1, 0]),
+            oid(&[2, 1, 2, 1]),
+            oid(&[2, 1, 2, 2]),
+            oid(&[2, 1, 3, 1]),
+        ]
+    );
+}
+
+#[test]
+fn test_ignores_wrong_community_and_refuses_set() {
+    let get = request(GET_REQUEST, "private", &[oid(&[1, 0])], 0, 0);
+    assert_eq!(respond(&get, "public", &view()).unwrap(), None);
+
+    let pdu = reply(&request(SET_REQUEST, "public", &[oid(&[1, 0])], 0, 0));
+    assert_eq!(pdu.error_status, NOT_WRITABLE);
+    assert_eq!(pdu.error_index, 1);
+}
+
+#[tokio::test]
+async fn test_agent_answers_over_udp() {
+    let manager = SerialManager::new();
+    manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let address = spawn(
+        &manager,
+        &SnmpConfig {
+            address: "127.0.0.1:0".to_string(),
+            community: "lab".to_string(),
+            base_oid: "1.3.6.1.4.1.8072.9999.9999".to_string(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket
+        .send_to(
+            &request(
+                GET_REQUEST,
+                "lab",
+                &[oid(&[1, 0]), oid(&[2, 1, 2, 2])],
+                0,
+                0,
+            ),
+            address,
+        )
+        .await
+        .unwrap();
+    let mut buf = [0u8; 1500];
+    let len = tokio::time::timeout(std::time::Duration::from_secs(2), socket.recv(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    let pdu = Message::decode(&buf[..len]).unwrap().pdu;
+    assert_eq!(pdu.varbinds[0].1, Value::Gauge32(2));
+    assert_eq!(pdu.varbinds[1].1, Value::OctetString(b"nm-b".to_vec()));
+}
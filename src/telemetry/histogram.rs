@@ -0,0 +1,68 @@
+//! Cumulative histograms for the Prometheus exposition: counts since the
+//! connection was opened, in fixed buckets, as scrapers expect.
+
+use std::fmt::Write as _;
+
+/// Upper bounds of the request latency buckets, in seconds.
+pub const LATENCY_BOUNDS: &[f64] = &[
+    0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0,
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    bounds: &'static [f64],
+    /// Per bucket, the last one above every bound.
+    counts: Vec<u64>,
+    sum: f64,
+}
+
+impl Histogram {
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let slot = self
+            .bounds
+            .iter()
+            .position(|&le| value <= le)
+            .unwrap_or(self.bounds.len());
+        self.counts[slot] += 1;
+        self.sum += value;
+    }
+
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// The `_bucket`, `_sum` and `_count` lines of `family` for one label
+    /// set, given as already escaped `name="value"` pairs.
+    pub fn write_exposition(&self, out: &mut String, family: &str, labels: &str) {
+        let mut cumulative = 0;
+        for (le, count) in self.bounds.iter().zip(&self.counts) {
+            cumulative += count;
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{},le=\"{}\"}} {}",
+                family, labels, le, cumulative
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"+Inf\"}} {}",
+            family,
+            labels,
+            self.count()
+        );
+        let _ = writeln!(out, "{}_sum{{{}}} {}", family, labels, self.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", family, labels, self.count());
+    }
+}
@@ -0,0 +1,360 @@
+//! Numeric values extracted from received lines, kept as in-memory time
+//! series with threshold alerts.
+
+use crate::config::{AlertConfig, MetricConfig};
+use crate::notifications::{Notification, Notifier, Severity};
+use crate::serial::SerialData;
+use crate::trace::TraceContext;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Arc;
+use tokio::sync::{broadcast, RwLock};
+use tracing::{info, warn};
+
+pub mod histogram;
+pub mod push;
+
+/// Longest partial line buffered while waiting for a newline.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// Checks a connection's metric definitions.
+pub fn validate(metrics: &[MetricConfig]) -> Result<()> {
+    let mut names = HashSet::new();
+    for metric in metrics {
+        if !names.insert(&metric.name) {
+            anyhow::bail!("Duplicate metric name: {}", metric.name);
+        }
+        compile(metric)?;
+        if metric.history == 0 {
+            anyhow::bail!("Metric {}: history must be greater than 0", metric.name);
+        }
+
+        for alert in &metric.alerts {
+            if alert.above.is_none() && alert.below.is_none() {
+                anyhow::bail!(
+                    "Metric {}: alert {} needs an `above` or `below` threshold",
+                    metric.name,
+                    alert.name
+                );
+            }
+            if alert.samples == 0 {
+                anyhow::bail!(
+                    "Metric {}: alert {} samples must be greater than 0",
+                    metric.name,
+                    alert.name
+                );
+            }
+        }
+    }
+    Ok(())
+}
+
+fn compile(metric: &MetricConfig) -> Result<Regex> {
+    let regex = Regex::new(&metric.pattern)
+        .map_err(|e| anyhow::anyhow!("Metric {}: invalid pattern: {}", metric.name, e))?;
+    if regex.captures_len() < 2 {
+        anyhow::bail!(
+            "Metric {}: pattern must contain a capture group",
+            metric.name
+        );
+    }
+    Ok(regex)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Sample {
+    pub timestamp: DateTime<Utc>,
+    pub value: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AlertState {
+    Firing,
+    Resolved,
+}
+
+/// Sent to an alert's webhook when it changes state.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AlertEvent {
+    pub connection: String,
+    pub metric: String,
+    pub alert: String,
+    pub state: AlertState,
+    pub value: f64,
+    pub timestamp: DateTime<Utc>,
+    /// The connection's trace, for finding its log lines.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+}
+
+impl AlertEvent {
+    pub fn to_notification(&self) -> Notification {
+        let (severity, label) = match self.state {
+            AlertState::Firing => (Severity::Warning, "firing"),
+            AlertState::Resolved => (Severity::Info, "resolved"),
+        };
+        Notification {
+            severity,
+            title: format!("Alert {} {} on {}", self.alert, label, self.connection),
+            message: format!("{} = {}", self.metric, self.value),
+            connection: Some(self.connection.clone()),
+            timestamp: self.timestamp,
+            trace: self.trace.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AlertStatus {
+    pub name: String,
+    pub firing: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub above: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub below: Option<f64>,
+    pub samples: usize,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MetricSummary {
+    pub name: String,
+    pub pattern: String,
+    pub samples: usize,
+    pub latest: Option<Sample>,
+    pub alerts: Vec<AlertStatus>,
+}
+
+struct Alert {
+    config: AlertConfig,
+    firing: bool,
+    /// Consecutive samples disagreeing with the current state.
+    streak: usize,
+}
+
+impl Alert {
+    fn breached(&self, value: f64) -> bool {
+        self.config.above.is_some_and(|limit| value > limit)
+            || self.config.below.is_some_and(|limit| value < limit)
+    }
+
+    /// Feeds a sample, returning the new state if it changed.
+    fn observe(&mut self, value: f64) -> Option<AlertState> {
+        if self.breached(value) == self.firing {
+            self.streak = 0;
+            return None;
+        }
+
+        self.streak += 1;
+        if self.streak < self.config.samples {
+            return None;
+        }
+
+        self.streak = 0;
+        self.firing = !self.firing;
+        Some(if self.firing {
+            AlertState::Firing
+        } else {
+            AlertState::Resolved
+        })
+    }
+}
+
+struct Metric {
+    config: MetricConfig,
+    regex: Regex,
+    samples: VecDeque<Sample>,
+    alerts: Vec<Alert>,
+}
+
+/// Extracts values from a connection's received data and tracks them.
+pub struct Telemetry {
+    connection: String,
+    metrics: Vec<Metric>,
+    line: Vec<u8>,
+}
+
+impl Telemetry {
+    pub fn new(connection: &str, metrics: &[MetricConfig]) -> Result<Self> {
+        validate(metrics)?;
+
+        let metrics = metrics
+            .iter()
+            .map(|config| {
+                Ok(Metric {
+                    regex: compile(config)?,
+                    samples: VecDeque::with_capacity(config.history.min(1024)),
+                    alerts: config
+                        .alerts
+                        .iter()
+                        .map(|alert| Alert {
+                            config: alert.clone(),
+                            firing: false,
+                            streak: 0,
+                        })
+                        .collect(),
+                    config: config.clone(),
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            connection: connection.to_string(),
+            metrics,
+            line: Vec::new(),
+        })
+    }
+
+    /// Feeds received bytes, returning any alerts that changed state.
+    pub fn feed(&mut self, data: &[u8], now: DateTime<Utc>) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for &byte in data {
+            if byte == b'\n' {
+                let line = std::mem::take(&mut self.line);
+                let text = String::from_utf8_lossy(&line);
+                events.extend(self.record_line(text.trim_end_matches('\r'), now));
+            } else if self.line.len() < MAX_LINE_LENGTH {
+                self.line.push(byte);
+            }
+        }
+
+        events
+    }
+
+    fn record_line(&mut self, line: &str, now: DateTime<Utc>) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for metric in &mut self.metrics {
+            for captures in metric.regex.captures_iter(line) {
+                let Some(value) = captures
+                    .get(1)
+                    .and_then(|m| m.as_str().trim().parse::<f64>().ok())
+                else {
+                    continue;
+                };
+
+                if metric.samples.len() == metric.config.history {
+                    metric.samples.pop_front();
+                }
+                metric.samples.push_back(Sample {
+                    timestamp: now,
+                    value,
+                });
+
+                for alert in &mut metric.alerts {
+                    if let Some(state) = alert.observe(value) {
+                        events.push(AlertEvent {
+                            connection: self.connection.clone(),
+                            metric: metric.config.name.clone(),
+                            alert: alert.config.name.clone(),
+                            state,
+                            value,
+                            timestamp: now,
+                            trace: None,
+                        });
+                    }
+                }
+            }
+        }
+
+        events
+    }
+
+    pub fn summaries(&self) -> Vec<MetricSummary> {
+        self.metrics
+            .iter()
+            .map(|metric| MetricSummary {
+                name: metric.config.name.clone(),
+                pattern: metric.config.pattern.clone(),
+                samples: metric.samples.len(),
+                latest: metric.samples.back().copied(),
+                alerts: metric
+                    .alerts
+                    .iter()
+                    .map(|alert| AlertStatus {
+                        name: alert.config.name.clone(),
+                        firing: alert.firing,
+                        above: alert.config.above,
+                        below: alert.config.below,
+                        samples: alert.config.samples,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    /// The most recent `limit` samples of a metric, oldest first.
+    pub fn samples(&self, metric: &str, limit: Option<usize>) -> Option<Vec<Sample>> {
+        let metric = self.metrics.iter().find(|m| m.config.name == metric)?;
+        let skip = limit.map_or(0, |limit| metric.samples.len().saturating_sub(limit));
+        Some(metric.samples.iter().skip(skip).copied().collect())
+    }
+
+    fn alert_config(&self, event: &AlertEvent) -> Option<&AlertConfig> {
+        self.metrics
+            .iter()
+            .find(|m| m.config.name == event.metric)?
+            .alerts
+            .iter()
+            .find(|a| a.config.name == event.alert)
+            .map(|a| &a.config)
+    }
+}
+
+/// Feeds a connection's received data into `telemetry` until the
+/// connection closes, tagging alerts with the connection's `trace`.
+pub(crate) async fn run(
+    telemetry: Arc<RwLock<Telemetry>>,
+    mut rx: broadcast::Receiver<SerialData>,
+    notifier: Notifier,
+    trace: TraceContext,
+) {
+    loop {
+        let data = match rx.recv().await {
+            Ok(data) => data,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                warn!("Metric extraction skipped {} chunks", skipped);
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => break,
+        };
+
+        let mut telemetry = telemetry.write().await;
+        for mut event in telemetry.feed(&data, Utc::now()) {
+            event.trace = Some(trace.clone());
+            info!(
+                "Alert {} on {}/{} {:?} at {}",
+                event.alert, event.connection, event.metric, event.state, event.value
+            );
+            if let Some(id) =
+                notifier
+                    .silences()
+                    .matching(&event.connection, &event.alert, event.timestamp)
+            {
+                info!("Alert {} is muted by silence {}", event.alert, id);
+                continue;
+            }
+
+            let Some(alert) = telemetry.alert_config(&event) else {
+                continue;
+            };
+            notifier.notify(&alert.notify, event.to_notification());
+
+            if let Some(ref url) = alert.webhook {
+                notifier.notify_url(
+                    url,
+                    alert.webhook_secret.clone(),
+                    &event,
+                    &event.to_notification(),
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,314 @@
+//! Periodic push of connection counters and extracted metrics to InfluxDB,
+//! a Telegraf socket or a Prometheus Pushgateway, for sites where nothing
+//! can reach in to scrape webmux.
+
+use super::MetricSummary;
+use crate::config::{MetricsPushConfig, MetricsPushTarget, TelegrafTransport};
+use crate::serial::{ConnectionHistograms, ConnectionStats, Direction, SerialManager};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use std::fmt::Write as _;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tracing::{info, warn};
+
+/// One connection's state at push time.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub stats: ConnectionStats,
+    pub metrics: Vec<MetricSummary>,
+    /// Only in the Prometheus exposition, as line protocol has no histograms.
+    pub histograms: ConnectionHistograms,
+}
+
+/// Current state of every connection, ordered by name.
+pub async fn snapshot(manager: &SerialManager) -> Vec<Snapshot> {
+    let mut snapshots = Vec::new();
+    for name in manager.list_connections().await {
+        let Some(connection) = manager.get_connection(&name).await else {
+            continue;
+        };
+        snapshots.push(Snapshot {
+            stats: connection.get_stats().await,
+            metrics: connection.metric_summaries().await,
+            histograms: connection.histograms().await,
+        });
+    }
+    snapshots.sort_by(|a, b| a.stats.name.cmp(&b.stats.name));
+    snapshots
+}
+
+fn escape_tag(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            ',' | '=' | ' ' | '\\' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+fn nanos(timestamp: DateTime<Utc>) -> i64 {
+    timestamp.timestamp_nanos_opt().unwrap_or_default()
+}
+
+/// InfluxDB line protocol: a `webmux_connection` point per connection and
+/// a `webmux_metric` point per metric with a sample, stamped when it was
+/// extracted.
+pub fn line_protocol(snapshots: &[Snapshot], now: DateTime<Utc>) -> String {
+    let mut out = String::new();
+    for snapshot in snapshots {
+        let stats = &snapshot.stats;
+        let connection = escape_tag(&stats.name);
+        let _ = writeln!(
+            out,
+            "webmux_connection,connection={},port={} bytes_received={}i,bytes_sent={}i,connected={},uptime_seconds={}i {}",
+            connection,
+            escape_tag(&stats.port),
+            stats.bytes_received,
+            stats.bytes_sent,
+            stats.is_connected,
+            stats.uptime_seconds,
+            nanos(now)
+        );
+        for metric in &snapshot.metrics {
+            // Line protocol has no representation for NaN or infinity
+            let Some(sample) = metric.latest.filter(|sample| sample.value.is_finite()) else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "webmux_metric,connection={},metric={} value={} {}",
+                connection,
+                escape_tag(&metric.name),
+                sample.value,
+                nanos(sample.timestamp)
+            );
+        }
+    }
+    out
+}
+
+/// Metric name, Prometheus type and how to read it from the stats.
+type Family = (&'static str, &'static str, fn(&ConnectionStats) -> u64);
+
+/// Prometheus text exposition format, as accepted by the Pushgateway and
+/// served at `/metrics`.
+pub fn exposition(snapshots: &[Snapshot]) -> String {
+    let mut out = String::new();
+    let families: [Family; 4] = [
+        ("webmux_bytes_received_total", "counter", |stats| {
+            stats.bytes_received
+        }),
+        ("webmux_bytes_sent_total", "counter", |stats| {
+            stats.bytes_sent
+        }),
+        ("webmux_connected", "gauge", |stats| {
+            stats.is_connected as u64
+        }),
+        ("webmux_uptime_seconds", "gauge", |stats| {
+            stats.uptime_seconds
+        }),
+    ];
+    for (family, kind, value) in families {
+        let _ = writeln!(out, "# TYPE {} {}", family, kind);
+        for snapshot in snapshots {
+            let _ = writeln!(
+                out,
+                "{}{{connection=\"{}\",port=\"{}\"}} {}",
+                family,
+                escape_label(&snapshot.stats.name),
+                escape_label(&snapshot.stats.port),
+                value(&snapshot.stats)
+            );
+        }
+    }
+
+    let _ = writeln!(out, "# TYPE webmux_metric gauge");
+    for snapshot in snapshots {
+        for metric in &snapshot.metrics {
+            let Some(sample) = metric.latest.filter(|sample| sample.value.is_finite()) else {
+                continue;
+            };
+            let _ = writeln!(
+                out,
+                "webmux_metric{{connection=\"{}\",metric=\"{}\"}} {}",
+                escape_label(&snapshot.stats.name),
+                escape_label(&metric.name),
+                sample.value
+            );
+        }
+    }
+
+    let _ = writeln!(out, "# TYPE webmux_request_duration_seconds histogram");
+    for snapshot in snapshots {
+        for (route, histogram) in &snapshot.histograms.request_seconds {
+            histogram.write_exposition(
+                &mut out,
+                "webmux_request_duration_seconds",
+                &format!(
+                    "connection=\"{}\",route=\"{}\"",
+                    escape_label(&snapshot.stats.name),
+                    route
+                ),
+            );
+        }
+    }
+
+    let _ = writeln!(out, "# TYPE webmux_frame_bytes histogram");
+    for snapshot in snapshots {
+        for (direction, histogram) in &snapshot.histograms.frame_bytes {
+            let direction = match direction {
+                Direction::Rx => "rx",
+                Direction::Tx => "tx",
+            };
+            histogram.write_exposition(
+                &mut out,
+                "webmux_frame_bytes",
+                &format!(
+                    "connection=\"{}\",direction=\"{}\"",
+                    escape_label(&snapshot.stats.name),
+                    direction
+                ),
+            );
+        }
+    }
+    out
+}
+
+enum TelegrafSink {
+    Tcp(TcpStream),
+    Udp(UdpSocket),
+}
+
+async fn connect_telegraf(address: &str, transport: TelegrafTransport) -> Result<TelegrafSink> {
+    match transport {
+        TelegrafTransport::Tcp => Ok(TelegrafSink::Tcp(TcpStream::connect(address).await?)),
+        TelegrafTransport::Udp => {
+            let target = tokio::net::lookup_host(address)
+                .await?
+                .next()
+                .ok_or_else(|| anyhow::anyhow!("Cannot resolve {}", address))?;
+            let bind = if target.is_ipv4() {
+                "0.0.0.0:0"
+            } else {
+                "[::]:0"
+            };
+            let socket = UdpSocket::bind(bind).await?;
+            socket.connect(target).await?;
+            Ok(TelegrafSink::Udp(socket))
+        }
+    }
+}
+
+/// Delivers snapshots to the configured target.
+pub struct Pusher {
+    config: MetricsPushConfig,
+    client: reqwest::Client,
+    /// Kept open between pushes; reconnected on the next push after a failure.
+    telegraf: Option<TelegrafSink>,
+}
+
+impl Pusher {
+    pub fn new(config: &MetricsPushConfig) -> Result<Self> {
+        // A push must not still be running when the next one is due
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(config.interval_secs))
+            .build()?;
+        Ok(Self {
+            config: config.clone(),
+            client,
+            telegraf: None,
+        })
+    }
+
+    pub async fn push(&mut self, snapshots: &[Snapshot]) -> Result<()> {
+        match self.config.target {
+            MetricsPushTarget::Influxdb { ref url, ref token } => {
+                let mut request = self
+                    .client
+                    .post(url)
+                    .header("Content-Type", "text/plain; charset=utf-8")
+                    .body(line_protocol(snapshots, Utc::now()));
+                if let Some(token) = token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+                request.send().await?.error_for_status()?;
+            }
+            MetricsPushTarget::Pushgateway { ref url, ref job } => {
+                self.client
+                    .put(format!("{}/metrics/job/{}", url.trim_end_matches('/'), job))
+                    .header("Content-Type", "text/plain; version=0.0.4")
+                    .body(exposition(snapshots))
+                    .send()
+                    .await?
+                    .error_for_status()?;
+            }
+            MetricsPushTarget::Telegraf {
+                ref address,
+                transport,
+            } => {
+                if self.telegraf.is_none() {
+                    self.telegraf = Some(connect_telegraf(address, transport).await?);
+                }
+                let payload = line_protocol(snapshots, Utc::now());
+                let result = match self.telegraf {
+                    Some(TelegrafSink::Tcp(ref mut stream)) => {
+                        stream.write_all(payload.as_bytes()).await
+                    }
+                    // One point per datagram keeps each well under the MTU
+                    Some(TelegrafSink::Udp(ref socket)) => {
+                        let mut result = Ok(());
+                        for line in payload.lines() {
+                            if let Err(e) = socket.send(format!("{}\n", line).as_bytes()).await {
+                                result = Err(e);
+                                break;
+                            }
+                        }
+                        result
+                    }
+                    None => Ok(()),
+                };
+                if let Err(e) = result {
+                    self.telegraf = None;
+                    return Err(e.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Pushes a snapshot of every connection each `interval_secs`. Failed
+/// pushes are logged and retried at the next interval.
+pub fn spawn(manager: &SerialManager, config: &MetricsPushConfig) -> Result<()> {
+    let mut pusher = Pusher::new(config)?;
+    let manager = manager.clone();
+    let interval = Duration::from_secs(config.interval_secs);
+    info!("Pushing metrics every {}s", config.interval_secs);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            let snapshots = snapshot(&manager).await;
+            if let Err(e) = pusher.push(&snapshots).await {
+                warn!("Metrics push failed: {}", e);
+            }
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use super::*;
+
+fn metric(pattern: &str, alerts: Vec<AlertConfig>) -> MetricConfig {
+    MetricConfig {
+        name: "temp".to_string(),
+        pattern: pattern.to_string(),
+        history: 3,
+        alerts,
+    }
+}
+
+fn alert(above: Option<f64>, below: Option<f64>, samples: usize) -> AlertConfig {
+    AlertConfig {
+        name: "overheat".to_string(),
+        above,
+        below,
+        samples,
+        webhook: None,
+        webhook_secret: None,
+        notify: vec![],
+    }
+}
+
+#[test]
+fn test_validate_rejects_bad_metrics() {
+    assert!(validate(&[metric(r"TEMP:(\d+)", vec![])]).is_ok());
+    assert!(validate(&[metric(r"TEMP:\d+", vec![])]).is_err());
+    assert!(validate(&[metric(r"TEMP:(\d+", vec![])]).is_err());
+    assert!(validate(&[metric(r"TEMP:(\d+)", vec![alert(None, None, 1)])]).is_err());
+    assert!(validate(&[metric(r"TEMP:(\d+)", vec![alert(Some(1.0), None, 0)])]).is_err());
+
+    let duplicate = metric(r"TEMP:(\d+)", vec![]);
+    assert!(validate(&[duplicate.clone(), duplicate]).is_err());
+}
+
+#[test]
+fn test_extracts_values_across_chunks() {
+    let mut telemetry = Telemetry::new("dev", &[metric(r"TEMP:(\d+\.\d+)", vec![])]).unwrap();
+    let now = Utc::now();
+
+    telemetry.feed(b"boot ok\r\nTEMP:2", now);
+    telemetry.feed(b"1.5\r\nTEMP:bad\nTEMP:22.0 TEMP:22.5\n", now);
+
+    let values: Vec<f64> = telemetry
+        .samples("temp", None)
+        .unwrap()
+        .iter()
+        .map(|s| s.value)
+        .collect();
+    assert_eq!(values, vec![21.5, 22.0, 22.5]);
+}
+
+#[test]
+fn test_history_is_bounded() {
+    let mut telemetry = Telemetry::new("dev", &[metric(r"V=(-?\d+)", vec![])]).unwrap();
+    telemetry.feed(b"V=1\nV=2\nV=3\nV=-4\n", Utc::now());
+
+    let values: Vec<f64> = telemetry
+        .samples("temp", None)
+        .unwrap()
+        .iter()
+        .map(|s| s.value)
+        .collect();
+    assert_eq!(values, vec![2.0, 3.0, -4.0]);
+
+    assert_eq!(telemetry.samples("temp", Some(1)).unwrap().len(), 1);
+    assert!(telemetry.samples("missing", None).is_none());
+
+    let summary = &telemetry.summaries()[0];
+    assert_eq!(summary.samples, 3);
+    assert_eq!(summary.latest.unwrap().value, -4.0);
+}
+
+#[test]
+fn test_alert_fires_after_consecutive_samples_and_resolves() {
+    let mut telemetry = Telemetry::new(
+        "dev",
+        &[metric(r"T=(\d+)", vec![alert(Some(80.0), None, 2)])],
+    )
+    .unwrap();
+    let now = Utc::now();
+
+    // A single spike is not enough
+    assert!(telemetry.feed(b"T=90\nT=70\nT=90\n", now).is_empty());
+
+    let events = telemetry.feed(b"T=95\n", now);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].state, AlertState::Firing);
+    assert_eq!(events[0].connection, "dev");
+    assert_eq!(events[0].alert, "overheat");
+    assert_eq!(events[0].value, 95.0);
+    assert!(telemetry.summaries()[0].alerts[0].firing);
+
+    // Stays firing without re-notifying, then resolves after two good samples
+    assert!(telemetry.feed(b"T=99\nT=60\n", now).is_empty());
+    let events = telemetry.feed(b"T=61\n", now);
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].state, AlertState::Resolved);
+    assert!(!telemetry.summaries()[0].alerts[0].firing);
+}
+
+#[test]
+fn test_alert_below_threshold() {
+    let mut telemetry = Telemetry::new(
+        "dev",
+        &[metric(r"BAT:(\d+\.\d+)V", vec![alert(None, Some(3.3), 1)])],
+    )
+    .unwrap();
+
+    let events = telemetry.feed(b"BAT:3.1V\n", Utc::now());
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].state, AlertState::Firing);
+}
+
+fn push_snapshots() -> Vec<push::Snapshot> {
+    let mut send_latency = histogram::Histogram::new(histogram::LATENCY_BOUNDS);
+    send_latency.observe(0.003);
+    send_latency.observe(0.2);
+    let timestamp = DateTime::parse_from_rfc3339("2025-11-30T15:30:45Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    vec![push::Snapshot {
+        stats: crate::serial::ConnectionStats {
+            name: "plc 1".to_string(),
+            port: "/dev/ttyUSB0".to_string(),
+            bytes_received: 1024,
+            bytes_sent: 16,
+            is_connected: true,
+            port_open: true,
+            uptime_seconds: 60,
+            line_errors: None,
+            keepalive: None,
+            rules: vec![],
+            reconnect: None,
+        },
+        metrics: vec![
+            MetricSummary {
+                name: "temp".to_string(),
+                pattern: r"TEMP:(\d+\.\d+)".to_string(),
+                samples: 1,
+                latest: Some(Sample {
+                    timestamp,
+                    value: 21.5,
+                }),
+                alerts: vec![],
+            },
+            MetricSummary {
+                name: "idle".to_string(),
+                pattern: r"IDLE:(\d+)".to_string(),
+                samples: 0,
+                latest: None,
+                alerts: vec![],
+            },
+        ],
+        histograms: crate::serial::ConnectionHistograms {
+            request_seconds: vec![("send", send_latency)],
+            frame_bytes: vec![],
+        },
+    }]
+}
+
+#[test]
+fn test_push_line_protocol() {
+    let now = DateTime::from_timestamp(1_764_516_646, 0).unwrap();
+    let lines = push::line_protocol(&push_snapshots(), now);
+    assert_eq!(
+        lines,
+        "webmux_connection,connection=plc\\ 1,port=/dev/ttyUSB0 \
+         bytes_received=1024i,bytes_sent=16i,connected=true,uptime_seconds=60i \
+         1764516646000000000\n\
+         webmux_metric,connection=plc\\ 1,metric=temp value=21.5 1764516645000000000\n"
+    );
+}
+
+#[test]
+fn test_push_exposition() {
+    let text = push::exposition(&push_snapshots());
+    assert!(text.contains("# TYPE webmux_bytes_received_total counter\n"));
+    assert!(text.contains(
+        "webmux_bytes_received_total{connection=\"plc 1\",port=\"/dev/ttyUSB0\"} 1024\n"
+    ));
+    assert!(text.contains("webmux_connected{connection=\"plc 1\",port=\"/dev/ttyUSB0\"} 1\n"));
+    assert!(text.contains("webmux_metric{connection=\"plc 1\",metric=\"temp\"} 21.5\n"));
+    assert!(!text.contains("idle"));
+
+    assert!(text.contains("# TYPE webmux_request_duration_seconds histogram\n"));
+    let bucket = |le: &str| {
+        format!(
+            "webmux_request_duration_seconds_bucket{{connection=\"plc 1\",route=\"send\",le=\"{}\"}}",
+            le
+        )
+    };
+    // Buckets count every observation up to their bound
+    assert!(text.contains(&format!("{} 0\n", bucket("0.0025"))));
+    assert!(text.contains(&format!("{} 1\n", bucket("0.005"))));
+    assert!(text.contains(&format!("{} 2\n", bucket("0.25"))));
+    assert!(text.contains(&format!("{} 2\n", bucket("+Inf"))));
+    assert!(text.contains(
+        "webmux_request_duration_seconds_count{connection=\"plc 1\",route=\"send\"} 2\n"
+    ));
+    assert!(text.contains(
+        "webmux_request_duration_seconds_sum{connection=\"plc 1\",route=\"send\"} 0.203\n"
+    ));
+}
+
+#[tokio::test]
+async fn test_push_to_telegraf_socket() {
+    use crate::config::{MetricsPushConfig, MetricsPushTarget, TelegrafTransport};
+    use tokio::io::AsyncBufReadExt;
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut pusher = push::Pusher::new(&MetricsPushConfig {
+        target: MetricsPushTarget::Telegraf {
+            address: listener.local_addr().unwrap().to_string(),
+            transport: TelegrafTransport::Tcp,
+        },
+        interval_secs: 10,
+    })
+    .unwrap();
+
+    pusher.push(&push_snapshots()).await.unwrap();
+    let (stream, _) = listener.accept().await.unwrap();
+    let mut lines = tokio::io::BufReader::new(stream).lines();
+    let first = lines.next_line().await.unwrap().unwrap();
+    assert!(first.starts_with("webmux_connection,connection=plc\\ 1,"));
+    let second = lines.next_line().await.unwrap().unwrap();
+    assert!(second.starts_with("webmux_metric,connection=plc\\ 1,metric=temp value=21.5 "));
+}
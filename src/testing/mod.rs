@@ -0,0 +1,228 @@
+//! In-process test harness for clients of the webmux API
+//! (`features = ["testing"]`).
+//!
+//! [`TestServer`] serves the full API on a loopback port. Connections are
+//! in-memory null-modem pairs: the client talks to one end and the test
+//! plays the device on the other, either directly through a [`Loopback`]
+//! or with a scripted [`Responder`]. No serial hardware or running server
+//! is needed.
+//!
+//! ```rust,ignore
+//! let server = TestServer::start().await?;
+//! server
+//!     .device("plc", Responder::new().on("STATUS", "RUNNING\r\n"))
+//!     .await?;
+//! // Point the client under test at server.ws_url("plc")
+//! ```
+
+use crate::config::ServerConfig;
+use crate::serial::{SerialData, SerialManager, VirtualPairRequest};
+use crate::web;
+use anyhow::Result;
+use regex::Regex;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+
+/// Suffix of the connection a test drives as the device.
+pub const DEVICE_SUFFIX: &str = "-device";
+
+/// The webmux API served on `127.0.0.1` at a free port; stopped on drop.
+pub struct TestServer {
+    manager: SerialManager,
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start() -> Result<Self> {
+        Self::with_config(&ServerConfig::default()).await
+    }
+
+    /// Serves with the limits and WebSocket settings in `config`; its host
+    /// and port are ignored.
+    pub async fn with_config(config: &ServerConfig) -> Result<Self> {
+        let manager = SerialManager::new();
+        let app = web::create_router_with_config(manager.clone(), config);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            let app = app.into_make_service_with_connect_info::<SocketAddr>();
+            let _ = axum::serve(listener, app).await;
+        });
+        Ok(Self {
+            manager,
+            addr,
+            task,
+        })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// `http://` URL of `path`, e.g. `/api/connections`.
+    pub fn url(&self, path: &str) -> String {
+        format!("http://{}{}", self.addr, path)
+    }
+
+    /// WebSocket stream URL of `connection`.
+    pub fn ws_url(&self, connection: &str) -> String {
+        format!("ws://{}/api/connections/{}/ws", self.addr, connection)
+    }
+
+    pub fn manager(&self) -> &SerialManager {
+        &self.manager
+    }
+
+    /// Adds connection `name` wired to `<name>-device`, which the returned
+    /// [`Loopback`] drives.
+    pub async fn loopback(&self, name: &str) -> Result<Loopback> {
+        let device = format!("{}{}", name, DEVICE_SUFFIX);
+        self.manager
+            .add_virtual_pair(&VirtualPairRequest {
+                name: name.to_string(),
+                a: Some(name.to_string()),
+                b: Some(device.clone()),
+            })
+            .await?;
+        let rx = self.manager.subscribe(&device).await?;
+        Ok(Loopback {
+            manager: self.manager.clone(),
+            device,
+            rx,
+            received: Vec::new(),
+        })
+    }
+
+    /// Adds connection `name` with `responder` playing the device.
+    pub async fn device(&self, name: &str, responder: Responder) -> Result<()> {
+        self.loopback(name).await?.respond(responder);
+        Ok(())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// The device end of a test connection.
+pub struct Loopback {
+    manager: SerialManager,
+    device: String,
+    rx: broadcast::Receiver<SerialData>,
+    /// Bytes from the client not yet returned by a read.
+    received: Vec<u8>,
+}
+
+impl Loopback {
+    /// Sends `data` to clients of the connection, as the device would.
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        self.manager.send_data(&self.device, data).await
+    }
+
+    /// Waits up to `timeout` for clients to have written `needle`, and
+    /// returns everything they wrote up to and including it.
+    pub async fn read_until(&mut self, needle: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+        tokio::time::timeout(timeout, self.next_until(needle))
+            .await
+            .map_err(|_| {
+                anyhow::anyhow!(
+                    "Timed out waiting for {:?}",
+                    String::from_utf8_lossy(needle)
+                )
+            })?
+    }
+
+    async fn next_until(&mut self, needle: &[u8]) -> Result<Vec<u8>> {
+        loop {
+            if let Some(start) = find(&self.received, needle) {
+                return Ok(self.received.drain(..start + needle.len()).collect());
+            }
+            match self.rx.recv().await {
+                Ok(data) => self.received.extend_from_slice(&data),
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => {
+                    anyhow::bail!("Connection {} was removed", self.device)
+                }
+            }
+        }
+    }
+
+    /// Answers each line clients write with `responder` until the
+    /// connection is removed.
+    pub fn respond(mut self, responder: Responder) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let line = match self.next_until(b"\n").await {
+                    Ok(line) => line,
+                    Err(_) => return,
+                };
+                let line = String::from_utf8_lossy(&line);
+                if let Some(reply) = responder.reply(line.trim_end_matches(['\r', '\n'])) {
+                    let _ = self.write(reply.as_bytes()).await;
+                }
+            }
+        })
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() {
+        return Some(0);
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Scripted device replies, tried in the order they were added.
+#[derive(Debug, Clone, Default)]
+pub struct Responder {
+    rules: Vec<(Regex, String)>,
+    fallback: Option<String>,
+}
+
+impl Responder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replies `reply` to the line `line` exactly.
+    pub fn on(self, line: &str, reply: &str) -> Self {
+        let pattern = Regex::new(&format!("^{}$", regex::escape(line)))
+            .expect("escaped text is a valid pattern");
+        self.on_pattern(pattern, reply)
+    }
+
+    /// Replies to lines matching `pattern`; `$1` and `$name` in `reply`
+    /// are replaced with the captures.
+    pub fn on_pattern(mut self, pattern: Regex, reply: &str) -> Self {
+        self.rules.push((pattern, reply.to_string()));
+        self
+    }
+
+    /// Reply to lines no rule matches; those are ignored otherwise.
+    pub fn otherwise(mut self, reply: &str) -> Self {
+        self.fallback = Some(reply.to_string());
+        self
+    }
+
+    /// The reply to `line`, without its line ending.
+    pub fn reply(&self, line: &str) -> Option<String> {
+        for (pattern, reply) in &self.rules {
+            if let Some(captures) = pattern.captures(line) {
+                let mut expanded = String::new();
+                captures.expand(reply, &mut expanded);
+                return Some(expanded);
+            }
+        }
+        self.fallback.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests;
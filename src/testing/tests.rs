@@ -0,0 +1,85 @@
+use super::*;
+use futures::{SinkExt, StreamExt};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+#[test]
+fn test_responder_rules() {
+    let responder = Responder::new()
+        .on("AT", "OK\r\n")
+        .on("a.b", "literal\r\n")
+        .on_pattern(Regex::new(r"^GET (\w+)$").unwrap(), "$1=42\r\n");
+
+    assert_eq!(responder.reply("AT").as_deref(), Some("OK\r\n"));
+    // Exact lines are not patterns
+    assert_eq!(responder.reply("a.b").as_deref(), Some("literal\r\n"));
+    assert_eq!(responder.reply("axb"), None);
+    assert_eq!(responder.reply("GET temp").as_deref(), Some("temp=42\r\n"));
+    assert_eq!(responder.reply("ATI"), None);
+
+    let responder = responder.otherwise("ERROR\r\n");
+    assert_eq!(responder.reply("ATI").as_deref(), Some("ERROR\r\n"));
+}
+
+#[tokio::test]
+async fn test_loopback_between_client_and_device() {
+    let server = TestServer::start().await.unwrap();
+    let mut device = server.loopback("uart").await.unwrap();
+
+    let response = reqwest::get(server.url("/api/connections")).await.unwrap();
+    assert_eq!(response.status(), 200);
+    let list: serde_json::Value = response.json().await.unwrap();
+    assert!(list.to_string().contains("uart-device"));
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(server.ws_url("uart"))
+        .await
+        .unwrap();
+    ws.send(WsMessage::Text("hello\r\n".into())).await.unwrap();
+    assert_eq!(
+        device.read_until(b"\n", TIMEOUT).await.unwrap(),
+        b"hello\r\n"
+    );
+    assert!(device
+        .read_until(b"\n", Duration::from_millis(50))
+        .await
+        .is_err());
+
+    device.write(b"world\r\n").await.unwrap();
+    let reply = tokio::time::timeout(TIMEOUT, ws.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert!(reply.to_text().unwrap().contains("world"));
+}
+
+#[tokio::test]
+async fn test_scripted_device() {
+    let server = TestServer::start().await.unwrap();
+    server
+        .device(
+            "plc",
+            Responder::new()
+                .on("STATUS", "RUNNING\r\n")
+                .otherwise("?\r\n"),
+        )
+        .await
+        .unwrap();
+
+    let mut rx = server.manager().subscribe("plc").await.unwrap();
+    server
+        .manager()
+        .send_data("plc", b"STATUS\r\nRESET\r\n")
+        .await
+        .unwrap();
+    let mut received = Vec::new();
+    while !received.ends_with(b"?\r\n") {
+        let chunk = tokio::time::timeout(TIMEOUT, rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.extend_from_slice(&chunk);
+    }
+    assert_eq!(received, b"RUNNING\r\n?\r\n");
+}
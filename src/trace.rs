@@ -0,0 +1,51 @@
+//! Ids for correlating logs, notifications and audit records. Each time a
+//! connection is opened starts a trace, and each session on it is a span
+//! in that trace. The ids follow W3C Trace Context, so they can be handed
+//! on as a `traceparent` header and picked up by tracing backends.
+
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::OsRng;
+use serde::Serialize;
+
+/// Header carrying a [`TraceContext`] on outgoing requests.
+pub const TRACEPARENT_HEADER: &str = "traceparent";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TraceContext {
+    /// 32 hex digits, shared by everything in the trace.
+    pub trace_id: String,
+    /// 16 hex digits, naming this span within it.
+    pub span_id: String,
+}
+
+impl TraceContext {
+    /// A new trace.
+    pub fn root() -> Self {
+        Self {
+            trace_id: random_hex::<16>(),
+            span_id: random_hex::<8>(),
+        }
+    }
+
+    /// A new span in the same trace.
+    pub fn child(&self) -> Self {
+        Self {
+            trace_id: self.trace_id.clone(),
+            span_id: random_hex::<8>(),
+        }
+    }
+
+    /// The `traceparent` header value, marked as sampled.
+    pub fn traceparent(&self) -> String {
+        format!("00-{}-{}-01", self.trace_id, self.span_id)
+    }
+}
+
+fn random_hex<const N: usize>() -> String {
+    let mut bytes = [0u8; N];
+    // All zeros is reserved as invalid
+    while bytes.iter().all(|&b| b == 0) {
+        OsRng.fill_bytes(&mut bytes);
+    }
+    hex::encode(bytes)
+}
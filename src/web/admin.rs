@@ -0,0 +1,79 @@
+//! Listener for the administrative endpoints (`server.admin`), so they can
+//! be kept on localhost or a Unix socket while the main listener is
+//! exposed.
+
+use crate::config::AdminConfig;
+use anyhow::Result;
+use axum::Router;
+use tracing::{error, info};
+
+/// Serves `app` where `config` says, returning a description of where.
+pub async fn spawn(app: Router, config: &AdminConfig) -> Result<String> {
+    match (&config.address, &config.socket) {
+        (Some(address), _) => {
+            let listener = tokio::net::TcpListener::bind(address).await?;
+            let address = listener.local_addr()?.to_string();
+            tokio::spawn(async move {
+                let app = app.into_make_service_with_connect_info::<std::net::SocketAddr>();
+                if let Err(e) = axum::serve(listener, app).await {
+                    error!("Admin API stopped: {}", e);
+                }
+            });
+            info!("Admin API listening on {}", address);
+            Ok(address)
+        }
+        (None, Some(socket)) => serve_unix(app, socket).await,
+        (None, None) => anyhow::bail!("admin needs exactly one of address and socket"),
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(app: Router, socket: &std::path::Path) -> Result<String> {
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    if let Some(parent) = socket
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    // A socket left behind by a previous run would make bind fail
+    if std::fs::symlink_metadata(socket).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(socket)?;
+    }
+    let listener = tokio::net::UnixListener::bind(socket)
+        .map_err(|e| anyhow::anyhow!("Cannot listen on {}: {}", socket.display(), e))?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))?;
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    error!("Admin API socket accept failed: {}", e);
+                    continue;
+                }
+            };
+            let service = TowerToHyperService::new(app.clone());
+            tokio::spawn(async move {
+                let _ = Builder::new(TokioExecutor::new())
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await;
+            });
+        }
+    });
+    let address = socket.display().to_string();
+    info!("Admin API listening on {}", address);
+    Ok(address)
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_app: Router, socket: &std::path::Path) -> Result<String> {
+    anyhow::bail!(
+        "admin socket {} needs Unix sockets; use address instead",
+        socket.display()
+    )
+}
@@ -0,0 +1,309 @@
+//! Break-glass local console (`server.attach`).
+//!
+//! `webmux attach` reaches a connection through a Unix socket served next
+//! to, not through, the HTTP stack, for when the web side misbehaves. The
+//! client sends one JSON line naming the port, gets one back, and from
+//! then on the socket carries raw bytes both ways. Attached consoles are
+//! ordinary sessions: they are listed, audited, can be kicked, and wait
+//! their turn for the write lock.
+
+use super::close::CloseReason;
+use super::sessions::{self, SessionCaps, SessionInfo, SessionRegistry};
+use crate::config::{AttachConfig, WriteLockConfig};
+use crate::logging::redact::Redactor;
+use crate::serial::{SerialData, SerialManager};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{broadcast, oneshot};
+use tracing::{info, warn};
+
+/// Longest handshake line accepted.
+const MAX_REQUEST_BYTES: u64 = 4096;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AttachRequest {
+    /// Port path of a running connection, e.g. `/dev/ttyUSB0` or one of
+    /// its `/dev/serial/by-id` links, or the connection's name.
+    pub port: String,
+    #[serde(default)]
+    pub client: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AttachReply {
+    Attached { connection: String, session: u64 },
+    Refused { error: String },
+}
+
+/// Listens on `config.socket`, replacing a socket left by an earlier run.
+pub async fn spawn(
+    manager: &SerialManager,
+    sessions: &SessionRegistry,
+    config: &AttachConfig,
+) -> Result<()> {
+    let path = &config.socket;
+    if let Some(parent) = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+    {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)
+        .map_err(|e| anyhow::anyhow!("Cannot listen on {}: {}", path.display(), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    info!("Local attach socket listening on {}", path.display());
+
+    let manager = manager.clone();
+    let sessions = sessions.clone();
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let manager = manager.clone();
+                    let sessions = sessions.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, &manager, &sessions).await {
+                            warn!("Local attach session failed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Local attach socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Connects to a server's attach socket, returning the stream, ready for
+/// raw bytes, and the name of the connection attached to.
+pub async fn connect(socket: &Path, request: &AttachRequest) -> Result<(UnixStream, String)> {
+    let mut stream = UnixStream::connect(socket).await.map_err(|e| {
+        anyhow::anyhow!(
+            "Cannot reach webmux at {}: {} (is server.attach set?)",
+            socket.display(),
+            e
+        )
+    })?;
+    let mut line = serde_json::to_string(request)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes()).await?;
+
+    // Byte by byte, so no console output is read past the reply
+    let mut reply = Vec::new();
+    loop {
+        let byte = stream.read_u8().await?;
+        if byte == b'\n' {
+            break;
+        }
+        reply.push(byte);
+    }
+    match serde_json::from_slice(&reply)? {
+        AttachReply::Attached { connection, .. } => Ok((stream, connection)),
+        AttachReply::Refused { error } => anyhow::bail!("{}", error),
+    }
+}
+
+/// The running connection on `port`, matched by path, by the device a
+/// path links to, or by name.
+async fn find_connection(manager: &SerialManager, port: &str) -> Option<String> {
+    let canonical = |path: &str| std::fs::canonicalize(path).ok();
+    let wanted = canonical(port);
+    let mut names = manager.list_connections().await;
+    names.sort();
+    for name in &names {
+        let Some(connection) = manager.get_connection(name).await else {
+            continue;
+        };
+        let configured = &connection.config().port;
+        if configured == port || (wanted.is_some() && canonical(configured) == wanted) {
+            return Some(name.clone());
+        }
+    }
+    names.into_iter().find(|name| name == port)
+}
+
+async fn reply(write: &mut OwnedWriteHalf, reply: &AttachReply) -> Result<()> {
+    let mut line = serde_json::to_string(reply)?;
+    line.push('\n');
+    write.write_all(line.as_bytes()).await?;
+    Ok(())
+}
+
+/// A console attached to a running connection.
+struct Attached {
+    name: String,
+    info: SessionInfo,
+    kick_rx: oneshot::Receiver<CloseReason>,
+    serial_rx: broadcast::Receiver<SerialData>,
+    write_lock: Option<WriteLockConfig>,
+    redactor: Option<Redactor>,
+}
+
+async fn attach(
+    line: &str,
+    manager: &SerialManager,
+    sessions: &SessionRegistry,
+) -> Result<Attached> {
+    let request: AttachRequest =
+        serde_json::from_str(line).map_err(|e| anyhow::anyhow!("Invalid attach request: {}", e))?;
+    if let Some(ref client) = request.client {
+        sessions::validate_client_label(client)?;
+    }
+    let name = find_connection(manager, &request.port)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("No running connection on {}", request.port))?;
+    let serial_rx = manager.subscribe(&name).await?;
+    let (write_lock, redact, trace) = manager
+        .get_connection(&name)
+        .await
+        .map(|connection| {
+            let config = connection.config();
+            (
+                config.write_lock.clone(),
+                config.logging.redact.clone(),
+                Some(connection.trace().clone()),
+            )
+        })
+        .unwrap_or_default();
+    // Break-glass access is not turned away by client limits
+    let (info, kick_rx) = sessions
+        .register(
+            &name,
+            request.client,
+            None,
+            false,
+            trace.as_ref(),
+            SessionCaps::default(),
+        )
+        .await?;
+    Ok(Attached {
+        name,
+        info,
+        kick_rx,
+        serial_rx,
+        write_lock,
+        // Validated with the configuration, so it only fails for broken input
+        redactor: redact.and_then(|redact| Redactor::new(&redact).ok()),
+    })
+}
+
+async fn serve(
+    stream: UnixStream,
+    manager: &SerialManager,
+    sessions: &SessionRegistry,
+) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut read = BufReader::new(read);
+    let mut line = String::new();
+    (&mut read)
+        .take(MAX_REQUEST_BYTES)
+        .read_line(&mut line)
+        .await?;
+
+    let attached = match attach(&line, manager, sessions).await {
+        Ok(attached) => attached,
+        Err(e) => {
+            let error = e.to_string();
+            return reply(&mut write, &AttachReply::Refused { error }).await;
+        }
+    };
+    let info = attached.info.clone();
+    info!("{} attached locally to {}", info, attached.name);
+    let result = relay(attached, read, &mut write, manager, sessions).await;
+    sessions.unregister(&info).await;
+    info!("{} detached locally from {}", info, info.connection);
+    result
+}
+
+async fn relay(
+    mut attached: Attached,
+    mut read: BufReader<OwnedReadHalf>,
+    write: &mut OwnedWriteHalf,
+    manager: &SerialManager,
+    sessions: &SessionRegistry,
+) -> Result<()> {
+    let name = attached.name.as_str();
+    let info = &attached.info;
+    reply(
+        write,
+        &AttachReply::Attached {
+            connection: name.to_string(),
+            session: info.id,
+        },
+    )
+    .await?;
+
+    let mut buf = vec![0u8; 4096];
+    loop {
+        tokio::select! {
+            reason = &mut attached.kick_rx => {
+                let reason = reason.map_or("closed", CloseReason::reason);
+                let notice = format!("\r\n[webmux: session {}]\r\n", reason);
+                let _ = write.write_all(notice.as_bytes()).await;
+                return Ok(());
+            }
+            result = attached.serial_rx.recv() => match result {
+                Ok(data) => {
+                    if let Some(ref mut redactor) = attached.redactor {
+                        redactor.received(&data);
+                    }
+                    write.write_all(&data).await?;
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Local console on {} lagged, skipped {} chunks", name, skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => {
+                    let _ = write.write_all(b"\r\n[webmux: connection removed]\r\n").await;
+                    return Ok(());
+                }
+            },
+            n = read.read(&mut buf) => {
+                let n = n?;
+                if n == 0 {
+                    return Ok(());
+                }
+                let data = &buf[..n];
+                if let Some(ref write_lock) = attached.write_lock {
+                    if let Err(holder) = sessions
+                        .write_locks()
+                        .acquire(info, write_lock.idle_release())
+                        .await
+                    {
+                        let notice = format!(
+                            "\r\n[webmux: not sent, {} holds the write lock]\r\n",
+                            holder.describe()
+                        );
+                        write.write_all(notice.as_bytes()).await?;
+                        continue;
+                    }
+                }
+                // What the audit log records and other sessions are shown
+                let scrubbed = match attached.redactor {
+                    Some(ref mut redactor) => redactor.sent(data),
+                    None => data.to_vec(),
+                };
+                if !scrubbed.is_empty() {
+                    sessions.audit().input(info.audit(), &scrubbed).await;
+                }
+                match manager.send_data(name, data).await {
+                    Ok(()) if !scrubbed.is_empty() => sessions.writes().wrote(info, scrubbed),
+                    Ok(()) => {}
+                    Err(e) => {
+                        let notice = format!("\r\n[webmux: not sent: {}]\r\n", e);
+                        write.write_all(notice.as_bytes()).await?;
+                    }
+                }
+            }
+        }
+    }
+}
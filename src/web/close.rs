@@ -0,0 +1,61 @@
+//! Application close codes for WebSocket sessions.
+//!
+//! Codes 4000-4999 are reserved for applications by RFC 6455. Each close
+//! frame carries the code and a short reason slug, so a client can tell a
+//! server restart, worth reconnecting after, from an operator who wants
+//! it gone.
+
+/// The connection was removed from the server.
+pub const CONNECTION_REMOVED: u16 = 4000;
+/// The client's credentials were refused. Reserved: sessions are not
+/// authenticated, so the server does not send it yet.
+pub const UNAUTHORIZED: u16 = 4001;
+/// An operator disconnected the session.
+pub const KICKED: u16 = 4002;
+/// The server is shutting down.
+pub const SERVER_SHUTDOWN: u16 = 4003;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    ConnectionRemoved,
+    Unauthorized,
+    Kicked,
+    ServerShutdown,
+}
+
+impl CloseReason {
+    pub fn from_code(code: u16) -> Option<Self> {
+        match code {
+            CONNECTION_REMOVED => Some(Self::ConnectionRemoved),
+            UNAUTHORIZED => Some(Self::Unauthorized),
+            KICKED => Some(Self::Kicked),
+            SERVER_SHUTDOWN => Some(Self::ServerShutdown),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> u16 {
+        match self {
+            Self::ConnectionRemoved => CONNECTION_REMOVED,
+            Self::Unauthorized => UNAUTHORIZED,
+            Self::Kicked => KICKED,
+            Self::ServerShutdown => SERVER_SHUTDOWN,
+        }
+    }
+
+    /// Reason text sent in the close frame.
+    pub fn reason(self) -> &'static str {
+        match self {
+            Self::ConnectionRemoved => "connection-removed",
+            Self::Unauthorized => "unauthorized",
+            Self::Kicked => "kicked",
+            Self::ServerShutdown => "server-shutdown",
+        }
+    }
+
+    /// Whether a client should reconnect on its own. Only a shutdown is
+    /// expected to be followed by the server coming back.
+    pub fn should_reconnect(self) -> bool {
+        self == Self::ServerShutdown
+    }
+}
@@ -0,0 +1,140 @@
+//! Per-subscriber thinning of WebSocket streams (`?max_rate=` / `?sample=`).
+//!
+//! Only what a WebSocket client receives is decimated; loggers, metrics and
+//! other subscribers still see every byte.
+
+use anyhow::Result;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Longest line buffered before it is emitted without a terminator.
+const MAX_LINE_LENGTH: usize = 4096;
+
+/// At most `max` messages per `window`, e.g. `10/s`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub max: u32,
+    pub window: Duration,
+}
+
+impl RateLimit {
+    pub fn parse(value: &str) -> Result<Self> {
+        let (count, unit) = value
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("Invalid max_rate {:?}: expected N/s or N/m", value))?;
+        let max: u32 = count
+            .trim()
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid max_rate {:?}: {}", value, e))?;
+        let window = match unit.trim() {
+            "s" => Duration::from_secs(1),
+            "m" => Duration::from_secs(60),
+            other => anyhow::bail!("Invalid max_rate unit {:?}: expected s or m", other),
+        };
+        if max == 0 {
+            anyhow::bail!("max_rate must be greater than 0");
+        }
+        Ok(Self { max, window })
+    }
+}
+
+/// Keep `keep` of every `every` lines, e.g. `lines:1/100`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineSample {
+    pub keep: u32,
+    pub every: u32,
+}
+
+impl LineSample {
+    pub fn parse(value: &str) -> Result<Self> {
+        let ratio = value
+            .strip_prefix("lines:")
+            .ok_or_else(|| anyhow::anyhow!("Invalid sample {:?}: expected lines:K/N", value))?;
+        let (keep, every) = ratio
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("Invalid sample {:?}: expected lines:K/N", value))?;
+        let parse = |n: &str| {
+            n.trim()
+                .parse::<u32>()
+                .map_err(|e| anyhow::anyhow!("Invalid sample {:?}: {}", value, e))
+        };
+        let (keep, every) = (parse(keep)?, parse(every)?);
+        if keep == 0 || keep > every {
+            anyhow::bail!("Invalid sample {:?}: need 0 < K <= N", value);
+        }
+        Ok(Self { keep, every })
+    }
+}
+
+/// Decides which messages a decimated subscriber receives.
+#[derive(Debug)]
+pub struct Decimator {
+    sample: Option<LineSample>,
+    rate: Option<RateLimit>,
+    seen: u64,
+    window_start: Option<Instant>,
+    in_window: u32,
+    line: Vec<u8>,
+}
+
+impl Decimator {
+    pub fn new(sample: Option<LineSample>, rate: Option<RateLimit>) -> Self {
+        Self {
+            sample,
+            rate,
+            seen: 0,
+            window_start: None,
+            in_window: 0,
+            line: Vec::new(),
+        }
+    }
+
+    /// Parses the `sample` and `max_rate` query parameters; `None` if
+    /// neither was given.
+    pub fn from_query(sample: Option<&str>, max_rate: Option<&str>) -> Result<Option<Self>> {
+        let sample = sample.map(LineSample::parse).transpose()?;
+        let rate = max_rate.map(RateLimit::parse).transpose()?;
+        Ok((sample.is_some() || rate.is_some()).then(|| Self::new(sample, rate)))
+    }
+
+    /// Whether the next message should be delivered.
+    pub fn allow(&mut self, now: Instant) -> bool {
+        let index = self.seen;
+        self.seen += 1;
+
+        if let Some(sample) = self.sample {
+            if index % sample.every as u64 >= sample.keep as u64 {
+                return false;
+            }
+        }
+
+        if let Some(rate) = self.rate {
+            match self.window_start {
+                Some(start) if now.duration_since(start) < rate.window => {}
+                _ => {
+                    self.window_start = Some(now);
+                    self.in_window = 0;
+                }
+            }
+            if self.in_window >= rate.max {
+                return false;
+            }
+            self.in_window += 1;
+        }
+
+        true
+    }
+
+    /// Splits raw bytes into complete lines (terminators included), so that
+    /// dropped messages never cut a line in half.
+    pub fn split_lines(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        for &byte in data {
+            self.line.push(byte);
+            if byte == b'\n' || self.line.len() >= MAX_LINE_LENGTH {
+                lines.push(std::mem::take(&mut self.line));
+            }
+        }
+        lines
+    }
+}
@@ -0,0 +1,65 @@
+//! Per-session echo suppression (`suppress_echo`).
+//!
+//! A device that echoes its input sends each byte a client types straight
+//! back. The client already shows what it typed, so the echo is removed
+//! from what that client receives; other subscribers see every byte.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How long after a write its echo is still expected.
+const ECHO_WINDOW: Duration = Duration::from_millis(500);
+
+/// Most bytes awaiting their echo; older ones are forgotten first.
+const MAX_PENDING: usize = 4096;
+
+/// Bytes a session wrote whose echo has not been seen yet.
+#[derive(Debug, Default)]
+pub struct EchoFilter {
+    pending: VecDeque<(u8, Instant)>,
+}
+
+impl EchoFilter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records bytes written by the session, before they reach the port.
+    pub fn sent(&mut self, data: &[u8], now: Instant) {
+        let expires = now + ECHO_WINDOW;
+        self.pending.extend(data.iter().map(|&b| (b, expires)));
+        let excess = self.pending.len().saturating_sub(MAX_PENDING);
+        self.pending.drain(..excess);
+    }
+
+    /// Removes the echo of recent writes from received `data`.
+    ///
+    /// Echoed bytes must arrive in the order they were written. The first
+    /// byte that does not match drops every pending echo, so output from
+    /// a device that stopped echoing (a password prompt, say) is never
+    /// swallowed.
+    pub fn filter(&mut self, data: &[u8], now: Instant) -> Vec<u8> {
+        while self
+            .pending
+            .front()
+            .is_some_and(|&(_, expires)| expires < now)
+        {
+            self.pending.pop_front();
+        }
+        let mut out = Vec::with_capacity(data.len());
+        for &b in data {
+            match self.pending.front() {
+                Some(&(expected, _)) if expected == b => {
+                    self.pending.pop_front();
+                }
+                Some(_) => {
+                    self.pending.clear();
+                    out.push(b);
+                }
+                None => out.push(b),
+            }
+        }
+        out
+    }
+}
@@ -35,9 +35,15 @@ fn test_data_format_base64() {
 fn test_connection_list_item_serialization() {
     let item = ConnectionListItem {
         name: "test".to_string(),
+        state: ConnectionState::Open,
+        error: None,
+        expires_at: None,
     };
     let json = serde_json::to_string(&item).unwrap();
     assert!(json.contains("test"));
+    assert!(json.contains(r#""state":"open""#));
+    assert!(!json.contains("expires_at"));
+    assert!(!json.contains("error"));
 }
 
 #[test]
@@ -49,11 +55,17 @@ fn test_connection_info_serialization() {
         data_bits: "8".to_string(),
         stop_bits: "1".to_string(),
         parity: "None".to_string(),
+        friendly_name: Some("FT232R USB UART".to_string()),
+        description: Some("FTDI FT232R USB UART (0403:6001)".to_string()),
+        expires_at: None,
+        identity: None,
+        error: None,
     };
     let json = serde_json::to_string(&info).unwrap();
     assert!(json.contains("test"));
     assert!(json.contains("ttyUSB0"));
     assert!(json.contains("115200"));
+    assert!(json.contains("0403:6001"));
 }
 
 #[test]
@@ -94,3 +106,368 @@ fn test_invalid_base64() {
     let result = general_purpose::STANDARD.decode(base64_string);
     assert!(result.is_err());
 }
+
+#[test]
+fn test_send_can_frame_request_into_frame() {
+    let json = r#"{"id": 291, "data": "01 02"}"#;
+    let request: SendCanFrameRequest = serde_json::from_str(json).unwrap();
+    let frame = request.into_frame().unwrap();
+    assert_eq!(frame.encode(), "t12320102\r");
+}
+
+#[test]
+fn test_send_can_frame_request_validation() {
+    let json = r#"{"id": 4096, "data": "01"}"#;
+    let request: SendCanFrameRequest = serde_json::from_str(json).unwrap();
+    assert!(request.into_frame().is_err());
+
+    let json = r#"{"id": 1, "dlc": 2, "data": "01"}"#;
+    let request: SendCanFrameRequest = serde_json::from_str(json).unwrap();
+    assert!(request.into_frame().is_err());
+}
+
+#[test]
+fn test_stream_query_parsing() {
+    use super::decimation::{LineSample, RateLimit};
+    use std::time::Duration;
+
+    assert_eq!(
+        RateLimit::parse("10/s").unwrap(),
+        RateLimit {
+            max: 10,
+            window: Duration::from_secs(1)
+        }
+    );
+    assert_eq!(
+        RateLimit::parse("120/m").unwrap().window,
+        Duration::from_secs(60)
+    );
+    assert!(RateLimit::parse("10").is_err());
+    assert!(RateLimit::parse("0/s").is_err());
+    assert!(RateLimit::parse("10/h").is_err());
+
+    assert_eq!(
+        LineSample::parse("lines:1/100").unwrap(),
+        LineSample {
+            keep: 1,
+            every: 100
+        }
+    );
+    assert!(LineSample::parse("bytes:1/100").is_err());
+    assert!(LineSample::parse("lines:0/10").is_err());
+    assert!(LineSample::parse("lines:5/2").is_err());
+
+    assert!(super::decimation::Decimator::from_query(None, None)
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn test_decimator_samples_lines() {
+    use super::decimation::{Decimator, LineSample};
+
+    let mut decimator = Decimator::new(Some(LineSample { keep: 1, every: 3 }), None);
+    let now = tokio::time::Instant::now();
+
+    let lines = decimator.split_lines(b"a\nb\nc\nd\ne");
+    assert_eq!(
+        lines,
+        vec![
+            b"a\n".to_vec(),
+            b"b\n".to_vec(),
+            b"c\n".to_vec(),
+            b"d\n".to_vec()
+        ]
+    );
+    // The unterminated "e" is held until its newline arrives
+    assert_eq!(decimator.split_lines(b"\n"), vec![b"e\n".to_vec()]);
+
+    let kept: Vec<bool> = (0..6).map(|_| decimator.allow(now)).collect();
+    assert_eq!(kept, vec![true, false, false, true, false, false]);
+}
+
+#[test]
+fn test_decimator_rate_limit() {
+    use super::decimation::{Decimator, RateLimit};
+    use std::time::Duration;
+
+    let mut decimator = Decimator::new(
+        None,
+        Some(RateLimit {
+            max: 2,
+            window: Duration::from_secs(1),
+        }),
+    );
+    let start = tokio::time::Instant::now();
+
+    assert!(decimator.allow(start));
+    assert!(decimator.allow(start + Duration::from_millis(100)));
+    assert!(!decimator.allow(start + Duration::from_millis(900)));
+    // A new window starts after a second
+    assert!(decimator.allow(start + Duration::from_millis(1000)));
+    assert!(decimator.allow(start + Duration::from_millis(1500)));
+    assert!(!decimator.allow(start + Duration::from_millis(1600)));
+}
+
+#[test]
+fn test_websocket_origin_checks() {
+    use super::origin::is_allowed;
+
+    let host = Some("webmux.lab:8080");
+    // No list configured: anything goes
+    assert!(is_allowed(&[], Some("https://evil.example"), host));
+
+    let allowed = vec![
+        "https://console.example.com/".to_string(),
+        "same-origin".to_string(),
+    ];
+    assert!(is_allowed(
+        &allowed,
+        Some("https://Console.Example.com"),
+        host
+    ));
+    assert!(is_allowed(&allowed, Some("http://webmux.lab:8080"), host));
+    assert!(!is_allowed(&allowed, Some("https://evil.example"), host));
+    assert!(!is_allowed(&allowed, Some("http://webmux.lab:9090"), host));
+    assert!(!is_allowed(&allowed, Some("null"), host));
+    // Non-browser clients send no Origin
+    assert!(is_allowed(&allowed, None, host));
+}
+
+#[tokio::test]
+async fn test_session_registry_enforces_caps() {
+    use crate::web::sessions::{SessionCaps, SessionRegistry};
+
+    let registry = SessionRegistry::default();
+    let caps = SessionCaps {
+        per_connection: Some(1),
+        total: Some(2),
+        per_address: None,
+    };
+    let (first, _kick) = registry
+        .register("gps", None, None, false, None, caps)
+        .await
+        .unwrap();
+    assert!(registry.check_capacity("gps", None, caps).await.is_err());
+    assert!(registry
+        .register("gps", None, None, false, None, caps)
+        .await
+        .is_err());
+
+    let _other = registry
+        .register("plc", None, None, false, None, caps)
+        .await
+        .unwrap();
+    let err = registry
+        .check_capacity("modem", None, caps)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("maximum of 2 clients"));
+
+    registry.unregister(&first).await;
+    assert!(registry
+        .register("gps", None, None, false, None, caps)
+        .await
+        .is_ok());
+    assert!(registry
+        .check_capacity("modem", None, SessionCaps::default())
+        .await
+        .is_ok());
+}
+
+#[tokio::test]
+async fn test_session_registry_caps_per_address() {
+    use crate::web::sessions::{SessionCaps, SessionRegistry};
+    use std::net::IpAddr;
+
+    let registry = SessionRegistry::default();
+    let caps = SessionCaps {
+        per_address: Some(1),
+        ..SessionCaps::default()
+    };
+    let alice: IpAddr = "203.0.113.9".parse().unwrap();
+    let bob: IpAddr = "198.51.100.4".parse().unwrap();
+    let (session, _kick) = registry
+        .register("gps", None, Some(alice), false, None, caps)
+        .await
+        .unwrap();
+    assert_eq!(
+        session.to_string(),
+        format!("session {} from 203.0.113.9", session.id)
+    );
+    let err = registry
+        .register("plc", None, Some(alice), false, None, caps)
+        .await
+        .unwrap_err();
+    assert!(err.to_string().contains("Client 203.0.113.9"));
+    assert!(registry
+        .check_capacity("plc", Some(bob), caps)
+        .await
+        .is_ok());
+    // Local sessions have no address to count against
+    assert!(registry.check_capacity("plc", None, caps).await.is_ok());
+}
+
+#[test]
+fn test_client_address_behind_trusted_proxies() {
+    use super::proxy::TrustedProxies;
+    use axum::http::HeaderMap;
+    use std::net::IpAddr;
+
+    let proxies =
+        TrustedProxies::parse(&["10.0.0.0/8".to_string(), "fd00::1".to_string()]).unwrap();
+    assert!(proxies.contains("10.1.2.3".parse().unwrap()));
+    assert!(proxies.contains("::ffff:10.1.2.3".parse().unwrap()));
+    assert!(proxies.contains("fd00::1".parse().unwrap()));
+    assert!(!proxies.contains("fd00::2".parse().unwrap()));
+    assert!(!proxies.contains("192.0.2.1".parse().unwrap()));
+    assert!(TrustedProxies::parse(&["10.0.0.0/33".to_string()]).is_err());
+    assert!(TrustedProxies::parse(&["proxy.lan".to_string()]).is_err());
+
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        "x-forwarded-for",
+        "192.0.2.66, 198.51.100.7, 10.0.0.2".parse().unwrap(),
+    );
+    let client =
+        |peer: &str, headers: &HeaderMap| proxies.client_address(peer.parse().unwrap(), headers);
+    // The leftmost entry is whatever the client claimed; stop at the first untrusted hop
+    assert_eq!(
+        client("10.0.0.1", &headers),
+        "198.51.100.7".parse::<IpAddr>().unwrap()
+    );
+    // Anyone else could have written the header themselves
+    assert_eq!(
+        client("192.0.2.1", &headers),
+        "192.0.2.1".parse::<IpAddr>().unwrap()
+    );
+
+    headers.insert("x-forwarded-for", "[2001:db8::7]:4711".parse().unwrap());
+    assert_eq!(
+        client("10.0.0.1", &headers),
+        "2001:db8::7".parse::<IpAddr>().unwrap()
+    );
+    headers.insert("x-forwarded-for", "unknown".parse().unwrap());
+    assert_eq!(
+        client("10.0.0.1", &headers),
+        "10.0.0.1".parse::<IpAddr>().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn test_proxy_protocol_headers() {
+    use super::proxy::{parse_v1, read_header};
+    use tokio::io::AsyncReadExt;
+
+    assert_eq!(
+        parse_v1("PROXY TCP6 2001:db8::7 2001:db8::1 4711 443\r\n").unwrap(),
+        Some("[2001:db8::7]:4711".parse().unwrap())
+    );
+    assert_eq!(parse_v1("PROXY UNKNOWN\r\n").unwrap(), None);
+    assert!(parse_v1("PROXY TCP4 2001:db8::7 10.0.0.1 4711 443\r\n").is_err());
+    assert!(parse_v1("PROXY TCP4 192.0.2.1\r\n").is_err());
+
+    // Whatever follows the header is left for HTTP
+    let mut v1: &[u8] = b"PROXY TCP4 192.0.2.1 10.0.0.1 51000 80\r\nGET /";
+    assert_eq!(
+        read_header(&mut v1).await.unwrap(),
+        Some("192.0.2.1:51000".parse().unwrap())
+    );
+    let mut rest = String::new();
+    v1.read_to_string(&mut rest).await.unwrap();
+    assert_eq!(rest, "GET /");
+
+    let mut v2 = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    v2.extend_from_slice(&[0x21, 0x11, 0, 12]);
+    v2.extend_from_slice(&[198, 51, 100, 7, 10, 0, 0, 1, 0x1f, 0x90, 0, 80]);
+    v2.extend_from_slice(b"GET /");
+    let mut reader: &[u8] = &v2;
+    assert_eq!(
+        read_header(&mut reader).await.unwrap(),
+        Some("198.51.100.7:8080".parse().unwrap())
+    );
+    assert_eq!(reader, b"GET /");
+
+    // LOCAL: the proxy's own health check
+    let mut local = b"\r\n\r\n\0\r\nQUIT\n".to_vec();
+    local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+    assert_eq!(read_header(&mut &local[..]).await.unwrap(), None);
+
+    let mut plain: &[u8] = b"GET / HTTP/1.1\r\n\r\n";
+    assert!(read_header(&mut plain).await.is_err());
+}
+
+#[test]
+fn test_echo_filter_drops_own_echo() {
+    use super::echo::EchoFilter;
+    use std::time::Duration;
+
+    let mut filter = EchoFilter::new();
+    let start = tokio::time::Instant::now();
+
+    // The echo may arrive split across reads, followed by real output
+    filter.sent(b"ls\r", start);
+    assert_eq!(filter.filter(b"l", start), b"");
+    assert_eq!(
+        filter.filter(b"s\r\nfile.txt\r\n", start),
+        b"\nfile.txt\r\n"
+    );
+
+    // A device that does not echo keeps its output intact
+    filter.sent(b"secret\r", start);
+    assert_eq!(filter.filter(b"\r\nDenied", start), b"\r\nDenied");
+    assert_eq!(filter.filter(b"secret", start), b"secret");
+
+    // Echo that arrives too late is passed through
+    filter.sent(b"x", start);
+    assert_eq!(filter.filter(b"x", start + Duration::from_secs(1)), b"x");
+}
+
+#[tokio::test]
+async fn test_write_lock_arbitrates_sessions() {
+    use super::write_lock::ReleaseReason;
+    use crate::web::sessions::{SessionCaps, SessionRegistry};
+    use std::time::Duration;
+
+    let registry = SessionRegistry::default();
+    let locks = registry.write_locks().clone();
+    let mut events = locks.subscribe();
+    let caps = SessionCaps::default();
+    let (alice, _kick) = registry
+        .register("core-sw", Some("alice".into()), None, false, None, caps)
+        .await
+        .unwrap();
+    let (bob, _kick) = registry
+        .register("core-sw", None, None, false, None, caps)
+        .await
+        .unwrap();
+
+    // The first to write holds the lock until they leave
+    locks.acquire(&alice, None).await.unwrap();
+    locks.acquire(&alice, None).await.unwrap();
+    let holder = locks.acquire(&bob, None).await.unwrap_err();
+    assert_eq!(holder.describe(), format!("session {} (alice)", alice.id));
+    assert_eq!(events.recv().await.unwrap().holder, Some(holder));
+
+    registry.unregister(&alice).await;
+    let event = events.recv().await.unwrap();
+    assert_eq!(event.holder, None);
+    assert_eq!(event.reason, Some(ReleaseReason::Disconnected));
+
+    // Input keeps an idle lock alive, silence releases it
+    let idle = Duration::from_millis(100);
+    locks.acquire(&bob, Some(idle)).await.unwrap();
+    events.recv().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    locks.acquire(&bob, Some(idle)).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert!(locks.holder("core-sw").await.is_some());
+
+    let event = tokio::time::timeout(Duration::from_secs(2), events.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(event.reason, Some(ReleaseReason::Idle));
+    assert_eq!(locks.holder("core-sw").await, None);
+    assert_eq!(locks.release("core-sw").await, None);
+}
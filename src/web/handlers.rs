@@ -1,193 +1,3042 @@
+use super::close::CloseReason;
+use super::decimation::Decimator;
+use super::echo::EchoFilter;
+use super::hub::{AgentInfo, RelayRequest};
+use super::lifecycle::{self, StopKind};
+use super::origin;
+use super::sessions::{self, SessionCaps, SessionInfo, SessionWrite};
+use super::write_lock::{LockHolder, ReleaseReason, WriteLockEvent};
 use super::{ApiError, AppState};
+use crate::config::drift::{self, ConfigDiff};
+use crate::config::{migrate, Config, ConnectionMode, LoggingConfig, SnifferConfig};
+use crate::flash::{self, esp, ihex, stk500, FlashProgress};
+use crate::logging::live::{self, LogEvent};
+use crate::logging::redact::Redactor;
+use crate::logging::search::{self, SearchResults};
+use crate::notifications::silence::{Silence, SilenceRequest};
+use crate::notifications::{Delivery, DeliveryState};
+use crate::serial::bulk::{BulkConnectionRequest, BulkFailure};
+use crate::serial::endpoints::NoReply;
+#[cfg(feature = "chaos")]
+use crate::serial::faults::{FaultSettings, FaultStatus};
+use crate::serial::sequence::{self, MAX_SEQUENCE_MS, MAX_STEPS, MAX_STEP_WAIT_MS};
+use crate::serial::{
+    discovery, ephemeral, Annotation, AnnotationRequest, CaptureBundle, CaptureInfo,
+    CaptureRequest, ConnectionExists, ConnectionRequest, DeviceIdentity, Direction,
+    EndpointReading, FramePage, ImportReport, LatencyReport, LatencyTestOptions, LeaseInfo,
+    LeaseMode, PortDescription, PortGroup, PortLease, PowerAction, Priority, QuotaUsage, ReplayGap,
+    RestartReport, SequenceReport, SequenceStep, ServerStats, SnifferInfo, SnifferRecord,
+    StartupReport, Throughput, TrafficProfile, TrafficRecord, VirtualPairInfo, VirtualPairRequest,
+};
+use crate::slcan::{CanFrame, SlcanDecoder};
+use crate::telemetry::{push, MetricSummary, Sample};
 use axum::{
+    body::{Body, Bytes},
     extract::{
-        ws::{Message, WebSocket, WebSocketUpgrade},
-        Path, State,
+        rejection::{BytesRejection, JsonRejection},
+        ws::{
+            close_code, rejection::WebSocketUpgradeRejection, CloseFrame, Message, WebSocket,
+            WebSocketUpgrade,
+        },
+        ConnectInfo, Path, Query, State,
+    },
+    http::{header, HeaderMap, Method, StatusCode, Uri},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Response,
     },
-    response::IntoResponse,
     Json,
 };
 use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
 use futures::{sink::SinkExt, stream::StreamExt};
 use serde::{Deserialize, Serialize};
-use tracing::{error, info};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::sync::{broadcast, mpsc};
+use tracing::{error, field, info, info_span, warn, Instrument};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionState {
+    Open,
+    /// Configured, but could not be opened; see `error`.
+    Failed,
+    /// Closed, with its settings kept so it can be enabled again.
+    Disabled,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionListItem {
+    pub name: String,
+    pub state: ConnectionState,
+    /// Why a failed connection could not be opened.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When the connection is due to be removed, if it was created with
+    /// `expires_in`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl ConnectionListItem {
+    fn open(name: String, expires_at: Option<DateTime<Utc>>) -> Self {
+        Self {
+            name,
+            state: ConnectionState::Open,
+            error: None,
+            expires_at,
+        }
+    }
+
+    fn disabled(name: String) -> Self {
+        Self {
+            name,
+            state: ConnectionState::Disabled,
+            error: None,
+            expires_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConnectionInfo {
+    pub name: String,
+    pub port: String,
+    pub baud_rate: u32,
+    pub data_bits: String,
+    pub stop_bits: String,
+    pub parity: String,
+    /// Name the OS gives the device, when it is currently present.
+    pub friendly_name: Option<String>,
+    /// Manufacturer, product and USB IDs of the adapter.
+    pub description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Model and firmware the device reported, on connections with
+    /// `identify`.
+    pub identity: Option<DeviceIdentity>,
+    /// Why a configured connection could not be opened.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateVirtualPairRequest {
+    #[serde(flatten)]
+    pub pair: VirtualPairRequest,
+    /// Seconds until the pair is removed.
+    #[serde(default)]
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendDataRequest {
+    pub data: String,
+    #[serde(default)]
+    pub format: DataFormat,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default)]
+    pub ack: Ack,
+}
+
+/// When a send request is answered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Ack {
+    /// Once the data is queued for the port.
+    #[default]
+    Queued,
+    /// Once the data has been written to the port.
+    Written,
+}
+
+/// Longest a send with `ack: written` waits for its data to go out.
+const WRITE_ACK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+#[derive(Debug, Deserialize)]
+pub struct UploadQuery {
+    #[serde(default = "default_upload_priority")]
+    pub priority: Priority,
+}
+
+fn default_upload_priority() -> Priority {
+    Priority::Bulk
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DataFormat {
+    #[default]
+    Text,
+    Hex,
+    Base64,
+}
+
+impl DataFormat {
+    pub fn decode(&self, data: String) -> anyhow::Result<Vec<u8>> {
+        match self {
+            DataFormat::Text => Ok(data.into_bytes()),
+            DataFormat::Hex => hex::decode(data.replace(" ", ""))
+                .map_err(|e| anyhow::anyhow!("Invalid hex data: {}", e)),
+            DataFormat::Base64 => general_purpose::STANDARD
+                .decode(&data)
+                .map_err(|e| anyhow::anyhow!("Invalid base64 data: {}", e)),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SequenceRequest {
+    pub steps: Vec<SequenceStepRequest>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SequenceStepRequest {
+    #[serde(default)]
+    pub data: String,
+    /// How `data` and `expect` are encoded.
+    #[serde(default)]
+    pub format: DataFormat,
+    /// Reply to wait for before the next step.
+    #[serde(default)]
+    pub expect: Option<String>,
+    /// How long to wait for `expect`.
+    #[serde(default = "default_expect_timeout_ms")]
+    pub timeout_ms: u64,
+    /// Pause before the next step.
+    #[serde(default)]
+    pub delay_ms: u64,
+}
+
+fn default_expect_timeout_ms() -> u64 {
+    1000
+}
+
+impl SequenceStepRequest {
+    fn into_step(self) -> anyhow::Result<SequenceStep> {
+        if self.timeout_ms == 0 || self.timeout_ms > MAX_STEP_WAIT_MS {
+            anyhow::bail!("timeout_ms must be between 1 and {}", MAX_STEP_WAIT_MS);
+        }
+        if self.delay_ms > MAX_STEP_WAIT_MS {
+            anyhow::bail!("delay_ms must be at most {}", MAX_STEP_WAIT_MS);
+        }
+        let expect = match self.expect {
+            Some(expect) => Some(self.format.decode(expect)?),
+            None => None,
+        };
+        if expect.as_ref().is_some_and(Vec::is_empty) {
+            anyhow::bail!("expect must not be empty");
+        }
+        Ok(SequenceStep {
+            data: self.format.decode(self.data)?,
+            expect,
+            timeout: std::time::Duration::from_millis(self.timeout_ms),
+            delay: std::time::Duration::from_millis(self.delay_ms),
+        })
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SendCanFrameRequest {
+    pub id: u32,
+    #[serde(default)]
+    pub extended: bool,
+    #[serde(default)]
+    pub rtr: bool,
+    #[serde(default)]
+    pub dlc: Option<u8>,
+    /// Frame payload as a hex string (spaces allowed).
+    #[serde(default)]
+    pub data: String,
+}
+
+impl SendCanFrameRequest {
+    pub fn into_frame(self) -> anyhow::Result<CanFrame> {
+        let data = hex::decode(self.data.replace(' ', ""))
+            .map_err(|e| anyhow::anyhow!("Invalid hex data: {}", e))?;
+        CanFrame::new(self.id, self.extended, self.rtr, self.dlc, data)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EspFlashQuery {
+    #[serde(default)]
+    pub chip: esp::EspChip,
+    /// Flash offset, decimal or `0x`-prefixed hex (default 0).
+    #[serde(default)]
+    pub offset: Option<String>,
+    #[serde(default = "default_true")]
+    pub reboot: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AvrFlashQuery {
+    #[serde(default)]
+    pub protocol: stk500::Stk500Protocol,
+    /// Flash page size in bytes (defaults to 128 for v1, 256 for v2).
+    #[serde(default)]
+    pub page_size: Option<usize>,
+    #[serde(default = "default_true")]
+    pub verify: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LeaseRequest {
+    #[serde(default)]
+    pub mode: LeaseMode,
+    #[serde(default = "default_lease_duration")]
+    pub duration_secs: u64,
+}
+
+#[derive(Debug, Deserialize, Default)]
+pub struct StreamQuery {
+    /// Deliver at most this many messages, e.g. `10/s` or `100/m`.
+    #[serde(default)]
+    pub max_rate: Option<String>,
+    /// Deliver only some lines, e.g. `lines:1/100`.
+    #[serde(default)]
+    pub sample: Option<String>,
+    /// Label identifying the client in session listings and logs; the
+    /// `X-Webmux-Client` header works too.
+    #[serde(default)]
+    pub client: Option<String>,
+    #[serde(default)]
+    pub encoding: StreamEncoding,
+    /// Write queue for the client's messages.
+    #[serde(default)]
+    pub priority: Priority,
+    /// Follow each received chunk with a `seq` message numbering it.
+    #[serde(default)]
+    pub seq: bool,
+    /// Resume after this chunk, replaying what is still buffered; implies
+    /// `seq`.
+    #[serde(default)]
+    pub since_seq: Option<u64>,
+    #[serde(default)]
+    pub mode: SessionMode,
+    #[serde(default)]
+    pub direction: StreamDirection,
+    /// Also receive `tx` messages with what other sessions write, with
+    /// `direction=rx`.
+    #[serde(default)]
+    pub share_input: bool,
+}
+
+/// Which traffic a WebSocket session receives.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamDirection {
+    /// What the device sends.
+    #[default]
+    Rx,
+    /// What is written to the port, by any client or the server itself.
+    Tx,
+    Both,
+}
+
+impl StreamDirection {
+    fn rx(self) -> bool {
+        self != Self::Tx
+    }
+
+    fn tx(self) -> bool {
+        self != Self::Rx
+    }
+}
+
+/// Whether a WebSocket session may write to the port.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SessionMode {
+    #[default]
+    ReadWrite,
+    /// A viewer: messages from the client are never written.
+    ReadOnly,
+}
+
+/// How raw traffic is carried in WebSocket messages.
+#[derive(Debug, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StreamEncoding {
+    /// Received bytes as binary messages; text and binary messages are
+    /// written as they are.
+    #[default]
+    Binary,
+    /// Received bytes as lowercase hex text messages; text messages are
+    /// decoded from hex before being written.
+    Hex,
+    /// Every message is binary and starts with a [`MuxChannel`] id, so
+    /// traffic, decoded frames and control messages share one framing.
+    Mux,
+}
+
+/// The byte leading each message of an `encoding=mux` session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum MuxChannel {
+    /// Raw traffic, in both directions.
+    Data = 0,
+    /// Decoded frames as JSON, such as CAN frames on `slcan` connections,
+    /// in both directions.
+    Decoded = 1,
+    /// The JSON text messages of other encodings: banners, write lock
+    /// changes and errors.
+    Control = 2,
+    /// Chunk numbers of sequenced sessions, as big-endian `u64`s.
+    Seq = 3,
+    /// Raw data written to the port, on sessions that asked for it with
+    /// `direction`.
+    Tx = 4,
+}
+
+impl MuxChannel {
+    pub fn message(self, payload: &[u8]) -> Message {
+        let mut data = Vec::with_capacity(payload.len() + 1);
+        data.push(self as u8);
+        data.extend_from_slice(payload);
+        Message::Binary(data)
+    }
+}
+
+/// A JSON control message, as text or on the mux control channel.
+fn control_message(frame: &impl Serialize, encoding: StreamEncoding) -> Message {
+    let json = serde_json::to_string(frame).unwrap_or_default();
+    match encoding {
+        StreamEncoding::Mux => MuxChannel::Control.message(json.as_bytes()),
+        StreamEncoding::Binary | StreamEncoding::Hex => Message::Text(json),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionsQuery {
+    /// Only list sessions on this connection.
+    #[serde(default)]
+    pub connection: Option<String>,
+}
+
+/// Sent to WebSocket clients as a text message when something they sent
+/// could not be delivered; the session stays open.
+#[derive(Debug, Serialize)]
+pub struct WsErrorFrame {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// Machine-readable reason: `write_failed`, `write_locked`,
+    /// `read_only`, `invalid_frame`, `invalid_hex`, `replay_incomplete` or
+    /// `not_found`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl WsErrorFrame {
+    pub fn new(code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            kind: "error",
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn into_message(self, encoding: StreamEncoding) -> Message {
+        control_message(&self, encoding)
+    }
+}
+
+/// Sent to WebSocket clients as a text message when they attach to a
+/// connection with a `banner`.
+#[derive(Debug, Serialize)]
+pub struct WsBannerFrame {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub text: String,
+}
+
+impl WsBannerFrame {
+    pub fn new(text: impl Into<String>) -> Self {
+        Self {
+            kind: "banner",
+            text: text.into(),
+        }
+    }
+
+    fn into_message(self, encoding: StreamEncoding) -> Message {
+        control_message(&self, encoding)
+    }
+}
+
+/// Sent on sequenced WebSocket sessions after the messages carrying a
+/// received chunk; passing the last `seq` as `since_seq` when reconnecting
+/// resumes right after it.
+#[derive(Debug, Serialize)]
+pub struct WsSeqFrame {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub seq: u64,
+}
+
+impl WsSeqFrame {
+    pub fn new(seq: u64) -> Self {
+        Self { kind: "seq", seq }
+    }
+
+    fn into_message(self, encoding: StreamEncoding) -> Message {
+        match encoding {
+            StreamEncoding::Mux => MuxChannel::Seq.message(&self.seq.to_be_bytes()),
+            StreamEncoding::Binary | StreamEncoding::Hex => control_message(&self, encoding),
+        }
+    }
+}
+
+/// Sent to every WebSocket session on a `write_lock` connection when the
+/// lock changes hands, and to a new session if it is held.
+#[derive(Debug, Serialize)]
+pub struct WsWriteLockFrame {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The session that may write, or `None` if anyone may take the lock.
+    pub holder: Option<LockHolder>,
+    /// Why the lock was released.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<ReleaseReason>,
+}
+
+impl WsWriteLockFrame {
+    fn held(holder: LockHolder) -> Self {
+        Self {
+            kind: "write_lock",
+            holder: Some(holder),
+            reason: None,
+        }
+    }
+
+    fn into_message(self, encoding: StreamEncoding) -> Message {
+        control_message(&self, encoding)
+    }
+}
+
+impl From<WriteLockEvent> for WsWriteLockFrame {
+    fn from(event: WriteLockEvent) -> Self {
+        Self {
+            kind: "write_lock",
+            holder: event.holder,
+            reason: event.reason,
+        }
+    }
+}
+
+/// What another session on the connection wrote, sent to sessions that
+/// receive only what the device sends.
+#[derive(Debug, Serialize)]
+pub struct WsTxFrame {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    /// The session that wrote it.
+    pub session: u64,
+    pub client: Option<String>,
+    pub data: String,
+    /// `text`, or `base64` for data that is not UTF-8.
+    pub format: &'static str,
+}
+
+impl WsTxFrame {
+    fn into_message(self, encoding: StreamEncoding) -> Message {
+        control_message(&self, encoding)
+    }
+}
+
+impl From<SessionWrite> for WsTxFrame {
+    fn from(write: SessionWrite) -> Self {
+        let (data, format) = match String::from_utf8(write.data) {
+            Ok(text) => (text, "text"),
+            Err(e) => (general_purpose::STANDARD.encode(e.into_bytes()), "base64"),
+        };
+        Self {
+            kind: "tx",
+            session: write.session,
+            client: write.client,
+            data,
+            format,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThroughputQuery {
+    /// Averaging window in seconds (1-60).
+    #[serde(default = "default_throughput_window")]
+    pub window: u64,
+}
+
+fn default_throughput_window() -> u64 {
+    10
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TailQuery {
+    /// Frames to return (1-1000).
+    #[serde(default = "default_tail_lines")]
+    pub lines: usize,
+    /// Only frames in this direction; both when absent.
+    #[serde(default)]
+    pub direction: Option<Direction>,
+}
+
+fn default_tail_lines() -> usize {
+    200
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TrafficProfileQuery {
+    /// Seconds of traffic to describe (1-600).
+    #[serde(default = "default_traffic_profile_window")]
+    pub window: u64,
+}
+
+fn default_traffic_profile_window() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize)]
+pub struct HexdumpQuery {
+    /// Most recent bytes to dump (1-65536).
+    #[serde(default = "default_hexdump_bytes")]
+    pub bytes: usize,
+    #[serde(default = "default_hexdump_direction")]
+    pub direction: Direction,
+}
+
+fn default_hexdump_bytes() -> usize {
+    512
+}
+
+fn default_hexdump_direction() -> Direction {
+    Direction::Rx
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FramesQuery {
+    /// Return chunks after this one; 0 for the oldest still kept.
+    #[serde(default)]
+    pub since_seq: u64,
+    /// Chunks to return at most (1-1000).
+    #[serde(default = "default_frames_limit")]
+    pub limit: usize,
+}
+
+fn default_frames_limit() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReadQuery {
+    /// Name of the reader whose place is kept.
+    #[serde(default = "default_cursor")]
+    pub cursor: String,
+    /// Chunks to return at most (1-1000).
+    #[serde(default = "default_frames_limit")]
+    pub limit: usize,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AnnotationsQuery {
+    /// Only annotations following chunks after this one; 0 for all.
+    #[serde(default)]
+    pub since_seq: u64,
+}
+
+fn default_cursor() -> String {
+    "default".to_string()
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PollQuery {
+    /// Return chunks after this one; 0 for the oldest still kept.
+    #[serde(default)]
+    pub since_seq: u64,
+    /// Seconds to wait for a chunk if there is none yet (0-120).
+    #[serde(default = "default_poll_timeout")]
+    pub timeout: u64,
+    /// Chunks to return at most (1-1000).
+    #[serde(default = "default_frames_limit")]
+    pub limit: usize,
+}
+
+fn default_poll_timeout() -> u64 {
+    30
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    /// Regex matched against each logged chunk.
+    pub q: String,
+    /// Comma-separated connection names; every connection with a text log
+    /// when absent.
+    #[serde(default)]
+    pub connections: Option<String>,
+    #[serde(default)]
+    pub from: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub to: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub offset: usize,
+    #[serde(default = "default_search_limit")]
+    pub limit: usize,
+}
+
+fn default_search_limit() -> usize {
+    100
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MetricSamplesQuery {
+    /// Return only the most recent `limit` samples.
+    #[serde(default)]
+    pub limit: Option<usize>,
+}
+
+fn default_lease_duration() -> u64 {
+    300
+}
+
+fn default_true() -> bool {
+    true
+}
+
+pub async fn list_connections(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<ConnectionListItem>>, ApiError> {
+    let connections = state.serial_manager.list_connections().await;
+    let mut items = Vec::with_capacity(connections.len());
+    for name in connections {
+        let expires_at = state.serial_manager.connection_expiry(&name).await;
+        items.push(ConnectionListItem::open(name, expires_at));
+    }
+    for failed in state.serial_manager.failed_connections().await {
+        items.push(ConnectionListItem {
+            name: failed.name,
+            state: ConnectionState::Failed,
+            error: Some(failed.error),
+            expires_at: None,
+        });
+    }
+    for disabled in state.serial_manager.disabled_connections().await {
+        items.push(ConnectionListItem::disabled(disabled.name));
+    }
+    Ok(Json(items))
+}
+
+/// Opens a configured connection that failed to open, with its configured
+/// settings.
+pub async fn retry_connection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ConnectionListItem>, ApiError> {
+    let failed = state
+        .serial_manager
+        .get_failed_connection(&name)
+        .await
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("No failed connection named {}", name),
+            )
+        })?;
+    state
+        .serial_manager
+        .retry_connection(&name)
+        .await
+        .map_err(|e| ApiError::bad_request(format!("Failed to open {}: {:#}", failed.port, e)))?;
+    Ok(Json(ConnectionListItem::open(name, None)))
+}
+
+/// Opens a disabled connection again, with the settings it was kept with.
+pub async fn enable_connection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ConnectionListItem>, ApiError> {
+    let port = match state.serial_manager.get_disabled_connection(&name).await {
+        Some(config) => Some(config.port),
+        None => state
+            .serial_manager
+            .get_failed_connection(&name)
+            .await
+            .map(|failed| failed.port),
+    };
+    let Some(port) = port else {
+        return match state.serial_manager.get_connection(&name).await {
+            Some(_) => {
+                let expires_at = state.serial_manager.connection_expiry(&name).await;
+                Ok(Json(ConnectionListItem::open(name, expires_at)))
+            }
+            None => Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Connection not found: {}", name),
+            )),
+        };
+    };
+    state
+        .serial_manager
+        .enable_connection(&name)
+        .await
+        .map_err(|e| ApiError::bad_request(format!("Failed to open {}: {:#}", port, e)))?;
+    Ok(Json(ConnectionListItem::open(name, None)))
+}
+
+/// Closes a connection but keeps it listed, so it can be enabled again.
+pub async fn disable_connection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ConnectionListItem>, ApiError> {
+    state
+        .serial_manager
+        .disable_connection(&name)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok(Json(ConnectionListItem::disabled(name)))
+}
+
+pub async fn create_connection(
+    State(state): State<AppState>,
+    Json(request): Json<ConnectionRequest>,
+) -> Result<(StatusCode, Json<ConnectionListItem>), ApiError> {
+    if request.name.is_empty() {
+        return Err(ApiError::bad_request("Connection name must not be empty"));
+    }
+    let expires_in = request
+        .expires_in
+        .map(ephemeral::expires_in)
+        .transpose()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let item = open_connection(&state, request, expires_in).await?;
+    Ok((StatusCode::CREATED, Json(item)))
+}
+
+/// Opens a checked request, failing with 409 if the name is taken.
+async fn open_connection(
+    state: &AppState,
+    request: ConnectionRequest,
+    expires_in: Option<std::time::Duration>,
+) -> Result<ConnectionListItem, ApiError> {
+    let config = request
+        .config()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    state
+        .serial_manager
+        .add_connection(config)
+        .await
+        .map_err(|e| match e.downcast_ref::<ConnectionExists>() {
+            Some(exists) => ApiError::new(StatusCode::CONFLICT, exists.to_string()),
+            None => ApiError::bad_request(format!("Failed to open {}: {}", request.port, e)),
+        })?;
+    let expires_at = match expires_in {
+        Some(after) => Some(
+            state
+                .serial_manager
+                .expire_connection(&request.name, after)
+                .await?,
+        ),
+        None => None,
+    };
+    Ok(ConnectionListItem::open(request.name, expires_at))
+}
+
+#[derive(Debug, Serialize)]
+pub struct BulkConnectionReport {
+    pub created: Vec<ConnectionListItem>,
+    pub failed: Vec<BulkFailure>,
+}
+
+/// Opens a connection from `template` on each port; one that fails does
+/// not stop the rest.
+pub async fn create_connections_bulk(
+    State(state): State<AppState>,
+    Json(request): Json<BulkConnectionRequest>,
+) -> Result<Json<BulkConnectionReport>, ApiError> {
+    let expires_in = request
+        .template
+        .expires_in
+        .map(ephemeral::expires_in)
+        .transpose()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let present = match request.glob {
+        Some(_) => discovery::list_ports().await?,
+        None => Vec::new(),
+    };
+    let requests = request
+        .expand(&present)
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let mut report = BulkConnectionReport {
+        created: Vec::new(),
+        failed: Vec::new(),
+    };
+    for request in requests {
+        let (name, port) = (request.name.clone(), request.port.clone());
+        match open_connection(&state, request, expires_in).await {
+            Ok(item) => report.created.push(item),
+            Err(e) => report.failed.push(BulkFailure {
+                name,
+                port,
+                error: e.error,
+            }),
+        }
+    }
+    info!(
+        "Opened {} connections in bulk, {} failed",
+        report.created.len(),
+        report.failed.len()
+    );
+    Ok(Json(report))
+}
+
+pub async fn delete_connection(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<&'static str, ApiError> {
+    state
+        .serial_manager
+        .remove_connection(&name)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok("Connection removed")
+}
+
+pub async fn list_ports() -> Result<Json<Vec<PortDescription>>, ApiError> {
+    Ok(Json(discovery::list_ports().await?))
+}
+
+pub async fn list_port_groups(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<PortGroup>>, ApiError> {
+    Ok(Json(state.serial_manager.port_groups().await?))
+}
+
+/// Closes every connection on an adapter's ports and opens them again.
+pub async fn restart_port_group(
+    State(state): State<AppState>,
+    Path(id): Path<String>,
+) -> Result<Json<RestartReport>, ApiError> {
+    let group = state
+        .serial_manager
+        .port_groups()
+        .await?
+        .into_iter()
+        .find(|group| group.id == id)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Port group not found: {}", id),
+            )
+        })?;
+    info!(
+        "Restarting {} connections on port group {}",
+        group.connections.len(),
+        id
+    );
+    let report = state
+        .serial_manager
+        .restart_connections(&group.connections)
+        .await;
+    Ok(Json(report))
+}
+
+pub async fn get_server_stats(State(state): State<AppState>) -> Json<ServerStats> {
+    Json(state.serial_manager.get_server_stats().await)
+}
+
+pub async fn list_quotas(State(state): State<AppState>) -> Json<Vec<QuotaUsage>> {
+    Json(state.serial_manager.quotas().await)
+}
+
+pub async fn get_config_diff(State(state): State<AppState>) -> Result<Json<ConfigDiff>, ApiError> {
+    if state.serial_manager.config_files().is_none() {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "The server was not started from a configuration file",
+        ));
+    }
+    // A file that no longer loads is reported as a server error
+    let diff = state.serial_manager.config_diff().await?;
+    Ok(Json(diff))
+}
+
+pub async fn export_config(State(state): State<AppState>) -> Result<Response, ApiError> {
+    let config = Config {
+        version: migrate::CURRENT_VERSION,
+        server: state.server.clone(),
+        serial_connections: state.serial_manager.export_connections().await?,
+    };
+    let yaml = serde_yaml::to_string(&config).map_err(anyhow::Error::from)?;
+    Ok(([(header::CONTENT_TYPE, "application/yaml")], yaml).into_response())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ImportQuery {
+    /// Report what the import would change without changing anything.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+pub async fn import_config(
+    State(state): State<AppState>,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> Result<Json<ImportReport>, ApiError> {
+    let config = Config::from_yaml(&body)
+        .map_err(|e| ApiError::bad_request(format!("Invalid configuration: {}", e)))?
+        .config;
+    config
+        .validate()
+        .map_err(|e| ApiError::bad_request(format!("Invalid configuration: {}", e)))?;
+
+    let mut report = state
+        .serial_manager
+        .import_connections(config.serial_connections, query.dry_run)
+        .await;
+    report.restart_required = drift::changed_fields(&state.server, &config.server);
+    if !query.dry_run {
+        info!(
+            "Imported configuration: {} opened, {} closed, {} reopened, {} failed",
+            report.open.len(),
+            report.close.len(),
+            report.reopen.len(),
+            report.failed.len()
+        );
+    }
+    Ok(Json(report))
+}
+
+pub async fn get_connection_info(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ConnectionInfo>, ApiError> {
+    let (config, identity, error) = match state.serial_manager.get_connection(&name).await {
+        Some(connection) => (connection.config().clone(), connection.identity(), None),
+        None => match state.serial_manager.get_failed_connection(&name).await {
+            Some(failed) => (failed.config, None, Some(failed.error)),
+            None => match state.serial_manager.get_disabled_connection(&name).await {
+                Some(config) => (config, None, None),
+                None => {
+                    // Return empty strings for non-existent connections
+                    return Ok(Json(ConnectionInfo {
+                        name,
+                        port: String::new(),
+                        baud_rate: 0,
+                        data_bits: String::new(),
+                        stop_bits: String::new(),
+                        parity: String::new(),
+                        friendly_name: None,
+                        description: None,
+                        expires_at: None,
+                        identity: None,
+                        error: None,
+                    }));
+                }
+            },
+        },
+    };
+    let ports = discovery::list_ports().await.unwrap_or_default();
+    let device = discovery::find_port(&ports, &config.port);
+    Ok(Json(ConnectionInfo {
+        name: config.name.clone(),
+        port: config.port.clone(),
+        baud_rate: config.baud_rate,
+        data_bits: match config.data_bits {
+            crate::config::DataBits::Five => "5",
+            crate::config::DataBits::Six => "6",
+            crate::config::DataBits::Seven => "7",
+            crate::config::DataBits::Eight => "8",
+        }
+        .to_string(),
+        stop_bits: match config.stop_bits {
+            crate::config::StopBits::One => "1",
+            crate::config::StopBits::Two => "2",
+        }
+        .to_string(),
+        parity: format!("{:?}", config.parity),
+        friendly_name: device.and_then(|device| device.friendly_name.clone()),
+        description: device.and_then(|device| device.description.clone()),
+        expires_at: state.serial_manager.connection_expiry(&name).await,
+        identity,
+        error,
+    }))
+}
+
+pub async fn send_data(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    request: Result<Json<SendDataRequest>, JsonRejection>,
+) -> Result<&'static str, ApiError> {
+    let started = std::time::Instant::now();
+    let max = state.limits.max_send_bytes;
+    let Json(request) = request.map_err(|e| {
+        ApiError::rejected_body(e.status(), e.body_text(), super::send_body_limit(max))
+    })?;
+    let data = request.format.decode(request.data)?;
+    if data.len() > max {
+        return Err(ApiError::too_large(format!(
+            "Payload of {} bytes exceeds the {} byte limit",
+            data.len(),
+            max
+        )));
+    }
+
+    if request.ack == Ack::Queued {
+        state
+            .serial_manager
+            .send_data_with_priority(&name, &data, request.priority)
+            .await?;
+        state
+            .serial_manager
+            .observe_request(&name, "send", started.elapsed())
+            .await;
+        return Ok("Data sent");
+    }
+
+    let written = state
+        .serial_manager
+        .send_data_written(&name, &data, request.priority);
+    match tokio::time::timeout(WRITE_ACK_TIMEOUT, written).await {
+        Ok(result) => result?,
+        Err(_) => {
+            return Err(ApiError::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "Data was queued but not written within {}s",
+                    WRITE_ACK_TIMEOUT.as_secs()
+                ),
+            ))
+        }
+    }
+    state
+        .serial_manager
+        .observe_request(&name, "send", started.elapsed())
+        .await;
+    Ok("Data written")
+}
+
+/// Runs steps that other clients' writes cannot come between, such as
+/// the messages of a handshake.
+pub async fn run_sequence(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    request: Result<Json<SequenceRequest>, JsonRejection>,
+) -> Result<Json<SequenceReport>, ApiError> {
+    let started = std::time::Instant::now();
+    let max = state.limits.max_send_bytes;
+    let Json(request) = request.map_err(|e| {
+        ApiError::rejected_body(e.status(), e.body_text(), super::sequence_body_limit(max))
+    })?;
+    if request.steps.is_empty() || request.steps.len() > MAX_STEPS {
+        return Err(ApiError::bad_request(format!(
+            "steps must hold between 1 and {} steps",
+            MAX_STEPS
+        )));
+    }
+    let steps = request
+        .steps
+        .into_iter()
+        .enumerate()
+        .map(|(i, step)| {
+            step.into_step()
+                .map_err(|e| ApiError::bad_request(format!("Step {}: {}", i + 1, e)))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let longest = sequence::longest_wait(&steps);
+    if longest > std::time::Duration::from_millis(MAX_SEQUENCE_MS) {
+        return Err(ApiError::bad_request(format!(
+            "Steps could wait {} ms in total, more than {}",
+            longest.as_millis(),
+            MAX_SEQUENCE_MS
+        )));
+    }
+    let total: usize = steps.iter().map(|step| step.data.len()).sum();
+    if total > max {
+        return Err(ApiError::too_large(format!(
+            "Payload of {} bytes exceeds the {} byte limit",
+            total, max
+        )));
+    }
+    if state.serial_manager.get_lease(&name).await.is_some() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Connection {} is leased to an external tool", name),
+        ));
+    }
+
+    let report = state.serial_manager.run_sequence(&name, &steps).await?;
+    state
+        .serial_manager
+        .observe_request(&name, "sequence", started.elapsed())
+        .await;
+    Ok(Json(report))
+}
+
+/// Writes the request body to the port as-is, by default on the bulk
+/// queue so interactive sessions stay responsive.
+pub async fn upload_data(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<UploadQuery>,
+    data: Result<Bytes, BytesRejection>,
+) -> Result<&'static str, ApiError> {
+    let data = data.map_err(|e| {
+        ApiError::rejected_body(e.status(), e.body_text(), state.limits.max_upload_bytes)
+    })?;
+    if data.is_empty() {
+        return Err(ApiError::bad_request("Upload is empty"));
+    }
+    info!("Uploading {} bytes to {}", data.len(), name);
+    state
+        .serial_manager
+        .send_data_with_priority(&name, &data, query.priority)
+        .await?;
+    Ok("Data sent")
+}
+
+pub async fn get_stats(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<crate::serial::ConnectionStats>, ApiError> {
+    let stats = state.serial_manager.get_stats(&name).await?;
+    Ok(Json(stats))
+}
+
+pub async fn get_throughput(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<ThroughputQuery>,
+) -> Result<Json<Throughput>, ApiError> {
+    if query.window == 0 || query.window > crate::serial::throughput::MAX_WINDOW_SECS {
+        return Err(ApiError::bad_request(format!(
+            "window must be between 1 and {}",
+            crate::serial::throughput::MAX_WINDOW_SECS
+        )));
+    }
+    let throughput = state
+        .serial_manager
+        .get_throughput(&name, query.window)
+        .await?;
+    Ok(Json(throughput))
+}
+
+pub async fn get_tail(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<TailQuery>,
+) -> Result<Json<Vec<TrafficRecord>>, ApiError> {
+    if query.lines == 0 || query.lines > crate::serial::history::MAX_FRAMES {
+        return Err(ApiError::bad_request(format!(
+            "lines must be between 1 and {}",
+            crate::serial::history::MAX_FRAMES
+        )));
+    }
+    let records = state
+        .serial_manager
+        .tail(&name, query.lines, query.direction)
+        .await?;
+    Ok(Json(records))
+}
+
+pub async fn get_traffic_profile(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<TrafficProfileQuery>,
+) -> Result<Json<TrafficProfile>, ApiError> {
+    if query.window == 0 || query.window > crate::serial::traffic_profile::MAX_PROFILE_WINDOW_SECS {
+        return Err(ApiError::bad_request(format!(
+            "window must be between 1 and {}",
+            crate::serial::traffic_profile::MAX_PROFILE_WINDOW_SECS
+        )));
+    }
+    let profile = state
+        .serial_manager
+        .traffic_profile(&name, query.window)
+        .await?;
+    Ok(Json(profile))
+}
+
+pub async fn get_hexdump(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<HexdumpQuery>,
+) -> Result<String, ApiError> {
+    if query.bytes == 0 || query.bytes > crate::serial::history::MAX_HEXDUMP_BYTES {
+        return Err(ApiError::bad_request(format!(
+            "bytes must be between 1 and {}",
+            crate::serial::history::MAX_HEXDUMP_BYTES
+        )));
+    }
+    let data = state
+        .serial_manager
+        .recent_bytes(&name, query.bytes, query.direction)
+        .await?;
+    Ok(crate::serial::hexdump::canonical(&data))
+}
+
+pub async fn get_frames(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<FramesQuery>,
+) -> Result<Json<FramePage>, ApiError> {
+    if query.limit == 0 || query.limit > crate::serial::replay::REPLAY_CHUNKS {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {}",
+            crate::serial::replay::REPLAY_CHUNKS
+        )));
+    }
+    let page = state
+        .serial_manager
+        .frames(&name, query.since_seq, query.limit)
+        .await?;
+    Ok(Json(page))
+}
+
+pub async fn read_frames(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<ReadQuery>,
+) -> Result<Json<FramePage>, ApiError> {
+    if query.limit == 0 || query.limit > crate::serial::replay::REPLAY_CHUNKS {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {}",
+            crate::serial::replay::REPLAY_CHUNKS
+        )));
+    }
+    if query.cursor.is_empty() || query.cursor.len() > 64 {
+        return Err(ApiError::bad_request(
+            "cursor must be 1 to 64 characters".to_string(),
+        ));
+    }
+    let page = state
+        .serial_manager
+        .read_frames(&name, &query.cursor, query.limit)
+        .await?;
+    Ok(Json(page))
+}
+
+pub async fn create_annotation(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<AnnotationRequest>,
+) -> Result<(StatusCode, Json<Annotation>), ApiError> {
+    request
+        .validate(Utc::now())
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let seq = request.seq;
+    let annotation = state
+        .serial_manager
+        .annotate(&name, request)
+        .await?
+        .ok_or_else(|| {
+            ApiError::bad_request(format!(
+                "Chunk {} is not kept; annotate by timestamp instead",
+                seq.unwrap_or_default()
+            ))
+        })?;
+    Ok((StatusCode::CREATED, Json(annotation)))
+}
+
+pub async fn list_annotations(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<AnnotationsQuery>,
+) -> Result<Json<Vec<Annotation>>, ApiError> {
+    let annotations = state
+        .serial_manager
+        .annotations(&name, query.since_seq)
+        .await?;
+    Ok(Json(annotations))
+}
+
+pub async fn poll_frames(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<PollQuery>,
+) -> Result<Json<FramePage>, ApiError> {
+    if query.limit == 0 || query.limit > crate::serial::replay::REPLAY_CHUNKS {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {}",
+            crate::serial::replay::REPLAY_CHUNKS
+        )));
+    }
+    if query.timeout > crate::serial::replay::MAX_POLL_SECS {
+        return Err(ApiError::bad_request(format!(
+            "timeout must be between 0 and {}",
+            crate::serial::replay::MAX_POLL_SECS
+        )));
+    }
+    let page = state
+        .serial_manager
+        .poll_frames(
+            &name,
+            query.since_seq,
+            query.limit,
+            std::time::Duration::from_secs(query.timeout),
+        )
+        .await?;
+    Ok(Json(page))
+}
+
+pub async fn search_logs(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResults>, ApiError> {
+    if params.limit == 0 || params.limit > search::MAX_LIMIT {
+        return Err(ApiError::bad_request(format!(
+            "limit must be between 1 and {}",
+            search::MAX_LIMIT
+        )));
+    }
+    if params.offset.saturating_add(params.limit) > search::MAX_DEPTH {
+        return Err(ApiError::bad_request(format!(
+            "offset and limit together must be at most {}; narrow the search with from and to",
+            search::MAX_DEPTH
+        )));
+    }
+    let pattern = regex::Regex::new(&params.q)
+        .map_err(|e| ApiError::bad_request(format!("Invalid pattern: {}", e)))?;
+
+    let has_text_log = |logging: &LoggingConfig| logging.enabled && !logging.raw;
+    let mut connections = Vec::new();
+    match params.connections {
+        Some(ref names) => {
+            for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+                let connection = state
+                    .serial_manager
+                    .get_connection(name)
+                    .await
+                    .ok_or_else(|| ApiError::bad_request(format!("Unknown connection {}", name)))?;
+                let logging = &connection.config().logging;
+                if !has_text_log(logging) {
+                    return Err(ApiError::bad_request(format!(
+                        "Connection {} has no text log to search",
+                        name
+                    )));
+                }
+                connections.push((name.to_string(), logging.clone()));
+            }
+        }
+        None => {
+            let mut names = state.serial_manager.list_connections().await;
+            names.sort();
+            for name in names {
+                if let Some(connection) = state.serial_manager.get_connection(&name).await {
+                    if has_text_log(&connection.config().logging) {
+                        connections.push((name, connection.config().logging.clone()));
+                    }
+                }
+            }
+        }
+    }
+
+    let query = search::SearchQuery {
+        pattern,
+        from: params.from,
+        to: params.to,
+        offset: params.offset,
+        limit: params.limit,
+    };
+    Ok(Json(search::search(&connections, &query).await?))
+}
+
+pub async fn latency_test(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(options): Json<LatencyTestOptions>,
+) -> Result<Json<LatencyReport>, ApiError> {
+    options
+        .validate()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if state.serial_manager.get_lease(&name).await.is_some() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Connection {} is leased to an external tool", name),
+        ));
+    }
+
+    let report = state.serial_manager.latency_test(&name, &options).await?;
+    Ok(Json(report))
+}
+
+pub async fn power(
+    State(state): State<AppState>,
+    Path((name, action)): Path<(String, String)>,
+) -> Result<&'static str, ApiError> {
+    let action = match action.as_str() {
+        "on" => PowerAction::On,
+        "off" => PowerAction::Off,
+        "cycle" => PowerAction::Cycle,
+        _ => {
+            return Err(ApiError::bad_request(
+                "Power action must be on, off or cycle",
+            ))
+        }
+    };
+    let has_power = state
+        .serial_manager
+        .get_connection(&name)
+        .await
+        .map(|connection| connection.config().power.is_some());
+    if has_power == Some(false) {
+        return Err(ApiError::bad_request(format!(
+            "Connection {} has no power control",
+            name
+        )));
+    }
+
+    state.serial_manager.power(&name, action).await?;
+    Ok(match action {
+        PowerAction::On => "Powered on",
+        PowerAction::Off => "Powered off",
+        PowerAction::Cycle => "Power cycled",
+    })
+}
+
+pub async fn send_can_frame(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<SendCanFrameRequest>,
+) -> Result<Json<CanFrame>, ApiError> {
+    let connection = state
+        .serial_manager
+        .get_connection(&name)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+
+    if connection.config().mode != ConnectionMode::Slcan {
+        return Err(ApiError::bad_request(format!(
+            "Connection {} is not in SLCAN mode",
+            name
+        )));
+    }
+
+    let frame = request
+        .into_frame()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    connection.send(frame.encode().as_bytes()).await?;
+    Ok(Json(frame))
+}
+
+/// Flashes an ESP32/ESP8266 attached to the connection with the request body.
+///
+/// The port is taken from the connection for the duration of the flash and
+/// progress is streamed back as newline-delimited JSON.
+pub async fn flash_esp(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<EspFlashQuery>,
+    image: Result<Bytes, BytesRejection>,
+) -> Result<Response, ApiError> {
+    let image = image.map_err(|e| {
+        ApiError::rejected_body(e.status(), e.body_text(), state.limits.max_upload_bytes)
+    })?;
+    let offset = match query.offset {
+        Some(offset) => {
+            flash::parse_offset(&offset).map_err(|e| ApiError::bad_request(e.to_string()))?
+        }
+        None => 0,
+    };
+    if image.is_empty() {
+        return Err(ApiError::bad_request("Firmware image is empty"));
+    }
+
+    let options = esp::EspFlashOptions {
+        chip: query.chip,
+        offset,
+        reboot: query.reboot,
+    };
+    info!(
+        "Flashing {} bytes to {} at 0x{:X}",
+        image.len(),
+        name,
+        options.offset
+    );
+
+    run_flash(&state, name, move |mut lease, progress| async move {
+        esp::flash(&mut *lease, &image, &options, &progress).await
+    })
+    .await
+}
+
+/// Uploads an Intel HEX sketch to an Arduino bootloader attached to the connection.
+pub async fn flash_avr(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Query(query): Query<AvrFlashQuery>,
+    body: Result<Bytes, BytesRejection>,
+) -> Result<Response, ApiError> {
+    let body = body.map_err(|e| {
+        ApiError::rejected_body(e.status(), e.body_text(), state.limits.max_upload_bytes)
+    })?;
+    let text = std::str::from_utf8(&body)
+        .map_err(|_| ApiError::bad_request("HEX file is not valid UTF-8"))?;
+    let image = ihex::parse(text).map_err(|e| ApiError::bad_request(e.to_string()))?;
+
+    let options = stk500::Stk500Options {
+        protocol: query.protocol,
+        page_size: query
+            .page_size
+            .unwrap_or_else(|| query.protocol.default_page_size()),
+        verify: query.verify,
+    };
+    info!(
+        "Uploading {} bytes to {} via {:?}",
+        image.data.len(),
+        name,
+        options.protocol
+    );
+
+    run_flash(&state, name, move |mut lease, progress| async move {
+        stk500::flash(
+            &mut *lease,
+            image.base_address,
+            &image.data,
+            &options,
+            &progress,
+        )
+        .await
+    })
+    .await
+}
+
+/// Takes exclusive access to the connection's port, runs `job` on it in the
+/// background and streams its progress back as newline-delimited JSON.
+async fn run_flash<F, Fut>(state: &AppState, name: String, job: F) -> Result<Response, ApiError>
+where
+    F: FnOnce(PortLease, mpsc::Sender<FlashProgress>) -> Fut + Send + 'static,
+    Fut: Future<Output = anyhow::Result<()>> + Send,
+{
+    let connection = state
+        .serial_manager
+        .get_connection(&name)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+    if state.serial_manager.get_lease(&name).await.is_some() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Connection {} is leased to an external tool", name),
+        ));
+    }
+    let lease = connection.acquire_exclusive().await?;
+
+    let (progress_tx, progress_rx) = mpsc::channel(32);
+    tokio::spawn(async move {
+        // The lease is dropped when the job finishes, handing the port back
+        let result = job(lease, progress_tx.clone()).await;
+
+        let event = match result {
+            Ok(()) => {
+                info!("Flashing {} completed", name);
+                FlashProgress::Done
+            }
+            Err(e) => {
+                error!("Flashing {} failed: {}", name, e);
+                FlashProgress::Error {
+                    message: e.to_string(),
+                }
+            }
+        };
+        flash::report(&progress_tx, event).await;
+    });
+
+    Ok(ndjson_progress(progress_rx))
+}
+
+fn ndjson_progress(progress_rx: mpsc::Receiver<FlashProgress>) -> Response {
+    let stream = futures::stream::unfold(progress_rx, |mut rx| async move {
+        let event = rx.recv().await?;
+        let mut line = serde_json::to_string(&event).unwrap_or_default();
+        line.push('\n');
+        Some((Ok::<_, std::convert::Infallible>(line), rx))
+    });
+
+    (
+        [(header::CONTENT_TYPE, "application/x-ndjson")],
+        Body::from_stream(stream),
+    )
+        .into_response()
+}
+
+pub async fn list_metrics(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<Vec<MetricSummary>>, ApiError> {
+    let metrics = state.serial_manager.get_metrics(&name).await?;
+    Ok(Json(metrics))
+}
+
+pub async fn get_metric_samples(
+    State(state): State<AppState>,
+    Path((name, metric)): Path<(String, String)>,
+    Query(query): Query<MetricSamplesQuery>,
+) -> Result<Json<Vec<Sample>>, ApiError> {
+    state
+        .serial_manager
+        .get_metric_samples(&name, &metric, query.limit)
+        .await?
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Metric not found: {}", metric),
+            )
+        })
+}
+
+/// Reads one of the values declared under the connection's `endpoints`.
+pub async fn query_endpoint(
+    State(state): State<AppState>,
+    Path((name, endpoint)): Path<(String, String)>,
+) -> Result<Json<EndpointReading>, ApiError> {
+    let started = std::time::Instant::now();
+    if state.serial_manager.get_lease(&name).await.is_some() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Connection {} is leased to an external tool", name),
+        ));
+    }
+
+    let reading = match state.serial_manager.query_endpoint(&name, &endpoint).await {
+        Ok(Some(reading)) => reading,
+        Ok(None) => {
+            return Err(ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Endpoint not found: {}", endpoint),
+            ))
+        }
+        Err(e) if e.is::<NoReply>() => {
+            return Err(ApiError::new(StatusCode::GATEWAY_TIMEOUT, e.to_string()))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    state
+        .serial_manager
+        .observe_request(&name, "endpoint", started.elapsed())
+        .await;
+    Ok(Json(reading))
+}
+
+pub async fn create_lease(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(request): Json<LeaseRequest>,
+) -> Result<Json<LeaseInfo>, ApiError> {
+    let duration = std::time::Duration::from_secs(request.duration_secs);
+    if duration.is_zero() || duration > crate::serial::lease::MAX_LEASE_DURATION {
+        return Err(ApiError::bad_request(format!(
+            "duration_secs must be between 1 and {}",
+            crate::serial::lease::MAX_LEASE_DURATION.as_secs()
+        )));
+    }
+    if state.serial_manager.get_lease(&name).await.is_some() {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Connection {} is already leased", name),
+        ));
+    }
 
-#[derive(Debug, Serialize)]
-pub struct ConnectionListItem {
-    pub name: String,
+    let info = state
+        .serial_manager
+        .start_lease(&name, request.mode, duration, &state.server.lease.bind_host)
+        .await?;
+    Ok(Json(info))
 }
 
-#[derive(Debug, Serialize)]
-pub struct ConnectionInfo {
-    pub name: String,
-    pub port: String,
-    pub baud_rate: u32,
-    pub data_bits: String,
-    pub stop_bits: String,
-    pub parity: String,
+pub async fn get_lease(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<LeaseInfo>, ApiError> {
+    state
+        .serial_manager
+        .get_lease(&name)
+        .await
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Connection {} is not leased", name),
+            )
+        })
 }
 
-#[derive(Debug, Deserialize)]
-pub struct SendDataRequest {
-    pub data: String,
-    #[serde(default)]
-    pub format: DataFormat,
+pub async fn end_lease(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<&'static str, ApiError> {
+    state
+        .serial_manager
+        .end_lease(&name)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok("Lease ended")
 }
 
-#[derive(Debug, Deserialize, Default)]
-#[serde(rename_all = "lowercase")]
-pub enum DataFormat {
-    #[default]
-    Text,
-    Hex,
-    Base64,
+pub async fn get_write_lock(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<LockHolder>, ApiError> {
+    state
+        .sessions
+        .write_locks()
+        .holder(&name)
+        .await
+        .map(Json)
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Connection {} is not write-locked", name),
+            )
+        })
 }
 
-pub async fn list_connections(
+pub async fn release_write_lock(
     State(state): State<AppState>,
-) -> Result<Json<Vec<ConnectionListItem>>, ApiError> {
-    let connections = state.serial_manager.list_connections().await;
-    let items = connections
-        .into_iter()
-        .map(|name| ConnectionListItem { name })
-        .collect();
-    Ok(Json(items))
+    Path(name): Path<String>,
+) -> Result<Json<LockHolder>, ApiError> {
+    let holder = state
+        .sessions
+        .write_locks()
+        .release(&name)
+        .await
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Connection {} is not write-locked", name),
+            )
+        })?;
+    info!(
+        "Released the write lock of {} on {} on operator request",
+        holder.describe(),
+        name
+    );
+    Ok(Json(holder))
 }
 
-pub async fn get_connection_info(
+pub async fn list_captures(State(state): State<AppState>) -> Json<Vec<CaptureInfo>> {
+    Json(state.serial_manager.list_captures().await)
+}
+
+pub async fn start_capture(
+    State(state): State<AppState>,
+    Json(request): Json<CaptureRequest>,
+) -> Result<Json<CaptureInfo>, ApiError> {
+    request
+        .validate()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if state.serial_manager.list_captures().await.len() >= crate::serial::capture::MAX_CAPTURES {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            "Too many captures; delete finished ones first",
+        ));
+    }
+    let info = state.serial_manager.start_capture(&request).await?;
+    Ok(Json(info))
+}
+
+pub async fn get_capture(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<CaptureBundle>, ApiError> {
+    state
+        .serial_manager
+        .get_capture(id)
+        .await
+        .map(Json)
+        .ok_or_else(|| ApiError::new(StatusCode::NOT_FOUND, format!("Capture not found: {}", id)))
+}
+
+pub async fn stop_capture(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<&'static str, ApiError> {
+    state
+        .serial_manager
+        .stop_capture(id)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok("Capture stopped")
+}
+
+pub async fn delete_capture(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<&'static str, ApiError> {
+    state
+        .serial_manager
+        .delete_capture(id)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok("Capture deleted")
+}
+
+pub async fn list_sniffers(State(state): State<AppState>) -> Json<Vec<SnifferInfo>> {
+    Json(state.serial_manager.list_sniffers().await)
+}
+
+pub async fn create_sniffer(
+    State(state): State<AppState>,
+    Json(config): Json<SnifferConfig>,
+) -> Result<Json<SnifferInfo>, ApiError> {
+    config
+        .validate()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if state
+        .serial_manager
+        .list_sniffers()
+        .await
+        .iter()
+        .any(|sniffer| sniffer.name == config.name)
+    {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Sniffer already exists: {}", config.name),
+        ));
+    }
+    let info = state.serial_manager.add_sniffer(&config).await?;
+    Ok(Json(info))
+}
+
+pub async fn delete_sniffer(
     State(state): State<AppState>,
     Path(name): Path<String>,
-) -> Result<Json<ConnectionInfo>, ApiError> {
-    match state.serial_manager.get_connection(&name).await {
-        Some(connection) => {
-            let config = connection.config();
-            Ok(Json(ConnectionInfo {
-                name: config.name.clone(),
-                port: config.port.clone(),
-                baud_rate: config.baud_rate,
-                data_bits: format!("{}", match config.data_bits {
-                    crate::config::DataBits::Five => "5",
-                    crate::config::DataBits::Six => "6",
-                    crate::config::DataBits::Seven => "7",
-                    crate::config::DataBits::Eight => "8",
-                }),
-                stop_bits: format!("{}", match config.stop_bits {
-                    crate::config::StopBits::One => "1",
-                    crate::config::StopBits::Two => "2",
-                }),
-                parity: format!("{:?}", config.parity),
-            }))
+) -> Result<&'static str, ApiError> {
+    state
+        .serial_manager
+        .remove_sniffer(&name)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok("Sniffer removed")
+}
+
+/// Sent to sniffer watchers as a text message for each chunk on the link.
+#[derive(Debug, Serialize)]
+pub struct WsSnifferFrame<'a> {
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    #[serde(flatten)]
+    pub record: &'a SnifferRecord,
+}
+
+/// Streams a sniffer's merged records as JSON text messages until the
+/// sniffer is removed.
+pub async fn sniffer_websocket(
+    ws: WebSocketUpgrade,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    check_origin(&state, &headers, &name)?;
+    let mut records = state
+        .serial_manager
+        .subscribe_sniffer(&name)
+        .await
+        .ok_or_else(|| {
+            ApiError::new(
+                StatusCode::NOT_FOUND,
+                format!("Sniffer not found: {}", name),
+            )
+        })?;
+
+    Ok(ws.on_upgrade(move |socket| async move {
+        let (mut ws_sender, mut ws_receiver) = socket.split();
+        loop {
+            let message = tokio::select! {
+                result = records.recv() => match result {
+                    Ok(record) => {
+                        let frame = WsSnifferFrame { kind: "record", record: &record };
+                        Message::Text(serde_json::to_string(&frame).unwrap_or_default())
+                    }
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => Message::Text(
+                        serde_json::json!({ "type": "lagged", "skipped": skipped }).to_string(),
+                    ),
+                    Err(broadcast::error::RecvError::Closed) => {
+                        let _ = ws_sender
+                            .send(close_message(CloseReason::ConnectionRemoved))
+                            .await;
+                        return;
+                    }
+                },
+                // Watchers only listen; anything but a close is ignored
+                incoming = ws_receiver.next() => match incoming {
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => continue,
+                },
+            };
+            if ws_sender.send(message).await.is_err() {
+                return;
+            }
         }
-        None => {
-            // Return empty strings for non-existent connections
-            Ok(Json(ConnectionInfo {
-                name,
-                port: String::new(),
-                baud_rate: 0,
-                data_bits: String::new(),
-                stop_bits: String::new(),
-                parity: String::new(),
-            }))
+    }))
+}
+
+pub async fn list_virtual_pairs(State(state): State<AppState>) -> Json<Vec<VirtualPairInfo>> {
+    Json(state.serial_manager.list_virtual_pairs().await)
+}
+
+pub async fn create_virtual_pair(
+    State(state): State<AppState>,
+    Json(request): Json<CreateVirtualPairRequest>,
+) -> Result<Json<VirtualPairInfo>, ApiError> {
+    let CreateVirtualPairRequest {
+        pair: request,
+        expires_in,
+    } = request;
+    let expires_in = expires_in
+        .map(ephemeral::expires_in)
+        .transpose()
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if state
+        .serial_manager
+        .get_virtual_pair(&request.name)
+        .await
+        .is_some()
+    {
+        return Err(ApiError::new(
+            StatusCode::CONFLICT,
+            format!("Virtual pair {} already exists", request.name),
+        ));
+    }
+    let (a, b) = request.endpoints();
+    for name in [&a, &b] {
+        if state.serial_manager.get_connection(name).await.is_some() {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("Connection {} already exists", name),
+            ));
         }
     }
+
+    let mut info = state
+        .serial_manager
+        .add_virtual_pair(&request)
+        .await
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    if let Some(after) = expires_in {
+        info.expires_at = Some(
+            state
+                .serial_manager
+                .expire_virtual_pair(&info.name, after)
+                .await?,
+        );
+    }
+    Ok(Json(info))
 }
 
-pub async fn send_data(
+pub async fn delete_virtual_pair(
     State(state): State<AppState>,
     Path(name): Path<String>,
-    Json(request): Json<SendDataRequest>,
 ) -> Result<&'static str, ApiError> {
-    let data = match request.format {
-        DataFormat::Text => request.data.into_bytes(),
-        DataFormat::Hex => hex::decode(request.data.replace(" ", ""))
-            .map_err(|e| anyhow::anyhow!("Invalid hex data: {}", e))?,
-        DataFormat::Base64 => general_purpose::STANDARD
-            .decode(&request.data)
-            .map_err(|e| anyhow::anyhow!("Invalid base64 data: {}", e))?,
+    state
+        .serial_manager
+        .remove_virtual_pair(&name)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::NOT_FOUND, e.to_string()))?;
+    Ok("Virtual pair removed")
+}
+
+/// Connection counters, extracted metrics and histograms in the
+/// Prometheus text format, for scraping.
+pub async fn prometheus_metrics(State(state): State<AppState>) -> Response {
+    let snapshots = push::snapshot(&state.serial_manager).await;
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        push::exposition(&snapshots),
+    )
+        .into_response()
+}
+
+pub async fn list_sessions(
+    State(state): State<AppState>,
+    Query(query): Query<SessionsQuery>,
+) -> Json<Vec<SessionInfo>> {
+    Json(state.sessions.list(query.connection.as_deref()).await)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeliveriesQuery {
+    /// Only list deliveries to this channel.
+    #[serde(default)]
+    pub channel: Option<String>,
+    #[serde(default)]
+    pub state: Option<DeliveryState>,
+}
+
+pub async fn list_deliveries(
+    State(state): State<AppState>,
+    Query(query): Query<DeliveriesQuery>,
+) -> Json<Vec<Delivery>> {
+    let deliveries = state
+        .serial_manager
+        .notifier()
+        .deliveries()
+        .into_iter()
+        .filter(|delivery| {
+            query
+                .channel
+                .as_ref()
+                .is_none_or(|c| *c == delivery.channel)
+        })
+        .filter(|delivery| query.state.is_none_or(|s| s == delivery.state))
+        .collect();
+    Json(deliveries)
+}
+
+pub async fn create_silence(
+    State(state): State<AppState>,
+    Json(request): Json<SilenceRequest>,
+) -> Result<(StatusCode, Json<Silence>), ApiError> {
+    let silence = state
+        .serial_manager
+        .notifier()
+        .silences()
+        .add(request, Utc::now())
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    info!(
+        "Silenced alerts matching {} on {} until {}",
+        silence.pattern,
+        silence.connection.as_deref().unwrap_or("all connections"),
+        silence.expires_at
+    );
+    Ok((StatusCode::CREATED, Json(silence)))
+}
+
+pub async fn list_silences(State(state): State<AppState>) -> Json<Vec<Silence>> {
+    Json(state.serial_manager.notifier().silences().list(Utc::now()))
+}
+
+pub async fn delete_silence(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<&'static str, ApiError> {
+    if !state.serial_manager.notifier().silences().remove(id) {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            format!("Silence not found: {}", id),
+        ));
+    }
+    Ok("Silence removed")
+}
+
+pub async fn kick_session(
+    State(state): State<AppState>,
+    Path(id): Path<u64>,
+) -> Result<Json<SessionInfo>, ApiError> {
+    let session = state.sessions.kick(id).await.ok_or_else(|| {
+        ApiError::new(StatusCode::NOT_FOUND, format!("Session not found: {}", id))
+    })?;
+    info!(
+        "Kicked {} from {} on operator request",
+        session, session.connection
+    );
+    Ok(Json(session))
+}
+
+#[derive(Debug, Serialize)]
+pub struct StopResponse {
+    pub action: StopKind,
+}
+
+pub async fn shutdown_server(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<StopResponse>), ApiError> {
+    stop_server(state, headers, StopKind::Shutdown).await
+}
+
+pub async fn restart_server(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> Result<(StatusCode, Json<StopResponse>), ApiError> {
+    stop_server(state, headers, StopKind::Restart).await
+}
+
+/// Starts the same graceful shutdown as SIGTERM. The reply is sent
+/// before sessions are closed.
+async fn stop_server(
+    state: AppState,
+    headers: HeaderMap,
+    action: StopKind,
+) -> Result<(StatusCode, Json<StopResponse>), ApiError> {
+    let Some(ref control) = state.server.remote_control else {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            "Remote control is not enabled (server.remote_control)",
+        ));
+    };
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !lifecycle::authorized(&control.token_file, authorization).await? {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong bearer token",
+        ));
+    }
+    if !state.lifecycle.request(action) {
+        return Err(ApiError::new(StatusCode::CONFLICT, "Already shutting down"));
+    }
+    info!("{:?} requested through the API", action);
+    Ok((StatusCode::ACCEPTED, Json(StopResponse { action })))
+}
+
+/// The tunnel a hub agent dials (`server.hub`), bearer-token checked.
+pub async fn hub_connect(
+    ws: Result<WebSocketUpgrade, WebSocketUpgradeRejection>,
+    State(state): State<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    let Some(ref hub) = state.server.hub else {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "This server is not a hub (server.hub)",
+        ));
     };
+    let authorization = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+    if !lifecycle::authorized(&hub.token_file, authorization).await? {
+        return Err(ApiError::new(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong bearer token",
+        ));
+    }
+    let ws = ws.map_err(|rejection| ApiError::bad_request(rejection.body_text()))?;
+    let address =
+        connect_info.map(|ConnectInfo(peer)| state.proxies.client_address(peer.ip(), &headers));
+    let registry = state.hub.clone();
+    Ok(ws.on_upgrade(move |socket| async move { registry.serve(socket, address).await }))
+}
 
-    state.serial_manager.send_data(&name, &data).await?;
-    Ok("Data sent")
+pub async fn list_hub_agents(State(state): State<AppState>) -> Json<Vec<AgentInfo>> {
+    Json(state.hub.list().await)
 }
 
-pub async fn get_stats(
+/// Relays a request to the agent's own API, e.g.
+/// `GET /api/hub/agents/lab-3/api/connections`.
+pub async fn relay_to_agent(
+    State(state): State<AppState>,
+    Path((agent, path)): Path<(String, String)>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, ApiError> {
+    if state.server.hub.is_none() {
+        return Err(ApiError::new(
+            StatusCode::NOT_FOUND,
+            "This server is not a hub (server.hub)",
+        ));
+    }
+    let path = match uri.query() {
+        Some(query) => format!("/{}?{}", path, query),
+        None => format!("/{}", path),
+    };
+    let request = RelayRequest {
+        method,
+        path,
+        content_type: headers
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string),
+        body: body.to_vec(),
+    };
+    state.hub.relay(&agent, request).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogStreamQuery {
+    /// Least severe level sent: `error`, `warn`, `info` or `debug`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Only events whose target starts with this, e.g. `webmux::serial`.
+    #[serde(default)]
+    pub target: Option<String>,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+/// What became of each configured connection at startup, with the exact
+/// error of any that failed to open.
+pub async fn get_startup_report(State(state): State<AppState>) -> Json<StartupReport> {
+    Json(state.serial_manager.startup_report())
+}
+
+/// The server's own tracing events as Server-Sent Events, one `log`
+/// event per line of what would be logged, and a `lagged` event when a
+/// slow client misses some.
+pub async fn stream_server_logs(
+    Query(query): Query<LogStreamQuery>,
+) -> Result<Sse<impl futures::Stream<Item = Result<Event, std::convert::Infallible>>>, ApiError> {
+    let level: tracing::Level = query
+        .level
+        .parse()
+        .ok()
+        .filter(|level| *level != tracing::Level::TRACE)
+        .ok_or_else(|| ApiError::bad_request("level must be one of error, warn, info and debug"))?;
+    let rx = live::subscribe().ok_or_else(|| {
+        ApiError::new(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Live logs are not available in this process",
+        )
+    })?;
+
+    let wanted = move |event: &LogEvent| {
+        event.level <= level
+            && query
+                .target
+                .as_deref()
+                .is_none_or(|target| event.target.starts_with(target))
+    };
+    let events = futures::stream::unfold(rx, move |mut rx| {
+        let wanted = wanted.clone();
+        async move {
+            loop {
+                let event = match rx.recv().await {
+                    Ok(event) if wanted(&event) => Event::default().event("log").json_data(&event),
+                    Ok(_) => continue,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => Event::default()
+                        .event("lagged")
+                        .json_data(serde_json::json!({ "skipped": skipped })),
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                };
+                // Serializing these cannot fail
+                return Some((Ok(event.unwrap_or_default()), rx));
+            }
+        }
+    });
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+#[cfg(feature = "chaos")]
+async fn faults(state: &AppState, name: &str) -> Result<crate::serial::faults::Faults, ApiError> {
+    let connection = state
+        .serial_manager
+        .get_connection(name)
+        .await
+        .ok_or_else(|| anyhow::anyhow!("Connection not found: {}", name))?;
+    Ok(connection.faults().clone())
+}
+
+#[cfg(feature = "chaos")]
+pub async fn get_faults(
     State(state): State<AppState>,
     Path(name): Path<String>,
-) -> Result<Json<crate::serial::ConnectionStats>, ApiError> {
-    let stats = state.serial_manager.get_stats(&name).await?;
-    Ok(Json(stats))
+) -> Result<Json<FaultStatus>, ApiError> {
+    Ok(Json(faults(&state, &name).await?.status()))
+}
+
+#[cfg(feature = "chaos")]
+pub async fn set_faults(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(settings): Json<FaultSettings>,
+) -> Result<Json<FaultStatus>, ApiError> {
+    let faults = faults(&state, &name).await?;
+    faults
+        .set(settings)
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    warn!("Injecting faults into {}: {:?}", name, settings);
+    Ok(Json(faults.status()))
+}
+
+#[cfg(feature = "chaos")]
+pub async fn clear_faults(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<FaultStatus>, ApiError> {
+    let faults = faults(&state, &name).await?;
+    faults.clear();
+    info!("Cleared injected faults on {}", name);
+    Ok(Json(faults.status()))
+}
+
+#[cfg(feature = "chaos")]
+pub async fn inject_read_error(
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<FaultStatus>, ApiError> {
+    let faults = faults(&state, &name).await?;
+    faults.fail_next_read();
+    warn!("Injecting a read error into {}", name);
+    Ok(Json(faults.status()))
+}
+
+/// Close frame carrying one of the application close codes.
+fn close_message(reason: CloseReason) -> Message {
+    Message::Close(Some(CloseFrame {
+        code: reason.code(),
+        reason: reason.reason().into(),
+    }))
+}
+
+/// Refuses WebSocket upgrades from pages not in `allowed_origins`.
+fn check_origin(state: &AppState, headers: &HeaderMap, name: &str) -> Result<(), ApiError> {
+    // An unreadable Origin is refused rather than treated as absent
+    let origin = headers
+        .get(header::ORIGIN)
+        .map(|value| value.to_str().unwrap_or("invalid"));
+    let host = headers
+        .get(header::HOST)
+        .and_then(|value| value.to_str().ok());
+    if !origin::is_allowed(&state.websocket.allowed_origins, origin, host) {
+        warn!(
+            "Refused WebSocket upgrade for {} from origin {}",
+            name,
+            origin.unwrap_or_default()
+        );
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            format!(
+                "Origin {} may not open WebSocket sessions",
+                origin.unwrap_or_default()
+            ),
+        ));
+    }
+    Ok(())
 }
 
 pub async fn websocket_handler(
     ws: WebSocketUpgrade,
     State(state): State<AppState>,
     Path(name): Path<String>,
-) -> impl IntoResponse {
-    ws.on_upgrade(move |socket| websocket_connection(socket, state, name))
+    Query(query): Query<StreamQuery>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    check_origin(&state, &headers, &name)?;
+    let address =
+        connect_info.map(|ConnectInfo(peer)| state.proxies.client_address(peer.ip(), &headers));
+
+    let decimator = Decimator::from_query(query.sample.as_deref(), query.max_rate.as_deref())
+        .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    let client = match query.client {
+        Some(client) => Some(client),
+        None => headers
+            .get(sessions::CLIENT_HEADER)
+            .map(|value| {
+                value
+                    .to_str()
+                    .map(str::to_string)
+                    .map_err(|_| ApiError::bad_request("client label must be printable ASCII"))
+            })
+            .transpose()?,
+    };
+    if let Some(ref client) = client {
+        sessions::validate_client_label(client)
+            .map_err(|e| ApiError::bad_request(e.to_string()))?;
+    }
+
+    if query.direction == StreamDirection::Tx && (query.seq || query.since_seq.is_some()) {
+        return Err(ApiError::bad_request(
+            "seq and since_seq number received chunks, so need direction rx or both",
+        ));
+    }
+    if query.encoding == StreamEncoding::Hex || query.direction.tx() {
+        let mode = state
+            .serial_manager
+            .get_connection(&name)
+            .await
+            .map(|connection| connection.config().mode);
+        if mode == Some(ConnectionMode::Slcan) {
+            return Err(ApiError::bad_request(if query.direction.tx() {
+                "direction=tx and direction=both are not available on slcan connections"
+            } else {
+                "encoding=hex is not available on slcan connections"
+            }));
+        }
+    }
+
+    let caps = session_caps(&state, &name).await;
+    state
+        .sessions
+        .check_capacity(&name, address, caps)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::CONFLICT, e.to_string()))?;
+
+    let max_message = state.limits.max_ws_message_bytes;
+    Ok(ws
+        .max_message_size(max_message)
+        .max_frame_size(max_message)
+        .on_upgrade(move |socket| {
+            let span = info_span!(
+                "session",
+                connection = %name,
+                session = field::Empty,
+                client = field::Empty,
+                trace_id = field::Empty,
+                span_id = field::Empty,
+                bytes_rx = field::Empty,
+                bytes_tx = field::Empty,
+            );
+            websocket_connection(
+                socket,
+                state,
+                name,
+                decimator,
+                client,
+                address,
+                query.encoding,
+                query.priority,
+                query.mode,
+                query.direction,
+                query.share_input,
+                Resume {
+                    sequenced: query.seq || query.since_seq.is_some(),
+                    since: query.since_seq,
+                },
+            )
+            .instrument(span)
+        }))
+}
+
+/// Session caps for `connection`, from its own config and the server-wide one.
+async fn session_caps(state: &AppState, connection: &str) -> SessionCaps {
+    SessionCaps {
+        per_connection: state
+            .serial_manager
+            .get_connection(connection)
+            .await
+            .and_then(|connection| connection.config().max_clients),
+        total: state.websocket.max_clients,
+        per_address: state.websocket.max_clients_per_address,
+    }
 }
 
-async fn websocket_connection(ws: WebSocket, state: AppState, connection_name: String) {
-    info!("WebSocket connection established for {}", connection_name);
+/// Whether a session numbers the chunks it receives, and where it resumes.
+struct Resume {
+    sequenced: bool,
+    since: Option<u64>,
+}
 
+#[allow(clippy::too_many_arguments)]
+async fn websocket_connection(
+    ws: WebSocket,
+    state: AppState,
+    connection_name: String,
+    mut decimator: Option<Decimator>,
+    client: Option<String>,
+    address: Option<IpAddr>,
+    encoding: StreamEncoding,
+    priority: Priority,
+    session_mode: SessionMode,
+    direction: StreamDirection,
+    share_input: bool,
+    resume: Resume,
+) {
     let (mut ws_sender, mut ws_receiver) = ws.split();
 
+    let connection = state.serial_manager.get_connection(&connection_name).await;
+    // Both directions in the order they happened, for what is written and,
+    // with `both`, what is received. Taken first, so that it has every
+    // chunk the subscription's replay leaves out.
+    let mut tap = connection
+        .as_ref()
+        .filter(|_| direction.tx())
+        .map(|connection| connection.tap());
     // Subscribe to serial data
-    let mut serial_rx = match state.serial_manager.subscribe(&connection_name).await {
-        Ok(rx) => rx,
+    let subscription = state
+        .serial_manager
+        .subscribe_from(&connection_name, resume.since)
+        .await;
+    let (serial_rx, replay) = match subscription {
+        Ok(subscribed) => subscribed,
         Err(e) => {
             error!(
                 "Failed to subscribe to connection {}: {}",
                 connection_name, e
             );
-            let _ = ws_sender.send(Message::Text(format!("Error: {}", e))).await;
+            let _ = ws_sender
+                .send(WsErrorFrame::new("not_found", e.to_string()).into_message(encoding))
+                .await;
+            return;
+        }
+    };
+
+    // Checked again here: other clients may have attached since the upgrade
+    let caps = session_caps(&state, &connection_name).await;
+    let (session, mut kick_rx) = match state
+        .sessions
+        .register(
+            &connection_name,
+            client,
+            address,
+            session_mode == SessionMode::ReadOnly,
+            connection.as_ref().map(|connection| connection.trace()),
+            caps,
+        )
+        .await
+    {
+        Ok(registered) => registered,
+        Err(e) => {
+            warn!("Refused WebSocket session for {}: {}", connection_name, e);
+            let _ = ws_sender
+                .send(Message::Close(Some(CloseFrame {
+                    code: close_code::AGAIN,
+                    reason: "Too many clients".into(),
+                })))
+                .await;
             return;
         }
     };
+    let span = tracing::Span::current();
+    span.record("session", session.id);
+    if let Some(ref client) = session.client {
+        span.record("client", client.as_str());
+    }
+    if let Some(ref trace) = session.trace {
+        span.record("trace_id", trace.trace_id.as_str());
+        span.record("span_id", trace.span_id.as_str());
+    }
+    info!(
+        "WebSocket connection established for {} ({})",
+        connection_name, session
+    );
+
+    let (mode, suppress_echo, banner, write_lock, redact) = connection
+        .map(|connection| {
+            let config = connection.config();
+            (
+                config.mode,
+                config.suppress_echo,
+                config.banner.clone(),
+                config.write_lock.clone(),
+                config.logging.redact.clone(),
+            )
+        })
+        .unwrap_or_default();
+    let mut lock_rx = state.sessions.write_locks().subscribe();
+    // Sessions watching what is written see these already
+    let mut writes_rx = (share_input && direction == StreamDirection::Rx)
+        .then(|| state.sessions.writes().subscribe());
+    let session_id = session.id;
+
+    // Ahead of any traffic, so the client sees them first
+    let mut intro = Vec::new();
+    if let Some(banner) = banner {
+        intro.push(WsBannerFrame::new(banner).into_message(encoding));
+    }
+    if write_lock.is_some() {
+        if let Some(holder) = state.sessions.write_locks().holder(&connection_name).await {
+            intro.push(WsWriteLockFrame::held(holder).into_message(encoding));
+        }
+    }
+    match replay.gap {
+        Some(ReplayGap::Expired { first, last }) => intro.push(
+            WsErrorFrame::new(
+                "replay_incomplete",
+                format!("Chunks {} to {} are no longer buffered", first, last),
+            )
+            .into_message(encoding),
+        ),
+        Some(ReplayGap::Unknown) => intro.push(
+            WsErrorFrame::new(
+                "replay_incomplete",
+                format!(
+                    "Chunk {} was never sent on this connection, which may have been reopened; the next is {}",
+                    resume.since.unwrap_or_default(),
+                    replay.next_seq
+                ),
+            )
+            .into_message(encoding),
+        ),
+        None => {}
+    }
+    for message in intro {
+        if ws_sender.send(message).await.is_err() {
+            state.sessions.unregister(&session).await;
+            return;
+        }
+    }
+    // Shared by both tasks: writes are recorded, then their echo is dropped
+    let echo = (suppress_echo && mode == ConnectionMode::Raw)
+        .then(|| Arc::new(std::sync::Mutex::new(EchoFilter::new())));
+    let echo_clone = echo.clone();
+    // Shared too: prompts are seen by one task, the replies by the other.
+    // Validated with the configuration, so it only fails for broken input.
+    let redactor = redact
+        .as_ref()
+        .and_then(|redact| Redactor::new(redact).ok())
+        .map(|redactor| Arc::new(std::sync::Mutex::new(redactor)));
+    let redactor_clone = redactor.clone();
+    // What is written is shown to the session with replies to prompts
+    // scrubbed, as in the logs; fed both directions in order to spot them
+    let mut tx_redactor = tap
+        .as_ref()
+        .and(redact.as_ref())
+        .and_then(|redact| Redactor::new(redact).ok());
+    // The session's own writes, left out of what it is shown as written
+    let own_writes = (suppress_echo && tap.is_some())
+        .then(|| Arc::new(std::sync::Mutex::new(EchoFilter::new())));
+    let own_writes_clone = own_writes.clone();
 
     let serial_manager = state.serial_manager.clone();
     let connection_name_clone = connection_name.clone();
+    // Error frames from the receive task, which does not own the sender
+    let (error_tx, mut error_rx) = mpsc::channel::<WsErrorFrame>(16);
+
+    let lock_connection = connection_name.clone();
+    // Device bytes delivered to the client, and client bytes written to it
+    let bytes_rx = Arc::new(AtomicU64::new(0));
+    let bytes_tx = Arc::new(AtomicU64::new(0));
+    let bytes_rx_clone = bytes_rx.clone();
+    let bytes_tx_clone = bytes_tx.clone();
 
     // Task to forward serial data to WebSocket
     let mut send_task = tokio::spawn(async move {
-        while let Ok(data) = serial_rx.recv().await {
-            // Send as binary data
-            if ws_sender.send(Message::Binary(data.clone())).await.is_err() {
-                break;
+        let mut decoder = SlcanDecoder::new();
+        let mut backlog = VecDeque::from(replay.chunks);
+        let mut next_seq = replay.next_seq;
+        // With `both`, received data comes from the tap as well, so that
+        // it is interleaved with what is written as it happened
+        let rx_tapped = direction == StreamDirection::Both && tap.is_some();
+        let mut serial_rx = (!rx_tapped).then_some(serial_rx);
+
+        loop {
+            // Replayed chunks go out before anything received since
+            let (seq, data) = if let Some(chunk) = backlog.pop_front() {
+                chunk
+            } else {
+                tokio::select! {
+                    reason = &mut kick_rx => {
+                        let reason = reason.unwrap_or(CloseReason::Kicked);
+                        let _ = ws_sender.send(close_message(reason)).await;
+                        return;
+                    }
+                    Some(frame) = error_rx.recv() => {
+                        if ws_sender.send(frame.into_message(encoding)).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    event = lock_rx.recv() => {
+                        // Missed changes are covered by the next one
+                        if let Ok(event) = event {
+                            if event.connection == lock_connection
+                                && ws_sender.send(WsWriteLockFrame::from(event).into_message(encoding)).await.is_err()
+                            {
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    write = async { writes_rx.as_mut().expect("checked by the branch guard").recv().await }, if writes_rx.is_some() => {
+                        // Missed writes are not worth ending the session over
+                        if let Ok(write) = write {
+                            if write.connection == lock_connection
+                                && write.session != session_id
+                                && ws_sender.send(WsTxFrame::from(write).into_message(encoding)).await.is_err()
+                            {
+                                return;
+                            }
+                        }
+                        continue;
+                    }
+                    result = async { tap.as_mut().expect("checked by the branch guard").recv().await }, if tap.is_some() => {
+                        let traffic = match result {
+                            Ok(traffic) => traffic,
+                            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                                warn!("WebSocket subscriber lagged, skipped {} chunks of traffic", skipped);
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => {
+                                let _ = ws_sender
+                                    .send(close_message(CloseReason::ConnectionRemoved))
+                                    .await;
+                                return;
+                            }
+                        };
+                        if traffic.direction == Direction::Tx {
+                            let mut data = traffic.data;
+                            if let Some(ref own_writes) = own_writes {
+                                data = own_writes
+                                    .lock()
+                                    .unwrap()
+                                    .filter(&data, tokio::time::Instant::now());
+                            }
+                            // Without the session's own writes, so a reply it
+                            // typed may leave the redactor masking the next line
+                            if let Some(ref mut redactor) = tx_redactor {
+                                data = redactor.sent(&data);
+                            }
+                            if data.is_empty() {
+                                continue;
+                            }
+                            let message = match encoding {
+                                StreamEncoding::Binary => Message::Binary(data),
+                                StreamEncoding::Hex => Message::Text(hex::encode(data)),
+                                StreamEncoding::Mux => MuxChannel::Tx.message(&data),
+                            };
+                            if ws_sender.send(message).await.is_err() {
+                                return;
+                            }
+                            continue;
+                        }
+                        if let Some(ref mut redactor) = tx_redactor {
+                            redactor.received(&traffic.data);
+                        }
+                        // Chunks up to the replay's last were sent with it
+                        match traffic.seq {
+                            Some(seq) if rx_tapped && seq >= next_seq => {
+                                next_seq = seq + 1;
+                                (seq, traffic.data)
+                            }
+                            _ => continue,
+                        }
+                    }
+                    result = async { serial_rx.as_mut().expect("checked by the branch guard").recv().await }, if serial_rx.is_some() => match result {
+                        Ok(data) => {
+                            let seq = next_seq;
+                            next_seq += 1;
+                            (seq, data)
+                        }
+                        Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                            warn!("WebSocket subscriber lagged, skipped {} chunks", skipped);
+                            next_seq += skipped;
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => {
+                            let _ = ws_sender
+                                .send(close_message(CloseReason::ConnectionRemoved))
+                                .await;
+                            return;
+                        }
+                    },
+                }
+            };
+
+            if let Some(ref redactor) = redactor {
+                redactor.lock().unwrap().received(&data);
+            }
+            if !direction.rx() {
+                continue;
+            }
+            let data = match echo {
+                Some(ref echo) => {
+                    let data = echo
+                        .lock()
+                        .unwrap()
+                        .filter(&data, tokio::time::Instant::now());
+                    if data.is_empty() {
+                        continue;
+                    }
+                    data
+                }
+                None => data,
+            };
+            bytes_rx_clone.fetch_add(data.len() as u64, Ordering::Relaxed);
+
+            let raw = |data: Vec<u8>| match encoding {
+                StreamEncoding::Binary => Message::Binary(data),
+                StreamEncoding::Hex => Message::Text(hex::encode(data)),
+                StreamEncoding::Mux => MuxChannel::Data.message(&data),
+            };
+            let messages = match mode {
+                // Decimated raw streams are cut into whole lines first
+                ConnectionMode::Raw => match decimator {
+                    Some(ref mut decimator) => {
+                        decimator.split_lines(&data).into_iter().map(raw).collect()
+                    }
+                    None => vec![raw(data)],
+                },
+                // Send each decoded CAN frame as a JSON text message
+                ConnectionMode::Slcan => decoder
+                    .feed(&data)
+                    .iter()
+                    .filter_map(|frame| serde_json::to_string(frame).ok())
+                    .map(|json| match encoding {
+                        StreamEncoding::Mux => MuxChannel::Decoded.message(json.as_bytes()),
+                        StreamEncoding::Binary | StreamEncoding::Hex => Message::Text(json),
+                    })
+                    .collect(),
+            };
+
+            for message in messages {
+                if let Some(ref mut decimator) = decimator {
+                    if !decimator.allow(tokio::time::Instant::now()) {
+                        continue;
+                    }
+                }
+                if ws_sender.send(message).await.is_err() {
+                    return;
+                }
+            }
+            if resume.sequenced
+                && ws_sender
+                    .send(WsSeqFrame::new(seq).into_message(encoding))
+                    .await
+                    .is_err()
+            {
+                return;
             }
         }
-    });
+    }.instrument(span.clone()));
+
+    let sessions = state.sessions.clone();
+    let session_clone = session.clone();
 
     // Task to receive data from WebSocket and send to serial port
-    let mut recv_task = tokio::spawn(async move {
-        while let Some(Ok(msg)) = ws_receiver.next().await {
-            match msg {
-                Message::Binary(data) => {
-                    if let Err(e) = serial_manager
-                        .send_data(&connection_name_clone, &data)
-                        .await
-                    {
-                        error!("Failed to send data to serial port: {}", e);
-                        break;
+    let mut recv_task = tokio::spawn(
+        async move {
+            // Failed writes are reported to the client, which keeps reading
+            let report = |code: &'static str, message: String| {
+                let error_tx = error_tx.clone();
+                async move {
+                    let _ = error_tx.send(WsErrorFrame::new(code, message)).await;
+                }
+            };
+
+            while let Some(Ok(msg)) = ws_receiver.next().await {
+                if session_clone.read_only && !matches!(msg, Message::Close(_)) {
+                    if let Message::Binary(_) | Message::Text(_) = msg {
+                        report(
+                            "read_only",
+                            "Nothing was sent: the session is read-only".to_string(),
+                        )
+                        .await;
                     }
+                    continue;
                 }
-                Message::Text(text) => {
-                    let data = text.into_bytes();
-                    if let Err(e) = serial_manager
-                        .send_data(&connection_name_clone, &data)
+                // Mux messages become what they would be without the channel
+                let msg = match msg {
+                    Message::Binary(data) if encoding == StreamEncoding::Mux => {
+                        match data.split_first() {
+                            Some((&channel, payload)) if channel == MuxChannel::Data as u8 => {
+                                Message::Binary(payload.to_vec())
+                            }
+                            Some((&channel, payload))
+                                if channel == MuxChannel::Decoded as u8
+                                    && mode == ConnectionMode::Slcan =>
+                            {
+                                Message::Text(String::from_utf8_lossy(payload).into_owned())
+                            }
+                            _ => {
+                                let message = match data.first() {
+                                    Some(channel) => format!(
+                                        "Channel {} does not take messages on this connection",
+                                        channel
+                                    ),
+                                    None => "Empty message has no channel".to_string(),
+                                };
+                                report("invalid_frame", message).await;
+                                continue;
+                            }
+                        }
+                    }
+                    Message::Text(_) if encoding == StreamEncoding::Mux => {
+                        report(
+                            "invalid_frame",
+                            "Text messages are not used with encoding=mux".to_string(),
+                        )
+                        .await;
+                        continue;
+                    }
+                    other => other,
+                };
+                let data = match msg {
+                    Message::Binary(data) => data,
+                    Message::Text(text) if mode == ConnectionMode::Slcan => {
+                        let frame = serde_json::from_str::<SendCanFrameRequest>(&text)
+                            .map_err(anyhow::Error::from)
+                            .and_then(SendCanFrameRequest::into_frame);
+                        match frame {
+                            Ok(frame) => frame.encode().into_bytes(),
+                            Err(e) => {
+                                warn!("Ignoring invalid CAN frame from WebSocket: {}", e);
+                                report("invalid_frame", format!("Invalid CAN frame: {}", e)).await;
+                                continue;
+                            }
+                        }
+                    }
+                    Message::Text(text) if encoding == StreamEncoding::Hex => {
+                        let digits: String = text.split_whitespace().collect();
+                        match hex::decode(digits) {
+                            Ok(data) => data,
+                            Err(e) => {
+                                report("invalid_hex", format!("Invalid hex data: {}", e)).await;
+                                continue;
+                            }
+                        }
+                    }
+                    Message::Text(text) => text.into_bytes(),
+                    Message::Close(_) => {
+                        info!("WebSocket closed for {}", connection_name_clone);
+                        break;
+                    }
+                    _ => continue,
+                };
+
+                if let Some(ref write_lock) = write_lock {
+                    if let Err(holder) = sessions
+                        .write_locks()
+                        .acquire(&session_clone, write_lock.idle_release())
                         .await
                     {
-                        error!("Failed to send data to serial port: {}", e);
-                        break;
+                        report(
+                            "write_locked",
+                            format!(
+                                "{} bytes were not sent: {} holds the write lock",
+                                data.len(),
+                                holder.describe()
+                            ),
+                        )
+                        .await;
+                        continue;
                     }
                 }
-                Message::Close(_) => {
-                    info!("WebSocket closed for {}", connection_name_clone);
-                    break;
+                // What the audit log records and other sessions are shown
+                let scrubbed = match redactor_clone {
+                    Some(ref redactor) => redactor.lock().unwrap().sent(&data),
+                    None => data.clone(),
+                };
+                if !scrubbed.is_empty() {
+                    sessions
+                        .audit()
+                        .input(session_clone.audit(), &scrubbed)
+                        .await;
+                }
+                if let Some(ref echo) = echo_clone {
+                    echo.lock()
+                        .unwrap()
+                        .sent(&data, tokio::time::Instant::now());
+                }
+                if let Some(ref own_writes) = own_writes_clone {
+                    own_writes
+                        .lock()
+                        .unwrap()
+                        .sent(&data, tokio::time::Instant::now());
+                }
+                match serial_manager
+                    .send_data_with_priority(&connection_name_clone, &data, priority)
+                    .await
+                {
+                    Ok(()) => {
+                        bytes_tx_clone.fetch_add(data.len() as u64, Ordering::Relaxed);
+                        if !scrubbed.is_empty() {
+                            sessions.writes().wrote(&session_clone, scrubbed);
+                        }
+                    }
+                    Err(e) => {
+                        error!("Failed to send data to serial port: {}", e);
+                        report(
+                            "write_failed",
+                            format!("{} bytes were not sent: {}", data.len(), e),
+                        )
+                        .await;
+                    }
                 }
-                _ => {}
             }
         }
-    });
+        .instrument(span.clone()),
+    );
 
     // Wait for either task to finish
     tokio::select! {
@@ -195,5 +3044,11 @@ async fn websocket_connection(ws: WebSocket, state: AppState, connection_name: S
         _ = (&mut recv_task) => send_task.abort(),
     }
 
-    info!("WebSocket connection closed for {}", connection_name);
+    state.sessions.unregister(&session).await;
+    span.record("bytes_rx", bytes_rx.load(Ordering::Relaxed));
+    span.record("bytes_tx", bytes_tx.load(Ordering::Relaxed));
+    info!(
+        "WebSocket connection closed for {} ({})",
+        connection_name, session
+    );
 }
@@ -0,0 +1,496 @@
+//! Reverse tunnels to a central hub. A gateway behind NAT runs as an agent
+//! (`server.hub_agent`): it dials out to the hub over a WebSocket and
+//! registers its connections. The hub (`server.hub`) lists its agents and
+//! relays API requests to them over their tunnels, so no inbound firewall
+//! rules are needed at the gateways.
+//!
+//! Tunnel messages are JSON text frames tagged by `type`. The agent sends
+//! `register` first and whenever its connections change; the hub sends a
+//! `request` per relayed call and the agent answers with a `response`
+//! carrying the same `id`.
+
+use super::ApiError;
+use crate::config::HubAgentConfig;
+use crate::serial::SerialManager;
+use anyhow::Result;
+use axum::extract::ws::{Message, WebSocket};
+use axum::http::{header, HeaderValue, Method, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::Router;
+use base64::{engine::general_purpose, Engine as _};
+use chrono::{DateTime, Utc};
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, RwLock};
+use tokio_tungstenite::tungstenite;
+use tower::Service;
+use tracing::{info, warn};
+
+/// Longest accepted agent name.
+pub const MAX_AGENT_NAME: usize = 64;
+
+/// How long a relayed request may take before the caller gets `504`.
+pub const RELAY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Largest response body an agent relays; larger ones are answered with
+/// `502`.
+pub const MAX_RELAY_BODY_BYTES: usize = 8 * 1024 * 1024;
+
+/// How long an agent has to register once its tunnel is up.
+const REGISTER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often an agent checks whether its connections changed.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Longest wait between an agent's attempts to reach the hub.
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+/// Relayed requests queued for one agent before callers are turned away.
+const QUEUED_REQUESTS: usize = 64;
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum TunnelMessage {
+    Register {
+        name: String,
+        connections: Vec<String>,
+    },
+    Request {
+        id: u64,
+        method: String,
+        /// Path and query on the agent, e.g. `/api/connections?x=1`.
+        path: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
+        /// Base64.
+        #[serde(default)]
+        body: String,
+    },
+    Response {
+        id: u64,
+        status: u16,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        content_type: Option<String>,
+        /// Base64.
+        #[serde(default)]
+        body: String,
+    },
+}
+
+/// Agent names end up in URLs, so keep them to one short path segment.
+pub fn validate_agent_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.len() > MAX_AGENT_NAME {
+        anyhow::bail!(
+            "agent name must be between 1 and {} characters",
+            MAX_AGENT_NAME
+        );
+    }
+    if name.contains('/') || name.chars().any(char::is_control) {
+        anyhow::bail!("agent name must not contain '/' or control characters");
+    }
+    Ok(())
+}
+
+/// Checks an agent's settings; its token file is checked by the caller.
+pub fn validate_agent(config: &HubAgentConfig) -> Result<()> {
+    validate_agent_name(&config.name)?;
+    let url = reqwest::Url::parse(&config.url)
+        .map_err(|e| anyhow::anyhow!("invalid url {}: {}", config.url, e))?;
+    if !matches!(url.scheme(), "ws" | "wss") {
+        anyhow::bail!("url {} must start with ws:// or wss://", config.url);
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AgentInfo {
+    pub name: String,
+    pub connections: Vec<String>,
+    pub connected_at: DateTime<Utc>,
+    /// Where the agent's tunnel came from, behind any trusted proxies.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+}
+
+/// A request to relay, as the hub received it.
+pub struct RelayRequest {
+    pub method: Method,
+    pub path: String,
+    pub content_type: Option<String>,
+    pub body: Vec<u8>,
+}
+
+struct RelayedResponse {
+    status: u16,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+impl IntoResponse for RelayedResponse {
+    fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::BAD_GATEWAY);
+        let mut response = (status, self.body).into_response();
+        if let Some(value) = self
+            .content_type
+            .and_then(|content_type| HeaderValue::from_str(&content_type).ok())
+        {
+            response.headers_mut().insert(header::CONTENT_TYPE, value);
+        }
+        response
+    }
+}
+
+type Relay = (RelayRequest, oneshot::Sender<RelayedResponse>);
+
+struct Agent {
+    info: AgentInfo,
+    /// Tells this tunnel apart from a later one under the same name.
+    generation: u64,
+    requests: mpsc::Sender<Relay>,
+}
+
+/// The agents whose tunnels are up, on the hub.
+#[derive(Clone, Default)]
+pub struct HubRegistry {
+    agents: Arc<RwLock<HashMap<String, Agent>>>,
+}
+
+impl HubRegistry {
+    pub async fn list(&self) -> Vec<AgentInfo> {
+        let mut agents: Vec<AgentInfo> = self
+            .agents
+            .read()
+            .await
+            .values()
+            .map(|agent| agent.info.clone())
+            .collect();
+        agents.sort_by(|a, b| a.name.cmp(&b.name));
+        agents
+    }
+
+    /// Sends `request` down `agent`'s tunnel and waits for the answer.
+    pub async fn relay(&self, agent: &str, request: RelayRequest) -> Result<Response, ApiError> {
+        let requests = self
+            .agents
+            .read()
+            .await
+            .get(agent)
+            .map(|agent| agent.requests.clone())
+            .ok_or_else(|| {
+                ApiError::new(
+                    StatusCode::NOT_FOUND,
+                    format!("Agent {} is not connected", agent),
+                )
+            })?;
+        let (reply_tx, reply_rx) = oneshot::channel();
+        let lost = || {
+            ApiError::new(
+                StatusCode::BAD_GATEWAY,
+                format!("Agent {} disconnected before answering", agent),
+            )
+        };
+        requests
+            .try_send((request, reply_tx))
+            .map_err(|e| match e {
+                mpsc::error::TrySendError::Full(_) => ApiError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    format!("Agent {} has too many requests in flight", agent),
+                ),
+                mpsc::error::TrySendError::Closed(_) => lost(),
+            })?;
+        match tokio::time::timeout(RELAY_TIMEOUT, reply_rx).await {
+            Ok(Ok(response)) => Ok(response.into_response()),
+            Ok(Err(_)) => Err(lost()),
+            Err(_) => Err(ApiError::new(
+                StatusCode::GATEWAY_TIMEOUT,
+                format!(
+                    "Agent {} did not answer within {} seconds",
+                    agent,
+                    RELAY_TIMEOUT.as_secs()
+                ),
+            )),
+        }
+    }
+
+    /// Serves one agent's tunnel until it closes or the agent reconnects.
+    pub(crate) async fn serve(&self, socket: WebSocket, address: Option<IpAddr>) {
+        let (mut sender, mut receiver) = socket.split();
+        let registered = tokio::time::timeout(REGISTER_TIMEOUT, receiver.next()).await;
+        let (name, connections) = match registered {
+            Ok(Some(Ok(Message::Text(text)))) => match serde_json::from_str(&text) {
+                Ok(TunnelMessage::Register { name, connections }) => (name, connections),
+                _ => {
+                    warn!("Hub agent tunnel did not start with a register message");
+                    return;
+                }
+            },
+            _ => {
+                warn!("Hub agent tunnel closed before registering");
+                return;
+            }
+        };
+        if let Err(e) = validate_agent_name(&name) {
+            warn!("Refused hub agent {:?}: {}", name, e);
+            let _ = sender.send(Message::Close(None)).await;
+            return;
+        }
+
+        let generation = NEXT_GENERATION.fetch_add(1, Ordering::Relaxed);
+        let (requests_tx, mut requests_rx) = mpsc::channel::<Relay>(QUEUED_REQUESTS);
+        let agent = Agent {
+            info: AgentInfo {
+                name: name.clone(),
+                connections,
+                connected_at: Utc::now(),
+                address,
+            },
+            generation,
+            requests: requests_tx,
+        };
+        // A reconnecting agent replaces its old tunnel, which may be half dead
+        if self
+            .agents
+            .write()
+            .await
+            .insert(name.clone(), agent)
+            .is_some()
+        {
+            info!("Hub agent {} reconnected", name);
+        } else {
+            info!("Hub agent {} connected", name);
+        }
+
+        let mut pending: HashMap<u64, oneshot::Sender<RelayedResponse>> = HashMap::new();
+        let mut next_id: u64 = 1;
+        loop {
+            tokio::select! {
+                relay = requests_rx.recv() => {
+                    // Replaced by a newer tunnel
+                    let Some((request, reply)) = relay else { break };
+                    let id = next_id;
+                    next_id += 1;
+                    let message = TunnelMessage::Request {
+                        id,
+                        method: request.method.to_string(),
+                        path: request.path,
+                        content_type: request.content_type,
+                        body: general_purpose::STANDARD.encode(&request.body),
+                    };
+                    let text = serde_json::to_string(&message).expect("messages serialize");
+                    if sender.send(Message::Text(text)).await.is_err() {
+                        break;
+                    }
+                    // Callers that timed out no longer wait for their answer
+                    pending.retain(|_, reply| !reply.is_closed());
+                    pending.insert(id, reply);
+                }
+                message = receiver.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue,
+                    };
+                    match serde_json::from_str(&text) {
+                        Ok(TunnelMessage::Response { id, status, content_type, body }) => {
+                            let Some(reply) = pending.remove(&id) else { continue };
+                            let Ok(body) = general_purpose::STANDARD.decode(body) else {
+                                warn!("Hub agent {} sent an undecodable response body", name);
+                                continue;
+                            };
+                            let _ = reply.send(RelayedResponse { status, content_type, body });
+                        }
+                        Ok(TunnelMessage::Register { connections, .. }) => {
+                            if let Some(agent) = self.agents.write().await.get_mut(&name) {
+                                if agent.generation == generation {
+                                    agent.info.connections = connections;
+                                }
+                            }
+                        }
+                        _ => warn!("Hub agent {} sent an unexpected message", name),
+                    }
+                }
+            }
+        }
+
+        let mut agents = self.agents.write().await;
+        if agents
+            .get(&name)
+            .is_some_and(|agent| agent.generation == generation)
+        {
+            agents.remove(&name);
+            info!("Hub agent {} disconnected", name);
+        }
+    }
+}
+
+/// Keeps a tunnel from this gateway to the hub up, reconnecting with a
+/// growing delay, and answers the requests relayed over it with `app`.
+pub fn spawn_agent(app: Router, manager: SerialManager, config: HubAgentConfig) {
+    tokio::spawn(async move {
+        let mut delay = Duration::from_secs(1);
+        loop {
+            match run_agent(&app, &manager, &config, &mut delay).await {
+                Ok(()) => info!("Hub {} closed the tunnel", config.url),
+                Err(e) => warn!("Tunnel to hub {} failed: {}", config.url, e),
+            }
+            tokio::time::sleep(delay).await;
+            delay = (delay * 2).min(MAX_RECONNECT_DELAY);
+        }
+    });
+}
+
+async fn run_agent(
+    app: &Router,
+    manager: &SerialManager,
+    config: &HubAgentConfig,
+    delay: &mut Duration,
+) -> Result<()> {
+    use tungstenite::client::IntoClientRequest;
+
+    let token = tokio::fs::read_to_string(&config.token_file)
+        .await
+        .map_err(|e| {
+            anyhow::anyhow!(
+                "Cannot read token file {}: {}",
+                config.token_file.display(),
+                e
+            )
+        })?;
+    let mut request = config.url.as_str().into_client_request()?;
+    request.headers_mut().insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_str(&format!("Bearer {}", token.trim()))?,
+    );
+    let (socket, _) = tokio_tungstenite::connect_async(request).await?;
+    let (mut sender, mut receiver) = socket.split();
+
+    let registration = |connections: Vec<String>| {
+        let message = TunnelMessage::Register {
+            name: config.name.clone(),
+            connections,
+        };
+        tungstenite::Message::Text(serde_json::to_string(&message).expect("messages serialize"))
+    };
+    let mut connections = sorted_connections(manager).await;
+    sender.send(registration(connections.clone())).await?;
+    info!("Registered with hub {} as {}", config.url, config.name);
+    *delay = Duration::from_secs(1);
+
+    let (responses_tx, mut responses_rx) = mpsc::channel::<TunnelMessage>(QUEUED_REQUESTS);
+    let mut refresh = tokio::time::interval(REFRESH_INTERVAL);
+    refresh.tick().await;
+    loop {
+        tokio::select! {
+            message = receiver.next() => {
+                let text = match message {
+                    Some(Ok(tungstenite::Message::Text(text))) => text,
+                    Some(Ok(tungstenite::Message::Close(_))) | None => return Ok(()),
+                    Some(Err(e)) => return Err(e.into()),
+                    Some(Ok(_)) => continue,
+                };
+                match serde_json::from_str(&text) {
+                    Ok(TunnelMessage::Request { id, method, path, content_type, body }) => {
+                        let app = app.clone();
+                        let responses_tx = responses_tx.clone();
+                        tokio::spawn(async move {
+                            let response = answer(app, id, &method, &path, content_type, &body).await;
+                            let _ = responses_tx.send(response).await;
+                        });
+                    }
+                    _ => warn!("Hub {} sent an unexpected message", config.url),
+                }
+            }
+            Some(response) = responses_rx.recv() => {
+                let text = serde_json::to_string(&response).expect("messages serialize");
+                sender.send(tungstenite::Message::Text(text)).await?;
+            }
+            _ = refresh.tick() => {
+                let current = sorted_connections(manager).await;
+                if current != connections {
+                    connections = current;
+                    sender.send(registration(connections.clone())).await?;
+                }
+            }
+        }
+    }
+}
+
+async fn sorted_connections(manager: &SerialManager) -> Vec<String> {
+    let mut connections = manager.list_connections().await;
+    connections.sort();
+    connections
+}
+
+/// Runs one relayed request through the local API. Responses that take
+/// longer than [`RELAY_TIMEOUT`], such as streams, or are larger than
+/// [`MAX_RELAY_BODY_BYTES`] are answered with an error instead.
+pub(crate) async fn answer(
+    mut app: Router,
+    id: u64,
+    method: &str,
+    path: &str,
+    content_type: Option<String>,
+    body: &str,
+) -> TunnelMessage {
+    let failed = |status: StatusCode, error: String| TunnelMessage::Response {
+        id,
+        status: status.as_u16(),
+        content_type: Some("application/json".to_string()),
+        body: general_purpose::STANDARD.encode(serde_json::json!({ "error": error }).to_string()),
+    };
+    let Ok(body) = general_purpose::STANDARD.decode(body) else {
+        return failed(StatusCode::BAD_REQUEST, "Relayed body is not base64".into());
+    };
+    let mut request = axum::http::Request::builder().method(method).uri(path);
+    if let Some(content_type) = content_type {
+        request = request.header(header::CONTENT_TYPE, content_type);
+    }
+    let request = match request.body(axum::body::Body::from(body)) {
+        Ok(request) => request,
+        Err(e) => return failed(StatusCode::BAD_REQUEST, e.to_string()),
+    };
+    let answered = tokio::time::timeout(RELAY_TIMEOUT, async {
+        let response = match app.call(request).await {
+            Ok(response) => response,
+            Err(infallible) => match infallible {},
+        };
+        let status = response.status().as_u16();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let body = axum::body::to_bytes(response.into_body(), MAX_RELAY_BODY_BYTES).await;
+        (status, content_type, body)
+    })
+    .await;
+    match answered {
+        Ok((status, content_type, Ok(body))) => TunnelMessage::Response {
+            id,
+            status,
+            content_type,
+            body: general_purpose::STANDARD.encode(body),
+        },
+        Ok((_, _, Err(e))) => failed(
+            StatusCode::BAD_GATEWAY,
+            format!(
+                "Response not relayed, it may exceed {} bytes: {}",
+                MAX_RELAY_BODY_BYTES, e
+            ),
+        ),
+        Err(_) => failed(
+            StatusCode::GATEWAY_TIMEOUT,
+            format!(
+                "Response not complete within {} seconds; streams cannot be relayed",
+                RELAY_TIMEOUT.as_secs()
+            ),
+        ),
+    }
+}
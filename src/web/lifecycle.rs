@@ -0,0 +1,104 @@
+//! Stopping and restarting the process through the API
+//! (`server.remote_control`), for orchestrators that manage webmux over
+//! the network and cannot send it signals.
+
+use anyhow::Result;
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::watch;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StopKind {
+    /// Exit once shut down.
+    Shutdown,
+    /// Run again with the same arguments once shut down.
+    Restart,
+}
+
+/// Shared between the handlers that ask for a stop and `main`, which
+/// waits for one next to the signals.
+#[derive(Clone)]
+pub struct Lifecycle {
+    stop: Arc<watch::Sender<Option<StopKind>>>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self {
+            stop: Arc::new(watch::channel(None).0),
+        }
+    }
+}
+
+impl Lifecycle {
+    /// Asks for a stop; false if one was already asked for.
+    pub fn request(&self, kind: StopKind) -> bool {
+        self.stop.send_if_modified(|stop| {
+            if stop.is_some() {
+                return false;
+            }
+            *stop = Some(kind);
+            true
+        })
+    }
+
+    /// The stop asked for so far, if any.
+    pub fn requested(&self) -> Option<StopKind> {
+        *self.stop.borrow()
+    }
+
+    /// Waits until a stop is asked for.
+    pub async fn stopped(&self) -> StopKind {
+        let mut rx = self.stop.subscribe();
+        let stop = rx
+            .wait_for(Option::is_some)
+            .await
+            .expect("sender is owned by self");
+        stop.expect("waited for Some")
+    }
+}
+
+/// Whether `authorization`, an `Authorization` header value, carries the
+/// token in `token_file`. The file is read on every call, so the token can
+/// be rotated without a restart.
+pub async fn authorized(token_file: &Path, authorization: Option<&str>) -> Result<bool> {
+    let token = tokio::fs::read_to_string(token_file)
+        .await
+        .map_err(|e| anyhow::anyhow!("Cannot read token file {}: {}", token_file.display(), e))?;
+    let token = token.trim();
+    let Some(presented) = authorization.and_then(|value| value.strip_prefix("Bearer ")) else {
+        return Ok(false);
+    };
+    Ok(!token.is_empty() && constant_time_eq(token.as_bytes(), presented.trim().as_bytes()))
+}
+
+/// Compares without returning early, so the time taken does not tell how
+/// much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Replaces the process with a fresh copy of itself, as started. Only
+/// returns on failure.
+pub fn restart() -> Result<()> {
+    let exe = std::env::current_exe()?;
+    let args: Vec<_> = std::env::args_os().skip(1).collect();
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Listening sockets are close-on-exec, so the new copy can bind them again
+        let error = std::process::Command::new(&exe).args(&args).exec();
+        Err(anyhow::anyhow!(
+            "Cannot restart {}: {}",
+            exe.display(),
+            error
+        ))
+    }
+    #[cfg(not(unix))]
+    {
+        std::process::Command::new(&exe).args(&args).spawn()?;
+        std::process::exit(0);
+    }
+}
@@ -1,43 +1,322 @@
-use crate::serial::SerialManager;
+use crate::config::{LimitsConfig, ServerConfig, WebSocketConfig};
+use crate::serial::{OverallHealth, SerialManager};
 use axum::{
-    http::StatusCode,
+    extract::{DefaultBodyLimit, State},
+    http::{header, HeaderMap, StatusCode},
     response::{IntoResponse, Response},
-    routing::{get, post},
+    routing::{any, delete, get, post},
     Json, Router,
 };
+use hub::HubRegistry;
+use lifecycle::Lifecycle;
+use proxy::TrustedProxies;
 use serde::Serialize;
+use sessions::SessionRegistry;
 use tower_http::{cors::CorsLayer, services::ServeDir};
 
+pub mod admin;
+#[cfg(unix)]
+pub mod attach;
+pub mod close;
+pub mod decimation;
+pub mod echo;
 mod handlers;
+pub mod hub;
+pub mod lifecycle;
+pub mod openapi;
+pub mod origin;
+pub mod proxy;
+pub mod sessions;
+pub mod write_lock;
 pub use handlers::*;
 
 #[derive(Clone)]
 pub struct AppState {
     pub serial_manager: SerialManager,
+    pub limits: LimitsConfig,
+    pub websocket: WebSocketConfig,
+    pub sessions: SessionRegistry,
+    /// Server settings the process was started with, for export.
+    pub server: ServerConfig,
+    /// Where remote shutdown and restart requests go.
+    pub lifecycle: Lifecycle,
+    /// Proxies allowed to name the client with `X-Forwarded-For`.
+    pub proxies: TrustedProxies,
+    /// Agents tunnelled in, when this server is a hub.
+    pub hub: HubRegistry,
 }
 
 pub fn create_router(serial_manager: SerialManager) -> Router {
-    let state = AppState { serial_manager };
+    create_router_with_config(serial_manager, &ServerConfig::default())
+}
+
+/// Request body limit for `/send`: hex with separators takes three
+/// characters per byte, plus room for the JSON envelope.
+fn send_body_limit(max_send_bytes: usize) -> usize {
+    max_send_bytes.saturating_mul(3).saturating_add(1024)
+}
 
+/// Request body limit for `/sequence`: as for `/send`, with room for the
+/// envelope of every step.
+fn sequence_body_limit(max_send_bytes: usize) -> usize {
+    send_body_limit(max_send_bytes)
+        .saturating_add(crate::serial::sequence::MAX_STEPS.saturating_mul(256))
+}
+
+/// Router honouring the request limits and WebSocket settings in `config`.
+pub fn create_router_with_config(serial_manager: SerialManager, config: &ServerConfig) -> Router {
+    create_router_with_sessions(serial_manager, config, SessionRegistry::default())
+}
+
+/// Like [`create_router_with_config`], tracking WebSocket sessions in
+/// `sessions` so the caller can close them on shutdown.
+pub fn create_router_with_sessions(
+    serial_manager: SerialManager,
+    config: &ServerConfig,
+    sessions: SessionRegistry,
+) -> Router {
+    create_router_with_lifecycle(serial_manager, config, sessions, Lifecycle::default())
+}
+
+/// Like [`create_router_with_sessions`], passing remote shutdown and
+/// restart requests to `lifecycle`.
+pub fn create_router_with_lifecycle(
+    serial_manager: SerialManager,
+    config: &ServerConfig,
+    sessions: SessionRegistry,
+    lifecycle: Lifecycle,
+) -> Router {
+    let router = public_routes(&config.limits);
+    // Without a separate admin listener, everything is served here
+    let router = match config.admin {
+        Some(_) => router,
+        None => router.merge(admin_routes(&config.limits)),
+    };
+    router
+        // Serve static files
+        .nest_service("/static", ServeDir::new("static"))
+        .layer(CorsLayer::permissive())
+        .with_state(app_state(serial_manager, config, sessions, lifecycle))
+}
+
+/// Administrative endpoints alone, for the listener in `server.admin`.
+pub fn create_admin_router(
+    serial_manager: SerialManager,
+    config: &ServerConfig,
+    sessions: SessionRegistry,
+    lifecycle: Lifecycle,
+) -> Router {
+    admin_routes(&config.limits)
+        .route("/health", get(health_check))
+        .with_state(app_state(serial_manager, config, sessions, lifecycle))
+}
+
+fn app_state(
+    serial_manager: SerialManager,
+    config: &ServerConfig,
+    sessions: SessionRegistry,
+    lifecycle: Lifecycle,
+) -> AppState {
+    AppState {
+        serial_manager,
+        limits: config.limits.clone(),
+        websocket: config.websocket.clone(),
+        sessions,
+        server: config.clone(),
+        lifecycle,
+        // Checked when the configuration was validated
+        proxies: config
+            .proxy
+            .as_ref()
+            .and_then(|proxy| TrustedProxies::parse(&proxy.trusted).ok())
+            .unwrap_or_default(),
+        hub: HubRegistry::default(),
+    }
+}
+
+/// Endpoints that reconfigure the server or override other clients.
+fn admin_routes(limits: &LimitsConfig) -> Router<AppState> {
+    let router = Router::new()
+        // The running setup as a configuration file, and applying one
+        .route("/api/config/export", get(export_config))
+        .route("/api/config/import", post(import_config))
+        // Muting and unmuting alerts
+        .route("/api/alerts/silence", post(create_silence))
+        .route("/api/alerts/silences/:id", delete(delete_silence))
+        // Creating and removing null-modem pairs
+        .route("/api/virtual-pairs", post(create_virtual_pair))
+        .route("/api/virtual-pairs/:name", delete(delete_virtual_pair))
+        // Starting and stopping sniffers
+        .route("/api/sniffers", post(create_sniffer))
+        .route("/api/sniffers/:name", delete(delete_sniffer))
+        // Restart every connection on a multi-port adapter
+        .route("/api/port-groups/:id/restart", post(restart_port_group))
+        // Open a connection at runtime, one or many at once
+        .route("/api/connections", post(create_connection))
+        .route("/api/connections/bulk", post(create_connections_bulk))
+        // Try again to open a configured connection that failed
+        .route("/api/connections/:name/retry", post(retry_connection))
+        // Close and remove a connection
+        .route("/api/connections/:name", delete(delete_connection))
+        // Close a connection but keep it listed, and open it again
+        .route("/api/connections/:name/enable", post(enable_connection))
+        .route("/api/connections/:name/disable", post(disable_connection))
+        // Free a write lock held by another session
+        .route(
+            "/api/connections/:name/write-lock",
+            delete(release_write_lock),
+        )
+        // Switch the attached device on, off, or off and on
+        .route("/api/connections/:name/power/:action", post(power))
+        // Flash an ESP32/ESP8266 through the connection's port
+        .route(
+            "/api/connections/:name/flash/esp",
+            post(flash_esp).layer(DefaultBodyLimit::max(limits.max_upload_bytes)),
+        )
+        // Upload an Intel HEX sketch to an Arduino (STK500v1/v2) bootloader
+        .route(
+            "/api/connections/:name/flash/avr",
+            post(flash_avr).layer(DefaultBodyLimit::max(limits.max_upload_bytes)),
+        )
+        // Lend the port to an external tool for a bounded time
+        .route(
+            "/api/connections/:name/lease",
+            post(create_lease).delete(end_lease),
+        )
+        // Starting, stopping and deleting recordings
+        .route("/api/captures", post(start_capture))
+        .route("/api/captures/:id", delete(delete_capture))
+        .route("/api/captures/:id/stop", post(stop_capture))
+        // API requests relayed to an agent's gateway
+        .route("/api/hub/agents/:agent/*path", any(relay_to_agent))
+        // Disconnect a session
+        .route("/api/sessions/:id", delete(kick_session))
+        // Stop or restart the whole process, as on SIGTERM
+        .route("/api/admin/shutdown", post(shutdown_server))
+        .route("/api/admin/restart", post(restart_server))
+        // Which configured connections opened, failed or are still pending
+        .route("/api/admin/startup", get(get_startup_report))
+        // The server's own log output, live
+        .route("/api/admin/logs/stream", get(stream_server_logs));
+    // Fault injection, only in builds made for resilience testing
+    #[cfg(feature = "chaos")]
+    let router = router
+        .route(
+            "/api/connections/:name/faults",
+            get(get_faults).put(set_faults).delete(clear_faults),
+        )
+        .route(
+            "/api/connections/:name/faults/read-error",
+            post(inject_read_error),
+        );
+    router
+}
+
+/// Everything else: reading, streaming and writing to the devices.
+fn public_routes(limits: &LimitsConfig) -> Router<AppState> {
     Router::new()
         // Serve frontend at root
         .route("/", get(serve_index))
         // Health check
         .route("/health", get(health_check))
-        // List all connections
+        // Counters and histograms for Prometheus to scrape
+        .route("/metrics", get(prometheus_metrics))
+        // OpenAPI description of this API
+        .route("/api/openapi.json", get(openapi_spec))
+        // Server-wide totals and log disk usage
+        .route("/api/stats", get(get_server_stats))
+        // Today's usage of the daily byte quotas
+        .route("/api/quotas", get(list_quotas))
+        // What a restart from the configuration files would change
+        .route("/api/config/diff", get(get_config_diff))
+        // Recent notification deliveries and their retry state
+        .route("/api/notifications/deliveries", get(list_deliveries))
+        // Temporarily muted alerts
+        .route("/api/alerts/silences", get(list_silences))
+        // Regex search across connection logs
+        .route("/api/search", get(search_logs))
+        // Time-correlated recordings of several connections
+        .route("/api/captures", get(list_captures))
+        .route("/api/captures/:id", get(get_capture))
+        // In-memory null-modem pairs
+        .route("/api/virtual-pairs", get(list_virtual_pairs))
+        // Two lines of a link merged by direction, and watching them live
+        .route("/api/sniffers", get(list_sniffers))
+        .route("/api/sniffers/:name/ws", get(sniffer_websocket))
+        // Serial ports present on the host
+        .route("/api/ports", get(list_ports))
+        // Ports grouped by the multi-port adapter they belong to
+        .route("/api/port-groups", get(list_port_groups))
+        // List connections
         .route("/api/connections", get(list_connections))
         // Get connection info
         .route("/api/connections/:name", get(get_connection_info))
         // Send data to a connection
-        .route("/api/connections/:name/send", post(send_data))
+        .route(
+            "/api/connections/:name/send",
+            post(send_data).layer(DefaultBodyLimit::max(send_body_limit(
+                limits.max_send_bytes,
+            ))),
+        )
+        // Steps written with no other client's writes in between
+        .route(
+            "/api/connections/:name/sequence",
+            post(run_sequence).layer(DefaultBodyLimit::max(sequence_body_limit(
+                limits.max_send_bytes,
+            ))),
+        )
+        // Write a file to the port on the bulk queue
+        .route(
+            "/api/connections/:name/upload",
+            post(upload_data).layer(DefaultBodyLimit::max(limits.max_upload_bytes)),
+        )
         // Get connection stats
         .route("/api/connections/:name/stats", get(get_stats))
+        // Rolling traffic rates
+        .route("/api/connections/:name/throughput", get(get_throughput))
+        // Recent traffic, one record per frame
+        .route("/api/connections/:name/tail", get(get_tail))
+        // Frame size and spacing distributions
+        .route(
+            "/api/connections/:name/traffic-profile",
+            get(get_traffic_profile),
+        )
+        // Recent traffic as a `hexdump -C` style dump
+        .route("/api/connections/:name/hexdump", get(get_hexdump))
+        // Numbered received chunks, paged by sequence number
+        .route("/api/connections/:name/frames", get(get_frames))
+        // The same, waiting for new chunks, for clients without WebSockets
+        .route("/api/connections/:name/poll", get(poll_frames))
+        // Chunks since a named reader's last call, advancing its cursor
+        .route("/api/connections/:name/read", get(read_frames))
+        // Notes pinned to points in the received data
+        .route(
+            "/api/connections/:name/annotations",
+            get(list_annotations).post(create_annotation),
+        )
+        // Round-trip timing through a loopback-capable device
+        .route("/api/connections/:name/latency-test", post(latency_test))
+        // Values extracted from received lines
+        .route("/api/connections/:name/metrics", get(list_metrics))
+        .route(
+            "/api/connections/:name/metrics/:metric",
+            get(get_metric_samples),
+        )
+        // Send a CAN frame through an SLCAN adapter
+        .route("/api/connections/:name/can/send", post(send_can_frame))
+        // Whether the port is lent to an external tool
+        .route("/api/connections/:name/lease", get(get_lease))
+        // Which session may write to a write_lock connection
+        .route("/api/connections/:name/write-lock", get(get_write_lock))
+        // Values declared in the config as a command and a reply pattern
+        .route("/api/connections/:name/:endpoint", get(query_endpoint))
+        // Open WebSocket sessions
+        .route("/api/sessions", get(list_sessions))
         // WebSocket for streaming data
         .route("/api/connections/:name/ws", get(websocket_handler))
-        // Serve static files
-        .nest_service("/static", ServeDir::new("static"))
-        .layer(CorsLayer::permissive())
-        .with_state(state)
+        // Reverse tunnels from gateways behind NAT
+        .route("/api/hub/connect", get(hub_connect))
+        .route("/api/hub/agents", get(list_hub_agents))
 }
 
 async fn serve_index() -> impl IntoResponse {
@@ -47,18 +326,66 @@ async fn serve_index() -> impl IntoResponse {
     }
 }
 
-async fn health_check() -> &'static str {
-    "OK"
+/// `OK`, or with `Accept: application/json` a summary of connection
+/// health that is `503` once every connection is down.
+async fn health_check(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    let wants_json = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains("application/json"));
+    if !wants_json {
+        return "OK".into_response();
+    }
+
+    let report = state.serial_manager.health().await;
+    let status = match report.status {
+        OverallHealth::Down => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+    (status, Json(report)).into_response()
+}
+
+async fn openapi_spec() -> Json<serde_json::Value> {
+    Json(openapi::spec())
 }
 
 #[derive(Debug, Serialize)]
 pub struct ApiError {
     pub error: String,
+    #[serde(skip)]
+    pub status: StatusCode,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, error: impl Into<String>) -> Self {
+        ApiError {
+            error: error.into(),
+            status,
+        }
+    }
+
+    pub fn bad_request(error: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, error)
+    }
+
+    pub fn too_large(error: impl Into<String>) -> Self {
+        Self::new(StatusCode::PAYLOAD_TOO_LARGE, error)
+    }
+
+    /// A request body the extractor refused; oversized ones get a message
+    /// naming the limit instead of axum's generic text.
+    pub(crate) fn rejected_body(status: StatusCode, detail: String, limit: usize) -> Self {
+        if status == StatusCode::PAYLOAD_TOO_LARGE {
+            Self::too_large(format!("Request body exceeds the {} byte limit", limit))
+        } else {
+            Self::new(status, detail)
+        }
+    }
 }
 
 impl IntoResponse for ApiError {
     fn into_response(self) -> Response {
-        (StatusCode::INTERNAL_SERVER_ERROR, Json(self)).into_response()
+        (self.status, Json(self)).into_response()
     }
 }
 
@@ -66,6 +393,7 @@ impl From<anyhow::Error> for ApiError {
     fn from(err: anyhow::Error) -> Self {
         ApiError {
             error: err.to_string(),
+            status: StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
 }
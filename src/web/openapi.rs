@@ -0,0 +1,1667 @@
+//! OpenAPI 3.0 description of the HTTP and WebSocket API, served at
+//! `/api/openapi.json` and printed by `webmux openapi dump` so client SDKs
+//! can be generated from it.
+//!
+//! Written by hand alongside the handlers; update it when routes or
+//! response types change.
+
+use serde_json::{json, Map, Value};
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn array_of(name: &str) -> Value {
+    json!({ "type": "array", "items": schema_ref(name) })
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({ "application/json": { "schema": schema } })
+}
+
+fn ok_json(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": json_body(schema) })
+}
+
+fn ok_text(description: &str) -> Value {
+    json!({
+        "description": description,
+        "content": { "text/plain": { "schema": { "type": "string" } } }
+    })
+}
+
+fn error(description: &str) -> Value {
+    ok_json(description, schema_ref("Error"))
+}
+
+fn path_param(name: &str, description: &str, schema: Value) -> Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "description": description,
+        "schema": schema
+    })
+}
+
+fn query_param(name: &str, description: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "description": description, "schema": schema })
+}
+
+fn connection_param() -> Value {
+    path_param("name", "Connection name", json!({ "type": "string" }))
+}
+
+fn operation(tag: &str, summary: &str, parameters: Vec<Value>, responses: Value) -> Value {
+    let mut operation = json!({
+        "tags": [tag],
+        "summary": summary,
+        "responses": responses
+    });
+    if !parameters.is_empty() {
+        operation["parameters"] = Value::Array(parameters);
+    }
+    operation
+}
+
+fn with_body(mut operation: Value, content: Value) -> Value {
+    operation["requestBody"] = json!({ "required": true, "content": content });
+    operation
+}
+
+fn object(properties: Value, required: &[&str]) -> Value {
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+fn schemas() -> Value {
+    let nullable_string = json!({ "type": "string", "nullable": true });
+    let nullable_number = json!({ "type": "number", "nullable": true });
+    let session_trace = object(
+        json!({
+            "trace_id": { "type": "string", "description": "The connection's trace, 32 hex digits" },
+            "span_id": { "type": "string", "description": "The session's span, 16 hex digits" }
+        }),
+        &["trace_id", "span_id"],
+    );
+    let mut schemas = json!({
+        "Error": object(json!({ "error": { "type": "string" } }), &["error"]),
+        "ConnectionListItem": object(json!({
+            "name": { "type": "string" },
+            "state": { "type": "string", "enum": ["open", "failed", "disabled"], "description": "`failed` for a configured connection that could not be opened, `disabled` for one closed with its settings kept" },
+            "error": { "type": "string", "description": "Why a failed connection could not be opened" },
+            "expires_at": { "type": "string", "format": "date-time" }
+        }), &["name", "state"]),
+        "ConnectionRequest": object(json!({
+            "name": { "type": "string" },
+            "port": { "type": "string", "description": "Device path, e.g. `/dev/ttyUSB0`" },
+            "profile": { "type": "string", "description": "Device preset, e.g. `cisco-console`, filling in the serial settings left out" },
+            "baud_rate": { "type": "integer", "default": 115200 },
+            "data_bits": { "type": "string", "enum": ["5", "6", "7", "8"], "default": "8" },
+            "stop_bits": { "type": "string", "enum": ["1", "2"], "default": "1" },
+            "parity": { "type": "string", "enum": ["none", "odd", "even"], "default": "none" },
+            "flow_control": { "type": "string", "enum": ["none", "software", "hardware"], "default": "none" },
+            "description": { "type": "string" },
+            "banner": { "type": "string", "description": "Shown to WebSocket clients when they attach" },
+            "expires_in": { "type": "integer", "minimum": 1, "maximum": 86400, "description": "Seconds until the connection is closed and removed" }
+        }), &["name", "port"]),
+        "BulkConnectionRequest": object(json!({
+            "template": {
+                "type": "object",
+                "description": "A ConnectionRequest without `port`; `{index}` (from 1) and `{port}` (the port's file name) in `name` and `description` are filled in per port",
+                "properties": {
+                    "name": { "type": "string", "example": "rack3-{index}" },
+                    "profile": { "type": "string" },
+                    "baud_rate": { "type": "integer", "default": 115200 },
+                    "data_bits": { "type": "string", "enum": ["5", "6", "7", "8"], "default": "8" },
+                    "stop_bits": { "type": "string", "enum": ["1", "2"], "default": "1" },
+                    "parity": { "type": "string", "enum": ["none", "odd", "even"], "default": "none" },
+                    "flow_control": { "type": "string", "enum": ["none", "software", "hardware"], "default": "none" },
+                    "description": { "type": "string" },
+                    "banner": { "type": "string" },
+                    "expires_in": { "type": "integer", "minimum": 1, "maximum": 86400 }
+                },
+                "required": ["name"]
+            },
+            "ports": { "type": "array", "items": { "type": "string" }, "maxItems": 256 },
+            "glob": { "type": "string", "description": "Matched against the paths and by-id links of the ports present, e.g. `/dev/ttyUSB*`; instead of `ports`" }
+        }), &["template"]),
+        "BulkConnectionReport": object(json!({
+            "created": array_of("ConnectionListItem"),
+            "failed": { "type": "array", "items": object(json!({
+                "name": { "type": "string" },
+                "port": { "type": "string" },
+                "error": { "type": "string" }
+            }), &["name", "port", "error"]) }
+        }), &["created", "failed"]),
+        "ConnectionInfo": object(json!({
+            "name": { "type": "string" },
+            "port": { "type": "string" },
+            "baud_rate": { "type": "integer" },
+            "data_bits": { "type": "string" },
+            "stop_bits": { "type": "string" },
+            "parity": { "type": "string" },
+            "friendly_name": nullable_string,
+            "description": nullable_string,
+            "expires_at": { "type": "string", "format": "date-time" },
+            "identity": {
+                "allOf": [schema_ref("DeviceIdentity")],
+                "nullable": true,
+                "description": "`null` until a connection with `identify` has identified its device"
+            },
+            "error": { "type": "string", "description": "Why a configured connection could not be opened" }
+        }), &["name", "port", "baud_rate", "data_bits", "stop_bits", "parity"]),
+        "DeviceIdentity": object(json!({
+            "model": nullable_string,
+            "firmware": nullable_string,
+            "source": { "type": "string", "enum": ["probe", "usb"] },
+            "identified_at": { "type": "string", "format": "date-time" }
+        }), &["source", "identified_at"]),
+        "ConnectionStats": object(json!({
+            "name": { "type": "string" },
+            "port": { "type": "string" },
+            "bytes_received": { "type": "integer", "format": "int64" },
+            "bytes_sent": { "type": "integer", "format": "int64" },
+            "is_connected": { "type": "boolean" },
+            "port_open": { "type": "boolean" },
+            "uptime_seconds": { "type": "integer", "format": "int64" },
+            "line_errors": {
+                "allOf": [schema_ref("LineErrors")],
+                "nullable": true,
+                "description": "`null` where the driver does not count line errors"
+            },
+            "keepalive": {
+                "allOf": [schema_ref("KeepaliveStats")],
+                "nullable": true,
+                "description": "`null` unless the connection has a `keepalive`"
+            },
+            "reconnect": {
+                "allOf": [schema_ref("ReconnectStats")],
+                "nullable": true,
+                "description": "`null` unless the connection has `reconnect`"
+            },
+            "rules": {
+                "type": "array",
+                "items": schema_ref("RuleStats"),
+                "description": "Left out unless the connection has `rules`"
+            }
+        }), &["name", "port", "bytes_received", "bytes_sent", "is_connected", "port_open", "uptime_seconds"]),
+        "HealthReport": object(json!({
+            "status": { "type": "string", "enum": ["ok", "degraded", "down"] },
+            "connected": { "type": "integer" },
+            "degraded": { "type": "integer" },
+            "disconnected": { "type": "integer" },
+            "problems": array_of("ConnectionHealth")
+        }), &["status", "connected", "degraded", "disconnected", "problems"]),
+        "ConnectionHealth": object(json!({
+            "name": { "type": "string" },
+            "state": { "type": "string", "enum": ["degraded", "disconnected"] },
+            "reason": { "type": "string" }
+        }), &["name", "state", "reason"]),
+        "Delivery": object(json!({
+            "id": { "type": "integer", "format": "int64" },
+            "channel": { "type": "string" },
+            "title": { "type": "string" },
+            "connection": { "type": "string" },
+            "state": { "type": "string", "enum": ["pending", "delivered", "failed"] },
+            "attempts": { "type": "integer" },
+            "last_error": { "type": "string" },
+            "created_at": { "type": "string", "format": "date-time" },
+            "updated_at": { "type": "string", "format": "date-time" }
+        }), &["id", "channel", "title", "state", "attempts", "created_at", "updated_at"]),
+        "SilenceRequest": object(json!({
+            "connection": { "type": "string", "description": "Only this connection's alerts; all connections if unset" },
+            "pattern": { "type": "string", "description": "Regex searched for in alert names" },
+            "duration": { "type": "integer", "minimum": 1, "maximum": 604800, "description": "Seconds until the silence ends" },
+            "comment": { "type": "string" }
+        }), &["pattern", "duration"]),
+        "Silence": object(json!({
+            "id": { "type": "integer", "format": "int64" },
+            "connection": { "type": "string" },
+            "pattern": { "type": "string" },
+            "comment": { "type": "string" },
+            "created_at": { "type": "string", "format": "date-time" },
+            "expires_at": { "type": "string", "format": "date-time" }
+        }), &["id", "pattern", "created_at", "expires_at"]),
+        "ServerStats": object(json!({
+            "connections": { "type": "integer" },
+            "bytes_received": { "type": "integer", "format": "int64" },
+            "bytes_sent": { "type": "integer", "format": "int64" },
+            "log_disk_usage": object(json!({
+                "total_bytes": { "type": "integer", "format": "int64" },
+                "connections": {
+                    "type": "array",
+                    "items": object(json!({
+                        "name": { "type": "string" },
+                        "bytes": { "type": "integer", "format": "int64" },
+                        "files": { "type": "integer" }
+                    }), &["name", "bytes", "files"])
+                }
+            }), &["total_bytes", "connections"])
+        }), &["connections", "bytes_received", "bytes_sent", "log_disk_usage"]),
+        "PortDescription": object(json!({
+            "path": { "type": "string" },
+            "kind": { "type": "string", "enum": ["usb", "pci", "bluetooth", "unknown"] },
+            "friendly_name": nullable_string,
+            "description": nullable_string,
+            "manufacturer": nullable_string,
+            "serial_number": nullable_string,
+            "vid": nullable_string,
+            "pid": nullable_string,
+            "by_id": nullable_string,
+            "usb_path": { "type": "string", "nullable": true, "description": "Bus and hub ports of the USB adapter, e.g. `1-2.3` (Linux only)" }
+        }), &["path", "kind"]),
+        "PortGroup": object(json!({
+            "id": { "type": "string", "example": "usb-1-2" },
+            "usb_path": { "type": "string", "description": "USB device or hub the ports share" },
+            "description": nullable_string,
+            "ports": { "type": "array", "items": { "type": "string" } },
+            "connections": { "type": "array", "items": { "type": "string" }, "description": "Connections open on the ports" }
+        }), &["id", "usb_path", "description", "ports", "connections"]),
+        "RestartReport": object(json!({
+            "restarted": { "type": "array", "items": { "type": "string" } },
+            "failed": { "type": "array", "items": object(json!({
+                "name": { "type": "string" },
+                "error": { "type": "string" }
+            }), &["name", "error"]) }
+        }), &["restarted", "failed"]),
+        "SendDataRequest": object(json!({
+            "data": { "type": "string" },
+            "format": { "type": "string", "enum": ["text", "hex", "base64"], "default": "text" },
+            "priority": { "type": "string", "enum": ["interactive", "bulk"], "default": "interactive" },
+            "ack": {
+                "type": "string",
+                "enum": ["queued", "written"],
+                "default": "queued",
+                "description": "Answer once the data is queued, or once it has been written to the port"
+            }
+        }), &["data"]),
+        "Throughput": object(json!({
+            "window_seconds": { "type": "integer" },
+            "rx_bytes_per_sec": { "type": "number" },
+            "tx_bytes_per_sec": { "type": "number" },
+            "rx_frames_per_sec": { "type": "number" },
+            "tx_frames_per_sec": { "type": "number" }
+        }), &["window_seconds", "rx_bytes_per_sec", "tx_bytes_per_sec", "rx_frames_per_sec", "tx_frames_per_sec"]),
+        "LatencyTestOptions": object(json!({
+            "count": { "type": "integer", "default": 10 },
+            "timeout_ms": { "type": "integer", "default": 1000 },
+            "interval_ms": { "type": "integer", "default": 0 }
+        }), &[]),
+        "LatencyReport": object(json!({
+            "sent": { "type": "integer" },
+            "received": { "type": "integer" },
+            "lost": { "type": "integer" },
+            "min_ms": nullable_number,
+            "avg_ms": nullable_number,
+            "p50_ms": nullable_number,
+            "p95_ms": nullable_number,
+            "p99_ms": nullable_number,
+            "max_ms": nullable_number,
+            "samples_ms": { "type": "array", "items": nullable_number }
+        }), &["sent", "received", "lost", "samples_ms"]),
+        "Sample": object(json!({
+            "timestamp": { "type": "string", "format": "date-time" },
+            "value": { "type": "number" }
+        }), &["timestamp", "value"]),
+        "MetricSummary": object(json!({
+            "name": { "type": "string" },
+            "pattern": { "type": "string" },
+            "samples": { "type": "integer" },
+            "latest": { "allOf": [schema_ref("Sample")], "nullable": true },
+            "alerts": {
+                "type": "array",
+                "items": object(json!({
+                    "name": { "type": "string" },
+                    "firing": { "type": "boolean" },
+                    "above": { "type": "number" },
+                    "below": { "type": "number" },
+                    "samples": { "type": "integer" }
+                }), &["name", "firing", "samples"])
+            }
+        }), &["name", "pattern", "samples", "alerts"]),
+        "CanFrame": object(json!({
+            "id": { "type": "integer" },
+            "extended": { "type": "boolean", "default": false },
+            "rtr": { "type": "boolean", "default": false },
+            "dlc": { "type": "integer", "minimum": 0, "maximum": 8 },
+            "data": { "type": "string", "description": "Payload as hex" },
+            "timestamp": { "type": "integer", "description": "Adapter timestamp in milliseconds" }
+        }), &["id", "dlc"]),
+        "SendCanFrameRequest": object(json!({
+            "id": { "type": "integer" },
+            "extended": { "type": "boolean", "default": false },
+            "rtr": { "type": "boolean", "default": false },
+            "dlc": { "type": "integer", "minimum": 0, "maximum": 8, "description": "Defaults to the payload length" },
+            "data": { "type": "string", "description": "Payload as hex, spaces allowed", "default": "" }
+        }), &["id"]),
+        "FlashProgress": object(json!({
+            "stage": {
+                "type": "string",
+                "enum": ["resetting", "syncing", "erasing", "writing", "verifying", "done", "error"]
+            },
+            "bytes": { "type": "integer" },
+            "written": { "type": "integer" },
+            "verified": { "type": "integer" },
+            "total": { "type": "integer" },
+            "message": { "type": "string" }
+        }), &["stage"]),
+        "LeaseRequest": object(json!({
+            "mode": { "type": "string", "enum": ["release", "tcp"], "default": "release" },
+            "duration_secs": { "type": "integer", "default": 300, "maximum": 3600 }
+        }), &[]),
+        "LeaseInfo": object(json!({
+            "connection": { "type": "string" },
+            "mode": { "type": "string", "enum": ["release", "tcp"] },
+            "expires_at": { "type": "string", "format": "date-time" },
+            "device": { "type": "string" },
+            "tcp_address": { "type": "string" }
+        }), &["connection", "mode", "expires_at"]),
+        "SearchResults": object(json!({
+            "records": {
+                "type": "array",
+                "items": object(json!({
+                    "timestamp": { "type": "string", "format": "date-time" },
+                    "connection": { "type": "string" },
+                    "direction": { "type": "string", "enum": ["rx", "tx"] },
+                    "text": { "type": "string" },
+                    "hex": { "type": "string" }
+                }), &["timestamp", "connection", "direction", "text", "hex"])
+            },
+            "next_offset": { "type": "integer", "nullable": true, "description": "Offset of the next page, if there are more matches" }
+        }), &["records", "next_offset"]),
+        "VirtualPairRequest": object(json!({
+            "name": { "type": "string" },
+            "a": { "type": "string", "description": "Defaults to `<name>-a`" },
+            "b": { "type": "string", "description": "Defaults to `<name>-b`" },
+            "expires_in": { "type": "integer", "minimum": 1, "maximum": 86400, "description": "Seconds until the pair is removed" }
+        }), &["name"]),
+        "VirtualPairInfo": object(json!({
+            "name": { "type": "string" },
+            "a": { "type": "string" },
+            "b": { "type": "string" },
+            "expires_at": { "type": "string", "format": "date-time" }
+        }), &["name", "a", "b"]),
+        "LockHolder": object(json!({
+            "session": { "type": "integer" },
+            "client": nullable_string,
+            "since": { "type": "string", "format": "date-time" }
+        }), &["session", "client", "since"]),
+        "SessionInfo": object(json!({
+            "id": { "type": "integer" },
+            "connection": { "type": "string" },
+            "client": nullable_string,
+            "address": { "type": "string", "description": "Client address, behind any trusted proxies; absent for local attach sessions" },
+            "connected_at": { "type": "string", "format": "date-time" },
+            "read_only": { "type": "boolean" },
+            "trace": session_trace
+        }), &["id", "connection", "connected_at", "read_only"]),
+        "AgentInfo": object(json!({
+            "name": { "type": "string" },
+            "connections": { "type": "array", "items": { "type": "string" } },
+            "connected_at": { "type": "string", "format": "date-time" },
+            "address": { "type": "string", "description": "Where the tunnel came from, behind any trusted proxies" }
+        }), &["name", "connections", "connected_at"])
+    });
+    // Added separately, as one json! for all of them exceeds the macro
+    // recursion limit
+    for part in [
+        traffic_schemas(),
+        capture_schemas(),
+        startup_schemas(),
+        endpoint_schemas(),
+        ws_message_schemas(),
+    ] {
+        if let (Value::Object(schemas), Value::Object(part)) = (&mut schemas, part) {
+            schemas.extend(part);
+        }
+    }
+    schemas
+}
+
+/// Records of traffic kept in memory.
+fn traffic_schemas() -> Value {
+    let nullable_number = json!({ "type": "number", "nullable": true });
+    json!({
+        "SequenceStep": object(json!({
+            "data": { "type": "string", "default": "" },
+            "format": { "type": "string", "enum": ["text", "hex", "base64"], "default": "text", "description": "Encoding of `data` and `expect`" },
+            "expect": { "type": "string", "description": "Reply to wait for before the next step" },
+            "timeout_ms": { "type": "integer", "minimum": 1, "maximum": 60000, "default": 1000 },
+            "delay_ms": { "type": "integer", "minimum": 0, "maximum": 60000, "default": 0, "description": "Pause before the next step" }
+        }), &[]),
+        "SequenceRequest": object(json!({
+            "steps": { "type": "array", "items": schema_ref("SequenceStep"), "minItems": 1, "maxItems": 100 }
+        }), &["steps"]),
+        "StepReport": object(json!({
+            "bytes_written": { "type": "integer" },
+            "matched": { "type": "boolean", "nullable": true, "description": "`null` without `expect`" },
+            "elapsed_ms": { "type": "number" }
+        }), &["bytes_written", "matched", "elapsed_ms"]),
+        "SequenceReport": object(json!({
+            "completed": { "type": "boolean", "description": "False once an expected reply did not arrive" },
+            "steps": array_of("StepReport")
+        }), &["completed", "steps"]),
+        "HistogramBucket": object(json!({
+            "le": { "type": "number", "nullable": true, "description": "Inclusive upper bound; `null` above the last one" },
+            "count": { "type": "integer", "format": "int64" }
+        }), &["le", "count"]),
+        "Distribution": object(json!({
+            "count": { "type": "integer", "format": "int64" },
+            "min": nullable_number,
+            "p50": nullable_number,
+            "p95": nullable_number,
+            "p99": nullable_number,
+            "max": nullable_number,
+            "buckets": array_of("HistogramBucket")
+        }), &["count", "min", "p50", "p95", "p99", "max", "buckets"]),
+        "DirectionProfile": object(json!({
+            "frame_bytes": schema_ref("Distribution"),
+            "gap_ms": schema_ref("Distribution")
+        }), &["frame_bytes", "gap_ms"]),
+        "TrafficProfile": object(json!({
+            "window_seconds": { "type": "integer" },
+            "rx": schema_ref("DirectionProfile"),
+            "tx": schema_ref("DirectionProfile")
+        }), &["window_seconds", "rx", "tx"]),
+        "RuleStats": object(json!({
+            "name": { "type": "string" },
+            "answered": { "type": "integer", "format": "int64", "description": "Lines answered in time" },
+            "violations": { "type": "integer", "format": "int64" },
+            "last_violation": { "type": "string", "format": "date-time", "nullable": true }
+        }), &["name", "answered", "violations", "last_violation"]),
+        "KeepaliveStats": object(json!({
+            "sent": { "type": "integer", "format": "int64" },
+            "last_sent": { "type": "string", "format": "date-time", "nullable": true }
+        }), &["sent"]),
+        "ReconnectStats": object(json!({
+            "reconnecting": { "type": "boolean", "description": "True while the port is gone and being reopened" },
+            "attempts": { "type": "integer", "description": "Failed attempts since the port was last lost" },
+            "reconnects": { "type": "integer", "format": "int64" },
+            "last_error": { "type": "string", "nullable": true },
+            "lost_at": { "type": "string", "format": "date-time", "nullable": true },
+            "reconnected_at": { "type": "string", "format": "date-time", "nullable": true }
+        }), &["reconnecting", "attempts", "reconnects"]),
+        "LineErrors": object(json!({
+            "framing": { "type": "integer", "format": "int64" },
+            "parity": { "type": "integer", "format": "int64" },
+            "overrun": { "type": "integer", "format": "int64", "description": "Bytes lost to full UART or driver buffers" }
+        }), &["framing", "parity", "overrun"]),
+        "TrafficRecord": object(json!({
+            "timestamp": { "type": "string", "format": "date-time" },
+            "direction": { "type": "string", "enum": ["rx", "tx"] },
+            "text": { "type": "string", "description": "Bytes as UTF-8, invalid sequences replaced" },
+            "hex": { "type": "string" },
+            "partial": { "type": "boolean", "description": "Frame still being received" }
+        }), &["timestamp", "direction", "text", "hex", "partial"]),
+        "QuotaUsage": object(json!({
+            "connection": { "type": "string", "nullable": true, "description": "`null` for the server-wide quota" },
+            "day": { "type": "string", "format": "date", "description": "UTC day the usage is for" },
+            "rx_bytes": { "type": "integer", "format": "int64" },
+            "tx_bytes": { "type": "integer", "format": "int64" },
+            "daily_rx_bytes": { "type": "integer", "format": "int64", "nullable": true },
+            "daily_tx_bytes": { "type": "integer", "format": "int64", "nullable": true },
+            "action": { "type": "string", "enum": ["warn", "throttle", "block"] },
+            "exceeded": { "type": "boolean" }
+        }), &["connection", "day", "rx_bytes", "tx_bytes", "daily_rx_bytes", "daily_tx_bytes", "action", "exceeded"]),
+        "ConfigDiff": object(json!({
+            "added": { "type": "array", "items": { "type": "string" }, "description": "Running but not configured; dropped by a restart" },
+            "removed": { "type": "array", "items": { "type": "string" }, "description": "Configured and enabled but not running; opened by a restart" },
+            "modified": array_of("ModifiedConnection")
+        }), &["added", "removed", "modified"]),
+        "ModifiedConnection": object(json!({
+            "name": { "type": "string" },
+            "fields": { "type": "array", "items": { "type": "string" }, "description": "Settings whose running value differs from the file" }
+        }), &["name", "fields"]),
+        "ImportReport": object(json!({
+            "dry_run": { "type": "boolean" },
+            "open": { "type": "array", "items": { "type": "string" }, "description": "Not running before the import" },
+            "close": { "type": "array", "items": { "type": "string" }, "description": "Running but left out of the import" },
+            "reopen": array_of("ModifiedConnection"),
+            "restart_required": { "type": "array", "items": { "type": "string" }, "description": "Server settings that differ; they apply after a restart" },
+            "failed": { "type": "array", "items": object(json!({
+                "name": { "type": "string" },
+                "error": { "type": "string" }
+            }), &["name", "error"]) }
+        }), &["dry_run", "open", "close", "reopen", "restart_required", "failed"]),
+        "FrameRecord": object(json!({
+            "seq": { "type": "integer" },
+            "timestamp": { "type": "string", "format": "date-time" },
+            "text": { "type": "string", "description": "Bytes as UTF-8, invalid sequences replaced" },
+            "hex": { "type": "string" }
+        }), &["seq", "timestamp", "text", "hex"]),
+        "FramePage": object(json!({
+            "frames": array_of("FrameRecord"),
+            "latest_seq": { "type": "integer", "description": "Newest chunk so far, 0 before the first" },
+            "gap": {
+                "type": "object",
+                "nullable": true,
+                "description": "Chunks after `since_seq` that can no longer be returned",
+                "properties": {
+                    "kind": { "type": "string", "enum": ["expired", "unknown"] },
+                    "first": { "type": "integer" },
+                    "last": { "type": "integer" }
+                },
+                "required": ["kind"]
+            },
+            "annotations": { "type": "array", "items": schema_ref("Annotation"), "description": "Annotations following the chunks on this page" }
+        }), &["frames", "latest_seq", "gap", "annotations"]),
+        "LogEvent": object(json!({
+            "timestamp": { "type": "string", "format": "date-time" },
+            "level": { "type": "string", "enum": ["error", "warn", "info", "debug"] },
+            "target": { "type": "string", "description": "Module the event came from" },
+            "message": { "type": "string" },
+            "fields": { "type": "object", "additionalProperties": { "type": "string" } }
+        }), &["timestamp", "level", "target", "message"]),
+        "AnnotationRequest": object(json!({
+            "text": { "type": "string", "minLength": 1, "maxLength": 1024 },
+            "severity": { "type": "string", "enum": ["info", "warning", "error"], "default": "info" },
+            "author": { "type": "string", "maxLength": 128 },
+            "seq": { "type": "integer", "minimum": 1, "description": "Received chunk the note follows; it must still be kept" },
+            "timestamp": { "type": "string", "format": "date-time", "description": "When the noted event happened, instead of seq; defaults to now" }
+        }), &["text"]),
+        "Annotation": object(json!({
+            "id": { "type": "integer", "format": "int64" },
+            "seq": { "type": "integer", "description": "Last chunk received at or before the noted point, 0 if none" },
+            "timestamp": { "type": "string", "format": "date-time" },
+            "severity": { "type": "string", "enum": ["info", "warning", "error"] },
+            "text": { "type": "string" },
+            "author": { "type": "string" },
+            "created_at": { "type": "string", "format": "date-time" }
+        }), &["id", "seq", "timestamp", "severity", "text", "created_at"])
+    })
+}
+
+/// What became of the configured connections when they were opened.
+fn startup_schemas() -> Value {
+    json!({
+        "StartupReport": object(json!({
+            "opened": { "type": "integer" },
+            "failed": { "type": "integer" },
+            "pending": { "type": "integer" },
+            "disabled": { "type": "integer" },
+            "connections": array_of("StartupConnection")
+        }), &["opened", "failed", "pending", "disabled", "connections"]),
+        "StartupConnection": object(json!({
+            "name": { "type": "string" },
+            "port": { "type": "string" },
+            "state": { "type": "string", "enum": ["pending", "opened", "failed", "disabled"] },
+            "error": { "type": "string", "description": "Why a failed connection did not open" },
+            "waiting_for": { "type": "string", "description": "What a pending connection is waiting for: a dependency, its startup delay or the port" },
+            "since": { "type": "string", "format": "date-time" }
+        }), &["name", "port", "state", "since"])
+    })
+}
+
+/// Values read through the connections' configured `endpoints`.
+fn endpoint_schemas() -> Value {
+    json!({
+        "EndpointReading": object(json!({
+            "connection": { "type": "string" },
+            "endpoint": { "type": "string" },
+            "value": { "type": "string", "description": "The pattern's first capture group, or its whole match" },
+            "number": { "type": "number", "nullable": true },
+            "line": { "type": "string", "description": "The reply line the pattern matched" },
+            "read_at": { "type": "string", "format": "date-time", "description": "When the device answered" },
+            "cached": { "type": "boolean", "description": "Served from an earlier query, within the endpoint's `poll_interval_ms`" }
+        }), &["connection", "endpoint", "value", "number", "line", "read_at", "cached"])
+    })
+}
+
+/// Recordings of several connections on a shared clock.
+fn capture_schemas() -> Value {
+    json!({
+        "CaptureRequest": object(json!({
+            "connections": { "type": "array", "items": { "type": "string" } },
+            "duration_secs": { "type": "integer", "default": 300, "maximum": 3600 },
+            "max_bytes": { "type": "integer", "default": 16777216, "maximum": 67108864 }
+        }), &["connections"]),
+        "CaptureInfo": object(json!({
+            "id": { "type": "integer" },
+            "connections": { "type": "array", "items": { "type": "string" } },
+            "state": { "type": "string", "enum": ["running", "stopped", "completed", "full"] },
+            "started_at": { "type": "string", "format": "date-time" },
+            "stopped_at": { "type": "string", "format": "date-time", "nullable": true },
+            "records": { "type": "integer" },
+            "bytes": { "type": "integer" },
+            "dropped_chunks": { "type": "integer" },
+            "clock": schema_ref("ClockInfo")
+        }), &["id", "connections", "state", "started_at", "records", "bytes", "dropped_chunks", "clock"]),
+        "CaptureBundle": {
+            "allOf": [
+                schema_ref("CaptureInfo"),
+                object(json!({
+                    "records": {
+                        "type": "array",
+                        "items": object(json!({
+                            "offset_us": { "type": "integer", "format": "int64" },
+                            "connection": { "type": "string" },
+                            "direction": { "type": "string", "enum": ["rx", "tx"] },
+                            "data": { "type": "string", "description": "Payload as hex" }
+                        }), &["offset_us", "connection", "direction", "data"])
+                    },
+                    "annotations": {
+                        "type": "array",
+                        "description": "Annotations made on the captured connections while recording",
+                        "items": {
+                            "allOf": [
+                                schema_ref("Annotation"),
+                                object(json!({
+                                    "offset_us": { "type": "integer", "format": "int64", "description": "From the capture's start, by the wall clock" },
+                                    "connection": { "type": "string" }
+                                }), &["offset_us", "connection"])
+                            ]
+                        }
+                    }
+                }), &["records", "annotations"])
+            ]
+        },
+        "ClockInfo": object(json!({
+            "host": { "type": "string" },
+            "start_wall": { "type": "string", "format": "date-time", "description": "Wall-clock time at offset 0" },
+            "start_monotonic_us": { "type": "integer", "format": "int64", "description": "Monotonic clock at offset 0" },
+            "ntp_synchronized": { "type": "boolean", "nullable": true },
+            "max_error_us": { "type": "integer", "format": "int64", "nullable": true }
+        }), &["host", "start_wall", "start_monotonic_us", "ntp_synchronized", "max_error_us"]),
+        "SnifferConfig": object(json!({
+            "name": { "type": "string" },
+            "tx": { "type": "string", "description": "Connection whose port listens to the link's TX line" },
+            "rx": { "type": "string", "description": "Connection whose port listens to the link's RX line" },
+            "capture_file": { "type": "string", "description": "Append the merged traffic here as JSON Lines of `SnifferRecord`" }
+        }), &["name", "tx", "rx"]),
+        "SnifferInfo": object(json!({
+            "name": { "type": "string" },
+            "tx": { "type": "string" },
+            "rx": { "type": "string" },
+            "capture_file": { "type": "string" },
+            "started_at": { "type": "string", "format": "date-time" },
+            "running": { "type": "boolean", "description": "False once either connection has gone away" },
+            "bytes_tx": { "type": "integer", "format": "int64" },
+            "bytes_rx": { "type": "integer", "format": "int64" },
+            "dropped_chunks": { "type": "integer", "format": "int64" }
+        }), &["name", "tx", "rx", "started_at", "running", "bytes_tx", "bytes_rx", "dropped_chunks"]),
+        "SnifferRecord": object(json!({
+            "timestamp": { "type": "string", "format": "date-time" },
+            "offset_us": { "type": "integer", "format": "int64", "description": "Since the sniffer started, on the monotonic clock" },
+            "direction": { "type": "string", "enum": ["tx", "rx"] },
+            "data": { "type": "string", "description": "Payload as hex" }
+        }), &["timestamp", "offset_us", "direction", "data"])
+    })
+}
+
+/// Text messages the WebSocket stream sends besides traffic.
+fn ws_message_schemas() -> Value {
+    json!({
+        "WsWriteLockFrame": object(json!({
+            "type": { "type": "string", "enum": ["write_lock"] },
+            "holder": { "allOf": [schema_ref("LockHolder")], "nullable": true },
+            "reason": { "type": "string", "enum": ["idle", "disconnected", "released"] }
+        }), &["type", "holder"]),
+        "WsBannerFrame": object(json!({
+            "type": { "type": "string", "enum": ["banner"] },
+            "text": { "type": "string" }
+        }), &["type", "text"]),
+        "WsTxFrame": object(json!({
+            "type": { "type": "string", "enum": ["tx"] },
+            "session": { "type": "integer", "description": "The session that wrote the data" },
+            "client": { "type": "string", "nullable": true },
+            "data": { "type": "string" },
+            "format": { "type": "string", "enum": ["text", "base64"] }
+        }), &["type", "session", "client", "data", "format"]),
+        "WsSeqFrame": object(json!({
+            "type": { "type": "string", "enum": ["seq"] },
+            "seq": { "type": "integer", "minimum": 1 }
+        }), &["type", "seq"]),
+        "WsErrorFrame": object(json!({
+            "type": { "type": "string", "enum": ["error"] },
+            "code": { "type": "string", "enum": ["write_failed", "write_locked", "read_only", "invalid_frame", "invalid_hex", "replay_incomplete", "not_found"] },
+            "message": { "type": "string" }
+        }), &["type", "code", "message"])
+    })
+}
+
+const WS_DESCRIPTION: &str = "\
+Upgrades to a WebSocket streaming the connection's traffic.
+
+Server to client: on connections with a `banner`, a `WsBannerFrame` JSON \
+text message first. Then received bytes as binary messages, or as lowercase hex \
+text messages with `encoding=hex`. On `slcan` \
+connections, received CAN frames arrive as JSON text messages in the \
+`CanFrame` shape. When something the client sent cannot be delivered, a \
+`WsErrorFrame` JSON text message is sent and the session stays open, \
+except for `not_found`, after which it is closed.
+
+Client to server: binary or text messages are written to the port; with \
+`encoding=hex`, text messages are hex (whitespace allowed) and decoded \
+first. On \
+`slcan` connections, text messages in the `CanFrame` shape are \
+transmitted as frames.
+
+On connections with a `write_lock`, the first session to write holds the \
+lock and writes from the others are refused with `write_locked`. Every \
+session gets a `WsWriteLockFrame` when the lock changes hands, and a new \
+session gets one if the lock is held.
+
+With `share_input=true` and the default `direction=rx`, what other \
+sessions on the connection write arrives as `WsTxFrame` JSON text \
+messages, scrubbed as in the logs.
+
+With `mode=readonly`, nothing the client sends is written; each message is \
+answered with a `read_only` error instead.
+
+With `direction=tx` the session receives what is written to the port, by \
+any session or the server itself, instead of what is received; \
+`direction=both` interleaves the two. With `suppress_echo`, the session's \
+own writes are left out. Not available on `slcan` connections.
+
+With `encoding=mux`, every message in both directions is binary and \
+starts with a channel byte: 0 raw traffic, 1 decoded frames as JSON \
+(`CanFrame` on `slcan` connections), 2 the JSON messages otherwise sent \
+as text, 3 a `seq` number as a big-endian u64, 4 data written to the \
+port with `direction` `tx` or `both`. Clients send on channels 0 and 1; anything else is answered with `invalid_frame`.
+
+With `seq=true`, the messages carrying each received chunk are followed by a `WsSeqFrame` numbering it. A client reconnecting with `since_seq` set to the last number it saw first receives the chunks it missed, if they are still among the last 1000 kept; otherwise it gets a `replay_incomplete` error before whatever could be replayed.
+
+Operators can disconnect a session through `DELETE /api/sessions/{id}`. \
+Sessions refused after the upgrade because a client cap was reached are \
+closed with code 1013. Otherwise the server closes sessions with an \
+application code and a matching reason: 4000 `connection-removed`, 4002 \
+`kicked` or 4003 `server-shutdown`; 4001 `unauthorized` is reserved and \
+not sent yet. Only 4003 is worth reconnecting after.";
+
+fn paths() -> Value {
+    let name = connection_param;
+    // Unknown connections surface as 500s, since the handlers pass the
+    // manager's error through unchanged
+    let not_found = || error("Connection not found");
+    let mut paths = Map::new();
+    let mut add = |path: &str, item: Value| {
+        paths.insert(path.to_string(), item);
+    };
+
+    add(
+        "/health",
+        json!({
+            "get": operation("server", "Liveness check, or connection health with `Accept: application/json`", vec![], json!({
+                "200": {
+                    "description": "`OK`, or the health summary",
+                    "content": {
+                        "text/plain": { "schema": { "type": "string" } },
+                        "application/json": { "schema": schema_ref("HealthReport") }
+                    }
+                },
+                "503": ok_json("Every connection is disconnected (JSON only)", schema_ref("HealthReport"))
+            }))
+        }),
+    );
+    add(
+        "/metrics",
+        json!({
+            "get": operation("server", "Counters, extracted metrics and latency and frame size histograms for Prometheus", vec![], json!({
+                "200": {
+                    "description": "Prometheus text exposition format",
+                    "content": { "text/plain": { "schema": { "type": "string" } } }
+                }
+            }))
+        }),
+    );
+    add(
+        "/api/stats",
+        json!({
+            "get": operation("server", "Totals across all connections", vec![],
+                json!({ "200": ok_json("Server statistics", schema_ref("ServerStats")) }))
+        }),
+    );
+    add(
+        "/api/quotas",
+        json!({
+            "get": operation("server", "Today's usage of the daily byte quotas, the server's first", vec![],
+                json!({ "200": ok_json("Quota usage", array_of("QuotaUsage")) }))
+        }),
+    );
+    add(
+        "/api/config/diff",
+        json!({
+            "get": operation("server", "Connections that differ from the configuration files", vec![],
+                json!({
+                    "200": ok_json("Differences", schema_ref("ConfigDiff")),
+                    "404": error("Not started from a configuration file"),
+                    "500": error("The configuration files no longer load")
+                }))
+        }),
+    );
+    add(
+        "/api/config/export",
+        json!({
+            "get": operation("server", "The running setup as a configuration file", vec![],
+                json!({
+                    "200": {
+                        "description": "Server settings and connections, including those opened at runtime",
+                        "content": { "application/yaml": { "schema": { "type": "string" } } }
+                    },
+                    "500": error("The configuration files no longer load")
+                }))
+        }),
+    );
+    add(
+        "/api/config/import",
+        json!({
+            "post": with_body(
+                operation("server", "Open, close and reopen connections to match a configuration file", vec![
+                    query_param("dry_run", "Only report what would change", json!({ "type": "boolean", "default": false }))
+                ], json!({
+                    "200": ok_json("What was changed", schema_ref("ImportReport")),
+                    "400": error("Not a valid configuration")
+                })),
+                json!({ "application/yaml": { "schema": { "type": "string" } } }),
+            )
+        }),
+    );
+    add(
+        "/api/ports",
+        json!({
+            "get": operation("server", "Serial ports present on the host", vec![],
+                json!({ "200": ok_json("Ports", array_of("PortDescription")) }))
+        }),
+    );
+    add(
+        "/api/port-groups",
+        json!({
+            "get": operation("server", "Ports grouped by the multi-port USB adapter they belong to", vec![],
+                json!({ "200": ok_json("Groups", array_of("PortGroup")) }))
+        }),
+    );
+    add(
+        "/api/port-groups/{id}/restart",
+        json!({
+            "post": operation("server", "Close and reopen every connection on an adapter's ports",
+                vec![path_param("id", "Group ID", json!({ "type": "string" }))],
+                json!({
+                    "200": ok_json("Connections reopened and those that failed", schema_ref("RestartReport")),
+                    "404": error("No such group")
+                }))
+        }),
+    );
+    add(
+        "/api/connections",
+        json!({
+            "get": operation("connections", "List connections", vec![],
+                json!({ "200": ok_json("Connections", array_of("ConnectionListItem")) })),
+            "post": with_body(
+                operation("connections", "Open a connection at runtime, optionally removed after `expires_in`", vec![], json!({
+                    "201": ok_json("Connection", schema_ref("ConnectionListItem")),
+                    "400": error("Invalid request or port could not be opened"),
+                    "409": error("Connection name in use")
+                })),
+                json_body(schema_ref("ConnectionRequest")),
+            )
+        }),
+    );
+    add(
+        "/api/connections/bulk",
+        json!({
+            "post": with_body(
+                operation("connections", "Open a connection from a template on each of many ports", vec![], json!({
+                    "200": ok_json("Connections opened and those that failed", schema_ref("BulkConnectionReport")),
+                    "400": error("Invalid template, or no ports given or matched")
+                })),
+                json_body(schema_ref("BulkConnectionRequest")),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/retry",
+        json!({
+            "post": operation("connections", "Try again to open a configured connection that failed", vec![name()], json!({
+                "200": ok_json("Connection opened", schema_ref("ConnectionListItem")),
+                "400": error("Opening the port failed again"),
+                "404": error("No failed connection with this name")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/enable",
+        json!({
+            "post": operation("connections", "Open a disabled connection again with its settings; retries a failed one", vec![name()], json!({
+                "200": ok_json("Connection opened", schema_ref("ConnectionListItem")),
+                "400": error("Opening the port failed; the connection is kept as failed"),
+                "404": error("No connection with this name")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/disable",
+        json!({
+            "post": operation("connections", "Close the connection but keep it listed, to be enabled again", vec![name()], json!({
+                "200": ok_json("Connection disabled", schema_ref("ConnectionListItem")),
+                "404": error("No connection with this name")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}",
+        json!({
+            "get": operation("connections", "Connection settings", vec![name()],
+                json!({ "200": ok_json("Connection", schema_ref("ConnectionInfo")) })),
+            "delete": operation("connections", "Close and remove the connection", vec![name()], json!({
+                "200": ok_text("Connection removed"),
+                "404": error("Connection not found")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/send",
+        json!({
+            "post": with_body(
+                operation("connections", "Write data to the port", vec![name()], json!({
+                    "200": ok_text("`Data sent`, or `Data written` with `ack: written`"),
+                    "400": error("Invalid data for the format"),
+                    "413": error("Payload over `max_send_bytes`"),
+                    "500": not_found(),
+                    "504": error("Not written within 30 seconds")
+                })),
+                json_body(schema_ref("SendDataRequest")),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/sequence",
+        json!({
+            "post": with_body(
+                operation("connections", "Write steps that no other client's writes can come between", vec![name()], json!({
+                    "200": ok_json("Report", schema_ref("SequenceReport")),
+                    "400": error("Invalid steps"),
+                    "409": error("Port is leased"),
+                    "413": error("Data over `max_send_bytes`"),
+                    "500": not_found()
+                })),
+                json_body(schema_ref("SequenceRequest")),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/upload",
+        json!({
+            "post": with_body(
+                operation("connections", "Write the request body to the port, on the bulk queue by default", vec![
+                    name(),
+                    query_param("priority", "Write queue to use", json!({ "type": "string", "enum": ["interactive", "bulk"], "default": "bulk" }))
+                ], json!({
+                    "200": ok_text("`Data sent`"),
+                    "400": error("Empty body"),
+                    "413": error("Body over `max_upload_bytes`"),
+                    "500": not_found()
+                })),
+                json!({ "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } }),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/stats",
+        json!({
+            "get": operation("connections", "Byte counters and state", vec![name()], json!({
+                "200": ok_json("Statistics", schema_ref("ConnectionStats")),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/throughput",
+        json!({
+            "get": operation("connections", "Rolling traffic rates", vec![
+                name(),
+                query_param("window", "Averaging window in seconds", json!({ "type": "integer", "minimum": 1, "maximum": 60, "default": 10 }))
+            ], json!({
+                "200": ok_json("Rates", schema_ref("Throughput")),
+                "400": error("Invalid window"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/tail",
+        json!({
+            "get": operation("connections", "Recent traffic, one record per frame", vec![
+                name(),
+                query_param("lines", "Frames to return, newest last", json!({ "type": "integer", "minimum": 1, "maximum": 1000, "default": 200 })),
+                query_param("direction", "Only frames in this direction", json!({ "type": "string", "enum": ["rx", "tx"] }))
+            ], json!({
+                "200": ok_json("Frames, oldest first", array_of("TrafficRecord")),
+                "400": error("Invalid lines or direction"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/traffic-profile",
+        json!({
+            "get": operation("connections", "Distributions of frame sizes and inter-frame gaps", vec![
+                name(),
+                query_param("window", "Seconds of traffic to describe", json!({ "type": "integer", "minimum": 1, "maximum": 600, "default": 60 }))
+            ], json!({
+                "200": ok_json("Profile", schema_ref("TrafficProfile")),
+                "400": error("Invalid window"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/hexdump",
+        json!({
+            "get": operation("connections", "Recent traffic as a canonical hex+ASCII dump", vec![
+                name(),
+                query_param("bytes", "Most recent bytes to dump", json!({ "type": "integer", "minimum": 1, "maximum": 65536, "default": 512 })),
+                query_param("direction", "Traffic direction", json!({ "type": "string", "enum": ["rx", "tx"], "default": "rx" }))
+            ], json!({
+                "200": ok_text("Dump in the format of `hexdump -C`"),
+                "400": error("Invalid bytes or direction"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/frames",
+        json!({
+            "get": operation("connections", "Numbered received chunks, for loggers paging by sequence number", vec![
+                name(),
+                query_param("since_seq", "Return chunks after this one; 0 for the oldest kept", json!({ "type": "integer", "minimum": 0, "default": 0 })),
+                query_param("limit", "Chunks to return at most", json!({ "type": "integer", "minimum": 1, "maximum": 1000, "default": 100 }))
+            ], json!({
+                "200": ok_json("Chunks, oldest first", schema_ref("FramePage")),
+                "400": error("Invalid since_seq or limit"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/read",
+        json!({
+            "get": operation("connections", "Received chunks since this cursor's last read, advancing it", vec![
+                name(),
+                query_param("cursor", "Name of the reader; a new one starts at the oldest chunk kept", json!({ "type": "string", "minLength": 1, "maxLength": 64, "default": "default" })),
+                query_param("limit", "Chunks to return at most", json!({ "type": "integer", "minimum": 1, "maximum": 1000, "default": 100 }))
+            ], json!({
+                "200": ok_json("Chunks, oldest first", schema_ref("FramePage")),
+                "400": error("Invalid cursor or limit"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/annotations",
+        json!({
+            "get": operation("connections", "Notes pinned to the received data, in the order of the data", vec![
+                name(),
+                query_param("since_seq", "Only annotations following chunks after this one; 0 for all", json!({ "type": "integer", "minimum": 0, "default": 0 }))
+            ], json!({
+                "200": ok_json("Annotations", array_of("Annotation")),
+                "500": not_found()
+            })),
+            "post": with_body(
+                operation("connections", "Pin a note to a chunk, a point in time, or the newest data", vec![name()], json!({
+                    "201": ok_json("Annotation", schema_ref("Annotation")),
+                    "400": error("Invalid request, or the chunk is no longer kept"),
+                    "500": not_found()
+                })),
+                json_body(schema_ref("AnnotationRequest")),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/poll",
+        json!({
+            "get": operation("connections", "Long-poll for received chunks, for clients that cannot hold a WebSocket open", vec![
+                name(),
+                query_param("since_seq", "Return chunks after this one; 0 for the oldest kept", json!({ "type": "integer", "minimum": 0, "default": 0 })),
+                query_param("timeout", "Seconds to wait if there are no chunks after since_seq yet", json!({ "type": "integer", "minimum": 0, "maximum": 120, "default": 30 })),
+                query_param("limit", "Chunks to return at most", json!({ "type": "integer", "minimum": 1, "maximum": 1000, "default": 100 }))
+            ], json!({
+                "200": ok_json("Chunks, oldest first; none if the timeout passed", schema_ref("FramePage")),
+                "400": error("Invalid timeout or limit"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/latency-test",
+        json!({
+            "post": with_body(
+                operation("connections", "Time probes echoed back by the device", vec![name()], json!({
+                    "200": ok_json("Report", schema_ref("LatencyReport")),
+                    "400": error("Invalid options"),
+                    "409": error("Port is leased"),
+                    "500": not_found()
+                })),
+                json_body(schema_ref("LatencyTestOptions")),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/power/{action}",
+        json!({
+            "post": operation("connections", "Switch the attached device on, off, or off and on", vec![
+                name(),
+                path_param("action", "Power action", json!({ "type": "string", "enum": ["on", "off", "cycle"] })),
+            ], json!({
+                "200": ok_text("Switched"),
+                "400": error("Unknown action, or no power control configured"),
+                "500": error("Connection not found, or the power control failed")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/metrics",
+        json!({
+            "get": operation("metrics", "Extracted metrics and alert state", vec![name()], json!({
+                "200": ok_json("Metrics", array_of("MetricSummary")),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/metrics/{metric}",
+        json!({
+            "get": operation("metrics", "Recorded samples of a metric, oldest first", vec![
+                name(),
+                path_param("metric", "Metric name", json!({ "type": "string" })),
+                query_param("limit", "Only the most recent samples", json!({ "type": "integer" }))
+            ], json!({
+                "200": ok_json("Samples", array_of("Sample")),
+                "404": error("Metric not defined"),
+                "500": not_found()
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/{endpoint}",
+        json!({
+            "get": operation("connections", "Write a configured endpoint's command and read the value from the reply, or serve its last reading within `poll_interval_ms`", vec![
+                name(),
+                path_param("endpoint", "Name of an entry in the connection's `endpoints`", json!({ "type": "string" }))
+            ], json!({
+                "200": ok_json("Reading", schema_ref("EndpointReading")),
+                "404": error("Endpoint not defined"),
+                "409": error("Connection is leased"),
+                "500": not_found(),
+                "504": error("No reply matched the pattern in time")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/can/send",
+        json!({
+            "post": with_body(
+                operation("connections", "Transmit a CAN frame through an SLCAN adapter", vec![name()], json!({
+                    "200": ok_json("Frame sent", schema_ref("CanFrame")),
+                    "400": error("Invalid frame or not an SLCAN connection"),
+                    "500": not_found()
+                })),
+                json_body(schema_ref("SendCanFrameRequest")),
+            )
+        }),
+    );
+    let flash_responses = || {
+        json!({
+            "200": {
+                "description": "Progress as newline-delimited `FlashProgress` objects",
+                "content": { "application/x-ndjson": { "schema": schema_ref("FlashProgress") } }
+            },
+            "400": error("Invalid image or options"),
+            "409": error("Port is leased or virtual"),
+            "413": error("Image over `max_upload_bytes`"),
+            "500": not_found()
+        })
+    };
+    add(
+        "/api/connections/{name}/flash/esp",
+        json!({
+            "post": with_body(
+                operation("flashing", "Flash an ESP32/ESP8266", vec![
+                    name(),
+                    query_param("chip", "Target chip", json!({ "type": "string", "enum": ["esp32", "esp8266"], "default": "esp32" })),
+                    query_param("offset", "Flash offset, decimal or 0x-prefixed hex", json!({ "type": "string", "default": "0" })),
+                    query_param("reboot", "Reset into the new firmware when done", json!({ "type": "boolean", "default": true }))
+                ], flash_responses()),
+                json!({ "application/octet-stream": { "schema": { "type": "string", "format": "binary" } } }),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/flash/avr",
+        json!({
+            "post": with_body(
+                operation("flashing", "Upload an Intel HEX sketch to an STK500 bootloader", vec![
+                    name(),
+                    query_param("protocol", "Bootloader protocol", json!({ "type": "string", "enum": ["stk500v1", "stk500v2"], "default": "stk500v1" })),
+                    query_param("page_size", "Flash page size in bytes", json!({ "type": "integer" })),
+                    query_param("verify", "Read back every page after writing", json!({ "type": "boolean", "default": true }))
+                ], flash_responses()),
+                json!({ "text/plain": { "schema": { "type": "string" } } }),
+            )
+        }),
+    );
+    add(
+        "/api/connections/{name}/write-lock",
+        json!({
+            "get": operation("sessions", "Session holding the write lock", vec![name()], json!({
+                "200": ok_json("Holder", schema_ref("LockHolder")),
+                "404": error("Not write-locked")
+            })),
+            "delete": operation("sessions", "Free the write lock for other sessions", vec![name()], json!({
+                "200": ok_json("Previous holder", schema_ref("LockHolder")),
+                "404": error("Not write-locked")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/lease",
+        json!({
+            "post": with_body(
+                operation("leases", "Lend the port to an external tool", vec![name()], json!({
+                    "200": ok_json("Lease", schema_ref("LeaseInfo")),
+                    "400": error("Invalid lease"),
+                    "409": error("Already leased or virtual"),
+                    "500": not_found()
+                })),
+                json_body(schema_ref("LeaseRequest")),
+            ),
+            "get": operation("leases", "Active lease", vec![name()], json!({
+                "200": ok_json("Lease", schema_ref("LeaseInfo")),
+                "404": error("No active lease")
+            })),
+            "delete": operation("leases", "End the lease early", vec![name()], json!({
+                "200": ok_text("Lease ended"),
+                "404": error("No active lease")
+            }))
+        }),
+    );
+    add(
+        "/api/connections/{name}/ws",
+        json!({
+            "get": {
+                "tags": ["streaming"],
+                "summary": "WebSocket stream",
+                "description": WS_DESCRIPTION,
+                "parameters": [
+                    name(),
+                    query_param("client", "Label shown in session listings; the X-Webmux-Client header works too", json!({ "type": "string", "maxLength": 64 })),
+                    query_param("max_rate", "Deliver at most this many messages, e.g. `10/s`", json!({ "type": "string" })),
+                    query_param("sample", "Deliver only some lines, e.g. `lines:1/100`", json!({ "type": "string" })),
+                    query_param("encoding", "Carry raw traffic as `binary` messages, as `hex` text, or share binary messages among channels with `mux`", json!({ "type": "string", "enum": ["binary", "hex", "mux"], "default": "binary" })),
+                    query_param("priority", "Write queue for the client's messages", json!({ "type": "string", "enum": ["interactive", "bulk"], "default": "interactive" })),
+                    query_param("seq", "Number received chunks with `WsSeqFrame` messages", json!({ "type": "boolean", "default": false })),
+                    query_param("since_seq", "Replay buffered chunks after this one; implies `seq`", json!({ "type": "integer", "minimum": 0 })),
+                    query_param("mode", "`readonly` never writes what the client sends", json!({ "type": "string", "enum": ["readwrite", "readonly"], "default": "readwrite" })),
+                    query_param("direction", "Receive what the device sends (`rx`), what is written to the port (`tx`), or both", json!({ "type": "string", "enum": ["rx", "tx", "both"], "default": "rx" })),
+                    query_param("share_input", "With `direction=rx`, also receive `WsTxFrame` messages with what other sessions write", json!({ "type": "boolean", "default": false }))
+                ],
+                "responses": {
+                    "101": { "description": "Switching to the WebSocket protocol" },
+                    "400": error("Invalid client label, decimation options, encoding or direction"),
+                    "403": error("Origin not allowed"),
+                    "409": error("Client cap reached")
+                }
+            }
+        }),
+    );
+    add(
+        "/api/notifications/deliveries",
+        json!({
+            "get": operation("server", "Recent notification deliveries, newest first", vec![
+                query_param("channel", "Only deliveries to this channel", json!({ "type": "string" })),
+                query_param("state", "Only deliveries in this state", json!({ "type": "string", "enum": ["pending", "delivered", "failed"] }))
+            ], json!({ "200": ok_json("Deliveries", array_of("Delivery")) }))
+        }),
+    );
+    add(
+        "/api/alerts/silence",
+        json!({
+            "post": with_body(
+                operation("server", "Mute matching alerts for a while", vec![], json!({
+                    "201": ok_json("Silence", schema_ref("Silence")),
+                    "400": error("Invalid pattern or duration")
+                })),
+                json_body(schema_ref("SilenceRequest")),
+            )
+        }),
+    );
+    add(
+        "/api/alerts/silences",
+        json!({
+            "get": operation("server", "Silences in effect", vec![],
+                json!({ "200": ok_json("Silences", array_of("Silence")) }))
+        }),
+    );
+    add(
+        "/api/alerts/silences/{id}",
+        json!({
+            "delete": operation("server", "End a silence early",
+                vec![path_param("id", "Silence ID", json!({ "type": "integer" }))],
+                json!({
+                    "200": ok_text("Silence removed"),
+                    "404": error("Silence not found")
+                }))
+        }),
+    );
+    add(
+        "/api/admin/startup",
+        json!({
+            "get": operation("server", "Which configured connections opened, failed or are still pending", vec![],
+                json!({ "200": ok_json("Startup report", schema_ref("StartupReport")) }))
+        }),
+    );
+    add(
+        "/api/admin/logs/stream",
+        json!({
+            "get": operation("server", "The server's own log output as Server-Sent Events", vec![
+                query_param("level", "Least severe level sent", json!({ "type": "string", "enum": ["error", "warn", "info", "debug"], "default": "info" })),
+                query_param("target", "Only events whose target starts with this, e.g. `webmux::serial`", json!({ "type": "string" }))
+            ], json!({
+                "200": {
+                    "description": "`log` events with a LogEvent as data, and `lagged` events with the number of events skipped",
+                    "content": { "text/event-stream": { "schema": { "type": "string" } } }
+                },
+                "400": error("Invalid level"),
+                "503": error("Live logs are not available in this process")
+            }))
+        }),
+    );
+    for (path, summary) in [
+        (
+            "/api/admin/shutdown",
+            "Shut the server down gracefully, as on SIGTERM",
+        ),
+        (
+            "/api/admin/restart",
+            "Shut down gracefully, then start again with the same arguments",
+        ),
+    ] {
+        add(
+            path,
+            json!({
+                "post": operation("server", summary, vec![], json!({
+                    "202": ok_json("Shutting down", object(json!({
+                        "action": { "type": "string", "enum": ["shutdown", "restart"] }
+                    }), &["action"])),
+                    "401": error("Missing or wrong bearer token"),
+                    "403": error("server.remote_control is not set"),
+                    "409": error("Already shutting down")
+                }))
+            }),
+        );
+    }
+    add(
+        "/api/sessions",
+        json!({
+            "get": operation("streaming", "Open WebSocket sessions", vec![
+                query_param("connection", "Only sessions on this connection", json!({ "type": "string" }))
+            ], json!({ "200": ok_json("Sessions", array_of("SessionInfo")) }))
+        }),
+    );
+    add(
+        "/api/sessions/{id}",
+        json!({
+            "delete": operation("streaming", "Disconnect a session", vec![
+                path_param("id", "Session id", json!({ "type": "integer" }))
+            ], json!({
+                "200": ok_json("The disconnected session", schema_ref("SessionInfo")),
+                "404": error("Session not open")
+            }))
+        }),
+    );
+    add(
+        "/api/hub/connect",
+        json!({
+            "get": operation("hub", "WebSocket tunnel an agent dials with the hub's bearer token", vec![], json!({
+                "101": { "description": "Tunnel open; the agent sends `register` first" },
+                "401": error("Missing or wrong bearer token"),
+                "404": error("server.hub is not set")
+            }))
+        }),
+    );
+    add(
+        "/api/hub/agents",
+        json!({
+            "get": operation("hub", "Agents tunnelled in to this hub", vec![], json!({
+                "200": ok_json("Agents", array_of("AgentInfo"))
+            }))
+        }),
+    );
+    let relay = |method: &str| {
+        operation(
+            "hub",
+            &format!("Relay a {} request to an agent's API", method),
+            vec![
+                path_param("agent", "Agent name", json!({ "type": "string" })),
+                path_param(
+                    "path",
+                    "Path on the agent, e.g. `api/connections`; the query string is passed along",
+                    json!({ "type": "string" }),
+                ),
+            ],
+            json!({
+                "default": { "description": "The agent's response, status and body unchanged" },
+                "404": error("server.hub is not set, or the agent is not connected"),
+                "502": error("The agent disconnected before answering"),
+                "503": error("Too many requests queued for the agent"),
+                "504": error("The agent did not answer within 30 seconds")
+            }),
+        )
+    };
+    add(
+        "/api/hub/agents/{agent}/{path}",
+        json!({
+            "get": relay("GET"),
+            "post": relay("POST"),
+            "put": relay("PUT"),
+            "delete": relay("DELETE")
+        }),
+    );
+    let mut pattern = query_param(
+        "q",
+        "Regex matched against each logged chunk",
+        json!({ "type": "string" }),
+    );
+    pattern["required"] = json!(true);
+    add(
+        "/api/search",
+        json!({
+            "get": operation("logs", "Regex search across connection text logs", vec![
+                pattern,
+                query_param("connections", "Comma-separated connection names; all with a text log by default", json!({ "type": "string" })),
+                query_param("from", "Earliest timestamp", json!({ "type": "string", "format": "date-time" })),
+                query_param("to", "Latest timestamp", json!({ "type": "string", "format": "date-time" })),
+                query_param("offset", "Matches to skip, from `next_offset`; `offset` plus `limit` is at most 10000", json!({ "type": "integer", "minimum": 0, "maximum": 9999, "default": 0 })),
+                query_param("limit", "Matches per page", json!({ "type": "integer", "minimum": 1, "maximum": 1000, "default": 100 }))
+            ], json!({
+                "200": ok_json("Matches in time order", schema_ref("SearchResults")),
+                "400": error("Invalid pattern, limit, offset or connection")
+            }))
+        }),
+    );
+    add(
+        "/api/captures",
+        json!({
+            "get": operation("captures", "List captures", vec![],
+                json!({ "200": ok_json("Captures", array_of("CaptureInfo")) })),
+            "post": with_body(
+                operation("captures", "Start recording several connections", vec![], json!({
+                    "200": ok_json("Capture", schema_ref("CaptureInfo")),
+                    "400": error("Invalid request"),
+                    "409": error("Too many captures"),
+                    "500": error("Connection not found")
+                })),
+                json_body(schema_ref("CaptureRequest")),
+            )
+        }),
+    );
+    let capture_id = || path_param("id", "Capture id", json!({ "type": "integer" }));
+    add(
+        "/api/captures/{id}",
+        json!({
+            "get": operation("captures", "Capture with its records in time order", vec![capture_id()], json!({
+                "200": ok_json("Capture", schema_ref("CaptureBundle")),
+                "404": error("Capture not found")
+            })),
+            "delete": operation("captures", "Delete a capture", vec![capture_id()], json!({
+                "200": ok_text("Capture deleted"),
+                "404": error("Capture not found")
+            }))
+        }),
+    );
+    add(
+        "/api/captures/{id}/stop",
+        json!({
+            "post": operation("captures", "Stop a running capture", vec![capture_id()], json!({
+                "200": ok_text("Capture stopped"),
+                "404": error("Capture not found")
+            }))
+        }),
+    );
+    add(
+        "/api/virtual-pairs",
+        json!({
+            "get": operation("virtual pairs", "List virtual null-modem pairs", vec![],
+                json!({ "200": ok_json("Pairs", array_of("VirtualPairInfo")) })),
+            "post": with_body(
+                operation("virtual pairs", "Create two connections wired to each other", vec![], json!({
+                    "200": ok_json("Pair", schema_ref("VirtualPairInfo")),
+                    "400": error("Invalid request"),
+                    "409": error("Pair or connection name in use")
+                })),
+                json_body(schema_ref("VirtualPairRequest")),
+            )
+        }),
+    );
+    add(
+        "/api/virtual-pairs/{name}",
+        json!({
+            "delete": operation("virtual pairs", "Remove a pair and both connections", vec![
+                path_param("name", "Pair name", json!({ "type": "string" }))
+            ], json!({
+                "200": ok_text("Virtual pair removed"),
+                "404": error("Pair not found")
+            }))
+        }),
+    );
+    add(
+        "/api/sniffers",
+        json!({
+            "get": operation("sniffers", "List sniffers", vec![],
+                json!({ "200": ok_json("Sniffers", array_of("SnifferInfo")) })),
+            "post": with_body(
+                operation("sniffers", "Merge two connections tapping a link's TX and RX lines", vec![], json!({
+                    "200": ok_json("Sniffer", schema_ref("SnifferInfo")),
+                    "400": error("Invalid request"),
+                    "409": error("Sniffer name in use"),
+                    "500": error("Connection not found or capture file not writable")
+                })),
+                json_body(schema_ref("SnifferConfig")),
+            )
+        }),
+    );
+    add(
+        "/api/sniffers/{name}",
+        json!({
+            "delete": operation("sniffers", "Stop a sniffer; its connections keep running", vec![
+                path_param("name", "Sniffer name", json!({ "type": "string" }))
+            ], json!({
+                "200": ok_text("Sniffer removed"),
+                "404": error("Sniffer not found")
+            }))
+        }),
+    );
+    add(
+        "/api/sniffers/{name}/ws",
+        json!({
+            "get": {
+                "tags": ["sniffers"],
+                "summary": "WebSocket stream of the merged records",
+                "description": "Sends each `SnifferRecord` as a JSON text message with `\"type\": \"record\"`, and `{\"type\": \"lagged\", \"skipped\": n}` when the client fell behind. Closes with `connection-removed` when the sniffer is removed.",
+                "parameters": [path_param("name", "Sniffer name", json!({ "type": "string" }))],
+                "responses": {
+                    "101": { "description": "Switching to the WebSocket protocol" },
+                    "403": error("Origin not allowed"),
+                    "404": error("Sniffer not found")
+                }
+            }
+        }),
+    );
+    add(
+        "/api/openapi.json",
+        json!({
+            "get": operation("server", "This document", vec![],
+                json!({ "200": ok_json("OpenAPI document", json!({ "type": "object" })) }))
+        }),
+    );
+    // Only built with the `chaos` feature
+    #[cfg(feature = "chaos")]
+    {
+        let settings = json!({
+            "write_delay_ms": { "type": "integer", "minimum": 0, "maximum": 60000, "default": 0, "description": "Held before each write goes out" },
+            "drop_every": { "type": "integer", "minimum": 0, "default": 0, "description": "Drop one in every this many received chunks; 0 drops none" }
+        });
+        let mut status = settings.clone();
+        status["read_error_pending"] = json!({ "type": "boolean" });
+        status["dropped_chunks"] = json!({ "type": "integer", "format": "int64" });
+        status["read_errors"] = json!({ "type": "integer", "format": "int64" });
+        let status = ok_json(
+            "Faults in effect",
+            object(
+                status,
+                &[
+                    "write_delay_ms",
+                    "drop_every",
+                    "read_error_pending",
+                    "dropped_chunks",
+                    "read_errors",
+                ],
+            ),
+        );
+        add(
+            "/api/connections/{name}/faults",
+            json!({
+                "get": operation("chaos", "Faults injected into the connection", vec![name()], json!({
+                    "200": status.clone(),
+                    "500": not_found()
+                })),
+                "put": with_body(
+                    operation("chaos", "Delay writes and drop received chunks", vec![name()], json!({
+                        "200": status.clone(),
+                        "400": error("Invalid settings"),
+                        "500": not_found()
+                    })),
+                    json_body(object(settings, &[])),
+                ),
+                "delete": operation("chaos", "Stop injecting faults", vec![name()], json!({
+                    "200": status.clone(),
+                    "500": not_found()
+                }))
+            }),
+        );
+        add(
+            "/api/connections/{name}/faults/read-error",
+            json!({
+                "post": operation("chaos", "Fail the next read, losing the connection", vec![name()], json!({
+                    "200": status,
+                    "500": not_found()
+                }))
+            }),
+        );
+    }
+
+    Value::Object(paths)
+}
+
+/// The complete OpenAPI document.
+pub fn spec() -> Value {
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "webmux",
+            "description": "Web-based serial port multiplexer",
+            "version": env!("CARGO_PKG_VERSION")
+        },
+        "paths": paths(),
+        "components": { "schemas": schemas() }
+    })
+}
@@ -0,0 +1,31 @@
+//! `Origin` checks for WebSocket upgrades. CORS does not apply to
+//! WebSockets, so without this any page a user visits could open a session
+//! riding on their cookies (cross-site WebSocket hijacking).
+
+/// Entry in `allowed_origins` matching pages served by webmux itself.
+pub const SAME_ORIGIN: &str = "same-origin";
+
+fn normalize(origin: &str) -> String {
+    origin.trim().trim_end_matches('/').to_ascii_lowercase()
+}
+
+/// Whether an upgrade carrying `origin` may proceed. Requests without an
+/// `Origin` header come from non-browser clients and are always allowed.
+pub fn is_allowed(allowed: &[String], origin: Option<&str>, host: Option<&str>) -> bool {
+    if allowed.is_empty() {
+        return true;
+    }
+    let Some(origin) = origin else {
+        return true;
+    };
+
+    let origin = normalize(origin);
+    allowed.iter().any(|entry| {
+        if entry == SAME_ORIGIN {
+            let authority = origin.split_once("://").map(|(_, rest)| rest);
+            host.is_some_and(|host| authority == Some(host.to_ascii_lowercase().as_str()))
+        } else {
+            normalize(entry) == origin
+        }
+    })
+}
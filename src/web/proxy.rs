@@ -0,0 +1,280 @@
+//! Client addresses behind reverse proxies (`server.proxy`). Without this
+//! every session appears to come from the proxy: `X-Forwarded-For` is
+//! honoured when a trusted proxy sends it, and the main listener can
+//! instead read the PROXY protocol header HAProxy and nginx put in front
+//! of each connection.
+
+use crate::config::ProxyConfig;
+use anyhow::Result;
+use axum::extract::ConnectInfo;
+use axum::http::HeaderMap;
+use axum::{Extension, Router};
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::net::TcpListener;
+use tower::Layer;
+use tracing::{debug, error};
+
+/// Header listing the addresses a request was forwarded for, client first.
+pub const FORWARDED_FOR_HEADER: &str = "x-forwarded-for";
+
+/// How long a connection may take to send its PROXY header.
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Pause after a failed accept, such as when out of file descriptors,
+/// as `axum::serve` makes.
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// Longest PROXY protocol v1 line, `\r\n` included.
+const MAX_V1_LENGTH: usize = 107;
+
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Addresses and CIDR ranges of the proxies allowed to name the client.
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    ranges: Vec<(IpAddr, u8)>,
+}
+
+impl TrustedProxies {
+    /// Parses entries such as `10.0.0.5`, `10.0.0.0/8` or `fd00::/8`.
+    pub fn parse(entries: &[String]) -> Result<Self> {
+        let ranges = entries
+            .iter()
+            .map(|entry| {
+                let (address, prefix) = match entry.split_once('/') {
+                    Some((address, prefix)) => (address, Some(prefix)),
+                    None => (entry.as_str(), None),
+                };
+                let address: IpAddr = address
+                    .parse()
+                    .map_err(|_| anyhow::anyhow!("invalid trusted proxy {}", entry))?;
+                let max = if address.is_ipv4() { 32 } else { 128 };
+                let prefix = match prefix {
+                    Some(prefix) => prefix
+                        .parse::<u8>()
+                        .ok()
+                        .filter(|prefix| *prefix <= max)
+                        .ok_or_else(|| anyhow::anyhow!("invalid prefix length in {}", entry))?,
+                    None => max,
+                };
+                Ok((address, prefix))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { ranges })
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = canonical(address);
+        self.ranges
+            .iter()
+            .any(|&(network, prefix)| match (network, address) {
+                (IpAddr::V4(network), IpAddr::V4(address)) => {
+                    let mask = u32::MAX.checked_shl(32 - u32::from(prefix)).unwrap_or(0);
+                    u32::from(network) & mask == u32::from(address) & mask
+                }
+                (IpAddr::V6(network), IpAddr::V6(address)) => {
+                    let mask = u128::MAX.checked_shl(128 - u32::from(prefix)).unwrap_or(0);
+                    u128::from(network) & mask == u128::from(address) & mask
+                }
+                _ => false,
+            })
+    }
+
+    /// The client a request from `peer` is on behalf of: the nearest
+    /// `X-Forwarded-For` entry not itself a trusted proxy. Requests from
+    /// untrusted peers are taken at face value.
+    pub fn client_address(&self, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+        let peer = canonical(peer);
+        if !self.contains(peer) {
+            return peer;
+        }
+        let mut client = peer;
+        // Each proxy appends the address it got the request from
+        for value in headers.get_all(FORWARDED_FOR_HEADER).iter().rev() {
+            let Ok(value) = value.to_str() else {
+                return client;
+            };
+            for entry in value.rsplit(',') {
+                let Some(address) = parse_forwarded(entry.trim()) else {
+                    return client;
+                };
+                client = address;
+                if !self.contains(address) {
+                    return address;
+                }
+            }
+        }
+        client
+    }
+}
+
+/// IPv4 clients reached over an IPv6 socket show up as `::ffff:a.b.c.d`.
+fn canonical(address: IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        v4 => v4,
+    }
+}
+
+/// An `X-Forwarded-For` entry, which some proxies write with a port.
+fn parse_forwarded(entry: &str) -> Option<IpAddr> {
+    if let Ok(address) = entry.parse::<IpAddr>() {
+        return Some(canonical(address));
+    }
+    entry
+        .parse::<SocketAddr>()
+        .ok()
+        .map(|address| canonical(address.ip()))
+}
+
+/// Reads a PROXY protocol header off the front of a connection, returning
+/// the client's address, or `None` for health checks the proxy makes on
+/// its own behalf (`UNKNOWN` and `LOCAL`). Nothing past the header is read.
+pub async fn read_header<R: AsyncRead + Unpin>(reader: &mut R) -> Result<Option<SocketAddr>> {
+    // Both versions are at least this long: v2's signature, v1's `PROXY UNKNOWN\r\n`
+    let mut start = [0u8; 12];
+    reader.read_exact(&mut start).await?;
+
+    if start == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        reader.read_exact(&mut fixed).await?;
+        let length = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).await?;
+        return parse_v2(fixed[0], fixed[1], &body);
+    }
+
+    if !start.starts_with(b"PROXY ") {
+        anyhow::bail!("connection did not start with a PROXY protocol header");
+    }
+    let mut line = start.to_vec();
+    while !line.ends_with(b"\r\n") {
+        if line.len() == MAX_V1_LENGTH {
+            anyhow::bail!("PROXY protocol v1 header is too long");
+        }
+        line.push(reader.read_u8().await?);
+    }
+    parse_v1(std::str::from_utf8(&line)?)
+}
+
+/// `PROXY TCP4 <source> <destination> <source port> <destination port>\r\n`.
+pub fn parse_v1(line: &str) -> Result<Option<SocketAddr>> {
+    let fields: Vec<&str> = line.trim_end_matches("\r\n").split(' ').collect();
+    match fields.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(None),
+        ["PROXY", family @ ("TCP4" | "TCP6"), source, _, port, _] => {
+            let address: IpAddr = source
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid source address {}", source))?;
+            if address.is_ipv4() != (*family == "TCP4") {
+                anyhow::bail!("source address {} is not {}", source, family);
+            }
+            let port: u16 = port
+                .parse()
+                .map_err(|_| anyhow::anyhow!("invalid source port {}", port))?;
+            Ok(Some(SocketAddr::new(address, port)))
+        }
+        _ => anyhow::bail!("malformed PROXY protocol v1 header"),
+    }
+}
+
+/// The binary header's version and command byte, family byte and the
+/// address block after them.
+pub fn parse_v2(version_command: u8, family: u8, body: &[u8]) -> Result<Option<SocketAddr>> {
+    if version_command >> 4 != 2 {
+        anyhow::bail!(
+            "unsupported PROXY protocol version {}",
+            version_command >> 4
+        );
+    }
+    match version_command & 0x0f {
+        0 => return Ok(None),
+        1 => {}
+        command => anyhow::bail!("unknown PROXY protocol command {}", command),
+    }
+    let truncated = || anyhow::anyhow!("PROXY protocol v2 address block is truncated");
+    match family >> 4 {
+        // AF_INET: source, destination, source port, destination port
+        1 => {
+            let block: [u8; 12] = body.get(..12).ok_or_else(truncated)?.try_into()?;
+            let address = std::net::Ipv4Addr::new(block[0], block[1], block[2], block[3]);
+            let port = u16::from_be_bytes([block[8], block[9]]);
+            Ok(Some(SocketAddr::new(address.into(), port)))
+        }
+        // AF_INET6
+        2 => {
+            let block: [u8; 36] = body.get(..36).ok_or_else(truncated)?.try_into()?;
+            let source: [u8; 16] = block[..16].try_into()?;
+            let port = u16::from_be_bytes([block[32], block[33]]);
+            Ok(Some(SocketAddr::new(
+                std::net::Ipv6Addr::from(source).into(),
+                port,
+            )))
+        }
+        // AF_UNSPEC and AF_UNIX carry no address worth keeping
+        _ => Ok(None),
+    }
+}
+
+/// Serves `app` on `listener`, reading a PROXY header from each connection
+/// first and handing handlers the client's address as [`ConnectInfo`],
+/// until `shutdown` completes. Connections from peers outside `trusted`
+/// are refused.
+pub async fn serve_proxy_protocol<F>(
+    listener: TcpListener,
+    app: Router,
+    config: &ProxyConfig,
+    shutdown: F,
+) -> Result<()>
+where
+    F: Future<Output = ()>,
+{
+    use hyper_util::rt::{TokioExecutor, TokioIo};
+    use hyper_util::server::conn::auto::Builder;
+    use hyper_util::service::TowerToHyperService;
+
+    let trusted = TrustedProxies::parse(&config.trusted)?;
+    tokio::pin!(shutdown);
+    loop {
+        let (mut stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    error!("Accept failed: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_DELAY).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => return Ok(()),
+        };
+        if !trusted.contains(peer.ip()) {
+            debug!("Refused connection from {}: not a trusted proxy", peer);
+            continue;
+        }
+        let app = app.clone();
+        tokio::spawn(async move {
+            let client = match tokio::time::timeout(HEADER_TIMEOUT, read_header(&mut stream)).await
+            {
+                Ok(Ok(client)) => client.unwrap_or(peer),
+                Ok(Err(e)) => {
+                    debug!("Dropped connection from {}: {}", peer, e);
+                    return;
+                }
+                Err(_) => {
+                    debug!("Dropped connection from {}: no PROXY header", peer);
+                    return;
+                }
+            };
+            let service = TowerToHyperService::new(Extension(ConnectInfo(client)).layer(app));
+            let _ = Builder::new(TokioExecutor::new())
+                .serve_connection_with_upgrades(TokioIo::new(stream), service)
+                .await;
+        });
+    }
+}
@@ -0,0 +1,314 @@
+//! Registry of open WebSocket sessions, so operators can see who is
+//! attached to a connection and disconnect them.
+
+use super::close::CloseReason;
+use super::write_lock::WriteLocks;
+use crate::logging::audit::{AuditEvent, AuditLog, AuditSession};
+use crate::trace::TraceContext;
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, oneshot, Notify, RwLock};
+
+/// Header a client can use instead of the `client` query parameter.
+pub const CLIENT_HEADER: &str = "x-webmux-client";
+
+/// Longest accepted client label.
+pub const MAX_CLIENT_LABEL: usize = 64;
+
+/// Writes buffered for slow sessions before they miss some.
+const WRITE_CAPACITY: usize = 256;
+
+static NEXT_SESSION_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub connection: String,
+    /// Label the client gave itself, e.g. `buildbot-3`.
+    pub client: Option<String>,
+    /// Where the client connected from, behind any trusted proxies;
+    /// absent for local attach sessions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub address: Option<IpAddr>,
+    pub connected_at: DateTime<Utc>,
+    /// Set for viewers, whose input is never written.
+    pub read_only: bool,
+    /// The session's span in its connection's trace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trace: Option<TraceContext>,
+}
+
+impl SessionInfo {
+    pub fn audit(&self) -> AuditSession<'_> {
+        AuditSession {
+            id: self.id,
+            connection: &self.connection,
+            client: self.client.as_deref(),
+            address: self.address,
+            trace: self.trace.as_ref(),
+        }
+    }
+}
+
+/// Human-readable session reference for log lines.
+impl std::fmt::Display for SessionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.client {
+            Some(ref client) => write!(f, "session {} ({})", self.id, client)?,
+            None => write!(f, "session {}", self.id)?,
+        }
+        match self.address {
+            Some(address) => write!(f, " from {}", address),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Client labels end up in logs and listings, so keep them short and printable.
+pub fn validate_client_label(label: &str) -> Result<()> {
+    if label.is_empty() || label.len() > MAX_CLIENT_LABEL {
+        anyhow::bail!(
+            "client label must be between 1 and {} characters",
+            MAX_CLIENT_LABEL
+        );
+    }
+    if label.chars().any(char::is_control) {
+        anyhow::bail!("client label must not contain control characters");
+    }
+    Ok(())
+}
+
+/// Caps on concurrent sessions; `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SessionCaps {
+    pub per_connection: Option<usize>,
+    pub total: Option<usize>,
+    /// Sessions from one client address, across connections.
+    pub per_address: Option<usize>,
+}
+
+struct Session {
+    info: SessionInfo,
+    /// Taken once the session has been told to close.
+    kick_tx: Option<oneshot::Sender<CloseReason>>,
+}
+
+/// What a session wrote to its connection, told to the other sessions so
+/// everyone watching sees both sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionWrite {
+    pub connection: String,
+    pub session: u64,
+    pub client: Option<String>,
+    /// Scrubbed on connections with `logging.redact`.
+    pub data: Vec<u8>,
+}
+
+#[derive(Clone)]
+pub struct SessionWrites {
+    events: broadcast::Sender<SessionWrite>,
+}
+
+impl Default for SessionWrites {
+    fn default() -> Self {
+        Self {
+            events: broadcast::channel(WRITE_CAPACITY).0,
+        }
+    }
+}
+
+impl SessionWrites {
+    pub fn subscribe(&self) -> broadcast::Receiver<SessionWrite> {
+        self.events.subscribe()
+    }
+
+    /// Tells the other sessions on `info`'s connection that it wrote `data`.
+    pub fn wrote(&self, info: &SessionInfo, data: Vec<u8>) {
+        // Nobody may be watching, which is fine
+        let _ = self.events.send(SessionWrite {
+            connection: info.connection.clone(),
+            session: info.id,
+            client: info.client.clone(),
+            data,
+        });
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct SessionRegistry {
+    sessions: Arc<RwLock<HashMap<u64, Session>>>,
+    /// Woken whenever a session unregisters.
+    unregistered: Arc<Notify>,
+    audit: AuditLog,
+    write_locks: WriteLocks,
+    writes: SessionWrites,
+}
+
+fn check_caps(
+    sessions: &HashMap<u64, Session>,
+    connection: &str,
+    address: Option<IpAddr>,
+    caps: SessionCaps,
+) -> Result<()> {
+    if let Some(max) = caps.per_connection {
+        let open = sessions
+            .values()
+            .filter(|session| session.info.connection == connection)
+            .count();
+        if open >= max {
+            anyhow::bail!(
+                "Connection {} already has the maximum of {} clients",
+                connection,
+                max
+            );
+        }
+    }
+    if let Some(max) = caps.total {
+        if sessions.len() >= max {
+            anyhow::bail!("Server already has the maximum of {} clients", max);
+        }
+    }
+    if let (Some(max), Some(address)) = (caps.per_address, address) {
+        let open = sessions
+            .values()
+            .filter(|session| session.info.address == Some(address))
+            .count();
+        if open >= max {
+            anyhow::bail!(
+                "Client {} already has the maximum of {} sessions",
+                address,
+                max
+            );
+        }
+    }
+    Ok(())
+}
+
+impl SessionRegistry {
+    /// A registry recording session events in `audit`.
+    pub fn with_audit(audit: AuditLog) -> Self {
+        Self {
+            audit,
+            ..Self::default()
+        }
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    pub fn write_locks(&self) -> &WriteLocks {
+        &self.write_locks
+    }
+
+    pub fn writes(&self) -> &SessionWrites {
+        &self.writes
+    }
+
+    /// Fails if another session on `connection` from `address` would
+    /// exceed `caps`.
+    pub async fn check_capacity(
+        &self,
+        connection: &str,
+        address: Option<IpAddr>,
+        caps: SessionCaps,
+    ) -> Result<()> {
+        check_caps(&*self.sessions.read().await, connection, address, caps)
+    }
+
+    /// Records a new session unless that would exceed `caps`, as a span in
+    /// the connection's `trace`; the receiver fires with the reason if it
+    /// must close.
+    pub async fn register(
+        &self,
+        connection: &str,
+        client: Option<String>,
+        address: Option<IpAddr>,
+        read_only: bool,
+        trace: Option<&TraceContext>,
+        caps: SessionCaps,
+    ) -> Result<(SessionInfo, oneshot::Receiver<CloseReason>)> {
+        let mut sessions = self.sessions.write().await;
+        check_caps(&sessions, connection, address, caps)?;
+
+        let info = SessionInfo {
+            id: NEXT_SESSION_ID.fetch_add(1, Ordering::Relaxed),
+            connection: connection.to_string(),
+            client,
+            address,
+            connected_at: Utc::now(),
+            read_only,
+            trace: trace.map(TraceContext::child),
+        };
+        let (kick_tx, kick_rx) = oneshot::channel();
+        sessions.insert(
+            info.id,
+            Session {
+                info: info.clone(),
+                kick_tx: Some(kick_tx),
+            },
+        );
+        drop(sessions);
+        self.audit.record(AuditEvent::Opened, info.audit()).await;
+        Ok((info, kick_rx))
+    }
+
+    /// Forgets a session once it has ended.
+    pub async fn unregister(&self, info: &SessionInfo) {
+        self.write_locks.session_ended(info).await;
+        self.audit.record(AuditEvent::Closed, info.audit()).await;
+        self.sessions.write().await.remove(&info.id);
+        self.unregistered.notify_waiters();
+    }
+
+    /// Open sessions, optionally only those on one connection.
+    pub async fn list(&self, connection: Option<&str>) -> Vec<SessionInfo> {
+        let sessions = self.sessions.read().await;
+        let mut list: Vec<SessionInfo> = sessions
+            .values()
+            .filter(|session| connection.is_none_or(|name| session.info.connection == name))
+            .map(|session| session.info.clone())
+            .collect();
+        list.sort_by_key(|info| info.id);
+        list
+    }
+
+    /// Disconnects a session, returning it if it was open.
+    pub async fn kick(&self, id: u64) -> Option<SessionInfo> {
+        let session = self.sessions.write().await.remove(&id)?;
+        if let Some(kick_tx) = session.kick_tx {
+            let _ = kick_tx.send(CloseReason::Kicked);
+        }
+        self.audit
+            .record(AuditEvent::Kicked, session.info.audit())
+            .await;
+        Some(session.info)
+    }
+
+    /// Tells every session to close with `reason`, then waits up to
+    /// `grace` for them to send their close frames and unregister.
+    pub async fn close_all(&self, reason: CloseReason, grace: Duration) {
+        for session in self.sessions.write().await.values_mut() {
+            if let Some(kick_tx) = session.kick_tx.take() {
+                let _ = kick_tx.send(reason);
+            }
+        }
+        let _ = tokio::time::timeout(grace, async {
+            loop {
+                // Created before the check so a notification in between is kept
+                let unregistered = self.unregistered.notified();
+                if self.sessions.read().await.is_empty() {
+                    return;
+                }
+                unregistered.await;
+            }
+        })
+        .await;
+    }
+}
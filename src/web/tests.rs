@@ -38,6 +38,55 @@ async fn test_health_check() {
     assert_eq!(body, "OK");
 }
 
+#[tokio::test]
+async fn test_health_json_counts_connection_states() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+    let health = || async {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/health")
+                    .header("Accept", "application/json")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        (status, body_to_json(response.into_body()).await)
+    };
+
+    let (status, json) = health().await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(json["status"], "ok");
+    assert_eq!(json["connected"], 2);
+    assert_eq!(json["problems"], serde_json::json!([]));
+
+    // Hanging up the far end loses nm-a's port, leaving nothing up
+    serial_manager.remove_connection("nm-b").await.unwrap();
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    while serial_manager.get_stats("nm-a").await.unwrap().is_connected {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let (status, json) = health().await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(json["status"], "down");
+    assert_eq!(json["disconnected"], 1);
+    assert_eq!(json["problems"][0]["name"], "nm-a");
+    assert_eq!(json["problems"][0]["state"], "disconnected");
+}
+
 #[tokio::test]
 async fn test_list_connections_empty() {
     let serial_manager = SerialManager::new();
@@ -184,6 +233,7 @@ async fn test_cors_headers() {
 async fn test_api_error_serialization() {
     let error = ApiError {
         error: "Test error".to_string(),
+        status: StatusCode::INTERNAL_SERVER_ERROR,
     };
     let json = serde_json::to_string(&error).unwrap();
     assert!(json.contains("Test error"));
@@ -195,3 +245,3424 @@ async fn test_api_error_from_anyhow() {
     let api_error: ApiError = anyhow_error.into();
     assert_eq!(api_error.error, "Something went wrong");
 }
+
+#[tokio::test]
+async fn test_send_can_frame_connection_not_found() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let payload = serde_json::json!({
+        "id": 291,
+        "data": "DEADBEEF"
+    });
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nonexistent/can/send")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(serde_json::to_string(&payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let json = body_to_json(response.into_body()).await;
+    assert!(json["error"]
+        .as_str()
+        .unwrap()
+        .contains("Connection not found"));
+}
+
+#[tokio::test]
+async fn test_api_error_bad_request_status() {
+    let response = ApiError::bad_request("nope").into_response();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["error"], "nope");
+}
+
+#[tokio::test]
+async fn test_flash_esp_rejects_bad_offset() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nonexistent/flash/esp?offset=0xnope")
+                .method("POST")
+                .header("content-type", "application/octet-stream")
+                .body(Body::from(vec![0u8; 16]))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_flash_esp_connection_not_found() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nonexistent/flash/esp?chip=esp8266&offset=0x0")
+                .method("POST")
+                .header("content-type", "application/octet-stream")
+                .body(Body::from(vec![0u8; 16]))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let json = body_to_json(response.into_body()).await;
+    assert!(json["error"]
+        .as_str()
+        .unwrap()
+        .contains("Connection not found"));
+}
+
+#[tokio::test]
+async fn test_flash_avr_rejects_invalid_hex() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nonexistent/flash/avr?protocol=stk500v2")
+                .method("POST")
+                .body(Body::from("not a hex file"))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_lease_validation_and_lookup() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/test_device/lease")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"mode": "tcp", "duration_secs": 0}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/test_device/lease")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"mode": "release"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    let json = body_to_json(response.into_body()).await;
+    assert!(json["error"]
+        .as_str()
+        .unwrap()
+        .contains("Connection not found"));
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/test_device/lease")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_tcp_lease_binds_configured_host() {
+    let (_device, slave) = tokio_serial::SerialStream::pair().unwrap();
+    let path = serialport::SerialPort::name(&slave).unwrap();
+    std::mem::forget(slave);
+    let serial_manager = SerialManager::new();
+    let request: crate::serial::ephemeral::ConnectionRequest =
+        serde_json::from_value(serde_json::json!({"name": "esp", "port": path})).unwrap();
+    serial_manager
+        .add_connection(request.config().unwrap())
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+
+    // The listener is not the caller's to place
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/esp/lease")
+                .method("POST")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"mode": "tcp", "bind_host": "0.0.0.0"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    let address = json["tcp_address"].as_str().unwrap();
+    assert!(address.starts_with("127.0.0.1:"), "{}", address);
+
+    serial_manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_metrics_connection_not_found() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    for uri in [
+        "/api/connections/nonexistent/metrics",
+        "/api/connections/nonexistent/metrics/temp?limit=10",
+    ] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let json = body_to_json(response.into_body()).await;
+        assert!(json["error"]
+            .as_str()
+            .unwrap()
+            .contains("Connection not found"));
+    }
+}
+
+#[tokio::test]
+async fn test_query_endpoint() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (mut device, slave) = tokio_serial::SerialStream::pair().unwrap();
+    let path = serialport::SerialPort::name(&slave).unwrap();
+    std::mem::forget(slave);
+
+    let serial_manager = SerialManager::new();
+    let request: crate::serial::ephemeral::ConnectionRequest =
+        serde_json::from_value(serde_json::json!({"name": "plc", "port": path})).unwrap();
+    let mut config = request.config().unwrap();
+    config.endpoints = vec![crate::config::EndpointConfig {
+        name: "pressure".to_string(),
+        command: "P?\r".to_string(),
+        pattern: r"^P=(\S+)".to_string(),
+        timeout_ms: 200,
+        poll_interval_ms: None,
+    }];
+    serial_manager.add_connection(config).await.unwrap();
+    let app = create_router(serial_manager);
+    let responder = tokio::spawn(async move {
+        let mut buf = [0u8; 16];
+        let n = device.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"P?\r");
+        device.write_all(b"P=2.75\r\n").await.unwrap();
+        device
+    });
+
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let response = app
+        .clone()
+        .oneshot(get("/api/connections/plc/pressure"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["value"], "2.75");
+    assert_eq!(json["number"], 2.75);
+    assert_eq!(json["line"], "P=2.75");
+    // Kept open, but silent from here on
+    let _device = responder.await.unwrap();
+
+    // The device has stopped answering
+    let response = app
+        .clone()
+        .oneshot(get("/api/connections/plc/pressure"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+    let response = app
+        .clone()
+        .oneshot(get("/api/connections/plc/flow"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    // Built-in routes are not shadowed
+    let response = app
+        .oneshot(get("/api/connections/plc/stats"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_server_stats_empty() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/stats")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["connections"], 0);
+    assert_eq!(json["bytes_received"], 0);
+    assert_eq!(json["log_disk_usage"]["total_bytes"], 0);
+    assert_eq!(json["log_disk_usage"]["connections"], serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_prometheus_metrics_histograms() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections/nm-a/send")
+                .header("content-type", "application/json")
+                .body(Body::from(r#"{"data":"hi"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/metrics")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"],
+        "text/plain; version=0.0.4"
+    );
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    let text = String::from_utf8(body.to_vec()).unwrap();
+    assert!(text.contains("# TYPE webmux_request_duration_seconds histogram\n"));
+    assert!(text
+        .contains("webmux_request_duration_seconds_count{connection=\"nm-a\",route=\"send\"} 1\n"));
+    assert!(!text.contains("connection=\"nm-b\",route="));
+    assert!(text.contains("# TYPE webmux_frame_bytes histogram\n"));
+}
+
+#[tokio::test]
+async fn test_tail_returns_recent_frames() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+
+    let mut b_rx = serial_manager.subscribe("nm-b").await.unwrap();
+    serial_manager.send_data("nm-a", b"AT\r\n").await.unwrap();
+    b_rx.recv().await.unwrap();
+    serial_manager.send_data("nm-b", b"OK\r\n").await.unwrap();
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    while serial_manager
+        .get_stats("nm-a")
+        .await
+        .unwrap()
+        .bytes_received
+        < 4
+    {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let tail = |uri: &'static str| {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+    let response = tail("/api/connections/nm-a/tail").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let records = body_to_json(response.into_body()).await;
+    assert_eq!(records[0]["direction"], "tx");
+    assert_eq!(records[0]["text"], "AT\r\n");
+    assert_eq!(records[1]["direction"], "rx");
+    assert_eq!(records[1]["hex"], "4f4b0d0a");
+
+    let response = tail("/api/connections/nm-a/tail?direction=rx&lines=5")
+        .await
+        .unwrap();
+    let records = body_to_json(response.into_body()).await;
+    assert_eq!(records.as_array().unwrap().len(), 1);
+    assert_eq!(records[0]["text"], "OK\r\n");
+
+    for uri in [
+        "/api/connections/nm-a/tail?lines=0",
+        "/api/connections/nm-a/tail?direction=up",
+    ] {
+        let response = tail(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_hexdump_of_recent_traffic() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+
+    let mut a_rx = serial_manager.subscribe("nm-a").await.unwrap();
+    serial_manager
+        .send_data("nm-b", b"\x01\x03hello world\r\n\xff")
+        .await
+        .unwrap();
+    a_rx.recv().await.unwrap();
+
+    let hexdump = |uri: &'static str| {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+    let response = hexdump("/api/connections/nm-a/hexdump").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()["content-type"]
+        .to_str()
+        .unwrap()
+        .starts_with("text/plain"));
+    assert_eq!(
+        body_to_string(response.into_body()).await,
+        "00000000  01 03 68 65 6c 6c 6f 20  77 6f 72 6c 64 0d 0a ff  |..hello world...|\n\
+         00000010\n"
+    );
+
+    let response = hexdump("/api/connections/nm-a/hexdump?bytes=3")
+        .await
+        .unwrap();
+    assert_eq!(
+        body_to_string(response.into_body()).await,
+        "00000000  0d 0a ff                                          |...|\n00000003\n"
+    );
+
+    // Nothing was sent on this side
+    let response = hexdump("/api/connections/nm-a/hexdump?direction=tx")
+        .await
+        .unwrap();
+    assert_eq!(body_to_string(response.into_body()).await, "");
+
+    for uri in [
+        "/api/connections/nm-a/hexdump?bytes=0",
+        "/api/connections/nm-a/hexdump?bytes=65537",
+    ] {
+        let response = hexdump(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn test_sequence_runs_steps_without_interleaving() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+    let sequence = |body: &'static str| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections/nm-a/sequence")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body))
+                .unwrap(),
+        )
+    };
+
+    // The far end answers the handshake and records everything it gets
+    let mut b_rx = serial_manager.subscribe("nm-b").await.unwrap();
+    let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+    let device = serial_manager.clone();
+    tokio::spawn(async move {
+        while let Ok(data) = b_rx.recv().await {
+            if data.ends_with(b"HELLO\r") {
+                device.send_data("nm-b", b"READY\r\n").await.unwrap();
+            }
+            seen_tx.send(data).unwrap();
+        }
+    });
+
+    let writer = serial_manager.clone();
+    let interloper = tokio::spawn(async move {
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        writer.send_data("nm-a", b"other").await.unwrap();
+    });
+    let response = sequence(
+        r#"{"steps": [
+            {"data": "HELLO\r", "expect": "READY", "delay_ms": 200},
+            {"data": "4b4559", "format": "hex"}
+        ]}"#,
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let report = body_to_json(response.into_body()).await;
+    assert_eq!(report["completed"], true);
+    assert_eq!(report["steps"][0]["matched"], true);
+    assert_eq!(report["steps"][1]["matched"], serde_json::Value::Null);
+    assert_eq!(report["steps"][1]["bytes_written"], 3);
+    interloper.await.unwrap();
+
+    let mut seen = Vec::new();
+    while seen.len() < b"HELLO\rKEYother".len() {
+        seen.extend(seen_rx.recv().await.unwrap());
+    }
+    assert_eq!(seen, b"HELLO\rKEYother");
+
+    // A reply that never comes ends the sequence
+    let response = sequence(
+        r#"{"steps": [
+            {"data": "x", "expect": "NEVER", "timeout_ms": 50},
+            {"data": "y"}
+        ]}"#,
+    )
+    .await
+    .unwrap();
+    let report = body_to_json(response.into_body()).await;
+    assert_eq!(report["completed"], false);
+    assert_eq!(report["steps"].as_array().unwrap().len(), 1);
+
+    for body in [
+        r#"{"steps": []}"#,
+        r#"{"steps": [{"data": "x", "expect": ""}]}"#,
+        r#"{"steps": [{"data": "x", "timeout_ms": 0}]}"#,
+        r#"{"steps": [{"data": "zz", "format": "hex"}]}"#,
+        // Could hold the transaction for six minutes
+        r#"{"steps": [
+            {"data": "x", "expect": "y", "timeout_ms": 60000, "delay_ms": 60000},
+            {"data": "x", "expect": "y", "timeout_ms": 60000, "delay_ms": 60000},
+            {"data": "x", "expect": "y", "timeout_ms": 60000, "delay_ms": 60000},
+            {"data": "x"}
+        ]}"#,
+    ] {
+        let response = sequence(body).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+    }
+}
+
+#[tokio::test]
+async fn test_frames_page_by_sequence() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+
+    let mut a_rx = serial_manager.subscribe("nm-a").await.unwrap();
+    for line in ["one\n", "two\n", "three\n"] {
+        serial_manager
+            .send_data("nm-b", line.as_bytes())
+            .await
+            .unwrap();
+        a_rx.recv().await.unwrap();
+    }
+
+    let frames = |uri: &'static str| {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+    let response = frames("/api/connections/nm-a/frames?limit=2")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["latest_seq"], 3);
+    assert_eq!(page["gap"], Value::Null);
+    assert_eq!(page["frames"][0]["seq"], 1);
+    assert_eq!(page["frames"][1]["text"], "two\n");
+
+    let response = frames("/api/connections/nm-a/frames?since_seq=2")
+        .await
+        .unwrap();
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["frames"].as_array().unwrap().len(), 1);
+    assert_eq!(page["frames"][0]["hex"], "74687265650a");
+
+    let response = frames("/api/connections/nm-a/frames?since_seq=9")
+        .await
+        .unwrap();
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["gap"]["kind"], "unknown");
+
+    let response = frames("/api/connections/nm-a/frames?limit=0")
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_read_advances_cursor() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+
+    let mut a_rx = serial_manager.subscribe("nm-a").await.unwrap();
+    for line in ["one\n", "two\n"] {
+        serial_manager
+            .send_data("nm-b", line.as_bytes())
+            .await
+            .unwrap();
+        a_rx.recv().await.unwrap();
+    }
+
+    let read = |uri: &'static str| {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+    let response = read("/api/connections/nm-a/read?cursor=hmi").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["frames"].as_array().unwrap().len(), 2);
+    assert_eq!(page["frames"][1]["text"], "two\n");
+
+    let response = read("/api/connections/nm-a/read?cursor=hmi").await.unwrap();
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["frames"].as_array().unwrap().len(), 0);
+    assert_eq!(page["latest_seq"], 2);
+
+    let response = read("/api/connections/nm-a/read").await.unwrap();
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["frames"][0]["seq"], 1);
+
+    for uri in [
+        "/api/connections/nm-a/read?cursor=",
+        "/api/connections/nm-a/read?limit=0",
+    ] {
+        let response = read(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_annotations_returned_with_frames_and_captures() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+    let capture = serial_manager
+        .start_capture(
+            &serde_json::from_value(serde_json::json!({ "connections": ["nm-a"] })).unwrap(),
+        )
+        .await
+        .unwrap();
+
+    let mut a_rx = serial_manager.subscribe("nm-a").await.unwrap();
+    serial_manager.send_data("nm-b", b"boot\n").await.unwrap();
+    a_rx.recv().await.unwrap();
+
+    let annotate = |body: serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections/nm-a/annotations")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+    let response = annotate(serde_json::json!({
+        "text": "started firmware update here",
+        "severity": "warning",
+        "author": "alice"
+    }))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let annotation = body_to_json(response.into_body()).await;
+    assert_eq!(annotation["seq"], 1);
+    assert_eq!(annotation["severity"], "warning");
+
+    for body in [
+        serde_json::json!({ "text": "" }),
+        serde_json::json!({ "text": "late", "seq": 99 }),
+        serde_json::json!({ "text": "both", "seq": 1, "timestamp": "2024-01-15T10:00:00Z" }),
+    ] {
+        let response = annotate(body).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nm-a/frames")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(
+        page["annotations"][0]["text"],
+        "started firmware update here"
+    );
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nm-a/annotations?since_seq=1")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        body_to_json(response.into_body()).await,
+        serde_json::json!([])
+    );
+
+    let bundle = serial_manager.get_capture(capture.id).await.unwrap();
+    assert_eq!(bundle.annotations.len(), 1);
+    assert_eq!(bundle.annotations[0].connection, "nm-a");
+    assert_eq!(
+        bundle.annotations[0].annotation.author.as_deref(),
+        Some("alice")
+    );
+}
+
+#[tokio::test]
+async fn test_poll_waits_for_new_frames() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+    let poll = |uri: &'static str| {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+
+    // Nothing arrives: an empty page once the timeout passes
+    let response = poll("/api/connections/nm-a/poll?timeout=0").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["frames"], serde_json::json!([]));
+    assert_eq!(page["latest_seq"], 0);
+
+    // The request is answered as soon as a chunk arrives
+    let waiting = tokio::spawn(poll("/api/connections/nm-a/poll?since_seq=0&timeout=10"));
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+    assert!(!waiting.is_finished());
+    serial_manager.send_data("nm-b", b"ready\n").await.unwrap();
+    let response = tokio::time::timeout(std::time::Duration::from_secs(5), waiting)
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["frames"][0]["seq"], 1);
+    assert_eq!(page["frames"][0]["text"], "ready\n");
+
+    // Chunks already kept come back without waiting
+    let response = tokio::time::timeout(
+        std::time::Duration::from_secs(5),
+        poll("/api/connections/nm-a/poll?since_seq=0&timeout=60"),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    let page = body_to_json(response.into_body()).await;
+    assert_eq!(page["latest_seq"], 1);
+
+    for uri in [
+        "/api/connections/nm-a/poll?timeout=121",
+        "/api/connections/nm-a/poll?limit=0",
+    ] {
+        let response = poll(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+}
+
+#[tokio::test]
+async fn test_search_validation() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager);
+    let get = |uri: &'static str| {
+        app.clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+    };
+
+    for uri in [
+        "/api/search?q=(",
+        "/api/search?q=ERR&limit=0",
+        "/api/search?q=ERR&offset=100000000",
+        "/api/search?q=ERR&offset=9950&limit=100",
+        "/api/search?q=ERR&connections=missing",
+        // Virtual pairs are not logged
+        "/api/search?q=ERR&connections=nm-a",
+        "/api/search?q=ERR&from=yesterday",
+    ] {
+        let response = get(uri).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", uri);
+    }
+
+    let response = get("/api/search?q=ERR").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["records"], serde_json::json!([]));
+    assert_eq!(json["next_offset"], Value::Null);
+}
+
+#[tokio::test]
+async fn test_upload_writes_body_to_port() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+    let mut device_rx = serial_manager.subscribe("nm-b").await.unwrap();
+    let upload = |body: &'static [u8]| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections/nm-a/upload")
+                .header("Content-Type", "application/octet-stream")
+                .body(Body::from(body))
+                .unwrap(),
+        )
+    };
+
+    let response = upload(b"\x00\x01binary\xff").await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let mut received = Vec::new();
+    while received.len() < 9 {
+        let chunk = tokio::time::timeout(std::time::Duration::from_secs(2), device_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        received.extend_from_slice(&chunk);
+    }
+    assert_eq!(received, b"\x00\x01binary\xff");
+
+    let response = upload(b"").await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_throughput_and_latency_validation() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nonexistent/throughput?window=0")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nonexistent/traffic-profile?window=601")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nonexistent/throughput")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections/nonexistent/latency-test")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"count": 0}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_virtual_pair_lifecycle() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let create = || {
+        Request::builder()
+            .method("POST")
+            .uri("/api/virtual-pairs")
+            .header("Content-Type", "application/json")
+            .body(Body::from(r#"{"name": "nm"}"#))
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(create()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["a"], "nm-a");
+    assert_eq!(json["b"], "nm-b");
+
+    let response = app.clone().oneshot(create()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections/nm-b")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri("/api/virtual-pairs/nm")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/virtual-pairs")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_runtime_connections_with_expiry() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager.clone());
+    let post = |uri: &str, body: &'static str| {
+        Request::builder()
+            .method("POST")
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    for body in [
+        r#"{"name": "tmp", "port": "/dev/nonexistent-webmux"}"#,
+        r#"{"name": "", "port": "/dev/null"}"#,
+        r#"{"name": "tmp", "port": "/dev/null", "expires_in": 0}"#,
+        r#"{"name": "tmp", "port": "/dev/null", "expires_in": 86401}"#,
+    ] {
+        let response = app
+            .clone()
+            .oneshot(post("/api/connections", body))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{}", body);
+    }
+
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/api/virtual-pairs",
+            r#"{"name": "nm", "expires_in": 1}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert!(json["expires_at"].is_string());
+
+    let response = app
+        .clone()
+        .oneshot(post(
+            "/api/connections",
+            r#"{"name": "nm-a", "port": "/dev/null"}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/connections")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    // Only the pair expires, not its ends
+    assert!(json
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|item| item.get("expires_at").is_none()));
+
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(3);
+    while !serial_manager.list_virtual_pairs().await.is_empty() {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+    }
+    assert!(serial_manager.list_connections().await.is_empty());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri("/api/connections/nm-a")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_capture_validation_and_lookup() {
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/captures")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"connections": []}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/captures")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"connections": ["nonexistent"]}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/captures/42")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_sniffer_lifecycle() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager);
+    let create = |body: &'static str| {
+        Request::builder()
+            .method("POST")
+            .uri("/api/sniffers")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(create(r#"{"name": "link", "tx": "nm-a", "rx": "nm-a"}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(create(r#"{"name": "link", "tx": "nm-a", "rx": "nm-b"}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["tx"], "nm-a");
+    assert_eq!(json["running"], true);
+
+    let response = app
+        .clone()
+        .oneshot(create(r#"{"name": "link", "tx": "nm-a", "rx": "nm-b"}"#))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/sniffers")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json.as_array().unwrap().len(), 1);
+
+    let delete = || {
+        Request::builder()
+            .method("DELETE")
+            .uri("/api/sniffers/link")
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app.clone().oneshot(delete()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app.oneshot(delete()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_send_size_limits() {
+    let config = crate::config::ServerConfig {
+        limits: crate::config::LimitsConfig {
+            max_send_bytes: 8,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let app = create_router_with_config(SerialManager::new(), &config);
+
+    let send = |body: String| {
+        Request::builder()
+            .method("POST")
+            .uri("/api/connections/nonexistent/send")
+            .header("Content-Type", "application/json")
+            .body(Body::from(body))
+            .unwrap()
+    };
+
+    // Decoded payload over the limit
+    let response = app
+        .clone()
+        .oneshot(send(r#"{"data": "0123456789"}"#.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(
+        json["error"],
+        "Payload of 10 bytes exceeds the 8 byte limit"
+    );
+
+    // Body over the limit is refused before it is parsed
+    let response = app
+        .clone()
+        .oneshot(send(format!(r#"{{"data": "{}"}}"#, "x".repeat(4096))))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    let json = body_to_json(response.into_body()).await;
+    assert!(json["error"].as_str().unwrap().contains("byte limit"));
+
+    // Within the limit the request reaches the connection lookup
+    let response = app
+        .oneshot(send(r#"{"data": "hi"}"#.to_string()))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn test_websocket_reports_failed_writes() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = create_router(serial_manager.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let (mut ws, _) =
+        tokio_tungstenite::connect_async(format!("ws://{}/api/connections/nm-a/ws", addr))
+            .await
+            .unwrap();
+
+    // Hang up the far end so nm-a's port goes away under the session
+    serial_manager.remove_connection("nm-b").await.unwrap();
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    while serial_manager.get_stats("nm-a").await.unwrap().is_connected {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    for _ in 0..2 {
+        ws.send(WsMessage::Text("AT\r\n".into())).await.unwrap();
+        let reply = tokio::time::timeout(std::time::Duration::from_secs(2), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+        let json: Value = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["code"], "write_failed");
+        assert!(json["message"].as_str().unwrap().starts_with("4 bytes"));
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_hex_encoding() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = create_router(serial_manager.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?encoding=hex",
+        addr
+    ))
+    .await
+    .unwrap();
+    let mut b_rx = serial_manager.subscribe("nm-b").await.unwrap();
+    let timeout = std::time::Duration::from_secs(2);
+
+    // Hex text from the client is decoded before it is written
+    ws.send(WsMessage::Text("01 03 00 0a".into()))
+        .await
+        .unwrap();
+    let written = tokio::time::timeout(timeout, b_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(written, vec![0x01, 0x03, 0x00, 0x0a]);
+
+    // and received bytes arrive as hex text
+    serial_manager
+        .send_data("nm-b", &[0xde, 0xad])
+        .await
+        .unwrap();
+    let reply = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    assert_eq!(reply.unwrap().unwrap(), WsMessage::Text("dead".into()));
+
+    ws.send(WsMessage::Text("0g".into())).await.unwrap();
+    let reply = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    let json: Value = serde_json::from_str(reply.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(json["code"], "invalid_hex");
+
+    match tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?encoding=base32",
+        addr
+    ))
+    .await
+    {
+        Err(WsError::Http(response)) => assert_eq!(response.status(), StatusCode::BAD_REQUEST),
+        other => panic!("expected 400, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_mux_encoding() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = create_router(serial_manager.clone());
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let (ws, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?encoding=mux&seq=true",
+        addr
+    ))
+    .await
+    .unwrap();
+    let mut b_rx = serial_manager.subscribe("nm-b").await.unwrap();
+    let timeout = std::time::Duration::from_secs(2);
+
+    // The channel byte is stripped before writing
+    let (mut tx, mut rx) = ws.split();
+    tx.send(WsMessage::Binary(vec![0, b'A', b'T', b'\r']))
+        .await
+        .unwrap();
+    let written = tokio::time::timeout(timeout, b_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(written, b"AT\r");
+
+    serial_manager.send_data("nm-b", b"OK\r\n").await.unwrap();
+    let data = tokio::time::timeout(timeout, rx.next()).await.unwrap();
+    assert_eq!(data.unwrap().unwrap().into_data(), b"\x00OK\r\n");
+    let seq = tokio::time::timeout(timeout, rx.next()).await.unwrap();
+    assert_eq!(
+        seq.unwrap().unwrap().into_data(),
+        [&[3u8][..], &1u64.to_be_bytes()].concat()
+    );
+
+    // Errors arrive on the control channel
+    tx.send(WsMessage::Binary(vec![2, b'{', b'}']))
+        .await
+        .unwrap();
+    let error = tokio::time::timeout(timeout, rx.next()).await.unwrap();
+    let data = error.unwrap().unwrap().into_data();
+    assert_eq!(data[0], 2);
+    let json: Value = serde_json::from_slice(&data[1..]).unwrap();
+    assert_eq!(json["code"], "invalid_frame");
+}
+
+#[tokio::test]
+async fn test_websocket_sessions_carry_client_label() {
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let connection_trace = serial_manager
+        .get_connection("nm-a")
+        .await
+        .unwrap()
+        .trace()
+        .clone();
+    let app = create_router(serial_manager);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = app.clone();
+    tokio::spawn(async move { axum::serve(listener, server).await });
+
+    let (mut labelled, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?client=buildbot-3",
+        addr
+    ))
+    .await
+    .unwrap();
+    let mut request = format!("ws://{}/api/connections/nm-b/ws", addr)
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("X-Webmux-Client", "dashboard".parse().unwrap());
+    let (_other, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+    let bad = tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?client=%07bell",
+        addr
+    ))
+    .await;
+    assert!(bad.is_err());
+
+    let list = |uri: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            body_to_json(response.into_body()).await
+        }
+    };
+
+    // Sessions register once the upgrade completes on the server side
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    while list("/api/sessions").await.as_array().unwrap().len() < 2 {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let sessions = list("/api/sessions?connection=nm-a").await;
+    assert_eq!(sessions.as_array().unwrap().len(), 1);
+    assert_eq!(sessions[0]["client"], "buildbot-3");
+    assert_eq!(list("/api/sessions").await[1]["client"], "dashboard");
+    // Each session is a span in its connection's trace
+    assert_eq!(sessions[0]["trace"]["trace_id"], connection_trace.trace_id);
+    assert_eq!(sessions[0]["trace"]["span_id"].as_str().unwrap().len(), 16);
+    assert_ne!(sessions[0]["trace"]["span_id"], connection_trace.span_id);
+
+    let id = sessions[0]["id"].as_u64().unwrap();
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("DELETE")
+                .uri(format!("/api/sessions/{}", id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let closed = tokio::time::timeout(std::time::Duration::from_secs(2), labelled.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    match closed {
+        tokio_tungstenite::tungstenite::Message::Close(Some(frame)) => {
+            assert_eq!(u16::from(frame.code), crate::web::close::KICKED);
+            assert_eq!(frame.reason, "kicked");
+        }
+        other => panic!("expected a close frame, got {:?}", other),
+    }
+    assert_eq!(
+        list("/api/sessions?connection=nm-a").await,
+        serde_json::json!([])
+    );
+}
+
+#[tokio::test]
+async fn test_sessions_record_client_address_behind_proxy() {
+    use tokio::io::AsyncWriteExt;
+    use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let proxy = crate::config::ProxyConfig {
+        trusted: vec!["127.0.0.0/8".to_string()],
+        protocol: false,
+    };
+    let config = crate::config::ServerConfig {
+        proxy: Some(proxy.clone()),
+        ..Default::default()
+    };
+    let app = crate::web::create_router_with_config(serial_manager, &config);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let forwarded = listener.local_addr().unwrap();
+    let server = app
+        .clone()
+        .into_make_service_with_connect_info::<std::net::SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, server).await });
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxied = listener.local_addr().unwrap();
+    let server = app.clone();
+    tokio::spawn(async move {
+        crate::web::proxy::serve_proxy_protocol(
+            listener,
+            server,
+            &proxy,
+            std::future::pending::<()>(),
+        )
+        .await
+    });
+
+    // The proxy appended itself after the client
+    let mut request = format!("ws://{}/api/connections/nm-a/ws", forwarded)
+        .into_client_request()
+        .unwrap();
+    request.headers_mut().insert(
+        "X-Forwarded-For",
+        "198.51.100.7, 127.0.0.2".parse().unwrap(),
+    );
+    let (_forwarded_ws, _) = tokio_tungstenite::connect_async(request).await.unwrap();
+
+    let mut stream = tokio::net::TcpStream::connect(proxied).await.unwrap();
+    stream
+        .write_all(b"PROXY TCP4 203.0.113.9 127.0.0.1 51000 8080\r\n")
+        .await
+        .unwrap();
+    let (_proxied_ws, _) = tokio_tungstenite::client_async(
+        format!("ws://{}/api/connections/nm-b/ws", proxied),
+        stream,
+    )
+    .await
+    .unwrap();
+
+    let list = || {
+        let app = app.clone();
+        async move {
+            let response = app
+                .oneshot(
+                    Request::builder()
+                        .uri("/api/sessions")
+                        .body(Body::empty())
+                        .unwrap(),
+                )
+                .await
+                .unwrap();
+            body_to_json(response.into_body()).await
+        }
+    };
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    let sessions = loop {
+        let sessions = list().await;
+        if sessions.as_array().unwrap().len() == 2 {
+            break sessions;
+        }
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    };
+    assert_eq!(sessions[0]["connection"], "nm-a");
+    assert_eq!(sessions[0]["address"], "198.51.100.7");
+    assert_eq!(sessions[1]["connection"], "nm-b");
+    assert_eq!(sessions[1]["address"], "203.0.113.9");
+}
+
+#[tokio::test]
+async fn test_websocket_banner_is_sent_first() {
+    use futures::StreamExt;
+    use tokio::io::AsyncWriteExt;
+
+    let (mut device, slave) = tokio_serial::SerialStream::pair().unwrap();
+    let path = serialport::SerialPort::name(&slave).unwrap();
+    std::mem::forget(slave);
+
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager.clone());
+    let body = serde_json::json!({
+        "name": "console",
+        "port": path,
+        "banner": "Lab router\nDo not reboot"
+    });
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let (mut ws, _) =
+        tokio_tungstenite::connect_async(format!("ws://{}/api/connections/console/ws", addr))
+            .await
+            .unwrap();
+    let timeout = std::time::Duration::from_secs(2);
+    let message = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    let banner: Value = serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(banner["type"], "banner");
+    assert_eq!(banner["text"], "Lab router\nDo not reboot");
+
+    // Traffic follows as usual
+    device.write_all(b"login: ").await.unwrap();
+    let message = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"login: ");
+    serial_manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_websocket_write_lock() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let (_device, slave) = tokio_serial::SerialStream::pair().unwrap();
+    let path = serialport::SerialPort::name(&slave).unwrap();
+    std::mem::forget(slave);
+
+    let serial_manager = SerialManager::new();
+    let request: crate::serial::ephemeral::ConnectionRequest =
+        serde_json::from_value(serde_json::json!({"name": "console", "port": path})).unwrap();
+    let mut config = request.config().unwrap();
+    config.write_lock = Some(crate::config::WriteLockConfig::default());
+    serial_manager.add_connection(config).await.unwrap();
+
+    let app = create_router(serial_manager.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let url = |client: &str| format!("ws://{}/api/connections/console/ws?client={}", addr, client);
+    let (mut alice, _) = tokio_tungstenite::connect_async(url("alice"))
+        .await
+        .unwrap();
+    let (mut bob, _) = tokio_tungstenite::connect_async(url("bob&share_input=true"))
+        .await
+        .unwrap();
+    let timeout = std::time::Duration::from_secs(2);
+
+    alice
+        .send(WsMessage::Text("show run\r".into()))
+        .await
+        .unwrap();
+    // Bob sees both that alice took the lock and what she wrote
+    let mut frames = Vec::new();
+    for _ in 0..2 {
+        let message = tokio::time::timeout(timeout, bob.next()).await.unwrap();
+        let frame: Value =
+            serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+        frames.push(frame);
+    }
+    frames.sort_by_key(|frame| frame["type"].as_str().unwrap().to_string());
+    assert_eq!(frames[0]["type"], "tx");
+    assert_eq!(frames[0]["client"], "alice");
+    assert_eq!(frames[0]["data"], "show run\r");
+    assert_eq!(frames[1]["type"], "write_lock");
+    assert_eq!(frames[1]["holder"]["client"], "alice");
+
+    bob.send(WsMessage::Text("reload\r".into())).await.unwrap();
+    let message = tokio::time::timeout(timeout, bob.next()).await.unwrap();
+    let frame: Value = serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(frame["type"], "error");
+    assert_eq!(frame["code"], "write_locked");
+
+    // The lock is freed for the others when its holder leaves
+    alice.close(None).await.unwrap();
+    let message = tokio::time::timeout(timeout, bob.next()).await.unwrap();
+    let frame: Value = serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(frame["type"], "write_lock");
+    assert_eq!(frame["holder"], Value::Null);
+    assert_eq!(frame["reason"], "disconnected");
+    serial_manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_websocket_read_only_session() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = create_router(serial_manager.clone());
+    let server = app.clone();
+    tokio::spawn(async move { axum::serve(listener, server).await });
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?mode=readonly",
+        addr
+    ))
+    .await
+    .unwrap();
+    ws.send(WsMessage::Text("reboot\r".into())).await.unwrap();
+    let reply = tokio::time::timeout(std::time::Duration::from_secs(2), ws.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let json: Value = serde_json::from_str(reply.to_text().unwrap()).unwrap();
+    assert_eq!(json["type"], "error");
+    assert_eq!(json["code"], "read_only");
+    assert_eq!(
+        serial_manager.get_stats("nm-a").await.unwrap().bytes_sent,
+        0
+    );
+
+    // Received data still reaches the viewer
+    serial_manager.send_data("nm-b", b"hello").await.unwrap();
+    let message = tokio::time::timeout(std::time::Duration::from_secs(2), ws.next())
+        .await
+        .unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"hello");
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/sessions")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    let sessions = body_to_json(response.into_body()).await;
+    assert_eq!(sessions[0]["read_only"], true);
+}
+
+#[tokio::test]
+async fn test_websocket_direction_filter() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(serial_manager.clone())).await });
+    let url = |query: &str| format!("ws://{}/api/connections/nm-a/ws?{}", addr, query);
+    let timeout = std::time::Duration::from_secs(2);
+
+    let (mut viewer, _) =
+        tokio_tungstenite::connect_async(url("mode=readonly&direction=both&encoding=mux"))
+            .await
+            .unwrap();
+    let (mut typist, _) = tokio_tungstenite::connect_async(url("direction=tx"))
+        .await
+        .unwrap();
+    let (mut device, _) =
+        tokio_tungstenite::connect_async(format!("ws://{}/api/connections/nm-b/ws", addr))
+            .await
+            .unwrap();
+
+    // Viewers see what another session types, on its own mux channel
+    typist.send(WsMessage::Text("ls\r".into())).await.unwrap();
+    let message = tokio::time::timeout(timeout, viewer.next()).await.unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"\x04ls\r");
+    let message = tokio::time::timeout(timeout, device.next()).await.unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"ls\r");
+
+    // and what the device sends back; a tx-only session does not
+    device.send(WsMessage::Text("ok\r\n".into())).await.unwrap();
+    let message = tokio::time::timeout(timeout, viewer.next()).await.unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"\x00ok\r\n");
+    let message = tokio::time::timeout(timeout, typist.next()).await.unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"ls\r");
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(200), typist.next())
+            .await
+            .is_err()
+    );
+
+    for query in ["direction=tx&seq=true", "direction=sideways"] {
+        match tokio_tungstenite::connect_async(url(query)).await {
+            Err(WsError::Http(response)) => {
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST)
+            }
+            other => panic!("expected 400, got {:?}", other.map(|_| ())),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_both_directions_arrive_in_order() {
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::{Error as WsError, Message as WsMessage};
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    serial_manager.send_data("nm-b", b"boot").await.unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let manager = serial_manager.clone();
+    tokio::spawn(async move { axum::serve(listener, create_router(manager)).await });
+
+    // Resumed from before the first chunk, which comes from the replay
+    let (mut viewer, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?direction=both&encoding=mux&since_seq=0",
+        addr
+    ))
+    .await
+    .unwrap();
+    async fn next<S>(ws: &mut S) -> Vec<u8>
+    where
+        S: futures::Stream<Item = Result<WsMessage, WsError>> + Unpin,
+    {
+        tokio::time::timeout(std::time::Duration::from_secs(2), ws.next())
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap()
+            .into_data()
+    }
+    assert_eq!(next(&mut viewer).await, b"\x00boot");
+    assert_eq!(
+        next(&mut viewer).await,
+        [&[3u8][..], &1u64.to_be_bytes()].concat()
+    );
+
+    // Each reply is written after its command, and arrives after it
+    for i in 0..20u64 {
+        let command = format!("c{}", i);
+        let reply = format!("r{}", i);
+        serial_manager
+            .send_data("nm-a", command.as_bytes())
+            .await
+            .unwrap();
+        serial_manager
+            .send_data("nm-b", reply.as_bytes())
+            .await
+            .unwrap();
+        assert_eq!(
+            next(&mut viewer).await,
+            [b"\x04", command.as_bytes()].concat()
+        );
+        assert_eq!(
+            next(&mut viewer).await,
+            [b"\x00", reply.as_bytes()].concat()
+        );
+        assert_eq!(
+            next(&mut viewer).await,
+            [&[3u8][..], &(i + 2).to_be_bytes()].concat()
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_websocket_shares_writes_with_other_sessions() {
+    use futures::{SinkExt, StreamExt};
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, create_router(serial_manager.clone())).await });
+    let url = |query: &str| format!("ws://{}/api/connections/nm-a/ws?{}", addr, query);
+    let timeout = std::time::Duration::from_secs(2);
+
+    let (mut writer, _) = tokio_tungstenite::connect_async(url("client=alice"))
+        .await
+        .unwrap();
+    let (mut viewer, _) = tokio_tungstenite::connect_async(url("mode=readonly&share_input=true"))
+        .await
+        .unwrap();
+    let (mut muxed, _) = tokio_tungstenite::connect_async(url("encoding=mux&share_input=true"))
+        .await
+        .unwrap();
+    // Only sessions that ask are told
+    let (mut terminal, _) = tokio_tungstenite::connect_async(url("mode=readonly"))
+        .await
+        .unwrap();
+
+    writer
+        .send(WsMessage::Binary(vec![b'o', b'k', 0xff]))
+        .await
+        .unwrap();
+    let message = tokio::time::timeout(timeout, viewer.next()).await.unwrap();
+    let frame: Value = serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(frame["type"], "tx");
+    assert_eq!(frame["client"], "alice");
+    assert_eq!(frame["format"], "base64");
+    assert_eq!(frame["data"], "b2v/");
+    // On the control channel with encoding=mux
+    let message = tokio::time::timeout(timeout, muxed.next()).await.unwrap();
+    let data = message.unwrap().unwrap().into_data();
+    assert_eq!(data[0], 2);
+    let muxed_frame: Value = serde_json::from_slice(&data[1..]).unwrap();
+    assert_eq!(muxed_frame["type"], "tx");
+    assert_eq!(muxed_frame["session"], frame["session"]);
+
+    // The writer is not sent its own input
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(200), writer.next())
+            .await
+            .is_err()
+    );
+    assert!(
+        tokio::time::timeout(std::time::Duration::from_millis(200), terminal.next())
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_websocket_resumes_from_sequence() {
+    use futures::StreamExt;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+    let url = |query: &str| format!("ws://{}/api/connections/nm-a/ws?{}", addr, query);
+    let timeout = std::time::Duration::from_secs(2);
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(url("seq=true"))
+        .await
+        .unwrap();
+    serial_manager.send_data("nm-b", b"boot\r\n").await.unwrap();
+    let message = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"boot\r\n");
+    let message = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    let marker: Value = serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(marker, serde_json::json!({"type": "seq", "seq": 1}));
+    drop(ws);
+
+    // Output while the client was away is replayed on reconnecting
+    let mut device_rx = serial_manager.subscribe("nm-a").await.unwrap();
+    serial_manager.send_data("nm-b", b"login: ").await.unwrap();
+    device_rx.recv().await.unwrap();
+    let (mut ws, _) = tokio_tungstenite::connect_async(url("since_seq=1"))
+        .await
+        .unwrap();
+    let message = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    assert_eq!(message.unwrap().unwrap().into_data(), b"login: ");
+    let message = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    let marker: Value = serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(marker["seq"], 2);
+
+    // A number from before a restart cannot be resumed from
+    let (mut ws, _) = tokio_tungstenite::connect_async(url("since_seq=50"))
+        .await
+        .unwrap();
+    let message = tokio::time::timeout(timeout, ws.next()).await.unwrap();
+    let error: Value = serde_json::from_str(message.unwrap().unwrap().to_text().unwrap()).unwrap();
+    assert_eq!(error["code"], "replay_incomplete");
+    serial_manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_websocket_sessions_are_audited() {
+    use crate::logging::AuditLog;
+    use crate::web::sessions::SessionRegistry;
+    use futures::SinkExt;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let audit = AuditLog::open(&crate::config::AuditConfig {
+        path: path.clone(),
+        keystrokes: true,
+        max_file_bytes: None,
+    })
+    .await
+    .unwrap();
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let mut device_rx = serial_manager.subscribe("nm-b").await.unwrap();
+    let sessions = SessionRegistry::with_audit(audit);
+    let app = crate::web::create_router_with_sessions(
+        serial_manager,
+        &crate::config::ServerConfig::default(),
+        sessions.clone(),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let (mut ws, _) = tokio_tungstenite::connect_async(format!(
+        "ws://{}/api/connections/nm-a/ws?client=alice",
+        addr
+    ))
+    .await
+    .unwrap();
+    ws.send(WsMessage::Binary(b"reload\r".to_vec()))
+        .await
+        .unwrap();
+    tokio::time::timeout(std::time::Duration::from_secs(2), device_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    ws.close(None).await.unwrap();
+
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    while !sessions.list(None).await.is_empty() {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    let records: Vec<Value> = std::fs::read_to_string(&path)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let events: Vec<&str> = records
+        .iter()
+        .map(|record| record["event"].as_str().unwrap())
+        .collect();
+    assert_eq!(events, ["opened", "input", "closed"]);
+    assert!(records
+        .iter()
+        .all(|record| record["client"] == "alice" && record["connection"] == "nm-a"));
+    assert_eq!(records[1]["hex"], "72656c6f61640d");
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_local_attach_session() {
+    use crate::web::attach::{self, AttachRequest};
+    use crate::web::sessions::SessionRegistry;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("attach.sock");
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let sessions = SessionRegistry::default();
+    attach::spawn(
+        &serial_manager,
+        &sessions,
+        &crate::config::AttachConfig {
+            socket: socket.clone(),
+        },
+    )
+    .await
+    .unwrap();
+
+    let request = |port: &str| AttachRequest {
+        port: port.to_string(),
+        client: Some("local:root".to_string()),
+    };
+    let error = attach::connect(&socket, &request("/dev/ttyNOPE"))
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("No running connection"));
+
+    let (mut stream, connection) = attach::connect(&socket, &request("nm-a")).await.unwrap();
+    assert_eq!(connection, "nm-a");
+    let listed = sessions.list(Some("nm-a")).await;
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].client.as_deref(), Some("local:root"));
+
+    let mut device_rx = serial_manager.subscribe("nm-b").await.unwrap();
+    stream.write_all(b"reload\r").await.unwrap();
+    let received = tokio::time::timeout(std::time::Duration::from_secs(2), device_rx.recv())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(received, b"reload\r");
+
+    serial_manager.send_data("nm-b", b"ok\r\n").await.unwrap();
+    let mut buf = [0u8; 4];
+    tokio::time::timeout(
+        std::time::Duration::from_secs(2),
+        stream.read_exact(&mut buf),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert_eq!(&buf, b"ok\r\n");
+
+    // Kicked like any other session
+    sessions.kick(listed[0].id).await.unwrap();
+    let mut rest = String::new();
+    tokio::time::timeout(
+        std::time::Duration::from_secs(2),
+        stream.read_to_string(&mut rest),
+    )
+    .await
+    .unwrap()
+    .unwrap();
+    assert!(rest.contains("[webmux: session kicked]"));
+}
+
+#[tokio::test]
+async fn test_websocket_close_codes_for_removal_and_shutdown() {
+    use crate::web::close::{CloseReason, CONNECTION_REMOVED, SERVER_SHUTDOWN};
+    use crate::web::sessions::SessionRegistry;
+    use futures::StreamExt;
+    use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let sessions = SessionRegistry::default();
+    let app = crate::web::create_router_with_sessions(
+        serial_manager.clone(),
+        &crate::config::ServerConfig::default(),
+        sessions.clone(),
+    );
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let connect = |name: &'static str| async move {
+        tokio_tungstenite::connect_async(format!("ws://{}/api/connections/{}/ws", addr, name))
+            .await
+            .unwrap()
+            .0
+    };
+    let mut a = connect("nm-a").await;
+    let mut b = connect("nm-b").await;
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    while sessions.list(None).await.len() < 2 {
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    let close_code = |message: WsMessage| match message {
+        WsMessage::Close(Some(frame)) => u16::from(frame.code),
+        other => panic!("expected a close frame, got {:?}", other),
+    };
+    let next = std::time::Duration::from_secs(2);
+
+    serial_manager.remove_connection("nm-b").await.unwrap();
+    let closed = tokio::time::timeout(next, b.next()).await.unwrap();
+    assert_eq!(close_code(closed.unwrap().unwrap()), CONNECTION_REMOVED);
+
+    sessions
+        .close_all(
+            CloseReason::ServerShutdown,
+            std::time::Duration::from_secs(2),
+        )
+        .await;
+    let closed = tokio::time::timeout(next, a.next()).await.unwrap();
+    assert_eq!(close_code(closed.unwrap().unwrap()), SERVER_SHUTDOWN);
+    assert!(sessions.list(None).await.is_empty());
+}
+
+#[tokio::test]
+async fn test_websocket_upgrade_rejects_foreign_origin() {
+    use tokio_tungstenite::tungstenite::{client::IntoClientRequest, Error as WsError};
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let config = crate::config::ServerConfig {
+        websocket: crate::config::WebSocketConfig {
+            allowed_origins: vec!["https://console.example.com".to_string()],
+            max_clients: None,
+            max_clients_per_address: None,
+        },
+        ..Default::default()
+    };
+    let app = create_router_with_config(serial_manager, &config);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let connect = |origin: &'static str| {
+        let mut request = format!("ws://{}/api/connections/nm-a/ws", addr)
+            .into_client_request()
+            .unwrap();
+        request
+            .headers_mut()
+            .insert("Origin", origin.parse().unwrap());
+        tokio_tungstenite::connect_async(request)
+    };
+
+    match connect("https://evil.example").await {
+        Err(WsError::Http(response)) => assert_eq!(response.status(), StatusCode::FORBIDDEN),
+        other => panic!("expected 403, got {:?}", other.map(|_| ())),
+    }
+    assert!(connect("https://console.example.com").await.is_ok());
+}
+
+#[tokio::test]
+async fn test_websocket_upgrade_rejects_clients_over_cap() {
+    use tokio_tungstenite::tungstenite::Error as WsError;
+
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let config = crate::config::ServerConfig {
+        websocket: crate::config::WebSocketConfig {
+            allowed_origins: Vec::new(),
+            max_clients: Some(1),
+            max_clients_per_address: None,
+        },
+        ..Default::default()
+    };
+    let app = create_router_with_config(serial_manager, &config);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = app.clone();
+    tokio::spawn(async move { axum::serve(listener, server).await });
+
+    let (_first, _) =
+        tokio_tungstenite::connect_async(format!("ws://{}/api/connections/nm-a/ws", addr))
+            .await
+            .unwrap();
+
+    // Wait for the first session to register before racing it
+    let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(2);
+    loop {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/api/sessions")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        if !body_to_json(response.into_body())
+            .await
+            .as_array()
+            .unwrap()
+            .is_empty()
+        {
+            break;
+        }
+        assert!(tokio::time::Instant::now() < deadline);
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+
+    match tokio_tungstenite::connect_async(format!("ws://{}/api/connections/nm-b/ws", addr)).await {
+        Err(WsError::Http(response)) => assert_eq!(response.status(), StatusCode::CONFLICT),
+        other => panic!("expected 409, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn test_openapi_spec_is_served_and_consistent() {
+    let app = create_router(SerialManager::new());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/openapi.json")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let spec = body_to_json(response.into_body()).await;
+    assert_eq!(spec, openapi::spec());
+
+    fn refs(value: &Value, found: &mut Vec<String>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(target)) = map.get("$ref") {
+                    found.push(target.clone());
+                }
+                map.values().for_each(|v| refs(v, found));
+            }
+            Value::Array(items) => items.iter().for_each(|v| refs(v, found)),
+            _ => {}
+        }
+    }
+    let mut found = Vec::new();
+    refs(&spec, &mut found);
+    assert!(!found.is_empty());
+    for target in found {
+        let name = target.trim_start_matches("#/components/schemas/");
+        assert!(
+            spec["components"]["schemas"].get(name).is_some(),
+            "unresolved {}",
+            target
+        );
+    }
+
+    let ws = &spec["paths"]["/api/connections/{name}/ws"]["get"];
+    assert!(ws["description"].as_str().unwrap().contains("WsErrorFrame"));
+    assert!(ws["responses"].get("409").is_some());
+    assert!(spec["paths"]["/api/sessions/{id}"].get("delete").is_some());
+}
+
+#[tokio::test]
+async fn test_power_requires_configured_control() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager);
+
+    for (uri, message) in [
+        ("/api/connections/nm-a/power/on", "no power control"),
+        ("/api/connections/nm-a/power/reboot", "on, off or cycle"),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let json = body_to_json(response.into_body()).await;
+        assert!(
+            json["error"].as_str().unwrap().contains(message),
+            "{}",
+            json
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_config_diff_endpoint() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    std::fs::write(
+        &path,
+        r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "modem"
+    port: "/dev/webmux-test-missing"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/modem.log"
+"#,
+    )
+    .unwrap();
+
+    let get_diff = |serial_manager: SerialManager| async move {
+        create_router(serial_manager)
+            .oneshot(
+                Request::builder()
+                    .uri("/api/config/diff")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+    };
+    assert_eq!(
+        get_diff(SerialManager::new()).await.status(),
+        StatusCode::NOT_FOUND
+    );
+
+    let serial_manager =
+        SerialManager::new().with_config_files(crate::config::drift::ConfigFiles {
+            paths: vec![path.to_string_lossy().into_owned()],
+            lenient: false,
+        });
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let response = get_diff(serial_manager.clone()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["added"], serde_json::json!(["nm-a", "nm-b"]));
+    assert_eq!(json["removed"], serde_json::json!(["modem"]));
+    assert_eq!(json["modified"], serde_json::json!([]));
+
+    std::fs::write(&path, "server: [").unwrap();
+    let response = get_diff(serial_manager).await;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+}
+
+#[tokio::test]
+async fn test_config_export_and_import() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.yaml");
+    std::fs::write(
+        &path,
+        r#"
+server:
+  host: "127.0.0.1"
+  port: 8080
+
+serial_connections:
+  - name: "modem"
+    port: "/dev/webmux-test-missing"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: false
+    logging:
+      enabled: false
+      path: "./logs/modem.log"
+"#,
+    )
+    .unwrap();
+    let serial_manager =
+        SerialManager::new().with_config_files(crate::config::drift::ConfigFiles {
+            paths: vec![path.to_string_lossy().into_owned()],
+            lenient: false,
+        });
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/config/export")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(
+        response.headers()["content-type"].to_str().unwrap(),
+        "application/yaml"
+    );
+    let exported = body_to_string(response.into_body()).await;
+    // Disabled connections are kept, virtual pairs left out
+    let config = crate::config::Config::from_yaml(&exported).unwrap().config;
+    assert_eq!(config.serial_connections.len(), 1);
+    assert_eq!(config.serial_connections[0].name, "modem");
+    assert!(!config.serial_connections[0].enabled);
+
+    let import = |uri: &'static str, body: String| {
+        let app = app.clone();
+        async move {
+            app.oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri(uri)
+                    .body(Body::from(body))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+        }
+    };
+
+    // Importing the export changes nothing
+    let response = import("/api/config/import", exported.clone()).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["dry_run"], false);
+    assert_eq!(json["open"], serde_json::json!([]));
+    assert_eq!(json["close"], serde_json::json!([]));
+    assert_eq!(json["restart_required"], serde_json::json!([]));
+
+    let changed = exported
+        .replace("port: 8080", "port: 9090")
+        .replace("enabled: false\n  logging", "enabled: true\n  logging");
+    let response = import("/api/config/import?dry_run=true", changed).await;
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["dry_run"], true);
+    assert_eq!(json["open"], serde_json::json!(["modem"]));
+    assert_eq!(json["restart_required"], serde_json::json!(["port"]));
+    assert!(serial_manager.get_connection("modem").await.is_none());
+
+    let response = import("/api/config/import", "server: [".to_string()).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn test_create_connections_bulk() {
+    let (mut ports, mut devices) = (Vec::new(), Vec::new());
+    for _ in 0..2 {
+        let (device, slave) = tokio_serial::SerialStream::pair().unwrap();
+        ports.push(serialport::SerialPort::name(&slave).unwrap());
+        std::mem::forget(slave);
+        devices.push(device);
+    }
+    ports.push("/dev/webmux-test-missing".to_string());
+
+    let serial_manager = SerialManager::new();
+    let app = create_router(serial_manager.clone());
+    let post = |body: Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections/bulk")
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+
+    let response = post(serde_json::json!({
+        "template": { "name": "bench-{index}", "baud_rate": 9600, "expires_in": 600 },
+        "ports": ports
+    }))
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["created"].as_array().unwrap().len(), 2);
+    assert_eq!(json["created"][0]["name"], "bench-1");
+    assert!(json["created"][1]["expires_at"].is_string());
+    assert_eq!(json["failed"][0]["name"], "bench-3");
+    assert_eq!(json["failed"][0]["port"], "/dev/webmux-test-missing");
+    let connection = serial_manager.get_connection("bench-2").await.unwrap();
+    assert_eq!(connection.config().baud_rate, 9600);
+
+    // Names already taken fail on their own
+    let response = post(serde_json::json!({
+        "template": { "name": "bench-{index}" },
+        "ports": [ports[0]]
+    }))
+    .await
+    .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["created"], serde_json::json!([]));
+    assert!(json["failed"][0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("already exists"));
+
+    for body in [
+        serde_json::json!({ "template": { "name": "bench" }, "ports": ports }),
+        serde_json::json!({ "template": { "name": "{index}" }, "ports": ports, "glob": "/dev/tty*" }),
+        serde_json::json!({ "template": { "name": "{index}" } }),
+    ] {
+        let response = post(body).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    serial_manager.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_port_groups_endpoints() {
+    let app = create_router(SerialManager::new());
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .uri("/api/port-groups")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_to_json(response.into_body()).await.is_array());
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/port-groups/usb-9-9/restart")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn test_alert_silence_endpoints() {
+    let app = create_router(SerialManager::new());
+    let request = |method: &str, uri: &str, body: Option<Value>| {
+        let builder = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Content-Type", "application/json");
+        let body = body.map_or_else(Body::empty, |body| Body::from(body.to_string()));
+        app.clone().oneshot(builder.body(body).unwrap())
+    };
+
+    let response = request(
+        "POST",
+        "/api/alerts/silence",
+        Some(serde_json::json!({
+            "connection": "plc",
+            "pattern": "^temp_",
+            "duration": 3600,
+            "comment": "sensor replaced on Monday"
+        })),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+    let silence = body_to_json(response.into_body()).await;
+    assert_eq!(silence["connection"], "plc");
+    assert!(silence["expires_at"].is_string());
+
+    let response = request(
+        "POST",
+        "/api/alerts/silence",
+        Some(serde_json::json!({ "pattern": "[", "duration": 60 })),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = request("GET", "/api/alerts/silences", None).await.unwrap();
+    let list = body_to_json(response.into_body()).await;
+    assert_eq!(list.as_array().unwrap().len(), 1);
+    assert_eq!(list[0]["id"], silence["id"]);
+
+    let uri = format!("/api/alerts/silences/{}", silence["id"]);
+    let response = request("DELETE", &uri, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = request("DELETE", &uri, None).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn test_admin_routes_move_to_admin_listener() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("admin.sock");
+    let config = crate::config::ServerConfig {
+        admin: Some(crate::config::AdminConfig {
+            address: None,
+            socket: Some(socket.clone()),
+        }),
+        ..Default::default()
+    };
+    let sessions = crate::web::sessions::SessionRegistry::default();
+    let public =
+        crate::web::create_router_with_sessions(serial_manager.clone(), &config, sessions.clone());
+
+    let request = |method: &str, uri: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    };
+    // Reads stay public, changes are refused there
+    let response = public
+        .clone()
+        .oneshot(request("GET", "/api/connections"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    for (method, uri) in [
+        ("POST", "/api/connections"),
+        ("DELETE", "/api/connections/nm-a"),
+        ("GET", "/api/config/export"),
+        ("DELETE", "/api/sessions/1"),
+        ("POST", "/api/connections/nm-a/power/cycle"),
+        ("POST", "/api/connections/nm-a/lease"),
+        ("POST", "/api/captures"),
+        ("GET", "/api/hub/agents/edge/api/connections"),
+    ] {
+        let response = public.clone().oneshot(request(method, uri)).await.unwrap();
+        assert!(
+            response.status() == StatusCode::METHOD_NOT_ALLOWED
+                || response.status() == StatusCode::NOT_FOUND,
+            "{} {} answered {}",
+            method,
+            uri,
+            response.status()
+        );
+    }
+
+    let admin = crate::web::create_admin_router(
+        serial_manager.clone(),
+        &config,
+        sessions,
+        crate::web::lifecycle::Lifecycle::default(),
+    );
+    let address = crate::web::admin::spawn(admin, config.admin.as_ref().unwrap())
+        .await
+        .unwrap();
+    assert_eq!(address, socket.display().to_string());
+
+    let stream = tokio::net::UnixStream::connect(&socket).await.unwrap();
+    let (mut sender, connection) =
+        hyper::client::conn::http1::handshake(hyper_util::rt::TokioIo::new(stream))
+            .await
+            .unwrap();
+    tokio::spawn(connection);
+    let response = sender
+        .send_request(request("DELETE", "/api/virtual-pairs/nm"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(serial_manager.get_connection("nm-a").await.is_none());
+}
+
+#[tokio::test]
+async fn test_remote_shutdown_needs_token() {
+    let dir = tempfile::tempdir().unwrap();
+    let token_file = dir.path().join("token");
+    std::fs::write(&token_file, "s3cret\n").unwrap();
+    let config = crate::config::ServerConfig {
+        remote_control: Some(crate::config::RemoteControlConfig { token_file }),
+        ..Default::default()
+    };
+    let lifecycle = crate::web::lifecycle::Lifecycle::default();
+    let app = crate::web::create_router_with_lifecycle(
+        SerialManager::new(),
+        &config,
+        crate::web::sessions::SessionRegistry::default(),
+        lifecycle.clone(),
+    );
+    let request = |uri: &str, token: Option<&str>| {
+        let mut builder = Request::builder().method("POST").uri(uri);
+        if let Some(token) = token {
+            builder = builder.header("Authorization", format!("Bearer {}", token));
+        }
+        builder.body(Body::empty()).unwrap()
+    };
+
+    for token in [None, Some("wrong")] {
+        let response = app
+            .clone()
+            .oneshot(request("/api/admin/shutdown", token))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    assert_eq!(lifecycle.requested(), None);
+
+    let response = app
+        .clone()
+        .oneshot(request("/api/admin/restart", Some("s3cret")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(
+        body_to_json(response.into_body()).await["action"],
+        "restart"
+    );
+    assert_eq!(
+        lifecycle.stopped().await,
+        crate::web::lifecycle::StopKind::Restart
+    );
+
+    // Only the first request counts
+    let response = app
+        .oneshot(request("/api/admin/shutdown", Some("s3cret")))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(
+        lifecycle.requested(),
+        Some(crate::web::lifecycle::StopKind::Restart)
+    );
+}
+
+#[tokio::test]
+async fn test_hub_relays_to_agent() {
+    let dir = tempfile::tempdir().unwrap();
+    let token_file = dir.path().join("token");
+    std::fs::write(&token_file, "s3cret\n").unwrap();
+
+    let hub_config = crate::config::ServerConfig {
+        hub: Some(crate::config::HubConfig {
+            token_file: token_file.clone(),
+        }),
+        ..Default::default()
+    };
+    let hub = crate::web::create_router_with_config(SerialManager::new(), &hub_config);
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = hub
+        .clone()
+        .into_make_service_with_connect_info::<std::net::SocketAddr>();
+    tokio::spawn(async move { axum::serve(listener, server).await });
+    let url = format!("ws://{}/api/hub/connect", addr);
+
+    // Agents need the hub's token
+    let mut request =
+        tokio_tungstenite::tungstenite::client::IntoClientRequest::into_client_request(
+            url.as_str(),
+        )
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("Authorization", "Bearer wrong".parse().unwrap());
+    match tokio_tungstenite::connect_async(request).await {
+        Err(tokio_tungstenite::tungstenite::Error::Http(response)) => {
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED)
+        }
+        other => panic!("expected 401, got {:?}", other.map(|_| ())),
+    }
+
+    let agent_manager = SerialManager::new();
+    agent_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let agent = create_router(agent_manager.clone());
+    crate::web::hub::spawn_agent(
+        agent,
+        agent_manager,
+        crate::config::HubAgentConfig {
+            url,
+            name: "lab-3".to_string(),
+            token_file,
+        },
+    );
+
+    let get = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+    let agents = loop {
+        let response = hub.clone().oneshot(get("/api/hub/agents")).await.unwrap();
+        let agents = body_to_json(response.into_body()).await;
+        if !agents.as_array().unwrap().is_empty() {
+            break agents;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    };
+    assert_eq!(agents[0]["name"], "lab-3");
+    assert_eq!(
+        agents[0]["connections"],
+        serde_json::json!(["nm-a", "nm-b"])
+    );
+    assert_eq!(agents[0]["address"], "127.0.0.1");
+
+    let response = hub
+        .clone()
+        .oneshot(get("/api/hub/agents/lab-3/api/connections/nm-a"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_to_json(response.into_body()).await["name"], "nm-a");
+
+    // Request bodies go through, and statuses come back as the agent sent them
+    let response = hub
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/hub/agents/lab-3/api/connections/nm-a/send")
+                .header("Content-Type", "application/json")
+                .body(Body::from(r#"{"data":"hi"}"#))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = hub
+        .clone()
+        .oneshot(get(
+            "/api/hub/agents/lab-3/api/connections/nm-a/tail?lines=0",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = hub
+        .oneshot(get("/api/hub/agents/lab-9/api/connections"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
+
+#[tokio::test(start_paused = true)]
+async fn test_hub_agent_answers_are_bounded() {
+    use crate::web::hub::{answer, TunnelMessage, MAX_RELAY_BODY_BYTES};
+    use axum::routing::get;
+
+    let app = axum::Router::new()
+        .route(
+            "/stream",
+            get(|| async {
+                Body::from_stream(futures::stream::pending::<
+                    Result<axum::body::Bytes, std::io::Error>,
+                >())
+            }),
+        )
+        .route(
+            "/large",
+            get(|| async { vec![0u8; MAX_RELAY_BODY_BYTES + 1] }),
+        );
+    let status = |message: TunnelMessage| match message {
+        TunnelMessage::Response { status, .. } => status,
+        other => panic!("expected a response, got {:?}", other),
+    };
+    let streamed = answer(app.clone(), 1, "GET", "/stream", None, "").await;
+    assert_eq!(status(streamed), 504);
+    let large = answer(app, 2, "GET", "/large", None, "").await;
+    assert_eq!(status(large), 502);
+}
+
+#[tokio::test]
+async fn test_hub_disabled_by_default() {
+    let app = create_router(SerialManager::new());
+    for uri in ["/api/hub/connect", "/api/hub/agents/lab-3/api/connections"] {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn test_remote_shutdown_disabled_by_default() {
+    let app = create_router(SerialManager::new());
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/admin/shutdown")
+                .header("Authorization", "Bearer anything")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_startup_report() {
+    let serial_manager = SerialManager::new();
+    let config = crate::config::Config::from_yaml(
+        r#"
+server:
+  host: "0.0.0.0"
+  port: 8080
+serial_connections:
+  - name: "console"
+    port: "/dev/webmux-test-missing"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/console.log"
+"#,
+    )
+    .unwrap()
+    .config;
+    let results = serial_manager
+        .start_connections(config.serial_connections)
+        .await;
+    assert!(results[0].1.is_err());
+    let app = create_router(serial_manager);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .uri("/api/admin/startup")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["failed"], 1);
+    assert_eq!(json["opened"], 0);
+    assert_eq!(json["connections"][0]["name"], "console");
+    assert_eq!(json["connections"][0]["state"], "failed");
+    assert!(json["connections"][0]["error"].is_string());
+    assert!(json["connections"][0].get("waiting_for").is_none());
+}
+
+#[tokio::test]
+async fn test_failed_connection_listed_and_retried() {
+    let serial_manager = SerialManager::new();
+    let config = crate::config::Config::from_yaml(
+        r#"
+server:
+  host: "0.0.0.0"
+  port: 8080
+serial_connections:
+  - name: "console"
+    port: "/dev/webmux-test-missing"
+    baud_rate: 9600
+    data_bits: 8
+    stop_bits: 1
+    parity: "none"
+    flow_control: "none"
+    enabled: true
+    logging:
+      enabled: false
+      path: "./logs/console.log"
+"#,
+    )
+    .unwrap()
+    .config;
+    serial_manager
+        .start_connections(config.serial_connections)
+        .await;
+    let app = create_router(serial_manager);
+    let request = |method: &str, uri: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(request("GET", "/api/connections"))
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json[0]["name"], "console");
+    assert_eq!(json[0]["state"], "failed");
+    assert!(json[0]["error"].is_string());
+
+    let response = app
+        .clone()
+        .oneshot(request("GET", "/api/connections/console"))
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["port"], "/dev/webmux-test-missing");
+    assert!(json["error"].is_string());
+
+    // Still missing, so it stays failed
+    let response = app
+        .clone()
+        .oneshot(request("POST", "/api/connections/console/retry"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let response = app
+        .clone()
+        .oneshot(request("POST", "/api/connections/other/retry"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+    let response = app
+        .clone()
+        .oneshot(request("DELETE", "/api/connections/console"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app
+        .oneshot(request("GET", "/api/connections"))
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json, serde_json::json!([]));
+}
+
+#[tokio::test]
+async fn test_connections_enabled_and_disabled() {
+    let (_device, slave) = tokio_serial::SerialStream::pair().unwrap();
+    let path = serialport::SerialPort::name(&slave).unwrap();
+    std::mem::forget(slave);
+
+    let app = create_router(SerialManager::new());
+    let request = |method: &str, uri: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    };
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method("POST")
+                .uri("/api/connections")
+                .header("content-type", "application/json")
+                .body(Body::from(
+                    serde_json::json!({"name": "usb", "port": path}).to_string(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CREATED);
+
+    let response = app
+        .clone()
+        .oneshot(request("POST", "/api/connections/usb/disable"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["state"], "disabled");
+    let response = app
+        .clone()
+        .oneshot(request("GET", "/api/connections"))
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(
+        json,
+        serde_json::json!([{"name": "usb", "state": "disabled"}])
+    );
+    let response = app
+        .clone()
+        .oneshot(request("GET", "/api/connections/usb"))
+        .await
+        .unwrap();
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["port"], path);
+
+    let response = app
+        .clone()
+        .oneshot(request("POST", "/api/connections/usb/enable"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let json = body_to_json(response.into_body()).await;
+    assert_eq!(json["state"], "open");
+    let response = app
+        .clone()
+        .oneshot(request("GET", "/api/connections/usb/stats"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    for uri in [
+        "/api/connections/other/enable",
+        "/api/connections/other/disable",
+    ] {
+        let response = app.clone().oneshot(request("POST", uri)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
+
+#[tokio::test]
+async fn test_server_log_stream() {
+    use futures::StreamExt;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let app = create_router(SerialManager::new());
+    let request = |uri: &str| Request::builder().uri(uri).body(Body::empty()).unwrap();
+
+    let response = app
+        .clone()
+        .oneshot(request("/api/admin/logs/stream?level=trace"))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let subscriber = tracing_subscriber::registry().with(crate::logging::live::layer().unwrap());
+    let response = app
+        .oneshot(request(
+            "/api/admin/logs/stream?level=info&target=webmux::serial",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    tracing::subscriber::with_default(subscriber, || {
+        tracing::debug!(target: "webmux::serial", "too detailed");
+        tracing::warn!(target: "webmux::web", "elsewhere");
+        let span = tracing::info_span!(
+            "connection",
+            connection = "plc",
+            bytes_rx = tracing::field::Empty
+        );
+        span.record("bytes_rx", 42);
+        let _entered = span.enter();
+        tracing::warn!(target: "webmux::serial", attempt = 3, "Reconnect failed");
+    });
+
+    let mut body = response.into_body().into_data_stream();
+    let chunk = tokio::time::timeout(std::time::Duration::from_secs(5), body.next())
+        .await
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    let text = String::from_utf8(chunk.to_vec()).unwrap();
+    assert!(text.starts_with("event: log"));
+    let data = text
+        .lines()
+        .find_map(|line| line.strip_prefix("data: "))
+        .unwrap();
+    let event: serde_json::Value = serde_json::from_str(data).unwrap();
+    assert_eq!(event["level"], "warn");
+    assert_eq!(event["message"], "Reconnect failed");
+    assert_eq!(event["fields"]["attempt"], "3");
+    // Fields of the spans the event happened in come along
+    assert_eq!(event["fields"]["connection"], "plc");
+    assert_eq!(event["fields"]["bytes_rx"], "42");
+}
+
+#[cfg(feature = "chaos")]
+#[tokio::test]
+async fn test_fault_injection_endpoints() {
+    let serial_manager = SerialManager::new();
+    serial_manager
+        .add_virtual_pair(&crate::serial::VirtualPairRequest {
+            name: "nm".to_string(),
+            a: None,
+            b: None,
+        })
+        .await
+        .unwrap();
+    let app = create_router(serial_manager.clone());
+    let request = |method: &str, uri: &str, body: &str| {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("Content-Type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    };
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            "PUT",
+            "/api/connections/nm-a/faults",
+            r#"{"write_delay_ms": 10, "drop_every": 3}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let status = body_to_json(response.into_body()).await;
+    assert_eq!(status["drop_every"], 3);
+
+    let response = app
+        .clone()
+        .oneshot(request(
+            "PUT",
+            "/api/connections/nm-a/faults",
+            r#"{"write_delay_ms": 999999}"#,
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    let response = app
+        .clone()
+        .oneshot(request("DELETE", "/api/connections/nm-a/faults", ""))
+        .await
+        .unwrap();
+    let status = body_to_json(response.into_body()).await;
+    assert_eq!(status["drop_every"], 0);
+
+    let response = app
+        .oneshot(request(
+            "POST",
+            "/api/connections/nm-a/faults/read-error",
+            "",
+        ))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
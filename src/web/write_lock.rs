@@ -0,0 +1,195 @@
+//! Exclusive-writer arbitration (`write_lock`): on connections that enable
+//! it, only one WebSocket session at a time may write. The first session
+//! to send takes the lock; the others keep viewing until it is released
+//! because the holder left, went idle, or an operator freed it.
+
+use super::sessions::SessionInfo;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio::time::Instant;
+use tracing::info;
+
+static NEXT_LOCK_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Lock changes buffered for slow sessions before they miss some.
+const EVENT_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LockHolder {
+    pub session: u64,
+    pub client: Option<String>,
+    pub since: DateTime<Utc>,
+}
+
+impl LockHolder {
+    /// `session 3 (alice)`, for messages to other clients.
+    pub fn describe(&self) -> String {
+        match self.client {
+            Some(ref client) => format!("session {} ({})", self.session, client),
+            None => format!("session {}", self.session),
+        }
+    }
+}
+
+/// Why a lock was released.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReleaseReason {
+    /// The holder sent nothing for the idle period.
+    Idle,
+    /// The holder's session ended.
+    Disconnected,
+    /// An operator released it through the API.
+    Released,
+}
+
+/// A change of holder on a connection, told to every session on it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteLockEvent {
+    pub connection: String,
+    /// The new holder, or `None` once released.
+    pub holder: Option<LockHolder>,
+    pub reason: Option<ReleaseReason>,
+}
+
+struct Lock {
+    /// Distinguishes successive locks on the same connection.
+    id: u64,
+    holder: LockHolder,
+    last_input: Instant,
+}
+
+#[derive(Clone)]
+pub struct WriteLocks {
+    locks: Arc<Mutex<HashMap<String, Lock>>>,
+    events: broadcast::Sender<WriteLockEvent>,
+}
+
+impl Default for WriteLocks {
+    fn default() -> Self {
+        Self {
+            locks: Arc::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+impl WriteLocks {
+    pub fn subscribe(&self) -> broadcast::Receiver<WriteLockEvent> {
+        self.events.subscribe()
+    }
+
+    pub async fn holder(&self, connection: &str) -> Option<LockHolder> {
+        let locks = self.locks.lock().await;
+        locks.get(connection).map(|lock| lock.holder.clone())
+    }
+
+    /// Lets `session` write, taking the lock if it is free, or returns the
+    /// session holding it. With `idle_release` the lock is freed once its
+    /// holder has sent nothing for that long.
+    pub async fn acquire(
+        &self,
+        session: &SessionInfo,
+        idle_release: Option<Duration>,
+    ) -> Result<(), LockHolder> {
+        let mut locks = self.locks.lock().await;
+        if let Some(lock) = locks.get_mut(&session.connection) {
+            if lock.holder.session != session.id {
+                return Err(lock.holder.clone());
+            }
+            lock.last_input = Instant::now();
+            return Ok(());
+        }
+
+        let id = NEXT_LOCK_ID.fetch_add(1, Ordering::Relaxed);
+        let holder = LockHolder {
+            session: session.id,
+            client: session.client.clone(),
+            since: Utc::now(),
+        };
+        locks.insert(
+            session.connection.clone(),
+            Lock {
+                id,
+                holder: holder.clone(),
+                last_input: Instant::now(),
+            },
+        );
+        info!("{} took the write lock on {}", session, session.connection);
+        let _ = self.events.send(WriteLockEvent {
+            connection: session.connection.clone(),
+            holder: Some(holder),
+            reason: None,
+        });
+
+        if let Some(idle) = idle_release {
+            let locks = self.clone();
+            let connection = session.connection.clone();
+            tokio::spawn(async move { locks.release_when_idle(connection, id, idle).await });
+        }
+        Ok(())
+    }
+
+    async fn release_when_idle(&self, connection: String, id: u64, idle: Duration) {
+        loop {
+            let deadline = {
+                let locks = self.locks.lock().await;
+                match locks.get(&connection) {
+                    Some(lock) if lock.id == id => lock.last_input + idle,
+                    // Released some other way
+                    _ => return,
+                }
+            };
+            tokio::time::sleep_until(deadline).await;
+
+            let mut locks = self.locks.lock().await;
+            if locks
+                .get(&connection)
+                .is_some_and(|lock| lock.id == id && lock.last_input + idle <= Instant::now())
+            {
+                locks.remove(&connection);
+                drop(locks);
+                info!(
+                    "Released the write lock on {} after {}s idle",
+                    connection,
+                    idle.as_secs()
+                );
+                self.released(connection, ReleaseReason::Idle);
+                return;
+            }
+        }
+    }
+
+    /// Frees the lock on `connection`, returning who held it.
+    pub async fn release(&self, connection: &str) -> Option<LockHolder> {
+        let lock = self.locks.lock().await.remove(connection)?;
+        self.released(connection.to_string(), ReleaseReason::Released);
+        Some(lock.holder)
+    }
+
+    /// Frees the lock `session` holds, if any, as it ends.
+    pub async fn session_ended(&self, session: &SessionInfo) {
+        let mut locks = self.locks.lock().await;
+        if locks
+            .get(&session.connection)
+            .is_some_and(|lock| lock.holder.session == session.id)
+        {
+            locks.remove(&session.connection);
+            drop(locks);
+            self.released(session.connection.clone(), ReleaseReason::Disconnected);
+        }
+    }
+
+    fn released(&self, connection: String, reason: ReleaseReason) {
+        let _ = self.events.send(WriteLockEvent {
+            connection,
+            holder: None,
+            reason: Some(reason),
+        });
+    }
+}
@@ -161,8 +161,13 @@ createApp({
         const loadConnections = async () => {
             try {
                 const response = await fetch('/api/connections');
-                connections.value = await response.json();
+                const items = await response.json();
+                // Only open connections can be attached to; old servers send no state
+                connections.value = items.filter(item => !item.state || item.state === 'open');
                 log(`Found ${connections.value.length} available connections`, 'system');
+                for (const item of items.filter(item => item.state === 'failed')) {
+                    log(`${item.name} could not be opened: ${item.error}`, 'error');
+                }
             } catch (error) {
                 log(`Error loading connections: ${error.message}`, 'error');
             }
@@ -190,7 +195,7 @@ createApp({
 
             // Construct WebSocket URL
             const protocol = window.location.protocol === 'https:' ? 'wss:' : 'ws:';
-            const wsUrl = `${protocol}//${window.location.host}/api/connections/${selectedConnection.value}/ws`;
+            const wsUrl = `${protocol}//${window.location.host}/api/connections/${selectedConnection.value}/ws?share_input=true`;
 
             ws = new WebSocket(wsUrl);
             ws.binaryType = 'arraybuffer'; // Handle binary data
@@ -298,6 +303,34 @@ createApp({
                 // Try to parse as JSON first
                 try {
                     const message = JSON.parse(text);
+                    if (message.type === 'error') {
+                        log(`Error (${message.code}): ${message.message}`, 'error');
+                        return;
+                    }
+                    if (message.type === 'write_lock') {
+                        if (message.holder) {
+                            const who = message.holder.client
+                                ? `session ${message.holder.session} (${message.holder.client})`
+                                : `session ${message.holder.session}`;
+                            log(`Write lock held by ${who}`, 'info');
+                        } else {
+                            log(`Write lock released${message.reason ? ` (${message.reason})` : ''}`, 'info');
+                        }
+                        return;
+                    }
+                    if (message.type === 'tx') {
+                        // Another session's input goes to the log; the device's echo already shows it in the terminal
+                        const written = message.format === 'base64' ? atob(message.data) : message.data;
+                        const who = message.client || `session ${message.session}`;
+                        log(`${who} wrote: ${JSON.stringify(written)}`, 'info');
+                        return;
+                    }
+                    if (message.type === 'banner') {
+                        // Shown in yellow so it is not mistaken for device output
+                        const lines = message.text.split(/\r?\n/).join('\r\n');
+                        term.write(`\x1b[33m${lines}\x1b[0m\r\n`);
+                        return;
+                    }
                     if (message.data) {
                         let displayData = message.data;
 
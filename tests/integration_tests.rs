@@ -4,10 +4,10 @@ use axum::{
 };
 use http_body_util::BodyExt;
 use serde_json::Value;
+use tower::ServiceExt;
 use webmux::config::*;
 use webmux::serial::SerialManager;
 use webmux::web;
-use tower::ServiceExt;
 
 async fn body_to_json(body: Body) -> Value {
     let bytes = body.collect().await.unwrap().to_bytes();